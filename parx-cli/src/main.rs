@@ -16,6 +16,14 @@ struct Cli {
     /// I/O niceness: class[:prio] where class=idle|be|rt and prio=0..7 (lower is higher priority)
     #[arg(long)]
     ionice: Option<String>,
+    /// Best-effort read of volumes that use optional features this build does
+    /// not support (required features are still refused)
+    #[arg(long = "compat-read")]
+    compat_read: bool,
+    /// Try to read volume indices written with a newer index schema as the
+    /// newest one this build knows, instead of refusing them
+    #[arg(long = "force-old-reader")]
+    force_old_reader: bool,
     #[command(subcommand)]
     command: Commands,
 }
@@ -27,6 +35,66 @@ enum GpuMode {
     Off,
 }
 
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum ProgressFormat {
+    Text,
+    Json,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum OuterScopeArg {
+    /// Outer shards protect the inner parity shards
+    Parity,
+    /// Outer shards protect data chunks and inner parity (burst losses)
+    Full,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum IfInUseArg {
+    Skip,
+    Wait,
+    Force,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Preset {
+    /// restic/borg repositories: skip locks, caches and rebuildable indices
+    BackupRepo,
+    /// Database files: chunks aligned to 8 KiB pages unless --align says otherwise
+    Database,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum Placement {
+    /// One set in --output for the whole input
+    #[default]
+    Single,
+    /// A set in `<dir>/.parx` for each top-level dir (and one in `INPUT/.parx`
+    /// for loose files), each repairable on its own
+    PerDir,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum OutputFormat {
+    /// Human-readable summary
+    #[default]
+    Text,
+    /// The report as one JSON object on stdout (warnings stay on stderr)
+    Json,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum HashExportFormat {
+    /// One JSON object per chunk
+    Jsonl,
+    /// One JSON object per file, borg item style
+    Borg,
+    /// casync chunk store paths, one chunk per line
+    Casync,
+}
+
+// Parsed once per run; boxing `Create` would only obscure the clap derive
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand, Debug)]
 enum Commands {
     /// Inspect and validate a volume's outer index/trailer (CRC check)
@@ -34,10 +102,19 @@ enum Commands {
         /// Path to a volume or file to inspect
         file: PathBuf,
     },
-    /// Create a minimal .parx set with N parity volumes and a simple CRC'd trailer
+    /// Protect INPUT with a parity set: Reed-Solomon parity volumes, each with a
+    /// checksummed index, plus manifest.json and manifest.v2
     Create {
         #[arg(long, default_value_t = 35)]
         parity: u32,
+        /// Spend about this much on parity, e.g. 50G, instead of a percentage
+        /// (the effective --parity is recorded in the manifest)
+        #[arg(long = "parity-size", value_name = "SIZE", conflicts_with_all = ["parity", "placement"])]
+        parity_size: Option<String>,
+        /// Exactly M parity shards per stripe instead of a percentage (e.g.
+        /// --stripe-k 10 --parity-shards 4)
+        #[arg(long = "parity-shards", value_name = "M", conflicts_with_all = ["parity", "parity_size"])]
+        parity_shards: Option<usize>,
         #[arg(long = "stripe-k", default_value_t = 64)]
         stripe_k: usize,
         #[arg(long="chunk-size", default_value_t=1<<20)]
@@ -45,62 +122,605 @@ enum Commands {
         /// Interleave chunks round-robin across files for resilience to full-file loss
         #[arg(long = "interleave-files", default_value_t = false)]
         interleave_files: bool,
+        /// Write every parity shard to N distinct volumes (needs N <= number of volumes)
+        #[arg(long = "shard-copies", default_value_t = 1)]
+        shard_copies: usize,
+        /// Also write N parity files over the whole volumes, so that any N
+        /// volumes (or parity files) may be lost outright
+        #[arg(long = "volume-parity", default_value_t = 0)]
+        volume_parity: usize,
         #[arg(long, default_value = ".parx")]
         output: PathBuf,
+        /// Spread the volumes round-robin over these dirs (comma-separated,
+        /// e.g. on different drives); the first one holds the manifest and
+        /// takes the place of --output
+        #[arg(
+            long = "output-dirs",
+            value_name = "DIR,DIR,...",
+            value_delimiter = ',',
+            conflicts_with_all = ["output", "set", "volume_parity", "keep_versions"]
+        )]
+        output_dirs: Vec<PathBuf>,
+        /// Write the named set NAME to <output>/sets/NAME/, next to other named
+        /// sets with parameters of their own (see `parx sets list`)
+        #[arg(long = "set", value_name = "NAME")]
+        set: Option<String>,
+        /// Give identical chunks (copies, VM image zeros) one stripe slot so
+        /// they take parity once
+        #[arg(long)]
+        dedup: bool,
+        /// Also write a manifest per top-level dir (in <output>/sub/<dir>/) so a copied
+        /// dir can be verified and repaired on its own with the volumes
+        #[arg(long = "sub-manifests", conflicts_with_all = ["placement", "interleave_files"])]
+        sub_manifests: bool,
+        /// Where parity goes: one set in --output, or one per top-level dir next to its data
+        #[arg(
+            long,
+            value_enum,
+            default_value = "single",
+            conflicts_with_all = ["output", "output_dirs", "set", "stdin_tar", "files_from", "keep_versions", "resume"]
+        )]
+        placement: Placement,
         /// Comma-separated sizes like 1M,1M,1M (just determines how many volumes & mock entry counts)
         #[arg(long = "volume-sizes", default_value = "1M,1M,1M")]
         volume_sizes: String,
-        /// Optional: size of outer RS grouping (stubbed)
+        /// Make as many volumes as it takes for none to exceed SIZE (e.g. 25G),
+        /// header and index included; replaces --volume-sizes
+        #[arg(long = "volume-max-size", value_name = "SIZE", conflicts_with = "volume_sizes")]
+        volume_max_size: Option<String>,
+        /// Name the volumes by TEMPLATE, e.g. 'disc-{id}.parxv'; {id} is the
+        /// volume number as in vol-000.parxv
+        #[arg(long = "volume-name", value_name = "TEMPLATE")]
+        volume_name: Option<String>,
+        /// Failure domains of the volumes, e.g. 'host=a:0-3,b:4-7' (volume
+        /// ids and ranges joined by +; repeatable, one per level such as
+        /// disk, host, site); create fails if losing one group would take
+        /// every parity shard of some stripe
+        #[arg(long = "volume-groups", value_name = "LEVEL=NAME:IDS,...")]
+        volume_groups: Vec<String>,
+        /// Stripes per outer RS group (0 = no outer parity)
         #[arg(long = "outer-group", default_value_t = 0)]
         outer_group: usize,
-        /// Optional: number of outer RS parity shards (stubbed)
+        /// Outer RS shards per group
         #[arg(long = "outer-parity", default_value_t = 0)]
         outer_parity: usize,
-        /// Optional: progress flag (stubbed)
-        #[arg(long, default_value_t = false)]
-        progress: bool,
+        /// What outer groups cover: inner parity only, or data chunks as well
+        #[arg(long = "outer-scope", value_enum, default_value = "parity")]
+        outer_scope: OuterScopeArg,
+        /// Report progress per stage (hashing, encoding, writing each volume)
+        /// on stderr, as text lines or one JSON object per line
+        #[arg(long, value_enum, num_args = 0..=1, require_equals = true, default_missing_value = "text")]
+        progress: Option<ProgressFormat>,
+        /// How often to report progress
+        #[arg(long = "progress-interval", default_value = "2s")]
+        progress_interval: String,
         #[arg(long, value_enum, default_value = "off")]
         gpu: GpuMode,
-        /// Input path (not read in this minimal implementation)
+        /// Keep parity for up to N previous versions under <output>/versions (0 = overwrite)
+        #[arg(long = "keep-versions", default_value_t = 0)]
+        keep_versions: usize,
+        /// Settings for a known kind of dataset
+        #[arg(long, value_enum)]
+        preset: Option<Preset>,
+        /// Skip paths matching this pattern (repeatable; `*`/`?` wildcards,
+        /// patterns without `/` match any path component)
+        #[arg(long)]
+        exclude: Vec<String>,
+        /// Continue an interrupted create into the same output from its journal
+        #[arg(long)]
+        resume: bool,
+        /// Stripes per journal segment (what an interrupted create can lose at most)
+        #[arg(long = "segment-stripes", default_value_t = parx_core::journal::DEFAULT_SEGMENT_STRIPES)]
+        segment_stripes: usize,
+        /// Codec of the volume indices: none, lz4, zstd or zstd:<level>
+        #[arg(long = "index-codec", default_value = "zstd")]
+        index_codec: String,
+        /// Codec of the manifest backup in every volume (none keeps damaged
+        /// backups partly readable)
+        #[arg(long = "backup-codec", default_value = "none")]
+        backup_codec: String,
+        /// Bytes gathered per volume before a write is issued (0 = one write per shard)
+        #[arg(long = "write-block", default_value = "4M")]
+        write_block: String,
+        /// Full blocks queued per volume before encoding waits for the writers
+        #[arg(long = "queue-depth", default_value_t = 4)]
+        queue_depth: usize,
+        /// Threads writing each volume in parallel (for RAID/NVMe arrays)
+        #[arg(long = "write-streams", default_value_t = 1)]
+        write_streams: usize,
+        /// Read the sources and write the volumes around the page cache
+        /// (O_DIRECT / FILE_FLAG_NO_BUFFERING) where the filesystem allows it
+        #[arg(long = "direct-io")]
+        direct_io: bool,
+        /// Read the sources with sequential read-ahead and drop each from the
+        /// page cache once hashed, sparing what else the machine has cached
+        #[arg(long = "drop-cache")]
+        drop_cache: bool,
+        /// Protect the files listed in FILE (one per line, `-` for stdin) in
+        /// list order instead of scanning INPUT; they must lie under INPUT
+        #[arg(long = "files-from", value_name = "FILE")]
+        files_from: Option<PathBuf>,
+        /// Entries of --files-from are NUL-separated (`find -print0`)
+        #[arg(short = '0', long = "null", requires = "files_from")]
+        null: bool,
+        /// Encode the tar stream on stdin (`tar c ... | parx create --stdin-tar DIR`)
+        /// instead of scanning INPUT, which names the dir it is extracted into
+        #[arg(long = "stdin-tar", conflicts_with_all = ["files_from", "media_align"])]
+        stdin_tar: bool,
+        /// Give stripes touching files matching this pattern extra parity (repeatable)
+        #[arg(long)]
+        critical: Vec<String>,
+        /// Extra parity shards per stripe touching a --critical file
+        #[arg(long = "critical-parity", default_value_t = 0)]
+        critical_parity: usize,
+        /// Give files matching PATTERN their own parity percentage, e.g.
+        /// 'photos/**=80' (repeatable; the first matching rule wins)
+        #[arg(long = "parity-rule", value_name = "PATTERN=PCT")]
+        parity_rule: Vec<String>,
+        /// Page size of the protected files, e.g. 8K for Postgres (chunk size must be a multiple)
+        #[arg(long)]
+        align: Option<String>,
+        /// Cut chunks at MP4 box / Matroska cluster boundaries so partly repaired videos stay playable
+        #[arg(long = "media-align")]
+        media_align: bool,
+        /// Label stored in the manifest and every volume, e.g. "Family photos 1998-2004"
+        #[arg(long)]
+        label: Option<String>,
+        /// Free-text creation notes (where the data came from, how it was made)
+        #[arg(long)]
+        notes: Option<String>,
+        /// Who to contact about this set
+        #[arg(long)]
+        contact: Option<String>,
+        /// Copy this parx executable into the output dir as `parx-recover-<os>-<arch>`
+        #[arg(long = "embed-recovery-stub")]
+        embed_recovery_stub: bool,
+        /// Sign the manifest with this PKCS#8 PEM ed25519 key (see `parx keygen`)
+        #[arg(long = "sign-key")]
+        sign_key: Option<PathBuf>,
+        /// Record chunk hashes keyed by the secret in FILE (at least 16 bytes),
+        /// so they cannot confirm that the set holds known content; verify and
+        /// repair then need the same file
+        #[arg(long = "hash-key", value_name = "FILE")]
+        hash_key: Option<PathBuf>,
+        /// Shell command run before the input is read (e.g. to quiesce a database)
+        #[arg(long = "pre-hook")]
+        pre_hook: Option<String>,
+        /// Shell command run after the input was read, even if encoding failed
+        #[arg(long = "post-hook")]
+        post_hook: Option<String>,
+        /// Directory or file to protect; the manifest records paths relative to it
+        input: PathBuf,
+    },
+
+    /// Bring an existing set in line with its input (added, changed and
+    /// removed files) without re-creating it; only affected stripes are re-encoded
+    Update {
+        #[arg(long)]
+        json: bool,
+        /// Treat the input as append-only (backup repositories): known files
+        /// must be unchanged and only new ones are encoded
+        #[arg(long = "append-only-aware")]
+        append_only_aware: bool,
+        #[arg(long, default_value = ".parx")]
+        output: PathBuf,
+        /// Shell command run before the input is read
+        #[arg(long = "pre-hook")]
+        pre_hook: Option<String>,
+        /// Shell command run after the input was read, even if the update failed
+        #[arg(long = "post-hook")]
+        post_hook: Option<String>,
+        /// Re-sign the manifest with this key; required for signed sets
+        #[arg(long = "sign-key")]
+        sign_key: Option<PathBuf>,
+        /// Take files that an earlier create, verify or repair hashed clean at most this
+        /// long ago (e.g. 30m) as intact while they look unchanged, without reading them
+        #[arg(long = "reuse-hashes", value_parser = parse_duration, default_value = "1h")]
+        reuse_hashes: std::time::Duration,
+        /// Read every file, ignoring the hashes cached in hashes.cache
+        #[arg(long = "no-hash-cache")]
+        no_hash_cache: bool,
         input: PathBuf,
     },
 
+    /// Drop files deleted on purpose from a set: their slots are freed and only
+    /// the stripes they shared are re-encoded
+    Forget {
+        #[arg(long)]
+        json: bool,
+        #[arg(long, default_value = ".parx")]
+        output: PathBuf,
+        /// Tree the manifest's paths are relative to; the remaining chunks of
+        /// affected stripes are read from it
+        #[arg(long, default_value = ".")]
+        root: PathBuf,
+        /// Re-sign the manifest with this key; required for signed sets
+        #[arg(long = "sign-key")]
+        sign_key: Option<PathBuf>,
+        /// Files, symlinks or directories as recorded in the manifest
+        #[arg(required = true)]
+        paths: Vec<String>,
+    },
+
+    /// Fill the holes updates left in a set's stripes with its last chunks and drop
+    /// the stripes emptied at the end, within an I/O and time budget
+    Rebalance {
+        #[arg(long)]
+        json: bool,
+        #[arg(long, default_value = ".parx")]
+        output: PathBuf,
+        /// Tree the manifest's paths are relative to; the chunks of re-encoded
+        /// stripes are read from it
+        #[arg(long, default_value = ".")]
+        root: PathBuf,
+        /// Read at most about this much chunk data (e.g. 10G)
+        #[arg(long = "max-io", value_name = "SIZE")]
+        max_io: Option<String>,
+        /// Start no further batch of moves after this long (e.g. 30m)
+        #[arg(long = "max-time", value_parser = parse_duration)]
+        max_time: Option<std::time::Duration>,
+        /// Re-sign the manifest with this key; required for signed sets
+        #[arg(long = "sign-key")]
+        sign_key: Option<PathBuf>,
+    },
+
+    /// Break down what a set takes on disk: source, parity, metadata, garbage
+    /// and the parity a rebalance would free
+    Du {
+        #[arg(long)]
+        json: bool,
+        /// Named set in the parity dir (see `parx sets list`)
+        #[arg(long)]
+        set: Option<String>,
+        #[arg(default_value = ".parx")]
+        dir: PathBuf,
+    },
+
+    /// Show a set's label, notes and contact plus its layout (dir, manifest or lone volume)
+    Info {
+        #[arg(long)]
+        json: bool,
+        path: PathBuf,
+    },
+
+    /// Dump a volume file: header, extension entries, trailer and index
+    Inspect {
+        #[arg(long)]
+        json: bool,
+        /// Index entries to list
+        #[arg(long, default_value_t = 10)]
+        entries: usize,
+        volume: PathBuf,
+    },
+
+    /// Live view of operations running on a parity dir (and its named sets),
+    /// e.g. a create started from cron
+    Top {
+        /// Print the current state once and exit
+        #[arg(long)]
+        once: bool,
+        /// With --once: print the states as a JSON array
+        #[arg(long, requires = "once")]
+        json: bool,
+        /// How often to refresh
+        #[arg(long, default_value = "1s")]
+        interval: String,
+        #[arg(default_value = ".parx")]
+        dir: PathBuf,
+    },
+
     /// Quick header+index summary
     Quickcheck { dir: PathBuf },
 
     /// Parity-aware audit that prints entries and whether index trailer parses
-    Paritycheck { dir: PathBuf },
+    Paritycheck {
+        #[arg(long)]
+        json: bool,
+        /// Hash every shard payload, not just parse each volume's index
+        #[arg(long)]
+        deep: bool,
+        /// With --deep, re-hash every volume, ignoring results cached in paritycheck.cache
+        #[arg(long)]
+        no_cache: bool,
+        dir: PathBuf,
+    },
 
-    /// Verify source files against manifest (stub: prints OK)
+    /// Verify source files against the manifest; prints OK, or DAMAGED and the
+    /// damaged files
+    ///
+    /// Exits 0 when every chunk and the Merkle root match. Damage exits 80
+    /// (corrupt), 81 (truncated), 82 (missing), 83 (unreadable) or 84
+    /// (unreachable), the highest class found winning; errors that stop the
+    /// run exit with the usual codes (65 bad data, 66 no input, 74 I/O, ...).
     Verify {
+        /// Same as `--format json`
+        #[arg(long)]
+        json: bool,
+        #[arg(long, value_enum, default_value = "text")]
+        format: OutputFormat,
+        #[arg(long)]
+        follow_symlinks: bool,
+        /// Verify a remote copy under this http(s):// URL prefix instead of a local root
+        #[arg(long, conflicts_with = "root")]
+        remote: Option<String>,
+        /// Skip and report files whose reads make no progress for this long (e.g. 30s)
+        #[arg(long = "io-timeout", value_parser = parse_duration)]
+        io_timeout: Option<std::time::Duration>,
+        /// Read again up to N times when a read fails with an error that
+        /// looks transient, as on a dropped network mount (0 = never)
+        #[arg(long = "io-retries", value_name = "N", default_value_t = 3)]
+        io_retries: u32,
+        /// Wait before the first retry; doubled for each further one, up to 30s
+        #[arg(long = "io-backoff", default_value = "500ms", value_parser = parse_duration)]
+        io_backoff: std::time::Duration,
+        /// Verify against the manifest backed up in this volume (when the
+        /// manifest files are lost); takes only ROOT
+        #[arg(long = "from-volume", value_name = "VOLUME", conflicts_with = "remote")]
+        from_volume: Option<PathBuf>,
+        /// Refuse to verify unless the manifest is signed by this public key
+        #[arg(long = "verify-key", conflicts_with = "remote")]
+        verify_key: Option<PathBuf>,
+        /// Key file of a set made with `create --hash-key`
+        #[arg(long = "hash-key", value_name = "FILE", conflicts_with = "remote")]
+        hash_key: Option<PathBuf>,
+        /// Pause while system I/O pressure (Linux PSI, some avg10) is at least PCT
+        /// percent, resuming under half of it
+        #[arg(long = "auto-throttle", value_name = "PCT", num_args = 0..=1, default_missing_value = "20")]
+        auto_throttle: Option<f64>,
+        /// Keep at most N files open at once while hashing (below `ulimit -n`)
+        #[arg(long = "max-open-files", value_name = "N", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+        max_open_files: Option<usize>,
+        /// Only read files last found intact longer ago than this (e.g. 30d),
+        /// skipping unchanged files checked more recently
+        #[arg(long = "older-than", value_name = "DURATION", value_parser = parse_duration, conflicts_with_all = ["remote", "from_volume"])]
+        older_than: Option<std::time::Duration>,
+        /// Look for missing files under other names and report them as moved
+        /// when their contents are intact
+        #[arg(long = "detect-moves", conflicts_with = "remote")]
+        detect_moves: bool,
+        /// Read files with sequential read-ahead and drop each from the page
+        /// cache once checked, sparing what else the machine has cached
+        #[arg(long = "drop-cache", conflicts_with = "remote")]
+        drop_cache: bool,
+        /// Verify the named set in sets/NAME/; MANIFEST is then the parity dir
+        /// holding it (e.g. .parx)
+        #[arg(long = "set", value_name = "NAME", conflicts_with = "from_volume")]
+        set: Option<String>,
+        #[arg(required_unless_present = "from_volume")]
+        manifest: Option<PathBuf>,
+        #[arg(required_unless_present_any = ["remote", "from_volume"])]
+        root: Option<PathBuf>,
+    },
+
+    /// Verify the data, quickcheck and deep-check the volumes and check the
+    /// set's geometry in one run, graded GREEN, AMBER or RED
+    CheckAll {
         #[arg(long)]
         json: bool,
         #[arg(long)]
         follow_symlinks: bool,
+        /// Re-hash every volume, ignoring results cached in paritycheck.cache
+        #[arg(long)]
+        no_cache: bool,
+        /// Key file of a set made with `create --hash-key`
+        #[arg(long = "hash-key", value_name = "FILE")]
+        hash_key: Option<PathBuf>,
+        /// Check the named set in sets/NAME/; MANIFEST is then the parity dir
+        /// holding it (e.g. .parx)
+        #[arg(long = "set", value_name = "NAME")]
+        set: Option<String>,
         manifest: PathBuf,
         root: PathBuf,
     },
 
-    /// Audit damage by stripe (stub: prints Repairable: YES)
+    /// Audit damage by stripe: bad chunks and usable parity per damaged stripe
     Audit {
+        /// Same as `--format json`
         #[arg(long)]
         json: bool,
+        #[arg(long, value_enum, default_value = "text")]
+        format: OutputFormat,
         #[arg(long)]
         follow_symlinks: bool,
+        /// Skip hashing unchanged files on checksumming filesystems (btrfs, ZFS,
+        /// fs-verity) that hashed clean before
+        #[arg(long = "fs-hints")]
+        fs_hints: bool,
+        /// Keep at most N files open at once while hashing (below `ulimit -n`)
+        #[arg(long = "max-open-files", value_name = "N", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+        max_open_files: Option<usize>,
         manifest: PathBuf,
         root: PathBuf,
     },
 
-    /// Attempt repair using parity (stub: no-op success)
+    /// Repair damaged or missing chunks from parity
     Repair {
+        /// Same as `--format json`
         #[arg(long)]
         json: bool,
+        #[arg(long, value_enum, default_value = "text")]
+        format: OutputFormat,
+        #[arg(long)]
+        follow_symlinks: bool,
+        /// Restore the tree to a retained protected version (see `parx versions`)
+        #[arg(long = "as-of")]
+        as_of: Option<u32>,
+        /// Extra parity volume location: a dir or http(s) URL, optionally with a
+        /// cost hint `@local|@lan|@remote[:ms]` (repeatable; cheapest copies are read first)
+        #[arg(long = "volumes")]
+        volumes: Vec<String>,
+        /// Parity volumes published under this http(s) URL (repeatable): only the
+        /// stripes local parity cannot repair are fetched, with range requests
+        #[arg(long = "volumes-url", value_name = "URL")]
+        volumes_url: Vec<String>,
+        /// Sign audit-log entries with this PKCS#8 PEM ed25519 key
+        #[arg(long = "audit-key")]
+        audit_key: Option<PathBuf>,
+        /// Remap recorded owner UIDs when restoring ownership, e.g. 1000:2000,1001:2001
+        #[arg(long = "chown-map")]
+        chown_map: Option<String>,
+        /// Only check and repair what a filesystem scrub reported: `zpool status -v`
+        /// output or btrfs kernel log lines (`-` for stdin)
+        #[arg(long = "from-scrub", conflicts_with = "as_of")]
+        from_scrub: Option<PathBuf>,
+        /// Skip and report files whose reads make no progress for this long (e.g. 30s)
+        #[arg(long = "io-timeout", value_parser = parse_duration)]
+        io_timeout: Option<std::time::Duration>,
+        /// Read and write again up to N times when a call fails with an
+        /// error that looks transient, as on a dropped network mount (0 = never)
+        #[arg(long = "io-retries", value_name = "N", default_value_t = 3)]
+        io_retries: u32,
+        /// Wait before the first retry; doubled for each further one, up to 30s
+        #[arg(long = "io-backoff", default_value = "500ms", value_parser = parse_duration)]
+        io_backoff: std::time::Duration,
+        /// Also restore the recorded mtimes and extended attributes of rewritten files (Linux)
+        #[arg(long = "restore-metadata", conflicts_with = "as_of")]
+        restore_metadata: bool,
+        /// Refuse to repair unless the manifest is signed by this public key
+        #[arg(long = "verify-key", conflicts_with = "as_of")]
+        verify_key: Option<PathBuf>,
+        /// Key file of a set made with `create --hash-key`
+        #[arg(long = "hash-key", value_name = "FILE", conflicts_with = "as_of")]
+        hash_key: Option<PathBuf>,
+        /// Pause while system I/O pressure (Linux PSI, some avg10) is at least PCT
+        /// percent, resuming under half of it
+        #[arg(
+            long = "auto-throttle",
+            value_name = "PCT",
+            num_args = 0..=1,
+            default_missing_value = "20",
+            conflicts_with = "as_of"
+        )]
+        auto_throttle: Option<f64>,
+        /// Take files that an earlier create, verify or repair hashed clean at most this
+        /// long ago (e.g. 30m) as intact while they look unchanged, without reading them
+        #[arg(long = "reuse-hashes", value_parser = parse_duration, default_value = "1h")]
+        reuse_hashes: std::time::Duration,
+        /// Read every file, ignoring the hashes cached in hashes.cache
+        #[arg(long = "no-hash-cache")]
+        no_hash_cache: bool,
+        /// Record the new name of missing files found intact under another
+        /// name in the manifest, instead of recreating them at the old path
+        #[arg(long = "fix-paths", conflicts_with = "as_of")]
+        fix_paths: bool,
+        /// Leave the tree untouched and write the reconstructed chunks to DIR as
+        /// standalone files `<idx>-<hash>.chunk`, listed with their targets in chunks.jsonl
+        #[arg(
+            long = "extract-chunks",
+            value_name = "DIR",
+            conflicts_with_all = ["as_of", "fix_paths", "restore_metadata"]
+        )]
+        extract_chunks: Option<PathBuf>,
+        /// What to do with damaged files other processes have locked or open
+        /// for writing: leave them (skip), wait up to 10 minutes for them
+        /// (wait), or patch them anyway (force)
+        #[arg(long = "if-in-use", value_enum, default_value = "skip")]
+        if_in_use: IfInUseArg,
+        /// Repair the named set in sets/NAME/; MANIFEST is then the parity dir
+        /// holding it (e.g. .parx)
+        #[arg(long = "set", value_name = "NAME")]
+        set: Option<String>,
+        manifest: PathBuf,
+        root: PathBuf,
+    },
+
+    /// Serve verified reads of protected files over a local socket (integrity proxy)
+    Serve {
+        /// Unix socket to listen on
+        #[arg(long)]
+        socket: PathBuf,
+        /// Fail reads of damaged chunks instead of repairing them first
+        #[arg(long)]
+        no_repair: bool,
         #[arg(long)]
         follow_symlinks: bool,
         manifest: PathBuf,
         root: PathBuf,
     },
 
+    /// Rewrite manifest.json (and manifest.v2) from the backup every volume carries
+    RecoverManifest {
+        /// Overwrite a manifest.json that still parses
+        #[arg(long)]
+        force: bool,
+        parx_dir: PathBuf,
+    },
+
+    /// Rebuild the index of a volume whose index and trailer are damaged by
+    /// finding its shards again
+    Fsck {
+        #[arg(long)]
+        json: bool,
+        #[arg(long)]
+        follow_symlinks: bool,
+        /// Rebuild even if the index reads fine
+        #[arg(long)]
+        force: bool,
+        /// Manifest of the set (default: manifest.json next to the volume)
+        #[arg(long)]
+        manifest: Option<PathBuf>,
+        /// Source tree to regenerate the volume's shards from; without it only
+        /// copies held by the other volumes identify shards
+        #[arg(long)]
+        root: Option<PathBuf>,
+        volume: PathBuf,
+    },
+
+    /// List protected dataset versions recorded in a parity dir
+    Versions {
+        #[arg(long)]
+        json: bool,
+        dir: PathBuf,
+    },
+
+    /// Volume maintenance (see `parx vol heal`)
+    Vol {
+        #[command(subcommand)]
+        cmd: VolCommands,
+    },
+
+    /// Named parity sets kept side by side in one parity dir (see `parx sets list`)
+    Sets {
+        #[command(subcommand)]
+        cmd: SetsCommands,
+    },
+
+    /// Map a file byte offset to its chunk, stripe and parity shards
+    WhichStripe {
+        #[arg(long)]
+        json: bool,
+        #[arg(long, default_value = ".parx/manifest.json")]
+        manifest: PathBuf,
+        /// File path as recorded in the manifest
+        file: String,
+        /// Byte offset (decimal or 0x-prefixed hex)
+        offset: String,
+    },
+
+    /// Print the chunk table (path, offset, len, hash) for other content-addressed tools
+    ExportHashes {
+        #[arg(long, value_enum, default_value = "jsonl")]
+        format: HashExportFormat,
+        #[arg(long, default_value = ".parx/manifest.json")]
+        manifest: PathBuf,
+    },
+
+    /// Check whether a file's content is (probably) part of the protected set
+    Contains {
+        #[arg(long)]
+        json: bool,
+        /// Chunk filter written by `create`
+        #[arg(long, default_value = ".parx/filter.bin")]
+        filter: PathBuf,
+        file: PathBuf,
+    },
+
+    /// Tamper-evident log of repair actions (see `parx audit-log verify`)
+    AuditLog {
+        #[command(subcommand)]
+        cmd: AuditLogCommands,
+    },
+
     /// Split a file into N parts named part-XXX.bin in out_dir
     Split { input: PathBuf, out_dir: PathBuf, n: usize },
 
@@ -115,6 +735,104 @@ enum Commands {
         /// Root directory of the dataset
         root: PathBuf,
     },
+
+    /// Generate an ed25519 keypair (PKCS#8 private key, SPKI public key)
+    Keygen { private_key: PathBuf, public_key: PathBuf },
+
+    /// Produce a signed distribution bundle (manifest v2 + whole-file witness hashes)
+    Publish {
+        /// PKCS#8 PEM ed25519 private key
+        #[arg(long = "sign-key")]
+        sign_key: PathBuf,
+        /// Add a bao outboard (BLAKE3 tree, 16 KiB leaves) per file, so
+        /// check-download can tell which byte ranges of a bad file differ
+        #[arg(long)]
+        bao: bool,
+        /// Output bundle path (default: bundle.parx-pub next to the manifest)
+        #[arg(long)]
+        out: Option<PathBuf>,
+        manifest: PathBuf,
+        root: PathBuf,
+    },
+
+    /// Check a downloaded tree against a signed distribution bundle
+    CheckDownload {
+        #[arg(long)]
+        json: bool,
+        /// Require the bundle to be signed by this public key; without it
+        /// the bundle is only checked against the key it carries, and the
+        /// result is reported as unauthenticated
+        #[arg(long)]
+        pubkey: Option<PathBuf>,
+        dir: PathBuf,
+        bundle: PathBuf,
+    },
+
+    /// Bundle a parity dir (manifests, filter, audit log, versions) into one checked archive
+    Pack {
+        /// Also bundle the parity volumes (needed to repair from the bundle alone)
+        #[arg(long)]
+        with_volumes: bool,
+        /// Bundle file to write (`-` for stdout)
+        #[arg(short, long)]
+        out: PathBuf,
+        parx_dir: PathBuf,
+    },
+
+    /// Check a bundle written by `parx pack` and extract it into a parity dir
+    Unpack {
+        /// Only check every member against the envelope; write nothing
+        #[arg(long)]
+        check: bool,
+        /// Overwrite files that already exist in DEST
+        #[arg(long)]
+        force: bool,
+        /// Bundle file (`-` for stdin)
+        bundle: PathBuf,
+        #[arg(required_unless_present = "check")]
+        dest: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum AuditLogCommands {
+    /// Check the hash chain (and signatures) of a set's repair audit log
+    Verify {
+        #[arg(long)]
+        json: bool,
+        /// Require every entry to be signed by this public key
+        #[arg(long)]
+        pubkey: Option<PathBuf>,
+        /// Parity dir containing audit.log, or the log file itself
+        path: PathBuf,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum SetsCommands {
+    /// List the named sets in a parity dir with their sizes and parity health
+    List {
+        #[arg(long)]
+        json: bool,
+        #[arg(default_value = ".parx")]
+        dir: PathBuf,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum VolCommands {
+    /// Regenerate damaged parity shards in place from the source data
+    Heal {
+        #[arg(long)]
+        json: bool,
+        #[arg(long)]
+        follow_symlinks: bool,
+        /// Sign audit-log entries with this PKCS#8 PEM ed25519 key
+        #[arg(long = "audit-key")]
+        audit_key: Option<PathBuf>,
+        manifest: PathBuf,
+        root: PathBuf,
+    },
 }
 
 // moved to parx-core::index
@@ -144,11 +862,50 @@ fn parse_size_token(tok: &str) -> Result<u64> {
         "K" | "KB" => 1024,
         "M" | "MB" => 1024 * 1024,
         "G" | "GB" => 1024 * 1024 * 1024,
+        "T" | "TB" => 1024 * 1024 * 1024 * 1024,
         _ => bail!("unknown size suffix {}", suffix),
     };
     Ok(base.saturating_mul(mul))
 }
 
+/// `30s`, `500ms`, `2m`, `1h` or plain seconds.
+fn parse_duration(tok: &str) -> Result<std::time::Duration> {
+    let s = tok.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (digits, unit) = s.split_at(split);
+    let n: u64 = digits.parse().with_context(|| format!("invalid duration: {}", s))?;
+    let ms = match unit {
+        "" | "s" => n.saturating_mul(1000),
+        "ms" => n,
+        "m" => n.saturating_mul(60_000),
+        "h" => n.saturating_mul(3_600_000),
+        "d" => n.saturating_mul(86_400_000),
+        _ => bail!("unknown duration unit {:?} (use ms, s, m, h or d)", unit),
+    };
+    if ms == 0 {
+        bail!("duration must be positive: {}", s);
+    }
+    Ok(std::time::Duration::from_millis(ms))
+}
+
+fn warn_stalled(files: &[String]) {
+    for f in files {
+        eprintln!("warn: reads of {} stalled past --io-timeout; skipped (failing disk?)", f);
+    }
+}
+
+fn warn_unreachable(files: &[String], retries: u64, recovered: u64) {
+    for f in files {
+        eprintln!("warn: reads of {} kept failing with transient errors; skipped (mount down?)", f);
+    }
+    if retries > 0 {
+        eprintln!(
+            "note: {} I/O call(s) retried after transient errors, {} then succeeded",
+            retries, recovered
+        );
+    }
+}
+
 fn parse_volume_sizes(csv: &str) -> Result<Vec<u64>> {
     let mut out = Vec::new();
     for tok in csv.split(',') {
@@ -169,18 +926,159 @@ fn list_volumes(dir: &Path) -> Result<Vec<PathBuf>> {
     for ent in fs::read_dir(dir).with_context(|| format!("read_dir {:?}", dir))? {
         let ent = ent?;
         let p = ent.path();
-        if let Some(name) = p.file_name().and_then(|s| s.to_str()) {
-            if name.starts_with("vol-") && name.ends_with(".parxv") {
-                vols.push(p);
-            }
+        // Any name: `create --volume-name` may have chosen it
+        if p.extension().is_some_and(|e| e == "parxv") {
+            vols.push(p);
         }
     }
     vols.sort();
     Ok(vols)
 }
 
+/// Volumes in `dir` and, when it holds the manifest of a set made with
+/// `--output-dirs`, in the set's other volume dirs.
+fn set_volumes(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut vols = list_volumes(dir)?;
+    let Ok((mf, _)) = parx_core::manifest::load(&dir.join("manifest.json")) else {
+        return Ok(vols);
+    };
+    for d in mf.volume_dirs() {
+        if !d.is_dir() {
+            eprintln!("warning: volume dir {:?} of the set is missing", d);
+        }
+        vols.extend(list_volumes(&d)?);
+    }
+    Ok(vols)
+}
+
 // moved to parx-core::index
 
+/// Run `f` between the optional quiesce hooks. The post hook runs whenever
+/// the pre hook succeeded, so a paused database is always resumed.
+fn with_hooks<T>(
+    pre: Option<&str>,
+    post: Option<&str>,
+    input: &Path,
+    f: impl FnOnce() -> Result<T>,
+) -> Result<T> {
+    if let Some(cmd) = pre {
+        run_hook(cmd, "pre", input)?;
+    }
+    let res = f();
+    if let Some(cmd) = post {
+        let hook = run_hook(cmd, "post", input);
+        let out = res?;
+        hook?;
+        return Ok(out);
+    }
+    res
+}
+
+/// Run a hook through the shell with `PARX_HOOK` (pre/post) and `PARX_INPUT`
+/// set; its stdout goes to stderr so `--json` output stays parseable.
+fn run_hook(cmd: &str, phase: &str, input: &Path) -> Result<()> {
+    #[cfg(windows)]
+    let mut c = {
+        let mut c = std::process::Command::new("cmd");
+        c.args(["/C", cmd]);
+        c
+    };
+    #[cfg(not(windows))]
+    let mut c = {
+        let mut c = std::process::Command::new("sh");
+        c.args(["-c", cmd]);
+        c
+    };
+    let status = c
+        .env("PARX_HOOK", phase)
+        .env("PARX_INPUT", input)
+        .stdout(std::io::stderr())
+        .status()
+        .with_context(|| format!("run {}-hook {:?}", phase, cmd))?;
+    if !status.success() {
+        bail!("{}-hook {:?} failed ({})", phase, cmd, status);
+    }
+    Ok(())
+}
+
+/// Prefix that makes paths under `input` relative to the current directory
+/// (`None` when `input` is the current directory or lies outside it).
+/// Entries of `create --files-from` (a file, or `-` for stdin).
+fn read_file_list(path: &Path, nul: bool) -> Result<Vec<PathBuf>> {
+    let mut data = Vec::new();
+    if path == Path::new("-") {
+        std::io::Read::read_to_end(&mut std::io::stdin(), &mut data)
+            .context("read file list from stdin")?;
+    } else {
+        data = std::fs::read(path).with_context(|| format!("read file list {:?}", path))?;
+    }
+    parx_core::encode::parse_file_list(&data, nul)
+}
+
+/// Chunks named by a scrub report; entries that cannot be mapped are warned about.
+fn scrub_targets(report: &Path, manifest: &Path) -> Result<std::collections::BTreeSet<u64>> {
+    let mut data = Vec::new();
+    if report == Path::new("-") {
+        std::io::Read::read_to_end(&mut std::io::stdin(), &mut data)
+            .context("read scrub report from stdin")?;
+    } else {
+        data = std::fs::read(report).with_context(|| format!("read scrub report {:?}", report))?;
+    }
+    let rep = parx_core::scrub::parse_report(&String::from_utf8_lossy(&data));
+    for u in &rep.unresolved {
+        eprintln!("warn: scrub entry names no file: {}", u);
+    }
+    let (mf, _) = parx_core::manifest::load(manifest)?;
+    let targets = parx_core::scrub::map_to_chunks(&mf, &rep.errors);
+    for p in &targets.unmatched {
+        eprintln!("warn: {} is not protected by this set", p);
+    }
+    Ok(targets.chunks)
+}
+
+fn cwd_rel_prefix(input: &Path) -> Result<Option<String>> {
+    let cwd = std::env::current_dir().context("current_dir")?;
+    // Compare canonical paths too, e.g. on macOS where CWD may be
+    // /private/var/... while input is /var/...
+    let mut prefix = input.strip_prefix(&cwd).ok().map(|p| p.to_path_buf());
+    if prefix.is_none() {
+        if let (Ok(cwd_can), Ok(inp_can)) = (cwd.canonicalize(), input.canonicalize()) {
+            if let Ok(p) = inp_can.strip_prefix(&cwd_can) {
+                prefix = Some(p.to_path_buf());
+            }
+        }
+    }
+    let pre = prefix.map(|p| p.to_string_lossy().to_string());
+    Ok(pre.filter(|p| !p.is_empty() && p != "."))
+}
+
+/// Prefix for a dir that does not exist yet (a tar stream's extraction
+/// target): the path itself when relative and free of `..`.
+fn lexical_rel_prefix(input: &Path) -> Result<Option<String>> {
+    if input.is_absolute() {
+        return cwd_rel_prefix(input);
+    }
+    let mut parts = Vec::new();
+    for c in input.components() {
+        match c {
+            std::path::Component::Normal(p) => parts.push(p.to_string_lossy().to_string()),
+            std::path::Component::CurDir => {}
+            _ => bail!("--stdin-tar: {:?} must not contain `..`", input),
+        }
+    }
+    Ok((!parts.is_empty()).then(|| parts.join("/")))
+}
+
+/// Split `LOCATION[@COST]`; the suffix only counts when it parses as a cost hint.
+fn parse_volume_location(spec: &str) -> (&str, Option<parx_core::storage::ReadCost>) {
+    if let Some((loc, hint)) = spec.rsplit_once('@') {
+        if let Ok(cost) = parx_core::storage::ReadCost::parse(hint) {
+            return (loc, Some(cost));
+        }
+    }
+    (spec, None)
+}
+
 fn apply_priority(nice: Option<i32>, ionice: Option<String>) {
     // CPU nice via renice: available on Unix (Linux/macOS). Best-effort.
     #[cfg(unix)]
@@ -231,6 +1129,145 @@ fn apply_priority(nice: Option<i32>, ionice: Option<String>) {
     }
 }
 
+fn warn_manifest_recovery(rep: &Option<parx_core::manifest_v2::RecoveryReport>) {
+    if let Some(r) = rep {
+        eprintln!(
+            "warn: manifest.json unreadable; recovered {} of {} file record(s) from manifest.v2",
+            r.files_recovered,
+            r.files_expected.map(|n| n.to_string()).unwrap_or_else(|| "?".into())
+        );
+        if !r.lost_files.is_empty() {
+            eprintln!("warn: lost file records (manifest positions): {:?}", r.lost_files);
+            eprintln!("warn: chunk ranges without metadata: {:?}", r.lost_chunk_ranges);
+            if !r.lost_file_ids.is_empty() {
+                eprintln!("warn: ids of the lost files: {:?}", r.lost_file_ids);
+            }
+        }
+    }
+}
+
+/// The throttle for `--auto-throttle PCT`, if given.
+fn throttle(pct: Option<f64>) -> Result<Option<parx_core::throttle::Throttle>> {
+    let Some(pct) = pct else { return Ok(None) };
+    if !(pct > 0.0 && pct <= 100.0) {
+        bail!("--auto-throttle takes a percentage in (0, 100], got {}", pct);
+    }
+    let t = parx_core::throttle::Throttle::new(pct);
+    if !t.available() {
+        eprintln!("warn: I/O pressure is not available here; --auto-throttle has no effect");
+    }
+    Ok(Some(t))
+}
+
+fn warn_parity_shortfall(short: &parx_core::verify::ParityShortfall) {
+    for v in &short.volumes_unreadable {
+        eprintln!("warn: volume {} is missing or its index is unreadable", v);
+    }
+    for v in &short.volumes_stray {
+        eprintln!("warn: volume {} does not belong to this manifest; not counted", v);
+    }
+    eprintln!(
+        "warn: {} stripe(s) have fewer parity shards than the set was made with (fewest left: {})",
+        short.stripes_degraded, short.min_parity_left
+    );
+    if short.stripes_unprotected > 0 {
+        eprintln!(
+            "warn: {} stripe(s) have no parity left; a single lost chunk there cannot be repaired",
+            short.stripes_unprotected
+        );
+    }
+}
+
+fn warn_throttled(ms: u64) {
+    if ms > 0 {
+        eprintln!(
+            "note: paused {:.1}s while the system was busy (--auto-throttle)",
+            ms as f64 / 1e3
+        );
+    }
+}
+
+/// One block per run for `parx top`: its stages with rate, ETA and what
+/// they work on, and how busy its workers are.
+fn render_runs(runs: &[parx_core::live::RunState]) -> String {
+    use parx_core::progress::human_bytes;
+    use std::fmt::Write;
+    let secs = |ms: u64| {
+        let s = ms / 1000;
+        match s {
+            0..=59 => format!("{}s", s),
+            60..=3599 => format!("{}m{:02}s", s / 60, s % 60),
+            _ => format!("{}h{:02}m", s / 3600, s / 60 % 60),
+        }
+    };
+    let mut out = String::new();
+    for r in runs {
+        let snap = &r.snapshot;
+        let _ =
+            write!(out, "{} (pid {}) on {}, running {}", r.op, r.pid, r.dir, secs(snap.elapsed_ms));
+        if r.is_stale() {
+            out.push_str(" [stale: no update, the run may have been killed]");
+        }
+        out.push('\n');
+        if snap.workers > 0 {
+            let _ = writeln!(out, "  workers: {}/{} busy", snap.workers_busy, snap.workers);
+        }
+        for s in &snap.stages {
+            let pct = match s.bytes_total {
+                0 => "    ".to_string(),
+                t => format!("{:>3}%", (s.bytes_done as u128 * 100 / t as u128).min(100)),
+            };
+            let state = match (s.done, s.eta_ms) {
+                (true, _) => "done".to_string(),
+                (false, Some(eta)) => format!("ETA {}", secs(eta)),
+                (false, None) => String::new(),
+            };
+            let line = format!(
+                "  {:<24} {} {:>10} {:>12}/s  {:<10} {}",
+                format!("{}{}", "  ".repeat(s.depth), s.path.rsplit('/').next().unwrap_or("")),
+                pct,
+                human_bytes(s.bytes_done),
+                human_bytes(s.bytes_per_sec),
+                state,
+                s.current.as_deref().unwrap_or("")
+            );
+            let _ = writeln!(out, "{}", line.trim_end());
+        }
+    }
+    out
+}
+
+fn warn_volumes(restored: &[String], unrecoverable: &[String]) {
+    for v in restored {
+        eprintln!("note: rebuilt {} from volume parity", v);
+    }
+    if !unrecoverable.is_empty() {
+        eprintln!(
+            "warn: {} volume file(s) lost or damaged, more than the volume parity covers: {}",
+            unrecoverable.len(),
+            unrecoverable.join(", ")
+        );
+    }
+}
+
+fn warn_metadata(rep: &parx_core::meta::MetaReport) {
+    if !rep.unprivileged.is_empty() {
+        eprintln!(
+            "warn: not privileged to restore ownership; {} file(s) now owned by the current user:",
+            rep.unprivileged.len()
+        );
+        for f in &rep.unprivileged {
+            eprintln!("  {}", f);
+        }
+    }
+    for f in &rep.failed {
+        eprintln!("warn: could not restore permissions/ownership of {}", f);
+    }
+    for f in &rep.unsupported {
+        eprintln!("warn: cannot restore mtime/extended attributes of {} on this platform", f);
+    }
+}
+
 fn configure_threads(threads: Option<usize>) {
     if let Some(n) = threads {
         if let Err(e) = rayon::ThreadPoolBuilder::new().num_threads(n).build_global() {
@@ -244,6 +1281,10 @@ fn run() -> Result<()> {
     // Apply process priority and thread config early
     apply_priority(cli.nice, cli.ionice.clone());
     configure_threads(cli.threads);
+    if cli.compat_read {
+        parx_core::volume::set_read_mode(parx_core::volume::ReadMode::Compat);
+    }
+    parx_core::volume::set_force_old_reader(cli.force_old_reader);
     match cli.command {
         Commands::OuterDecode { file } => {
             // Practical implementation: try to read and validate the trailer+index CRC
@@ -272,167 +1313,1172 @@ fn run() -> Result<()> {
         }
         Commands::Create {
             parity,
+            parity_size,
+            parity_shards,
             stripe_k,
             chunk_size,
             interleave_files,
+            shard_copies,
+            volume_parity,
             output,
+            output_dirs,
+            set,
+            dedup,
+            sub_manifests,
+            placement,
             volume_sizes,
+            volume_max_size,
+            volume_name,
             outer_group,
             outer_parity,
-            progress: _,
-            gpu: _,
+            outer_scope,
+            progress,
+            progress_interval,
+            gpu,
+            keep_versions,
+            preset,
+            exclude,
+            critical,
+            critical_parity,
+            parity_rule,
+            volume_groups,
+            resume,
+            segment_stripes,
+            index_codec,
+            backup_codec,
+            write_block,
+            queue_depth,
+            write_streams,
+            direct_io,
+            drop_cache,
+            files_from,
+            null,
+            stdin_tar,
+            align,
+            media_align,
+            label,
+            notes,
+            contact,
+            embed_recovery_stub,
+            sign_key,
+            hash_key,
+            pre_hook,
+            post_hook,
             input,
         } => {
-            let sizes = parse_volume_sizes(&volume_sizes)?;
-            let cfg = parx_core::encode::EncoderConfig {
-                chunk_size,
-                stripe_k,
-                parity_pct: parity,
-                volumes: sizes.len(),
-                outer_group,
-                outer_parity,
+            let (output, volume_dirs) = match (&set, output_dirs.split_first()) {
+                (Some(name), _) => (parx_core::sets::dir_of(&output, name)?, Vec::new()),
+                (None, Some((first, rest))) => (first.clone(), rest.to_vec()),
+                (None, None) => (output, Vec::new()),
+            };
+            let align = align.as_deref().map(parse_size_token).transpose()?.map(|a| a as usize);
+            let mut opts = parx_core::encode::EncodeOptions {
+                exclude,
+                align,
+                media_align,
+                info: parx_core::manifest::SetInfo { label, notes, contact },
+                recovery_stub: if embed_recovery_stub {
+                    Some(std::env::current_exe().context("locate the parx executable")?)
+                } else {
+                    None
+                },
+                outer_scope: match outer_scope {
+                    OuterScopeArg::Parity => parx_core::outer::OuterScope::Parity,
+                    OuterScopeArg::Full => parx_core::outer::OuterScope::Full,
+                },
+                critical,
+                critical_parity,
+                parity_rules: parity_rule
+                    .iter()
+                    .map(|r| r.parse())
+                    .collect::<Result<_>>()
+                    .context("--parity-rule")?,
+                parity_shards,
+                volume_parity,
+                parity_size: parity_size
+                    .as_deref()
+                    .map(parse_size_token)
+                    .transpose()
+                    .context("--parity-size")?,
+                files: match &files_from {
+                    Some(p) => Some(read_file_list(p, null)?),
+                    None => None,
+                },
+                segment_stripes,
+                resume,
+                interrupt_after_segments: None,
+                codecs: parx_core::codec::Codecs {
+                    index: index_codec.parse().context("--index-codec")?,
+                    backup: backup_codec.parse().context("--backup-codec")?,
+                },
+                write: parx_core::volwriter::WriteTuning {
+                    block_size: parse_size_token(&write_block)? as usize,
+                    queue_depth,
+                    streams: write_streams,
+                },
+                // Record paths relative to the working dir so that later
+                // commands can use `.` as the root (per tests/README)
+                rel_prefix: if stdin_tar && !input.exists() {
+                    lexical_rel_prefix(&input)?
+                } else {
+                    cwd_rel_prefix(&input)?
+                },
+                sign_key: sign_key.as_deref().map(parx_core::sign::load_signing_key).transpose()?,
+                hash_key: hash_key
+                    .as_deref()
+                    .map(parx_core::manifest::HashKey::load)
+                    .transpose()?,
+                dedup,
+                sub_manifests,
+                volume_max_size: volume_max_size
+                    .as_deref()
+                    .map(parse_size_token)
+                    .transpose()
+                    .context("--volume-max-size")?,
+                volume_name,
+                volume_dirs,
+                progress: Some(parx_core::progress::Progress::new()),
+                direct_io,
+                drop_cache,
+                volume_groups: volume_groups
+                    .iter()
+                    .map(|g| g.parse())
+                    .collect::<Result<_>>()
+                    .context("--volume-groups")?,
+            };
+            // Reports until dropped at the end of this command
+            let _reporter = match (progress, &opts.progress) {
+                (Some(format), Some(p)) => {
+                    let sink: std::sync::Arc<dyn parx_core::progress::ProgressSink> = match format {
+                        ProgressFormat::Text => std::sync::Arc::new(parx_core::progress::TextSink),
+                        ProgressFormat::Json => std::sync::Arc::new(parx_core::progress::JsonSink),
+                    };
+                    let every =
+                        parse_duration(&progress_interval).context("--progress-interval")?;
+                    Some(p.start(sink, every))
+                }
+                _ => None,
+            };
+            // Published for `parx top` in other terminals; never fails the run
+            let _state = match (&opts.progress, placement) {
+                (Some(p), Placement::Single) => {
+                    let every = std::time::Duration::from_secs(1);
+                    parx_core::live::StateFileSink::new(&output, "create", every)
+                        .ok()
+                        .map(|sink| p.start(std::sync::Arc::new(sink), every))
+                }
+                _ => None,
+            };
+            if resume && keep_versions > 0 {
+                bail!("--resume continues the set in place; drop --keep-versions");
+            }
+            if preset == Some(Preset::Database) && opts.align.is_none() {
+                opts.align = Some(8 * 1024);
+            }
+            if preset == Some(Preset::BackupRepo) {
+                if interleave_files {
+                    bail!("--preset backup-repo keeps each pack's chunks together; drop --interleave-files");
+                }
+                opts.exclude
+                    .extend(parx_core::encode::BACKUP_REPO_EXCLUDES.iter().map(|p| p.to_string()));
+            }
+            let sizes = parse_volume_sizes(&volume_sizes)?;
+            let cfg = parx_core::encode::EncoderConfig {
+                chunk_size,
+                stripe_k,
+                parity_pct: parity,
+                volumes: sizes.len(),
+                outer_group,
+                outer_parity,
                 interleave_files,
+                shard_copies,
+                gpu: match gpu {
+                    GpuMode::Off => parx_core::encode::GpuMode::Off,
+                    GpuMode::On => parx_core::encode::GpuMode::On,
+                    GpuMode::Auto => parx_core::encode::GpuMode::Auto,
+                },
             };
-            let _manifest = parx_core::encode::Encoder::encode(&input, &output, &cfg)?;
-            // Adjust manifest paths to be relative to current working directory
-            // so that downstream commands can use `.` as the root (per tests/README).
-            let cwd = std::env::current_dir().context("current_dir")?;
-            // Adjust manifest so relpaths are relative to CWD, even on macOS where
-            // CWD may be /private/var/... while input is /var/...
-            let mut maybe_prefix = input.strip_prefix(&cwd).ok().map(|p| p.to_path_buf());
-            if maybe_prefix.is_none() {
-                if let (Ok(cwd_can), Ok(inp_can)) = (cwd.canonicalize(), input.canonicalize()) {
-                    if let Ok(p) = inp_can.strip_prefix(&cwd_can) {
-                        maybe_prefix = Some(p.to_path_buf());
-                    }
+            if placement == Placement::PerDir {
+                opts.rel_prefix = None;
+                let sets = with_hooks(pre_hook.as_deref(), post_hook.as_deref(), &input, || {
+                    parx_core::encode::Encoder::encode_per_dir(&input, &cfg, &opts)
+                })?;
+                for s in &sets {
+                    eprintln!("create: {} file(s) under {:?} -> {:?}", s.files, s.root, s.output);
                 }
+                return Ok(());
             }
-            if let Some(prefix) = maybe_prefix {
-                let mpath = output.join("manifest.json");
-                let mut mf: parx_core::manifest::Manifest =
-                    serde_json::from_reader(File::open(&mpath)?)?;
-                let pre = prefix.to_string_lossy().to_string();
-                let pre = if pre.is_empty() || pre == "." { None } else { Some(pre) };
-                if let Some(pre) = pre {
-                    for fe in &mut mf.files {
-                        fe.rel_path = format!("{}/{}", pre, fe.rel_path);
+            // The old set is archived once the new one is written
+            let aside =
+                if keep_versions > 0 { parx_core::versions::set_aside(&output)? } else { None };
+            let mf = with_hooks(pre_hook.as_deref(), post_hook.as_deref(), &input, || {
+                if stdin_tar {
+                    parx_core::encode::Encoder::encode_stream(
+                        std::io::stdin().lock(),
+                        &output,
+                        &cfg,
+                        &opts,
+                    )
+                } else {
+                    parx_core::encode::Encoder::encode_with(&input, &output, &cfg, &opts)
+                }
+            });
+            let mf = match (mf, aside) {
+                (Ok(mf), aside) => {
+                    if keep_versions > 0 {
+                        let parent = aside.map(|a| a.archive()).transpose()?;
+                        parx_core::versions::record_current(&output, parent, keep_versions)?;
                     }
-                    let mut f = File::create(&mpath)?;
-                    f.write_all(serde_json::to_string_pretty(&mf)?.as_bytes())?;
+                    mf
+                }
+                (Err(e), Some(aside)) => {
+                    aside.restore().context("put the previous set back")?;
+                    return Err(e);
                 }
+                (Err(e), None) => return Err(e),
+            };
+            if mf.is_empty() {
+                eprintln!("create: no data to protect in {:?}; wrote an empty set", input);
+            } else if opts.parity_size.is_some() {
+                let m = mf.max_parity_shards();
+                let bytes = mf.geometry().parity_bytes(m, mf.shard_copies);
+                eprintln!(
+                    "create: --parity-size gives {} parity shard(s) per stripe ({}% parity, {} bytes over {} volume(s))",
+                    m, mf.parity_pct, bytes, mf.volumes
+                );
             }
             // No stdout on success per tests
         }
 
-        Commands::Quickcheck { dir } => {
-            let vols = list_volumes(&dir)?;
-            if vols.is_empty() {
-                println!("Volumes: 0, total entries: 0");
+        Commands::Update {
+            json,
+            append_only_aware,
+            output,
+            pre_hook,
+            post_hook,
+            sign_key,
+            reuse_hashes,
+            no_hash_cache,
+            input,
+        } => {
+            let prefix = cwd_rel_prefix(&input)?;
+            let opts = parx_core::update::UpdateOptions {
+                append_only: append_only_aware,
+                sign_key: sign_key.as_deref().map(parx_core::sign::load_signing_key).transpose()?,
+                reuse_hashes: (!no_hash_cache).then_some(reuse_hashes),
+            };
+            let rep = with_hooks(pre_hook.as_deref(), post_hook.as_deref(), &input, || {
+                parx_core::update::update_with_options(&output, &input, prefix.as_deref(), &opts)
+            })?;
+            if json {
+                println!("{}", parx_core::report::to_json(&rep)?);
+            } else {
+                println!(
+                    "files: {} added, {} changed, {} removed, {} unchanged; chunks: {} added, {} rewritten, {} freed; stripes: {} new, {} rewritten",
+                    rep.files_added,
+                    rep.files_changed,
+                    rep.files_removed,
+                    rep.files_unchanged,
+                    rep.chunks_added,
+                    rep.chunks_rewritten,
+                    rep.chunks_freed,
+                    rep.stripes_added,
+                    rep.stripes_rewritten
+                );
+                if rep.symlinks_changed > 0 {
+                    println!("symlinks: {} changed", rep.symlinks_changed);
+                }
+            }
+        }
+
+        Commands::Forget { json, output, root, sign_key, paths } => {
+            let sk = sign_key.as_deref().map(parx_core::sign::load_signing_key).transpose()?;
+            let rep = parx_core::update::forget(&output, &root, &paths, sk.as_ref())?;
+            if json {
+                println!("{}", parx_core::report::to_json(&rep)?);
+            } else {
+                println!(
+                    "forgot {} file(s) ({} bytes), {} symlink(s); chunks: {} freed; stripes: {} rewritten",
+                    rep.files_forgotten,
+                    rep.bytes_forgotten,
+                    rep.symlinks_forgotten,
+                    rep.chunks_freed,
+                    rep.stripes_rewritten
+                );
+            }
+        }
+
+        Commands::Rebalance { json, output, root, max_io, max_time, sign_key } => {
+            let opts = parx_core::rebalance::RebalanceOptions {
+                max_io: max_io.as_deref().map(parse_size_token).transpose()?,
+                max_time,
+                sign_key: sign_key.as_deref().map(parx_core::sign::load_signing_key).transpose()?,
+            };
+            let rep = parx_core::rebalance::rebalance(&output, &root, &opts)?;
+            if json {
+                println!("{}", parx_core::report::to_json(&rep)?);
+            } else {
+                println!(
+                    "chunks moved: {}; holes: {} -> {}; stripes: {} -> {} ({} rewritten); read {} bytes",
+                    rep.chunks_moved,
+                    rep.holes_before,
+                    rep.holes_after,
+                    rep.stripes_before,
+                    rep.stripes_after,
+                    rep.stripes_rewritten,
+                    rep.bytes_read
+                );
+                if rep.budget_exhausted {
+                    println!("budget spent before every hole was filled; run again to continue");
+                }
+            }
+        }
+
+        Commands::Du { json, set, dir } => {
+            let dir = match &set {
+                Some(name) => parx_core::sets::dir_of(&dir, name)?,
+                None => dir,
+            };
+            let rep = parx_core::usage::usage(&dir)?;
+            if json {
+                println!("{}", parx_core::report::to_json(&rep)?);
                 return Ok(());
             }
-            let mut total_entries = 0u64;
-            for p in &vols {
-                let mut f = File::open(p)?;
-                // trailer & index (compressed bincode)
-                match parx_core::index::read_trailer(&mut f).and_then(|(off, len, crc)| {
-                    parx_core::index::read_index_count(
-                        &mut f,
-                        off,
-                        len,
-                        crc,
-                        &parx_core::index::IndexLimits::default(),
-                    )
-                    .map(|n| (off, len, crc, n))
-                }) {
-                    Ok((_off, _len, _crc, n)) => {
-                        total_entries += n as u64;
-                        println!("{}: entries={}", p.file_name().unwrap().to_string_lossy(), n);
-                    }
-                    Err(_) => {
-                        println!("{}: entries=0", p.file_name().unwrap().to_string_lossy());
+            println!("source:        {} bytes in {} file(s)", rep.source_bytes, rep.files);
+            println!("parity:        {} bytes", rep.parity_bytes);
+            if rep.volume_parity_bytes > 0 {
+                println!("volume parity: {} bytes", rep.volume_parity_bytes);
+            }
+            println!("metadata:      {} bytes", rep.metadata_bytes);
+            println!("garbage:       {} bytes", rep.garbage_bytes);
+            if rep.other_bytes > 0 {
+                println!("other:         {} bytes", rep.other_bytes);
+            }
+            if rep.versions_bytes > 0 {
+                println!("versions:      {} bytes", rep.versions_bytes);
+            }
+            println!("overhead:      {:.1}%", rep.overhead_pct);
+            if rep.holes > 0 {
+                println!(
+                    "holes:         {} chunk slot(s); `parx rebalance` would free {} bytes of parity",
+                    rep.holes, rep.rebalance_reclaimable_bytes
+                );
+            }
+            for v in &rep.volumes {
+                match &v.error {
+                    Some(e) => println!("  {}: {} bytes, unreadable: {}", v.path, v.bytes, e),
+                    None => println!(
+                        "  {}: {} bytes (parity {}, metadata {}, garbage {})",
+                        v.path, v.bytes, v.parity_bytes, v.metadata_bytes, v.garbage_bytes
+                    ),
+                }
+            }
+        }
+
+        Commands::Info { json, path } => {
+            let rep = parx_core::query::info(&path)?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&rep)?);
+            } else {
+                let show = |name: &str, v: &Option<String>| {
+                    if let Some(v) = v {
+                        println!("{:<9}{}", format!("{}:", name), v);
                     }
+                };
+                show("Label", &rep.info.label);
+                show("Notes", &rep.info.notes);
+                show("Contact", &rep.info.contact);
+                if rep.info.is_empty() {
+                    println!("(no label recorded)");
+                }
+                show("Created", &rep.created_utc);
+                show("Set id", &rep.set_id);
+                if let (Some(files), Some(bytes)) = (rep.files, rep.total_bytes) {
+                    println!("Files:   {} ({} bytes)", files, bytes);
+                }
+                if rep.empty {
+                    println!("Empty set: no data to protect, nothing to repair");
+                }
+                print!("Layout:  k={} m={}", rep.stripe_k, rep.parity_shards);
+                if let Some(cs) = rep.chunk_size {
+                    print!(", {} byte chunks", cs);
+                }
+                if let Some(v) = rep.volumes {
+                    print!(", {} volume(s)", v);
+                }
+                if let Some(id) = rep.volume_id {
+                    print!(" (this is volume {})", id);
                 }
+                println!();
+                if !rep.features.is_empty() {
+                    println!("Features: {}", rep.features.join(", "));
+                }
+                for s in &rep.recovery_stubs {
+                    println!("Recovery: {} ({}) {}", s.file, s.platform, s.status);
+                }
+            }
+            let bad = rep.recovery_stubs.iter().filter(|s| !s.is_ok()).count();
+            if bad > 0 {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!(
+                        "{} recovery stub(s) missing or not matching the manifest; do not run them",
+                        bad
+                    ),
+                )
+                .into());
             }
-            println!("Volumes: {}, total entries: {}", vols.len(), total_entries);
         }
 
-        Commands::Paritycheck { dir } => {
-            let vols = list_volumes(&dir)?;
-            println!("Parity audit across {} volume(s):", vols.len());
-            if vols.is_empty() {
-                println!("  (no parity volumes found)");
+        Commands::Inspect { json, entries, volume } => {
+            let d = parx_core::query::inspect(&volume, entries)?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&d)?);
                 return Ok(());
             }
-            for p in &vols {
-                let mut f = match File::open(p) {
-                    Ok(f) => f,
-                    Err(e) => {
-                        println!(
-                            "  {:<20} entries{:>6}   index: OPEN_ERROR({})",
-                            p.file_name().unwrap().to_string_lossy(),
-                            0,
-                            e
-                        );
-                        continue;
+            println!("File:      {} ({} bytes)", volume.display(), d.file_len);
+            println!(
+                "Header:    k={} m={} entries={} flags={:#010x} ({} bytes)",
+                d.k, d.m, d.entries, d.flags, d.header_len
+            );
+            if !d.features.is_empty() {
+                println!("Features:  {}", d.features.join(", "));
+            }
+            let opt = |v: Option<u32>| v.map_or("-".to_string(), |v| v.to_string());
+            println!("Volume id: {}  chunk size: {}", opt(d.volume_id), opt(d.chunk_size));
+            println!("Extensions: {}", d.tlvs.len());
+            for t in &d.tlvs {
+                let name = t.name.unwrap_or("?");
+                println!("  {:#06x} {:<14} {:>6}  {}", t.key, name, t.len, t.value);
+            }
+            if let Some(e) = &d.tlv_error {
+                println!("  unreadable: {}", e);
+            }
+            match (&d.trailer, &d.trailer_error) {
+                (Some(t), _) => println!(
+                    "Trailer:   v{} index at {} ({} bytes) crc32 {}",
+                    t.version, t.index_offset, t.index_len, t.crc32
+                ),
+                (None, e) => println!("Trailer:   unreadable: {}", e.as_deref().unwrap_or("?")),
+            }
+            match (&d.index, &d.index_error) {
+                (Some(i), _) => {
+                    println!(
+                        "Index:     {} entries, codec {}, schema {}, crc {}",
+                        i.entries,
+                        i.codec.as_deref().unwrap_or("?"),
+                        i.schema.as_deref().unwrap_or("?"),
+                        if i.crc_ok { "ok" } else { "BAD (recovered)" }
+                    );
+                    if !i.metaparity.is_empty() {
+                        println!("Metadata parity: {}", i.metaparity.join(", "));
                     }
-                };
-                match parx_core::index::read_trailer(&mut f).and_then(|(off, len, crc)| {
-                    parx_core::index::read_index_count(
-                        &mut f,
-                        off,
-                        len,
-                        crc,
-                        &parx_core::index::IndexLimits::default(),
-                    )
-                }) {
-                    Ok(n) => {
+                    let kinds: Vec<String> =
+                        i.by_kind.iter().map(|(k, n)| format!("{} {}", n, k)).collect();
+                    println!("  {}", kinds.join(", "));
+                    for e in &i.sample {
                         println!(
-                            "  {:<20} entries{:>6}   index: OK",
-                            p.file_name().unwrap().to_string_lossy(),
-                            n
+                            "  {:<15} stripe {:>6} #{:<3} @ {:>10} +{:<8} {}",
+                            e.kind,
+                            e.stripe,
+                            e.parity_idx,
+                            e.offset,
+                            e.len,
+                            e.hash.as_deref().map_or("-", |h| &h[..16])
                         );
                     }
-                    Err(_) => {
-                        println!(
-                            "  {:<20} entries{:>6}   index: ERROR",
-                            p.file_name().unwrap().to_string_lossy(),
-                            0
-                        );
+                    if i.sample.len() < i.entries {
+                        println!("  ... {} more (--entries N)", i.entries - i.sample.len());
                     }
                 }
+                (None, e) => println!("Index:     unreadable: {}", e.as_deref().unwrap_or("?")),
             }
         }
 
-        Commands::Verify { json, follow_symlinks, manifest, root } => {
-            let policy = parx_core::path_safety::PathPolicy { follow_symlinks };
-            let report = parx_core::verify::verify_with_policy(&manifest, &root, policy)?;
+        Commands::Top { once, json, interval, dir } => {
+            use std::io::IsTerminal;
+            let every = parse_duration(&interval).context("--interval")?;
+            let clear = !once && std::io::stdout().is_terminal();
+            let mut seen = false;
+            loop {
+                let runs = parx_core::live::running(&dir)?;
+                if json {
+                    println!("{}", serde_json::to_string(&runs)?);
+                    return Ok(());
+                }
+                if runs.is_empty() {
+                    if seen {
+                        println!("all operations on {:?} finished", dir);
+                    } else {
+                        println!("no operation running on {:?}", dir);
+                    }
+                    return Ok(());
+                }
+                seen = true;
+                if clear {
+                    print!("\x1b[2J\x1b[H");
+                }
+                print!("{}", render_runs(&runs));
+                if once {
+                    return Ok(());
+                }
+                std::thread::sleep(every);
+            }
+        }
+
+        Commands::Quickcheck { dir } => {
+            let vols = set_volumes(&dir)?;
+            if vols.is_empty() {
+                println!("Volumes: 0, total entries: 0");
+                return Ok(());
+            }
+            // With the set's manifest alongside, volumes of another set or
+            // generation are flagged
+            let set = Some(dir.join("manifest.json"))
+                .filter(|mp| mp.exists())
+                .and_then(|mp| parx_core::manifest::load(&mp).ok())
+                .map(|(mf, _)| mf.set_tag())
+                .unwrap_or_default();
+            let rep = parx_core::parity_audit::quickcheck(&vols, set);
+            for v in &rep.volumes {
+                match (v.index.as_str(), &v.error, &v.set_id) {
+                    ("foreign", _, Some(theirs)) => println!(
+                        "{}: foreign volume (set {}; manifest.json is set {})",
+                        v.volume,
+                        theirs,
+                        set.id.map(|s| s.to_string()).unwrap_or_default()
+                    ),
+                    ("stale", _, _) => println!(
+                        "{}: stale volume (generation {}; manifest.json is generation {})",
+                        v.volume,
+                        v.generation.map_or("none".to_string(), |g| g.to_string()),
+                        set.generation.unwrap_or_default()
+                    ),
+                    ("open_error", Some(e), _) => bail!("open {}: {}", v.volume, e),
+                    ("unsupported", Some(e), _) => println!("{}: unsupported: {}", v.volume, e),
+                    _ => println!("{}: entries={}", v.volume, v.entries),
+                }
+            }
+            println!("Volumes: {}, total entries: {}", vols.len(), rep.total_entries);
+            if rep.foreign > 0 {
+                bail!("{} foreign volume(s) in {:?}", rep.foreign, dir);
+            }
+            if rep.stale > 0 {
+                bail!("{} stale volume(s) in {:?}", rep.stale, dir);
+            }
+        }
+
+        Commands::Paritycheck { json, deep, no_cache, dir } => {
+            use parx_core::paritycache::ParityCache;
+            let vols = set_volumes(&dir)?;
+            let cache =
+                if no_cache || !deep { ParityCache::default() } else { ParityCache::load(&dir) };
+            let (mut rep, next) = parx_core::parity_audit::paritycheck(&vols, &cache, deep);
+            // With the set's manifest alongside, its failure domains
+            if let Some((mf, _)) = Some(dir.join("manifest.json"))
+                .filter(|mp| mp.exists())
+                .and_then(|mp| parx_core::manifest::load(&mp).ok())
+            {
+                rep.domains = parx_core::domains::of_manifest(&mf)?;
+            }
+            if deep {
+                if let Err(e) = next.save(&dir) {
+                    eprintln!(
+                        "warning: could not update {}: {:#}",
+                        parx_core::paritycache::CACHE_FILE,
+                        e
+                    );
+                }
+            }
             if json {
-                println!("{}", serde_json::to_string(&report)?);
+                println!("{}", parx_core::report::to_json(&rep)?);
+                return Ok(());
+            }
+            println!("Parity audit across {} volume(s):", rep.volumes.len());
+            if rep.volumes.is_empty() {
+                println!("  (no parity volumes found)");
+            }
+            for v in &rep.volumes {
+                let index = match (v.index.as_str(), &v.error) {
+                    ("ok", _) => "OK".to_string(),
+                    ("unsupported", Some(e)) => format!("UNSUPPORTED ({})", e),
+                    ("open_error", Some(e)) => format!("OPEN_ERROR({})", e),
+                    _ => "ERROR".to_string(),
+                };
+                let shards = match v.shards_bad {
+                    Some(0) => "   shards: OK".to_string(),
+                    Some(n) => format!("   shards: BAD({})", n),
+                    None => String::new(),
+                };
+                println!(
+                    "  {:<20} entries{:>6}   index: {}{}{}",
+                    v.volume,
+                    v.entries,
+                    index,
+                    shards,
+                    if v.cached { " (cached)" } else { "" }
+                );
+            }
+            for d in &rep.domains {
+                println!(
+                    "  {}: {} group(s); can lose any {} ({} parity shard(s) per stripe left at worst after losing one{})",
+                    d.level,
+                    d.groups,
+                    d.tolerates,
+                    d.parity_left_after_one,
+                    d.worst_group.as_deref().map_or(String::new(), |g| format!(", {}", g))
+                );
+            }
+            if rep.shards_bad > 0 {
+                println!(
+                    "{} damaged parity shard(s); run `parx vol heal` to regenerate",
+                    rep.shards_bad
+                );
+            }
+        }
+
+        Commands::Verify {
+            json,
+            format,
+            follow_symlinks,
+            remote,
+            io_timeout,
+            io_retries,
+            io_backoff,
+            from_volume,
+            verify_key,
+            hash_key,
+            auto_throttle,
+            max_open_files,
+            older_than,
+            detect_moves,
+            drop_cache,
+            set,
+            manifest,
+            root,
+        } => {
+            let json = json || format == OutputFormat::Json;
+            let manifest = match (&set, manifest) {
+                (Some(name), Some(dir)) => Some(parx_core::sets::manifest_of(&dir, name)?),
+                (_, manifest) => manifest,
+            };
+            let opts = parx_core::verify::VerifyOptions {
+                older_than,
+                detect_moves,
+                policy: parx_core::path_safety::PathPolicy { follow_symlinks },
+                io_timeout,
+                retry: parx_core::retry::RetryPolicy { retries: io_retries, backoff: io_backoff },
+                verify_key: verify_key
+                    .as_deref()
+                    .map(parx_core::sign::load_verifying_key)
+                    .transpose()?,
+                throttle: throttle(auto_throttle)?,
+                max_open_files,
+                progress: None,
+                hash_key: hash_key
+                    .as_deref()
+                    .map(parx_core::manifest::HashKey::load)
+                    .transpose()?,
+                drop_cache,
+            };
+            let report = match (from_volume, manifest, remote, root) {
+                // The lone positional is ROOT here
+                (Some(vol), Some(root), _, None) => {
+                    parx_core::verify::verify_from_volume(&vol, &root, &opts)?
+                }
+                (Some(_), ..) => bail!("--from-volume takes only ROOT, not a manifest"),
+                (None, Some(manifest), Some(url), _) => {
+                    parx_core::verify::verify_remote(&manifest, &url)?
+                }
+                (None, Some(manifest), None, Some(root)) => {
+                    parx_core::verify::verify_with_options(&manifest, &root, &opts)?
+                }
+                _ => bail!("either ROOT or --remote is required"),
+            };
+            warn_manifest_recovery(&report.manifest_recovery);
+            warn_stalled(&report.stalled_files);
+            warn_unreachable(&[], report.io_retries, report.io_recovered);
+            for l in &report.symlinks_bad {
+                eprintln!("warn: symlink {} is missing or points elsewhere", l);
+            }
+            for m in &report.moved_files {
+                eprintln!("warn: {} was moved to {} (repair --fix-paths records it)", m.from, m.to);
+            }
+            warn_throttled(report.throttled_ms);
+            if let Some(short) = &report.parity_shortfall {
+                warn_parity_shortfall(short);
+            }
+            if json {
+                println!("{}", parx_core::report::to_json(&report)?);
+            } else if verify_exit_code(&report).is_none() {
+                if report.files_skipped > 0 {
+                    println!("OK ({} file(s) verified recently, skipped)", report.files_skipped);
+                } else {
+                    println!("OK");
+                }
             } else {
-                println!("OK");
+                println!(
+                    "DAMAGED: chunks_ok={} chunks_bad={} merkle_ok={} symlinks_bad={}",
+                    report.chunks_ok,
+                    report.chunks_bad,
+                    report.merkle_ok,
+                    report.symlinks_bad.len()
+                );
+                for d in &report.damaged_files {
+                    use parx_core::verify::DamageKind;
+                    let what = match d.kind {
+                        DamageKind::ContentMismatch => "corrupt",
+                        DamageKind::Truncated => "truncated",
+                        DamageKind::Missing => "missing",
+                        DamageKind::PermissionDenied => "permission denied",
+                        DamageKind::Unreadable => "unreadable",
+                        DamageKind::Unreachable => "unreachable",
+                    };
+                    match &d.error {
+                        Some(e) => println!("  {}: {} ({})", what, d.path, e),
+                        None => println!("  {}: {} ({} bad chunk(s))", what, d.path, d.chunks_bad),
+                    }
+                }
+                if report.chunks_displaced > 0 {
+                    println!(
+                        "  {} chunk(s) only moved within their file; repair moves them back",
+                        report.chunks_displaced
+                    );
+                }
+            }
+            if let Some(code) = verify_exit_code(&report) {
+                std::process::exit(code);
             }
         }
 
-        Commands::Audit { json, follow_symlinks: _, manifest, root: _ } => {
-            let mf: parx_core::manifest::Manifest =
-                serde_json::from_reader(File::open(&manifest)?)?;
-            let ar = parx_core::parity_audit::audit(std::path::Path::new(&mf.parity_dir))?;
+        Commands::CheckAll { json, follow_symlinks, no_cache, hash_key, set, manifest, root } => {
+            use parx_core::checkall::{CheckAllOptions, Grade};
+            let manifest = match &set {
+                Some(name) => parx_core::sets::manifest_of(&manifest, name)?,
+                None => manifest,
+            };
+            let verify = parx_core::verify::VerifyOptions {
+                policy: parx_core::path_safety::PathPolicy { follow_symlinks },
+                hash_key: hash_key
+                    .as_deref()
+                    .map(parx_core::manifest::HashKey::load)
+                    .transpose()?,
+                ..Default::default()
+            };
+            let rep = parx_core::checkall::check_all(
+                &manifest,
+                &root,
+                &CheckAllOptions { verify, no_cache },
+            )?;
             if json {
-                println!("{}", serde_json::to_string(&ar)?);
+                println!("{}", parx_core::report::to_json(&rep)?);
             } else {
-                println!("Repairable: YES");
+                let grade = match rep.grade {
+                    Grade::Green => "GREEN",
+                    Grade::Amber => "AMBER",
+                    Grade::Red => "RED",
+                };
+                println!("Health: {}", grade);
+                for r in &rep.reasons {
+                    println!("  - {}", r);
+                }
+                let v = &rep.verify;
+                println!(
+                    "  verify:      {} chunk(s) ok, {} bad, merkle {}",
+                    v.chunks_ok,
+                    v.chunks_bad,
+                    if v.merkle_ok { "ok" } else { "MISMATCH" }
+                );
+                println!(
+                    "  quickcheck:  {} volume(s), {} entries, {} foreign, {} stale",
+                    rep.quickcheck.volumes.len(),
+                    rep.quickcheck.total_entries,
+                    rep.quickcheck.foreign,
+                    rep.quickcheck.stale
+                );
+                println!("  paritycheck: {} damaged shard(s)", rep.paritycheck.shards_bad);
+                for d in &rep.paritycheck.domains {
+                    println!(
+                        "  domains:     {}: can lose any {} of {} group(s)",
+                        d.level, d.tolerates, d.groups
+                    );
+                }
+                println!(
+                    "  geometry:    {} stripe(s), {} volume(s) checked, {} problem(s)",
+                    rep.geometry.stripes,
+                    rep.geometry.volumes_checked,
+                    rep.geometry.problems.len()
+                );
+            }
+            match rep.grade {
+                Grade::Green => {}
+                Grade::Amber => std::process::exit(90),
+                Grade::Red => std::process::exit(91),
             }
         }
 
-        Commands::Repair { json, follow_symlinks, manifest, root } => {
+        Commands::Audit {
+            json,
+            format,
+            follow_symlinks,
+            fs_hints,
+            max_open_files,
+            manifest,
+            root,
+        } => {
             let policy = parx_core::path_safety::PathPolicy { follow_symlinks };
-            let rr = parx_core::repair::repair_with_policy(&manifest, &root, policy)?;
+            let opts =
+                parx_core::audit::AuditOptions { fs_hints, max_open_files, ..Default::default() };
+            let ar = parx_core::audit::audit_with(&manifest, &root, policy, &opts)?;
+            warn_manifest_recovery(&ar.manifest_recovery);
+            if json || format == OutputFormat::Json {
+                println!("{}", parx_core::report::to_json(&ar)?);
+            } else {
+                println!(
+                    "{} stripe(s), {} chunk(s) ok, {} bad",
+                    ar.stripes, ar.chunks_ok, ar.chunks_bad
+                );
+                if ar.files_trusted > 0 {
+                    println!("  {} unchanged file(s) trusted to the filesystem", ar.files_trusted);
+                }
+                for d in &ar.damaged {
+                    println!(
+                        "  stripe {}: {} bad chunk(s), {} parity shard(s) usable{}",
+                        d.stripe,
+                        d.bad_chunks.len(),
+                        d.parity_available,
+                        if d.repairable { "" } else { " (NOT repairable)" }
+                    );
+                }
+                println!("Repairable: {}", if ar.repairable { "YES" } else { "NO" });
+            }
+        }
+
+        Commands::Repair {
+            json,
+            format,
+            follow_symlinks,
+            as_of,
+            volumes,
+            volumes_url,
+            audit_key,
+            chown_map,
+            from_scrub,
+            io_timeout,
+            io_retries,
+            io_backoff,
+            restore_metadata,
+            verify_key,
+            hash_key,
+            auto_throttle,
+            reuse_hashes,
+            no_hash_cache,
+            fix_paths,
+            extract_chunks,
+            if_in_use,
+            set,
+            manifest,
+            root,
+        } => {
+            let manifest = match &set {
+                Some(name) => parx_core::sets::manifest_of(&manifest, name)?,
+                None => manifest,
+            };
+            let policy = parx_core::path_safety::PathPolicy { follow_symlinks };
+            let json = json || format == OutputFormat::Json;
+            if let Some(version) = as_of {
+                let (mf, _) = parx_core::manifest::load(&manifest)?;
+                let parity_dir = Path::new(&mf.parity_dir);
+                let rr = parx_core::versions::restore_as_of(parity_dir, version, &root, policy)?;
+                warn_metadata(&rr.metadata);
+                if json {
+                    println!("{}", parx_core::report::to_json(&rr)?);
+                }
+                if rr.chunks_failed > 0 {
+                    bail!(
+                        "restore to version {}: {} chunk(s) unrecoverable",
+                        version,
+                        rr.chunks_failed
+                    );
+                }
+                return Ok(());
+            }
+            for url in &volumes_url {
+                let (loc, _) = parse_volume_location(url);
+                if !(loc.starts_with("http://") || loc.starts_with("https://")) {
+                    bail!("--volumes-url {:?}: expected an http:// or https:// URL", url);
+                }
+            }
+            let mut extra_dirs = Vec::new();
+            let mut extra_sources = Vec::new();
+            for spec in volumes.iter().chain(&volumes_url) {
+                let (loc, cost) = parse_volume_location(spec);
+                if loc.starts_with("http://") || loc.starts_with("https://") {
+                    let (mf, _) = parx_core::manifest::load(&manifest)?;
+                    let cost = cost.unwrap_or(parx_core::storage::ReadCost::REMOTE);
+                    extra_sources.push(parx_core::repair::VolumeSource::http_of(loc, &mf, cost)?);
+                } else if let Some(cost) = cost {
+                    extra_sources.push(parx_core::repair::VolumeSource::dir(Path::new(loc), cost)?);
+                } else {
+                    extra_dirs.push(PathBuf::from(loc));
+                }
+            }
+            let opts = parx_core::repair::RepairOptions {
+                extra_dirs,
+                extra_sources,
+                audit_key: audit_key
+                    .as_deref()
+                    .map(parx_core::sign::load_signing_key)
+                    .transpose()?,
+                verify_key: verify_key
+                    .as_deref()
+                    .map(parx_core::sign::load_verifying_key)
+                    .transpose()?,
+                chown_map: chown_map
+                    .as_deref()
+                    .map(parx_core::meta::ChownMap::parse)
+                    .transpose()?
+                    .unwrap_or_default(),
+                only_chunks: from_scrub
+                    .as_deref()
+                    .map(|p| scrub_targets(p, &manifest))
+                    .transpose()?,
+                io_timeout,
+                retry: parx_core::retry::RetryPolicy { retries: io_retries, backoff: io_backoff },
+                restore_metadata,
+                throttle: throttle(auto_throttle)?,
+                reuse_hashes: (!no_hash_cache).then_some(reuse_hashes),
+                fix_paths,
+                extract_chunks,
+                progress: None,
+                hash_key: hash_key
+                    .as_deref()
+                    .map(parx_core::manifest::HashKey::load)
+                    .transpose()?,
+                if_in_use: match if_in_use {
+                    IfInUseArg::Skip => parx_core::inuse::IfInUse::Skip,
+                    IfInUseArg::Wait => parx_core::inuse::IfInUse::Wait,
+                    IfInUseArg::Force => parx_core::inuse::IfInUse::Force,
+                },
+            };
+            let rr = parx_core::repair::repair_with_options(&manifest, &root, policy, &opts)?;
+            for m in &rr.moved_files {
+                eprintln!("repair: {} moved to {}, manifest updated", m.from, m.to);
+            }
+            warn_volumes(&rr.volumes_restored, &rr.volumes_unrecoverable);
+            for v in &rr.foreign_volumes {
+                eprintln!("warn: {} is a foreign volume (another set); not used", v);
+            }
+            for v in &rr.stale_volumes {
+                eprintln!(
+                    "warn: {} is a stale volume (another generation of the set); not used",
+                    v
+                );
+            }
+            warn_manifest_recovery(&rr.manifest_recovery);
+            warn_stalled(&rr.stalled_files);
+            warn_unreachable(&rr.unreachable_files, rr.io_retries, rr.io_recovered);
+            for f in &rr.in_use_files {
+                eprintln!("warn: {} is {}; not repaired (see --if-in-use)", f.path, f.by);
+            }
+            warn_metadata(&rr.metadata);
+            warn_throttled(rr.throttled_ms);
+            for l in &rr.symlinks_failed {
+                eprintln!("warn: could not recreate symlink {}", l);
+            }
+            if let Some(dir) = &opts.extract_chunks {
+                eprintln!(
+                    "repair: {} chunk(s) written to {:?}, tree left untouched",
+                    rr.extracted_chunks, dir
+                );
+            }
             if json {
-                println!("{}", serde_json::to_string(&rr)?);
+                println!("{}", parx_core::report::to_json(&rr)?);
             }
             // default: silent success for tests
         }
 
+        Commands::Sets { cmd: SetsCommands::List { json, dir } } => {
+            use parx_core::sets::SetHealth;
+            let rep = parx_core::sets::list(&dir)?;
+            if json {
+                println!("{}", parx_core::report::to_json(&rep)?);
+                return Ok(());
+            }
+            if rep.sets.is_empty() {
+                println!("no named sets in {:?}", dir);
+            }
+            for s in &rep.sets {
+                if s.health == SetHealth::Unreadable {
+                    println!("{}: unreadable: {}", s.name, s.error.as_deref().unwrap_or("?"));
+                    continue;
+                }
+                let health = match (&s.health, &s.parity_shortfall) {
+                    (SetHealth::Ok, _) | (_, None) => "ok".to_string(),
+                    (h, Some(short)) => format!(
+                        "{} ({} stripe(s) short of parity, {} with none left)",
+                        if *h == SetHealth::Unprotected { "unprotected" } else { "degraded" },
+                        short.stripes_degraded,
+                        short.stripes_unprotected
+                    ),
+                };
+                println!(
+                    "{}: {} file(s), {} bytes; parity {} bytes in {} volume(s); {}",
+                    s.name, s.files, s.data_bytes, s.parity_bytes, s.volumes, health
+                );
+            }
+        }
+
+        Commands::Vol {
+            cmd: VolCommands::Heal { json, follow_symlinks, audit_key, manifest, root },
+        } => {
+            let policy = parx_core::path_safety::PathPolicy { follow_symlinks };
+            let key = audit_key.as_deref().map(parx_core::sign::load_signing_key).transpose()?;
+            let hr = parx_core::heal::heal(&manifest, &root, policy, key.as_ref())?;
+            warn_volumes(&hr.volumes_restored, &hr.volumes_unrecoverable);
+            if json {
+                println!("{}", parx_core::report::to_json(&hr)?);
+            } else {
+                println!(
+                    "checked {} shard(s) in {} volume(s): {} bad, {} healed, {} unhealable",
+                    hr.shards_checked,
+                    hr.volumes_scanned,
+                    hr.shards_bad,
+                    hr.shards_healed,
+                    hr.shards_unhealable
+                );
+                for v in &hr.unreadable_volumes {
+                    println!("  {}: index unreadable (re-create needed)", v);
+                }
+            }
+            if hr.shards_unhealable > 0 {
+                bail!(
+                    "{} shard(s) could not be regenerated; repair the source data first",
+                    hr.shards_unhealable
+                );
+            }
+        }
+
+        Commands::ExportHashes { format, manifest } => {
+            let (mf, recovery) = parx_core::manifest::load(&manifest)?;
+            warn_manifest_recovery(&recovery);
+            let format = match format {
+                HashExportFormat::Jsonl => parx_core::export::ExportFormat::Jsonl,
+                HashExportFormat::Borg => parx_core::export::ExportFormat::Borg,
+                HashExportFormat::Casync => parx_core::export::ExportFormat::Casync,
+            };
+            let mut out = std::io::BufWriter::new(std::io::stdout().lock());
+            parx_core::export::export_hashes(&mf, format, &mut out)?;
+            std::io::Write::flush(&mut out)?;
+        }
+
+        Commands::WhichStripe { json, manifest, file, offset } => {
+            let off = match offset.strip_prefix("0x") {
+                Some(hex) => u64::from_str_radix(hex, 16),
+                None => offset.parse(),
+            }
+            .with_context(|| format!("offset {:?}", offset))?;
+            let loc = parx_core::query::which_stripe(&manifest, &file, off)?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&loc)?);
+                return Ok(());
+            }
+            println!(
+                "{} @ {}: chunk {} (bytes {}..{}), stripe {} position {}/{}",
+                loc.rel_path,
+                loc.offset,
+                loc.chunk,
+                loc.chunk_file_offset,
+                loc.chunk_file_offset + loc.chunk_len as u64,
+                loc.stripe,
+                loc.stripe_pos,
+                loc.stripe_k
+            );
+            if let Some(id) = loc.chunk_id {
+                println!("chunk id: file {} chunk {}", id.file, id.ordinal);
+            }
+            println!("stripe members:");
+            for m in &loc.members {
+                println!(
+                    "  chunk {:<8} {} [{}..{})",
+                    m.chunk,
+                    m.rel_path,
+                    m.file_offset,
+                    m.file_offset + m.len as u64
+                );
+            }
+            println!("parity shards (need {}):", loc.parity_needed);
+            for s in &loc.parity {
+                println!(
+                    "  p{:<3} {} @ {} len {}  {}",
+                    s.parity_idx, s.volume, s.offset, s.len, s.status
+                );
+            }
+            match loc.outer_group {
+                Some(g) => {
+                    println!("outer group {} ({} shard(s)):", g, loc.outer.len());
+                    for s in &loc.outer {
+                        println!(
+                            "  o{:<3} {} @ {} len {}  {}",
+                            s.parity_idx, s.volume, s.offset, s.len, s.status
+                        );
+                    }
+                }
+                None => println!("outer parity: none"),
+            }
+            for v in &loc.unreadable_volumes {
+                println!("warn: {}: index unreadable, shards unknown", v);
+            }
+        }
+
+        Commands::Contains { json, filter, file } => {
+            let flt = parx_core::filter::ChunkFilter::load(&filter)?;
+            let rep = parx_core::filter::contains(&flt, &file)?;
+            if json {
+                println!("{}", serde_json::to_string(&rep)?);
+            } else {
+                let verdict = if rep.found == 0 && rep.chunks > 0 {
+                    "not in the set"
+                } else if rep.all() {
+                    "probably in the set"
+                } else {
+                    "partly in the set"
+                };
+                println!(
+                    "{}: {} ({}/{} chunks, {}/{} bytes)",
+                    file.display(),
+                    verdict,
+                    rep.found,
+                    rep.chunks,
+                    rep.bytes_found,
+                    rep.bytes
+                );
+            }
+        }
+
+        Commands::AuditLog { cmd: AuditLogCommands::Verify { json, pubkey, path } } => {
+            let log = if path.is_dir() { path.join(parx_core::audit_log::AUDIT_LOG) } else { path };
+            let vk = pubkey.as_deref().map(parx_core::sign::load_verifying_key).transpose()?;
+            let rep = parx_core::audit_log::verify(&log, vk.as_ref())?;
+            if json {
+                println!("{}", serde_json::to_string(&rep)?);
+            } else if rep.is_ok() {
+                println!("OK: {} entries ({} signed)", rep.entries, rep.signed);
+                for s in &rep.signers {
+                    println!("  signer {}", s);
+                }
+            } else {
+                println!("BROKEN: {}", rep.error.as_deref().unwrap_or("unknown error"));
+            }
+            if !rep.is_ok() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("audit log chain broken after {} valid entries", rep.entries),
+                )
+                .into());
+            }
+        }
+
         Commands::Split { input, out_dir, n } => {
             if n == 0 {
                 anyhow::bail!("n must be > 0");
@@ -549,10 +2595,239 @@ fn run() -> Result<()> {
                 );
             }
         }
+
+        Commands::Serve { socket, no_repair, follow_symlinks, manifest, root } => {
+            let opts = parx_core::serve::ServeOptions {
+                policy: parx_core::path_safety::PathPolicy { follow_symlinks },
+                repair: !no_repair,
+            };
+            let proxy = parx_core::serve::IntegrityProxy::open(&manifest, &root, opts)?;
+            #[cfg(unix)]
+            {
+                eprintln!("serving verified reads on {:?}", socket);
+                std::sync::Arc::new(proxy).serve(&socket)?;
+            }
+            #[cfg(not(unix))]
+            {
+                let _ = (proxy, socket);
+                bail!("`parx serve` needs Unix domain sockets");
+            }
+        }
+
+        Commands::Fsck { json, follow_symlinks, force, manifest, root, volume } => {
+            let manifest = manifest.unwrap_or_else(|| {
+                volume.parent().unwrap_or(Path::new(".")).join(parx_core::manifest::MANIFEST_JSON)
+            });
+            let opts = parx_core::fsck::FsckOptions {
+                root,
+                policy: parx_core::path_safety::PathPolicy { follow_symlinks },
+                force,
+            };
+            let fr = parx_core::fsck::fsck(&volume, &manifest, &opts)?;
+            if json {
+                println!("{}", parx_core::report::to_json(&fr)?);
+            } else if fr.index_intact {
+                println!("{}: index intact, nothing to do (--force rebuilds it anyway)", fr.volume);
+            } else {
+                println!(
+                    "{}: rebuilt index with {} shard(s); {} missing",
+                    fr.volume, fr.shards_found, fr.shards_missing
+                );
+            }
+            if fr.stripes_unreadable > 0 {
+                eprintln!(
+                    "warn: source data of {} stripe(s) is damaged; their shards could not be \
+                     identified (repair, then run fsck --force)",
+                    fr.stripes_unreadable
+                );
+            }
+        }
+
+        Commands::RecoverManifest { force, parx_dir } => {
+            let json_path = parx_dir.join(parx_core::manifest::MANIFEST_JSON);
+            let intact = std::fs::read(&json_path).ok().is_some_and(|b| {
+                serde_json::from_slice::<parx_core::manifest::Manifest>(&b).is_ok()
+            });
+            if intact && !force {
+                bail!("{:?} is intact; pass --force to overwrite it", json_path);
+            }
+            let (mf, vol, rep) = parx_core::manifest_backup::find(&parx_dir)?;
+            parx_core::manifest::save(&mf, &parx_dir)?;
+            let from = vol.file_name().unwrap_or_default().to_string_lossy();
+            match rep {
+                None => {
+                    println!("recovered manifest.json from {} ({} files)", from, mf.files.len())
+                }
+                Some(r) => {
+                    println!(
+                        "recovered manifest.json from {} with damage: {} of {} file record(s)",
+                        from,
+                        r.files_recovered,
+                        r.files_expected.map(|n| n.to_string()).unwrap_or_else(|| "?".into())
+                    );
+                    if !r.lost_files.is_empty() {
+                        eprintln!(
+                            "warn: lost file records (manifest positions): {:?}",
+                            r.lost_files
+                        );
+                        eprintln!("warn: chunk ranges without metadata: {:?}", r.lost_chunk_ranges);
+                        if !r.lost_file_ids.is_empty() {
+                            eprintln!("warn: ids of the lost files: {:?}", r.lost_file_ids);
+                        }
+                    }
+                }
+            }
+        }
+
+        Commands::Versions { json, dir } => {
+            let graph = parx_core::versions::VersionGraph::load(&dir)?;
+            if json {
+                println!("{}", serde_json::to_string(&graph)?);
+            } else if graph.versions.is_empty() {
+                println!("(no versions recorded)");
+            } else {
+                for v in &graph.versions {
+                    println!(
+                        "v{:<4} parent={:<6} chunks={:<8} reused={:<8} {} {}",
+                        v.id,
+                        v.parent.map(|p| format!("v{}", p)).unwrap_or_else(|| "-".into()),
+                        v.total_chunks,
+                        v.reused_chunks,
+                        v.created_utc,
+                        match v.dir.as_deref() {
+                            Some(".") => "(live)",
+                            Some(_) => "(retained)",
+                            None => "(pruned)",
+                        }
+                    );
+                }
+            }
+        }
+
+        Commands::Keygen { private_key, public_key } => {
+            let vk = parx_core::sign::generate_keypair(&private_key, &public_key)?;
+            eprintln!("keygen: fingerprint {}", parx_core::sign::fingerprint(&vk));
+        }
+
+        Commands::Publish { sign_key, bao, out, manifest, root } => {
+            let key = parx_core::sign::load_signing_key(&sign_key)?;
+            let opts = parx_core::publish::PublishOptions { bao };
+            let bundle = parx_core::publish::publish(&manifest, &root, &key, &opts)?;
+            let out = out.unwrap_or_else(|| {
+                manifest.parent().unwrap_or(Path::new(".")).join("bundle.parx-pub")
+            });
+            parx_core::publish::write_bundle(&bundle, &out)?;
+            eprintln!(
+                "publish: {} file(s) -> {:?} (signer {})",
+                bundle.body.witnesses.len(),
+                out,
+                parx_core::sign::fingerprint(&key.verifying_key())
+            );
+        }
+
+        Commands::CheckDownload { json, pubkey, dir, bundle } => {
+            let expected = pubkey.map(|p| parx_core::sign::load_verifying_key(&p)).transpose()?;
+            let rep = parx_core::publish::check_download(&dir, &bundle, expected.as_ref())?;
+            if json {
+                println!("{}", serde_json::to_string(&rep)?);
+            } else {
+                for p in &rep.files_missing {
+                    println!("MISSING {}", p);
+                }
+                for p in &rep.files_bad {
+                    println!("BAD     {}", p);
+                    for d in rep.damaged.iter().filter(|d| &d.rel_path == p) {
+                        println!("        bytes {}..{}", d.offset, d.offset + d.len);
+                    }
+                }
+                for p in &rep.files_error {
+                    println!("ERROR   {}", p);
+                }
+                if rep.is_ok() {
+                    println!("OK ({} files, signer {})", rep.files_ok, rep.signer);
+                }
+            }
+            if !rep.authenticated {
+                eprintln!(
+                    "warning: the bundle was checked against the key it carries ({}), which \
+                     anyone can re-sign with; pass --pubkey with the publisher's key to \
+                     authenticate it",
+                    rep.signer
+                );
+            }
+            if !rep.is_ok() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!(
+                        "download check failed: {} bad, {} missing, {} unreadable",
+                        rep.files_bad.len(),
+                        rep.files_missing.len(),
+                        rep.files_error.len()
+                    ),
+                )
+                .into());
+            }
+        }
+
+        Commands::Pack { with_volumes, out, parx_dir } => {
+            let opts = parx_core::pack::PackOptions { volumes: with_volumes };
+            let rep = if out == Path::new("-") {
+                parx_core::pack::pack(&parx_dir, std::io::stdout().lock(), &opts)?
+            } else {
+                let f = std::fs::File::create(&out).with_context(|| format!("create {:?}", out))?;
+                parx_core::pack::pack(&parx_dir, std::io::BufWriter::new(f), &opts)?
+            };
+            eprintln!(
+                "pack: {} file(s), {} bytes{} -> {:?}",
+                rep.files,
+                rep.bytes,
+                if rep.volumes { " (with volumes)" } else { "" },
+                out
+            );
+        }
+
+        Commands::Unpack { check, force, bundle, dest } => {
+            let opts = parx_core::pack::UnpackOptions { check_only: check, force };
+            let dest = dest.unwrap_or_default();
+            let env = if bundle == Path::new("-") {
+                parx_core::pack::unpack(std::io::stdin().lock(), &dest, &opts)?
+            } else {
+                let f =
+                    std::fs::File::open(&bundle).with_context(|| format!("open {:?}", bundle))?;
+                parx_core::pack::unpack(std::io::BufReader::new(f), &dest, &opts)?
+            };
+            let bytes: u64 = env.members.iter().map(|m| m.size).sum();
+            if check {
+                println!("OK ({} file(s), {} bytes)", env.members.len(), bytes);
+            } else {
+                println!("unpacked {} file(s), {} bytes into {:?}", env.members.len(), bytes, dest);
+                if !env.volumes {
+                    eprintln!(
+                        "note: the bundle holds no volumes; copy them into {:?} to repair",
+                        dest
+                    );
+                }
+            }
+        }
     }
     Ok(())
 }
 
+/// Exit code of a verify run that found damage (see docs/exit-codes.md): the
+/// highest of the classes present, 80 for damage not tied to one file
+fn verify_exit_code(report: &parx_core::verify::VerifyReport) -> Option<i32> {
+    use parx_core::verify::DamageKind;
+    let worst = report.damaged_files.iter().map(|d| match d.kind {
+        DamageKind::ContentMismatch => 80,
+        DamageKind::Truncated => 81,
+        DamageKind::Missing => 82,
+        DamageKind::PermissionDenied | DamageKind::Unreadable => 83,
+        DamageKind::Unreachable => 84,
+    });
+    let damaged = report.chunks_bad > 0 || !report.merkle_ok || !report.symlinks_bad.is_empty();
+    worst.max().or(damaged.then_some(80))
+}
+
 fn exit_code_for_error(e: &anyhow::Error) -> i32 {
     // POSIX-ish mapping, inspired by sysexits.h where feasible
     // EX_OK=0, EX_USAGE=64, EX_DATAERR=65, EX_NOINPUT=66, EX_CANTCREAT=73, EX_IOERR=74, EX_CONFIG=78, EX_NOPERM=77
@@ -567,7 +2842,9 @@ fn exit_code_for_error(e: &anyhow::Error) -> i32 {
             _ => 74,                // EX_IOERR for other I/O
         };
     }
-    if e.downcast_ref::<serde_json::Error>().is_some() {
+    if e.downcast_ref::<serde_json::Error>().is_some()
+        || e.downcast_ref::<parx_core::volume::FeatureError>().is_some()
+    {
         return 65; // EX_DATAERR
     }
     // Default: generic software error
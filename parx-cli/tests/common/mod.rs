@@ -0,0 +1,11 @@
+//! Helpers shared by the CLI tests.
+
+use assert_cmd::prelude::*;
+use std::process::Command;
+
+/// The `parx` binary, run in `dir`.
+pub fn parx(dir: &std::path::Path) -> Command {
+    let mut cmd = Command::cargo_bin("parx").unwrap();
+    cmd.current_dir(dir);
+    cmd
+}
@@ -0,0 +1,83 @@
+#![cfg(unix)]
+
+use assert_cmd::prelude::*;
+use assert_fs::prelude::*;
+use predicates::prelude::*;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::io::{Seek, SeekFrom, Write};
+
+mod common;
+use common::parx;
+
+fn create(dir: &std::path::Path, extra: &[&str]) -> assert_cmd::assert::Assert {
+    let mut args = vec![
+        "create",
+        "--parity",
+        "50",
+        "--stripe-k",
+        "4",
+        "--output",
+        ".parx",
+        "--volume-sizes",
+        "1M",
+        "--pre-hook",
+        "echo \"pre $PARX_INPUT\" >> hook.log",
+        "--post-hook",
+        "echo post >> hook.log",
+    ];
+    args.extend_from_slice(extra);
+    args.push("db");
+    parx(dir).args(&args).assert()
+}
+
+#[test]
+fn database_preset_aligns_chunks_and_runs_hooks() {
+    let td = assert_fs::TempDir::new().unwrap();
+    let mut rng = StdRng::seed_from_u64(8);
+    let pages: Vec<u8> = (0..16 * 8192).map(|_| rng.gen()).collect();
+    td.child("db/base.sqlite").write_binary(&pages).unwrap();
+
+    // 12 KiB chunks would split 8 KiB pages; the post hook still resumes the database
+    create(td.path(), &["--preset", "database", "--chunk-size", "12288"])
+        .failure()
+        .stderr(predicate::str::contains("not a multiple of --align 8192"));
+    td.child("hook.log").assert("pre db\npost\n");
+
+    std::fs::remove_file(td.child("hook.log").path()).unwrap();
+    create(td.path(), &["--preset", "database", "--chunk-size", "16384"]).success();
+    td.child("hook.log").assert("pre db\npost\n");
+    let mf: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(td.child(".parx/manifest.json").path()).unwrap())
+            .unwrap();
+    assert_eq!(mf["ext"]["3"], serde_json::json!([0, 32, 0, 0]));
+
+    // A torn page lands in exactly one chunk and comes back as the original image
+    let mut f =
+        std::fs::OpenOptions::new().write(true).open(td.child("db/base.sqlite").path()).unwrap();
+    f.seek(SeekFrom::Start(5 * 8192)).unwrap();
+    f.write_all(&[0u8; 8192]).unwrap();
+    drop(f);
+    let out = parx(td.path())
+        .args(["repair", "--json", ".parx/manifest.json", "."])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let rr: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(rr["repaired_chunks"], 1);
+    assert_eq!(std::fs::read(td.child("db/base.sqlite").path()).unwrap(), pages);
+}
+
+#[test]
+fn failing_pre_hook_aborts_create() {
+    let td = assert_fs::TempDir::new().unwrap();
+    td.child("db/base.sqlite").write_binary(&[1u8; 8192]).unwrap();
+    parx(td.path())
+        .args(["create", "--pre-hook", "exit 3", "--post-hook", "touch post", "db"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("pre-hook"));
+    td.child(".parx/manifest.json").assert(predicate::path::missing());
+    td.child("post").assert(predicate::path::missing());
+}
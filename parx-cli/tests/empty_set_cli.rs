@@ -0,0 +1,52 @@
+use assert_cmd::prelude::*;
+use assert_fs::prelude::*;
+use predicates::prelude::*;
+
+mod common;
+use common::parx;
+
+#[test]
+fn empty_input_makes_a_valid_empty_set() {
+    let td = assert_fs::TempDir::new().unwrap();
+    td.child("data").create_dir_all().unwrap();
+    parx(td.path())
+        .args(["create", "--parity-size", "1M", "--output", ".parx", "data"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("wrote an empty set"))
+        .stderr(predicate::str::contains("--parity-size gives").not());
+    parx(td.path()).args(["verify", ".parx/manifest.json", "."]).assert().success().stdout("OK\n");
+    parx(td.path()).args(["repair", ".parx/manifest.json", "."]).assert().success();
+    parx(td.path())
+        .args(["info", ".parx"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Empty set: no data to protect"));
+    parx(td.path())
+        .args(["info", "--json", ".parx"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\"empty\": true"));
+}
+
+#[test]
+fn missing_empty_file_is_reported_and_recreated() {
+    let td = assert_fs::TempDir::new().unwrap();
+    td.child("data/empty").touch().unwrap();
+    td.child("data/sub/also-empty").touch().unwrap();
+    parx(td.path()).args(["create", "--output", ".parx", "data"]).assert().success();
+    std::fs::remove_dir_all(td.path().join("data/sub")).unwrap();
+
+    parx(td.path())
+        .args(["verify", ".parx/manifest.json", "."])
+        .assert()
+        .code(82)
+        .stdout(predicate::str::contains("missing: data/sub/also-empty"));
+    parx(td.path())
+        .args(["repair", "--json", ".parx/manifest.json", "."])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\"empty_files_restored\":1"));
+    td.child("data/sub/also-empty").assert(predicate::path::is_file());
+    parx(td.path()).args(["verify", ".parx/manifest.json", "."]).assert().success();
+}
@@ -0,0 +1,103 @@
+use assert_cmd::prelude::*;
+use assert_fs::prelude::*;
+use predicates::prelude::*;
+use std::io::{Seek, SeekFrom, Write};
+
+mod common;
+use common::parx;
+
+fn set_flags(vol: &std::path::Path, flags: u32) {
+    let mut f = std::fs::OpenOptions::new().write(true).open(vol).unwrap();
+    f.seek(SeekFrom::Start(20)).unwrap();
+    f.write_all(&flags.to_le_bytes()).unwrap();
+}
+
+fn damage(path: &std::path::Path) {
+    let mut f = std::fs::OpenOptions::new().write(true).open(path).unwrap();
+    f.seek(SeekFrom::Start(10)).unwrap();
+    f.write_all(&[0u8; 32]).unwrap();
+}
+
+#[test]
+fn unknown_volume_features_are_refused_unless_optional_and_compat() {
+    let td = assert_fs::TempDir::new().unwrap();
+    let data: Vec<u8> = (0..20_000u32).map(|i| (i * 7 % 251) as u8).collect();
+    td.child("d/a.bin").write_binary(&data).unwrap();
+    parx(td.path())
+        .args(["create", "--parity", "50", "--stripe-k", "4", "--output", ".parx", "d"])
+        .assert()
+        .success();
+    let vol = td.child(".parx/vol-000.parxv");
+    damage(td.child("d/a.bin").path());
+
+    // An optional feature from a newer writer: strict readers stop and say why
+    set_flags(vol.path(), 1 << 20);
+    parx(td.path()).args(["repair", ".parx/manifest.json", "."]).assert().code(65).stderr(
+        predicate::str::contains("unknown bit 20").and(predicate::str::contains("--compat-read")),
+    );
+    parx(td.path())
+        .args(["paritycheck", ".parx"])
+        .assert()
+        .stdout(predicate::str::contains("UNSUPPORTED"));
+    // ...and in-place writers refuse it even in compat mode
+    parx(td.path())
+        .args(["--compat-read", "vol", "heal", ".parx/manifest.json", "."])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("refusing to heal"));
+    parx(td.path())
+        .args(["--compat-read", "repair", ".parx/manifest.json", "."])
+        .assert()
+        .success();
+    assert_eq!(std::fs::read(td.child("d/a.bin").path()).unwrap(), data);
+
+    // A required feature cannot be ignored
+    damage(td.child("d/a.bin").path());
+    set_flags(vol.path(), parx_core::volume::feature::ENCRYPTED);
+    parx(td.path())
+        .args(["--compat-read", "repair", ".parx/manifest.json", "."])
+        .assert()
+        .code(65)
+        .stderr(predicate::str::contains("requires encryption"));
+}
+
+#[test]
+fn index_of_a_newer_schema_needs_force_old_reader() {
+    use parx_core::index::{encode_trailer, read_index, read_trailer, IndexLimits};
+    let td = assert_fs::TempDir::new().unwrap();
+    let data: Vec<u8> = (0..20_000u32).map(|i| (i * 13 % 251) as u8).collect();
+    td.child("d/a.bin").write_binary(&data).unwrap();
+    parx(td.path())
+        .args(["create", "--parity", "50", "--stripe-k", "4", "--output", ".parx", "d"])
+        .assert()
+        .success();
+    damage(td.child("d/a.bin").path());
+
+    // Re-index every volume with a schema v4 payload holding v3 entries
+    for vol in std::fs::read_dir(td.child(".parx").path()).unwrap() {
+        let vol = vol.unwrap().path();
+        if vol.extension().is_none_or(|e| e != "parxv") {
+            continue;
+        }
+        let mut f = std::fs::OpenOptions::new().read(true).write(true).open(&vol).unwrap();
+        let (off, len, crc) = read_trailer(&mut f).unwrap();
+        let entries = read_index(&mut f, off, len, crc, &IndexLimits::default()).unwrap();
+        let mut raw = parx_core::volume::encode_entries(&entries).unwrap();
+        raw[6] = b'4';
+        let payload = parx_core::codec::Codec::default().compress(&raw).unwrap();
+        let mut h = crc32fast::Hasher::new();
+        h.update(&payload);
+        let idx_off = f.seek(SeekFrom::End(0)).unwrap();
+        f.write_all(&payload).unwrap();
+        f.write_all(&encode_trailer(idx_off, payload.len() as u64, h.finalize())).unwrap();
+    }
+
+    parx(td.path()).args(["repair", ".parx/manifest.json", "."]).assert().code(65).stderr(
+        predicate::str::contains("newer parx").and(predicate::str::contains("--force-old-reader")),
+    );
+    parx(td.path())
+        .args(["--force-old-reader", "repair", ".parx/manifest.json", "."])
+        .assert()
+        .success();
+    assert_eq!(std::fs::read(td.child("d/a.bin").path()).unwrap(), data);
+}
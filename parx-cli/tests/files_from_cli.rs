@@ -0,0 +1,63 @@
+use assert_cmd::prelude::*;
+use assert_fs::prelude::*;
+use predicates::prelude::*;
+
+mod common;
+use common::parx;
+
+const CREATE: &[&str] = &[
+    "create",
+    "--parity",
+    "50",
+    "--stripe-k",
+    "4",
+    "--chunk-size",
+    "4096",
+    "--output",
+    ".parx",
+    "--volume-sizes",
+    "1M",
+];
+
+#[test]
+fn files_from_stdin_keeps_list_order_and_only_protects_listed_files() {
+    let td = assert_fs::TempDir::new().unwrap();
+    for name in ["a", "b", "c", "unlisted"] {
+        td.child(format!("data/{}", name)).write_str(&name.repeat(5000)).unwrap();
+    }
+    assert_cmd::Command::from_std(parx(td.path()))
+        .args(CREATE)
+        .args(["--files-from", "-", "-0", "data"])
+        .write_stdin("data/c\0./data/a\0data/b\0data/c\0")
+        .assert()
+        .success();
+    let mf: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(td.child(".parx/manifest.json").path()).unwrap())
+            .unwrap();
+    let files = mf["files"].as_array().unwrap();
+    let paths: Vec<&str> = files.iter().map(|f| f["rel_path"].as_str().unwrap()).collect();
+    assert_eq!(paths, ["data/c", "data/a", "data/b"]);
+    // Chunks are numbered in list order
+    assert_eq!(files[0]["chunks"][0]["idx"], 0);
+    assert_eq!(files[2]["chunks"][0]["idx"], 4);
+
+    parx(td.path())
+        .args(["update", "--append-only-aware", "data"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--files-from"));
+}
+
+#[test]
+fn files_from_rejects_paths_outside_the_input() {
+    let td = assert_fs::TempDir::new().unwrap();
+    td.child("data/a").write_str("a").unwrap();
+    td.child("other/b").write_str("b").unwrap();
+    td.child("list.txt").write_str("data/a\nother/b\n").unwrap();
+    parx(td.path())
+        .args(CREATE)
+        .args(["--files-from", "list.txt", "data"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("is not under"));
+}
@@ -0,0 +1,106 @@
+use assert_cmd::prelude::*;
+use assert_fs::prelude::*;
+use predicates::prelude::*;
+use std::process::Command;
+
+mod common;
+use common::parx;
+
+#[test]
+fn label_is_shown_from_manifest_and_lone_volume() {
+    let td = assert_fs::TempDir::new().unwrap();
+    td.child("photos/a.jpg").write_binary(&[7u8; 10_000]).unwrap();
+    parx(td.path())
+        .args([
+            "create",
+            "--parity",
+            "50",
+            "--stripe-k",
+            "4",
+            "--output",
+            ".parx",
+            "--volume-sizes",
+            "1M,1M",
+            "--label",
+            "Family photos 1998-2004",
+            "--notes",
+            "Scanned from albums",
+            "--contact",
+            "jo@example.org",
+            "photos",
+        ])
+        .assert()
+        .success();
+
+    parx(td.path())
+        .args(["info", ".parx"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Label:   Family photos 1998-2004"))
+        .stdout(predicate::str::contains("Contact: jo@example.org"))
+        .stdout(predicate::str::contains("Files:   1 (10000 bytes)"));
+
+    // A volume found on its own still says what it belongs to
+    let lone = td.child("found");
+    lone.create_dir_all().unwrap();
+    std::fs::copy(td.child(".parx/vol-001.parxv").path(), lone.child("vol-001.parxv").path())
+        .unwrap();
+    let out = parx(td.path())
+        .args(["info", "--json", "found"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let v: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(v["source"], "volume");
+    assert_eq!(v["label"], "Family photos 1998-2004");
+    assert_eq!(v["notes"], "Scanned from albums");
+    assert_eq!(v["stripe_k"], 4);
+    assert_eq!(v["volume_id"], 1);
+}
+
+#[test]
+fn oversized_label_is_rejected() {
+    let td = assert_fs::TempDir::new().unwrap();
+    td.child("d/a").write_binary(&[1u8; 100]).unwrap();
+    let label = "x".repeat(64 * 1024 + 1);
+    parx(td.path())
+        .args(["create", "--label", &label, "d"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--label is longer than"));
+}
+
+#[test]
+fn embedded_recovery_stub_is_checked_by_info() {
+    let td = assert_fs::TempDir::new().unwrap();
+    td.child("d/a").write_binary(&[3u8; 5000]).unwrap();
+    parx(td.path())
+        .args(["create", "--embed-recovery-stub", "--output", ".parx", "d"])
+        .assert()
+        .success();
+    let stub = std::fs::read_dir(td.child(".parx").path())
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .find(|p| p.file_name().unwrap().to_string_lossy().starts_with("parx-recover-"))
+        .expect("stub written");
+
+    // The stub is a working parx
+    Command::new(&stub).arg("--help").assert().success();
+    parx(td.path()).args(["info", ".parx"]).assert().success().stdout(
+        predicate::str::contains("Recovery: parx-recover-").and(predicate::str::contains(" ok")),
+    );
+
+    // A modified stub must not be trusted
+    let mut data = std::fs::read(&stub).unwrap();
+    let last = data.len() - 1;
+    data[last] ^= 0xFF;
+    std::fs::write(&stub, data).unwrap();
+    parx(td.path())
+        .args(["info", ".parx"])
+        .assert()
+        .code(65)
+        .stdout(predicate::str::contains("hash mismatch"))
+        .stderr(predicate::str::contains("do not run them"));
+}
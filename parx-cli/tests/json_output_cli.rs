@@ -0,0 +1,113 @@
+use assert_cmd::prelude::*;
+use assert_fs::prelude::*;
+use std::process::Command;
+
+fn parx_json(dir: &std::path::Path, args: &[&str]) -> serde_json::Value {
+    parx_json_status(dir, args, 0)
+}
+
+fn parx_json_status(dir: &std::path::Path, args: &[&str], code: i32) -> serde_json::Value {
+    let out = Command::cargo_bin("parx")
+        .unwrap()
+        .current_dir(dir)
+        .args(args)
+        .args(["--format", "json", ".parx/manifest.json", "."])
+        .output()
+        .unwrap();
+    assert_eq!(out.status.code(), Some(code), "{}", String::from_utf8_lossy(&out.stderr));
+    serde_json::from_slice(&out.stdout).expect("stdout is one JSON object")
+}
+
+#[test]
+fn verify_audit_and_repair_report_json() {
+    let td = assert_fs::TempDir::new().unwrap();
+    td.child("data/a.bin").write_binary(&vec![1u8; 32 * 1024]).unwrap();
+    td.child("data/b.bin").write_binary(&vec![2u8; 32 * 1024]).unwrap();
+    Command::cargo_bin("parx")
+        .unwrap()
+        .current_dir(td.path())
+        .args(["create", "--parity", "25", "--stripe-k", "4", "--chunk-size", "4096"])
+        .args(["--output", ".parx", "--volume-sizes", "1M", "data"])
+        .assert()
+        .success();
+    // One chunk of stripe 0 and two of stripe 4 (m = 1)
+    let mut a = vec![1u8; 32 * 1024];
+    a[100] = 0;
+    td.child("data/a.bin").write_binary(&a).unwrap();
+    let mut b = vec![2u8; 32 * 1024];
+    b[0] = 0;
+    b[4096] = 0;
+    td.child("data/b.bin").write_binary(&b).unwrap();
+
+    // Bit rot exits with 80 once the report is out
+    let v = parx_json_status(td.path(), &["verify"], 80);
+    assert_eq!((v["chunks_ok"].as_u64(), v["chunks_bad"].as_u64()), (Some(13), Some(3)));
+    assert_eq!(v["damaged_files"][0]["kind"], "content_mismatch");
+    assert_eq!(v["merkle_ok"], false);
+
+    let a = parx_json(td.path(), &["audit"]);
+    assert_eq!(a["stripes"], 4);
+    assert_eq!(a["repairable"], false);
+    let damaged = a["damaged"].as_array().unwrap();
+    assert_eq!(damaged.len(), 2);
+    assert_eq!(damaged[0]["stripe"], 0);
+    assert_eq!(damaged[0]["bad_chunks"], serde_json::json!([0]));
+    assert_eq!(damaged[0]["parity_available"], 1);
+    assert_eq!(damaged[0]["repairable"], true);
+    assert_eq!(damaged[1]["bad_chunks"], serde_json::json!([8, 9]));
+    assert_eq!(damaged[1]["repairable"], false);
+
+    let r = parx_json(td.path(), &["repair"]);
+    assert_eq!((r["repaired_chunks"].as_u64(), r["failed_chunks"].as_u64()), (Some(1), Some(2)));
+
+    // Every report carries the schema version and its kind
+    for (rep, kind) in [(&v, "verify"), (&a, "audit"), (&r, "repair")] {
+        assert_eq!(rep["schema_version"], parx_core::report::SCHEMA_VERSION);
+        assert_eq!(rep["kind"], kind);
+    }
+    let out = Command::cargo_bin("parx")
+        .unwrap()
+        .current_dir(td.path())
+        .args(["paritycheck", "--json", "--deep", ".parx"])
+        .output()
+        .unwrap();
+    let p: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!((p["kind"].as_str(), p["deep"].as_bool()), (Some("paritycheck"), Some(true)));
+    assert_eq!(p["volumes"][0]["volume"], "vol-000.parxv");
+    assert_eq!(
+        (p["volumes"][0]["index"].as_str(), p["shards_bad"].as_u64()),
+        (Some("ok"), Some(0))
+    );
+}
+
+#[test]
+fn create_streams_progress_as_json_lines() {
+    let td = assert_fs::TempDir::new().unwrap();
+    td.child("data/a.bin").write_binary(&vec![3u8; 40 * 1024]).unwrap();
+    let out = Command::cargo_bin("parx")
+        .unwrap()
+        .current_dir(td.path())
+        .args(["create", "--parity", "50", "--stripe-k", "4", "--chunk-size", "4096"])
+        .args(["--volume-sizes", "1M,1M", "--progress=json", "--output", ".parx", "data"])
+        .output()
+        .unwrap();
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    let last: serde_json::Value = serde_json::from_str(stderr.lines().last().unwrap()).unwrap();
+    assert_eq!(last["last"], true);
+    let stages = last["stages"].as_array().unwrap();
+    let get = |p: &str| stages.iter().find(|s| s["path"] == p).unwrap();
+    assert_eq!(get("hash")["bytes_done"], 40 * 1024);
+    assert_eq!(get("write")["bytes_done"], get("write")["bytes_total"]);
+    assert_eq!(get("write/vol-001.parxv")["depth"], 1);
+
+    // Without a value `--progress` is text, and leaves the input dir alone
+    let out = Command::cargo_bin("parx")
+        .unwrap()
+        .current_dir(td.path())
+        .args(["create", "--output", ".parx2", "--progress", "data"])
+        .output()
+        .unwrap();
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    assert!(String::from_utf8_lossy(&out.stderr).contains("hash 100%"));
+}
@@ -1,10 +1,18 @@
 use assert_cmd::prelude::*;
 use assert_fs::prelude::*;
+use parx_core::index::{read_index, read_trailer, IndexLimits};
 use predicates::prelude::*;
-use rand::{rngs::StdRng, Rng, SeedableRng};
-use std::io::{Seek, SeekFrom, Write};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::fs;
+use std::io::{BufRead, BufReader, Seek, SeekFrom, Write};
+use std::net::TcpListener;
+use std::path::PathBuf;
 use std::process::Command;
 
+mod common;
+use common::parx;
+
 #[test]
 fn corrupt_index_trailer_does_not_panic() {
     let td = assert_fs::TempDir::new().unwrap();
@@ -74,3 +82,507 @@ fn corrupt_index_trailer_does_not_panic() {
         .success()
         .stdout(predicate::str::contains("Parity audit across"));
 }
+
+#[test]
+fn vol_heal_regenerates_damaged_parity_shard() {
+    let td = assert_fs::TempDir::new().unwrap();
+    let data = td.child("data");
+    data.create_dir_all().unwrap();
+    let mut rng = StdRng::seed_from_u64(3);
+    let bytes: Vec<u8> = (0..48 * 1024).map(|_| rng.gen()).collect();
+    std::fs::write(data.child("a.bin").path(), &bytes).unwrap();
+    parx(td.path())
+        .args([
+            "create",
+            "--parity",
+            "50",
+            "--stripe-k",
+            "4",
+            "--chunk-size",
+            "4096",
+            "--output",
+            ".parx",
+            "--volume-sizes",
+            "1M,1M",
+            "data",
+        ])
+        .assert()
+        .success();
+
+    let vol = td.child(".parx/vol-000.parxv");
+    let pristine = std::fs::read(vol.path()).unwrap();
+    let mut f = std::fs::OpenOptions::new().read(true).write(true).open(vol.path()).unwrap();
+    let (off, len, crc) = read_trailer(&mut f).unwrap();
+    let entries = read_index(&mut f, off, len, crc, &IndexLimits::default()).unwrap();
+    f.seek(SeekFrom::Start(entries[0].offset + 100)).unwrap();
+    f.write_all(&[0xAB; 100]).unwrap();
+    drop(f);
+
+    // Without --deep only the indices are read, so the damage goes unseen
+    parx(td.path())
+        .args(["paritycheck", ".parx"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("index: OK"))
+        .stdout(predicate::str::contains("shards:").not());
+    parx(td.path())
+        .args(["paritycheck", "--deep", ".parx"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("shards: BAD(1)"))
+        .stdout(predicate::str::contains("parx vol heal"));
+
+    parx(td.path())
+        .args(["vol", "heal", ".parx/manifest.json", "."])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1 bad, 1 healed, 0 unhealable"));
+
+    assert_eq!(std::fs::read(vol.path()).unwrap(), pristine);
+    parx(td.path())
+        .args(["paritycheck", "--deep", ".parx"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("BAD").not());
+}
+
+fn create(dir: &std::path::Path, input: &str, output: &str) {
+    parx(dir)
+        .args([
+            "create",
+            "--parity",
+            "50",
+            "--stripe-k",
+            "4",
+            "--output",
+            output,
+            "--volume-sizes",
+            "1M,1M",
+            "--gpu",
+            "off",
+            input,
+        ])
+        .assert()
+        .success();
+}
+
+fn set_id(dir: &std::path::Path, path: &str) -> String {
+    let out =
+        parx(dir).args(["info", "--json", path]).assert().success().get_output().stdout.clone();
+    let v: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    v["set_id"].as_str().expect("set id").to_string()
+}
+
+#[test]
+fn quickcheck_flags_a_volume_of_another_set() {
+    let td = assert_fs::TempDir::new().unwrap();
+    td.child("a/x.bin").write_binary(&[1u8; 40_000]).unwrap();
+    td.child("b/x.bin").write_binary(&[2u8; 40_000]).unwrap();
+    create(td.path(), "a", ".parx-a");
+    create(td.path(), "b", ".parx-b");
+    let (ours, theirs) = (set_id(td.path(), ".parx-a"), set_id(td.path(), ".parx-b"));
+    assert_ne!(ours, theirs);
+    assert_eq!(set_id(td.path(), ".parx-a/vol-001.parxv"), ours);
+
+    parx(td.path()).args(["quickcheck", ".parx-a"]).assert().success();
+
+    std::fs::copy(
+        td.child(".parx-b/vol-001.parxv").path(),
+        td.child(".parx-a/vol-001.parxv").path(),
+    )
+    .unwrap();
+    parx(td.path())
+        .args(["quickcheck", ".parx-a"])
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains(format!(
+            "vol-001.parxv: foreign volume (set {}; manifest.json is set {})",
+            theirs, ours
+        )))
+        .stderr(predicate::str::contains("1 foreign volume(s)"));
+
+    // Without a manifest alongside there is nothing to compare against
+    std::fs::remove_file(td.child(".parx-a/manifest.json").path()).unwrap();
+    parx(td.path()).args(["quickcheck", ".parx-a"]).assert().success();
+}
+
+#[test]
+fn volumes_are_cut_to_size_and_named_by_the_template() {
+    let td = assert_fs::TempDir::new().unwrap();
+    let data: Vec<u8> =
+        (0..400_000u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8).collect();
+    td.child("data/a.bin").write_binary(&data).unwrap();
+    parx(td.path())
+        .args([
+            "create",
+            "--stripe-k",
+            "4",
+            "--chunk-size",
+            "4096",
+            "--volume-max-size",
+            "48K",
+            "--volume-name",
+            "disc-{id}.parxv",
+            "--gpu",
+            "off",
+            "data",
+        ])
+        .assert()
+        .success();
+    let mut discs = 0;
+    for ent in std::fs::read_dir(td.child(".parx").path()).unwrap() {
+        let ent = ent.unwrap();
+        let name = ent.file_name().to_string_lossy().to_string();
+        assert!(!name.starts_with("vol-"), "{}", name);
+        if name.ends_with(".parxv") {
+            assert!(name.starts_with("disc-"), "{}", name);
+            assert!(ent.metadata().unwrap().len() <= 48 * 1024, "{}", name);
+            discs += 1;
+        }
+    }
+    assert!(discs > 3, "{}", discs);
+    td.child(".parx/disc-000.parxv").assert(predicate::path::exists());
+
+    parx(td.path())
+        .args(["quickcheck", ".parx"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(format!("Volumes: {}", discs)));
+    let mut bad = data.clone();
+    bad[70_000] ^= 0xFF;
+    std::fs::write(td.child("data/a.bin").path(), &bad).unwrap();
+    std::fs::remove_file(td.child(".parx/disc-001.parxv").path()).unwrap();
+    parx(td.path()).args(["repair", ".parx/manifest.json", "."]).assert().success();
+    parx(td.path()).args(["verify", ".parx/manifest.json", "."]).assert().success();
+
+    parx(td.path())
+        .args(["create", "--output", "small", "--volume-max-size", "4K", "data"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--volume-max-size 4096 is too small"));
+    parx(td.path())
+        .args(["create", "--output", "odd", "--volume-name", "disc.parxv", "data"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("must hold {id} exactly once"));
+}
+
+#[test]
+fn paritycheck_skips_unchanged_volumes() {
+    let td = assert_fs::TempDir::new().unwrap();
+    td.child("data/a.bin").write_binary(&[5u8; 40 * 1024]).unwrap();
+    parx(td.path())
+        .args(["create", "--parity", "50", "--stripe-k", "4", "--chunk-size", "4096"])
+        .args(["--output", ".parx", "--volume-sizes", "1M,1M", "data"])
+        .assert()
+        .success();
+
+    parx(td.path())
+        .args(["paritycheck", "--deep", ".parx"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("(cached)").not());
+    assert!(td.child(".parx/paritycheck.cache").path().exists());
+    parx(td.path())
+        .args(["paritycheck", "--deep", ".parx"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("shards: OK (cached)").count(2));
+    parx(td.path())
+        .args(["paritycheck", "--deep", "--no-cache", ".parx"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("(cached)").not());
+
+    // A write to a volume changes its fingerprint, so it is hashed again
+    let mut f = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(td.child(".parx/vol-001.parxv").path())
+        .unwrap();
+    let (off, len, crc) = read_trailer(&mut f).unwrap();
+    let entries = read_index(&mut f, off, len, crc, &IndexLimits::default()).unwrap();
+    f.seek(SeekFrom::Start(entries[0].offset + 100)).unwrap();
+    f.write_all(&[0xAB; 100]).unwrap();
+    drop(f);
+    parx(td.path())
+        .args(["paritycheck", "--deep", ".parx"])
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::contains("vol-000.parxv").and(predicate::str::contains("OK (cached)")),
+        )
+        .stdout(predicate::str::contains("shards: BAD(1)\n"));
+}
+
+fn create_grouped(dir: &std::path::Path, groups: &str) -> assert_cmd::assert::Assert {
+    parx(dir)
+        .args(["create", "--parity", "50", "--stripe-k", "4", "--chunk-size", "4096"])
+        .args(["--volume-sizes", "1M,1M,1M,1M", "--gpu", "off"])
+        .args(["--volume-groups", groups, "data"])
+        .assert()
+}
+
+#[test]
+fn volume_groups_are_checked_at_create_and_reported_by_paritycheck() {
+    let td = assert_fs::TempDir::new().unwrap();
+    let data: Vec<u8> = (0..50_000u32).map(|i| (i * 13 % 251) as u8).collect();
+    td.child("data/a.bin").write_binary(&data).unwrap();
+
+    create_grouped(td.path(), "host=a:0-1")
+        .failure()
+        .stderr(predicate::str::contains("volume 2 is in no host group"));
+    create_grouped(td.path(), "host").failure().stderr(predicate::str::contains("--volume-groups"));
+    // Both parity shards of each stripe on volumes 0 and 1
+    create_grouped(td.path(), "host=a:0-1,b:2-3")
+        .failure()
+        .stderr(predicate::str::contains("losing host \"a\" would take every parity shard"));
+    create_grouped(td.path(), "host=a:0+2,b:1+3").success();
+
+    parx(td.path())
+        .args(["paritycheck", ".parx"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("host: 2 group(s); can lose any 1"));
+    parx(td.path())
+        .args(["paritycheck", "--json", ".parx"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\"level\":\"host\""))
+        .stdout(predicate::str::contains("\"tolerates\":1"));
+}
+
+#[test]
+fn recover_manifest_rewrites_it_from_a_surviving_volume() {
+    let td = assert_fs::TempDir::new().unwrap();
+    td.child("data/a.bin").write_binary(&[1u8; 20_000]).unwrap();
+    td.child("data/b.bin").write_binary(&[2u8; 3000]).unwrap();
+    parx(td.path())
+        .args(["create", "--parity", "50", "--stripe-k", "4", "--chunk-size", "4096"])
+        .args(["--output", ".parx", "--volume-sizes", "1M,1M", "data"])
+        .assert()
+        .success();
+    let original = std::fs::read(td.child(".parx/manifest.json").path()).unwrap();
+
+    parx(td.path())
+        .args(["recover-manifest", ".parx"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--force"));
+
+    // manifest.json, its v2 companion and the first volume are gone
+    for f in ["manifest.json", "manifest.v2", "vol-000.parxv"] {
+        std::fs::remove_file(td.child(".parx").child(f).path()).unwrap();
+    }
+    parx(td.path())
+        .args(["recover-manifest", ".parx"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("from vol-001.parxv (2 files)"));
+    assert_eq!(std::fs::read(td.child(".parx/manifest.json").path()).unwrap(), original);
+    assert!(td.child(".parx/manifest.v2").path().exists());
+    parx(td.path()).args(["verify", ".parx/manifest.json", "."]).assert().success();
+}
+
+#[test]
+fn verify_uses_the_manifest_backed_up_in_a_volume() {
+    let td = assert_fs::TempDir::new().unwrap();
+    let a: Vec<u8> = (0..20_000u32).map(|i| (i % 251) as u8).collect();
+    td.child("data/a.bin").write_binary(&a).unwrap();
+    td.child("data/b.bin").write_binary(&[7u8; 3000]).unwrap();
+    parx(td.path())
+        .args(["create", "--parity", "50", "--stripe-k", "4", "--chunk-size", "4096"])
+        .args(["--output", ".parx", "--volume-sizes", "1M,1M", "data"])
+        .assert()
+        .success();
+    // update refreshes the backups along with manifest.json
+    td.child("data/c.bin").write_binary(&[9u8; 5000]).unwrap();
+    parx(td.path()).args(["update", "data"]).assert().success();
+    for f in ["manifest.json", "manifest.v2"] {
+        std::fs::remove_file(td.child(".parx").child(f).path()).unwrap();
+    }
+
+    let verify = |vol: &str, code: i32| {
+        let out =
+            parx(td.path()).args(["verify", "--json", "--from-volume", vol, "."]).output().unwrap();
+        assert_eq!(out.status.code(), Some(code), "{}", String::from_utf8_lossy(&out.stderr));
+        serde_json::from_slice::<serde_json::Value>(&out.stdout).unwrap()
+    };
+    let v = verify(".parx/vol-001.parxv", 0);
+    assert_eq!((v["chunks_ok"].as_u64(), v["chunks_bad"].as_u64()), (Some(8), Some(0)));
+    assert_eq!(v["merkle_ok"], true);
+
+    let mut damaged = a.clone();
+    damaged[9000] ^= 0xFF;
+    td.child("data/a.bin").write_binary(&damaged).unwrap();
+    let v = verify(".parx/vol-000.parxv", 80);
+    assert_eq!((v["chunks_ok"].as_u64(), v["chunks_bad"].as_u64()), (Some(7), Some(1)));
+
+    parx(td.path())
+        .args(["verify", "--from-volume", ".parx/vol-000.parxv", ".parx/manifest.json", "."])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("takes only ROOT"));
+}
+
+/// Minimal HTTP/1.1 file server honouring HEAD and `Range: bytes=a-b`; one request per connection.
+fn serve_dir(root: PathBuf) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(s) => s,
+                Err(_) => continue,
+            };
+            let mut rd = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            rd.read_line(&mut line).unwrap();
+            let head = line.starts_with("HEAD ");
+            let path = line.split_whitespace().nth(1).unwrap_or("/").to_string();
+            let mut range = None;
+            loop {
+                let mut h = String::new();
+                if rd.read_line(&mut h).unwrap() == 0 || h == "\r\n" {
+                    break;
+                }
+                if let Some(v) = h.to_ascii_lowercase().strip_prefix("range: bytes=") {
+                    let (a, b) = v.trim().split_once('-').unwrap();
+                    range = Some((a.parse::<usize>().unwrap(), b.parse::<usize>().unwrap()));
+                }
+            }
+            match fs::read(root.join(path.trim_start_matches('/'))) {
+                Ok(data) => {
+                    let (status, body) = match range {
+                        Some((a, b)) => ("206 Partial Content", &data[a..=b.min(data.len() - 1)]),
+                        None => ("200 OK", &data[..]),
+                    };
+                    let hdr = format!(
+                        "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        status,
+                        body.len()
+                    );
+                    let _ = stream.write_all(hdr.as_bytes());
+                    if !head {
+                        let _ = stream.write_all(body);
+                    }
+                }
+                Err(_) => {
+                    let _ = stream.write_all(
+                        b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    );
+                }
+            }
+        }
+    });
+    format!("http://{}", addr)
+}
+
+#[test]
+fn repair_fetches_parity_from_a_published_mirror() {
+    let td = assert_fs::TempDir::new().unwrap();
+    let data: Vec<u8> =
+        (0..40_000u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8).collect();
+    td.child("data/a.bin").write_binary(&data).unwrap();
+    parx(td.path())
+        .args(["create", "--stripe-k", "4", "--chunk-size", "4096", "--gpu", "off", "data"])
+        .assert()
+        .success();
+
+    // Publish the volumes; locally only the manifest is kept
+    let mirror = td.child("www");
+    fs::create_dir(mirror.path()).unwrap();
+    for ent in fs::read_dir(td.child(".parx").path()).unwrap() {
+        let p = ent.unwrap().path();
+        if p.extension().is_some_and(|x| x == "parxv") {
+            fs::rename(&p, mirror.path().join(p.file_name().unwrap())).unwrap();
+        }
+    }
+    let url = serve_dir(mirror.path().to_path_buf());
+    let mut bad = data.clone();
+    bad[20_000] ^= 0xFF;
+    fs::write(td.child("data/a.bin").path(), &bad).unwrap();
+
+    let out = parx(td.path())
+        .args(["repair", "--json", "--volumes-url", &format!("{}/", url)])
+        .args([".parx/manifest.json", "."])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let v: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(v["repaired_chunks"], 1);
+    assert!(v["remote_shard_reads"].as_u64().unwrap() > 0);
+    assert_eq!(fs::read(td.child("data/a.bin").path()).unwrap(), data);
+
+    parx(td.path())
+        .args(["repair", "--volumes-url", "www", ".parx/manifest.json", "."])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("expected an http:// or https:// URL"));
+}
+
+#[test]
+fn volumes_spread_over_drives_and_the_primary_can_be_lost() {
+    let td = assert_fs::TempDir::new().unwrap();
+    let data: Vec<u8> =
+        (0..60_000u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 11) as u8).collect();
+    td.child("data/a.bin").write_binary(&data).unwrap();
+    parx(td.path())
+        .args([
+            "create",
+            "--parity",
+            "75",
+            "--stripe-k",
+            "4",
+            "--chunk-size",
+            "4096",
+            "--output-dirs",
+            "a/.parx,b/.parx,c/.parx",
+            "--gpu",
+            "off",
+            "data",
+        ])
+        .assert()
+        .success();
+    td.child("a/.parx/manifest.json").assert(predicate::path::exists());
+    td.child("a/.parx/vol-000.parxv").assert(predicate::path::exists());
+    td.child("b/.parx/vol-001.parxv").assert(predicate::path::exists());
+    td.child("c/.parx/vol-002.parxv").assert(predicate::path::exists());
+    td.child("a/.parx/vol-001.parxv").assert(predicate::path::missing());
+
+    parx(td.path())
+        .args(["quickcheck", "a/.parx"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("vol-002.parxv: entries="))
+        .stdout(predicate::str::contains("Volumes: 3"));
+    let out = parx(td.path())
+        .args(["paritycheck", "--json", "--deep", "a/.parx"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let v: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(v["volumes"].as_array().unwrap().len(), 3);
+    assert_eq!(v["shards_bad"], 0);
+
+    // The drive holding the manifest dies: it comes back from the backup in
+    // another drive's volume, and repair finds the rest
+    std::fs::remove_dir_all(td.child("a").path()).unwrap();
+    let mut bad = data.clone();
+    bad[5_000] ^= 0xFF;
+    bad[9_000] ^= 0xFF;
+    std::fs::write(td.child("data/a.bin").path(), &bad).unwrap();
+    parx(td.path()).args(["recover-manifest", "b/.parx"]).assert().success();
+    parx(td.path()).args(["repair", "b/.parx/manifest.json", "."]).assert().success();
+    assert_eq!(std::fs::read(td.child("data/a.bin").path()).unwrap(), data);
+
+    parx(td.path())
+        .args(["create", "--output-dirs", "x/.parx,y/.parx", "--volume-parity", "1", "data"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+}
@@ -0,0 +1,218 @@
+use assert_cmd::prelude::*;
+use assert_fs::prelude::*;
+use predicates::prelude::*;
+
+mod common;
+use common::parx;
+
+#[test]
+fn publish_then_check_download_detects_tampering_and_wrong_signer() {
+    let td = assert_fs::TempDir::new().unwrap();
+    let data = td.child("data");
+    data.create_dir_all().unwrap();
+    std::fs::write(data.child("a.bin").path(), vec![3u8; 70_000]).unwrap();
+    std::fs::write(data.child("b.txt").path(), b"release notes").unwrap();
+
+    parx(td.path()).args(["keygen", "pub.key", "pub.pem"]).assert().success();
+    parx(td.path()).args(["keygen", "other.key", "other.pem"]).assert().success();
+    parx(td.path())
+        .args([
+            "create",
+            "--parity",
+            "50",
+            "--stripe-k",
+            "4",
+            "--chunk-size",
+            "16384",
+            "--output",
+            ".parx",
+            "--volume-sizes",
+            "1M",
+            "data",
+        ])
+        .assert()
+        .success();
+    parx(td.path())
+        .args(["publish", "--sign-key", "pub.key", ".parx/manifest.json", "."])
+        .assert()
+        .success();
+    assert!(td.child(".parx/bundle.parx-pub").path().exists());
+
+    parx(td.path())
+        .args(["check-download", "--pubkey", "pub.pem", ".", ".parx/bundle.parx-pub"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("OK (2 files"));
+
+    // Pinned to a different key: refuse
+    parx(td.path())
+        .args(["check-download", "--pubkey", "other.pem", ".", ".parx/bundle.parx-pub"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("unexpected key"));
+
+    // Tampered download: reported and non-zero exit
+    std::fs::write(data.child("b.txt").path(), b"release notez").unwrap();
+    parx(td.path())
+        .args(["check-download", ".", ".parx/bundle.parx-pub"])
+        .assert()
+        .code(65)
+        .stdout(predicate::str::contains("BAD     data/b.txt"));
+}
+
+#[test]
+fn check_download_trusts_only_a_pinned_key_and_the_stored_body() {
+    let td = assert_fs::TempDir::new().unwrap();
+    td.child("data/a.bin").write_binary(&[5u8; 40_000]).unwrap();
+    td.child("data/b.txt").write_binary(b"notes").unwrap();
+    parx(td.path()).args(["keygen", "pub.key", "pub.pem"]).assert().success();
+    parx(td.path()).args(["keygen", "other.key", "other.pem"]).assert().success();
+    parx(td.path())
+        .args(["create", "--stripe-k", "4", "--chunk-size", "16384", "data"])
+        .assert()
+        .success();
+    parx(td.path())
+        .args(["publish", "--sign-key", "pub.key", ".parx/manifest.json", "."])
+        .assert()
+        .success();
+    // Anyone can re-sign: without --pubkey that passes, but unauthenticated
+    parx(td.path())
+        .args(["publish", "--sign-key", "other.key", "--out", "evil.parx-pub"])
+        .args([".parx/manifest.json", "."])
+        .assert()
+        .success();
+    parx(td.path())
+        .args(["check-download", "--json", ".", "evil.parx-pub"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\"authenticated\":false"))
+        .stderr(predicate::str::contains("pass --pubkey"));
+    parx(td.path())
+        .args(["check-download", "--json", "--pubkey", "pub.pem", ".", ".parx/bundle.parx-pub"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\"authenticated\":true"))
+        .stderr(predicate::str::is_empty());
+
+    // The signature covers the body as stored, fields this version does
+    // not know included
+    let stored = std::fs::read(td.child(".parx/bundle.parx-pub").path()).unwrap();
+    let json: serde_json::Value =
+        serde_json::from_slice(&zstd::stream::decode_all(&stored[..]).unwrap()).unwrap();
+    let body = serde_json::to_string(&json["body"]).unwrap();
+    let write = |name: &str, body: &str, key: &str| {
+        let sk = parx_core::sign::load_signing_key(td.child(key).path()).unwrap();
+        let sig = parx_core::sign::sign_bytes(&sk, body.as_bytes());
+        let text =
+            format!("{{\"body\":{},\"signature\":{}}}", body, serde_json::to_string(&sig).unwrap());
+        let packed = zstd::stream::encode_all(text.as_bytes(), 3).unwrap();
+        std::fs::write(td.child(name).path(), packed).unwrap();
+    };
+    let future = format!("{{\"x_future\":[1, 2],{}", &body[1..]);
+    write("future.parx-pub", &future, "pub.key");
+    parx(td.path())
+        .args(["check-download", "--pubkey", "pub.pem", ".", "future.parx-pub"])
+        .assert()
+        .success();
+    // Edited after signing: the stored bytes no longer match
+    let text =
+        zstd::stream::decode_all(&std::fs::read(td.child("future.parx-pub").path()).unwrap()[..])
+            .unwrap();
+    let edited = String::from_utf8(text).unwrap().replacen("[1, 2]", "[1, 3]", 1);
+    std::fs::write(
+        td.child("edited.parx-pub").path(),
+        zstd::stream::encode_all(edited.as_bytes(), 3).unwrap(),
+    )
+    .unwrap();
+    parx(td.path())
+        .args(["check-download", "--pubkey", "pub.pem", ".", "edited.parx-pub"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("signature check failed"));
+
+    // A file that cannot be read is an error, not missing
+    std::fs::remove_file(td.child("data/b.txt").path()).unwrap();
+    std::fs::create_dir(td.child("data/b.txt").path()).unwrap();
+    parx(td.path())
+        .args(["check-download", "--pubkey", "pub.pem", ".", ".parx/bundle.parx-pub"])
+        .assert()
+        .code(65)
+        .stdout(predicate::str::contains("ERROR   data/b.txt"))
+        .stdout(predicate::str::contains("MISSING").not());
+}
+
+#[test]
+fn bundle_carries_manifest_v2_and_bao_outboards_locate_damage() {
+    let td = assert_fs::TempDir::new().unwrap();
+    let data: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+    td.child("data/a.bin").write_binary(&data).unwrap();
+    td.child("data/b.txt").write_binary(b"notes").unwrap();
+    parx(td.path()).args(["keygen", "pub.key", "pub.pem"]).assert().success();
+    parx(td.path())
+        .args(["create", "--stripe-k", "4", "--chunk-size", "16384", "data"])
+        .assert()
+        .success();
+    parx(td.path())
+        .args(["publish", "--bao", "--sign-key", "pub.key", ".parx/manifest.json", "."])
+        .assert()
+        .success();
+
+    let (bundle, _) =
+        parx_core::publish::read_bundle(td.child(".parx/bundle.parx-pub").path(), None).unwrap();
+    let shipped = bundle.body.manifest().unwrap();
+    let written = parx_core::manifest_v2::decode(
+        &std::fs::read(td.child(".parx/manifest.v2").path()).unwrap(),
+    )
+    .unwrap();
+    let files = |m: &parx_core::manifest::Manifest| {
+        m.files.iter().map(|f| (f.rel_path.clone(), f.size)).collect::<Vec<_>>()
+    };
+    assert_eq!(files(&shipped), files(&written));
+    assert!(shipped.parity_dir.is_empty());
+    // Length, then one parent node per leaf but the last
+    let a = bundle.body.witnesses.iter().find(|w| w.rel_path == "data/a.bin").unwrap();
+    let ob = a.bao_outboard.as_ref().unwrap();
+    assert_eq!(ob.len(), 2 * (8 + 64 * 6));
+    assert_eq!(&ob[..16], &hex_le(100_000));
+
+    parx(td.path())
+        .args(["check-download", "--pubkey", "pub.pem", ".", ".parx/bundle.parx-pub"])
+        .assert()
+        .success();
+    // One byte in the third leaf: only that leaf is reported
+    let mut bad = data.clone();
+    bad[40_000] ^= 1;
+    td.child("data/a.bin").write_binary(&bad).unwrap();
+    parx(td.path())
+        .args(["check-download", "--pubkey", "pub.pem", ".", ".parx/bundle.parx-pub"])
+        .assert()
+        .code(65)
+        .stdout(predicate::str::contains("BAD     data/a.bin\n        bytes 32768..49152\n"));
+    // Two adjacent leaves and the short last one
+    bad[50_000] ^= 1;
+    bad[99_999] ^= 1;
+    td.child("data/a.bin").write_binary(&bad).unwrap();
+    let out = parx(td.path())
+        .args(["check-download", "--json", "--pubkey", "pub.pem", ".", ".parx/bundle.parx-pub"])
+        .output()
+        .unwrap();
+    let rep: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(
+        rep["damaged"],
+        serde_json::json!([
+            {"rel_path": "data/a.bin", "offset": 32768, "len": 32768},
+            {"rel_path": "data/a.bin", "offset": 98304, "len": 1696},
+        ])
+    );
+    // At another size there is nothing to locate
+    td.child("data/a.bin").write_binary(&bad[..99_000]).unwrap();
+    parx(td.path())
+        .args(["check-download", "--json", "--pubkey", "pub.pem", ".", ".parx/bundle.parx-pub"])
+        .assert()
+        .code(65)
+        .stdout(predicate::str::contains("\"damaged\":[]"));
+}
+
+fn hex_le(n: u64) -> String {
+    n.to_le_bytes().iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,77 @@
+use assert_cmd::prelude::*;
+use assert_fs::prelude::*;
+use predicates::prelude::*;
+
+mod common;
+use common::parx;
+
+#[test]
+fn signed_manifest_is_checked_by_verify_repair_and_update() {
+    let td = assert_fs::TempDir::new().unwrap();
+    let data = td.child("data");
+    data.create_dir_all().unwrap();
+    std::fs::write(data.child("a.bin").path(), vec![7u8; 20_000]).unwrap();
+    parx(td.path()).args(["keygen", "set.key", "set.pem"]).assert().success();
+    parx(td.path()).args(["keygen", "other.key", "other.pem"]).assert().success();
+    parx(td.path())
+        .args(["create", "--parity", "50", "--stripe-k", "4", "--chunk-size", "4096"])
+        .args(["--output", ".parx", "--sign-key", "set.key", "data"])
+        .assert()
+        .success();
+
+    let verify = |key: &str| {
+        parx(td.path()).args(["verify", "--verify-key", key, ".parx/manifest.json", "."]).assert()
+    };
+    verify("set.pem").success().stdout(predicate::str::contains("OK"));
+    verify("other.pem").failure().stderr(predicate::str::contains("unexpected key"));
+
+    // Updating a signed set needs its key, and keeps the set signed
+    std::fs::write(data.child("b.bin").path(), vec![8u8; 5_000]).unwrap();
+    parx(td.path())
+        .args(["update", "--output", ".parx", "data"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("signing key"));
+    parx(td.path())
+        .args(["update", "--output", ".parx", "--sign-key", "other.key", "data"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("another key"));
+    parx(td.path())
+        .args(["update", "--output", ".parx", "--sign-key", "set.key", "data"])
+        .assert()
+        .success();
+    verify("set.pem").success().stdout(predicate::str::contains("OK"));
+
+    // A tampered manifest is refused before anything is read or written
+    let mpath = td.child(".parx/manifest.json");
+    let text = std::fs::read_to_string(mpath.path()).unwrap();
+    std::fs::write(mpath.path(), text.replacen("\"size\": 20000", "\"size\": 19999", 1)).unwrap();
+    verify("set.pem").failure().stderr(predicate::str::contains("manifest signature"));
+    parx(td.path())
+        .args(["repair", "--verify-key", "set.pem", ".parx/manifest.json", "."])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("manifest signature"));
+    // Without --verify-key the manifest is taken as it is
+    parx(td.path()).args(["verify", ".parx/manifest.json", "."]).assert().success();
+}
+
+#[test]
+fn verify_key_rejects_unsigned_manifests() {
+    let td = assert_fs::TempDir::new().unwrap();
+    let data = td.child("data");
+    data.create_dir_all().unwrap();
+    std::fs::write(data.child("a.bin").path(), vec![1u8; 9_000]).unwrap();
+    parx(td.path()).args(["keygen", "set.key", "set.pem"]).assert().success();
+    parx(td.path())
+        .args(["create", "--parity", "50", "--stripe-k", "4", "--chunk-size", "4096"])
+        .args(["--output", ".parx", "data"])
+        .assert()
+        .success();
+    parx(td.path())
+        .args(["verify", "--verify-key", "set.pem", ".parx/manifest.json", "."])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("not signed"));
+}
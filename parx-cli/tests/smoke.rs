@@ -1,9 +1,14 @@
 use assert_cmd::prelude::*;
 use assert_fs::prelude::*;
 use predicates::prelude::*;
-use rand::{rngs::StdRng, Rng, SeedableRng};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::io::{Seek, SeekFrom, Write};
 use std::process::Command;
 
+mod common;
+use common::parx;
+
 fn write_random(path: &std::path::Path, bytes: usize, seed: u64) {
     let mut rng = StdRng::seed_from_u64(seed);
     let data: Vec<u8> = (0..bytes).map(|_| rng.gen()).collect();
@@ -88,3 +93,748 @@ fn create_verify_repair_happy_path() {
         .success()
         .stdout(predicate::str::contains("OK"));
 }
+
+#[test]
+fn direct_io_create_verifies() {
+    let td = assert_fs::TempDir::new().unwrap();
+    let data: Vec<u8> = (0..50_000u32).map(|i| (i * 7 % 251) as u8).collect();
+    td.child("data/a.bin").write_binary(&data).unwrap();
+    parx(td.path())
+        .args(["create", "--direct-io", "--stripe-k", "4", "--chunk-size", "4096"])
+        .args(["--gpu", "off", "data"])
+        .assert()
+        .success();
+    parx(td.path()).args(["verify", ".parx/manifest.json", "."]).assert().success();
+    parx(td.path()).args(["paritycheck", "--deep", ".parx"]).assert().success();
+}
+
+#[test]
+fn drop_cache_create_and_verify_see_the_same_data() {
+    let td = assert_fs::TempDir::new().unwrap();
+    let data: Vec<u8> = (0..50_000u32).map(|i| (i * 13 % 251) as u8).collect();
+    td.child("data/a.bin").write_binary(&data).unwrap();
+    parx(td.path())
+        .args(["create", "--drop-cache", "--stripe-k", "4", "--chunk-size", "4096"])
+        .args(["--gpu", "off", "data"])
+        .assert()
+        .success();
+    parx(td.path()).args(["verify", "--drop-cache", ".parx/manifest.json", "."]).assert().success();
+
+    let mut bad = data.clone();
+    bad[20_000] ^= 0x40;
+    td.child("data/a.bin").write_binary(&bad).unwrap();
+    parx(td.path())
+        .args(["verify", "--drop-cache", "--json", ".parx/manifest.json", "."])
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("\"chunks_bad\":1"));
+}
+
+#[test]
+fn create_with_parity_size_reports_the_effective_parity() {
+    let td = assert_fs::TempDir::new().unwrap();
+    td.child("data/a.bin").write_binary(&[3u8; 64 * 1024]).unwrap();
+    // 16 chunks in 2 stripes of 8: 16K holds 2 parity shards per stripe
+    Command::cargo_bin("parx")
+        .unwrap()
+        .current_dir(td.path())
+        .args(["create", "--parity-size", "16K", "--stripe-k", "8", "--chunk-size", "4096"])
+        .args(["--output", ".parx", "--volume-sizes", "1M,1M", "data"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "create: --parity-size gives 2 parity shard(s) per stripe (25% parity, 16384 bytes over 2 volume(s))",
+        ));
+    let mf: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(td.path().join(".parx/manifest.json")).unwrap())
+            .unwrap();
+    assert_eq!(mf["parity_pct"], 25);
+
+    Command::cargo_bin("parx")
+        .unwrap()
+        .current_dir(td.path())
+        .args(["create", "--parity-size", "16K", "--parity", "10", "data"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+}
+
+fn report(cmd: &mut Command) -> serde_json::Value {
+    let out = cmd.assert().success().get_output().stdout.clone();
+    serde_json::from_slice(&out).unwrap()
+}
+
+#[test]
+fn rebalance_compacts_a_set_after_removals() {
+    let td = assert_fs::TempDir::new().unwrap();
+    for i in 0..6u8 {
+        let data: Vec<u8> = (0..8_000u32).map(|j| (j as u8).wrapping_mul(i + 1)).collect();
+        td.child(format!("data/f{}.bin", i)).write_binary(&data).unwrap();
+    }
+    parx(td.path())
+        .args(["create", "--stripe-k", "4", "--chunk-size", "4096", "--gpu", "off", "data"])
+        .assert()
+        .success();
+    for i in [0, 2] {
+        std::fs::remove_file(td.child(format!("data/f{}.bin", i)).path()).unwrap();
+    }
+    parx(td.path()).args(["update", "data"]).assert().success();
+
+    let v = report(parx(td.path()).args(["rebalance", "--json", "--max-io", "0"]));
+    assert_eq!((v["kind"].as_str(), v["chunks_moved"].as_u64()), (Some("rebalance"), Some(0)));
+    assert_eq!(v["budget_exhausted"], true);
+    let v = report(parx(td.path()).args(["rebalance", "--json", "--max-time", "10m"]));
+    assert_eq!((v["holes_before"].as_u64(), v["holes_after"].as_u64()), (Some(4), Some(0)));
+    assert_eq!((v["stripes_before"].as_u64(), v["stripes_after"].as_u64()), (Some(3), Some(2)));
+    parx(td.path()).args(["verify", ".parx/manifest.json", "."]).assert().success();
+}
+
+#[test]
+fn forget_drops_deleted_files_so_verify_passes_again() {
+    let td = assert_fs::TempDir::new().unwrap();
+    td.child("data/a.bin").write_binary(&[1u8; 9_000]).unwrap();
+    td.child("data/old/b.bin").write_binary(&[2u8; 7_000]).unwrap();
+    parx(td.path())
+        .args(["create", "--stripe-k", "4", "--chunk-size", "4096", "--gpu", "off", "data"])
+        .assert()
+        .success();
+    std::fs::remove_dir_all(td.child("data/old").path()).unwrap();
+    parx(td.path()).args(["verify", ".parx/manifest.json", "."]).assert().failure();
+
+    parx(td.path())
+        .args(["forget", "data/nope"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("\"data/nope\" is not in the set"));
+    let out = parx(td.path())
+        .args(["forget", "--json", "data/old"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let v: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(v["kind"], "forget");
+    assert_eq!((v["files_forgotten"].as_u64(), v["chunks_freed"].as_u64()), (Some(1), Some(2)));
+    parx(td.path()).args(["verify", ".parx/manifest.json", "."]).assert().success();
+    let mf = std::fs::read_to_string(td.child(".parx/manifest.json").path()).unwrap();
+    assert!(!mf.contains("old/b.bin"));
+}
+
+#[test]
+fn check_all_grades_the_set_and_nests_each_report() {
+    let td = assert_fs::TempDir::new().unwrap();
+    td.child("data/a.bin").write_binary(&[5u8; 30_000]).unwrap();
+    parx(td.path())
+        .args(["create", "--stripe-k", "4", "--chunk-size", "4096", "--gpu", "off", "data"])
+        .assert()
+        .success();
+    parx(td.path())
+        .args(["check-all", ".parx/manifest.json", "."])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Health: GREEN"));
+
+    let mut bad = [5u8; 30_000];
+    bad[9000] = 0;
+    td.child("data/a.bin").write_binary(&bad).unwrap();
+    let out = parx(td.path())
+        .args(["check-all", "--json", ".parx/manifest.json", "."])
+        .assert()
+        .code(90)
+        .get_output()
+        .stdout
+        .clone();
+    let v: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(v["kind"], "check-all");
+    assert_eq!(v["grade"], "AMBER");
+    assert_eq!(v["verify"]["chunks_bad"], 1);
+    assert_eq!(v["paritycheck"]["shards_bad"], 0);
+    assert!(v["quickcheck"]["total_entries"].as_u64().unwrap() > 0);
+    assert_eq!(v["geometry"]["problems"], serde_json::json!([]));
+}
+
+#[test]
+fn du_breaks_down_a_set_and_a_named_one() {
+    let td = assert_fs::TempDir::new().unwrap();
+    td.child("data/a.bin").write_binary(&[7u8; 20_000]).unwrap();
+    parx(td.path())
+        .args(["create", "--stripe-k", "4", "--chunk-size", "4096", "--gpu", "off", "data"])
+        .assert()
+        .success();
+    parx(td.path())
+        .args([
+            "create",
+            "--set",
+            "b",
+            "--stripe-k",
+            "4",
+            "--chunk-size",
+            "4096",
+            "--gpu",
+            "off",
+            "data",
+        ])
+        .assert()
+        .success();
+
+    let out = parx(td.path()).args(["du", "--json"]).assert().success().get_output().stdout.clone();
+    let v: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!((v["kind"].as_str(), v["source_bytes"].as_u64()), (Some("usage"), Some(20_000)));
+    assert_eq!((v["garbage_bytes"].as_u64(), v["other_bytes"].as_u64()), (Some(0), Some(0)));
+    assert!(v["parity_bytes"].as_u64().unwrap() > 0);
+
+    parx(td.path())
+        .args(["du", "--set", "b"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("source:        20000 bytes in 1 file(s)"))
+        .stdout(predicate::str::contains("garbage:       0 bytes"));
+    parx(td.path()).args(["du", "--set", "nope"]).assert().failure();
+}
+
+#[test]
+fn retry_settings_are_taken_and_reported() {
+    let td = assert_fs::TempDir::new().unwrap();
+    td.child("data/a.bin").write_binary(&[3u8; 20_000]).unwrap();
+    parx(td.path())
+        .args(["create", "--stripe-k", "4", "--chunk-size", "4096", "--gpu", "off", "data"])
+        .assert()
+        .success();
+
+    let out = parx(td.path())
+        .args(["verify", "--json", "--io-retries", "5", "--io-backoff", "50ms"])
+        .args([".parx/manifest.json", "."])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let v: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(v["io_retries"], 0);
+    assert_eq!(v["io_recovered"], 0);
+
+    let out = parx(td.path())
+        .args(["repair", "--json", "--io-retries", "0", ".parx/manifest.json", "."])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let v: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(v["io_retries"], 0);
+    assert!(v.get("unreachable_files").is_none());
+
+    parx(td.path())
+        .args(["verify", "--io-backoff", "0", ".parx/manifest.json", "."])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("duration must be positive"));
+}
+
+fn export(dir: &std::path::Path, format: &str) -> String {
+    let out = parx(dir).args(["export-hashes", "--format", format]).output().unwrap();
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    String::from_utf8(out.stdout).unwrap()
+}
+
+#[test]
+fn export_hashes_in_each_format() {
+    let td = assert_fs::TempDir::new().unwrap();
+    let a: Vec<u8> = (0..6000u32).map(|i| (i % 251) as u8).collect();
+    td.child("data/a.bin").write_binary(&a).unwrap();
+    td.child("data/b.bin").write_binary(&[7u8; 100]).unwrap();
+    parx(td.path())
+        .args(["create", "--parity", "50", "--stripe-k", "4", "--chunk-size", "4096"])
+        .args(["--output", ".parx", "--volume-sizes", "1M", "data"])
+        .assert()
+        .success();
+
+    let lines: Vec<serde_json::Value> =
+        export(td.path(), "jsonl").lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[1]["path"], "data/a.bin");
+    assert_eq!((lines[1]["offset"].as_u64(), lines[1]["len"].as_u64()), (Some(4096), Some(1904)));
+    assert_eq!(lines[1]["chunk"], 1);
+    // Full chunks carry the plain BLAKE3 of their bytes
+    assert_eq!(lines[0]["hash"], blake3::hash(&a[..4096]).to_hex().as_str());
+
+    let borg: Vec<serde_json::Value> =
+        export(td.path(), "borg").lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+    assert_eq!(borg.len(), 2);
+    assert_eq!(borg[0]["type"], "-");
+    assert_eq!(borg[0]["size"], 6000);
+    assert_eq!(borg[0]["chunks"][1][0], lines[1]["hash"]);
+    assert_eq!(borg[0]["chunks"][1][1], 1904);
+
+    let casync = export(td.path(), "casync");
+    let first: Vec<&str> = casync.lines().next().unwrap().split('\t').collect();
+    let id = lines[0]["hash"].as_str().unwrap();
+    assert_eq!(first, [format!("{}/{}.cacnk", &id[..4], id).as_str(), "data/a.bin", "0", "4096"]);
+}
+
+#[test]
+fn keyed_set_needs_its_key_to_verify_and_repair() {
+    let td = assert_fs::TempDir::new().unwrap();
+    td.child("data/a.bin").write_binary(&[7u8; 20_000]).unwrap();
+    td.child("hash.key").write_binary(b"a secret of more than sixteen bytes").unwrap();
+    td.child("other.key").write_binary(b"another secret, also long enough").unwrap();
+    td.child("short.key").write_binary(b"short").unwrap();
+    let create = ["create", "--stripe-k", "4", "--chunk-size", "4096", "--gpu", "off"];
+    parx(td.path())
+        .args(create)
+        .args(["--hash-key", "short.key", "data"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("too short"));
+    parx(td.path()).args(create).args(["--hash-key", "hash.key", "data"]).assert().success();
+
+    parx(td.path())
+        .args(["verify", ".parx/manifest.json", "."])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--hash-key"));
+    parx(td.path())
+        .args(["verify", "--hash-key", "other.key", ".parx/manifest.json", "."])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("wrong hash key"));
+    parx(td.path())
+        .args(["verify", "--hash-key", "hash.key", ".parx/manifest.json", "."])
+        .assert()
+        .success();
+
+    let mut bad = [7u8; 20_000];
+    bad[5000] = 0;
+    td.child("data/a.bin").write_binary(&bad).unwrap();
+    parx(td.path())
+        .args(["repair", "--hash-key", "hash.key", ".parx/manifest.json", "."])
+        .assert()
+        .success();
+    assert_eq!(std::fs::read(td.child("data/a.bin").path()).unwrap(), vec![7u8; 20_000]);
+}
+
+#[test]
+fn extract_chunks_writes_the_rebuilt_chunk_and_leaves_the_file_alone() {
+    let td = assert_fs::TempDir::new().unwrap();
+    let data: Vec<u8> = (0..16_384u32).map(|i| (i * 7 % 251) as u8).collect();
+    td.child("data/a.bin").write_binary(&data).unwrap();
+    parx(td.path())
+        .args([
+            "create",
+            "--stripe-k",
+            "4",
+            "--chunk-size",
+            "4096",
+            "--volume-sizes",
+            "1M",
+            "--gpu",
+            "off",
+            "data",
+        ])
+        .assert()
+        .success();
+    let mut bad = data.clone();
+    bad[8192 + 5] ^= 0xFF;
+    std::fs::write(td.child("data/a.bin").path(), &bad).unwrap();
+
+    parx(td.path())
+        .args(["repair", "--extract-chunks", "out", ".parx/manifest.json", "."])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("1 chunk(s) written to \"out\", tree left untouched"));
+    assert_eq!(std::fs::read(td.child("data/a.bin").path()).unwrap(), bad);
+
+    let index = std::fs::read_to_string(td.child("out/chunks.jsonl").path()).unwrap();
+    let rec: serde_json::Value = serde_json::from_str(index.trim()).unwrap();
+    assert_eq!((rec["chunk"].as_u64(), rec["offset"].as_u64()), (Some(2), Some(8192)));
+    let chunk = std::fs::read(td.child("out").path().join(rec["file"].as_str().unwrap())).unwrap();
+    assert_eq!(chunk, &data[8192..12_288]);
+    assert!(rec["file"].as_str().unwrap().starts_with("00000002-"));
+
+    parx(td.path())
+        .args(["repair", "--extract-chunks", "out", "--fix-paths", ".parx/manifest.json", "."])
+        .assert()
+        .failure();
+}
+
+#[test]
+fn contains_reports_full_partial_and_absent_content() {
+    let td = assert_fs::TempDir::new().unwrap();
+    let mut rng = StdRng::seed_from_u64(3);
+    let archived: Vec<u8> = (0..20_000).map(|_| rng.gen()).collect();
+    let fresh: Vec<u8> = (0..8192).map(|_| rng.gen()).collect();
+    let data = td.child("data");
+    data.create_dir_all().unwrap();
+    data.child("a.bin").write_binary(&archived).unwrap();
+    parx(td.path())
+        .args([
+            "create",
+            "--parity",
+            "50",
+            "--stripe-k",
+            "4",
+            "--chunk-size",
+            "4096",
+            "--output",
+            ".parx",
+            "--volume-sizes",
+            "1M",
+            "data",
+        ])
+        .assert()
+        .success();
+    td.child(".parx/filter.bin").assert(predicate::path::exists());
+
+    // Same content under another name, including the short tail chunk
+    td.child("incoming/copy.bin").write_binary(&archived).unwrap();
+    parx(td.path())
+        .args(["contains", "incoming/copy.bin"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("probably in the set (5/5 chunks"));
+
+    let mut mixed = archived[..8192].to_vec();
+    mixed.extend_from_slice(&fresh);
+    td.child("incoming/mixed.bin").write_binary(&mixed).unwrap();
+    let out = parx(td.path())
+        .args(["contains", "--json", "incoming/mixed.bin"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let v: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(v["chunks"], 4);
+    assert_eq!(v["found"], 2);
+
+    td.child("incoming/new.bin").write_binary(&fresh).unwrap();
+    parx(td.path())
+        .args(["contains", "incoming/new.bin"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("not in the set"));
+}
+
+#[test]
+fn repair_writes_signed_hash_chained_audit_log() {
+    let td = assert_fs::TempDir::new().unwrap();
+    let data = td.child("data");
+    data.create_dir_all().unwrap();
+    let mut rng = StdRng::seed_from_u64(5);
+    let bytes: Vec<u8> = (0..40 * 1024).map(|_| rng.gen()).collect();
+    std::fs::write(data.child("a.bin").path(), &bytes).unwrap();
+    parx(td.path()).args(["keygen", "audit.key", "audit.pem"]).assert().success();
+    parx(td.path())
+        .args([
+            "create",
+            "--parity",
+            "50",
+            "--stripe-k",
+            "4",
+            "--chunk-size",
+            "4096",
+            "--output",
+            ".parx",
+            "--volume-sizes",
+            "1M,1M",
+            "data",
+        ])
+        .assert()
+        .success();
+
+    // Damage two chunks in different stripes
+    let mut f = std::fs::OpenOptions::new().write(true).open(data.child("a.bin").path()).unwrap();
+    for off in [100u64, 5 * 4096 + 7] {
+        f.seek(SeekFrom::Start(off)).unwrap();
+        f.write_all(&[0u8; 50]).unwrap();
+    }
+    drop(f);
+    parx(td.path())
+        .args(["repair", "--audit-key", "audit.key", ".parx/manifest.json", "."])
+        .assert()
+        .success();
+    assert_eq!(std::fs::read(data.child("a.bin").path()).unwrap(), bytes);
+
+    let log = td.child(".parx/audit.log");
+    let text = std::fs::read_to_string(log.path()).unwrap();
+    assert_eq!(text.lines().count(), 2);
+    assert!(text.contains("\"action\":\"repair\"") && text.contains("parity[0]@vol-000.parxv"));
+
+    parx(td.path())
+        .args(["audit-log", "verify", "--pubkey", "audit.pem", ".parx"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("OK: 2 entries (2 signed)"));
+
+    // Rewriting history breaks the chain
+    std::fs::write(log.path(), text.replacen("\"offset\":0", "\"offset\":1", 1)).unwrap();
+    parx(td.path())
+        .args(["audit-log", "verify", ".parx"])
+        .assert()
+        .code(65)
+        .stdout(predicate::str::contains("entry 0: content does not match its hash"));
+}
+
+#[test]
+fn packed_set_repairs_after_unpacking_elsewhere() {
+    let td = assert_fs::TempDir::new().unwrap();
+    let data = td.child("data");
+    data.create_dir_all().unwrap();
+    let content: Vec<u8> = (0..30_000u32).map(|i| (i * 31 % 251) as u8).collect();
+    std::fs::write(data.child("a.bin").path(), &content).unwrap();
+    parx(td.path())
+        .args(["create", "--parity", "50", "--stripe-k", "4", "--chunk-size", "4096"])
+        .args(["--output", ".parx", "data"])
+        .assert()
+        .success();
+    parx(td.path())
+        .args(["pack", "--with-volumes", "-o", "set.parxpack", ".parx"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("with volumes"));
+    parx(td.path())
+        .args(["unpack", "--check", "set.parxpack"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("OK"));
+
+    // A damaged bundle is refused and leaves nothing behind
+    let mut bad = std::fs::read(td.child("set.parxpack").path()).unwrap();
+    let n = bad.len();
+    bad[n / 2] ^= 0xFF;
+    std::fs::write(td.child("bad.parxpack").path(), bad).unwrap();
+    parx(td.path())
+        .args(["unpack", "bad.parxpack", "broken"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("damaged"));
+    let left = walk(&td.child("broken").path().to_path_buf());
+    assert!(left.iter().all(|p| !p.ends_with(".parxv")), "{:?}", left);
+
+    parx(td.path()).args(["unpack", "set.parxpack", "moved"]).assert().success();
+    parx(td.path())
+        .args(["unpack", "set.parxpack", "moved"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--force"));
+    std::fs::remove_dir_all(td.child(".parx").path()).unwrap();
+
+    let mut damaged = content.clone();
+    damaged[5000..5100].fill(0);
+    std::fs::write(data.child("a.bin").path(), &damaged).unwrap();
+    parx(td.path()).args(["repair", "moved/manifest.json", "."]).assert().success();
+    assert_eq!(std::fs::read(data.child("a.bin").path()).unwrap(), content);
+}
+
+fn walk(dir: &std::path::PathBuf) -> Vec<String> {
+    let Ok(rd) = std::fs::read_dir(dir) else { return Vec::new() };
+    rd.flatten()
+        .flat_map(|e| {
+            if e.path().is_dir() {
+                walk(&e.path())
+            } else {
+                vec![e.path().to_string_lossy().to_string()]
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn named_sets_live_side_by_side_with_their_own_parameters() {
+    let td = assert_fs::TempDir::new().unwrap();
+    td.child("photos/a.jpg").write_binary(&[7u8; 50_000]).unwrap();
+    td.child("docs/b.txt").write_binary(&[9u8; 30_000]).unwrap();
+    for (name, parity, sizes) in [("photos", "50", "1M,1M"), ("docs", "20", "1M")] {
+        parx(td.path())
+            .args([
+                "create",
+                "--set",
+                name,
+                "--parity",
+                parity,
+                "--stripe-k",
+                "4",
+                "--chunk-size",
+                "4096",
+                "--volume-sizes",
+                sizes,
+                "--gpu",
+                "off",
+                name,
+            ])
+            .assert()
+            .success();
+    }
+    td.child(".parx/sets/photos/vol-001.parxv").assert(predicate::path::exists());
+    td.child(".parx/sets/docs/manifest.json").assert(predicate::path::exists());
+    td.child(".parx/manifest.json").assert(predicate::path::missing());
+
+    parx(td.path())
+        .args(["sets", "list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("docs: 1 file(s), 30000 bytes;"))
+        .stdout(predicate::str::contains("in 1 volume(s); ok"))
+        .stdout(predicate::str::contains("photos: 1 file(s), 50000 bytes;"));
+
+    // Damage in one set is repaired from that set's parity
+    let mut b = std::fs::read(td.child("docs/b.txt").path()).unwrap();
+    b[100] ^= 0xFF;
+    std::fs::write(td.child("docs/b.txt").path(), &b).unwrap();
+    parx(td.path()).args(["verify", "--set", "photos", ".parx", "."]).assert().success();
+    parx(td.path()).args(["verify", "--set", "docs", ".parx", "."]).assert().failure();
+    parx(td.path()).args(["repair", "--set", "docs", ".parx", "."]).assert().success();
+    parx(td.path()).args(["verify", "--set", "docs", ".parx", "."]).assert().success();
+
+    parx(td.path())
+        .args(["verify", "--set", "music", ".parx", "."])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("no set named \"music\""));
+    parx(td.path())
+        .args(["create", "--set", "../up", "photos"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid set name"));
+
+    std::fs::remove_file(td.child(".parx/sets/photos/vol-001.parxv").path()).unwrap();
+    let out = parx(td.path())
+        .args(["sets", "list", "--json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let v: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(v["kind"], "sets");
+    assert_eq!(v["sets"][0]["name"], "docs");
+    assert_eq!(v["sets"][0]["health"], "ok");
+    assert_eq!(v["sets"][1]["health"], "degraded");
+}
+
+#[test]
+fn inspect_dumps_header_tlvs_trailer_and_index() {
+    let td = assert_fs::TempDir::new().unwrap();
+    let data: Vec<u8> = (0..40_000u32).map(|i| (i * 31 % 253) as u8).collect();
+    td.child("d/a.bin").write_binary(&data).unwrap();
+    parx(td.path())
+        .args([
+            "create",
+            "--parity",
+            "50",
+            "--stripe-k",
+            "4",
+            "--chunk-size",
+            "4096",
+            "--label",
+            "tapes",
+            "--output",
+            ".parx",
+            "d",
+        ])
+        .assert()
+        .success();
+    let vol = ".parx/vol-000.parxv";
+
+    parx(td.path()).args(["inspect", "--entries", "2", vol]).assert().success().stdout(
+        predicate::str::contains("k=4 m=2")
+            .and(predicate::str::contains("chunk size: 4096"))
+            .and(predicate::str::contains("label"))
+            .and(predicate::str::contains("\"tapes\""))
+            .and(predicate::str::contains("Trailer:   v1"))
+            .and(predicate::str::contains("codec zstd, schema v3, crc ok"))
+            .and(predicate::str::contains("more (--entries N)")),
+    );
+
+    let out = parx(td.path()).args(["inspect", "--json", vol]).output().unwrap();
+    assert!(out.status.success());
+    let v: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(v["k"], 4);
+    assert_eq!(v["volume_id"], 0);
+    assert_eq!(v["tlvs"][0]["name"], "volume_id");
+    let idx = &v["index"];
+    assert_eq!(idx["crc_ok"], true);
+    assert_eq!(idx["metaparity"], serde_json::json!(["index", "manifest"]));
+    let n = idx["entries"].as_u64().unwrap();
+    assert!(n > 2);
+    assert_eq!(
+        idx["by_kind"].as_object().unwrap().values().map(|c| c.as_u64().unwrap()).sum::<u64>(),
+        n
+    );
+    assert_eq!(idx["sample"].as_array().unwrap().len() as u64, n.min(10));
+    let off = v["trailer"]["index_offset"].as_u64().unwrap();
+
+    // A wrecked trailer and index: the dump still shows the rest, and the
+    // index as read through the head copy
+    let path = td.child(vol);
+    let mut f = std::fs::OpenOptions::new().write(true).open(path.path()).unwrap();
+    let len = f.metadata().unwrap().len();
+    f.seek(SeekFrom::Start(off)).unwrap();
+    f.write_all(&vec![0xee; (len - off) as usize]).unwrap();
+    drop(f);
+    let out = parx(td.path()).args(["inspect", "--json", vol]).output().unwrap();
+    assert!(out.status.success());
+    let v: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert!(v["trailer_error"].as_str().unwrap().contains("bad trailer magic"));
+    assert_eq!(v["index"]["entries"].as_u64().unwrap(), n);
+
+    // Not a volume at all
+    parx(td.path())
+        .args(["inspect", "d/a.bin"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("not a ParXive volume"));
+}
+
+#[test]
+fn which_stripe_maps_offset_to_chunk_and_shards() {
+    let td = assert_fs::TempDir::new().unwrap();
+    let data = td.child("data");
+    data.create_dir_all().unwrap();
+    data.child("a.bin").write_binary(&vec![1u8; 12 * 1024]).unwrap();
+    data.child("b.bin").write_binary(&vec![2u8; 8 * 1024]).unwrap();
+    parx(td.path())
+        .args([
+            "create",
+            "--parity",
+            "50",
+            "--stripe-k",
+            "4",
+            "--chunk-size",
+            "4096",
+            "--output",
+            ".parx",
+            "--volume-sizes",
+            "1M,1M",
+            "data",
+        ])
+        .assert()
+        .success();
+
+    // b.bin byte 5000 is global chunk 4 -> stripe 1, position 0
+    let out = parx(td.path())
+        .args(["which-stripe", "--json", "data/b.bin", "0x1388"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let v: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(v["chunk"], 4);
+    assert_eq!(v["stripe"], 1);
+    assert_eq!(v["stripe_pos"], 0);
+    assert_eq!(v["members"].as_array().unwrap().len(), 1);
+    let parity = v["parity"].as_array().unwrap();
+    assert_eq!(parity.len(), 2);
+    assert!(parity.iter().all(|s| s["status"] == "ok"));
+
+    parx(td.path())
+        .args(["which-stripe", "./data/a.bin", "100"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("chunk 0 (bytes 0..4096), stripe 0 position 0/4"))
+        .stdout(predicate::str::contains("vol-001.parxv"));
+
+    parx(td.path()).args(["which-stripe", "data/a.bin", "999999"]).assert().failure();
+}
@@ -0,0 +1,136 @@
+use assert_cmd::prelude::*;
+use assert_fs::prelude::*;
+use predicates::prelude::*;
+
+mod common;
+use common::parx;
+
+const CREATE: &[&str] = &[
+    "create",
+    "--parity",
+    "50",
+    "--stripe-k",
+    "4",
+    "--chunk-size",
+    "4096",
+    "--output",
+    ".parx",
+    "--volume-sizes",
+    "1M,1M",
+];
+
+/// A tar member: ustar header, data and padding.
+fn member(tar: &mut Vec<u8>, name: &str, kind: u8, data: &[u8]) {
+    let mut h = [0u8; 512];
+    h[..name.len()].copy_from_slice(name.as_bytes());
+    h[100..107].copy_from_slice(b"0000644");
+    h[108..115].copy_from_slice(b"0001750");
+    h[116..123].copy_from_slice(b"0001750");
+    h[124..135].copy_from_slice(format!("{:011o}", data.len()).as_bytes());
+    h[136..147].copy_from_slice(b"00000000000");
+    h[156] = kind;
+    h[257..263].copy_from_slice(b"ustar\0");
+    h[263..265].copy_from_slice(b"00");
+    h[148..156].copy_from_slice(b"        ");
+    let sum: u32 = h.iter().map(|&b| b as u32).sum();
+    h[148..155].copy_from_slice(format!("{:06o}\0", sum).as_bytes());
+    tar.extend_from_slice(&h);
+    tar.extend_from_slice(data);
+    tar.resize(tar.len().div_ceil(512) * 512, 0);
+}
+
+fn pax_record(key: &str, value: &str) -> String {
+    let body = format!(" {}={}\n", key, value);
+    let mut len = body.len() + 1;
+    while format!("{}{}", len, body).len() != len {
+        len += 1;
+    }
+    format!("{}{}", len, body)
+}
+
+#[test]
+fn stdin_tar_protects_members_and_repairs_the_extracted_tree() {
+    let td = assert_fs::TempDir::new().unwrap();
+    let long = format!("deep/{}/b.bin", "x".repeat(120));
+    let files: Vec<(String, Vec<u8>)> = vec![
+        ("sub/a.bin".to_string(), (0..9000u32).map(|i| (i * 7) as u8).collect()),
+        (long.clone(), vec![0xb5; 5000]),
+        ("pax/c.bin".to_string(), (0..4096u32).map(|i| (i % 251) as u8).collect()),
+        ("d.bin".to_string(), b"ddd".to_vec()),
+    ];
+    let mut tar = Vec::new();
+    member(&mut tar, "./sub/", b'5', b"");
+    member(&mut tar, "./sub/a.bin", b'0', &files[0].1);
+    member(&mut tar, "././@LongLink", b'L', format!("{}\0", long).as_bytes());
+    member(&mut tar, "truncated-name", b'0', &files[1].1);
+    member(&mut tar, "./PaxHeaders/c.bin", b'x', pax_record("path", "pax/c.bin").as_bytes());
+    member(&mut tar, "short-c.bin", b'0', &files[2].1);
+    member(&mut tar, "link", b'2', b"");
+    member(&mut tar, "d.bin", b'0', b"stale");
+    member(&mut tar, "d.bin", b'0', &files[3].1);
+    tar.extend_from_slice(&[0u8; 1024]);
+
+    assert_cmd::Command::from_std(parx(td.path()))
+        .args(CREATE)
+        .args(["--stdin-tar", "data"])
+        .write_stdin(tar)
+        .assert()
+        .success();
+    let mf: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(td.child(".parx/manifest.json").path()).unwrap())
+            .unwrap();
+    let paths: Vec<&str> =
+        mf["files"].as_array().unwrap().iter().map(|f| f["rel_path"].as_str().unwrap()).collect();
+    let long_path = format!("data/{}", long);
+    assert_eq!(paths, ["data/d.bin", &long_path, "data/pax/c.bin", "data/sub/a.bin"]);
+    assert_eq!(mf["total_bytes"], 9000 + 5000 + 4096 + 3);
+
+    // Extract the archive and check it against the set
+    for (name, data) in &files {
+        td.child(format!("data/{}", name)).write_binary(data).unwrap();
+    }
+    parx(td.path())
+        .args(["verify", ".parx/manifest.json", "."])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("OK"));
+
+    let mut damaged = files[0].1.clone();
+    damaged[4096..8192].fill(0);
+    td.child("data/sub/a.bin").write_binary(&damaged).unwrap();
+    parx(td.path())
+        .args(["audit", ".parx/manifest.json", "."])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Repairable: YES"));
+    parx(td.path()).args(["repair", ".parx/manifest.json", "."]).assert().success();
+    assert_eq!(std::fs::read(td.child("data/sub/a.bin").path()).unwrap(), files[0].1);
+    parx(td.path())
+        .args(["verify", ".parx/manifest.json", "."])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("OK"));
+}
+
+#[test]
+fn stdin_tar_refuses_paths_leaving_the_extraction_dir() {
+    let td = assert_fs::TempDir::new().unwrap();
+    let mut tar = Vec::new();
+    member(&mut tar, "../evil", b'0', b"x");
+    tar.extend_from_slice(&[0u8; 1024]);
+    assert_cmd::Command::from_std(parx(td.path()))
+        .args(CREATE)
+        .args(["--stdin-tar", "data"])
+        .write_stdin(tar)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("refusing path"));
+
+    assert_cmd::Command::from_std(parx(td.path()))
+        .args(CREATE)
+        .args(["--stdin-tar", "data"])
+        .write_stdin(vec![0x41u8; 700])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("checksum"));
+}
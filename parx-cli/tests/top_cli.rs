@@ -0,0 +1,89 @@
+use assert_cmd::prelude::*;
+use assert_fs::prelude::*;
+use predicates::prelude::*;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+mod common;
+use common::parx;
+
+#[test]
+fn top_shows_a_run_published_by_another_process() {
+    let td = assert_fs::TempDir::new().unwrap();
+    parx(td.path())
+        .args(["top", "--once"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("no operation running on \".parx\""));
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+    let state = serde_json::json!({
+        "op": "create",
+        "pid": 4242,
+        "dir": ".parx/sets/photos",
+        "updated_ms": now,
+        "interval_ms": 1000,
+        "snapshot": {
+            "elapsed_ms": 65_000,
+            "last": false,
+            "workers": 8,
+            "workers_busy": 6,
+            "stages": [
+                {"path": "hash", "depth": 0, "bytes_done": 3u64 << 30, "bytes_total": 3u64 << 30,
+                 "items_done": 4, "items_total": 4, "elapsed_ms": 40_000,
+                 "bytes_per_sec": 80 << 20, "done": true},
+                {"path": "write", "depth": 0, "bytes_done": 1u64 << 30, "bytes_total": 4u64 << 30,
+                 "items_done": 0, "items_total": 0, "elapsed_ms": 25_000,
+                 "bytes_per_sec": 40 << 20, "eta_ms": 76_000, "current": "vol-001.parxv",
+                 "done": false}
+            ]
+        }
+    });
+    td.child(".parx/sets/photos/running/create-4242.json").write_str(&state.to_string()).unwrap();
+
+    parx(td.path())
+        .args(["top", "--once"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("create (pid 4242) on .parx/sets/photos, running 1m05s\n"))
+        .stdout(predicate::str::contains("workers: 6/8 busy"))
+        .stdout(predicate::str::is_match(r"hash +100% +3\.0 GiB +80\.0 MiB/s +done").unwrap())
+        .stdout(
+            predicate::str::is_match(
+                r"write +25% +1\.0 GiB +40\.0 MiB/s +ETA 1m16s +vol-001.parxv",
+            )
+            .unwrap(),
+        );
+
+    let out = parx(td.path())
+        .args(["top", "--once", "--json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let v: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(v[0]["pid"], 4242);
+    assert_eq!(v[0]["snapshot"]["stages"][1]["current"], "vol-001.parxv");
+
+    // A run that stopped updating is flagged rather than shown as live
+    let mut stale = state.clone();
+    stale["updated_ms"] = serde_json::json!(now - 60_000);
+    td.child(".parx/sets/photos/running/create-4242.json").write_str(&stale.to_string()).unwrap();
+    parx(td.path())
+        .args(["top", "--once"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("[stale: no update"));
+}
+
+#[test]
+fn create_leaves_no_state_behind() {
+    let td = assert_fs::TempDir::new().unwrap();
+    td.child("data/a.bin").write_binary(&[5u8; 20_000]).unwrap();
+    parx(td.path())
+        .args(["create", "--stripe-k", "4", "--volume-sizes", "1M", "--gpu", "off", "data"])
+        .assert()
+        .success();
+    td.child(".parx/manifest.json").assert(predicate::path::exists());
+    td.child(".parx/running").assert(predicate::path::missing());
+}
@@ -0,0 +1,148 @@
+use assert_cmd::prelude::*;
+use assert_fs::prelude::*;
+use predicates::prelude::*;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::io::{Seek, SeekFrom, Write};
+
+mod common;
+use common::parx;
+
+fn pack(rng: &mut StdRng, len: usize) -> Vec<u8> {
+    (0..len).map(|_| rng.gen()).collect()
+}
+
+#[test]
+fn append_only_update_encodes_new_packs_and_skips_locks() {
+    let td = assert_fs::TempDir::new().unwrap();
+    let mut rng = StdRng::seed_from_u64(21);
+    let repo = td.child("repo");
+    repo.child("config").write_binary(&pack(&mut rng, 300)).unwrap();
+    repo.child("data/00/pack-a").write_binary(&pack(&mut rng, 5000)).unwrap();
+    repo.child("locks/1234").write_str("lock").unwrap();
+    parx(td.path())
+        .args([
+            "create",
+            "--preset",
+            "backup-repo",
+            "--parity",
+            "50",
+            "--stripe-k",
+            "4",
+            "--chunk-size",
+            "4096",
+            "--output",
+            ".parx",
+            "--volume-sizes",
+            "1M,1M",
+            "repo",
+        ])
+        .assert()
+        .success();
+    let mf = std::fs::read_to_string(td.child(".parx/manifest.json").path()).unwrap();
+    assert!(mf.contains("repo/data/00/pack-a") && !mf.contains("locks/1234"));
+
+    // A backup run adds packs (the last stripe was partial) and another lock
+    let new_pack = pack(&mut rng, 20_000);
+    repo.child("data/01/pack-b").write_binary(&new_pack).unwrap();
+    repo.child("locks/5678").write_str("lock").unwrap();
+    let out = parx(td.path())
+        .args(["update", "--append-only-aware", "--json", "repo"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let v: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(v["files_added"], 1);
+    assert_eq!(v["files_unchanged"], 2);
+    assert_eq!(v["chunks_added"], 5);
+    assert_eq!(v["stripes_rewritten"], 1);
+    assert_eq!(v["stripes_added"], 1);
+
+    parx(td.path()).args(["verify", ".parx/manifest.json", "."]).assert().success();
+
+    // The new pack and the rewritten tail stripe are both protected
+    let mut f =
+        std::fs::OpenOptions::new().write(true).open(repo.child("data/01/pack-b").path()).unwrap();
+    f.seek(SeekFrom::Start(100)).unwrap();
+    f.write_all(&[0u8; 64]).unwrap();
+    f.seek(SeekFrom::Start(17_000)).unwrap();
+    f.write_all(&[0u8; 64]).unwrap();
+    drop(f);
+    parx(td.path()).args(["repair", ".parx/manifest.json", "."]).assert().success();
+    assert_eq!(std::fs::read(repo.child("data/01/pack-b").path()).unwrap(), new_pack);
+    parx(td.path()).args(["paritycheck", ".parx"]).assert().success();
+
+    // Rewriting a known pack is not an append
+    repo.child("data/00/pack-a").write_binary(&pack(&mut rng, 100)).unwrap();
+    parx(td.path())
+        .args(["update", "--append-only-aware", "repo"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("changed or removed"));
+}
+
+#[test]
+fn update_reencodes_only_stripes_of_changed_files() {
+    let td = assert_fs::TempDir::new().unwrap();
+    let mut rng = StdRng::seed_from_u64(22);
+    // 4 KiB chunks, k=4: a.bin is chunks 0-2, b.bin 3-5, c.bin 6-7
+    let mut a = pack(&mut rng, 12_000);
+    td.child("data/a.bin").write_binary(&a).unwrap();
+    td.child("data/b.bin").write_binary(&pack(&mut rng, 10_000)).unwrap();
+    td.child("data/c.bin").write_binary(&pack(&mut rng, 5_000)).unwrap();
+    parx(td.path())
+        .args(["create", "--parity", "50", "--stripe-k", "4", "--chunk-size", "4096"])
+        .args(["--output", ".parx", "--volume-sizes", "1M,1M", "data"])
+        .assert()
+        .success();
+
+    // Edit one chunk of a.bin, drop c.bin, add d.bin
+    a[5000] ^= 0xFF;
+    td.child("data/a.bin").write_binary(&a).unwrap();
+    std::fs::remove_file(td.child("data/c.bin").path()).unwrap();
+    let d = pack(&mut rng, 4096);
+    td.child("data/d.bin").write_binary(&d).unwrap();
+    let out = parx(td.path()).args(["update", "--json", "data"]).output().unwrap();
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    let v: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    let counts = |keys: &[&str]| keys.iter().map(|k| v[k].as_u64().unwrap()).collect::<Vec<_>>();
+    assert_eq!(
+        counts(&["files_added", "files_changed", "files_removed", "files_unchanged"]),
+        [1, 1, 1, 1]
+    );
+    assert_eq!(counts(&["chunks_added", "chunks_rewritten", "chunks_freed"]), [1, 1, 2]);
+    assert_eq!(counts(&["stripes_added", "stripes_rewritten"]), [1, 2]);
+    parx(td.path()).args(["verify", ".parx/manifest.json", "."]).assert().success();
+    parx(td.path())
+        .args(["paritycheck", "--deep", ".parx"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("BAD").not());
+
+    // Both the edited chunk and the new file are protected
+    for (name, off) in [("data/a.bin", 4200), ("data/d.bin", 10)] {
+        let mut f = std::fs::OpenOptions::new().write(true).open(td.child(name).path()).unwrap();
+        f.seek(SeekFrom::Start(off)).unwrap();
+        f.write_all(&[0u8; 32]).unwrap();
+    }
+    parx(td.path()).args(["repair", ".parx/manifest.json", "."]).assert().success();
+    assert_eq!(std::fs::read(td.child("data/a.bin").path()).unwrap(), a);
+    assert_eq!(std::fs::read(td.child("data/d.bin").path()).unwrap(), d);
+
+    for name in ["data/a.parx.bak", "data/d.parx.bak"] {
+        std::fs::remove_file(td.child(name).path()).unwrap();
+    }
+
+    // A file that outgrows its slots moves to the end
+    let b = pack(&mut rng, 20_000);
+    td.child("data/b.bin").write_binary(&b).unwrap();
+    let out = parx(td.path()).args(["update", "--json", "data"]).output().unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!((v["chunks_added"].as_u64(), v["chunks_freed"].as_u64()), (Some(5), Some(3)));
+    parx(td.path()).args(["verify", ".parx/manifest.json", "."]).assert().success();
+    parx(td.path()).args(["update", "--json", "data"]).assert().success().stdout(
+        predicate::str::contains("\"files_changed\":0")
+            .and(predicate::str::contains("\"stripes_added\":0")),
+    );
+}
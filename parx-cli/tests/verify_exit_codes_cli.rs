@@ -0,0 +1,60 @@
+use assert_cmd::prelude::*;
+use assert_fs::prelude::*;
+use predicates::prelude::*;
+
+mod common;
+use common::parx;
+
+#[test]
+fn verify_names_the_damage_and_exits_with_the_worst_class() {
+    let td = assert_fs::TempDir::new().unwrap();
+    for name in ["a.bin", "b.bin", "c.bin"] {
+        td.child("data").child(name).write_binary(&[name.as_bytes()[0]; 8192]).unwrap();
+    }
+    parx(td.path())
+        .args(["create", "--parity", "50", "--stripe-k", "4", "--chunk-size", "4096"])
+        .args(["--output", ".parx", "--volume-sizes", "1M", "data"])
+        .assert()
+        .success();
+    let verify = || parx(td.path()).args(["verify", ".parx/manifest.json", "."]).assert();
+
+    let mut a = vec![b'a'; 8192];
+    a[10] ^= 1;
+    td.child("data/a.bin").write_binary(&a).unwrap();
+    verify().code(80).stdout(predicate::str::contains("corrupt: data/a.bin (1 bad chunk(s))"));
+
+    td.child("data/b.bin").write_binary(&[b'b'; 5000]).unwrap();
+    verify().code(81).stdout(predicate::str::contains("truncated: data/b.bin"));
+
+    std::fs::remove_file(td.child("data/c.bin").path()).unwrap();
+    verify()
+        .code(82)
+        .stdout(predicate::str::contains("missing: data/c.bin (2 bad chunk(s))"))
+        .stdout(predicate::str::contains("corrupt: data/a.bin"));
+}
+
+#[test]
+fn verify_warns_when_parity_coverage_degraded() {
+    let td = assert_fs::TempDir::new().unwrap();
+    td.child("data/a.bin").write_binary(&[7u8; 16 * 1024]).unwrap();
+    parx(td.path())
+        .args(["create", "--parity", "25", "--stripe-k", "4", "--chunk-size", "4096"])
+        .args(["--output", ".parx", "--volume-sizes", "1M,1M", "data"])
+        .assert()
+        .success();
+    let verify = || parx(td.path()).args(["verify", ".parx/manifest.json", "."]).assert();
+    verify().success().stderr(predicate::str::contains("parity").not());
+
+    // The only parity shard of the stripe lives on vol-000; the data is fine
+    std::fs::remove_file(td.child(".parx/vol-000.parxv").path()).unwrap();
+    verify()
+        .success()
+        .stdout(predicate::str::contains("OK"))
+        .stderr(predicate::str::contains("volume vol-000.parxv is missing"))
+        .stderr(predicate::str::contains("1 stripe(s) have no parity left"));
+    parx(td.path())
+        .args(["verify", "--json", ".parx/manifest.json", "."])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\"stripes_unprotected\":1"));
+}
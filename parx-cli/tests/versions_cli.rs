@@ -0,0 +1,142 @@
+use assert_cmd::prelude::*;
+use assert_fs::prelude::*;
+use predicates::prelude::*;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+mod common;
+use common::parx;
+
+fn create(dir: &std::path::Path) {
+    create_with(dir, &[]).success();
+}
+
+fn create_with(dir: &std::path::Path, extra: &[&str]) -> assert_cmd::assert::Assert {
+    parx(dir)
+        .arg("create")
+        .args(extra)
+        .args([
+            "--parity",
+            "50",
+            "--stripe-k",
+            "4",
+            "--chunk-size",
+            "4096",
+            "--output",
+            ".parx",
+            "--volume-sizes",
+            "1M,1M",
+            "--keep-versions",
+            "2",
+            "data",
+        ])
+        .assert()
+}
+
+#[test]
+fn repair_as_of_restores_previous_version() {
+    let td = assert_fs::TempDir::new().unwrap();
+    let data = td.child("data");
+    data.create_dir_all().unwrap();
+    let mut rng = StdRng::seed_from_u64(7);
+    let a: Vec<u8> = (0..32 * 1024).map(|_| rng.gen()).collect();
+    let b: Vec<u8> = (0..20 * 1024).map(|_| rng.gen()).collect();
+    std::fs::write(data.child("a.bin").path(), &a).unwrap();
+    std::fs::write(data.child("b.bin").path(), &b).unwrap();
+    create(td.path());
+
+    // v2: edit one chunk of a.bin and rename b.bin
+    let mut a2 = a.clone();
+    a2[5000..5100].fill(0xEE);
+    std::fs::write(data.child("a.bin").path(), &a2).unwrap();
+    std::fs::rename(data.child("b.bin").path(), data.child("c.bin").path()).unwrap();
+    create(td.path());
+
+    parx(td.path())
+        .args(["versions", ".parx"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("v1"))
+        .stdout(predicate::str::contains("(retained)"))
+        .stdout(predicate::str::contains("(live)"));
+
+    let out = parx(td.path())
+        .args(["repair", "--json", "--as-of", "1", ".parx/manifest.json", "."])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let rep: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(rep["chunks_failed"], 0);
+    assert!(rep["chunks_reused"].as_u64().unwrap() >= 5, "renamed file reused: {}", rep);
+    assert!(rep["chunks_reconstructed"].as_u64().unwrap() >= 1);
+    // c.bin is only in v2
+    assert_eq!(rep["files_removed"], 1);
+    assert!(!data.child("c.bin").path().exists());
+
+    assert_eq!(std::fs::read(data.child("a.bin").path()).unwrap(), a);
+    assert_eq!(std::fs::read(data.child("b.bin").path()).unwrap(), b);
+    parx(td.path())
+        .args(["verify", ".parx/versions/v1/manifest.json", "."])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("OK"));
+}
+
+fn generation(dir: &std::path::Path) -> u64 {
+    let (mf, _) = parx_core::manifest::load(&dir.join(".parx/manifest.json")).unwrap();
+    mf.generation().unwrap()
+}
+
+#[test]
+fn failed_create_keeps_the_live_set_and_versions_share_parity() {
+    let td = assert_fs::TempDir::new().unwrap();
+    let data = td.child("data");
+    data.create_dir_all().unwrap();
+    let mut rng = StdRng::seed_from_u64(9);
+    let v1: Vec<u8> = (0..64 * 1024).map(|_| rng.gen()).collect();
+    std::fs::write(data.child("a.bin").path(), &v1).unwrap();
+    create(td.path());
+    assert_eq!(generation(td.path()), 1);
+
+    // A create that fails leaves the set as it was
+    create_with(td.path(), &["--pre-hook", "exit 3"]).failure();
+    parx(td.path()).args(["verify", ".parx/manifest.json", "."]).assert().success();
+    assert!(!td.child(".parx/versions/.pending").path().exists());
+    assert!(!td.child(".parx/versions/v1").path().exists());
+
+    // v2 changes the first chunk, v3 the last; v1's last chunk is then only
+    // in v2, whose archive keeps the parity of that stripe
+    let mut v2 = v1.clone();
+    v2[..100].fill(1);
+    std::fs::write(data.child("a.bin").path(), &v2).unwrap();
+    create(td.path());
+    assert_eq!(generation(td.path()), 2);
+    let mut v3 = v2.clone();
+    v3[60 * 1024..].fill(2);
+    std::fs::write(data.child("a.bin").path(), &v3).unwrap();
+    create(td.path());
+    assert_eq!(generation(td.path()), 3);
+
+    let out = parx(td.path()).args(["versions", "--json", ".parx"]).output().unwrap();
+    let graph: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    let ids: Vec<u64> =
+        graph["versions"].as_array().unwrap().iter().map(|v| v["id"].as_u64().unwrap()).collect();
+    assert_eq!(ids, [1, 2, 3]);
+    // 64 KiB in stripes of 4 chunks of 4 KiB: only the changed stripe keeps parity
+    for v in &graph["versions"].as_array().unwrap()[..2] {
+        assert_eq!(v["stripes_without_parity"], 3, "{}", v);
+    }
+
+    let out = parx(td.path())
+        .args(["repair", "--json", "--as-of", "1", ".parx/manifest.json", "."])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let rep: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(rep["chunks_failed"], 0);
+    assert_eq!(rep["chunks_reconstructed"], 2);
+    assert_eq!(std::fs::read(data.child("a.bin").path()).unwrap(), v1);
+}
@@ -0,0 +1,59 @@
+//! Size and speed of each index codec on a synthetic index laid out like
+//! `create` writes it (round-robin inner shards with hashes).
+//!
+//! `cargo bench -p parx-core --bench index_codecs [-- ENTRIES]`
+
+use parx_core::codec::{self, Codec};
+use parx_core::volume::{encode_entries, ShardKind, VolumeEntry};
+use std::time::Instant;
+
+fn main() {
+    let n: usize = std::env::args().skip(1).find_map(|a| a.parse().ok()).unwrap_or(1_000_000);
+    let chunk = 1u64 << 20;
+    let mut state = 0x9E37_79B9_7F4A_7C15u64;
+    let entries: Vec<VolumeEntry> = (0..n as u64)
+        .map(|i| {
+            let mut hash = [0u8; 32];
+            for b in hash.chunks_mut(8) {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                b.copy_from_slice(&state.to_le_bytes());
+            }
+            VolumeEntry {
+                stripe: i / 2,
+                parity_idx: (i % 2) as u16,
+                offset: 64 + i * chunk,
+                len: chunk as u32,
+                hash: Some(hash),
+                kind: ShardKind::Inner,
+            }
+        })
+        .collect();
+    let raw = encode_entries(&entries).expect("serialize index");
+    println!("{} entries, {} bytes raw", n, raw.len());
+    println!(
+        "{:<10} {:>12} {:>7} {:>12} {:>12}",
+        "codec", "bytes", "ratio", "comp MB/s", "decomp MB/s"
+    );
+    for c in
+        [Codec::None, Codec::Lz4, Codec::Zstd(1), Codec::Zstd(0), Codec::Zstd(9), Codec::Zstd(19)]
+    {
+        let t = Instant::now();
+        let packed = c.compress(&raw).expect("compress");
+        let comp = t.elapsed().as_secs_f64();
+        let t = Instant::now();
+        let back = codec::decompress(&packed, raw.len()).expect("decompress");
+        let decomp = t.elapsed().as_secs_f64();
+        assert_eq!(back.len(), raw.len());
+        let mbs = |s: f64| raw.len() as f64 / 1e6 / s.max(1e-9);
+        println!(
+            "{:<10} {:>12} {:>7.3} {:>12.0} {:>12.0}",
+            c.to_string(),
+            packed.len(),
+            packed.len() as f64 / raw.len() as f64,
+            mbs(comp),
+            mbs(decomp)
+        );
+    }
+}
@@ -0,0 +1,129 @@
+//! Async variants of [`verify`](crate::verify) and [`repair`](crate::repair)
+//! for tokio services (feature `async`): a backup daemon or a web UI awaits
+//! them like any other I/O, without setting up a thread pool of its own.
+//!
+//! Each call starts the run on tokio's blocking pool right away and returns
+//! an [`Operation`]: a stream of [`Event::Progress`] snapshots (the stages
+//! of [`crate::progress`], every [`INTERVAL`], and one marked `last` when
+//! the run stops) that ends with [`Event::Done`] carrying the report, or
+//! with the error that stopped the run. The reads and writes themselves stay
+//! blocking ones on that pool, as with tokio's own file I/O. Dropping the
+//! operation does not cancel the run; it completes in the background.
+
+use crate::path_safety::PathPolicy;
+use crate::progress::{Progress, ProgressSink, Snapshot};
+use crate::repair::{RepairOptions, RepairReport};
+use crate::verify::{VerifyOptions, VerifyReport};
+use anyhow::{anyhow, Result};
+use futures_core::Stream;
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::task::JoinHandle;
+
+/// How often a running operation sends a progress snapshot.
+pub const INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Debug)]
+pub enum Event<R> {
+    Progress(Snapshot),
+    /// The run finished; always the last event
+    Done(R),
+}
+
+/// Forwards snapshots into the operation's channel.
+struct ChannelSink(UnboundedSender<Snapshot>);
+
+impl ProgressSink for ChannelSink {
+    fn report(&self, snap: &Snapshot) {
+        // Nobody listening any more is fine: the run goes on
+        let _ = self.0.send(snap.clone());
+    }
+}
+
+/// A verify or repair running on tokio's blocking pool.
+pub struct Operation<R> {
+    events: UnboundedReceiver<Snapshot>,
+    task: Option<JoinHandle<Result<R>>>,
+    progress: Progress,
+}
+
+impl<R: Send + 'static> Operation<R> {
+    fn spawn(progress: Progress, run: impl FnOnce() -> Result<R> + Send + 'static) -> Self {
+        let (tx, events) = unbounded_channel();
+        let registry = progress.clone();
+        let task = tokio::task::spawn_blocking(move || {
+            let reporter = registry.start(Arc::new(ChannelSink(tx)), INTERVAL);
+            let res = run();
+            // Sends the last snapshot and closes the channel
+            drop(reporter);
+            res
+        });
+        Self { events, task: Some(task), progress }
+    }
+
+    /// Progress right now, without waiting for the next event.
+    pub fn snapshot(&self) -> Snapshot {
+        self.progress.snapshot()
+    }
+
+    /// Skip the progress events and wait for the report.
+    pub async fn finish(mut self) -> Result<R> {
+        self.events.close();
+        match self.task.take() {
+            Some(task) => joined(task.await),
+            None => Err(anyhow!("operation already finished")),
+        }
+    }
+}
+
+fn joined<R>(res: Result<Result<R>, tokio::task::JoinError>) -> Result<R> {
+    res.map_err(|e| anyhow!("operation panicked: {}", e))?
+}
+
+impl<R> Stream for Operation<R> {
+    type Item = Result<Event<R>>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let Some(task) = this.task.as_mut() else { return Poll::Ready(None) };
+        if let Some(snap) = ready!(this.events.poll_recv(cx)) {
+            return Poll::Ready(Some(Ok(Event::Progress(snap))));
+        }
+        // The channel closes only once the run returned
+        let res = ready!(Pin::new(task).poll(cx));
+        this.task = None;
+        Poll::Ready(Some(joined(res).map(Event::Done)))
+    }
+}
+
+/// [`crate::verify::verify_with_options`] as an [`Operation`] with stage
+/// `verify`. Must be called within a tokio runtime.
+pub fn verify_async(
+    manifest_path: PathBuf,
+    root: PathBuf,
+    mut opts: VerifyOptions,
+) -> Operation<VerifyReport> {
+    let progress = opts.progress.get_or_insert_with(Progress::new).clone();
+    Operation::spawn(progress, move || {
+        crate::verify::verify_with_options(&manifest_path, &root, &opts)
+    })
+}
+
+/// [`crate::repair::repair_with_options`] as an [`Operation`] with stages
+/// `scan` and `rebuild`. Must be called within a tokio runtime.
+pub fn repair_async(
+    manifest_path: PathBuf,
+    root: PathBuf,
+    policy: PathPolicy,
+    mut opts: RepairOptions,
+) -> Operation<RepairReport> {
+    let progress = opts.progress.get_or_insert_with(Progress::new).clone();
+    Operation::spawn(progress, move || {
+        crate::repair::repair_with_options(&manifest_path, &root, policy, &opts)
+    })
+}
@@ -0,0 +1,185 @@
+//! Damage audit: which stripes lost chunks and whether their parity can still
+//! rebuild them, without writing anything.
+
+use crate::fshint::{self, FileStamp, Hints};
+use crate::manifest;
+use crate::manifest_v2::RecoveryReport;
+use crate::path_safety::{validate_path, PathPolicy};
+use crate::repair::{collect_parity_copies, VolumeSource};
+use crate::storage::ReadCost;
+use crate::volume::ShardKind;
+use anyhow::{Context, Result};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// A stripe with at least one bad or missing data chunk.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StripeDamage {
+    pub stripe: u64,
+    /// Chunk indices that are missing or fail their hash
+    pub bad_chunks: Vec<u64>,
+    /// Parity shards of the stripe that are readable and pass their hash
+    pub parity_available: usize,
+    /// Inner parity suffices: `bad_chunks.len() <= parity_available`
+    pub repairable: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditReport {
+    pub stripes: u64,
+    pub chunks_ok: u64,
+    pub chunks_bad: u64,
+    /// Damaged stripes in stripe order
+    pub damaged: Vec<StripeDamage>,
+    /// Every damaged stripe is repairable from inner parity. Sets with outer
+    /// parity may recover more than this says.
+    pub repairable: bool,
+    /// Files not hashed because the filesystem vouched for them (`fs_hints`)
+    pub files_trusted: u64,
+    /// Volumes whose index could not be read
+    pub unreadable_volumes: u64,
+    /// Present when manifest.json was unreadable and the v2 companion was used.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub manifest_recovery: Option<RecoveryReport>,
+}
+
+impl crate::report::Report for AuditReport {
+    const KIND: &'static str = "audit";
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AuditOptions {
+    /// Skip hashing files the filesystem checksums itself and that have not
+    /// changed since they last hashed clean (see [`crate::fshint`]); updates
+    /// the hints in the parity dir
+    pub fs_hints: bool,
+    /// Files open at once across all workers (see [`crate::fdlimit`])
+    pub max_open_files: Option<usize>,
+    /// Treat every file as living on a checksumming filesystem (tests)
+    #[doc(hidden)]
+    pub assume_checksummed: bool,
+}
+
+/// Hash every chunk under `root`, then read the parity of the damaged stripes
+/// to decide whether `repair` could restore them.
+pub fn audit(manifest_path: &Path, root: &Path, policy: PathPolicy) -> Result<AuditReport> {
+    audit_with(manifest_path, root, policy, &AuditOptions::default())
+}
+
+/// Per-file outcome: bad chunks, whether hashing was skipped, and the stamp
+/// to remember when the file hashed clean on a checksumming filesystem.
+type FileAudit = (Vec<u64>, bool, Option<FileStamp>);
+
+pub fn audit_with(
+    manifest_path: &Path,
+    root: &Path,
+    policy: PathPolicy,
+    opts: &AuditOptions,
+) -> Result<AuditReport> {
+    let (mf, manifest_recovery) = manifest::load(manifest_path)?;
+    mf.require_plain_hashes("audit")?;
+    let parity_dir = &mf.parity_dir_at(manifest_path);
+    let hints = if opts.fs_hints { Hints::load(parity_dir) } else { Hints::default() };
+    let geo = mf.geometry();
+    let m_max = mf.max_parity_shards();
+    let fds = crate::fdlimit::FdLimit::new(opts.max_open_files);
+    let per_file: Vec<FileAudit> = mf
+        .files
+        .par_iter()
+        .map(|fe| -> Result<FileAudit> {
+            let path = validate_path(root, Path::new(&fe.rel_path), policy)
+                .with_context(|| format!("validate path {:?}", fe.rel_path))?;
+            let Ok((mut f, _permit)) = fds.open(&path) else {
+                return Ok((fe.chunks.iter().map(|c| c.idx).collect(), false, None));
+            };
+            let stamp = if opts.fs_hints
+                && (opts.assume_checksummed || fshint::checksummed_by(&f, &path).is_some())
+            {
+                fshint::stamp(&f)
+            } else {
+                None
+            };
+            if stamp.is_some() && hints.files.get(&fe.rel_path) == stamp.as_ref() {
+                return Ok((Vec::new(), true, stamp));
+            }
+            let mut bad = Vec::new();
+            for ch in &fe.chunks {
+                let mut buf = vec![0u8; ch.len as usize];
+                let ok = f.seek(SeekFrom::Start(ch.file_offset)).is_ok()
+                    && f.read_exact(&mut buf).is_ok()
+                    && mf.hasher().matches(&buf, &ch.hash_hex);
+                if !ok {
+                    bad.push(ch.idx);
+                }
+            }
+            Ok((bad, false, stamp))
+        })
+        .collect::<Result<_>>()?;
+
+    // A slot is lost once every location of it is bad; deduplicated slots
+    // (`create --dedup`) have more than one
+    let mut locations: HashMap<u64, usize> = HashMap::new();
+    for ch in mf.files.iter().flat_map(|fe| &fe.chunks) {
+        *locations.entry(ch.idx).or_default() += 1;
+    }
+    let mut bad_locations: BTreeMap<u64, usize> = BTreeMap::new();
+    let mut files_trusted = 0u64;
+    let mut next_hints = Hints::default();
+    for (fe, (bad, trusted, stamp)) in mf.files.iter().zip(per_file) {
+        files_trusted += trusted as u64;
+        if let (true, Some(stamp)) = (bad.is_empty(), stamp) {
+            next_hints.files.insert(fe.rel_path.clone(), stamp);
+        }
+        for idx in bad {
+            *bad_locations.entry(idx).or_default() += 1;
+        }
+    }
+    let mut by_stripe: BTreeMap<u64, Vec<u64>> = BTreeMap::new();
+    for (idx, n) in bad_locations {
+        if n >= locations[&idx] {
+            by_stripe.entry(geo.stripe_of(idx)).or_default().push(idx);
+        }
+    }
+    if opts.fs_hints {
+        next_hints.save(parity_dir)?;
+    }
+    let chunks_bad = by_stripe.values().map(Vec::len).sum::<usize>() as u64;
+    let wanted: HashSet<u64> = by_stripe.keys().copied().collect();
+    let local: Vec<VolumeSource> = std::iter::once(parity_dir.to_path_buf())
+        .chain(mf.volume_dirs())
+        .map(|d| VolumeSource::dir(&d, ReadCost::LOCAL))
+        .collect::<Result<_>>()?;
+    let parity = collect_parity_copies(
+        &local.iter().collect::<Vec<_>>(),
+        mf.chunk_size,
+        Some(&wanted),
+        ShardKind::Inner,
+        mf.set_tag(),
+    )?;
+
+    let damaged: Vec<StripeDamage> = by_stripe
+        .into_iter()
+        .map(|(stripe, mut bad_chunks)| {
+            bad_chunks.sort_unstable();
+            let parity_available = parity
+                .variant(stripe, 0)
+                .map(|v| v.iter().filter(|(pi, _, _)| *pi < m_max).count())
+                .unwrap_or(0);
+            let repairable = bad_chunks.len() <= parity_available;
+            StripeDamage { stripe, bad_chunks, parity_available, repairable }
+        })
+        .collect();
+    Ok(AuditReport {
+        stripes: geo.stripes,
+        chunks_ok: mf.total_chunks - chunks_bad,
+        chunks_bad,
+        repairable: damaged.iter().all(|d| d.repairable),
+        damaged,
+        files_trusted,
+        unreadable_volumes: parity.unreadable_volumes,
+        manifest_recovery,
+    })
+}
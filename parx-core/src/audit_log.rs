@@ -0,0 +1,177 @@
+//! Tamper-evident audit trail of repair actions (`<parity dir>/audit.log`).
+//!
+//! One JSON entry per line. Each entry's hash covers its sequence number,
+//! timestamp, event and the previous entry's hash, so editing or removing an
+//! entry breaks the chain for everything after it. Entries may additionally be
+//! signed (ed25519 over the entry hash). The chain alone cannot detect that
+//! trailing entries were cut off; pin the last hash elsewhere if that matters.
+
+use crate::sign::{self, SignatureBlock};
+use anyhow::{Context, Result};
+use ed25519_dalek::{SigningKey, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+
+pub const AUDIT_LOG: &str = "audit.log";
+const GENESIS_HEX: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// One repair action: what was rewritten and which shards it was rebuilt from.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct AuditEvent {
+    /// "repair" (source chunk rewritten) or "heal" (parity shard regenerated)
+    pub action: String,
+    /// Data file (manifest rel path) or volume file name that was written
+    pub target: String,
+    pub offset: u64,
+    pub len: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk: Option<u64>,
+    pub stripe: u64,
+    /// Shards used for reconstruction, e.g. `data[2]`, `parity[1]@vol-001.parxv`
+    pub sources: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AuditEntry {
+    pub seq: u64,
+    pub ts_utc: String,
+    pub event: AuditEvent,
+    pub prev_hex: String,
+    pub hash_hex: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<SignatureBlock>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AuditVerifyReport {
+    pub entries: u64,
+    pub signed: u64,
+    /// Fingerprints of the keys that signed entries
+    pub signers: Vec<String>,
+    /// Sequence number (or line number when unparsable) of the first broken entry
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_bad: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl AuditVerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.first_bad.is_none()
+    }
+}
+
+fn entry_hash(seq: u64, ts_utc: &str, event: &AuditEvent, prev_hex: &str) -> Result<String> {
+    let body = serde_json::to_vec(&(seq, ts_utc, event, prev_hex))?;
+    Ok(blake3::hash(&body).to_hex().to_string())
+}
+
+/// Append events to the log in `parity_dir`, continuing the existing chain.
+pub fn append(parity_dir: &Path, events: &[AuditEvent], key: Option<&SigningKey>) -> Result<()> {
+    if events.is_empty() {
+        return Ok(());
+    }
+    let path = parity_dir.join(AUDIT_LOG);
+    let (mut seq, mut prev) = match std::fs::read_to_string(&path) {
+        Ok(s) => match s.lines().rev().find(|l| !l.trim().is_empty()) {
+            Some(last) => {
+                let e: AuditEntry = serde_json::from_str(last)
+                    .with_context(|| format!("parse last entry of {:?}", path))?;
+                (e.seq + 1, e.hash_hex)
+            }
+            None => (0, GENESIS_HEX.to_string()),
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => (0, GENESIS_HEX.to_string()),
+        Err(e) => return Err(e).with_context(|| format!("read {:?}", path)),
+    };
+    let mut out = String::new();
+    for ev in events {
+        let ts_utc = chrono::Utc::now().to_rfc3339();
+        let hash_hex = entry_hash(seq, &ts_utc, ev, &prev)?;
+        let signature = key.map(|k| sign::sign_bytes(k, hash_hex.as_bytes()));
+        let entry = AuditEntry {
+            seq,
+            ts_utc,
+            event: ev.clone(),
+            prev_hex: prev,
+            hash_hex: hash_hex.clone(),
+            signature,
+        };
+        out.push_str(&serde_json::to_string(&entry)?);
+        out.push('\n');
+        seq += 1;
+        prev = hash_hex;
+    }
+    let mut f = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("open {:?}", path))?;
+    f.write_all(out.as_bytes())?;
+    f.sync_all()?;
+    Ok(())
+}
+
+/// Walk the chain and check every hash and signature. With `expected`, every
+/// entry must be signed by that key.
+pub fn verify(log_path: &Path, expected: Option<&VerifyingKey>) -> Result<AuditVerifyReport> {
+    let text = std::fs::read_to_string(log_path).with_context(|| format!("read {:?}", log_path))?;
+    let mut rep = AuditVerifyReport::default();
+    let mut prev = GENESIS_HEX.to_string();
+    for (line_no, line) in text.lines().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+        let e: AuditEntry = match serde_json::from_str(line) {
+            Ok(e) => e,
+            Err(err) => {
+                rep.first_bad = Some(line_no as u64);
+                rep.error = Some(format!("line {}: {}", line_no + 1, err));
+                break;
+            }
+        };
+        match check_entry(&e, rep.entries, &prev, expected)? {
+            Ok(signer) => {
+                if let Some(vk) = signer {
+                    rep.signed += 1;
+                    let fp = sign::fingerprint(&vk);
+                    if !rep.signers.contains(&fp) {
+                        rep.signers.push(fp);
+                    }
+                }
+            }
+            Err(msg) => {
+                rep.first_bad = Some(e.seq);
+                rep.error = Some(format!("entry {}: {}", e.seq, msg));
+                break;
+            }
+        }
+        rep.entries += 1;
+        prev = e.hash_hex;
+    }
+    Ok(rep)
+}
+
+/// Inner `Err` describes why the entry breaks the chain; `Ok(Some(_))` is the signer.
+fn check_entry(
+    e: &AuditEntry,
+    want_seq: u64,
+    prev: &str,
+    expected: Option<&VerifyingKey>,
+) -> Result<std::result::Result<Option<VerifyingKey>, String>> {
+    if e.seq != want_seq {
+        return Ok(Err(format!("expected sequence {}", want_seq)));
+    }
+    if e.prev_hex != prev {
+        return Ok(Err("chain broken (prev hash mismatch)".to_string()));
+    }
+    if entry_hash(e.seq, &e.ts_utc, &e.event, &e.prev_hex)? != e.hash_hex {
+        return Ok(Err("content does not match its hash".to_string()));
+    }
+    Ok(match &e.signature {
+        Some(sig) => sign::verify_bytes(sig, e.hash_hex.as_bytes(), expected)
+            .map(Some)
+            .map_err(|err| err.to_string()),
+        None if expected.is_some() => Err("not signed".to_string()),
+        None => Ok(None),
+    })
+}
@@ -0,0 +1,86 @@
+//! Where inner RS parity is computed during create. [`CpuBackend`] encodes
+//! stripes on the rayon pool; with the `cuda` feature,
+//! [`CudaBackend`](crate::cuda_backend::cuda::CudaBackend) encodes whole
+//! batches of stripes on the GPU (`create --gpu`).
+
+use crate::rs_codec::RsCodec;
+use anyhow::Result;
+use reed_solomon_erasure::galois_8;
+
+/// Computes inner RS parity for batches of stripes.
+pub trait ComputeBackend {
+    /// Short name for logs and reports.
+    fn name(&self) -> &'static str;
+
+    /// Parity of every stripe in `stripes`, each given as its `rs.k` data
+    /// shards of `len` bytes. Returns `rs.m` parity shards per stripe, in
+    /// the order of `stripes`.
+    fn encode_batch(
+        &self,
+        rs: &RsCodec,
+        stripes: &[Vec<&[u8]>],
+        len: usize,
+    ) -> Result<Vec<Vec<Vec<u8>>>>;
+}
+
+/// Encodes each stripe with the CPU codec, stripes in parallel.
+pub struct CpuBackend;
+
+impl ComputeBackend for CpuBackend {
+    fn name(&self) -> &'static str {
+        "cpu"
+    }
+
+    fn encode_batch(
+        &self,
+        rs: &RsCodec,
+        stripes: &[Vec<&[u8]>],
+        len: usize,
+    ) -> Result<Vec<Vec<Vec<u8>>>> {
+        use rayon::prelude::*;
+        stripes
+            .par_iter()
+            .map(|data| {
+                let mut parity: Vec<Vec<u8>> = (0..rs.m).map(|_| vec![0u8; len]).collect();
+                let mut out: Vec<&mut [u8]> = parity.iter_mut().map(|b| b.as_mut_slice()).collect();
+                rs.encode_sep(data, &mut out)?;
+                Ok(parity)
+            })
+            .collect()
+    }
+}
+
+/// GF(2^8) log/antilog tables of the field the CPU codec works in, for
+/// kernels that multiply by lookup. `exp` is stored twice over so that
+/// `exp[log[a] + log[b]]` needs no reduction modulo 255.
+pub struct Gf8Tables {
+    pub log: [u8; 256],
+    pub exp: [u8; 512],
+}
+
+impl Gf8Tables {
+    pub fn new() -> Self {
+        let mut t = Self { log: [0; 256], exp: [0; 512] };
+        let mut x = 1u8;
+        for i in 0..255 {
+            t.exp[i] = x;
+            t.exp[i + 255] = x;
+            t.log[x as usize] = i as u8;
+            x = galois_8::mul(x, 2);
+        }
+        t
+    }
+
+    pub fn mul(&self, a: u8, b: u8) -> u8 {
+        if a == 0 || b == 0 {
+            return 0;
+        }
+        self.exp[self.log[a as usize] as usize + self.log[b as usize] as usize]
+    }
+}
+
+impl Default for Gf8Tables {
+    fn default() -> Self {
+        Self::new()
+    }
+}
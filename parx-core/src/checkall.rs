@@ -0,0 +1,263 @@
+//! Every health check of a set in one run (`parx check-all`): `verify` of
+//! the source tree, `quickcheck` and a deep `paritycheck` of the volumes,
+//! and a check that the manifest and the volumes agree on the set's
+//! geometry. The reports stay nested as they are; [`Grade`] sums them up
+//! for dashboards and cron jobs that only want to know whether to act.
+
+use crate::manifest::{self, Manifest};
+use crate::parity_audit::{self, ParitycheckReport, QuickcheckReport};
+use crate::paritycache::ParityCache;
+use crate::verify::{verify_with_options, VerifyOptions, VerifyReport};
+use crate::volume::{ShardKind, VolumeHeader};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+/// Overall health of a set, worst of its checks.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Grade {
+    /// Data and parity intact, every stripe fully protected
+    Green,
+    /// Damage or lost parity that `repair` or `vol heal` can deal with
+    Amber,
+    /// Data at risk: stripes without parity, damage on both sides, or a set
+    /// whose manifest and volumes disagree
+    Red,
+}
+
+/// Whether the manifest and the volumes describe the same layout.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct GeometryReport {
+    pub stripes: u64,
+    /// Volumes of the set whose header and index were compared
+    pub volumes_checked: usize,
+    pub problems: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CheckAllReport {
+    pub grade: Grade,
+    /// Why the grade is not GREEN, worst first
+    pub reasons: Vec<String>,
+    pub verify: VerifyReport,
+    pub quickcheck: QuickcheckReport,
+    pub paritycheck: ParitycheckReport,
+    pub geometry: GeometryReport,
+}
+
+impl crate::report::Report for CheckAllReport {
+    const KIND: &'static str = "check-all";
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CheckAllOptions {
+    pub verify: VerifyOptions,
+    /// Hash every shard, even those `paritycheck.cache` vouches for
+    pub no_cache: bool,
+}
+
+/// Run every check of the set of `manifest_path` over the tree at `root`.
+pub fn check_all(
+    manifest_path: &Path,
+    root: &Path,
+    opts: &CheckAllOptions,
+) -> Result<CheckAllReport> {
+    let verify = verify_with_options(manifest_path, root, &opts.verify)?;
+    let (mf, _) = manifest::load(manifest_path)?;
+    let parity_dir = mf.parity_dir_at(manifest_path);
+    let vols = set_volumes(&mf, &parity_dir)?;
+    let quickcheck = parity_audit::quickcheck(&vols, mf.set_tag());
+    let cache = if opts.no_cache { ParityCache::default() } else { ParityCache::load(&parity_dir) };
+    let (mut paritycheck, next) = parity_audit::paritycheck(&vols, &cache, true);
+    paritycheck.domains = crate::domains::of_manifest(&mf)?;
+    // A cache that cannot be written only costs the next run time
+    let _ = next.save(&parity_dir);
+    let geometry = geometry(&mf, &parity_dir);
+    let (grade, reasons) = grade(&verify, &quickcheck, &paritycheck, &geometry);
+    Ok(CheckAllReport { grade, reasons, verify, quickcheck, paritycheck, geometry })
+}
+
+/// Volumes in the parity dir and the set's other volume dirs, whichever set
+/// they belong to.
+fn set_volumes(mf: &Manifest, parity_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut vols = Vec::new();
+    for dir in std::iter::once(parity_dir.to_path_buf()).chain(mf.volume_dirs()) {
+        if !dir.is_dir() {
+            continue;
+        }
+        for ent in std::fs::read_dir(&dir).with_context(|| format!("read_dir {:?}", dir))? {
+            let p = ent?.path();
+            if p.extension().is_some_and(|e| e == "parxv") {
+                vols.push(p);
+            }
+        }
+    }
+    vols.sort();
+    Ok(vols)
+}
+
+/// Compare the chunk layout of the manifest with its own geometry, and the
+/// header and index of each of the set's volumes with the manifest.
+/// Volumes that are missing or do not parse are left to the other checks.
+pub fn geometry(mf: &Manifest, parity_dir: &Path) -> GeometryReport {
+    let geo = mf.geometry();
+    let mut rep = GeometryReport { stripes: geo.stripes, ..Default::default() };
+    let problems = &mut rep.problems;
+    if mf.chunk_size == 0 || mf.stripe_k == 0 {
+        problems.push(format!(
+            "manifest has chunk size {} and {} data shards per stripe",
+            mf.chunk_size, mf.stripe_k
+        ));
+        return rep;
+    }
+    let mut slots: HashMap<u64, (&str, &str)> = HashMap::new();
+    for fe in &mf.files {
+        let mut end = Some(0u64);
+        for ch in &fe.chunks {
+            if end.is_some() && (end != Some(ch.file_offset) || ch.len as usize > mf.chunk_size) {
+                problems.push(format!(
+                    "{}: chunk at byte {} (len {}) does not follow on from the one before",
+                    fe.rel_path, ch.file_offset, ch.len
+                ));
+                end = None;
+            }
+            end = end.map(|e| e + ch.len as u64);
+            if ch.idx >= mf.total_chunks {
+                problems.push(format!(
+                    "{}: chunk slot {} lies past the set's {} slots",
+                    fe.rel_path, ch.idx, mf.total_chunks
+                ));
+            }
+            // Deduplicated chunks share a slot, but only with their equals
+            match slots.insert(ch.idx, (&fe.rel_path, &ch.hash_hex)) {
+                Some((other, hash)) if hash != ch.hash_hex => problems.push(format!(
+                    "slot {} holds different chunks of {} and {}",
+                    ch.idx, other, fe.rel_path
+                )),
+                _ => {}
+            }
+        }
+        if let Some(end) = end.filter(|&e| e != fe.size) {
+            problems
+                .push(format!("{}: chunks cover {} of its {} bytes", fe.rel_path, end, fe.size));
+        }
+    }
+
+    let max_parity = mf.max_parity_shards();
+    for vid in 0..mf.volumes {
+        let path = mf.volume_path(parity_dir, vid);
+        let Ok(mut f) = File::open(&path) else { continue };
+        let name = mf.volume_name(vid);
+        let Ok(hdr) = VolumeHeader::read_from(&f) else { continue };
+        if mf.set_tag().stray(&manifest::SetTag::from_ext(&hdr.ext)).is_some() {
+            // Reported by quickcheck
+            continue;
+        }
+        rep.volumes_checked += 1;
+        if hdr.k as usize != mf.stripe_k {
+            rep.problems.push(format!(
+                "{}: header says {} data shards per stripe, manifest {}",
+                name, hdr.k, mf.stripe_k
+            ));
+        }
+        let chunk_size = hdr.ext.get_u32(crate::ext::key::CHUNK_SIZE);
+        if let Some(cs) = chunk_size.filter(|&cs| cs as usize != mf.chunk_size) {
+            rep.problems.push(format!(
+                "{}: header says chunk size {}, manifest {}",
+                name, cs, mf.chunk_size
+            ));
+        }
+        let Ok(entries) = crate::index::read_trailer(&mut f).and_then(|(off, len, crc)| {
+            crate::index::read_index(&mut f, off, len, crc, &crate::index::IndexLimits::default())
+        }) else {
+            continue;
+        };
+        let stray = entries
+            .iter()
+            .filter(|e| match e.kind {
+                ShardKind::Inner => e.stripe >= geo.stripes || e.parity_idx as usize >= max_parity,
+                ShardKind::Replica => e.stripe >= geo.stripes || e.parity_idx as usize >= geo.k,
+                _ => false,
+            })
+            .count();
+        if stray > 0 {
+            rep.problems.push(format!(
+                "{}: {} shard(s) indexed for stripes or parity slots the set does not have",
+                name, stray
+            ));
+        }
+    }
+    rep
+}
+
+fn grade(
+    verify: &VerifyReport,
+    quick: &QuickcheckReport,
+    parity: &ParitycheckReport,
+    geometry: &GeometryReport,
+) -> (Grade, Vec<String>) {
+    let (mut red, mut amber) = (Vec::new(), Vec::new());
+    for p in &geometry.problems {
+        red.push(format!("geometry: {}", p));
+    }
+    if quick.foreign > 0 {
+        red.push(format!("{} volume(s) of another set in the parity dirs", quick.foreign));
+    }
+    if quick.stale > 0 {
+        red.push(format!(
+            "{} volume(s) of another generation of the set in the parity dirs",
+            quick.stale
+        ));
+    }
+    let data_damaged = verify.chunks_bad > 0 || !verify.merkle_ok;
+    let short = verify.parity_shortfall.as_ref();
+    if let Some(s) = short.filter(|s| s.stripes_unprotected > 0) {
+        red.push(format!("{} stripe(s) have no parity left", s.stripes_unprotected));
+    } else if let Some(s) = short {
+        amber.push(format!(
+            "{} stripe(s) lost parity shards ({} left at least)",
+            s.stripes_degraded, s.min_parity_left
+        ));
+    }
+    if data_damaged {
+        let what =
+            format!("{} bad chunk(s) in {} file(s)", verify.chunks_bad, verify.damaged_files.len());
+        if parity.shards_bad > 0 || short.is_some() {
+            red.push(format!("{}, with parity damaged too: repair may not recover them", what));
+        } else {
+            amber.push(format!("{}; run `parx repair`", what));
+        }
+    }
+    if !verify.symlinks_bad.is_empty() {
+        amber.push(format!("{} symlink(s) missing or changed", verify.symlinks_bad.len()));
+    }
+    if verify.manifest_recovery.is_some() {
+        amber.push("manifest.json was unreadable; read its v2 companion".to_string());
+    }
+    if parity.shards_bad > 0 {
+        amber.push(format!("{} damaged parity shard(s); run `parx vol heal`", parity.shards_bad));
+    }
+    for d in parity.domains.iter().filter(|d| d.tolerates == 0) {
+        amber.push(format!(
+            "losing {} {} would take every parity shard of some stripe",
+            d.level,
+            d.worst_group.as_deref().unwrap_or("?")
+        ));
+    }
+    let unchecked = parity.volumes.iter().filter(|v| v.index != "ok").count();
+    if unchecked > 0 {
+        amber.push(format!("{} volume(s) could not be checked", unchecked));
+    }
+    let grade = if !red.is_empty() {
+        Grade::Red
+    } else if !amber.is_empty() {
+        Grade::Amber
+    } else {
+        Grade::Green
+    };
+    red.extend(amber);
+    (grade, red)
+}
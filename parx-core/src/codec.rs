@@ -0,0 +1,204 @@
+//! Compression codecs for volume metadata: the index and the manifest backup.
+//!
+//! A compressed payload names its codec itself. zstd payloads are bare zstd
+//! frames, as every index was before codecs were selectable, so older readers
+//! keep reading sets made with the default. Other codecs are framed as
+//! `PXCD codec_id(1) raw_len(8) payload`, with ids from [`id`]. The codecs a
+//! volume was created with are recorded in its header (see [`Codecs`]) so that
+//! `update`, `vol heal` and manifest refreshes rewrite it the same way.
+
+use crate::ext::{key, ExtMap};
+use anyhow::{bail, Context, Result};
+use std::fmt;
+
+/// Codec ids of framed payloads.
+pub mod id {
+    pub const NONE: u8 = 0;
+    pub const ZSTD: u8 = 1;
+    pub const LZ4: u8 = 2;
+}
+
+const FRAME_MAGIC: &[u8; 4] = b"PXCD";
+const FRAME_HEADER: usize = 4 + 1 + 8;
+const ZSTD_MAGIC: &[u8; 4] = &[0x28, 0xB5, 0x2F, 0xFD];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Codec {
+    None,
+    /// zstd at a level (0 = zstd's default, currently 3)
+    Zstd(i32),
+    /// LZ4 block format, fast to compress and decompress
+    Lz4,
+}
+
+impl Default for Codec {
+    fn default() -> Self {
+        Codec::Zstd(0)
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Codec::None => write!(f, "none"),
+            Codec::Zstd(0) => write!(f, "zstd"),
+            Codec::Zstd(level) => write!(f, "zstd:{}", level),
+            Codec::Lz4 => write!(f, "lz4"),
+        }
+    }
+}
+
+impl std::str::FromStr for Codec {
+    type Err = anyhow::Error;
+
+    /// `none`, `lz4`, `zstd` or `zstd:<level>`.
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s.trim().to_ascii_lowercase().as_str() {
+            "none" => Codec::None,
+            "lz4" => Codec::Lz4,
+            "zstd" => Codec::Zstd(0),
+            other => match other.strip_prefix("zstd:") {
+                Some(level) => {
+                    let level: i32 = level.parse().context("zstd level")?;
+                    if !zstd::compression_level_range().contains(&level) {
+                        bail!("zstd level {} is out of range", level);
+                    }
+                    Codec::Zstd(level)
+                }
+                None => bail!("unknown codec {:?} (none, lz4, zstd, zstd:<level>)", s),
+            },
+        })
+    }
+}
+
+impl Codec {
+    pub fn id(self) -> u8 {
+        match self {
+            Codec::None => id::NONE,
+            Codec::Zstd(_) => id::ZSTD,
+            Codec::Lz4 => id::LZ4,
+        }
+    }
+
+    /// Most bytes [`Codec::compress`] makes of `raw_len` bytes.
+    pub fn max_len(self, raw_len: usize) -> usize {
+        match self {
+            Codec::Zstd(_) => zstd::zstd_safe::compress_bound(raw_len),
+            Codec::None => FRAME_HEADER + raw_len,
+            // LZ4_compressBound: one literal run is the worst a block gets;
+            // lz4_flex's own bound is its scratch size, 10% over
+            Codec::Lz4 => FRAME_HEADER + raw_len + raw_len / 255 + 16,
+        }
+    }
+
+    pub fn compress(self, raw: &[u8]) -> Result<Vec<u8>> {
+        let payload = match self {
+            Codec::Zstd(level) => {
+                return zstd::stream::encode_all(raw, level).context("zstd compress");
+            }
+            Codec::None => raw.to_vec(),
+            Codec::Lz4 => lz4_flex::block::compress(raw),
+        };
+        let mut out = Vec::with_capacity(FRAME_HEADER + payload.len());
+        out.extend_from_slice(FRAME_MAGIC);
+        out.push(self.id());
+        out.extend_from_slice(&(raw.len() as u64).to_le_bytes());
+        out.extend_from_slice(&payload);
+        Ok(out)
+    }
+}
+
+/// Whether `buf` starts like a payload of [`Codec::compress`].
+pub fn is_compressed(buf: &[u8]) -> bool {
+    buf.starts_with(FRAME_MAGIC) || buf.starts_with(ZSTD_MAGIC)
+}
+
+/// Codec a payload names, as [`Codec`] prints it (without the zstd level,
+/// which is not recorded); `None` for bytes no codec wrote.
+pub fn payload_codec(buf: &[u8]) -> Option<String> {
+    if buf.starts_with(ZSTD_MAGIC) {
+        return Some("zstd".into());
+    }
+    match buf.strip_prefix(FRAME_MAGIC)?.first()? {
+        &id::NONE => Some("none".into()),
+        &id::LZ4 => Some("lz4".into()),
+        other => Some(format!("unknown codec id {}", other)),
+    }
+}
+
+/// Decompress a payload of any codec, refusing output over `limit` bytes.
+pub fn decompress(buf: &[u8], limit: usize) -> Result<Vec<u8>> {
+    if !buf.starts_with(FRAME_MAGIC) {
+        let mut out = Vec::new();
+        let dec = zstd::stream::read::Decoder::new(buf).context("zstd decompress")?;
+        std::io::Read::read_to_end(&mut std::io::Read::take(dec, limit as u64 + 1), &mut out)
+            .context("zstd decompress")?;
+        if out.len() > limit {
+            bail!("decompressed size exceeds {} bytes", limit);
+        }
+        return Ok(out);
+    }
+    if buf.len() < FRAME_HEADER {
+        bail!("truncated codec frame");
+    }
+    let raw_len = u64::from_le_bytes(buf[5..13].try_into().unwrap());
+    if raw_len > limit as u64 {
+        bail!("decompressed size {} exceeds {} bytes", raw_len, limit);
+    }
+    let payload = &buf[FRAME_HEADER..];
+    let out = match buf[4] {
+        id::NONE => payload.to_vec(),
+        id::LZ4 => lz4_flex::block::decompress(payload, raw_len as usize)
+            .map_err(|e| anyhow::anyhow!("lz4 decompress: {}", e))?,
+        other => {
+            return Err(crate::volume::FeatureError(format!(
+                "payload compressed with codec id {} by a newer parx; please upgrade",
+                other
+            ))
+            .into())
+        }
+    };
+    if out.len() as u64 != raw_len {
+        bail!("codec frame holds {} bytes, expected {}", out.len(), raw_len);
+    }
+    Ok(out)
+}
+
+/// Codecs of a volume's metadata, recorded in its header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Codecs {
+    pub index: Codec,
+    /// The manifest backup; `none` stores the manifest v2 bytes as they are,
+    /// which lets a damaged backup still yield its intact sections
+    pub backup: Codec,
+}
+
+impl Default for Codecs {
+    fn default() -> Self {
+        Self { index: Codec::default(), backup: Codec::None }
+    }
+}
+
+impl Codecs {
+    /// Record the codecs that differ from the defaults.
+    pub fn to_ext(self, ext: &mut ExtMap) {
+        let d = Codecs::default();
+        if self.index != d.index {
+            ext.insert(key::INDEX_CODEC, self.index.to_string().into_bytes());
+        }
+        if self.backup != d.backup {
+            ext.insert(key::BACKUP_CODEC, self.backup.to_string().into_bytes());
+        }
+    }
+
+    /// Codecs recorded in `ext`; unknown or absent entries mean the defaults.
+    pub fn from_ext(ext: &ExtMap) -> Self {
+        let get =
+            |k| ext.get(k).and_then(|v| std::str::from_utf8(v).ok()).and_then(|s| s.parse().ok());
+        let d = Codecs::default();
+        Self {
+            index: get(key::INDEX_CODEC).unwrap_or(d.index),
+            backup: get(key::BACKUP_CODEC).unwrap_or(d.backup),
+        }
+    }
+}
@@ -1,26 +1,120 @@
 //! Optional CUDA backend (feature `cuda`). CPU fallback is default.
+//!
+//! [`cuda::CudaBackend`] encodes batches of GF(2^8) stripes on the device:
+//! the data of a batch is uploaded once, one thread computes one byte of one
+//! parity shard by table-lookup multiplication, and the parity comes back in
+//! a single copy. GF(2^16) stripes are encoded on the CPU.
 
 #[cfg(feature = "cuda")]
 pub mod cuda {
+    use crate::backend::{ComputeBackend, CpuBackend, Gf8Tables};
+    use crate::rs_codec::{RsCodec, RsField};
     use anyhow::Result;
     use rustacuda::prelude::*;
     use std::ffi::CString;
 
-    // Minimal PTX stub; swap for a real RS kernel later.
+    // parity[row][x] = XOR over j of coef[p][j] * data[s][j][x], where
+    // row = s * m + p; one thread per (x, row).
     const PTX: &str = r#"
 .version 6.0
 .target sm_50
 .address_size 64
 
-.visible .entry noop_kernel(
-    .param .u64 pdata,
-    .param .u32 len
+.visible .entry rs_encode(
+    .param .u64 p_data,
+    .param .u64 p_parity,
+    .param .u64 p_coef,
+    .param .u64 p_log,
+    .param .u64 p_exp,
+    .param .u32 p_k,
+    .param .u32 p_m,
+    .param .u32 p_len
 )
 {
+    .reg .pred %p<4>;
+    .reg .b32 %r<20>;
+    .reg .b64 %rd<16>;
+
+    ld.param.u64 %rd1, [p_data];
+    ld.param.u64 %rd2, [p_parity];
+    ld.param.u64 %rd3, [p_coef];
+    ld.param.u64 %rd4, [p_log];
+    ld.param.u64 %rd5, [p_exp];
+    ld.param.u32 %r1, [p_k];
+    ld.param.u32 %r2, [p_m];
+    ld.param.u32 %r3, [p_len];
+    cvta.to.global.u64 %rd1, %rd1;
+    cvta.to.global.u64 %rd2, %rd2;
+    cvta.to.global.u64 %rd3, %rd3;
+    cvta.to.global.u64 %rd4, %rd4;
+    cvta.to.global.u64 %rd5, %rd5;
+
+    // x = byte offset within the shard
+    mov.u32 %r4, %ctaid.x;
+    mov.u32 %r5, %ntid.x;
+    mov.u32 %r6, %tid.x;
+    mad.lo.u32 %r7, %r4, %r5, %r6;
+    setp.ge.u32 %p1, %r7, %r3;
+    @%p1 bra DONE;
+
+    // row = s * m + p
+    mov.u32 %r8, %ctaid.y;
+    div.u32 %r9, %r8, %r2;
+    mul.lo.u32 %r10, %r9, %r2;
+    sub.u32 %r10, %r8, %r10;
+
+    // %rd6 = &data[s][0][x], %rd9 = &coef[p][0]
+    mul.wide.u32 %rd6, %r9, %r1;
+    cvt.u64.u32 %rd7, %r3;
+    mul.lo.u64 %rd6, %rd6, %rd7;
+    cvt.u64.u32 %rd8, %r7;
+    add.u64 %rd6, %rd6, %rd8;
+    add.u64 %rd6, %rd1, %rd6;
+    mul.wide.u32 %rd9, %r10, %r1;
+    add.u64 %rd9, %rd3, %rd9;
+
+    mov.u32 %r11, 0;
+    mov.u32 %r12, 0;
+LOOP:
+    setp.ge.u32 %p2, %r12, %r1;
+    @%p2 bra STORE;
+    ld.global.u8 %r13, [%rd6];
+    ld.global.u8 %r14, [%rd9];
+    setp.eq.u32 %p3, %r13, 0;
+    @%p3 bra NEXT;
+    setp.eq.u32 %p3, %r14, 0;
+    @%p3 bra NEXT;
+    cvt.u64.u32 %rd10, %r13;
+    add.u64 %rd10, %rd4, %rd10;
+    ld.global.u8 %r15, [%rd10];
+    cvt.u64.u32 %rd11, %r14;
+    add.u64 %rd11, %rd4, %rd11;
+    ld.global.u8 %r16, [%rd11];
+    add.u32 %r17, %r15, %r16;
+    cvt.u64.u32 %rd12, %r17;
+    add.u64 %rd12, %rd5, %rd12;
+    ld.global.u8 %r18, [%rd12];
+    xor.b32 %r11, %r11, %r18;
+NEXT:
+    add.u64 %rd6, %rd6, %rd7;
+    add.u64 %rd9, %rd9, 1;
+    add.u32 %r12, %r12, 1;
+    bra LOOP;
+STORE:
+    mul.wide.u32 %rd13, %r8, %r3;
+    add.u64 %rd13, %rd13, %rd8;
+    add.u64 %rd13, %rd2, %rd13;
+    st.global.u8 [%rd13], %r11;
+DONE:
     ret;
 }
 "#;
 
+    /// Threads per block along the shard bytes.
+    const BLOCK: u32 = 256;
+    /// Largest grid y dimension, i.e. parity shards per launch.
+    const MAX_ROWS: usize = 65535;
+
     pub struct CudaCtx {
         _context: Context, // keep context alive
         module: Module,
@@ -37,33 +131,125 @@ pub mod cuda {
             let module = Module::load_from_string(&CString::new(PTX).unwrap())?;
             Ok(Self { _context: context, module })
         }
+    }
 
-        /// Sanity-check kernel launch (no-op). Replace with real encode later.
-        pub fn encode_noop(&self) -> Result<()> {
-            let func = self.module.get_function(&CString::new("noop_kernel").unwrap())?;
+    /// GF(2^8) RS encoding on the first CUDA device. The context is bound to
+    /// the creating thread, so batches must be submitted from that thread.
+    pub struct CudaBackend {
+        ctx: CudaCtx,
+        tables: Gf8Tables,
+    }
 
-            // launch! requires the stream to be a local identifier
-            let stream = Stream::new(StreamFlags::DEFAULT, None)?;
+    /// Constant inputs of a batch's launches.
+    struct Consts {
+        coef: DeviceBuffer<u8>,
+        log: DeviceBuffer<u8>,
+        exp: DeviceBuffer<u8>,
+    }
 
+    impl CudaBackend {
+        pub fn new() -> Result<Self> {
+            Ok(Self { ctx: CudaCtx::new()?, tables: Gf8Tables::new() })
+        }
+
+        /// One launch over `stripes`; `stripes.len() * m` must fit the grid.
+        fn launch(
+            &self,
+            c: &mut Consts,
+            k: usize,
+            m: usize,
+            stripes: &[Vec<&[u8]>],
+            len: usize,
+        ) -> Result<Vec<Vec<Vec<u8>>>> {
+            let mut host = Vec::with_capacity(stripes.len() * k * len);
+            for data in stripes {
+                for d in data {
+                    host.extend_from_slice(d);
+                }
+            }
+            let mut data = DeviceBuffer::from_slice(&host)?;
+            let rows = stripes.len() * m;
+            // SAFETY: the kernel writes every byte of every parity row
+            let mut parity = unsafe { DeviceBuffer::<u8>::uninitialized(rows * len)? };
+            let func = self.ctx.module.get_function(&CString::new("rs_encode").unwrap())?;
+            // launch! requires the stream to be a local identifier
+            let stream = Stream::new(StreamFlags::NON_BLOCKING, None)?;
+            let grid = ((len as u32).div_ceil(BLOCK), rows as u32, 1);
+            // SAFETY: the buffers hold `stripes * k * len` data bytes,
+            // `rows * len` parity bytes, `m * k` coefficients and the tables
             unsafe {
-                rustacuda::launch!(func<<<1, 1, 0, stream>>>(0u64, 0u32))?;
+                rustacuda::launch!(func<<<grid, BLOCK, 0, stream>>>(
+                    data.as_device_ptr(),
+                    parity.as_device_ptr(),
+                    c.coef.as_device_ptr(),
+                    c.log.as_device_ptr(),
+                    c.exp.as_device_ptr(),
+                    k as u32,
+                    m as u32,
+                    len as u32
+                ))?;
             }
             stream.synchronize()?;
-            Ok(())
+            let mut out = vec![0u8; rows * len];
+            parity.copy_to(&mut out[..])?;
+            Ok(out.chunks(m * len).map(|s| s.chunks(len).map(<[u8]>::to_vec).collect()).collect())
+        }
+    }
+
+    impl ComputeBackend for CudaBackend {
+        fn name(&self) -> &'static str {
+            "cuda"
+        }
+
+        fn encode_batch(
+            &self,
+            rs: &RsCodec,
+            stripes: &[Vec<&[u8]>],
+            len: usize,
+        ) -> Result<Vec<Vec<Vec<u8>>>> {
+            if rs.field() != RsField::Gf8 || stripes.is_empty() || len == 0 {
+                return CpuBackend.encode_batch(rs, stripes, len);
+            }
+            let mut c = Consts {
+                coef: DeviceBuffer::from_slice(&rs.parity_matrix()?.concat())?,
+                log: DeviceBuffer::from_slice(&self.tables.log)?,
+                exp: DeviceBuffer::from_slice(&self.tables.exp)?,
+            };
+            let mut out = Vec::with_capacity(stripes.len());
+            for part in stripes.chunks((MAX_ROWS / rs.m).max(1)) {
+                out.extend(self.launch(&mut c, rs.k, rs.m, part, len)?);
+            }
+            Ok(out)
         }
     }
 }
 
 #[cfg(not(feature = "cuda"))]
 pub mod cuda {
-    use anyhow::Result;
-    pub struct CudaCtx;
-    impl CudaCtx {
+    use anyhow::{bail, Result};
+
+    /// Stand-in for builds without the `cuda` feature; never constructed.
+    pub struct CudaBackend;
+
+    impl CudaBackend {
         pub fn new() -> Result<Self> {
-            Ok(CudaCtx)
+            bail!("this build has no CUDA support (feature `cuda`)")
         }
-        pub fn encode_noop(&self) -> Result<()> {
-            Ok(())
+    }
+
+    #[cfg(feature = "full")]
+    impl crate::backend::ComputeBackend for CudaBackend {
+        fn name(&self) -> &'static str {
+            "cuda"
+        }
+
+        fn encode_batch(
+            &self,
+            rs: &crate::rs_codec::RsCodec,
+            stripes: &[Vec<&[u8]>],
+            len: usize,
+        ) -> Result<Vec<Vec<Vec<u8>>>> {
+            crate::backend::CpuBackend.encode_batch(rs, stripes, len)
         }
     }
 }
@@ -0,0 +1,222 @@
+//! Unbuffered file I/O (`create --direct-io`).
+//!
+//! A create reads every source file once and writes every volume once; going
+//! through the page cache, a multi-terabyte run evicts everything else the
+//! machine had cached (database pages, other services' files) for data that
+//! will not be read again soon. Opened with `O_DIRECT` on Linux and
+//! `FILE_FLAG_NO_BUFFERING` on Windows, reads and writes bypass the cache,
+//! at the price of buffers, offsets and lengths aligned to [`ALIGN`].
+//!
+//! [`DirectReader`] reads a file front to back in aligned blocks and hands
+//! out the bytes like any reader. [`write_at`] writes the aligned middle of a
+//! range through the unbuffered handle and the unaligned ends, a page at
+//! most each, through a buffered one. Filesystems that refuse unbuffered
+//! opens (tmpfs, some network filesystems) and other platforms get `None`
+//! from the `open_*` functions, and callers use buffered I/O as before.
+
+use std::alloc::{self, Layout};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// Alignment of buffers, offsets and lengths of unbuffered I/O; a multiple
+/// of the logical block size of common disks (512 bytes or 4 KiB).
+pub const ALIGN: usize = 4096;
+
+/// Bytes a [`DirectReader`] reads at a time.
+pub const READ_BLOCK: usize = 1 << 20;
+
+/// Zeroed heap buffer aligned to [`ALIGN`].
+pub struct AlignedBuf {
+    ptr: *mut u8,
+    len: usize,
+}
+
+// Safety: the buffer is owned memory like a `Vec<u8>`
+unsafe impl Send for AlignedBuf {}
+unsafe impl Sync for AlignedBuf {}
+
+impl AlignedBuf {
+    /// `len` zero bytes, rounded up to a multiple of [`ALIGN`].
+    pub fn new(len: usize) -> Self {
+        let len = len.max(1).div_ceil(ALIGN) * ALIGN;
+        let layout = Layout::from_size_align(len, ALIGN).expect("aligned buffer size");
+        // Safety: the layout has a nonzero size
+        let ptr = unsafe { alloc::alloc_zeroed(layout) };
+        if ptr.is_null() {
+            alloc::handle_alloc_error(layout);
+        }
+        Self { ptr, len }
+    }
+}
+
+impl std::ops::Deref for AlignedBuf {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        // Safety: `ptr` points at `len` initialized bytes owned by `self`
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl std::ops::DerefMut for AlignedBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        // Safety: as for `deref`, and `&mut self` is unique
+        unsafe { std::slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+}
+
+impl Drop for AlignedBuf {
+    fn drop(&mut self) {
+        let layout = Layout::from_size_align(self.len, ALIGN).expect("aligned buffer size");
+        // Safety: allocated in `new` with this layout
+        unsafe { alloc::dealloc(self.ptr, layout) }
+    }
+}
+
+/// Reads a file front to back, bypassing the page cache.
+pub struct DirectReader {
+    file: File,
+    buf: AlignedBuf,
+    /// Next byte of `buf` to hand out, and the end of what was read into it
+    pos: usize,
+    filled: usize,
+    /// File offset of the next block, always aligned
+    offset: u64,
+    /// Length of the file when it was opened
+    len: u64,
+    eof: bool,
+}
+
+impl DirectReader {
+    /// Open `path` for unbuffered reading; `None` where that is not
+    /// supported.
+    pub fn open(path: &Path) -> io::Result<Option<Self>> {
+        let Some(file) = sys::open(path, false)? else { return Ok(None) };
+        let len = file.metadata()?.len();
+        let buf = AlignedBuf::new(READ_BLOCK);
+        Ok(Some(Self { file, buf, pos: 0, filled: 0, offset: 0, len, eof: false }))
+    }
+
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+}
+
+impl Read for DirectReader {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.filled {
+            if self.eof || out.is_empty() {
+                return Ok(0);
+            }
+            let n = loop {
+                match read_at(&self.file, &mut self.buf, self.offset) {
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    res => break res?,
+                }
+            };
+            // Only the last block of a file may come back short: a read
+            // from an unaligned offset would fail
+            self.eof = n < self.buf.len();
+            if self.eof && self.offset + (n as u64) < self.len {
+                return Err(io::Error::other("short unbuffered read before the end of the file"));
+            }
+            self.offset += n as u64;
+            (self.pos, self.filled) = (0, n);
+        }
+        let n = out.len().min(self.filled - self.pos);
+        out[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Open the existing file `path` for unbuffered writing; `None` where that
+/// is not supported.
+pub fn open_write(path: &Path) -> io::Result<Option<File>> {
+    sys::open(path, true)
+}
+
+/// Write `data` at `off`: the whole [`ALIGN`]ed blocks within the range
+/// through `direct`, the bytes before and after them through `buffered`
+/// (both handles of the same file).
+pub fn write_at(direct: &File, buffered: &File, off: u64, data: &[u8]) -> io::Result<()> {
+    let end = off + data.len() as u64;
+    let a = ALIGN as u64;
+    let (start, stop) = (off.div_ceil(a) * a, end / a * a);
+    if start >= stop {
+        return crate::uring::write_all_at(buffered, data, off);
+    }
+    let (head, rest) = data.split_at((start - off) as usize);
+    let (middle, tail) = rest.split_at((stop - start) as usize);
+    crate::uring::write_all_at(buffered, head, off)?;
+    let mut block = AlignedBuf::new(READ_BLOCK.min(middle.len()));
+    let mut at = start;
+    for piece in middle.chunks(block.len()) {
+        block[..piece.len()].copy_from_slice(piece);
+        crate::uring::write_all_at(direct, &block[..piece.len()], at)?;
+        at += piece.len() as u64;
+    }
+    crate::uring::write_all_at(buffered, tail, stop)
+}
+
+#[cfg(unix)]
+fn read_at(f: &File, buf: &mut [u8], off: u64) -> io::Result<usize> {
+    std::os::unix::fs::FileExt::read_at(f, buf, off)
+}
+
+#[cfg(windows)]
+fn read_at(f: &File, buf: &mut [u8], off: u64) -> io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_read(f, buf, off)
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::fs::{File, OpenOptions};
+    use std::io;
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::path::Path;
+
+    pub(super) fn open(path: &Path, write: bool) -> io::Result<Option<File>> {
+        let res =
+            OpenOptions::new().read(!write).write(write).custom_flags(libc::O_DIRECT).open(path);
+        match res {
+            Ok(f) => Ok(Some(f)),
+            Err(e) if e.raw_os_error() == Some(libc::EINVAL) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use std::fs::{File, OpenOptions};
+    use std::io;
+    use std::os::windows::fs::OpenOptionsExt;
+    use std::path::Path;
+
+    /// `FILE_FLAG_NO_BUFFERING`
+    const NO_BUFFERING: u32 = 0x2000_0000;
+    /// `ERROR_INVALID_PARAMETER`
+    const INVALID_PARAMETER: i32 = 87;
+
+    pub(super) fn open(path: &Path, write: bool) -> io::Result<Option<File>> {
+        let res =
+            OpenOptions::new().read(!write).write(write).custom_flags(NO_BUFFERING).open(path);
+        match res {
+            Ok(f) => Ok(Some(f)),
+            Err(e) if e.raw_os_error() == Some(INVALID_PARAMETER) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+mod sys {
+    use std::fs::File;
+    use std::io;
+    use std::path::Path;
+
+    pub(super) fn open(_path: &Path, _write: bool) -> io::Result<Option<File>> {
+        Ok(None)
+    }
+}
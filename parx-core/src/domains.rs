@@ -0,0 +1,253 @@
+//! Failure domains of a set's volumes (`create --volume-groups`).
+//!
+//! Volumes kept on the same disk, host or site are lost together. A level
+//! such as `host=a:0-3,b:4-7` names the groups of one kind of domain and
+//! the volume ids in each; a set may have several levels (disk, host,
+//! site), each covering every volume once. The levels are recorded in the
+//! manifest (ext key `VOLUME_GROUPS`).
+//!
+//! The source files are not in the volumes, so losing a group loses parity,
+//! not data: a stripe stays repairable as long as it keeps some parity
+//! shards. [`tolerance`] reports, per level, how many groups may be lost at
+//! once with every stripe keeping at least one, and how many a stripe keeps
+//! at worst after losing one group. `create` refuses placements where a
+//! single group holds every parity shard of some stripe.
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+/// One kind of failure domain and its groups of volumes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Level {
+    pub name: String,
+    pub groups: Vec<Group>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Group {
+    pub name: String,
+    /// Volume ids, ascending
+    pub volumes: Vec<usize>,
+}
+
+/// How one level of failure domains holds up.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct DomainTolerance {
+    pub level: String,
+    pub groups: usize,
+    /// Most groups that may be lost at once with every stripe keeping at
+    /// least one parity shard
+    pub tolerates: usize,
+    /// Fewest parity shards a stripe keeps after losing its worst group (0
+    /// with no stripes)
+    pub parity_left_after_one: usize,
+    /// The group whose loss leaves that few
+    pub worst_group: Option<String>,
+}
+
+fn valid_name(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+impl std::str::FromStr for Level {
+    type Err = anyhow::Error;
+
+    /// `LEVEL=GROUP:IDS,GROUP:IDS,...`, IDS being volume ids and ranges
+    /// joined by `+`, e.g. `host=a:0-3,b:4-7+9`.
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((name, rest)) = s.split_once('=') else {
+            bail!("volume groups {:?} are not LEVEL=GROUP:IDS,...", s);
+        };
+        if !valid_name(name) {
+            bail!("volume groups {:?}: level name {:?} is not letters, digits, -, _ or .", s, name);
+        }
+        let mut groups = Vec::new();
+        for g in rest.split(',') {
+            let Some((gname, ids)) = g.split_once(':') else {
+                bail!("volume groups {:?}: {:?} is not GROUP:IDS", s, g);
+            };
+            if !valid_name(gname) {
+                bail!(
+                    "volume groups {:?}: group name {:?} is not letters, digits, -, _ or .",
+                    s,
+                    gname
+                );
+            }
+            let mut volumes = BTreeSet::new();
+            for part in ids.split('+') {
+                let parse = |n: &str| {
+                    n.trim().parse::<usize>().with_context(|| {
+                        format!("volume groups {:?}: {:?} is not a volume id", s, n)
+                    })
+                };
+                let (lo, hi) = match part.split_once('-') {
+                    Some((lo, hi)) => (parse(lo)?, parse(hi)?),
+                    None => (parse(part)?, parse(part)?),
+                };
+                if lo > hi {
+                    bail!("volume groups {:?}: range {:?} runs backwards", s, part);
+                }
+                volumes.extend(lo..=hi);
+            }
+            groups.push(Group { name: gname.to_string(), volumes: volumes.into_iter().collect() });
+        }
+        Ok(Level { name: name.to_string(), groups })
+    }
+}
+
+impl std::fmt::Display for Level {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}=", self.name)?;
+        for (i, g) in self.groups.iter().enumerate() {
+            let ids: Vec<String> = g.volumes.iter().map(|v| v.to_string()).collect();
+            write!(f, "{}{}:{}", if i > 0 { "," } else { "" }, g.name, ids.join("+"))?;
+        }
+        Ok(())
+    }
+}
+
+/// Check that `levels` have distinct names and that each puts every one of
+/// `volumes` volumes into exactly one group with a name of its own.
+pub fn check(levels: &[Level], volumes: usize) -> Result<()> {
+    let mut names = BTreeSet::new();
+    for level in levels {
+        if !names.insert(&level.name) {
+            bail!("--volume-groups: level {:?} is given twice", level.name);
+        }
+        let mut group_names = BTreeSet::new();
+        let mut owner: Vec<Option<&str>> = vec![None; volumes];
+        for g in &level.groups {
+            if !group_names.insert(&g.name) {
+                bail!("--volume-groups: {} {:?} is given twice", level.name, g.name);
+            }
+            for &v in &g.volumes {
+                let Some(slot) = owner.get_mut(v) else {
+                    bail!(
+                        "--volume-groups: {} {:?} lists volume {}, but the set has {} volume(s)",
+                        level.name,
+                        g.name,
+                        v,
+                        volumes
+                    );
+                };
+                if let Some(other) = slot.replace(&g.name) {
+                    bail!(
+                        "--volume-groups: volume {} is in {} {:?} and {:?}",
+                        v,
+                        level.name,
+                        other,
+                        g.name
+                    );
+                }
+            }
+        }
+        if let Some(v) = owner.iter().position(Option::is_none) {
+            bail!("--volume-groups: volume {} is in no {} group", v, level.name);
+        }
+    }
+    Ok(())
+}
+
+/// Levels recorded in a manifest's ext value (one per line).
+pub fn from_ext(ext: &crate::ext::ExtMap) -> Result<Vec<Level>> {
+    let Some(v) = ext.get(crate::ext::key::VOLUME_GROUPS) else { return Ok(Vec::new()) };
+    let text = std::str::from_utf8(v).context("volume groups are not UTF-8")?;
+    text.lines().filter(|l| !l.is_empty()).map(str::parse).collect()
+}
+
+/// Ext value recording `levels`.
+pub fn to_ext(levels: &[Level], ext: &mut crate::ext::ExtMap) {
+    let text: String = levels.iter().map(|l| format!("{}\n", l)).collect();
+    ext.insert(crate::ext::key::VOLUME_GROUPS, text.into_bytes());
+}
+
+/// Tolerance of each of `levels` for `stripes` stripes of `shards_of(s)`
+/// parity shards, `copies` copies each, copy `c` of shard `pi` of stripe
+/// `s` on volume `place(s, pi, c)`. A shard is lost with a group only when
+/// every copy of it is.
+pub fn tolerance(
+    levels: &[Level],
+    stripes: u64,
+    shards_of: &dyn Fn(u64) -> usize,
+    copies: usize,
+    place: &dyn Fn(u64, usize, usize) -> usize,
+) -> Vec<DomainTolerance> {
+    let mut out = Vec::with_capacity(levels.len());
+    for level in levels {
+        let mut group_of = Vec::new();
+        for (gi, g) in level.groups.iter().enumerate() {
+            for &v in &g.volumes {
+                if group_of.len() <= v {
+                    group_of.resize(v + 1, None);
+                }
+                group_of[v] = Some(gi);
+            }
+        }
+        let n = level.groups.len();
+        let mut tol = DomainTolerance {
+            level: level.name.clone(),
+            groups: n,
+            tolerates: n.saturating_sub(1),
+            parity_left_after_one: usize::MAX,
+            worst_group: None,
+        };
+        for s in 0..stripes {
+            let m = shards_of(s);
+            if m == 0 {
+                continue;
+            }
+            // Groups holding a copy of any shard, and per group the shards
+            // all of whose copies it holds
+            let mut holding = BTreeSet::new();
+            let mut only_in = vec![0usize; n + 1];
+            for pi in 0..m {
+                let gs: BTreeSet<usize> = (0..copies.max(1))
+                    .map(|c| group_of.get(place(s, pi, c)).copied().flatten().unwrap_or(n))
+                    .collect();
+                if gs.len() == 1 {
+                    only_in[*gs.first().expect("one group")] += 1;
+                }
+                holding.extend(gs);
+            }
+            // All its parity goes only once every holding group is lost
+            tol.tolerates = tol.tolerates.min(holding.len() - 1);
+            let (worst, lost) = only_in.iter().enumerate().max_by_key(|(_, &l)| l).expect("groups");
+            if m - lost < tol.parity_left_after_one {
+                tol.parity_left_after_one = m - lost;
+                tol.worst_group = level.groups.get(worst).map(|g| g.name.clone());
+            }
+        }
+        if tol.parity_left_after_one == usize::MAX {
+            tol.parity_left_after_one = 0;
+        }
+        out.push(tol);
+    }
+    out
+}
+
+/// [`tolerance`] of the levels recorded in `mf`, by its placement.
+pub fn of_manifest(mf: &crate::manifest::Manifest) -> Result<Vec<DomainTolerance>> {
+    let levels = from_ext(&mf.ext)?;
+    let geo = mf.geometry();
+    let shards_of = |s| mf.parity_shards_of(s);
+    let place = |s, pi, c| mf.inner_shard_volume(s, pi, c);
+    Ok(tolerance(&levels, geo.stripes, &shards_of, mf.shard_copies.max(1), &place))
+}
+
+/// Refuse levels under which losing one group takes every parity shard of
+/// some stripe (or that have a single group).
+pub fn require_one(tols: &[DomainTolerance]) -> Result<()> {
+    if let Some(t) = tols.iter().find(|t| t.tolerates == 0) {
+        bail!(
+            "--volume-groups: losing {} {:?} would take every parity shard of some stripe; \
+             spread the volumes so that each {} holds only part of a stripe's parity \
+             (more volumes, or fewer per {})",
+            t.level,
+            t.worst_group.as_deref().unwrap_or("?"),
+            t.level,
+            t.level
+        );
+    }
+    Ok(())
+}
@@ -1,267 +1,1997 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, ensure, Context, Result};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fs::{File, OpenOptions};
 use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 
-use crate::manifest::{ChunkRef, FileEntry, Manifest};
-use crate::merkle;
-use crate::rs_codec::RsCodec;
-use crate::volume::{vol_name, VolumeEntry};
+use crate::backend::{ComputeBackend, CpuBackend};
+use crate::cuda_backend::cuda::CudaBackend;
+use crate::ext::{self, ExtMap};
+use crate::geometry::Geometry;
+use crate::index::IndexCopy;
+use crate::journal::{Journal, JournalHeader, JournalWriter, Segment, DEFAULT_SEGMENT_STRIPES};
+use crate::manifest::{ChunkRef, FileEntry, Manifest, ParityGroup, SetId, SetInfo, SymlinkEntry};
+use crate::media::MediaLayout;
+use crate::merkle::{self, ChunkHasher};
+use crate::meta::FileMeta;
+use crate::metaparity::{self, MetaParity};
+use crate::outer::{OuterLayout, OuterScope};
+use crate::path_safety::canonical_rel;
+use crate::progress::Stage;
+use crate::rs_codec::{RsCodec, RsField};
+use crate::volume::{ShardKind, VolumeEntry, VolumeHeader};
+
+/// Where parity is computed (`create --gpu`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GpuMode {
+    #[default]
+    Off,
+    /// Require a CUDA device; fail when there is none
+    On,
+    /// Use a CUDA device when one is present
+    Auto,
+}
 
 pub struct EncoderConfig {
     pub chunk_size: usize,
     pub stripe_k: usize,
     pub parity_pct: u32,
+    /// Number of parity volumes (the CLI uses one per `--volume-sizes` entry)
     pub volumes: usize,
     pub outer_group: usize,
     pub outer_parity: usize,
     pub interleave_files: bool,
+    /// Write each parity shard to this many distinct volumes (1 = no replication)
+    pub shard_copies: usize,
+    pub gpu: GpuMode,
+}
+
+/// Knobs beyond the stripe geometry.
+#[derive(Clone, Debug, Default)]
+pub struct EncodeOptions {
+    /// Skip paths matching these patterns (see [`is_excluded`]); recorded in
+    /// the manifest so later updates scan the same way
+    pub exclude: Vec<String>,
+    /// Page size of the protected files (databases); the chunk size must be a
+    /// multiple so that every chunk holds whole pages
+    pub align: Option<usize>,
+    /// Cut chunks at MP4 box / Matroska cluster boundaries (see `media`)
+    pub media_align: bool,
+    /// Label/notes/contact stored in the manifest and every volume header
+    pub info: SetInfo,
+    /// Executable to copy into the parity dir as a recovery stub
+    pub recovery_stub: Option<PathBuf>,
+    /// What outer groups cover when `outer_group`/`outer_parity` are set
+    pub outer_scope: OuterScope,
+    /// Files (same patterns as `exclude`) whose stripes get extra parity
+    pub critical: Vec<String>,
+    /// Extra inner parity shards for each stripe touching a critical file
+    pub critical_parity: usize,
+    /// Parity levels of their own for the files matching these rules; the
+    /// first matching rule wins (see [`ParityRule`])
+    pub parity_rules: Vec<ParityRule>,
+    /// Exactly this many inner parity shards per stripe (`create
+    /// --parity-shards`), in place of `EncoderConfig::parity_pct`
+    pub parity_shards: Option<usize>,
+    /// Parity budget in bytes (`create --parity-size`): each stripe gets as
+    /// many parity shards as fit once the layout is known, in place of
+    /// `EncoderConfig::parity_pct`; the manifest records the effective percentage
+    pub parity_size: Option<u64>,
+    /// Parity files over the finished volumes, so that this many whole
+    /// volumes may be lost (`create --volume-parity`, see [`crate::volparity`])
+    pub volume_parity: usize,
+    /// Protect exactly these files, in this order, instead of scanning the
+    /// root; they must lie under it
+    pub files: Option<Vec<PathBuf>>,
+    /// Stripes per create journal segment (0 = `DEFAULT_SEGMENT_STRIPES`)
+    pub segment_stripes: usize,
+    /// Continue an interrupted create from its journal (see `journal`)
+    pub resume: bool,
+    /// Fail after this many journal segments, leaving the set as a crash would
+    #[doc(hidden)]
+    pub interrupt_after_segments: Option<usize>,
+    /// Codecs of the volume indices and manifest backups
+    pub codecs: crate::codec::Codecs,
+    /// Volume writer block size, queue depth and streams per volume
+    pub write: crate::volwriter::WriteTuning,
+    /// Record paths as `<prefix>/<path under root>`, e.g. the input dir relative
+    /// to the working dir so that later commands can use `.` as their root
+    pub rel_prefix: Option<String>,
+    /// Sign the manifest with this key (see [`crate::sign::sign_manifest`])
+    pub sign_key: Option<ed25519_dalek::SigningKey>,
+    /// Give identical chunks one stripe slot (`create --dedup`); every
+    /// location keeps its own [`ChunkRef`] pointing at the shared slot
+    pub dedup: bool,
+    /// Start every top-level directory on a fresh stripe and write a
+    /// manifest for each (`create --sub-manifests`, see [`crate::submanifest`])
+    pub sub_manifests: bool,
+    /// Lay out as many volumes as it takes for none to exceed this many bytes,
+    /// headers and indices included (`create --volume-max-size`); the
+    /// volume count of [`EncoderConfig`] is then ignored
+    pub volume_max_size: Option<u64>,
+    /// Name the volumes by this template instead of `vol-NNN.parxv`
+    /// (`create --volume-name`, see [`crate::volume::check_name_template`])
+    pub volume_name: Option<String>,
+    /// Spread the volumes over these directories besides the parity dir,
+    /// round-robin (`create --output-dirs`), so that losing one device
+    /// loses only its share of them
+    pub volume_dirs: Vec<PathBuf>,
+    /// Count bytes into the stages `hash`, `encode`, `outer` and
+    /// `write/vol-NNN` of this registry (see [`crate::progress`])
+    pub progress: Option<crate::progress::Progress>,
+    /// Record keyed BLAKE3 chunk hashes instead of plain ones, and no
+    /// rolling checksums (`create --hash-key`); verify and repair then need
+    /// the key too
+    pub hash_key: Option<crate::manifest::HashKey>,
+    /// Read the source files and write the volumes around the page cache
+    /// where the filesystem allows it (`create --direct-io`, see
+    /// [`crate::direct`])
+    pub direct_io: bool,
+    /// Read the source files with sequential read-ahead and drop their
+    /// pages from the cache once hashed (`create --drop-cache`, see
+    /// [`crate::pagecache`])
+    pub drop_cache: bool,
+    /// Failure domains of the volumes (`create --volume-groups`), checked
+    /// against the placement and recorded in the manifest (see
+    /// [`crate::domains`])
+    pub volume_groups: Vec<crate::domains::Level>,
+}
+
+/// `create --parity-rule PATTERN=PCT`: files matching PATTERN (same syntax
+/// as `exclude`) get PCT percent parity instead of the set's. Their chunks
+/// fill stripes of their own, so no stripe mixes two levels.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ParityRule {
+    pub pattern: String,
+    pub parity_pct: u32,
+}
+
+impl std::str::FromStr for ParityRule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((pattern, pct)) = s.rsplit_once('=') else {
+            bail!("parity rule {:?} is not PATTERN=PCT", s);
+        };
+        let parity_pct = pct
+            .trim()
+            .trim_end_matches('%')
+            .parse()
+            .with_context(|| format!("parity rule {:?}: {:?} is not a percentage", s, pct))?;
+        if pattern.is_empty() || parity_pct == 0 {
+            bail!("parity rule {:?} needs a pattern and a parity above 0", s);
+        }
+        Ok(ParityRule { pattern: pattern.to_string(), parity_pct })
+    }
+}
+
+/// Parity shards per stripe for a budget of `budget` bytes over the stripes
+/// of `geo`. Stays within the smallest RS field that has room for parity.
+fn budget_parity(budget: u64, geo: &Geometry, cfg: &EncoderConfig) -> Result<usize> {
+    let (k, stripes) = (geo.k, geo.stripes);
+    let per_shard = geo.parity_bytes(1, cfg.shard_copies);
+    let field = RsField::for_shards(k + 1);
+    if field == RsField::Gf16 && cfg.chunk_size % 2 != 0 {
+        bail!("stripes over 256 shards need an even chunk size (got {})", cfg.chunk_size);
+    }
+    let m = (budget / per_shard.max(1)).min((field.max_shards() - k) as u64) as usize;
+    if m == 0 {
+        bail!(
+            "--parity-size {} is too small: one parity shard per stripe takes {} bytes over {} stripe(s)",
+            budget,
+            per_shard,
+            stripes
+        );
+    }
+    Ok(m)
+}
+
+/// Entries a backup repository (restic, borg) rewrites or deletes in place:
+/// lock files, caches and rebuildable indices. Used by the `backup-repo` preset.
+pub const BACKUP_REPO_EXCLUDES: &[&str] =
+    &["locks", "lock.*", "cache", "tmp", "hints.*", "index.*", "integrity.*"];
+
+/// Parity dir of every set written by [`Encoder::encode_per_dir`], inside the
+/// directory it protects (scans always skip it).
+pub const PER_DIR_SET: &str = ".parx";
+
+/// One set of a per-directory placement.
+#[derive(Clone, Debug)]
+pub struct PlacedSet {
+    /// The directory it protects; its manifest's paths are relative to it
+    pub root: PathBuf,
+    pub output: PathBuf,
+    pub files: usize,
 }
 
+/// Longest label/notes/contact text accepted (each is repeated in every volume header).
+pub const MAX_INFO_LEN: usize = 64 * 1024;
+
+/// Data and parity bytes of the stripes handed to the compute backend at once.
+const BATCH_BYTES: usize = 256 << 20;
+
 pub struct Encoder;
 
+/// What is encoded: a tree on disk or a tar stream.
+enum Input<'a> {
+    Tree(&'a Path),
+    Tar(Box<dyn Read + 'a>),
+}
+
+/// Settings checked and derived before any input is read.
+struct Setup {
+    backend: Box<dyn ComputeBackend>,
+    /// Inner parity shards per stripe
+    m: usize,
+    outer: Option<OuterLayout>,
+    field: RsField,
+}
+
+/// A file's chunks before they get their global order.
+struct TmpFile {
+    rel_path: String,
+    size: u64,
+    chunks: Vec<TmpChunk>,
+    meta: Option<FileMeta>,
+    media: Option<MediaLayout>,
+    /// Stamp of the file while it was read, if it held still (see [`crate::hashcache`])
+    stamp: Option<crate::fshint::FileStamp>,
+}
+
 impl Encoder {
     pub fn encode(root: &Path, output: &Path, cfg: &EncoderConfig) -> Result<Manifest> {
-        // 1) Discover files (regular files only, skip .parx)
-        let mut files: Vec<PathBuf> = Vec::new();
-        for ent in walkdir::WalkDir::new(root).min_depth(1) {
+        Self::encode_with(root, output, cfg, &EncodeOptions::default())
+    }
+
+    pub fn encode_with(
+        root: &Path,
+        output: &Path,
+        cfg: &EncoderConfig,
+        opts: &EncodeOptions,
+    ) -> Result<Manifest> {
+        Self::encode_input(Input::Tree(root), output, cfg, opts)
+    }
+
+    /// Encode the regular files of a tar stream (`tar c | parx create
+    /// --stdin-tar`) without them being on disk. Members are recorded under
+    /// their archive paths (behind `rel_prefix`) in the same canonical order
+    /// a scan of the extracted tree uses, so the set verifies and repairs
+    /// against that tree. Directories, links and special files are skipped;
+    /// a member repeated later in the stream replaces the earlier one.
+    pub fn encode_stream(
+        reader: impl Read,
+        output: &Path,
+        cfg: &EncoderConfig,
+        opts: &EncodeOptions,
+    ) -> Result<Manifest> {
+        if opts.files.is_some() || opts.media_align {
+            bail!("a tar stream cannot be combined with --files-from or --media-align");
+        }
+        Self::encode_input(Input::Tar(Box::new(reader)), output, cfg, opts)
+    }
+
+    /// Protect each top-level directory of `root` with its own set in
+    /// `<dir>/.parx`, and the files directly in `root` with one in
+    /// `root/.parx` (`create --placement per-dir`). Parity stays next to the
+    /// data it covers, and every set records paths relative to its own
+    /// directory, so a copy of `photos/` with its `.parx` verifies and
+    /// repairs without the rest of the tree. Exclude patterns with a `/`
+    /// apply to the directory they start with; directories without files get
+    /// no set.
+    pub fn encode_per_dir(
+        root: &Path,
+        cfg: &EncoderConfig,
+        opts: &EncodeOptions,
+    ) -> Result<Vec<PlacedSet>> {
+        if opts.files.is_some() || opts.rel_prefix.is_some() || opts.resume {
+            bail!("per-directory placement cannot be combined with --files-from or --resume");
+        }
+        if opts.parity_size.is_some() {
+            // Every set would get the whole budget
+            bail!("per-directory placement cannot be combined with --parity-size");
+        }
+        let mut dirs = Vec::new();
+        let mut loose = Vec::new();
+        for ent in std::fs::read_dir(root).with_context(|| format!("read_dir {:?}", root))? {
             let ent = ent?;
-            let p = ent.path();
-            if ent.file_type().is_dir() {
+            let name = ent.file_name();
+            if name == PER_DIR_SET || is_excluded(Path::new(&name), &opts.exclude) {
                 continue;
             }
-            if !ent.file_type().is_file() {
-                continue;
+            let ty = ent.file_type()?;
+            if ty.is_dir() {
+                dirs.push(ent.path());
+            } else if ty.is_file() {
+                loose.push(ent.path());
             }
-            if p.components().any(|c| c.as_os_str() == ".parx") {
+        }
+        dirs.sort_by_cached_key(|p| rel_sort_key(p.strip_prefix(root).unwrap_or(p)));
+        loose.sort_by_cached_key(|p| rel_sort_key(p.strip_prefix(root).unwrap_or(p)));
+
+        let mut sets = Vec::new();
+        for dir in dirs {
+            let name = dir.file_name().unwrap_or_default().to_string_lossy().to_string();
+            let sub = EncodeOptions {
+                exclude: subset_patterns(&name, &opts.exclude),
+                critical: subset_patterns(&name, &opts.critical),
+                parity_rules: opts
+                    .parity_rules
+                    .iter()
+                    .filter_map(|r| {
+                        let pattern =
+                            subset_patterns(&name, std::slice::from_ref(&r.pattern)).pop()?;
+                        Some(ParityRule { pattern, ..r.clone() })
+                    })
+                    .collect(),
+                ..opts.clone()
+            };
+            let (files, _) = scan_files(&dir, &sub.exclude)?;
+            if files.is_empty() {
                 continue;
             }
-            files.push(p.to_path_buf());
-        }
-
-        // 2) Chunk and hash (collect per-file first, assign global order later)
-        struct TmpChunk {
-            buf: Vec<u8>,
-            len: u32,
-            file_offset: u64,
-            hash_hex: String,
-        }
-        struct TmpFile {
-            rel_path: String,
-            size: u64,
-            chunks: Vec<TmpChunk>,
-        }
-
-        let mut tmp_files: Vec<TmpFile> = Vec::new();
-        let mut total_bytes: u64 = 0;
-        for path in &files {
-            // Prefer a simple prefix strip since WalkDir yields paths under `root`.
-            // This avoids macOS `/var` -> `/private/var` symlink quirks and ensures
-            // manifest relpaths never contain parent traversal segments.
-            let rel = path.strip_prefix(root).expect("walked path not under root");
-            let rel_path = rel.to_string_lossy().to_string();
-            let mut f = File::open(path).with_context(|| format!("open {:?}", path))?;
-            let size = f.metadata()?.len();
-            total_bytes += size;
-            let mut remaining = size;
-            let mut file_offset = 0u64;
-            let mut chunks = Vec::new();
-            while remaining > 0 {
-                let to_read = std::cmp::min(remaining, cfg.chunk_size as u64) as usize;
-                let mut buf = vec![0u8; cfg.chunk_size];
-                let readn = f.read(&mut buf[..to_read])?;
-                if readn == 0 {
-                    break;
-                }
-                if readn < cfg.chunk_size {
-                    for b in &mut buf[readn..] {
-                        *b = 0;
-                    }
-                }
-                let hash_hex = blake3::hash(&buf).to_hex().to_string();
-                chunks.push(TmpChunk { buf, len: readn as u32, file_offset, hash_hex });
-                remaining -= readn as u64;
-                file_offset += readn as u64;
-            }
-            tmp_files.push(TmpFile { rel_path, size, chunks });
-        }
-
-        // Assign global ordering: sequential per file or round-robin across files
-        let mut order: Vec<(usize, usize)> = Vec::new(); // (file_idx, local_chunk_idx)
-        if cfg.interleave_files {
-            let mut rr = 0usize;
-            loop {
-                let mut appended = false;
-                for (fi, tf) in tmp_files.iter().enumerate() {
-                    if rr < tf.chunks.len() {
-                        order.push((fi, rr));
-                        appended = true;
-                    }
+            let output = dir.join(PER_DIR_SET);
+            Self::encode_with(&dir, &output, cfg, &sub)?;
+            sets.push(PlacedSet { root: dir, output, files: files.len() });
+        }
+        if !loose.is_empty() {
+            let output = root.join(PER_DIR_SET);
+            let files = loose.len();
+            let top = EncodeOptions { files: Some(loose), ..opts.clone() };
+            Self::encode_with(root, &output, cfg, &top)?;
+            sets.push(PlacedSet { root: root.to_path_buf(), output, files });
+        }
+        if sets.is_empty() {
+            bail!("no files to protect under {:?}", root);
+        }
+        Ok(sets)
+    }
+
+    fn encode_input(
+        input: Input<'_>,
+        output: &Path,
+        cfg: &EncoderConfig,
+        opts: &EncodeOptions,
+    ) -> Result<Manifest> {
+        for (what, v) in [
+            ("label", &opts.info.label),
+            ("notes", &opts.info.notes),
+            ("contact", &opts.info.contact),
+        ] {
+            if v.as_ref().is_some_and(|v| v.len() > MAX_INFO_LEN) {
+                bail!("--{} is longer than {} bytes", what, MAX_INFO_LEN);
+            }
+        }
+        let backend: Box<dyn ComputeBackend> = match cfg.gpu {
+            GpuMode::Off => Box::new(CpuBackend),
+            GpuMode::On if !cfg!(feature = "cuda") => {
+                bail!("--gpu on: this build has no CUDA support (feature `cuda`)")
+            }
+            GpuMode::On => Box::new(CudaBackend::new().context("--gpu on: no usable CUDA device")?),
+            GpuMode::Auto => match CudaBackend::new() {
+                Ok(gpu) => Box::new(gpu),
+                Err(_) => Box::new(CpuBackend),
+            },
+        };
+        // Chunk and shard lengths are recorded in 32 bits
+        if cfg.chunk_size == 0 || u32::try_from(cfg.chunk_size).is_err() {
+            bail!("chunk size {} is not between 1 byte and 4 GiB", cfg.chunk_size);
+        }
+        if let Some(align) = opts.align {
+            if align == 0 || cfg.chunk_size % align != 0 {
+                bail!("chunk size {} is not a multiple of --align {}", cfg.chunk_size, align);
+            }
+        }
+        if opts.parity_shards.is_some() && opts.parity_size.is_some() {
+            bail!("--parity-shards and --parity-size cannot be combined");
+        }
+        let m = opts
+            .parity_shards
+            .unwrap_or_else(|| Geometry::parity_for_pct(cfg.stripe_k, cfg.parity_pct));
+        let outer =
+            OuterLayout::new(cfg.stripe_k, m, cfg.outer_group, cfg.outer_parity, opts.outer_scope);
+        if let Some(layout) = &outer {
+            layout.validate(opts.critical_parity)?;
+        }
+        if opts.critical.is_empty() != (opts.critical_parity == 0) {
+            bail!("--critical and --critical-parity must be given together");
+        }
+        if opts.sub_manifests
+            && (outer.is_some() || opts.critical_parity > 0 || cfg.interleave_files)
+        {
+            // Outer groups, critical stripes and interleaving all span directories
+            bail!("--sub-manifests cannot be combined with outer or critical parity or --interleave-files");
+        }
+        if opts.parity_size.is_some()
+            && (outer.is_some() || opts.critical_parity > 0 || !opts.parity_rules.is_empty())
+        {
+            // Their extra parity would come on top of the budget
+            bail!(
+                "--parity-size cannot be combined with outer or critical parity or --parity-rule"
+            );
+        }
+        if !opts.parity_rules.is_empty() {
+            if m == 0 {
+                bail!("--parity-rule needs parity (--parity > 0)");
+            }
+            if outer.is_some()
+                || opts.critical_parity > 0
+                || opts.sub_manifests
+                || cfg.interleave_files
+            {
+                // Each group must fill whole stripes, and keep them to itself
+                bail!("--parity-rule cannot be combined with outer or critical parity, --sub-manifests or --interleave-files");
+            }
+        }
+        let rule_shards =
+            opts.parity_rules.iter().map(|r| Geometry::parity_for_pct(cfg.stripe_k, r.parity_pct));
+        let m_max = rule_shards.fold(m + opts.critical_parity, usize::max);
+        // Stripes past 256 shards need GF(2^16); critical stripes and parity
+        // groups share the set's field so their shards extend the plain
+        // stripes' parity
+        let field = RsField::for_shards(cfg.stripe_k + m_max);
+        if m > 0 {
+            if cfg.stripe_k + m > field.max_shards() {
+                bail!(
+                    "stripe of {} data and {} parity shards exceeds the {} shards RS can hold",
+                    cfg.stripe_k,
+                    m,
+                    field.max_shards()
+                );
+            }
+            if field == RsField::Gf16 && cfg.chunk_size % 2 != 0 {
+                bail!("stripes over 256 shards need an even chunk size (got {})", cfg.chunk_size);
+            }
+        }
+        if opts.critical_parity > 0 {
+            if m == 0 {
+                bail!("--critical-parity needs parity (--parity > 0)");
+            }
+            if cfg.stripe_k + m + opts.critical_parity > field.max_shards() {
+                bail!("--critical-parity {} is too large for this stripe", opts.critical_parity);
+            }
+        }
+        if cfg.stripe_k + m_max > field.max_shards() {
+            bail!("a --parity-rule needs more than the {} shards RS can hold", field.max_shards());
+        }
+        opts.write.validate()?;
+        if let Some(template) = &opts.volume_name {
+            crate::volume::check_name_template(template)?;
+        }
+        if !opts.volume_dirs.is_empty() && opts.volume_parity > 0 {
+            bail!("--volume-parity is not combinable with --output-dirs");
+        }
+        let setup = Setup { backend, m, outer, field };
+        let hasher = ChunkHasher::new(cfg.chunk_size, opts.hash_key.as_ref().map(|k| k.0));
+        // 1) Discover files (regular files only, skip .parx and excluded paths)
+        let root = match input {
+            Input::Tree(root) => root,
+            Input::Tar(reader) => {
+                let tmp_files = tar_files(reader, &hasher, &opts.exclude)?;
+                return Self::encode_files(tmp_files, Vec::new(), output, cfg, opts, setup);
+            }
+        };
+        let (files, symlinks) = match &opts.files {
+            Some(list) => (listed_files(root, list, &opts.exclude)?, Vec::new()),
+            None => scan_files(root, &opts.exclude)?,
+        };
+
+        // 2) Chunk and hash
+        let tmp_files = hash_tree(root, &files, &hasher, opts)?;
+        Self::encode_files(tmp_files, symlinks, output, cfg, opts, setup)
+    }
+
+    /// Lay out the chunks of `tmp_files`, then write the parity volumes and
+    /// the manifest.
+    fn encode_files(
+        mut tmp_files: Vec<TmpFile>,
+        symlinks: Vec<SymlinkEntry>,
+        output: &Path,
+        cfg: &EncoderConfig,
+        opts: &EncodeOptions,
+        setup: Setup,
+    ) -> Result<Manifest> {
+        let Setup { backend, m, outer, field } = setup;
+        // The files of each parity rule are laid out together, after the rest
+        if !opts.parity_rules.is_empty() {
+            let rule = |tf: &TmpFile| rule_of(&opts.parity_rules, &tf.rel_path);
+            tmp_files.sort_by_key(|tf| rule(tf).map_or(0, |r| r + 1));
+        }
+        let layout = lay_out(&tmp_files, cfg, opts);
+        let plan = ShardPlan::new(&layout, cfg, opts, m, field)?;
+        // Their extra parity joins the outer groups
+        let outer =
+            outer.map(|l| l.with_critical(opts.critical_parity, OuterLayout::runs(&plan.critical)));
+        let Layout { chunks, files, merkle_root_hex, deduped, dir_roots, .. } = layout;
+        let progress = opts.progress.clone().unwrap_or_default();
+        let create = Create { output, cfg, opts, chunks, plan, outer, progress };
+
+        // A resumed create rewrites every header at the end, so the set
+        // gets a fresh id either way
+        let set_id = SetId::random()?;
+        // One past the set this replaces, so that its volumes left in the
+        // output dir read as stale rather than as the set's own
+        let generation = crate::manifest::load(&output.join(crate::manifest::MANIFEST_JSON))
+            .ok()
+            .and_then(|(old, _)| old.generation())
+            .map_or(1, |g| g + 1);
+        let set_ext = set_header_ext(opts, create.plan.field, set_id, generation);
+        let mut manifest =
+            create.manifest(files, symlinks, merkle_root_hex, deduped, set_id, generation)?;
+
+        // 4) Compute RS parity per stripe and write volumes (round-robin placement)
+        create.progress.set_workers(rayon::current_num_threads());
+        let encoding = create.progress.stage("encode");
+        let outer_stage = create.outer.as_ref().map(|_| create.progress.stage("outer"));
+        let mut vols = create.open_volumes(&mut manifest, &set_ext, &dir_roots)?;
+        create.write_inner(&mut vols, &*backend, &encoding)?;
+        encoding.finish();
+        if let (Some(layout), Some(stage)) = (&create.outer, &outer_stage) {
+            create.write_outer(layout, &mut vols, stage)?;
+        }
+
+        let subs = create.seal(&mut manifest, &dir_roots)?;
+        create.finish_volumes(&mut vols, &manifest, &set_ext)?;
+        create.save(&manifest, &subs, &tmp_files, &vols.names)?;
+        vols.close(output)?;
+        Ok(manifest)
+    }
+}
+
+/// Chunk and hash `files` of the tree at `root` (collected per file; they
+/// get their global order later).
+fn hash_tree(
+    root: &Path,
+    files: &[PathBuf],
+    hasher: &ChunkHasher,
+    opts: &EncodeOptions,
+) -> Result<Vec<TmpFile>> {
+    let hashing = opts.progress.clone().unwrap_or_default().stage("hash");
+    let sizes = files.iter().map(|p| std::fs::metadata(p).map_or(0, |md| md.len()));
+    hashing.add_total(sizes.sum(), files.len() as u64);
+    let mut tmp_files: Vec<TmpFile> = Vec::new();
+    for path in files {
+        if let Some(p) = &opts.progress {
+            p.check_cancelled()?;
+        }
+        // Prefer a simple prefix strip since WalkDir yields paths under `root`.
+        // This avoids macOS `/var` -> `/private/var` symlink quirks and ensures
+        // manifest relpaths never contain parent traversal segments.
+        let rel = path.strip_prefix(root).expect("walked path not under root");
+        let rel_path = canonical_rel(rel);
+        hashing.set_current(&rel_path);
+        let (media, cuts) = media_cuts(path, opts.media_align);
+        let before = crate::hashcache::stamp_of(path);
+        let (md, chunks) = read_chunks(path, hasher, &cuts, opts.direct_io, opts.drop_cache)?;
+        let stamp = before.filter(|b| crate::hashcache::stamp_of(path).as_ref() == Some(b));
+        let size = md.len();
+        let meta = crate::meta::capture(path, &md);
+        let media = media.map(|container| MediaLayout {
+            container: container.to_string(),
+            aligned_cuts: aligned_cuts(&chunks, &cuts),
+        });
+        tmp_files.push(TmpFile { rel_path, size, chunks, meta, media, stamp });
+        hashing.add_bytes(size);
+        hashing.add_items(1);
+    }
+    hashing.finish();
+    Ok(tmp_files)
+}
+
+/// The parity rule a file falls under, if any: the first that matches.
+fn rule_of(rules: &[ParityRule], rel: &str) -> Option<usize> {
+    let matches = |r: &ParityRule| is_excluded(Path::new(rel), std::slice::from_ref(&r.pattern));
+    rules.iter().position(matches)
+}
+
+/// Where the chunks of a create go.
+struct Layout {
+    /// Stripe data in slot order, zeros where a stripe is cut short
+    chunks: Vec<Vec<u8>>,
+    /// The files with their chunks' slots
+    files: Vec<FileEntry>,
+    merkle_root_hex: String,
+    /// Chunks stored in the slot of an identical one (`dedup`)
+    deduped: u64,
+    /// Merkle root of the chunks of each top-level directory (`sub_manifests`)
+    dir_roots: BTreeMap<String, String>,
+    /// First stripe of each run of files under one parity rule (or none)
+    rule_starts: Vec<(Option<usize>, u64)>,
+}
+
+/// Give the chunks of `tmp_files` their global order: sequential per file
+/// or round-robin across files.
+fn lay_out(tmp_files: &[TmpFile], cfg: &EncoderConfig, opts: &EncodeOptions) -> Layout {
+    let hasher = ChunkHasher::new(cfg.chunk_size, opts.hash_key.as_ref().map(|k| k.0));
+    let mut order: Vec<(usize, usize)> = Vec::new(); // (file_idx, local_chunk_idx)
+    if cfg.interleave_files {
+        let mut rr = 0usize;
+        loop {
+            let mut appended = false;
+            for (fi, tf) in tmp_files.iter().enumerate() {
+                if rr < tf.chunks.len() {
+                    order.push((fi, rr));
+                    appended = true;
                 }
-                if !appended {
-                    break;
+            }
+            if !appended {
+                break;
+            }
+            rr += 1;
+        }
+    } else {
+        for (fi, tf) in tmp_files.iter().enumerate() {
+            for ci in 0..tf.chunks.len() {
+                order.push((fi, ci));
+            }
+        }
+    }
+
+    // Build final buffers and manifest file entries with global idx, and Merkle list
+    let mut chunk_buffers: Vec<Vec<u8>> = Vec::with_capacity(order.len());
+    let mut all_chunk_hashes = Vec::with_capacity(order.len());
+    let mut file_entries: Vec<FileEntry> = tmp_files
+        .iter()
+        .map(|tf| FileEntry {
+            id: 0,
+            rel_path: tf.rel_path.clone(),
+            size: tf.size,
+            chunks: Vec::new(),
+            meta: tf.meta.clone(),
+            media: tf.media.clone(),
+        })
+        .collect();
+    let mut next_idx: u64 = 0;
+    // Slot of each distinct chunk hash (`dedup`); the Merkle list keeps
+    // every location so that it matches what verify hashes
+    let mut slots: HashMap<&str, u64> = HashMap::new();
+    let mut deduped = 0u64;
+    // Chunk hashes of each top-level directory (`sub_manifests`)
+    let mut dir_hashes: BTreeMap<&str, Vec<blake3::Hash>> = BTreeMap::new();
+    if opts.sub_manifests {
+        for tf in tmp_files {
+            if let Some(dir) = crate::submanifest::top_dir(&tf.rel_path) {
+                dir_hashes.entry(dir).or_default();
+            }
+        }
+    }
+    let mut cur_dir = None;
+    let mut rule_starts: Vec<(Option<usize>, u64)> = Vec::new();
+    for (fi, ci) in order {
+        let tc = &tmp_files[fi].chunks[ci];
+        all_chunk_hashes.push(hasher.hash(&tc.buf));
+        if !opts.parity_rules.is_empty() {
+            let rule = rule_of(&opts.parity_rules, &tmp_files[fi].rel_path);
+            if rule_starts.last().map(|(r, _)| *r) != Some(rule) {
+                // A group starts on a fresh stripe and shares no slot
+                while next_idx % cfg.stripe_k as u64 != 0 {
+                    chunk_buffers.push(vec![0u8; cfg.chunk_size]);
+                    next_idx += 1;
                 }
-                rr += 1;
+                slots.clear();
+                rule_starts.push((rule, next_idx / cfg.stripe_k as u64));
             }
-        } else {
-            for (fi, tf) in tmp_files.iter().enumerate() {
-                for ci in 0..tf.chunks.len() {
-                    order.push((fi, ci));
+        }
+        if opts.sub_manifests {
+            let dir = crate::submanifest::top_dir(&tmp_files[fi].rel_path);
+            if cur_dir != Some(dir) {
+                // A new directory starts on a fresh stripe; the slots
+                // skipped hold zeros, and no slot is shared across
+                while next_idx % cfg.stripe_k as u64 != 0 {
+                    chunk_buffers.push(vec![0u8; cfg.chunk_size]);
+                    next_idx += 1;
                 }
+                slots.clear();
+                cur_dir = Some(dir);
+            }
+            if let Some(dir) = dir {
+                dir_hashes.entry(dir).or_default().push(hasher.hash(&tc.buf));
             }
         }
+        let idx = match slots.get(tc.hash_hex.as_str()) {
+            Some(&idx) => {
+                deduped += 1;
+                idx
+            }
+            None => {
+                if opts.dedup {
+                    slots.insert(&tc.hash_hex, next_idx);
+                }
+                chunk_buffers.push(tc.buf.clone());
+                next_idx += 1;
+                next_idx - 1
+            }
+        };
+        file_entries[fi].chunks.push(ChunkRef {
+            idx,
+            file_offset: tc.file_offset,
+            len: tc.len,
+            hash_hex: tc.hash_hex.clone(),
+            // Unkeyed, so left out where the hashes are keyed
+            weak: opts.hash_key.is_none().then_some(tc.weak),
+        });
+    }
+    // 3) Merkle root over final order
+    Layout {
+        chunks: chunk_buffers,
+        files: file_entries,
+        merkle_root_hex: merkle::root(&all_chunk_hashes).to_hex().to_string(),
+        deduped,
+        dir_roots: dir_hashes
+            .iter()
+            .map(|(dir, hashes)| (dir.to_string(), merkle::root(hashes).to_hex().to_string()))
+            .collect(),
+        rule_starts,
+    }
+}
+
+/// How many inner parity shards each stripe of a layout gets.
+struct ShardPlan {
+    geo: Geometry,
+    /// Shards of a plain stripe
+    m: usize,
+    field: RsField,
+    critical_parity: usize,
+    /// Stripes holding at least one chunk of a critical file
+    critical: HashSet<u64>,
+    parity_groups: Vec<ParityGroup>,
+}
 
-        // Build final buffers and manifest file entries with global idx, and Merkle list
-        let mut chunk_buffers: Vec<Vec<u8>> = Vec::with_capacity(order.len());
-        let mut all_chunk_hashes = Vec::with_capacity(order.len());
-        let mut file_entries: Vec<FileEntry> = tmp_files
+impl ShardPlan {
+    fn new(
+        layout: &Layout,
+        cfg: &EncoderConfig,
+        opts: &EncodeOptions,
+        m: usize,
+        field: RsField,
+    ) -> Result<Self> {
+        let geo = Geometry::new(cfg.stripe_k, m, cfg.chunk_size, layout.chunks.len() as u64);
+        let critical = layout
+            .files
+            .iter()
+            .filter(|fe| is_excluded(Path::new(&fe.rel_path), &opts.critical))
+            .flat_map(|fe| fe.chunks.iter().map(|c| geo.stripe_of(c.idx)))
+            .collect();
+        let stripes_end = geo.stripes;
+        let starts = &layout.rule_starts;
+        let parity_groups = opts
+            .parity_rules
             .iter()
-            .map(|tf| FileEntry {
-                rel_path: tf.rel_path.clone(),
-                size: tf.size,
-                chunks: Vec::new(),
+            .enumerate()
+            .map(|(i, r)| {
+                let at = starts.iter().position(|(rule, _)| *rule == Some(i));
+                let first_stripe = at.map_or(stripes_end, |at| starts[at].1);
+                let end =
+                    at.and_then(|at| starts.get(at + 1)).map_or(stripes_end, |(_, start)| *start);
+                ParityGroup {
+                    pattern: r.pattern.clone(),
+                    parity_pct: r.parity_pct,
+                    parity_shards: Geometry::parity_for_pct(cfg.stripe_k, r.parity_pct),
+                    first_stripe,
+                    stripes: end - first_stripe,
+                }
             })
             .collect();
-        let mut next_idx: u64 = 0;
-        for (fi, ci) in order {
-            let tc = &tmp_files[fi].chunks[ci];
-            all_chunk_hashes.push(blake3::hash(&tc.buf));
-            chunk_buffers.push(tc.buf.clone());
-            file_entries[fi].chunks.push(ChunkRef {
-                idx: next_idx,
-                file_offset: tc.file_offset,
-                len: tc.len,
-                hash_hex: tc.hash_hex.clone(),
-            });
-            next_idx += 1;
+        // With a budget, the parity fits the stripes actually laid out; an
+        // empty set has none and keeps the percentage's shards for updates
+        let (m, field) = match opts.parity_size {
+            Some(budget) if stripes_end > 0 => {
+                let m = budget_parity(budget, &geo, cfg)?;
+                (m, RsField::for_shards(cfg.stripe_k + m))
+            }
+            _ => (m, field),
+        };
+        Ok(Self { geo, m, field, critical_parity: opts.critical_parity, critical, parity_groups })
+    }
+
+    /// Inner parity shards of stripe `s`.
+    fn shards_of(&self, s: u64) -> usize {
+        match self
+            .parity_groups
+            .iter()
+            .find(|g| (g.first_stripe..g.first_stripe + g.stripes).contains(&s))
+        {
+            Some(g) => g.parity_shards,
+            None if self.critical.contains(&s) => self.m + self.critical_parity,
+            None => self.m,
         }
+    }
 
-        // 3) Merkle root over final order
-        let merkle_root_hex = merkle::root(&all_chunk_hashes).to_hex().to_string();
+    /// Every shard count a stripe may get.
+    fn sizes(&self) -> BTreeSet<usize> {
+        let mut sizes = BTreeSet::from([self.m]);
+        if self.critical_parity > 0 {
+            sizes.insert(self.m + self.critical_parity);
+        }
+        sizes.extend(self.parity_groups.iter().map(|g| g.parity_shards));
+        sizes
+    }
+}
 
-        // 4) Compute RS parity per stripe and write volumes (round-robin placement)
+/// What the stages of one create share once the chunks are laid out.
+struct Create<'a> {
+    output: &'a Path,
+    cfg: &'a EncoderConfig,
+    opts: &'a EncodeOptions,
+    chunks: Vec<Vec<u8>>,
+    plan: ShardPlan,
+    outer: Option<OuterLayout>,
+    progress: crate::progress::Progress,
+}
+
+/// Which volume each copy of a parity shard goes to.
+#[derive(Clone, Copy)]
+struct Placement {
+    volumes: usize,
+    copies: usize,
+    /// Split by size, a stripe's shards start one volume further than the
+    /// previous stripe's, so every volume gets its share
+    rotate: bool,
+}
+
+impl Placement {
+    fn inner(&self, s: u64, pi: usize, c: usize) -> usize {
+        crate::volume::shard_volume(if self.rotate { s } else { 0 }, pi, c, self.volumes)
+    }
+
+    fn outer(&self, g: u64, pi: usize, c: usize) -> usize {
+        crate::volume::shard_volume(g, pi, c, self.volumes)
+    }
+}
+
+/// The parity volumes of a create, open for writing, and the journal of
+/// what they hold.
+struct VolumeSet {
+    files: Vec<(File, Vec<VolumeEntry>)>,
+    paths: Vec<PathBuf>,
+    names: Vec<String>,
+    place: Placement,
+    /// Room after each header for the head copy of its index
+    index_copy: u64,
+    journal: JournalWriter,
+    /// Entries of each volume already in the journal
+    journaled: Vec<usize>,
+    /// Stripes whose parity is written
+    done: u64,
+    writing: Vec<Stage>,
+    /// One create (or resume) per parity dir at a time
+    _lock: File,
+}
+
+impl VolumeSet {
+    /// Drop the journal of a create that completed; the lock goes last.
+    fn close(self, output: &Path) -> Result<()> {
+        drop(self.journal);
+        std::fs::remove_file(Journal::path(output)).context("remove create journal")
+    }
+}
+
+impl Create<'_> {
+    /// The manifest of the set; the volume count is filled in once they
+    /// are laid out.
+    fn manifest(
+        &self,
+        files: Vec<FileEntry>,
+        symlinks: Vec<SymlinkEntry>,
+        merkle_root_hex: String,
+        deduped: u64,
+        set_id: SetId,
+        generation: u64,
+    ) -> Result<Manifest> {
+        let (output, cfg, opts, plan) = (self.output, self.cfg, self.opts, &self.plan);
+        let mut mext = ExtMap::new();
+        if let Some(align) = opts.align {
+            mext.insert_u32(ext::key::PAGE_ALIGN, align as u32);
+        }
+        if opts.media_align {
+            mext.insert_u32(ext::key::MEDIA_ALIGN, 1);
+        }
+        if let Some(layout) = &self.outer {
+            opts.outer_scope.to_ext(&mut mext);
+            layout.critical_to_ext(&mut mext)?;
+        }
+        if opts.files.is_some() {
+            mext.insert_u32(ext::key::FILE_LIST, 1);
+        }
+        if deduped > 0 {
+            mext.insert_u64(ext::key::DEDUP, deduped);
+        }
+        plan.field.to_ext(&mut mext);
+        set_id.to_ext(&mut mext);
+        mext.insert_u64(ext::key::GENERATION, generation);
+        if let Some(cap) = opts.volume_max_size {
+            mext.insert_u64(ext::key::VOLUME_MAX_SIZE, cap);
+        }
+        if let Some(template) = &opts.volume_name {
+            mext.insert(ext::key::VOLUME_NAME, template.as_bytes().to_vec());
+        }
+        if !opts.volume_dirs.is_empty() {
+            // Absolute, so that the set finds them from wherever it is used
+            let cwd = std::env::current_dir().context("current dir")?;
+            let here = cwd.join(output);
+            let mut text = String::new();
+            for dir in &opts.volume_dirs {
+                let dir = cwd.join(dir);
+                ensure!(dir != here, "{:?} is the parity dir itself", dir);
+                text.push_str(&format!("{}\n", dir.to_string_lossy()));
+            }
+            mext.insert(ext::key::VOLUME_DIRS, text.into_bytes());
+        }
+        if !opts.volume_groups.is_empty() {
+            crate::domains::to_ext(&opts.volume_groups, &mut mext);
+        }
+        // Rounded down; the manifest records `m` itself
+        let parity_pct = if opts.parity_shards.is_some() || opts.parity_size.is_some() {
+            Geometry::pct_for_parity(cfg.stripe_k, plan.m)
+        } else {
+            cfg.parity_pct
+        };
+        let mut manifest = Manifest {
+            created_utc: chrono::Utc::now().to_rfc3339(),
+            chunk_size: cfg.chunk_size,
+            stripe_k: cfg.stripe_k,
+            parity_pct,
+            parity_shards: Some(plan.m),
+            total_bytes: files.iter().map(|fe| fe.size).sum(),
+            total_chunks: self.chunks.len() as u64,
+            files: match &opts.rel_prefix {
+                Some(pre) => files
+                    .into_iter()
+                    .map(|fe| FileEntry { rel_path: format!("{}/{}", pre, fe.rel_path), ..fe })
+                    .collect(),
+                None => files,
+            },
+            symlinks: match &opts.rel_prefix {
+                Some(pre) => symlinks
+                    .into_iter()
+                    .map(|l| SymlinkEntry { rel_path: format!("{}/{}", pre, l.rel_path), ..l })
+                    .collect(),
+                None => symlinks,
+            },
+            next_file_id: 0,
+            merkle_root_hex,
+            parity_dir: output.to_string_lossy().to_string(),
+            volumes: cfg.volumes.max(1),
+            outer_group: cfg.outer_group,
+            outer_parity: cfg.outer_parity,
+            shard_copies: cfg.shard_copies.max(1),
+            exclude: opts.exclude.clone(),
+            critical: opts.critical.clone(),
+            critical_parity: opts.critical_parity,
+            parity_groups: plan.parity_groups.clone(),
+            volume_parity: opts.volume_parity,
+            info: opts.info.clone(),
+            recovery_stubs: Vec::new(),
+            ext: mext,
+            hash_key: None,
+            signed_form: None,
+        };
+        if let Some(key) = &opts.hash_key {
+            manifest.set_hash_key(key.clone());
+        }
+        manifest.assign_file_ids();
+        Ok(manifest)
+    }
+
+    /// How many volumes the set gets, and the room after each header for
+    /// the head copy of its index.
+    fn volume_count(
+        &self,
+        manifest: &Manifest,
+        set_ext: &ExtMap,
+        dir_roots: &BTreeMap<String, String>,
+    ) -> Result<(usize, u64)> {
+        let (cfg, opts, plan) = (self.cfg, self.opts, &self.plan);
+        let stripes = plan.geo.stripes;
+        let copies = cfg.shard_copies.max(1);
+        let Some(cap) = opts.volume_max_size else {
+            let vol_count = cfg.volumes.max(1);
+            // The most shards one volume can get, plus a slice of manifest
+            // backup for every 256 bytes of manifest a chunk may take
+            let widest = plan.sizes().last().copied().unwrap_or(plan.m);
+            let outer_shards = self.outer.as_ref().map_or(0, |l| {
+                stripes.div_ceil(l.group as u64) * (l.parity.div_ceil(vol_count) * copies) as u64
+            });
+            let index_copy = crate::index::index_copy_reserve(
+                stripes * (widest.div_ceil(vol_count) * copies) as u64
+                    + outer_shards
+                    + (manifest.total_chunks * 256).div_ceil(cfg.chunk_size as u64),
+            );
+            return Ok((vol_count, index_copy));
+        };
+        let mut sized = manifest.clone();
+        if opts.sub_manifests {
+            crate::submanifest::record(&mut sized.ext, dir_roots);
+        }
+        SizePlan {
+            cap,
+            stripes,
+            shards_of: &|s| plan.shards_of(s),
+            outer: self.outer.as_ref().map(|l| (stripes.div_ceil(l.group as u64), l.parity)),
+            copies,
+            chunk_size: cfg.chunk_size as u64,
+            header_len: volume_header(cfg, set_ext, 0, plan.m as u32, 0, 0).encoded_len(),
+            manifest: &sized,
+            codecs: opts.codecs,
+        }
+        .fit()
+    }
+
+    /// Lay out the volumes and open them with placeholder headers, or cut
+    /// the volumes of an interrupted run back to their last journaled
+    /// length (`resume`). Records the volume count in `manifest`.
+    fn open_volumes(
+        &self,
+        manifest: &mut Manifest,
+        set_ext: &ExtMap,
+        dir_roots: &BTreeMap<String, String>,
+    ) -> Result<VolumeSet> {
+        let (output, cfg, opts, plan) = (self.output, self.cfg, self.opts, &self.plan);
         std::fs::create_dir_all(output).with_context(|| format!("create dir {:?}", output))?;
-        let vol_count = cfg.volumes.max(1);
+        for dir in manifest.volume_dirs() {
+            std::fs::create_dir_all(&dir).with_context(|| format!("create dir {:?}", dir))?;
+        }
+        let (vol_count, index_copy) = self.volume_count(manifest, set_ext, dir_roots)?;
+        let copies = cfg.shard_copies.max(1);
+        if copies > vol_count {
+            bail!("--shard-copies {} needs at least as many volumes (have {})", copies, vol_count);
+        }
+        manifest.volumes = vol_count;
+        let names: Vec<String> = (0..vol_count).map(|vid| manifest.volume_name(vid)).collect();
+        let paths: Vec<PathBuf> =
+            (0..vol_count).map(|vid| manifest.volume_path(output, vid)).collect();
+        let place =
+            Placement { volumes: vol_count, copies, rotate: opts.volume_max_size.is_some() };
+        let stripes = plan.geo.stripes;
+        if !opts.volume_groups.is_empty() {
+            crate::domains::check(&opts.volume_groups, vol_count)?;
+            crate::domains::require_one(&crate::domains::tolerance(
+                &opts.volume_groups,
+                stripes,
+                &|s| plan.shards_of(s),
+                copies,
+                &|s, pi, c| place.inner(s, pi, c),
+            ))?;
+        }
+
+        let lock_file =
+            File::create(output.join(".parx.repair.lock")).context("create global repair lock")?;
+        fs2::FileExt::try_lock_exclusive(&lock_file).context("acquire global repair lock")?;
+        let jheader = JournalHeader {
+            chunk_size: cfg.chunk_size as u64,
+            stripe_k: cfg.stripe_k as u64,
+            parity_shards: plan.m as u64,
+            volumes: vol_count as u64,
+            shard_copies: copies as u64,
+            critical: opts.critical.clone(),
+            critical_parity: opts.critical_parity as u64,
+            parity_rules: opts.parity_rules.clone(),
+            total_chunks: manifest.total_chunks,
+            merkle_root_hex: manifest.merkle_root_hex.clone(),
+            volume_max_size: opts.volume_max_size.unwrap_or(0),
+        };
+        // Stripes already encoded by the interrupted run, with their entries
+        let resumed = if opts.resume {
+            let j = Journal::load(output)?;
+            if j.header != jheader {
+                bail!(
+                    "the create journal in {:?} was written for other input or settings; run create without --resume",
+                    output
+                );
+            }
+            j.segments.last().is_some().then_some(j)
+        } else {
+            None
+        };
 
-        // Open volumes, write placeholder headers
         let mut files_out: Vec<(File, Vec<VolumeEntry>)> = Vec::new();
-        for vid in 0..vol_count {
-            let path = output.join(vol_name(vid));
+        for (vid, path) in paths.iter().enumerate() {
             let f = OpenOptions::new()
                 .create(true)
                 .read(true)
                 .write(true)
-                .truncate(true)
-                .open(&path)
+                .truncate(resumed.is_none())
+                .open(path)
                 .with_context(|| format!("create {:?}", path))?;
-            // placeholder header (entries=0 for now)
-            super_write_simple_header(&f, cfg.stripe_k as u32, 0, 0)?;
+            if let Some(j) = &resumed {
+                f.set_len(j.segments.last().unwrap().volume_lens[vid])?;
+                let entries = j.segments.iter().flat_map(|s| s.entries[vid].iter().cloned());
+                files_out.push((f, entries.collect()));
+                continue;
+            }
+            // placeholder header (entries=0 for now); the extension area
+            // must keep the same size when the header is rewritten below
+            let hdr = volume_header(cfg, set_ext, vid, 0, 0, index_copy);
+            hdr.write_to(&f)?;
+            f.set_len(hdr.encoded_len() + index_copy)?;
             files_out.push((f, Vec::new()));
         }
+        let mut journaled: Vec<usize> = files_out.iter().map(|(_, e)| e.len()).collect();
+        let (journal, done) = match &resumed {
+            Some(j) => (
+                JournalWriter::reopen(output, j.intact_len)?,
+                j.segments.last().unwrap().stripes_done,
+            ),
+            None => {
+                let mut w = JournalWriter::create(output, &jheader)?;
+                let mut vols: Vec<_> = files_out.iter_mut().map(|(f, e)| (&*f, e)).collect();
+                w.segment(&snapshot(&mut vols, &mut journaled, 0)?)?;
+                (w, 0)
+            }
+        };
 
-        // Inner RS
+        let writing: Vec<Stage> =
+            names.iter().map(|name| self.progress.stage(&format!("write/{}", name))).collect();
+        // Parity bytes each volume is still to get
+        let cs = cfg.chunk_size as u64;
+        for s in done..stripes {
+            for pi in 0..plan.shards_of(s) {
+                (0..copies).for_each(|c| writing[place.inner(s, pi, c)].add_total(cs, 0));
+            }
+        }
+        if let Some(layout) = &self.outer {
+            for g in 0..stripes.div_ceil(layout.group as u64) {
+                for pi in 0..layout.parity {
+                    (0..copies).for_each(|c| writing[place.outer(g, pi, c)].add_total(cs, 0));
+                }
+            }
+        }
+        Ok(VolumeSet {
+            files: files_out,
+            paths,
+            names,
+            place,
+            index_copy,
+            journal,
+            journaled,
+            done,
+            writing,
+            _lock: lock_file,
+        })
+    }
+
+    /// Inner RS, a journal segment at a time.
+    fn write_inner(
+        &self,
+        vols: &mut VolumeSet,
+        backend: &dyn ComputeBackend,
+        encoding: &Stage,
+    ) -> Result<()> {
+        use rayon::prelude::*;
+        use std::sync::{Arc, Mutex};
+        let (cfg, opts, plan) = (self.cfg, self.opts, &self.plan);
+        if plan.m == 0 {
+            return Ok(());
+        }
         let k = cfg.stripe_k;
-        let mut m = (cfg.stripe_k as u64 * cfg.parity_pct as u64).div_ceil(100);
-        if cfg.parity_pct == 0 {
-            m = 0;
+        let stripes = plan.geo.stripes;
+        let seg_stripes =
+            if opts.segment_stripes == 0 { DEFAULT_SEGMENT_STRIPES } else { opts.segment_stripes };
+        let mut segments_written = 0;
+        // Wrap volumes in buffered writers for synchronized concurrent appends
+        let mut writers = Vec::with_capacity(vols.files.len());
+        for ((f, entries), path) in std::mem::take(&mut vols.files).into_iter().zip(&vols.paths) {
+            let end = f.metadata()?.len();
+            let direct = if opts.direct_io {
+                crate::direct::open_write(path).with_context(|| format!("open {:?}", path))?
+            } else {
+                None
+            };
+            let w = crate::volwriter::VolumeWriter::with_direct(f, direct, end, &opts.write);
+            writers.push(Arc::new(Mutex::new((w, entries))));
         }
-        let m = m as usize;
-        if m > 0 {
-            use rayon::prelude::*;
-            use std::sync::{Arc, Mutex};
-            let total_chunks = chunk_buffers.len();
-            let stripes = total_chunks.div_ceil(k);
-            // Wrap volumes for synchronized concurrent appends
-            let vols: Vec<_> =
-                files_out.into_iter().map(|pair| Arc::new(Mutex::new(pair))).collect();
-            (0..stripes).into_par_iter().for_each(|s| {
-                // Build data shards for this stripe
-                let mut data_bufs: Vec<Vec<u8>> = Vec::with_capacity(k);
-                for i in 0..k {
-                    let idx = s * k + i;
-                    if idx < total_chunks {
-                        data_bufs.push(chunk_buffers[idx].clone());
-                    } else {
-                        data_bufs.push(vec![0u8; cfg.chunk_size]);
-                    }
+        // Codecs are shared by all stripes of a size: setting up a
+        // GF(2^16) matrix is costly
+        let codecs: BTreeMap<usize, RsCodec> = plan
+            .sizes()
+            .into_iter()
+            .map(|n| Ok((n, RsCodec::with_field(plan.field, k, n).context("init RS")?)))
+            .collect::<Result<_>>()?;
+        let m_max = codecs.keys().last().copied().unwrap_or(plan.m);
+        let stripe_bytes = (k * cfg.chunk_size) as u64;
+        let (place, writing) = (vols.place, &vols.writing);
+        encoding.add_total((stripes - vols.done) * stripe_bytes, stripes - vols.done);
+        while vols.done < stripes {
+            let end = (vols.done + seg_stripes as u64).min(stripes);
+            // Stripes go to the backend in batches of bounded size; a
+            // batch's parity is appended to the volumes in parallel
+            let zeros = vec![0u8; cfg.chunk_size];
+            let batch = (BATCH_BYTES / ((k + m_max) * cfg.chunk_size)).max(1);
+            let seg: Vec<u64> = (vols.done..end).collect();
+            for part in seg.chunks(batch) {
+                let mut by_size: BTreeMap<usize, Vec<u64>> = BTreeMap::new();
+                for &s in part {
+                    by_size.entry(plan.shards_of(s)).or_default().push(s);
                 }
-                let mut parity_bufs: Vec<Vec<u8>> =
-                    (0..m).map(|_| vec![0u8; cfg.chunk_size]).collect();
-                let mut shards: Vec<&mut [u8]> = Vec::with_capacity(k + m);
-                for b in &mut data_bufs {
-                    shards.push(b.as_mut_slice());
+                let mut parity: Vec<(u64, Vec<Vec<u8>>)> = Vec::with_capacity(part.len());
+                for (n, stripes) in by_size {
+                    let rs = &codecs[&n];
+                    let data: Vec<Vec<&[u8]>> = stripes
+                        .iter()
+                        .map(|&s| {
+                            (0..k)
+                                .map(|i| {
+                                    self.chunks
+                                        .get(s as usize * k + i)
+                                        .map_or(zeros.as_slice(), |b| b.as_slice())
+                                })
+                                .collect()
+                        })
+                        .collect();
+                    let out = backend
+                        .encode_batch(rs, &data, cfg.chunk_size)
+                        .with_context(|| format!("RS encode ({})", backend.name()))?;
+                    encoding.add_items(stripes.len() as u64);
+                    encoding.add_bytes(stripes.len() as u64 * stripe_bytes);
+                    parity.extend(stripes.into_iter().zip(out));
                 }
-                for b in &mut parity_bufs {
-                    shards.push(b.as_mut_slice());
+                // Append parity shards to volumes; replicas go to the next
+                // volumes round-robin so every copy lands on a distinct volume
+                parity.into_par_iter().try_for_each(|(s, parity_bufs)| -> Result<()> {
+                    let _busy = self.progress.busy();
+                    for (pi, pbuf) in parity_bufs.into_iter().enumerate() {
+                        let hash = *blake3::hash(&pbuf).as_bytes();
+                        for c in 0..place.copies {
+                            let vid = place.inner(s, pi, c);
+                            let mut guard = writers[vid].lock().expect("lock vol");
+                            let (ref mut vw, ref mut vindex) = *guard;
+                            let off = vw.append(&pbuf)?;
+                            writing[vid].add_bytes(pbuf.len() as u64);
+                            vindex.push(VolumeEntry {
+                                stripe: s,
+                                parity_idx: pi as u16,
+                                offset: off,
+                                len: cfg.chunk_size as u32,
+                                hash: Some(hash),
+                                kind: ShardKind::Inner,
+                            });
+                        }
+                    }
+                    Ok(())
+                })?;
+            }
+            vols.done = end;
+            let mut guards: Vec<_> = writers.iter().map(|v| v.lock().expect("lock vol")).collect();
+            for g in guards.iter_mut() {
+                g.0.flush()?;
+            }
+            let mut refs: Vec<_> = guards
+                .iter_mut()
+                .map(|g| {
+                    let (w, entries) = &mut **g;
+                    (w.file(), entries)
+                })
+                .collect();
+            vols.journal.segment(&snapshot(&mut refs, &mut vols.journaled, end)?)?;
+            segments_written += 1;
+            if opts.interrupt_after_segments == Some(segments_written) {
+                bail!("create interrupted after {} journal segments", segments_written);
+            }
+            self.progress
+                .check_cancelled()
+                .context("create stopped at a journal segment; `--resume` continues it")?;
+        }
+        // Unwrap volumes back
+        for v in writers {
+            let (w, entries) =
+                Arc::try_unwrap(v).ok().expect("unwrap arc").into_inner().expect("unlock");
+            vols.files.push((w.finish()?, entries));
+        }
+        Ok(())
+    }
+
+    /// Outer RS over groups of stripes (inner parity is recomputed per
+    /// group; a critical stripe's extra shards extend the same code).
+    fn write_outer(&self, layout: &OuterLayout, vols: &mut VolumeSet, stage: &Stage) -> Result<()> {
+        use rayon::prelude::*;
+        let (cfg, plan) = (self.cfg, &self.plan);
+        let k = cfg.stripe_k;
+        let stripes = plan.geo.stripes;
+        let widest = plan.m + layout.critical_parity;
+        let rs = RsCodec::with_field(plan.field, k, widest).context("init RS")?;
+        let group_count = stripes.div_ceil(layout.group as u64);
+        stage.add_total(0, group_count);
+        let groups: Vec<(u64, Vec<Vec<u8>>)> = (0..group_count)
+            .into_par_iter()
+            .map(|g| -> Result<(u64, Vec<Vec<u8>>)> {
+                let _busy = self.progress.busy();
+                let mut members = Vec::new();
+                for s in layout.stripes_of(g, stripes) {
+                    let mut bufs: Vec<Vec<u8>> = (0..k + widest)
+                        .map(|i| {
+                            let idx = s as usize * k + i;
+                            match self.chunks.get(idx) {
+                                Some(b) if i < k => b.clone(),
+                                _ => vec![0u8; cfg.chunk_size],
+                            }
+                        })
+                        .collect();
+                    let mut shards: Vec<&mut [u8]> =
+                        bufs.iter_mut().map(|b| b.as_mut_slice()).collect();
+                    rs.encode(&mut shards).context("RS encode")?;
+                    bufs.truncate(k + layout.parity_of(s));
+                    members.push(bufs);
                 }
-                // Construct RS per task to avoid sharing concerns
-                let rs = RsCodec::new(k, m).expect("init RS");
-                rs.encode(&mut shards[..]).expect("RS encode");
-                // Append parity shards to volumes
-                for (pi, pbuf) in parity_bufs.into_iter().enumerate() {
-                    let vid = pi % vol_count;
-                    let mut guard = vols[vid].lock().expect("lock vol");
-                    let (ref mut vf, ref mut vindex) = *guard;
-                    let off = vf.metadata().expect("meta").len();
-                    vf.seek(SeekFrom::End(0)).expect("seek end");
-                    vf.write_all(&pbuf).expect("write parity");
+                stage.add_items(1);
+                Ok((g, layout.encode(&members)?))
+            })
+            .collect::<Result<_>>()?;
+        stage.finish();
+        for (g, shards) in groups {
+            for (pi, pbuf) in shards.into_iter().enumerate() {
+                let hash = *blake3::hash(&pbuf).as_bytes();
+                for c in 0..vols.place.copies {
+                    let vid = vols.place.outer(g, pi, c);
+                    let (vf, vindex) = &mut vols.files[vid];
+                    let off = vf.metadata()?.len();
+                    vf.seek(SeekFrom::End(0))?;
+                    vf.write_all(&pbuf)?;
+                    vols.writing[vid].add_bytes(pbuf.len() as u64);
                     vindex.push(VolumeEntry {
-                        stripe: s as u32,
+                        stripe: g,
                         parity_idx: pi as u16,
                         offset: off,
                         len: cfg.chunk_size as u32,
-                        hash: None,
-                        outer_for_stripe: None,
+                        hash: Some(hash),
+                        kind: ShardKind::Outer,
                     });
                 }
+            }
+        }
+        Ok(())
+    }
+
+    /// Complete the manifest once the parity is written: recovery stub,
+    /// sub-manifests, signature. Returns the sub-manifests.
+    fn seal(
+        &self,
+        manifest: &mut Manifest,
+        dir_roots: &BTreeMap<String, String>,
+    ) -> Result<Vec<(String, Manifest)>> {
+        let opts = self.opts;
+        if let Some(exe) = &opts.recovery_stub {
+            manifest.recovery_stubs.push(crate::stub::embed(self.output, exe)?);
+        }
+        let mut subs = crate::submanifest::split(manifest, opts.rel_prefix.as_deref(), dir_roots);
+        if opts.sub_manifests {
+            crate::submanifest::record(&mut manifest.ext, dir_roots);
+        }
+        if let Some(sk) = &opts.sign_key {
+            crate::sign::sign_manifest(manifest, sk)?;
+            for (_, sub) in &mut subs {
+                crate::sign::sign_manifest(sub, sk)?;
+            }
+        }
+        Ok(subs)
+    }
+
+    /// Finalize indices and headers; every volume carries a manifest
+    /// backup and parity over manifest.json.
+    fn finish_volumes(
+        &self,
+        vols: &mut VolumeSet,
+        manifest: &Manifest,
+        set_ext: &ExtMap,
+    ) -> Result<()> {
+        let (cfg, opts) = (self.cfg, self.opts);
+        let mf_parity = MetaParity::protect(&crate::manifest::to_json(manifest)?)?;
+        for (vid, (vf, vindex)) in vols.files.iter_mut().enumerate() {
+            let end = vf.metadata()?.len();
+            crate::manifest_backup::append(vf, vindex, end, manifest, opts.codecs.backup)?;
+            // Header first: writing the index fills in its head copy record
+            let entries = crate::volume::entry_count(vindex)?;
+            volume_header(cfg, set_ext, vid, self.plan.m as u32, entries, vols.index_copy)
+                .write_to(&*vf)?;
+            crate::index::write_index_and_trailer_with(
+                vf,
+                vindex,
+                &[(metaparity::TAG_MANIFEST, &mf_parity)],
+                opts.codecs.index,
+            )?;
+            vols.writing[vid].finish();
+            if let Some(cap) = opts.volume_max_size {
+                let len = vf.metadata()?.len();
+                ensure!(
+                    len <= cap,
+                    "{} came out at {} bytes, over --volume-max-size {}",
+                    vols.names[vid],
+                    len,
+                    cap
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the manifest, its sub-manifests and what else the parity dir
+    /// keeps next to them, removing what an earlier set there left behind.
+    fn save(
+        &self,
+        manifest: &Manifest,
+        subs: &[(String, Manifest)],
+        tmp_files: &[TmpFile],
+        names: &[String],
+    ) -> Result<()> {
+        let output = self.output;
+        crate::manifest::save(manifest, output)?;
+        if self.opts.volume_parity > 0 {
+            crate::volparity::protect(output, names, self.opts.volume_parity)?;
+        } else {
+            // Volume parity of an earlier set here would not match this one
+            crate::volparity::remove(output)?;
+        }
+        // Sub-manifests of an earlier set here would not match this one
+        let sub_root = output.join(crate::submanifest::SUB_DIR);
+        if sub_root.is_dir() {
+            std::fs::remove_dir_all(&sub_root).with_context(|| format!("remove {:?}", sub_root))?;
+        }
+        for (dir, sub) in subs {
+            let path = crate::submanifest::path_of(output, dir);
+            let sub_dir = path.parent().expect("sub-manifest dir");
+            std::fs::create_dir_all(sub_dir)
+                .with_context(|| format!("create dir {:?}", sub_dir))?;
+            crate::manifest::save(sub, sub_dir)?;
+        }
+        // Keyed hashes are of no use to `parx contains`, which has no key
+        if manifest.hash_mode().is_none() {
+            crate::filter::ChunkFilter::from_manifest(manifest)?
+                .save(&output.join(crate::filter::FILTER_FILE))?;
+        }
+        // Files read here need not be read again by a repair or update soon after
+        let mut hashes = crate::hashcache::HashCache::default();
+        for (fe, tf) in manifest.files.iter().zip(tmp_files) {
+            if let Some(stamp) = &tf.stamp {
+                hashes.insert(&fe.rel_path, stamp.clone(), &fe.chunks);
+            }
+        }
+        if !hashes.files.is_empty() {
+            hashes.save(output)?;
+        }
+        Ok(())
+    }
+}
+
+/// Sync the volumes and collect what a journal segment records: their
+/// lengths and the entries added since the previous segment.
+fn snapshot(
+    vols: &mut [(&File, &mut Vec<VolumeEntry>)],
+    journaled: &mut [usize],
+    stripes_done: u64,
+) -> Result<Segment> {
+    let mut seg = Segment { stripes_done, ..Default::default() };
+    for (v, n) in vols.iter_mut().zip(journaled.iter_mut()) {
+        let (vf, vindex) = v;
+        vf.sync_data()?;
+        seg.volume_lens.push(vf.metadata()?.len());
+        seg.entries.push(vindex[*n..].to_vec());
+        *n = vindex.len();
+    }
+    Ok(seg)
+}
+
+// Volume header (keeps CLI/header semantics consistent)
+/// Regular files under `root` in canonical order (see [`rel_sort_key`]),
+/// skipping `.parx` and anything matching `exclude` (excluded dirs are not
+/// descended into), and the symlinks met on the way (not followed).
+pub(crate) fn scan_files(
+    root: &Path,
+    exclude: &[String],
+) -> Result<(Vec<PathBuf>, Vec<SymlinkEntry>)> {
+    let mut files: Vec<PathBuf> = Vec::new();
+    let mut symlinks = Vec::new();
+    let walker = walkdir::WalkDir::new(root).min_depth(1).into_iter();
+    for ent in walker.filter_entry(|e| {
+        e.path().strip_prefix(root).map_or(true, |rel| !is_excluded(rel, exclude))
+    }) {
+        let ent = ent?;
+        let p = ent.path();
+        if p.components().any(|c| c.as_os_str() == ".parx") {
+            continue;
+        }
+        if ent.path_is_symlink() {
+            let target = std::fs::read_link(p).with_context(|| format!("read link {:?}", p))?;
+            symlinks.push(SymlinkEntry {
+                rel_path: canonical_rel(p.strip_prefix(root).unwrap_or(p)),
+                target: target.to_string_lossy().to_string(),
             });
-            // Unwrap volumes back
-            let mut files_out_unwrapped: Vec<(File, Vec<VolumeEntry>)> = Vec::new();
-            for v in vols {
-                let pair = Arc::try_unwrap(v).expect("unwrap arc").into_inner().expect("unlock");
-                files_out_unwrapped.push(pair);
+            continue;
+        }
+        if !ent.file_type().is_file() {
+            continue;
+        }
+        files.push(p.to_path_buf());
+    }
+    files.sort_by_cached_key(|p| rel_sort_key(p.strip_prefix(root).unwrap_or(p)));
+    symlinks.sort_by_cached_key(|l| rel_sort_key(Path::new(&l.rel_path)));
+    // Names that differ only in their Unicode normalization would share a
+    // manifest path
+    for pair in files.windows(2) {
+        let [a, b] = pair else { continue };
+        if rel_sort_key(a.strip_prefix(root).unwrap_or(a))
+            == rel_sort_key(b.strip_prefix(root).unwrap_or(b))
+        {
+            bail!("{:?} and {:?} differ only in Unicode normalization; rename one", a, b);
+        }
+    }
+    Ok((files, symlinks))
+}
+
+/// Sort key that fixes the file order, and with it the stripe layout, of a
+/// scanned tree: the relative path in manifest form ([`canonical_rel`]:
+/// `/`-joined, NFC), compared as UTF-8 bytes. Unlike native path ordering
+/// (UTF-16 on Windows, walk order per directory) it does not depend on the
+/// OS, locale, separator or normalization, so the same tree yields the same
+/// geometry everywhere (`a.txt` < `a/b` < `b`).
+pub fn rel_sort_key(rel: &Path) -> Vec<u8> {
+    canonical_rel(rel).into_bytes()
+}
+
+/// Files of an explicit list (`create --files-from`), in list order, as
+/// paths under `root`. Relative entries are taken relative to the current
+/// directory like a shell would. Entries must be regular files under `root`;
+/// duplicates, `.parx` contents and excluded paths are dropped.
+pub(crate) fn listed_files(
+    root: &Path,
+    list: &[PathBuf],
+    exclude: &[String],
+) -> Result<Vec<PathBuf>> {
+    let root_can = root.canonicalize().with_context(|| format!("resolve {:?}", root))?;
+    let mut seen = HashMap::new();
+    let mut files = Vec::with_capacity(list.len());
+    for p in list {
+        let md = std::fs::symlink_metadata(p).with_context(|| format!("listed file {:?}", p))?;
+        if !md.is_file() {
+            bail!("listed path {:?} is not a regular file", p);
+        }
+        let rel = match p.strip_prefix(root) {
+            Ok(rel) => rel.to_path_buf(),
+            // e.g. `a/b` listed against root `.`, or root given via a symlink
+            Err(_) => {
+                let dir =
+                    p.parent().filter(|d| !d.as_os_str().is_empty()).unwrap_or(Path::new("."));
+                let dir = dir.canonicalize().with_context(|| format!("resolve {:?}", dir))?;
+                let name = p.file_name().with_context(|| format!("listed path {:?}", p))?;
+                dir.strip_prefix(&root_can)
+                    .map(|d| d.join(name))
+                    .map_err(|_| anyhow::anyhow!("listed file {:?} is not under {:?}", p, root))?
+            }
+        };
+        if rel.components().any(|c| !matches!(c, std::path::Component::Normal(_))) {
+            bail!("listed path {:?} must not go through `..`", p);
+        }
+        if rel.components().any(|c| c.as_os_str() == ".parx") || is_excluded(&rel, exclude) {
+            continue;
+        }
+        match seen.get(&canonical_rel(&rel)) {
+            None => {
+                seen.insert(canonical_rel(&rel), rel.clone());
+                files.push(root.join(rel));
+            }
+            Some(first) if *first != rel => {
+                bail!("{:?} and {:?} differ only in Unicode normalization; rename one", first, rel)
             }
-            files_out = files_out_unwrapped;
+            Some(_) => {}
         }
+    }
+    Ok(files)
+}
 
-        // Finalize indices and headers
-        for (vf, vindex) in files_out.iter_mut() {
-            crate::index::write_index_and_trailer(vf, vindex)?;
-            super_write_simple_header(vf, k as u32, m as u32, vindex.len() as u32)?;
+/// Split a `--files-from` list: one path per line, or NUL-separated when
+/// `nul` is set (`find -print0`). Empty entries are skipped.
+pub fn parse_file_list(data: &[u8], nul: bool) -> Result<Vec<PathBuf>> {
+    let sep = if nul { b'\0' } else { b'\n' };
+    data.split(|&b| b == sep)
+        .map(|e| if nul { e } else { e.strip_suffix(b"\r").unwrap_or(e) })
+        .filter(|e| !e.is_empty())
+        .enumerate()
+        .map(|(i, e)| {
+            std::str::from_utf8(e)
+                .map(PathBuf::from)
+                .with_context(|| format!("entry {} of the file list is not UTF-8", i + 1))
+        })
+        .collect()
+}
+
+/// Patterns of `patterns` as seen from the top-level directory `name`:
+/// component patterns as they are, path patterns under `name/` without that
+/// prefix, other path patterns dropped.
+pub(crate) fn subset_patterns(name: &str, patterns: &[String]) -> Vec<String> {
+    patterns
+        .iter()
+        .filter_map(|p| {
+            let t = p.trim_matches('/');
+            if !t.contains('/') {
+                return Some(p.clone());
+            }
+            t.strip_prefix(name).and_then(|r| r.strip_prefix('/')).map(str::to_string)
+        })
+        .collect()
+}
+
+/// Patterns without `/` match any single path component (`locks`, `lock.*`);
+/// patterns with `/` match the whole relative path (`data/tmp/*`).
+/// `*` matches any run of characters within a component, `**` any run across
+/// components, `?` a single character.
+pub fn is_excluded(rel: &Path, patterns: &[String]) -> bool {
+    let comps: Vec<String> =
+        rel.components().map(|c| c.as_os_str().to_string_lossy().to_string()).collect();
+    let full = comps.join("/");
+    patterns.iter().any(|p| {
+        let p = p.trim_matches('/');
+        if p.contains('/') {
+            glob_match(p.as_bytes(), full.as_bytes())
+        } else {
+            comps.iter().any(|c| glob_match(p.as_bytes(), c.as_bytes()))
         }
+    })
+}
 
-        // Manifest
-        let manifest = Manifest {
-            created_utc: chrono::Utc::now().to_rfc3339(),
-            chunk_size: cfg.chunk_size,
-            stripe_k: cfg.stripe_k,
-            parity_pct: cfg.parity_pct,
-            total_bytes,
-            total_chunks: next_idx,
-            files: file_entries,
-            merkle_root_hex,
-            parity_dir: output.to_string_lossy().to_string(),
-            volumes: vol_count,
-            outer_group: cfg.outer_group,
-            outer_parity: cfg.outer_parity,
+fn glob_match(pat: &[u8], s: &[u8]) -> bool {
+    match pat.split_first() {
+        None => s.is_empty(),
+        Some((b'*', [b'*', rest @ ..])) => (0..=s.len()).any(|i| glob_match(rest, &s[i..])),
+        Some((b'*', rest)) => (0..=s.len())
+            .take_while(|&i| i == 0 || s[i - 1] != b'/')
+            .any(|i| glob_match(rest, &s[i..])),
+        Some((&c, rest)) => match s.split_first() {
+            Some((&d, srest)) if (c == b'?' && d != b'/') || c == d => glob_match(rest, srest),
+            _ => false,
+        },
+    }
+}
+
+/// Container kind and boundary offsets of `path` when media alignment is on.
+pub(crate) fn media_cuts(path: &Path, enabled: bool) -> (Option<&'static str>, Vec<u64>) {
+    match enabled.then(|| crate::media::detect(path)).flatten() {
+        Some((container, cuts)) => (Some(container), cuts),
+        None => (None, Vec::new()),
+    }
+}
+
+pub(crate) fn aligned_cuts(chunks: &[TmpChunk], cuts: &[u64]) -> u64 {
+    chunks.iter().filter(|c| cuts.binary_search(&(c.file_offset + c.len as u64)).is_ok()).count()
+        as u64
+}
+
+pub(crate) struct TmpChunk {
+    pub buf: Vec<u8>,
+    pub len: u32,
+    pub file_offset: u64,
+    pub hash_hex: String,
+    pub weak: u32,
+}
+
+/// Split a file into zero-padded chunks of the hasher's size and hash each
+/// one. A chunk also ends at the first of `cuts` (container boundaries)
+/// inside it. With `direct`, the file is read around the page cache where
+/// the filesystem allows it; with `drop_cache`, through it with the hints
+/// of [`crate::pagecache`].
+pub(crate) fn read_chunks(
+    path: &Path,
+    hasher: &ChunkHasher,
+    cuts: &[u64],
+    direct: bool,
+    drop_cache: bool,
+) -> Result<(std::fs::Metadata, Vec<TmpChunk>)> {
+    if direct {
+        let opened = crate::direct::DirectReader::open(path);
+        if let Some(mut r) = opened.with_context(|| format!("open {:?}", path))? {
+            let md = r.file().metadata()?;
+            let chunks = chunk_stream(&mut r, md.len(), hasher, cuts)
+                .with_context(|| format!("read {:?}", path))?;
+            return Ok((md, chunks));
+        }
+    }
+    let mut f = File::open(path).with_context(|| format!("open {:?}", path))?;
+    let md = f.metadata()?;
+    if drop_cache {
+        crate::pagecache::sequential(&f);
+    }
+    let batched = match crate::uring::enabled() {
+        true => read_chunks_batched(&f, md.len(), hasher, cuts),
+        false => None,
+    };
+    let chunks = match batched {
+        Some(chunks) => chunks,
+        None => chunk_stream(&mut f, md.len(), hasher, cuts)
+            .with_context(|| format!("read {:?}", path))?,
+    };
+    if drop_cache {
+        crate::pagecache::evict(&f);
+    }
+    Ok((md, chunks))
+}
+
+/// [`read_chunks`] with the reads submitted in batches (see
+/// [`crate::uring`]). `None` when a read fails or the file got shorter, for
+/// [`chunk_stream`] to read it again and report what went wrong.
+fn read_chunks_batched(
+    f: &File,
+    len: u64,
+    hasher: &ChunkHasher,
+    cuts: &[u64],
+) -> Option<Vec<TmpChunk>> {
+    let chunk_size = hasher.chunk_size;
+    let mut extents = Vec::new();
+    let (mut file_offset, mut remaining) = (0u64, len);
+    while remaining > 0 {
+        let n = chunk_len(file_offset, remaining, chunk_size, cuts);
+        extents.push((file_offset, n));
+        file_offset += n as u64;
+        remaining -= n as u64;
+    }
+    if extents.len() as u64 > crate::manifest::MAX_FILE_CHUNKS {
+        return None;
+    }
+    let per_batch =
+        (crate::uring::BATCH_BYTES / chunk_size.max(1)).clamp(1, crate::uring::QUEUE_DEPTH);
+    let mut chunks = Vec::with_capacity(extents.len());
+    for group in extents.chunks(per_batch) {
+        let mut bufs: Vec<Vec<u8>> = group.iter().map(|_| vec![0u8; chunk_size]).collect();
+        let mut reqs: Vec<(u64, &mut [u8])> =
+            group.iter().zip(&mut bufs).map(|(&(off, n), b)| (off, &mut b[..n])).collect();
+        if crate::uring::read_at_many(f, &mut reqs).iter().any(Result::is_err) {
+            return None;
+        }
+        for (&(file_offset, n), buf) in group.iter().zip(bufs) {
+            let hash_hex = hasher.hash(&buf).to_hex().to_string();
+            let weak = crate::resync::weak(&buf[..n]);
+            chunks.push(TmpChunk { buf, len: n as u32, file_offset, hash_hex, weak });
+        }
+    }
+    Some(chunks)
+}
+
+/// Chunk the next `len` bytes of `r` like [`read_chunks`]; stops early if
+/// `r` ends first.
+fn chunk_stream(
+    r: &mut impl Read,
+    len: u64,
+    hasher: &ChunkHasher,
+    cuts: &[u64],
+) -> Result<Vec<TmpChunk>> {
+    let chunk_size = hasher.chunk_size;
+    let mut remaining = len;
+    let mut file_offset = 0u64;
+    let mut chunks = Vec::new();
+    while remaining > 0 {
+        let to_read = chunk_len(file_offset, remaining, chunk_size, cuts);
+        let mut buf = vec![0u8; chunk_size];
+        let mut readn = 0;
+        while readn < to_read {
+            match r.read(&mut buf[readn..to_read]) {
+                Ok(0) => break,
+                Ok(n) => readn += n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        if readn == 0 {
+            break;
+        }
+        if chunks.len() as u64 == crate::manifest::MAX_FILE_CHUNKS {
+            bail!(
+                "more than {} chunks in one file; use a larger --chunk-size",
+                crate::manifest::MAX_FILE_CHUNKS
+            );
+        }
+        let hash_hex = hasher.hash(&buf).to_hex().to_string();
+        let weak = crate::resync::weak(&buf[..readn]);
+        chunks.push(TmpChunk { buf, len: readn as u32, file_offset, hash_hex, weak });
+        remaining -= readn as u64;
+        file_offset += readn as u64;
+    }
+    Ok(chunks)
+}
+
+/// Regular files of a tar stream, chunked, in canonical order.
+fn tar_files(
+    reader: Box<dyn Read + '_>,
+    hasher: &ChunkHasher,
+    exclude: &[String],
+) -> Result<Vec<TmpFile>> {
+    use crate::tarstream::{EntryKind, TarReader};
+    let mut tar = TarReader::new(reader);
+    let mut files = BTreeMap::new();
+    while let Some(ent) = tar.next_entry()? {
+        let rel = Path::new(&ent.path);
+        if ent.kind != EntryKind::File
+            || ent.path.is_empty()
+            || rel.components().any(|c| c.as_os_str() == ".parx")
+            || rel.ancestors().any(|a| !a.as_os_str().is_empty() && is_excluded(a, exclude))
+        {
+            continue;
+        }
+        let chunks = chunk_stream(&mut tar.data(), ent.size, hasher, &[])?;
+        if chunks.iter().map(|c| c.len as u64).sum::<u64>() != ent.size {
+            bail!("tar stream ends inside {:?}", ent.path);
+        }
+        let meta = FileMeta {
+            mode: Some(ent.mode),
+            uid: Some(ent.uid),
+            gid: Some(ent.gid),
+            mtime: Some(ent.mtime),
+            ..Default::default()
         };
-        let mpath = output.join("manifest.json");
-        let mut mf = File::create(&mpath).context("create manifest.json")?;
-        mf.write_all(serde_json::to_string_pretty(&manifest)?.as_bytes())?;
+        files.insert(
+            rel_sort_key(rel),
+            TmpFile {
+                rel_path: canonical_rel(rel),
+                size: ent.size,
+                chunks,
+                meta: Some(meta),
+                media: None,
+                stamp: None,
+            },
+        );
+    }
+    Ok(files.into_values().collect())
+}
 
-        Ok(manifest)
+/// Length of the chunk starting at `file_offset`: a full chunk, the rest of
+/// the file, or up to the first cut that leaves a worthwhile piece.
+pub(crate) fn chunk_len(
+    file_offset: u64,
+    remaining: u64,
+    chunk_size: usize,
+    cuts: &[u64],
+) -> usize {
+    let mut len = std::cmp::min(remaining, chunk_size as u64);
+    let min = crate::media::min_piece(chunk_size);
+    let from = cuts.partition_point(|&c| c < file_offset + min);
+    if let Some(&c) = cuts.get(from) {
+        if c < file_offset + len {
+            len = c - file_offset;
+        }
+    }
+    len as usize
+}
+
+/// Extensions shared by every volume header of a set.
+fn set_header_ext(opts: &EncodeOptions, field: RsField, set_id: SetId, generation: u64) -> ExtMap {
+    let mut ext = ExtMap::new();
+    set_id.to_ext(&mut ext);
+    ext.insert_u64(ext::key::GENERATION, generation);
+    opts.info.to_ext(&mut ext);
+    field.to_ext(&mut ext);
+    opts.codecs.to_ext(&mut ext);
+    ext
+}
+
+/// Room for what create adds to the manifest after a `--volume-max-size`
+/// layout is chosen: the signature, the recovery stub, the volume count.
+const MANIFEST_SLACK: usize = 2048;
+
+/// What decides how many volumes a set split by `--volume-max-size` needs.
+struct SizePlan<'a> {
+    cap: u64,
+    stripes: u64,
+    shards_of: &'a dyn Fn(u64) -> usize,
+    /// Outer groups and parity shards per group
+    outer: Option<(u64, usize)>,
+    copies: usize,
+    chunk_size: u64,
+    header_len: u64,
+    /// The manifest as far as known, for the size of its backup and parity
+    manifest: &'a Manifest,
+    codecs: crate::codec::Codecs,
+}
+
+impl SizePlan<'_> {
+    /// Parity shards each of `vols` volumes gets.
+    fn shard_counts(&self, vols: usize) -> Vec<u64> {
+        let mut counts = vec![0u64; vols];
+        for s in 0..self.stripes {
+            for pi in 0..(self.shards_of)(s) {
+                for c in 0..self.copies {
+                    counts[crate::volume::shard_volume(s, pi, c, vols)] += 1;
+                }
+            }
+        }
+        if let Some((groups, parity)) = self.outer {
+            for g in 0..groups {
+                for pi in 0..parity {
+                    for c in 0..self.copies {
+                        counts[crate::volume::shard_volume(g, pi, c, vols)] += 1;
+                    }
+                }
+            }
+        }
+        counts
+    }
+
+    /// Most bytes a volume of `shards` parity shards takes, and the room
+    /// reserved for the head copy of its index.
+    fn volume_len(&self, shards: u64, backup_len: u64, json_len: usize) -> Result<(u64, u64)> {
+        let entry = crate::volume::encode_entries(&[VolumeEntry {
+            stripe: 0,
+            parity_idx: 0,
+            offset: 0,
+            len: 0,
+            hash: Some([0; 32]),
+            kind: ShardKind::Inner,
+        }])?;
+        let empty = crate::volume::encode_entries(&[])?;
+        let entries = shards + backup_len.div_ceil(self.chunk_size);
+        let raw = empty.len() as u64 + entries * (entry.len() - empty.len()) as u64;
+        let index = self.codecs.index.max_len(usize::try_from(raw)?);
+        let index_copy = crate::index::index_copy_reserve(entries);
+        let tail = backup_len
+            + index as u64
+            + metaparity::block_len(&[index, json_len]) as u64
+            + crate::index::TRAILER_V2_LEN;
+        Ok((self.header_len + index_copy + shards * self.chunk_size + tail, index_copy))
+    }
+
+    /// Fewest volumes (at least one per shard copy) that each stay within the
+    /// cap, and the head copy room of each.
+    fn fit(&self) -> Result<(usize, u64)> {
+        let v2 = crate::manifest_v2::encode(self.manifest)?.len() + MANIFEST_SLACK;
+        let backup_len = match self.codecs.backup {
+            crate::codec::Codec::None => v2,
+            codec => codec.max_len(v2),
+        } as u64;
+        let json_len = crate::manifest::to_json(self.manifest)?.len() + MANIFEST_SLACK;
+        let fits = |shards| -> Result<bool> {
+            Ok(self.volume_len(shards, backup_len, json_len)?.0 <= self.cap)
+        };
+        if !fits(1)? {
+            let (least, _) = self.volume_len(1, backup_len, json_len)?;
+            bail!(
+                "--volume-max-size {} is too small: a volume with one shard takes {} bytes",
+                self.cap,
+                least
+            );
+        }
+        // Most shards a volume may hold
+        let total: u64 = self.shard_counts(1)[0];
+        let (mut lo, mut hi) = (1, total.max(1));
+        while lo < hi {
+            let mid = lo + (hi - lo).div_ceil(2);
+            if fits(mid)? {
+                lo = mid;
+            } else {
+                hi = mid - 1;
+            }
+        }
+        let mut vols = usize::try_from(total.div_ceil(lo))?.max(self.copies).max(1);
+        // Past one volume per shard and stripe, more volumes no longer
+        // spread the shards thinner
+        let most = usize::try_from(total + self.stripes)?;
+        loop {
+            let max = self.shard_counts(vols).into_iter().max().unwrap_or(0);
+            if max <= lo {
+                break;
+            }
+            if vols >= most {
+                bail!(
+                    "--volume-max-size {} is too small: a volume fits {} shard(s), but the \
+                     placement puts {} on some volume however many there are",
+                    self.cap,
+                    lo,
+                    max
+                );
+            }
+            vols += 1;
+        }
+        let max = self.shard_counts(vols).into_iter().max().unwrap_or(0);
+        Ok((vols, self.volume_len(max, backup_len, json_len)?.1))
     }
 }
 
-// Simple header writer (keeps CLI/header semantics consistent)
-fn super_write_simple_header(mut f: &File, k: u32, m: u32, entries: u32) -> Result<()> {
-    let mut buf = Vec::with_capacity(8 + 4 + 4 + 4 + 12);
-    buf.extend_from_slice(b"PARXVOL\0");
-    buf.extend_from_slice(&k.to_le_bytes());
-    buf.extend_from_slice(&m.to_le_bytes());
-    buf.extend_from_slice(&entries.to_le_bytes());
-    buf.extend_from_slice(&[0u8; 12]);
-    f.seek(SeekFrom::Start(0))?;
-    f.write_all(&buf)?;
-    Ok(())
+fn volume_header(
+    cfg: &EncoderConfig,
+    set_ext: &ExtMap,
+    vid: usize,
+    m: u32,
+    entries: u32,
+    index_copy: u64,
+) -> VolumeHeader {
+    let mut ext = set_ext.clone();
+    ext.insert_u32(ext::key::VOLUME_ID, vid as u32);
+    ext.insert_u32(ext::key::CHUNK_SIZE, cfg.chunk_size as u32);
+    // Fixed size, so the header's length is known before its offset is
+    IndexCopy::default().to_ext(&mut ext);
+    let mut hdr = VolumeHeader { k: cfg.stripe_k as u32, m, entries, flags: 0, ext };
+    IndexCopy::reserved(hdr.encoded_len(), index_copy).to_ext(&mut hdr.ext);
+    hdr
 }
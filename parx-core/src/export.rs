@@ -0,0 +1,87 @@
+//! Chunk-table export (`parx export-hashes`) for content-addressed tools that
+//! want to cross-reference protected data without parsing the manifest.
+//!
+//! Chunk ids are ParXive's: BLAKE3 over the chunk zero-padded to the set's
+//! chunk size, which equals the plain BLAKE3 of the bytes for every chunk but
+//! the short last one of a file.
+
+use crate::manifest::Manifest;
+use anyhow::Result;
+use serde::Serialize;
+use std::io::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// One JSON object per chunk: `{"path","offset","len","hash","chunk"}`
+    Jsonl,
+    /// One JSON object per file in the shape of a borg archive item, with
+    /// `chunks` as `[id, size]` pairs
+    Borg,
+    /// One line per chunk, `<id path>\t<path>\t<offset>\t<len>`, where the id
+    /// path follows the casync chunk store layout (`abcd/abcd….cacnk`)
+    Casync,
+}
+
+#[derive(Serialize)]
+struct ChunkLine<'a> {
+    path: &'a str,
+    offset: u64,
+    len: u32,
+    hash: &'a str,
+    chunk: u64,
+}
+
+#[derive(Serialize)]
+struct BorgItem<'a> {
+    #[serde(rename = "type")]
+    kind: &'a str,
+    path: &'a str,
+    size: u64,
+    chunks: Vec<(&'a str, u32)>,
+}
+
+/// Write the chunk table of `mf` to `w`, files in manifest order.
+pub fn export_hashes(mf: &Manifest, format: ExportFormat, w: &mut dyn Write) -> Result<()> {
+    for fe in &mf.files {
+        match format {
+            ExportFormat::Jsonl => {
+                for ch in &fe.chunks {
+                    let line = ChunkLine {
+                        path: &fe.rel_path,
+                        offset: ch.file_offset,
+                        len: ch.len,
+                        hash: &ch.hash_hex,
+                        chunk: ch.idx,
+                    };
+                    serde_json::to_writer(&mut *w, &line)?;
+                    writeln!(w)?;
+                }
+            }
+            ExportFormat::Borg => {
+                let item = BorgItem {
+                    kind: "-",
+                    path: &fe.rel_path,
+                    size: fe.size,
+                    chunks: fe.chunks.iter().map(|c| (c.hash_hex.as_str(), c.len)).collect(),
+                };
+                serde_json::to_writer(&mut *w, &item)?;
+                writeln!(w)?;
+            }
+            ExportFormat::Casync => {
+                for ch in &fe.chunks {
+                    let id = &ch.hash_hex;
+                    writeln!(
+                        w,
+                        "{}/{}.cacnk\t{}\t{}\t{}",
+                        id.get(..4).unwrap_or(id),
+                        id,
+                        fe.rel_path,
+                        ch.file_offset,
+                        ch.len
+                    )?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
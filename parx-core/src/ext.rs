@@ -0,0 +1,208 @@
+//! Forward-compatible extension area shared by manifest v2 and volume headers.
+//!
+//! Encoded as a flat TLV list: `key u16 | len u32 | value[len]` (little endian).
+//! Readers keep every entry but only interpret the keys they know; unknown keys
+//! are carried along untouched so newer writers never break older readers.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+/// Upper bound on a single extension value; anything larger is treated as garbage.
+pub const MAX_EXT_VALUE: usize = 1 << 20;
+
+/// Well-known keys. Values below 0x8000 are reserved for ParXive itself;
+/// 0x8000.. are free for third-party tools.
+pub mod key {
+    /// u32 LE: id of the volume a header belongs to.
+    pub const VOLUME_ID: u16 = 0x0001;
+    /// u32 LE: chunk size the volume's shards were cut with.
+    pub const CHUNK_SIZE: u16 = 0x0002;
+    /// u32 LE: page size the chunks are aligned to (manifest; `create --align`).
+    pub const PAGE_ALIGN: u16 = 0x0003;
+    /// u32 LE: non-zero when chunks were cut at media container boundaries.
+    pub const MEDIA_ALIGN: u16 = 0x0004;
+    /// UTF-8: user label of the set (`create --label`), repeated in every volume.
+    pub const LABEL: u16 = 0x0005;
+    /// UTF-8: free-text creation notes.
+    pub const NOTES: u16 = 0x0006;
+    /// UTF-8: contact information of the owner.
+    pub const CONTACT: u16 = 0x0007;
+    /// u32 LE: members of outer groups, 0 = inner parity only, 1 = data and parity.
+    pub const OUTER_SCOPE: u16 = 0x0008;
+    /// u32 LE: non-zero when the files came from a list (`create --files-from`).
+    pub const FILE_LIST: u16 = 0x0009;
+    /// u32 LE: Galois field of the inner RS code, 16 = GF(2^16); absent = GF(2^8).
+    pub const RS_FIELD: u16 = 0x000A;
+    /// UTF-8: codec of the volume index (`create --index-codec`); absent = zstd.
+    pub const INDEX_CODEC: u16 = 0x000B;
+    /// UTF-8: codec of the manifest backup (`create --backup-codec`); absent = none.
+    pub const BACKUP_CODEC: u16 = 0x000C;
+    /// Manifest only: 32-byte ed25519 public key followed by the 64-byte
+    /// signature over the manifest (`create --sign-key`, see `sign`).
+    pub const MANIFEST_SIG: u16 = 0x000D;
+    /// Manifest only, u64 LE: chunk locations that share the stripe slot of an
+    /// identical chunk (`create --dedup`); absent = every location has its own.
+    pub const DEDUP: u16 = 0x000E;
+    /// Sub-manifest only, UTF-8: the top-level directory it covers
+    /// (`create --sub-manifests`, see `submanifest`).
+    pub const SUBSET: u16 = 0x000F;
+    /// Manifest only, UTF-8: one `<merkle root hex> <dir>` line per
+    /// sub-manifest of the set.
+    pub const SUB_MANIFESTS: u16 = 0x0010;
+    /// Volume only, 36 bytes: where the head copy of the index lives and what
+    /// it holds (see `index::IndexCopy`).
+    pub const INDEX_COPY: u16 = 0x0011;
+    /// 16 bytes: random id of the set (`manifest::SetId`), in the manifest
+    /// and every volume header; absent on sets made before it existed.
+    pub const SET_ID: u16 = 0x0012;
+    /// Manifest only, u64 LE: byte size no volume exceeds
+    /// (`create --volume-max-size`); its shards rotate across the volumes
+    /// stripe by stripe. Absent = inner shard `i` goes to volume `i`.
+    pub const VOLUME_MAX_SIZE: u16 = 0x0013;
+    /// Manifest only, UTF-8: template the volume files are named by
+    /// (`create --volume-name`, see `volume::check_name_template`).
+    pub const VOLUME_NAME: u16 = 0x0014;
+    /// Manifest only, UTF-8: one line per directory besides the parity dir
+    /// that holds volumes of the set (`create --output-dirs`); volume `v`
+    /// lives in dir `v % (n + 1)`, the parity dir being dir 0.
+    pub const VOLUME_DIRS: u16 = 0x0015;
+    /// Manifest only, UTF-8: how chunk hashes were computed; `keyed-blake3
+    /// <key id>` for sets made with `create --hash-key` (see
+    /// `manifest::HashKey`). Absent = plain BLAKE3.
+    pub const HASH_MODE: u16 = 0x0016;
+    /// u64 LE: generation of the set, in the manifest and every volume
+    /// header. `create` starts at one past the manifest it replaces,
+    /// `update`, `forget` and `rebalance` count it up as they rewrite the
+    /// volumes; a volume of the same set but another generation is stale
+    /// (see `manifest::SetTag`). Absent on sets made before it existed.
+    pub const GENERATION: u16 = 0x0017;
+    /// Manifest only, UTF-8: failure domains of the volumes given to
+    /// `create --volume-groups`, one `LEVEL=GROUP:IDS,...` per line (see
+    /// `domains::Level`).
+    pub const VOLUME_GROUPS: u16 = 0x0018;
+    /// Manifest only: stripes of critical files in a set with outer groups,
+    /// as runs of `first(8) count(8)` (u64 LE); their extra inner parity
+    /// shards are outer members too (see `outer`).
+    pub const CRITICAL_STRIPES: u16 = 0x0019;
+    /// First key available for vendor/private use.
+    pub const PRIVATE_BASE: u16 = 0x8000;
+
+    /// Name of a well-known key, for dumps.
+    pub fn name(key: u16) -> Option<&'static str> {
+        Some(match key {
+            VOLUME_ID => "volume_id",
+            CHUNK_SIZE => "chunk_size",
+            PAGE_ALIGN => "page_align",
+            MEDIA_ALIGN => "media_align",
+            LABEL => "label",
+            NOTES => "notes",
+            CONTACT => "contact",
+            OUTER_SCOPE => "outer_scope",
+            FILE_LIST => "file_list",
+            RS_FIELD => "rs_field",
+            INDEX_CODEC => "index_codec",
+            BACKUP_CODEC => "backup_codec",
+            MANIFEST_SIG => "manifest_sig",
+            DEDUP => "dedup",
+            SUBSET => "subset",
+            SUB_MANIFESTS => "sub_manifests",
+            INDEX_COPY => "index_copy",
+            SET_ID => "set_id",
+            VOLUME_MAX_SIZE => "volume_max_size",
+            VOLUME_NAME => "volume_name",
+            VOLUME_DIRS => "volume_dirs",
+            HASH_MODE => "hash_mode",
+            GENERATION => "generation",
+            VOLUME_GROUPS => "volume_groups",
+            CRITICAL_STRIPES => "critical_stripes",
+            k if k >= PRIVATE_BASE => "private",
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct ExtMap(BTreeMap<u16, Vec<u8>>);
+
+impl ExtMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn insert(&mut self, key: u16, value: Vec<u8>) -> Option<Vec<u8>> {
+        self.0.insert(key, value)
+    }
+
+    pub fn get(&self, key: u16) -> Option<&[u8]> {
+        self.0.get(&key).map(|v| v.as_slice())
+    }
+
+    pub fn remove(&mut self, key: u16) -> Option<Vec<u8>> {
+        self.0.remove(&key)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &[u8])> {
+        self.0.iter().map(|(k, v)| (*k, v.as_slice()))
+    }
+
+    pub fn insert_u32(&mut self, key: u16, v: u32) {
+        self.insert(key, v.to_le_bytes().to_vec());
+    }
+
+    /// Read a u32 value; `None` if absent or not exactly 4 bytes.
+    pub fn get_u32(&self, key: u16) -> Option<u32> {
+        self.get(key).and_then(|v| v.try_into().ok()).map(u32::from_le_bytes)
+    }
+
+    pub fn insert_u64(&mut self, key: u16, v: u64) {
+        self.insert(key, v.to_le_bytes().to_vec());
+    }
+
+    /// Read a u64 value; `None` if absent or not exactly 8 bytes.
+    pub fn get_u64(&self, key: u16) -> Option<u64> {
+        self.get(key).and_then(|v| v.try_into().ok()).map(u64::from_le_bytes)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        for (k, v) in &self.0 {
+            out.extend_from_slice(&k.to_le_bytes());
+            out.extend_from_slice(&(v.len() as u32).to_le_bytes());
+            out.extend_from_slice(v);
+        }
+        out
+    }
+
+    /// Parse a TLV area. Truncated or oversized entries are an error; a
+    /// repeated key keeps the last value.
+    pub fn decode(mut buf: &[u8]) -> Result<Self> {
+        let mut map = BTreeMap::new();
+        while !buf.is_empty() {
+            if buf.len() < 6 {
+                bail!("extension area truncated in entry header");
+            }
+            let k = u16::from_le_bytes([buf[0], buf[1]]);
+            let len = u32::from_le_bytes([buf[2], buf[3], buf[4], buf[5]]) as usize;
+            if len > MAX_EXT_VALUE {
+                bail!("extension {:#06x} too large ({} bytes)", k, len);
+            }
+            if buf.len() < 6 + len {
+                bail!("extension {:#06x} truncated", k);
+            }
+            map.insert(k, buf[6..6 + len].to_vec());
+            buf = &buf[6 + len..];
+        }
+        Ok(Self(map))
+    }
+}
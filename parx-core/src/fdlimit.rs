@@ -0,0 +1,78 @@
+//! Bound on the files a parallel pass holds open at once (`--max-open-files`).
+//! Hashing a huge tree on many threads, with stalled reads left to their
+//! helper threads by the watchdog, can otherwise run into `ulimit -n`. When
+//! the process hits the limit anyway (EMFILE/ENFILE), opens wait for another
+//! file to close instead of counting the file as unreadable.
+
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// How long an open keeps retrying while the process is out of descriptors.
+const EXHAUSTED_WAIT: Duration = Duration::from_secs(10);
+
+/// Counting semaphore over open files, shared by the workers of one pass.
+#[derive(Clone)]
+pub struct FdLimit(Arc<(Mutex<usize>, Condvar)>);
+
+/// One open file's share of the limit, given back on drop.
+pub struct Permit(FdLimit);
+
+impl FdLimit {
+    /// At most `max` files open at once; `None` only waits on exhaustion.
+    pub fn new(max: Option<usize>) -> Self {
+        FdLimit(Arc::new((Mutex::new(max.unwrap_or(usize::MAX).max(1)), Condvar::new())))
+    }
+
+    /// Wait for a free slot.
+    pub fn acquire(&self) -> Permit {
+        let (free, cv) = &*self.0;
+        let mut free = free.lock().unwrap_or_else(|e| e.into_inner());
+        while *free == 0 {
+            free = cv.wait(free).unwrap_or_else(|e| e.into_inner());
+        }
+        *free -= 1;
+        Permit(self.clone())
+    }
+
+    /// Open `path` for reading within the limit, retrying while the process
+    /// is out of descriptors.
+    pub fn open(&self, path: &Path) -> io::Result<(File, Permit)> {
+        let permit = self.acquire();
+        let deadline = Instant::now() + EXHAUSTED_WAIT;
+        loop {
+            match File::open(path) {
+                Ok(f) => return Ok((f, permit)),
+                Err(e) if is_exhausted(&e) && Instant::now() < deadline => {
+                    // Another worker closing its file frees a descriptor
+                    let (free, cv) = &*self.0;
+                    let free = free.lock().unwrap_or_else(|e| e.into_inner());
+                    let _ = cv.wait_timeout(free, Duration::from_millis(20));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let (free, cv) = &*(self.0).0;
+        *free.lock().unwrap_or_else(|e| e.into_inner()) += 1;
+        cv.notify_all();
+    }
+}
+
+/// The process (EMFILE) or the system (ENFILE) has no descriptor to spare.
+pub fn is_exhausted(e: &io::Error) -> bool {
+    #[cfg(unix)]
+    let codes = [23, 24];
+    // ERROR_TOO_MANY_OPEN_FILES
+    #[cfg(windows)]
+    let codes = [4];
+    #[cfg(not(any(unix, windows)))]
+    let codes: [i32; 0] = [];
+    e.raw_os_error().is_some_and(|c| codes.contains(&c))
+}
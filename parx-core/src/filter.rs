@@ -0,0 +1,196 @@
+//! Bloom filter over every chunk hash of a set (`<parity dir>/filter.bin`).
+//!
+//! Answers "is this data part of the protected set?" without loading the
+//! manifest: `parx contains` chunks an arbitrary file the same way `create`
+//! does and probes the filter. A hit is probable (about 1% false positives),
+//! a miss is certain.
+
+use anyhow::{bail, Context, Result};
+use crc32fast::Hasher as Crc32;
+use serde::Serialize;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+pub const FILTER_FILE: &str = "filter.bin";
+const FILTER_MAGIC: &[u8; 8] = b"PARXBLM\0";
+const FILTER_VERSION: u8 = 1;
+/// ~1% false-positive rate with 7 probes
+const BITS_PER_ITEM: u64 = 10;
+const PROBES: u8 = 7;
+const HEADER_LEN: usize = 8 + 1 + 1 + 2 + 4 + 8 + 8;
+
+pub struct ChunkFilter {
+    chunk_size: u32,
+    /// Chunks of media files were cut at container boundaries
+    media_align: bool,
+    probes: u8,
+    items: u64,
+    bits: Vec<u8>,
+}
+
+impl ChunkFilter {
+    /// Empty filter sized for `items` chunk hashes.
+    pub fn with_capacity(chunk_size: usize, items: u64) -> Self {
+        let nbits = (items.max(1) * BITS_PER_ITEM).next_multiple_of(8);
+        Self {
+            chunk_size: chunk_size as u32,
+            media_align: false,
+            probes: PROBES,
+            items: 0,
+            bits: vec![0; (nbits / 8) as usize],
+        }
+    }
+
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size as usize
+    }
+
+    pub fn items(&self) -> u64 {
+        self.items
+    }
+
+    /// Bit positions for a chunk hash (double hashing over the digest halves).
+    fn positions(&self, hash: &[u8; 32]) -> impl Iterator<Item = usize> {
+        let nbits = self.bits.len() as u64 * 8;
+        let h1 = u64::from_le_bytes(hash[0..8].try_into().unwrap());
+        let h2 = u64::from_le_bytes(hash[8..16].try_into().unwrap()) | 1;
+        (0..self.probes as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % nbits) as usize)
+    }
+
+    pub fn insert(&mut self, hash: &[u8; 32]) {
+        for p in self.positions(hash).collect::<Vec<_>>() {
+            self.bits[p / 8] |= 1 << (p % 8);
+        }
+        self.items += 1;
+    }
+
+    /// `false`: definitely not in the set; `true`: probably in it.
+    pub fn may_contain(&self, hash: &[u8; 32]) -> bool {
+        self.positions(hash).all(|p| self.bits[p / 8] & (1 << (p % 8)) != 0)
+    }
+
+    /// Build from the chunk hashes recorded in a manifest.
+    pub fn from_manifest(mf: &crate::manifest::Manifest) -> Result<Self> {
+        let mut f = Self::with_capacity(mf.chunk_size, mf.total_chunks);
+        f.media_align = mf.ext.get_u32(crate::ext::key::MEDIA_ALIGN).is_some_and(|v| v != 0);
+        for fe in &mf.files {
+            for ch in &fe.chunks {
+                let raw = crate::hex::unhex(&ch.hash_hex)
+                    .with_context(|| format!("chunk {} hash", ch.idx))?;
+                let h: [u8; 32] =
+                    raw.try_into().map_err(|_| anyhow::anyhow!("chunk {} hash length", ch.idx))?;
+                f.insert(&h);
+            }
+        }
+        Ok(f)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(HEADER_LEN + self.bits.len() + 4);
+        out.extend_from_slice(FILTER_MAGIC);
+        out.push(FILTER_VERSION);
+        out.push(self.probes);
+        out.push(self.media_align as u8);
+        out.push(0);
+        out.extend_from_slice(&self.chunk_size.to_le_bytes());
+        out.extend_from_slice(&self.items.to_le_bytes());
+        out.extend_from_slice(&(self.bits.len() as u64).to_le_bytes());
+        out.extend_from_slice(&self.bits);
+        let mut h = Crc32::new();
+        h.update(&out);
+        out.extend_from_slice(&h.finalize().to_le_bytes());
+        out
+    }
+
+    pub fn from_bytes(buf: &[u8]) -> Result<Self> {
+        if buf.len() < HEADER_LEN + 4 || &buf[0..8] != FILTER_MAGIC {
+            bail!("not a ParXive chunk filter");
+        }
+        if buf[8] != FILTER_VERSION {
+            bail!("unsupported filter version {}", buf[8]);
+        }
+        let (body, crc) = buf.split_at(buf.len() - 4);
+        let mut h = Crc32::new();
+        h.update(body);
+        if h.finalize().to_le_bytes() != crc {
+            bail!("filter CRC mismatch");
+        }
+        let probes = buf[9];
+        let chunk_size = u32::from_le_bytes(buf[12..16].try_into().unwrap());
+        let items = u64::from_le_bytes(buf[16..24].try_into().unwrap());
+        let nbytes = u64::from_le_bytes(buf[24..32].try_into().unwrap());
+        if probes == 0
+            || chunk_size == 0
+            || nbytes == 0
+            || nbytes != (body.len() - HEADER_LEN) as u64
+        {
+            bail!("corrupt filter header");
+        }
+        let media_align = buf[10] & 1 != 0;
+        Ok(Self { chunk_size, media_align, probes, items, bits: body[HEADER_LEN..].to_vec() })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, self.to_bytes()).with_context(|| format!("write {:?}", path))
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let buf = std::fs::read(path).with_context(|| format!("read {:?}", path))?;
+        Self::from_bytes(&buf).with_context(|| format!("parse {:?}", path))
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ContainsReport {
+    pub chunks: u64,
+    /// Chunks whose hash is probably in the set
+    pub found: u64,
+    pub bytes: u64,
+    pub bytes_found: u64,
+}
+
+impl ContainsReport {
+    /// Every chunk (probably) present; an empty file counts as contained.
+    pub fn all(&self) -> bool {
+        self.found == self.chunks
+    }
+}
+
+/// Chunk `path` like `create` does (zero-padded tail, media boundaries when
+/// the set was made with them) and probe each chunk.
+pub fn contains(filter: &ChunkFilter, path: &Path) -> Result<ContainsReport> {
+    let mut f = File::open(path).with_context(|| format!("open {:?}", path))?;
+    let size = f.metadata()?.len();
+    let cs = filter.chunk_size();
+    let (_, cuts) = crate::encode::media_cuts(path, filter.media_align);
+    let mut rep = ContainsReport::default();
+    let mut buf = vec![0u8; cs];
+    let mut offset = 0u64;
+    while offset < size {
+        let want = crate::encode::chunk_len(offset, size - offset, cs, &cuts);
+        let mut n = 0;
+        while n < want {
+            let r = f.read(&mut buf[n..want])?;
+            if r == 0 {
+                break;
+            }
+            n += r;
+        }
+        if n == 0 {
+            break;
+        }
+        buf[n..].fill(0);
+        rep.chunks += 1;
+        rep.bytes += n as u64;
+        if filter.may_contain(blake3::hash(&buf).as_bytes()) {
+            rep.found += 1;
+            rep.bytes_found += n as u64;
+        }
+        offset += n as u64;
+        if n < want {
+            break;
+        }
+    }
+    Ok(rep)
+}
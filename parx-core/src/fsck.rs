@@ -0,0 +1,355 @@
+//! Rebuild the index of a volume whose index, head copy and trailer are all
+//! damaged (`parx fsck`). The shards themselves usually survive. The shards
+//! the volume should hold follow from the manifest and the placement rules;
+//! they are regenerated from the source tree (and taken from replicas in the
+//! other volumes) and found in the volume by their hashes, first at
+//! `chunk_size` strides after the header, then with a rolling checksum for
+//! those that sit off the stride (see [`crate::resync`]). The new manifest
+//! backup, index and trailer are appended: bytes nothing matched are left in
+//! place, so a later run with more sources can still find them.
+
+use crate::codec::Codecs;
+use crate::ext::key;
+use crate::heal::SourceSlots;
+use crate::index::{
+    read_index, read_trailer, write_index_and_trailer_with, IndexCopy, IndexLimits,
+};
+use crate::manifest::{self, ChunkRef, Manifest};
+use crate::merkle::ChunkHasher;
+use crate::metaparity::{MetaParity, TAG_MANIFEST};
+use crate::outer::OuterLayout;
+use crate::path_safety::PathPolicy;
+use crate::rs_codec::{RsCodec, RsField};
+use crate::volume::{check_features, ReadMode, ShardKind, VolumeEntry, VolumeHeader};
+use anyhow::{bail, Context, Result};
+use fs2::FileExt;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FsckReport {
+    pub volume: String,
+    /// The index was readable, so nothing was changed
+    pub index_intact: bool,
+    /// Shards found and listed in the rebuilt index
+    pub shards_found: u64,
+    /// Shards the volume should hold that were not found: rotten, or not
+    /// identifiable from the sources given
+    pub shards_missing: u64,
+    /// Stripes whose source chunks are damaged, so their parity could not be
+    /// regenerated (run `repair` first, or give fsck a copy of the data)
+    pub stripes_unreadable: u64,
+    pub index_rewritten: bool,
+}
+
+impl crate::report::Report for FsckReport {
+    const KIND: &'static str = "fsck";
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FsckOptions {
+    /// Source tree to regenerate shards from; without it only replicas in
+    /// the other volumes identify shards
+    pub root: Option<PathBuf>,
+    pub policy: PathPolicy,
+    /// Rebuild even when the index reads fine
+    pub force: bool,
+}
+
+/// A shard the volume should hold.
+struct Wanted {
+    kind: ShardKind,
+    stripe: u64,
+    pi: u16,
+    hash: [u8; 32],
+    weak: u32,
+    /// Part of the stripe's configured parity (critical-file extras are
+    /// looked for, but not missed)
+    counted: bool,
+}
+
+/// Rebuild the index of `volume` from the shards found in it.
+pub fn fsck(volume: &Path, manifest_path: &Path, opts: &FsckOptions) -> Result<FsckReport> {
+    let (mf, _) = manifest::load(manifest_path)?;
+    let parity_dir = volume.parent().unwrap_or(Path::new("."));
+    let name = volume.file_name().unwrap_or_default().to_string_lossy().to_string();
+    let mut rep = FsckReport { volume: name.clone(), ..Default::default() };
+    // The index is rewritten in place, as by heal and repair
+    let lock_file =
+        File::create(parity_dir.join(".parx.repair.lock")).context("create global repair lock")?;
+    lock_file.try_lock_exclusive().context("acquire global repair lock")?;
+
+    let mut f = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(volume)
+        .with_context(|| format!("open {:?}", volume))?;
+    let mut hdr = VolumeHeader::read_from(&f)
+        .with_context(|| format!("read the header of {:?} (fsck needs it intact)", volume))?;
+    check_features(hdr.flags, ReadMode::Strict)
+        .with_context(|| format!("refusing to rebuild {:?}", volume))?;
+    if !opts.force {
+        let index = read_trailer(&mut f)
+            .and_then(|(off, len, crc)| read_index(&mut f, off, len, crc, &IndexLimits::default()));
+        if index.is_ok() {
+            rep.index_intact = true;
+            return Ok(rep);
+        }
+    }
+    let Some(vid) = hdr.ext.get_u32(key::VOLUME_ID) else {
+        bail!("{:?} does not record its volume id", volume);
+    };
+    let cs = mf.chunk_size;
+    let data_start = IndexCopy::from_ext(&hdr.ext).map_or(hdr.encoded_len(), |c| c.off + c.cap);
+
+    let mut wanted: HashMap<(ShardKind, u64, u16), Wanted> = HashMap::new();
+    // Replicas may sit in any of the set's dirs (`create --output-dirs`)
+    let mut dirs = vec![parity_dir.to_path_buf()];
+    if !mf.volume_dirs().is_empty() {
+        let mut seen: Vec<PathBuf> = dirs.iter().filter_map(|d| d.canonicalize().ok()).collect();
+        for dir in std::iter::once(mf.parity_dir_at(manifest_path)).chain(mf.volume_dirs()) {
+            match dir.canonicalize() {
+                Ok(c) if !seen.contains(&c) => {
+                    seen.push(c);
+                    dirs.push(dir);
+                }
+                _ => {}
+            }
+        }
+    }
+    for dir in &dirs {
+        replicas(&mf, dir, &name, vid as usize, &mut wanted);
+    }
+    if let Some(root) = &opts.root {
+        mf.require_plain_hashes("fsck --root")?;
+        rep.stripes_unreadable = regenerate(&mf, root, opts.policy, vid as usize, &mut wanted)?;
+    }
+
+    // Shards at their stride, then the rest wherever they are
+    let mut found = scan_aligned(volume, data_start, cs, &wanted)?;
+    let taken: Vec<ChunkRef> = found.values().map(|&off| chunk_ref(off, cs, None, "")).collect();
+    let keys: Vec<_> = wanted.keys().filter(|k| !found.contains_key(k)).copied().collect();
+    if !keys.is_empty() {
+        let refs: Vec<ChunkRef> = keys
+            .iter()
+            .enumerate()
+            .map(|(i, k)| {
+                let w = &wanted[k];
+                chunk_ref(i as u64, cs, Some(w.weak), &blake3::Hash::from(w.hash).to_hex())
+            })
+            .collect();
+        let refs_of: Vec<&ChunkRef> = refs.iter().collect();
+        let taken_of: Vec<&ChunkRef> = taken.iter().collect();
+        let moved = crate::resync::locate(
+            volume,
+            &refs_of,
+            &taken_of,
+            &ChunkHasher::new(cs, None),
+            &|| (),
+        )?;
+        for (i, off) in moved {
+            if off >= data_start {
+                found.insert(keys[i as usize], off);
+            }
+        }
+    }
+    rep.shards_found = found.len() as u64;
+    rep.shards_missing =
+        wanted.iter().filter(|(k, w)| w.counted && !found.contains_key(k)).count() as u64;
+
+    let mut entries: Vec<VolumeEntry> = found
+        .iter()
+        .map(|(k, &offset)| VolumeEntry {
+            stripe: k.1,
+            parity_idx: k.2,
+            offset,
+            len: cs as u32,
+            hash: Some(wanted[k].hash),
+            kind: k.0,
+        })
+        .collect();
+    entries.sort_by_key(|e| e.offset);
+    let codecs = Codecs::from_ext(&hdr.ext);
+    let end = f.metadata()?.len();
+    crate::manifest_backup::append(&mut f, &mut entries, end, &mf, codecs.backup)?;
+    // Header first: writing the index fills in its head copy record
+    hdr.entries = crate::volume::entry_count(&entries)?;
+    hdr.write_to(&f)?;
+    let mf_parity = MetaParity::protect(&crate::manifest::to_json(&mf)?)?;
+    write_index_and_trailer_with(&f, &entries, &[(TAG_MANIFEST, &mf_parity)], codecs.index)?;
+    f.sync_all()?;
+    rep.index_rewritten = true;
+    crate::volparity::refresh(parity_dir)?;
+    Ok(rep)
+}
+
+fn chunk_ref(offset: u64, cs: usize, weak: Option<u32>, hash: &str) -> ChunkRef {
+    ChunkRef { idx: 0, file_offset: offset, len: cs as u32, hash_hex: hash.to_string(), weak }
+}
+
+/// Whether parity shard `pi` of kind `kind` of stripe (or outer group)
+/// `base` has a copy on volume `vid`.
+fn placed_on(kind: ShardKind, base: u64, pi: usize, mf: &Manifest, vid: usize) -> bool {
+    let vols = mf.volumes.max(1);
+    (0..mf.shard_copies.max(1)).any(|c| match kind {
+        ShardKind::Inner => mf.inner_shard_volume(base, pi, c) == vid,
+        _ => crate::volume::shard_volume(base, pi, c, vols) == vid,
+    })
+}
+
+fn want(wanted: &mut HashMap<(ShardKind, u64, u16), Wanted>, w: Wanted) {
+    wanted.entry((w.kind, w.stripe, w.pi)).or_insert(w);
+}
+
+/// Shards of `vid` that the other volumes of the set hold replicas of.
+fn replicas(
+    mf: &Manifest,
+    dir: &Path,
+    own: &str,
+    vid: usize,
+    wanted: &mut HashMap<(ShardKind, u64, u16), Wanted>,
+) {
+    let Ok(rd) = std::fs::read_dir(dir) else { return };
+    for p in rd.filter_map(|e| e.ok().map(|e| e.path())) {
+        let is_other = p.extension().is_some_and(|e| e == "parxv")
+            && p.file_name().is_some_and(|n| n.to_string_lossy() != own);
+        let Some(mut f) = is_other.then(|| File::open(&p).ok()).flatten() else { continue };
+        let Ok(entries) = read_trailer(&mut f)
+            .and_then(|(off, len, crc)| read_index(&mut f, off, len, crc, &IndexLimits::default()))
+        else {
+            continue;
+        };
+        for e in entries {
+            if !matches!(e.kind, ShardKind::Inner | ShardKind::Outer) {
+                continue;
+            }
+            let placed = placed_on(e.kind, e.stripe, e.parity_idx as usize, mf, vid);
+            let Some(hash) = e.hash.filter(|_| placed) else {
+                continue;
+            };
+            let mut buf = vec![0u8; e.len as usize];
+            let read = std::io::Seek::seek(&mut f, std::io::SeekFrom::Start(e.offset))
+                .and_then(|_| f.read_exact(&mut buf));
+            if read.is_err() || *blake3::hash(&buf).as_bytes() != hash || buf.len() != mf.chunk_size
+            {
+                continue;
+            }
+            let counted = e.kind == ShardKind::Outer
+                || (e.parity_idx as usize) < mf.parity_shards_of(e.stripe);
+            let weak = crate::resync::weak(&buf);
+            let (kind, stripe, pi) = (e.kind, e.stripe, e.parity_idx);
+            want(wanted, Wanted { kind, stripe, pi, hash, weak, counted });
+        }
+    }
+}
+
+/// Regenerate the shards of `vid` from the source tree; returns how many
+/// stripes could not be read.
+fn regenerate(
+    mf: &Manifest,
+    root: &Path,
+    policy: PathPolicy,
+    vid: usize,
+    wanted: &mut HashMap<(ShardKind, u64, u16), Wanted>,
+) -> Result<u64> {
+    let geo = mf.geometry();
+    let k = geo.k;
+    let m_max = mf.max_parity_shards();
+    if m_max == 0 {
+        return Ok(0);
+    }
+    let source = SourceSlots::new(mf, root, policy)?;
+    let rs = RsCodec::with_field(RsField::from_ext(&mf.ext), k, m_max).context("init RS")?;
+    // Data and inner parity of a stripe, `k + m_max` shards
+    let encode = |s: u64| -> Option<Vec<Vec<u8>>> {
+        let mut bufs = source.stripe(s)?;
+        bufs.extend((0..m_max).map(|_| vec![0u8; mf.chunk_size]));
+        let mut shards: Vec<&mut [u8]> = bufs.iter_mut().map(|b| b.as_mut_slice()).collect();
+        rs.encode(&mut shards).ok()?;
+        Some(bufs)
+    };
+    let shard = |kind, stripe, pi: usize, buf: &[u8], counted| Wanted {
+        kind,
+        stripe,
+        pi: pi as u16,
+        hash: *blake3::hash(buf).as_bytes(),
+        weak: crate::resync::weak(buf),
+        counted,
+    };
+    let inner: Vec<Option<Vec<Wanted>>> = (0..geo.stripes)
+        .into_par_iter()
+        .map(|s| {
+            let bufs = encode(s)?;
+            let plain = mf.parity_shards_of(s);
+            let mine = (0..m_max).filter(|&pi| placed_on(ShardKind::Inner, s, pi, mf, vid));
+            Some(mine.map(|pi| shard(ShardKind::Inner, s, pi, &bufs[k + pi], pi < plain)).collect())
+        })
+        .collect();
+    let mut unreadable = 0;
+    for ws in inner {
+        match ws {
+            Some(ws) => ws.into_iter().for_each(|w| want(wanted, w)),
+            None => unreadable += 1,
+        }
+    }
+    if let Some(layout) = OuterLayout::from_manifest(mf) {
+        let groups = geo.stripes.div_ceil(layout.group as u64);
+        let outer: Vec<Vec<Wanted>> = (0..groups)
+            .into_par_iter()
+            .map(|g| {
+                let members: Option<Vec<Vec<Vec<u8>>>> = layout
+                    .stripes_of(g, geo.stripes)
+                    .map(|s| {
+                        encode(s).map(|mut b| {
+                            b.truncate(k + layout.parity_of(s));
+                            b
+                        })
+                    })
+                    .collect();
+                let Some(Ok(shards)) = members.map(|m| layout.encode(&m)) else {
+                    return Vec::new();
+                };
+                let mine = shards
+                    .iter()
+                    .enumerate()
+                    .filter(|(pi, _)| placed_on(ShardKind::Outer, g, *pi, mf, vid));
+                mine.map(|(pi, b)| shard(ShardKind::Outer, g, pi, b, true)).collect()
+            })
+            .collect();
+        outer.into_iter().flatten().for_each(|w| want(wanted, w));
+    }
+    Ok(unreadable)
+}
+
+/// Where each wanted shard sits at a `chunk_size` stride from `start`.
+fn scan_aligned(
+    volume: &Path,
+    start: u64,
+    cs: usize,
+    wanted: &HashMap<(ShardKind, u64, u16), Wanted>,
+) -> Result<HashMap<(ShardKind, u64, u16), u64>> {
+    let by_hash: HashMap<[u8; 32], Vec<(ShardKind, u64, u16)>> =
+        wanted.iter().fold(HashMap::new(), |mut m, (k, w)| {
+            m.entry(w.hash).or_default().push(*k);
+            m
+        });
+    let mut f = File::open(volume).with_context(|| format!("open {:?}", volume))?;
+    std::io::Seek::seek(&mut f, std::io::SeekFrom::Start(start))?;
+    let mut r = BufReader::with_capacity(1 << 20, f);
+    let mut found = HashMap::new();
+    let mut buf = vec![0u8; cs];
+    let mut off = start;
+    while r.read_exact(&mut buf).is_ok() {
+        let h = *blake3::hash(&buf).as_bytes();
+        if let Some(k) = by_hash.get(&h).and_then(|ks| ks.iter().find(|k| !found.contains_key(*k)))
+        {
+            found.insert(*k, off);
+        }
+        off += cs as u64;
+    }
+    Ok(found)
+}
@@ -0,0 +1,210 @@
+//! Filesystem health hints for `audit --fs-hints`.
+//!
+//! Hashing every chunk dominates audit time on large, mostly healthy arrays.
+//! Where the filesystem checksums file data itself (btrfs with data checksums,
+//! ZFS, or a file with fs-verity enabled), a corrupted block fails its read
+//! with EIO and shows up in the filesystem's scrub, so a file that was
+//! verified once and has not changed since can be trusted without re-hashing.
+//! "Not changed" is judged by a [`FileStamp`]: size, inode, mtime, ctime and a
+//! digest of the file's extent map (FIEMAP), which also catches rewrites that
+//! restored the timestamps. Stamps of files that hashed clean are kept in
+//! `<parity dir>/fshints.json`; anything else is hashed as usual.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::Path;
+
+pub const HINTS_FILE: &str = "fshints.json";
+
+/// What identifies a file's content without reading it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct FileStamp {
+    pub size: u64,
+    pub ino: u64,
+    pub mtime_ns: i128,
+    pub ctime_ns: i128,
+    /// BLAKE3 over the FIEMAP extents (logical, physical, length, flags);
+    /// `None` where the filesystem does not support FIEMAP
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extents: Option<String>,
+}
+
+/// Stamps of files that last hashed clean, keyed by manifest path.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Hints {
+    pub files: BTreeMap<String, FileStamp>,
+}
+
+impl Hints {
+    /// The hints in `parity_dir`; empty when there are none or they do not parse.
+    pub fn load(parity_dir: &Path) -> Self {
+        std::fs::read(parity_dir.join(HINTS_FILE))
+            .ok()
+            .and_then(|b| serde_json::from_slice(&b).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, parity_dir: &Path) -> Result<()> {
+        let path = parity_dir.join(HINTS_FILE);
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec(self)?)
+            .with_context(|| format!("write {:?}", tmp))?;
+        std::fs::rename(&tmp, &path).with_context(|| format!("rename {:?}", tmp))
+    }
+}
+
+/// The stamp of an open file; `None` on platforms without inode/ctime.
+pub fn stamp(f: &File) -> Option<FileStamp> {
+    sys::stamp(f)
+}
+
+/// Name of the integrity layer that checksums `f`'s data on every read, if
+/// any: `"btrfs"`, `"zfs"` or `"fs-verity"`. Always `None` off Linux.
+pub fn checksummed_by(f: &File, path: &Path) -> Option<&'static str> {
+    sys::checksummed_by(f, path)
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use super::FileStamp;
+    use std::fs::File;
+    use std::os::unix::fs::MetadataExt;
+    use std::os::unix::io::AsRawFd;
+    use std::path::Path;
+
+    const BTRFS_SUPER_MAGIC: u32 = 0x9123_683e;
+    const ZFS_SUPER_MAGIC: u32 = 0x2fc1_2fc1;
+    const FS_NOCOW_FL: libc::c_long = 0x0080_0000;
+    const FS_VERITY_FL: libc::c_long = 0x0010_0000;
+    // _IOWR('f', 11, struct fiemap)
+    const FS_IOC_FIEMAP: u32 = 0xC020_660B;
+    const FIEMAP_FLAG_SYNC: u32 = 0x1;
+    const FIEMAP_EXTENT_LAST: u32 = 0x1;
+    const EXTENTS_PER_CALL: usize = 64;
+
+    #[repr(C)]
+    #[derive(Clone, Copy, Default)]
+    struct FiemapExtent {
+        logical: u64,
+        physical: u64,
+        length: u64,
+        reserved64: [u64; 2],
+        flags: u32,
+        reserved: [u32; 3],
+    }
+
+    #[repr(C)]
+    struct Fiemap {
+        start: u64,
+        length: u64,
+        flags: u32,
+        mapped_extents: u32,
+        extent_count: u32,
+        reserved: u32,
+        extents: [FiemapExtent; EXTENTS_PER_CALL],
+    }
+
+    pub fn stamp(f: &File) -> Option<FileStamp> {
+        let md = f.metadata().ok()?;
+        Some(FileStamp {
+            size: md.len(),
+            ino: md.ino(),
+            mtime_ns: md.mtime() as i128 * 1_000_000_000 + md.mtime_nsec() as i128,
+            ctime_ns: md.ctime() as i128 * 1_000_000_000 + md.ctime_nsec() as i128,
+            extents: extent_digest(f),
+        })
+    }
+
+    fn extent_digest(f: &File) -> Option<String> {
+        let mut h = blake3::Hasher::new();
+        let mut start = 0u64;
+        loop {
+            let mut map = Fiemap {
+                start,
+                length: u64::MAX - start,
+                flags: FIEMAP_FLAG_SYNC,
+                mapped_extents: 0,
+                extent_count: EXTENTS_PER_CALL as u32,
+                reserved: 0,
+                extents: [FiemapExtent::default(); EXTENTS_PER_CALL],
+            };
+            // SAFETY: `map` is a properly sized `struct fiemap` with room for
+            // `extent_count` extents; the kernel writes at most that many.
+            let rc = unsafe { libc::ioctl(f.as_raw_fd(), FS_IOC_FIEMAP as _, &mut map) };
+            if rc != 0 {
+                return None;
+            }
+            let n = (map.mapped_extents as usize).min(EXTENTS_PER_CALL);
+            for e in &map.extents[..n] {
+                for v in [e.logical, e.physical, e.length, e.flags as u64] {
+                    h.update(&v.to_le_bytes());
+                }
+            }
+            match map.extents[..n].last() {
+                Some(e) if e.flags & FIEMAP_EXTENT_LAST == 0 => start = e.logical + e.length,
+                _ => return Some(h.finalize().to_hex().to_string()),
+            }
+        }
+    }
+
+    fn inode_flags(f: &File) -> Option<libc::c_long> {
+        let mut flags: libc::c_long = 0;
+        // SAFETY: FS_IOC_GETFLAGS writes at most a `long` into `flags`.
+        let rc = unsafe { libc::ioctl(f.as_raw_fd(), libc::FS_IOC_GETFLAGS as _, &mut flags) };
+        (rc == 0).then_some(flags)
+    }
+
+    pub fn checksummed_by(f: &File, path: &Path) -> Option<&'static str> {
+        let flags = inode_flags(f);
+        if flags.is_some_and(|fl| fl & FS_VERITY_FL != 0) {
+            return Some("fs-verity");
+        }
+        // SAFETY: `statfs` is plain old data; fstatfs fills it on success.
+        let mut st: libc::statfs = unsafe { std::mem::zeroed() };
+        if unsafe { libc::fstatfs(f.as_raw_fd(), &mut st) } != 0 {
+            return None;
+        }
+        match st.f_type as u32 {
+            ZFS_SUPER_MAGIC => Some("zfs"),
+            // NOCOW files and nodatasum mounts carry no data checksums
+            BTRFS_SUPER_MAGIC
+                if !flags.is_some_and(|fl| fl & FS_NOCOW_FL != 0) && !nodatasum_mount(path) =>
+            {
+                Some("btrfs")
+            }
+            _ => None,
+        }
+    }
+
+    /// The btrfs mount holding `path` was mounted without data checksums.
+    fn nodatasum_mount(path: &Path) -> bool {
+        let Ok(path) = path.canonicalize() else { return true };
+        let Ok(mounts) = std::fs::read_to_string("/proc/self/mounts") else { return true };
+        mounts
+            .lines()
+            .filter_map(|l| {
+                let mut it = l.split_whitespace();
+                let (_, dir, fstype, opts) = (it.next()?, it.next()?, it.next()?, it.next()?);
+                (fstype == "btrfs" && path.starts_with(dir)).then_some((dir, opts))
+            })
+            .max_by_key(|(dir, _)| dir.len())
+            .map_or(true, |(_, opts)| opts.split(',').any(|o| o == "nodatasum" || o == "nodatacow"))
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod sys {
+    use super::FileStamp;
+    use std::fs::File;
+    use std::path::Path;
+
+    pub fn stamp(_f: &File) -> Option<FileStamp> {
+        None
+    }
+
+    pub fn checksummed_by(_f: &File, _path: &Path) -> Option<&'static str> {
+        None
+    }
+}
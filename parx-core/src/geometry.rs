@@ -0,0 +1,73 @@
+//! Stripe geometry of a parity set: how chunk indices fall into stripes of
+//! `k` data shards, how many parity shards a percentage gives, and how much
+//! of the last stripe is real data. Built once from the encoder settings or
+//! the manifest, so that create, audit, repair, update and heal all round
+//! the same way.
+
+use crate::manifest::Manifest;
+use core::ops::Range;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Geometry {
+    /// Data shards per stripe, at least 1
+    pub k: usize,
+    /// Inner parity shards of a plain stripe
+    pub m: usize,
+    pub chunk_size: usize,
+    /// Data chunks in the set
+    pub total_chunks: u64,
+    /// Stripes over those chunks; the last one may be short
+    pub stripes: u64,
+}
+
+impl Geometry {
+    pub fn new(k: usize, m: usize, chunk_size: usize, total_chunks: u64) -> Self {
+        let k = k.max(1);
+        Self { k, m, chunk_size, total_chunks, stripes: total_chunks.div_ceil(k as u64) }
+    }
+
+    pub fn from_manifest(mf: &Manifest) -> Self {
+        Self::new(mf.stripe_k, mf.parity_shards(), mf.chunk_size, mf.total_chunks)
+    }
+
+    /// Parity shards for `pct` percent of `k` data shards, rounded up.
+    pub fn parity_for_pct(k: usize, pct: u32) -> usize {
+        (k as u64 * pct as u64).div_ceil(100) as usize
+    }
+
+    /// Percentage that `m` parity shards are of `k`, rounded down.
+    pub fn pct_for_parity(k: usize, m: usize) -> u32 {
+        ((m as u64).saturating_mul(100) / k.max(1) as u64).min(u32::MAX as u64) as u32
+    }
+
+    pub fn stripe_of(&self, idx: u64) -> u64 {
+        idx / self.k as u64
+    }
+
+    /// Position of chunk `idx` within its stripe.
+    pub fn slot_of(&self, idx: u64) -> usize {
+        (idx % self.k as u64) as usize
+    }
+
+    pub fn chunk_at(&self, stripe: u64, slot: usize) -> u64 {
+        stripe * self.k as u64 + slot as u64
+    }
+
+    /// Chunk indices of the `k` data slots of `stripe`, padding included.
+    pub fn slots(&self, stripe: u64) -> Range<u64> {
+        let first = stripe * self.k as u64;
+        first..first + self.k as u64
+    }
+
+    /// Slots of `stripe` holding data: `k`, fewer in a short last stripe,
+    /// 0 past the end. The rest encode as zeros.
+    pub fn k_active(&self, stripe: u64) -> usize {
+        let first = stripe.saturating_mul(self.k as u64);
+        self.total_chunks.saturating_sub(first).min(self.k as u64) as usize
+    }
+
+    /// Bytes taken by `m` parity shards on every stripe, `copies` times over.
+    pub fn parity_bytes(&self, m: usize, copies: usize) -> u64 {
+        self.stripes * m as u64 * self.chunk_size as u64 * copies.max(1) as u64
+    }
+}
@@ -0,0 +1,115 @@
+//! Chunk hashes shared between phases, so that a file hashed by one parx
+//! command is not read again by the next one while it has not changed.
+//!
+//! `create`, `verify`, `repair` and `update` read every file they cover. When
+//! they run back to back (verify, then repair what it found, then update),
+//! each one would read the whole tree again although the earlier one already
+//! hashed it. Whenever one of them finds a file holding exactly the chunks the
+//! manifest lists, it records the file's [`FileStamp`] in
+//! `<parity dir>/hashes.cache`. `repair` and `update` skip files whose stamp
+//! still matches a recent entry. `verify` and `vol heal` do not: verify is
+//! what finds damage that leaves the stamp alone, and heal needs the bytes
+//! themselves to re-encode a stripe. The entries' ages do let a scrub of an
+//! archive too large for one window start with the files checked longest
+//! ago (`verify --older-than`).
+//!
+//! An entry holds a digest of the file's chunk list rather than the hashes
+//! themselves, and only counts while it is younger than the age the caller
+//! accepts, so that bit rot is never hidden for long by a stale entry.
+
+use crate::fshint::{self, FileStamp};
+use crate::manifest::ChunkRef;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const CACHE_FILE: &str = "hashes.cache";
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CachedFile {
+    pub stamp: FileStamp,
+    /// [`digest`] of the chunks the file was found to hold
+    pub chunks: String,
+    /// When the file was hashed, in seconds since the Unix epoch
+    pub hashed_unix: u64,
+}
+
+impl CachedFile {
+    /// Whether the entry is for `stamp` and the chunk list with `digest`,
+    /// and at most `max_age` old.
+    pub fn holds(&self, stamp: &FileStamp, digest: &str, max_age: Duration) -> bool {
+        self.stamp == *stamp
+            && now_unix().saturating_sub(self.hashed_unix) <= max_age.as_secs()
+            && self.chunks == digest
+    }
+}
+
+/// Files last found intact, keyed by manifest path.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct HashCache {
+    pub files: BTreeMap<String, CachedFile>,
+}
+
+impl HashCache {
+    /// The cache in `parity_dir`; empty when there is none or it does not parse.
+    pub fn load(parity_dir: &Path) -> Self {
+        std::fs::read(parity_dir.join(CACHE_FILE))
+            .ok()
+            .and_then(|b| serde_json::from_slice(&b).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, parity_dir: &Path) -> Result<()> {
+        let path = parity_dir.join(CACHE_FILE);
+        let tmp = path.with_extension("cache.tmp");
+        std::fs::write(&tmp, serde_json::to_vec(self)?)
+            .with_context(|| format!("write {:?}", tmp))?;
+        std::fs::rename(&tmp, &path).with_context(|| format!("rename {:?}", tmp))
+    }
+
+    /// Whether `rel`, as `stamp` describes it, was found at most `max_age`
+    /// ago to hold exactly `chunks`.
+    pub fn holds(
+        &self,
+        rel: &str,
+        stamp: &FileStamp,
+        chunks: &[ChunkRef],
+        max_age: Duration,
+    ) -> bool {
+        self.files.get(rel).is_some_and(|c| c.holds(stamp, &digest(chunks), max_age))
+    }
+
+    /// Record that `rel`, as `stamp` describes it, holds `chunks`.
+    pub fn insert(&mut self, rel: &str, stamp: FileStamp, chunks: &[ChunkRef]) {
+        let entry = CachedFile { stamp, chunks: digest(chunks), hashed_unix: now_unix() };
+        self.files.insert(rel.to_string(), entry);
+    }
+
+    pub fn remove(&mut self, rel: &str) {
+        self.files.remove(rel);
+    }
+}
+
+/// BLAKE3 over the offset, length and hash of each chunk.
+pub fn digest(chunks: &[ChunkRef]) -> String {
+    let mut h = blake3::Hasher::new();
+    for c in chunks {
+        h.update(&c.file_offset.to_le_bytes());
+        h.update(&c.len.to_le_bytes());
+        h.update(c.hash_hex.as_bytes());
+    }
+    h.finalize().to_hex().to_string()
+}
+
+/// The stamp of the file at `path`; `None` when it cannot be opened or the
+/// platform has no stamps.
+pub fn stamp_of(path: &Path) -> Option<FileStamp> {
+    fshint::stamp(&File::open(path).ok()?)
+}
+
+fn now_unix() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
@@ -0,0 +1,294 @@
+//! In-place healing of parity volumes: regenerate individual parity shards from
+//! the (verified) source data instead of re-creating the whole set.
+
+use crate::audit_log::{self, AuditEvent};
+use crate::codec::Codecs;
+use crate::index::{read_index, read_trailer, write_index_and_trailer_with, IndexLimits};
+use crate::manifest;
+use crate::metaparity::{read_volume_block, TAG_MANIFEST};
+use crate::path_safety::{validate_path, PathPolicy};
+use crate::rs_codec::{RsCodec, RsField};
+use crate::volume::{check_features, FeatureError, ReadMode, VolumeEntry, VolumeHeader};
+use anyhow::{bail, Context, Result};
+use ed25519_dalek::SigningKey;
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Result of checking the shards listed in one volume index against their hashes.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ShardCheck {
+    pub entries: usize,
+    /// Index positions whose shard is unreadable or fails its recorded hash.
+    pub bad: Vec<usize>,
+    /// Index positions without a recorded hash (volumes from older encoders).
+    pub unhashed: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HealReport {
+    pub volumes_scanned: u64,
+    pub shards_checked: u64,
+    pub shards_bad: u64,
+    pub shards_healed: u64,
+    /// Shards that could not be regenerated because source chunks of the
+    /// stripe are damaged or missing (run `repair` first).
+    pub shards_unhealable: u64,
+    /// Volumes whose index was rewritten (new hashes recorded).
+    pub indices_rewritten: u64,
+    pub unreadable_volumes: Vec<String>,
+    /// Volumes and volume parity files rebuilt from volume parity (see
+    /// [`crate::volparity`])
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub volumes_restored: Vec<String>,
+    /// Lost or damaged volumes beyond what the volume parity covers
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub volumes_unrecoverable: Vec<String>,
+}
+
+impl crate::report::Report for HealReport {
+    const KIND: &'static str = "heal";
+}
+
+/// Read a volume index and, when `deep`, hash every shard it lists. Shards
+/// are read in parallel with positional reads.
+pub fn check_volume(f: &mut File, deep: bool) -> Result<(Vec<VolumeEntry>, ShardCheck)> {
+    use rayon::prelude::*;
+    let (off, len, crc) = read_trailer(f)?;
+    let entries = read_index(f, off, len, crc, &IndexLimits::default())?;
+    let mut chk = ShardCheck { entries: entries.len(), ..Default::default() };
+    if !deep {
+        return Ok((entries, chk));
+    }
+    let f = &*f;
+    let status: Vec<Option<bool>> = entries
+        .par_iter()
+        .map(|e| {
+            let want = e.hash?;
+            let mut buf = vec![0u8; e.len as usize];
+            Some(
+                read_exact_at(f, e.offset, &mut buf).is_ok()
+                    && *blake3::hash(&buf).as_bytes() == want,
+            )
+        })
+        .collect();
+    for (i, st) in status.into_iter().enumerate() {
+        match st {
+            None => chk.unhashed.push(i),
+            Some(false) => chk.bad.push(i),
+            Some(true) => {}
+        }
+    }
+    Ok((entries, chk))
+}
+
+/// The source tree of a set, read a stripe at a time.
+pub(crate) struct SourceSlots<'a> {
+    mf: &'a manifest::Manifest,
+    /// Every location of each slot; deduplicated slots (`create --dedup`) have several
+    chunks: HashMap<u64, Vec<(PathBuf, u64, u32, &'a str)>>,
+}
+
+impl<'a> SourceSlots<'a> {
+    pub(crate) fn new(mf: &'a manifest::Manifest, root: &Path, policy: PathPolicy) -> Result<Self> {
+        let mut chunks: HashMap<u64, Vec<_>> = HashMap::new();
+        for fe in &mf.files {
+            let safe = validate_path(root, Path::new(&fe.rel_path), policy)
+                .with_context(|| format!("validate path {:?}", fe.rel_path))?;
+            for ch in &fe.chunks {
+                let loc = (safe.clone(), ch.file_offset, ch.len, ch.hash_hex.as_str());
+                chunks.entry(ch.idx).or_default().push(loc);
+            }
+        }
+        Ok(Self { mf, chunks })
+    }
+
+    /// The `k` data shards of stripe `s`, each padded to `chunk_size`;
+    /// `None` when a chunk has no intact copy left.
+    pub(crate) fn stripe(&self, s: u64) -> Option<Vec<Vec<u8>>> {
+        let cs = self.mf.chunk_size;
+        let mut bufs = Vec::with_capacity(self.mf.stripe_k);
+        for idx in self.mf.geometry().slots(s) {
+            // Past the end, or a slot freed by `update`: encoded as zeros
+            let Some(locs) = self.chunks.get(&idx) else {
+                bufs.push(vec![0u8; cs]);
+                continue;
+            };
+            let intact = locs.iter().find_map(|(path, off, len, want)| {
+                let mut buf = vec![0u8; cs];
+                let mut f = File::open(path).ok()?;
+                f.seek(SeekFrom::Start(*off)).ok()?;
+                f.read_exact(&mut buf[..*len as usize]).ok()?;
+                self.mf.hasher().matches(&buf, want).then_some(buf)
+            });
+            bufs.push(intact?);
+        }
+        Some(bufs)
+    }
+}
+
+#[cfg(unix)]
+fn read_exact_at(f: &File, off: u64, buf: &mut [u8]) -> std::io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(f, buf, off)
+}
+
+#[cfg(windows)]
+fn read_exact_at(f: &File, mut off: u64, mut buf: &mut [u8]) -> std::io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match f.seek_read(buf, off)? {
+            0 => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+            n => {
+                buf = &mut buf[n..];
+                off += n as u64;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Check every volume in the manifest's parity dir and regenerate bad shards
+/// (and shards without a recorded hash) by re-encoding only their stripe from
+/// the source tree. Volumes are patched in place; indices are rewritten when
+/// an entry gains a hash. Every rewritten shard is recorded in the audit log.
+/// Volumes lost or damaged as a whole are first rebuilt from the set's volume
+/// parity, when it has one.
+pub fn heal(
+    manifest_path: &Path,
+    root: &Path,
+    policy: PathPolicy,
+    audit_key: Option<&SigningKey>,
+) -> Result<HealReport> {
+    let (mf, _) = manifest::load(manifest_path)?;
+    mf.require_plain_hashes("heal")?;
+    let parity_dir = mf.parity_dir_at(manifest_path);
+    // Share the repair lock: healing and repair both write into the set
+    let lock_file =
+        File::create(parity_dir.join(".parx.repair.lock")).context("create global repair lock")?;
+    lock_file.try_lock_exclusive().context("acquire global repair lock")?;
+
+    let geo = mf.geometry();
+    let (k, m) = (geo.k, geo.m);
+    if m == 0 && !mf.is_empty() {
+        bail!("no parity available (parity_shards=0)");
+    }
+    // Encode every shard a stripe may hold; the first m match a plain stripe
+    // (an empty set has no stripe and may have been made without parity)
+    let m = mf.max_parity_shards();
+    let rs = RsCodec::with_field(RsField::from_ext(&mf.ext), k, m.max(1)).context("init RS")?;
+
+    let source = SourceSlots::new(&mf, root, policy)?;
+
+    // Regenerated parity per stripe (None: source damaged), shared across volumes
+    let mut stripes: HashMap<u64, Option<Vec<Vec<u8>>>> = HashMap::new();
+    let encode_stripe = |s: u64| -> Option<Vec<Vec<u8>>> {
+        let mut bufs = source.stripe(s)?;
+        bufs.extend((0..m).map(|_| vec![0u8; mf.chunk_size]));
+        let mut shards: Vec<&mut [u8]> = bufs.iter_mut().map(|b| b.as_mut_slice()).collect();
+        rs.encode(&mut shards).ok()?;
+        Some(bufs.split_off(k))
+    };
+
+    let mut rep = HealReport::default();
+    if mf.volume_parity > 0 {
+        let restore = crate::volparity::restore(&parity_dir)?;
+        rep.volumes_restored = restore.restored;
+        rep.volumes_unrecoverable = restore.unrecoverable;
+    }
+    let mut events: Vec<AuditEvent> = Vec::new();
+    let mut vols: Vec<PathBuf> = Vec::new();
+    // The volume dirs of `create --output-dirs` may sit on a drive that is gone
+    for dir in std::iter::once(parity_dir.clone()).chain(mf.volume_dirs()) {
+        let rd = match std::fs::read_dir(&dir) {
+            Err(_) if dir != parity_dir => continue,
+            rd => rd.with_context(|| format!("read_dir {:?}", dir))?,
+        };
+        let mut here: Vec<PathBuf> = rd
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.extension().map(|s| s == "parxv").unwrap_or(false))
+            .collect();
+        here.sort();
+        vols.extend(here);
+    }
+    for p in vols {
+        let name = p.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let mut f = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&p)
+            .with_context(|| format!("open {:?}", p))?;
+        // Shards are rewritten in place: never touch a volume whose features
+        // are not fully understood, even under --compat-read
+        let features =
+            VolumeHeader::read_from(&f).and_then(|h| check_features(h.flags, ReadMode::Strict));
+        if let Err(e) = features {
+            if e.is::<FeatureError>() {
+                return Err(e.context(format!("refusing to heal {}", name)));
+            }
+        }
+        let Ok((mut entries, chk)) = check_volume(&mut f, true) else {
+            rep.unreadable_volumes.push(name);
+            continue;
+        };
+        rep.volumes_scanned += 1;
+        rep.shards_checked += chk.entries as u64;
+        rep.shards_bad += chk.bad.len() as u64;
+        let mut index_dirty = false;
+        for &i in chk.bad.iter().chain(chk.unhashed.iter()) {
+            let e = &mut entries[i];
+            // Only inner parity can be regenerated from the source stripe
+            if !e.is_inner() {
+                rep.shards_unhealable += 1;
+                continue;
+            }
+            let regen = stripes.entry(e.stripe).or_insert_with(|| encode_stripe(e.stripe));
+            let Some(shard) = regen.as_ref().and_then(|p| p.get(e.parity_idx as usize)) else {
+                rep.shards_unhealable += 1;
+                continue;
+            };
+            let data = &shard[..(e.len as usize).min(shard.len())];
+            let mut cur = vec![0u8; data.len()];
+            let intact = f.seek(SeekFrom::Start(e.offset)).is_ok()
+                && f.read_exact(&mut cur).is_ok()
+                && cur == data;
+            if !intact {
+                f.seek(SeekFrom::Start(e.offset))?;
+                f.write_all(data)?;
+                rep.shards_healed += 1;
+                events.push(AuditEvent {
+                    action: "heal".to_string(),
+                    target: name.clone(),
+                    offset: e.offset,
+                    len: data.len() as u32,
+                    chunk: None,
+                    stripe: e.stripe,
+                    sources: (0..k).map(|i| format!("data[{}]", i)).collect(),
+                });
+            }
+            if e.hash.is_none() {
+                e.hash = Some(*blake3::hash(data).as_bytes());
+                index_dirty = true;
+            }
+        }
+        if index_dirty {
+            // The manifest parity is carried over; the index's is rebuilt
+            let (idx_off, idx_len, _) = read_trailer(&mut f)?;
+            let kept = read_volume_block(&mut f, idx_off, idx_len)
+                .and_then(|mut b| b.remove(&TAG_MANIFEST));
+            let sections: Vec<_> = kept.iter().map(|mp| (TAG_MANIFEST, mp)).collect();
+            let codec = VolumeHeader::read_from(&f).map(|h| Codecs::from_ext(&h.ext).index)?;
+            f.set_len(idx_off)?;
+            write_index_and_trailer_with(&f, &entries, &sections, codec)?;
+            rep.indices_rewritten += 1;
+        }
+        f.sync_all()?;
+    }
+    if rep.shards_healed > 0 || rep.indices_rewritten > 0 {
+        crate::volparity::refresh(&parity_dir)?;
+    }
+    audit_log::append(&parity_dir, &events, audit_key)?;
+    Ok(rep)
+}
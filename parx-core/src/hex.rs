@@ -0,0 +1,23 @@
+use anyhow::{bail, Result};
+
+pub(crate) fn hex(b: &[u8]) -> String {
+    b.iter().map(|x| format!("{:02x}", x)).collect()
+}
+
+pub(crate) fn unhex(s: &str) -> Result<Vec<u8>> {
+    // Over the bytes: slicing the str could split a multi-byte char, and
+    // from_str_radix would take a sign
+    fn nibble(c: u8) -> Result<u8> {
+        Ok(match c {
+            b'0'..=b'9' => c - b'0',
+            b'a'..=b'f' => c - b'a' + 10,
+            b'A'..=b'F' => c - b'A' + 10,
+            _ => bail!("invalid hex"),
+        })
+    }
+    let b = s.as_bytes();
+    if b.len() % 2 != 0 {
+        bail!("odd-length hex string");
+    }
+    b.chunks_exact(2).map(|p| Ok(nibble(p[0])? << 4 | nibble(p[1])?)).collect()
+}
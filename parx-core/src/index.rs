@@ -1,12 +1,99 @@
-use crate::volume::VolumeEntry;
+use crate::codec::{self, Codec};
+use crate::ext::{key, ExtMap, MAX_EXT_VALUE};
+use crate::manifest::SetTag;
+use crate::metaparity::{encode_block, read_block, read_volume_block, MetaParity, TAG_INDEX};
+use crate::storage::DataSource;
+use crate::volume::{
+    check_header_bytes, decode_entries, encode_entries, force_old_reader, read_mode, VolumeEntry,
+    VolumeHeader, VOLUME_HEADER_FIXED, VOLUME_MAGIC,
+};
 use anyhow::{bail, Context, Result};
 use crc32fast::Hasher as Crc32;
 use std::fs::File;
 use std::io::{Read, Seek, SeekFrom, Write};
 
-/// Constants for trailer format
+/// Constants for trailer format. v1: `magic NUL off(8) len(4) crc(4)`;
+/// v2 widens the index length for indices of 4 GiB and more:
+/// `magic 0x02 off(8) len(8) crc(4)`. Writers emit v1 whenever the length
+/// fits so older readers keep working.
 const TRAILER_MAGIC: &[u8] = b"PARXINDEX"; // 9 bytes
 const TRAILER_LEN: u64 = 9 + 1 + 8 + 4 + 4; // magic + NUL + off + len + crc
+const TRAILER_V2: u8 = 2;
+/// The longer of the two trailer layouts
+pub(crate) const TRAILER_V2_LEN: u64 = 9 + 1 + 8 + 8 + 4;
+
+/// Encode the trailer for an index of `idx_len` bytes at `idx_off`.
+pub fn encode_trailer(idx_off: u64, idx_len: u64, crc: u32) -> Vec<u8> {
+    let mut tr = Vec::with_capacity(TRAILER_V2_LEN as usize);
+    tr.extend_from_slice(TRAILER_MAGIC);
+    match u32::try_from(idx_len) {
+        Ok(len) => {
+            tr.push(0);
+            tr.extend_from_slice(&idx_off.to_le_bytes());
+            tr.extend_from_slice(&len.to_le_bytes());
+        }
+        Err(_) => {
+            tr.push(TRAILER_V2);
+            tr.extend_from_slice(&idx_off.to_le_bytes());
+            tr.extend_from_slice(&idx_len.to_le_bytes());
+        }
+    }
+    tr.extend_from_slice(&crc.to_le_bytes());
+    tr
+}
+
+/// Second copy of a volume's compressed index, in space `create` reserves
+/// right after the header, so that a damaged trailer or tail leaves the
+/// index readable. Recorded in the header extension [`key::INDEX_COPY`] as
+/// `off(8) cap(8) len(8) crc(4) idx_off(8)`; every index write refreshes it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize)]
+pub struct IndexCopy {
+    /// Start and size of the reserved space
+    pub off: u64,
+    pub cap: u64,
+    /// Length of the copy; 0 while none is written, or once the index
+    /// outgrew the space (after `update` added shards)
+    pub len: u64,
+    pub crc: u32,
+    /// Where the primary index at the end of the volume starts
+    pub idx_off: u64,
+}
+
+impl IndexCopy {
+    /// Space reserved at `off`, nothing written yet.
+    pub fn reserved(off: u64, cap: u64) -> Self {
+        Self { off, cap, ..Default::default() }
+    }
+
+    pub fn to_ext(&self, ext: &mut ExtMap) {
+        let mut v = Vec::with_capacity(36);
+        v.extend_from_slice(&self.off.to_le_bytes());
+        v.extend_from_slice(&self.cap.to_le_bytes());
+        v.extend_from_slice(&self.len.to_le_bytes());
+        v.extend_from_slice(&self.crc.to_le_bytes());
+        v.extend_from_slice(&self.idx_off.to_le_bytes());
+        ext.insert(key::INDEX_COPY, v);
+    }
+
+    pub fn from_ext(ext: &ExtMap) -> Option<Self> {
+        let v = ext.get(key::INDEX_COPY).filter(|v| v.len() == 36)?;
+        let u64_at = |o: usize| u64::from_le_bytes(v[o..o + 8].try_into().unwrap());
+        Some(Self {
+            off: u64_at(0),
+            cap: u64_at(8),
+            len: u64_at(16),
+            crc: u32::from_le_bytes(v[24..28].try_into().unwrap()),
+            idx_off: u64_at(28),
+        })
+    }
+}
+
+/// Space to reserve for the head copy of an index of up to `entries` shards:
+/// room for them uncompressed, plus the manifest backup slices.
+pub fn index_copy_reserve(entries: u64) -> u64 {
+    // A bincode entry takes 56 bytes
+    entries * 64 + 4096
+}
 
 #[derive(Clone, Copy, Debug)]
 pub struct IndexLimits {
@@ -20,77 +107,269 @@ impl Default for IndexLimits {
     }
 }
 
-/// Write a compressed (zstd) bincode index at EOF and append a CRC'd trailer.
-pub fn write_index_and_trailer(mut f: &File, entries: &[VolumeEntry]) -> Result<()> {
+/// Write a compressed (zstd) bincode index at EOF, its metadata parity and a
+/// CRC'd trailer.
+pub fn write_index_and_trailer(f: &File, entries: &[VolumeEntry]) -> Result<()> {
+    write_index_and_trailer_with(f, entries, &[], Codec::default())
+}
+
+/// Like [`write_index_and_trailer`], compressing with `codec` and also storing
+/// the parity `sections` of other metadata (e.g. the manifest) in the block
+/// after the index.
+pub fn write_index_and_trailer_with(
+    mut f: &File,
+    entries: &[VolumeEntry],
+    sections: &[(u8, &MetaParity)],
+    codec: Codec,
+) -> Result<()> {
     // Serialize
-    let raw = bincode::serialize(entries).context("serialize index")?;
-    // Compress with default level; bounded in readers
-    let compressed = zstd::stream::encode_all(&raw[..], 0).context("zstd compress index")?;
-    let idx_len = compressed.len() as u32;
+    let raw = encode_entries(entries).context("serialize index")?;
+    // Compress; bounded in readers
+    let compressed = codec.compress(&raw).context("compress index")?;
     let idx_off = f.metadata()?.len();
     // CRC over compressed payload
-    let mut h = Crc32::new();
-    h.update(&compressed);
-    let crc = h.finalize();
-    // Append index
+    let crc = crc32(&compressed);
+    let index_parity = MetaParity::protect(&compressed)?;
+    let mut all = vec![(TAG_INDEX, &index_parity)];
+    all.extend(sections.iter().filter(|(tag, _)| *tag != TAG_INDEX).copied());
+    // Append index, parity block and trailer
     f.seek(SeekFrom::End(0))?;
     f.write_all(&compressed)?;
-    // Trailer
-    let mut tr = Vec::with_capacity(TRAILER_LEN as usize);
-    tr.extend_from_slice(TRAILER_MAGIC);
-    tr.push(0);
-    tr.extend_from_slice(&idx_off.to_le_bytes());
-    tr.extend_from_slice(&idx_len.to_le_bytes());
-    tr.extend_from_slice(&crc.to_le_bytes());
-    f.write_all(&tr)?;
-    Ok(())
+    f.write_all(&encode_block(&all))?;
+    f.write_all(&encode_trailer(idx_off, compressed.len() as u64, crc))?;
+    write_head_copy(f, idx_off, &compressed, crc)
+}
+
+/// Refresh the head copy of a volume that reserved room for one; bare
+/// index files and older volumes have none.
+fn write_head_copy(mut f: &File, idx_off: u64, compressed: &[u8], crc: u32) -> Result<()> {
+    let Ok(mut hdr) = VolumeHeader::read_from(f) else {
+        return Ok(());
+    };
+    let Some(mut copy) = IndexCopy::from_ext(&hdr.ext) else {
+        return Ok(());
+    };
+    copy.idx_off = idx_off;
+    (copy.len, copy.crc) = (0, 0);
+    if compressed.len() as u64 <= copy.cap {
+        f.seek(SeekFrom::Start(copy.off))?;
+        f.write_all(compressed)?;
+        (copy.len, copy.crc) = (compressed.len() as u64, crc);
+    }
+    // Same size as before: the header is rewritten in place
+    copy.to_ext(&mut hdr.ext);
+    hdr.write_to(f)
+}
+
+/// Extensions of the header of a volume read through `read_at`, if it reads.
+fn header_ext(mut read_at: impl FnMut(u64, &mut [u8]) -> Result<()>) -> Option<ExtMap> {
+    let mut fixed = [0u8; VOLUME_HEADER_FIXED];
+    read_at(0, &mut fixed).ok()?;
+    if &fixed[..8] != VOLUME_MAGIC {
+        return None;
+    }
+    let ext_len = u32::from_le_bytes(fixed[24..28].try_into().unwrap()) as usize;
+    if ext_len > 16 * MAX_EXT_VALUE {
+        return None;
+    }
+    let mut ext = vec![0u8; ext_len];
+    read_at(VOLUME_HEADER_FIXED as u64, &mut ext).ok()?;
+    ExtMap::decode(&ext).ok()
+}
+
+fn head_copy(read_at: impl FnMut(u64, &mut [u8]) -> Result<()>) -> Option<IndexCopy> {
+    IndexCopy::from_ext(&header_ext(read_at)?).filter(|c| c.len > 0)
+}
+
+/// Set id and generation in the header of a volume read through `src`;
+/// `None` when the header does not read.
+pub fn read_set_tag(src: &dyn DataSource, rel_path: &str) -> Option<SetTag> {
+    Some(SetTag::from_ext(&header_ext(|off, b: &mut [u8]| src.read_at(rel_path, off, b))?))
 }
 
-/// Read trailer at EOF; returns (index_off, index_len, crc32)
-pub fn read_trailer(f: &mut File) -> Result<(u64, u32, u32)> {
+/// The head copy, if it holds the index `len` bytes long with `crc`.
+fn read_head_copy(
+    mut read_at: impl FnMut(u64, &mut [u8]) -> Result<()>,
+    len: usize,
+    crc: u32,
+) -> Option<Vec<u8>> {
+    let copy = head_copy(&mut read_at).filter(|c| c.len == len as u64 && c.crc == crc)?;
+    let mut buf = vec![0u8; len];
+    read_at(copy.off, &mut buf).ok()?;
+    (crc32(&buf) == crc).then_some(buf)
+}
+
+fn file_reader(f: &mut File) -> impl FnMut(u64, &mut [u8]) -> Result<()> + '_ {
+    move |off, buf| {
+        f.seek(SeekFrom::Start(off))?;
+        Ok(f.read_exact(buf)?)
+    }
+}
+
+/// Read trailer (v1 or v2) at EOF; returns (index_off, index_len, crc32).
+/// When it is damaged, the same values come from the head copy record in
+/// the header (see [`IndexCopy`]). Volumes whose header declares features
+/// this build cannot read are refused here.
+pub fn read_trailer(f: &mut File) -> Result<(u64, u64, u32)> {
     let flen = f.metadata()?.len();
     if flen < TRAILER_LEN {
         bail!("too short");
     }
-    f.seek(SeekFrom::Start(flen - TRAILER_LEN))?;
-    let mut tr = vec![0u8; TRAILER_LEN as usize];
+    if flen >= VOLUME_HEADER_FIXED as u64 {
+        let mut fixed = [0u8; VOLUME_HEADER_FIXED];
+        f.seek(SeekFrom::Start(0))?;
+        f.read_exact(&mut fixed)?;
+        check_header_bytes(&fixed, read_mode())?;
+    }
+    let tail = TRAILER_V2_LEN.min(flen);
+    f.seek(SeekFrom::Start(flen - tail))?;
+    let mut tr = vec![0u8; tail as usize];
     f.read_exact(&mut tr)?;
+    let parsed = parse_trailer(&tr).and_then(|(off, len, crc)| {
+        check_index_range(off, len, flen)?;
+        Ok((off, len, crc))
+    });
+    match parsed {
+        Err(e) => head_copy(file_reader(f)).map(|c| (c.idx_off, c.len, c.crc)).ok_or(e),
+        ok => ok,
+    }
+}
+
+/// Parse the trailer at the end of `tail` (the last `TRAILER_V2_LEN` bytes of
+/// a volume, or fewer for tiny files). The v2 form is tried first; its magic
+/// sits 4 bytes earlier than a v1 magic would.
+fn parse_trailer(tail: &[u8]) -> Result<(u64, u64, u32)> {
+    parse_trailer_versioned(tail).map(|(_, off, len, crc)| (off, len, crc))
+}
+
+/// [`parse_trailer`], also returning the trailer version (1 or 2).
+fn parse_trailer_versioned(tail: &[u8]) -> Result<(u8, u64, u64, u32)> {
+    let u64_at = |b: &[u8]| u64::from_le_bytes(b[..8].try_into().unwrap());
+    let u32_at = |b: &[u8]| u32::from_le_bytes(b[..4].try_into().unwrap());
+    if tail.len() as u64 >= TRAILER_V2_LEN {
+        let tr = &tail[tail.len() - TRAILER_V2_LEN as usize..];
+        if &tr[0..9] == TRAILER_MAGIC && tr[9] == TRAILER_V2 {
+            return Ok((2, u64_at(&tr[10..]), u64_at(&tr[18..]), u32_at(&tr[26..])));
+        }
+    }
+    let tr = &tail[tail.len() - TRAILER_LEN as usize..];
     if &tr[0..9] != TRAILER_MAGIC || tr[9] != 0 {
         bail!("bad trailer magic");
     }
-    let mut off8 = [0u8; 8];
-    off8.copy_from_slice(&tr[10..18]);
-    let mut len4 = [0u8; 4];
-    len4.copy_from_slice(&tr[18..22]);
-    let mut crc4 = [0u8; 4];
-    crc4.copy_from_slice(&tr[22..26]);
-    Ok((u64::from_le_bytes(off8), u32::from_le_bytes(len4), u32::from_le_bytes(crc4)))
+    Ok((1, u64_at(&tr[10..]), u32_at(&tr[18..]) as u64, u32_at(&tr[22..])))
+}
+
+/// The trailer as stored, without the fallbacks of [`read_trailer`]:
+/// `(version, index_off, index_len, crc32)`, for dumps.
+pub fn raw_trailer(f: &mut File) -> Result<(u8, u64, u64, u32)> {
+    let flen = f.metadata()?.len();
+    if flen < TRAILER_LEN {
+        bail!("too short");
+    }
+    let tail = TRAILER_V2_LEN.min(flen);
+    let mut tr = vec![0u8; tail as usize];
+    file_reader(f)(flen - tail, &mut tr)?;
+    let t = parse_trailer_versioned(&tr)?;
+    check_index_range(t.1, t.2, flen)?;
+    Ok(t)
+}
+
+/// The index must lie between the header and the trailer.
+fn check_index_range(idx_off: u64, idx_len: u64, flen: u64) -> Result<()> {
+    if idx_off.saturating_add(idx_len) > flen - TRAILER_LEN {
+        bail!("index range past end of volume");
+    }
+    Ok(())
 }
 
-/// Verify CRC, decompress, and decode index with limits applied.
+/// Verify CRC (rebuilding a damaged index from its metadata parity, else
+/// taking the head copy), decompress, and decode index with limits applied.
 pub fn read_index(
     f: &mut File,
     idx_off: u64,
-    idx_len: u32,
+    idx_len: u64,
     crc: u32,
     limits: &IndexLimits,
 ) -> Result<Vec<VolumeEntry>> {
-    let mut buf = vec![0u8; idx_len as usize];
-    f.seek(SeekFrom::Start(idx_off))?;
-    f.read_exact(&mut buf)?;
+    let idx_len = usize::try_from(idx_len).context("index too large for this platform")?;
+    let mut buf = vec![0u8; idx_len];
+    let read = file_reader(f)(idx_off, &mut buf);
+    if read.is_ok() && crc32(&buf) != crc {
+        if let Some(fixed) = read_volume_block(f, idx_off, idx_len as u64)
+            .and_then(|b| b.get(&TAG_INDEX)?.recover(&buf))
+        {
+            buf = fixed;
+        }
+    }
+    if read.is_err() || crc32(&buf) != crc {
+        match read_head_copy(file_reader(f), idx_len, crc) {
+            Some(copy) => buf = copy,
+            None => read?,
+        }
+    }
+    decode_index(&buf, crc, limits)
+}
+
+/// Read the trailer and index of volume `rel_path` through a [`DataSource`].
+pub fn read_index_from(
+    src: &dyn DataSource,
+    rel_path: &str,
+    limits: &IndexLimits,
+) -> Result<Vec<VolumeEntry>> {
+    let flen = src.len(rel_path)?;
+    if flen < TRAILER_LEN {
+        bail!("too short");
+    }
+    if flen >= VOLUME_HEADER_FIXED as u64 {
+        let mut fixed = [0u8; VOLUME_HEADER_FIXED];
+        src.read_at(rel_path, 0, &mut fixed)?;
+        check_header_bytes(&fixed, read_mode())?;
+    }
+    let tail = TRAILER_V2_LEN.min(flen);
+    let mut tr = vec![0u8; tail as usize];
+    src.read_at(rel_path, flen - tail, &mut tr)?;
+    let read_at = |off, b: &mut [u8]| src.read_at(rel_path, off, b);
+    let parsed = parse_trailer(&tr).and_then(|(off, len, crc)| {
+        check_index_range(off, len, flen)?;
+        Ok((off, len, crc))
+    });
+    let (idx_off, idx_len, crc) = match parsed {
+        Err(e) => head_copy(read_at).map(|c| (c.idx_off, c.len, c.crc)).ok_or(e)?,
+        Ok(t) => t,
+    };
+    let idx_len = usize::try_from(idx_len).context("index too large for this platform")?;
+    let mut buf = vec![0u8; idx_len];
+    let read = src.read_at(rel_path, idx_off, &mut buf);
+    if read.is_ok() && crc32(&buf) != crc {
+        if let Some(fixed) = read_block(read_at, idx_off + idx_len as u64)
+            .and_then(|b| b.get(&TAG_INDEX)?.recover(&buf))
+        {
+            buf = fixed;
+        }
+    }
+    if read.is_err() || crc32(&buf) != crc {
+        match read_head_copy(read_at, idx_len, crc) {
+            Some(copy) => buf = copy,
+            None => read?,
+        }
+    }
+    decode_index(&buf, crc, limits)
+}
+
+fn crc32(b: &[u8]) -> u32 {
     let mut h = Crc32::new();
-    h.update(&buf);
-    let got = h.finalize();
-    if got != crc {
+    h.update(b);
+    h.finalize()
+}
+
+fn decode_index(buf: &[u8], crc: u32, limits: &IndexLimits) -> Result<Vec<VolumeEntry>> {
+    if crc32(buf) != crc {
         bail!("index CRC mismatch");
     }
     // Decompress with a guard on output size
-    let decompressed = zstd::stream::decode_all(&buf[..]).context("zstd decompress index")?;
-    if decompressed.len() > limits.max_uncompressed_bytes {
-        bail!("index too large: {} bytes", decompressed.len());
-    }
-    let entries: Vec<VolumeEntry> =
-        bincode::deserialize(&decompressed).context("bincode index decode")?;
+    let decompressed =
+        codec::decompress(buf, limits.max_uncompressed_bytes).context("decompress index")?;
+    let entries = decode_entries(&decompressed, force_old_reader()).context("decode index")?;
     if entries.len() > limits.max_entries {
         bail!("too many index entries");
     }
@@ -101,7 +380,7 @@ pub fn read_index(
 pub fn read_index_count(
     f: &mut File,
     idx_off: u64,
-    idx_len: u32,
+    idx_len: u64,
     crc: u32,
     limits: &IndexLimits,
 ) -> Result<usize> {
@@ -0,0 +1,184 @@
+//! Whether other processes are using a file that repair is about to patch
+//! (`repair --if-in-use`).
+//!
+//! Rewriting a database file or a log while its owner writes to it mixes
+//! the two sets of writes, and the owner's next write may undo the repair
+//! or, worse, land on top of a half-replaced file. Before a damaged file is
+//! patched, [`in_use`] looks for:
+//! - locks: `flock` and POSIX (`fcntl`) locks held by other processes on
+//!   Unix, byte-range locks on Windows;
+//! - writers: on Linux, other processes with the file open for writing
+//!   (found through `/proc/<pid>/fd`; processes of other users are only
+//!   seen when running as root); on Windows, any handle whose sharing mode
+//!   refuses a reader that denies writing.
+//!
+//! Elsewhere only locks are seen. A process that opens the file after the
+//! check is not noticed; the check narrows the window, it does not close it.
+
+use std::fs::File;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// What repair does with a damaged file another process is using.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IfInUse {
+    /// Leave it as it is and report it (`in_use_files`)
+    #[default]
+    Skip,
+    /// Wait for it to be free, up to [`WAIT_MAX`], then skip it
+    Wait,
+    /// Patch it anyway, without checking
+    Force,
+}
+
+/// Longest [`IfInUse::Wait`] waits for one file.
+pub const WAIT_MAX: Duration = Duration::from_secs(600);
+
+/// How often a waiting repair looks again.
+const WAIT_POLL: Duration = Duration::from_millis(500);
+
+/// Who is using `path`, e.g. "open for writing by pid 4242 (postgres)";
+/// `None` when nobody is, as far as this platform can tell, or the file
+/// does not exist.
+pub fn in_use(path: &Path) -> Option<String> {
+    let f = File::open(path).ok()?;
+    sys::locked(&f, path).or_else(|| sys::writers(path))
+}
+
+/// Apply `policy` to `path`: `None` once it may be patched, else why not.
+pub fn settle(path: &Path, policy: IfInUse) -> Option<String> {
+    if policy == IfInUse::Force {
+        return None;
+    }
+    let start = Instant::now();
+    loop {
+        let why = in_use(path)?;
+        if policy == IfInUse::Skip || start.elapsed() >= WAIT_MAX {
+            return Some(why);
+        }
+        std::thread::sleep(WAIT_POLL);
+    }
+}
+
+#[cfg(unix)]
+fn flocked(path: &Path) -> Option<String> {
+    use fs2::FileExt;
+    // A lock of our own on a handle of its own: held elsewhere, it fails
+    let f = File::open(path).ok()?;
+    match f.try_lock_exclusive() {
+        Ok(()) => None,
+        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => Some("locked (flock)".to_string()),
+        Err(_) => None,
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::fs::{self, File};
+    use std::os::unix::fs::MetadataExt;
+    use std::os::unix::io::AsRawFd;
+    use std::path::Path;
+
+    pub(super) fn locked(f: &File, path: &Path) -> Option<String> {
+        // SAFETY: `flock` is plain data; all-zero is a valid value
+        let mut fl: libc::flock = unsafe { std::mem::zeroed() };
+        fl.l_type = libc::F_WRLCK as libc::c_short;
+        fl.l_whence = libc::SEEK_SET as libc::c_short;
+        // SAFETY: `fl` outlives the call, which only writes into it
+        let r = unsafe { libc::fcntl(f.as_raw_fd(), libc::F_GETLK, &mut fl) };
+        if r == 0 && fl.l_type != libc::F_UNLCK as libc::c_short {
+            return Some(format!("locked (fcntl) by {}", process(fl.l_pid as u32)));
+        }
+        super::flocked(path)
+    }
+
+    pub(super) fn writers(path: &Path) -> Option<String> {
+        let md = fs::metadata(path).ok()?;
+        let me = std::process::id();
+        for ent in fs::read_dir("/proc").ok()?.flatten() {
+            let Some(pid) = ent.file_name().to_str().and_then(|s| s.parse::<u32>().ok()) else {
+                continue;
+            };
+            if pid == me {
+                continue;
+            }
+            // Processes of other users are not readable; skip them
+            let Ok(fds) = fs::read_dir(ent.path().join("fd")) else { continue };
+            for fd in fds.flatten() {
+                let same = fs::metadata(fd.path())
+                    .is_ok_and(|m| m.dev() == md.dev() && m.ino() == md.ino());
+                if same && writable(&ent.path().join("fdinfo").join(fd.file_name())) {
+                    return Some(format!("open for writing by {}", process(pid)));
+                }
+            }
+        }
+        None
+    }
+
+    /// Whether the descriptor described by `fdinfo` was opened for writing.
+    fn writable(fdinfo: &Path) -> bool {
+        let Ok(info) = fs::read_to_string(fdinfo) else { return false };
+        info.lines()
+            .find_map(|l| l.strip_prefix("flags:"))
+            .and_then(|f| i32::from_str_radix(f.trim(), 8).ok())
+            .is_some_and(|flags| flags & libc::O_ACCMODE != libc::O_RDONLY)
+    }
+
+    fn process(pid: u32) -> String {
+        match fs::read_to_string(format!("/proc/{}/comm", pid)) {
+            Ok(name) => format!("pid {} ({})", pid, name.trim()),
+            Err(_) => format!("pid {}", pid),
+        }
+    }
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+mod sys {
+    use std::fs::File;
+    use std::path::Path;
+
+    pub(super) fn locked(_f: &File, path: &Path) -> Option<String> {
+        super::flocked(path)
+    }
+
+    pub(super) fn writers(_path: &Path) -> Option<String> {
+        None
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use std::fs::{File, OpenOptions};
+    use std::os::windows::fs::OpenOptionsExt;
+    use std::path::Path;
+
+    /// `FILE_SHARE_READ`
+    const SHARE_READ: u32 = 0x1;
+    /// `ERROR_SHARING_VIOLATION`, `ERROR_LOCK_VIOLATION`
+    const SHARING_VIOLATION: i32 = 32;
+    const LOCK_VIOLATION: i32 = 33;
+
+    pub(super) fn locked(f: &File, _path: &Path) -> Option<String> {
+        use fs2::FileExt;
+        match f.try_lock_shared() {
+            Ok(()) => None,
+            Err(e) if e.raw_os_error() == Some(LOCK_VIOLATION) => {
+                Some("locked (byte-range lock)".to_string())
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                Some("locked (byte-range lock)".to_string())
+            }
+            Err(_) => None,
+        }
+    }
+
+    pub(super) fn writers(path: &Path) -> Option<String> {
+        // Denying writers fails while anyone has the file open for writing
+        match OpenOptions::new().read(true).share_mode(SHARE_READ).open(path) {
+            Err(e) if e.raw_os_error() == Some(SHARING_VIOLATION) => {
+                Some("open for writing by another process".to_string())
+            }
+            _ => None,
+        }
+    }
+}
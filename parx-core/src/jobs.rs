@@ -0,0 +1,257 @@
+//! Long-running operations as jobs, for front ends that run several of them
+//! and must stay responsive: the TUI, service mode and GUIs.
+//!
+//! A [`JobManager`] runs each job on a thread of its own and hands out a
+//! [`JobId`]. Front ends poll [`JobManager::status`] (state, the latest
+//! [`Snapshot`] of its stages, and the report or error once done), subscribe
+//! a [`ProgressSink`] to get snapshots pushed at the manager's interval, and
+//! [`JobManager::cancel`] jobs. Cancelling sets the flag of the job's
+//! [`Progress`]; the operation stops at its next safe point (verify and
+//! repair between files and before repair writes anything, create at the
+//! next journal segment, so `create --resume` continues it) and the job ends
+//! as [`JobState::Cancelled`].
+
+use crate::encode::{EncodeOptions, Encoder, EncoderConfig};
+use crate::path_safety::PathPolicy;
+use crate::progress::{Cancelled, Progress, ProgressSink, Snapshot};
+use crate::repair::RepairOptions;
+use crate::verify::VerifyOptions;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+pub type JobId = u64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    Running,
+    /// Cancel requested; the job has not reached a point to stop at yet
+    Cancelling,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+impl JobState {
+    pub fn is_finished(self) -> bool {
+        !matches!(self, JobState::Running | JobState::Cancelling)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct JobStatus {
+    pub id: JobId,
+    /// What the job runs: `verify`, `repair`, `create` or the kind given to
+    /// [`JobManager::spawn`]
+    pub kind: String,
+    pub state: JobState,
+    pub progress: Snapshot,
+    /// The job's report as JSON, once it succeeded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<serde_json::Value>,
+    /// Why it failed or was cancelled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// State, report and error of a finished job.
+type Outcome = (JobState, Option<serde_json::Value>, Option<String>);
+
+/// Passes each snapshot on to the job's subscribers.
+#[derive(Default)]
+struct Fanout(Mutex<Vec<Arc<dyn ProgressSink>>>);
+
+impl ProgressSink for Fanout {
+    fn report(&self, snap: &Snapshot) {
+        for sink in self.0.lock().expect("job subscribers").iter() {
+            sink.report(snap);
+        }
+    }
+}
+
+struct Job {
+    kind: String,
+    progress: Progress,
+    subscribers: Arc<Fanout>,
+    outcome: Arc<Mutex<Option<Outcome>>>,
+    /// Set once the last snapshot went out as well
+    ended: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl Job {
+    fn status(&self, id: JobId) -> JobStatus {
+        let outcome = self.outcome.lock().expect("job outcome").clone();
+        let (state, result, error) = outcome.unwrap_or_else(|| {
+            let state =
+                if self.progress.is_cancelled() { JobState::Cancelling } else { JobState::Running };
+            (state, None, None)
+        });
+        let progress = Snapshot { last: state.is_finished(), ..self.progress.snapshot() };
+        JobStatus { id, kind: self.kind.clone(), state, progress, result, error }
+    }
+}
+
+/// Registry of the jobs of a process. Share it behind an `Arc`.
+pub struct JobManager {
+    jobs: Mutex<BTreeMap<JobId, Job>>,
+    next: AtomicU64,
+    interval: Duration,
+}
+
+impl Default for JobManager {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(1))
+    }
+}
+
+impl JobManager {
+    /// Subscribers get a snapshot every `interval`.
+    pub fn new(interval: Duration) -> Self {
+        Self { jobs: Mutex::default(), next: AtomicU64::new(1), interval }
+    }
+
+    /// Run `run` as a job of `kind`. It gets the job's [`Progress`] to
+    /// count into and to check for cancellation; its report must serialize
+    /// to JSON.
+    pub fn spawn<R, F>(&self, kind: &str, run: F) -> JobId
+    where
+        R: Serialize,
+        F: FnOnce(Progress) -> Result<R> + Send + 'static,
+    {
+        let id = self.next.fetch_add(1, Ordering::Relaxed);
+        let job = Job {
+            kind: kind.to_string(),
+            progress: Progress::new(),
+            subscribers: Arc::default(),
+            outcome: Arc::default(),
+            ended: Arc::default(),
+        };
+        let (progress, outcome, ended) =
+            (job.progress.clone(), job.outcome.clone(), job.ended.clone());
+        let reporter = job.progress.start(job.subscribers.clone(), self.interval);
+        self.jobs.lock().expect("jobs").insert(id, job);
+        thread::spawn(move || {
+            let res = catch_unwind(AssertUnwindSafe(|| run(progress)))
+                .unwrap_or_else(|_| Err(anyhow!("job panicked")))
+                .and_then(|r| Ok(serde_json::to_value(r)?));
+            let done = match res {
+                Ok(v) => (JobState::Succeeded, Some(v), None),
+                Err(e) if e.downcast_ref::<Cancelled>().is_some() => {
+                    (JobState::Cancelled, None, Some(format!("{:#}", e)))
+                }
+                Err(e) => (JobState::Failed, None, Some(format!("{:#}", e))),
+            };
+            // Final before the last snapshot goes out
+            *outcome.lock().expect("job outcome") = Some(done);
+            drop(reporter);
+            *ended.0.lock().expect("job ended") = true;
+            ended.1.notify_all();
+        });
+        id
+    }
+
+    /// [`crate::verify::verify_with_options`] as a job (stage `verify`).
+    pub fn spawn_verify(&self, manifest: PathBuf, root: PathBuf, opts: VerifyOptions) -> JobId {
+        self.spawn("verify", move |progress| {
+            let opts = VerifyOptions { progress: Some(progress), ..opts };
+            crate::verify::verify_with_options(&manifest, &root, &opts)
+        })
+    }
+
+    /// [`crate::repair::repair_with_options`] as a job (stages `scan` and
+    /// `rebuild`).
+    pub fn spawn_repair(
+        &self,
+        manifest: PathBuf,
+        root: PathBuf,
+        policy: PathPolicy,
+        opts: RepairOptions,
+    ) -> JobId {
+        self.spawn("repair", move |progress| {
+            let opts = RepairOptions { progress: Some(progress), ..opts };
+            crate::repair::repair_with_options(&manifest, &root, policy, &opts)
+        })
+    }
+
+    /// [`Encoder::encode_with`] as a job (stages `hash`, `encode`, `outer`
+    /// and `write/vol-NNN`); the result is the manifest.
+    pub fn spawn_create(
+        &self,
+        root: PathBuf,
+        output: PathBuf,
+        cfg: EncoderConfig,
+        opts: EncodeOptions,
+    ) -> JobId {
+        self.spawn("create", move |progress| {
+            let opts = EncodeOptions { progress: Some(progress), ..opts };
+            Encoder::encode_with(&root, &output, &cfg, &opts)
+        })
+    }
+
+    pub fn status(&self, id: JobId) -> Option<JobStatus> {
+        self.jobs.lock().expect("jobs").get(&id).map(|j| j.status(id))
+    }
+
+    /// Every job not yet removed, oldest first.
+    pub fn list(&self) -> Vec<JobStatus> {
+        self.jobs.lock().expect("jobs").iter().map(|(&id, j)| j.status(id)).collect()
+    }
+
+    /// Push the job's snapshots to `sink` until it ends, the final one
+    /// marked `last` (right away for a job that already ended). False for
+    /// an unknown job.
+    pub fn subscribe(&self, id: JobId, sink: Arc<dyn ProgressSink>) -> bool {
+        let jobs = self.jobs.lock().expect("jobs");
+        let Some(job) = jobs.get(&id) else { return false };
+        // Held while checking, so a job ending now waits with its last
+        // snapshot until the sink is in
+        let mut sinks = job.subscribers.0.lock().expect("job subscribers");
+        let status = job.status(id);
+        if status.state.is_finished() {
+            drop(sinks);
+            sink.report(&status.progress);
+        } else {
+            sinks.push(sink);
+        }
+        true
+    }
+
+    /// Ask the job to stop; false for an unknown or finished job.
+    pub fn cancel(&self, id: JobId) -> bool {
+        let jobs = self.jobs.lock().expect("jobs");
+        let Some(job) = jobs.get(&id) else { return false };
+        if job.outcome.lock().expect("job outcome").is_some() {
+            return false;
+        }
+        job.progress.cancel();
+        true
+    }
+
+    /// Block until the job has ended and its subscribers got the last
+    /// snapshot.
+    pub fn wait(&self, id: JobId) -> Option<JobStatus> {
+        let ended = self.jobs.lock().expect("jobs").get(&id)?.ended.clone();
+        let (lock, cvar) = &*ended;
+        drop(cvar.wait_while(lock.lock().expect("job ended"), |done| !*done));
+        self.status(id)
+    }
+
+    /// Drop a finished job from the registry, returning its final status;
+    /// running jobs stay.
+    pub fn remove(&self, id: JobId) -> Option<JobStatus> {
+        let mut jobs = self.jobs.lock().expect("jobs");
+        let status = jobs.get(&id)?.status(id);
+        if !status.state.is_finished() {
+            return None;
+        }
+        jobs.remove(&id);
+        Some(status)
+    }
+}
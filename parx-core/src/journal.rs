@@ -0,0 +1,150 @@
+//! Create journal (`<parity dir>/create.journal`): the volume indices of a
+//! running `create`, written a segment at a time.
+//!
+//! Layout: `PARXJNL\0`, then records of `len u32 | crc32 u32 | payload`. The
+//! first record is the [`JournalHeader`] describing the run; each later one a
+//! zstd-compressed [`Segment`] with the index entries of the stripes finished
+//! since the previous segment. A segment is only appended after the volumes
+//! were synced, so every segment that checks out refers to shards on disk. A
+//! crash loses at most the stripes after the last segment; `create --resume`
+//! reads the intact prefix and carries on from there. The journal is removed
+//! once the final indices are written.
+
+use crate::volume::VolumeEntry;
+use anyhow::{bail, Context, Result};
+use crc32fast::Hasher as Crc32;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+pub const JOURNAL_FILE: &str = "create.journal";
+const MAGIC: &[u8; 8] = b"PARXJNL\0";
+/// Stripes per segment unless `EncodeOptions::segment_stripes` says otherwise.
+pub const DEFAULT_SEGMENT_STRIPES: usize = 1024;
+
+/// What a journal was written for; a resumed run must match it exactly.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct JournalHeader {
+    pub chunk_size: u64,
+    pub stripe_k: u64,
+    pub parity_shards: u64,
+    pub volumes: u64,
+    pub shard_copies: u64,
+    pub critical: Vec<String>,
+    pub critical_parity: u64,
+    pub parity_rules: Vec<crate::encode::ParityRule>,
+    pub total_chunks: u64,
+    /// Merkle root over the chunk hashes, i.e. the exact input
+    pub merkle_root_hex: String,
+    /// `--volume-max-size`, which decides where shards go (0 = none)
+    pub volume_max_size: u64,
+}
+
+/// Index entries of the stripes finished since the previous segment.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Segment {
+    /// Stripes `0..stripes_done` are complete
+    pub stripes_done: u64,
+    /// Length of each volume after the segment's shards
+    pub volume_lens: Vec<u64>,
+    /// New entries per volume
+    pub entries: Vec<Vec<VolumeEntry>>,
+}
+
+/// Appends segments to a journal.
+pub struct JournalWriter {
+    f: File,
+}
+
+impl JournalWriter {
+    /// Start a fresh journal in `dir`, replacing any previous one.
+    pub fn create(dir: &Path, header: &JournalHeader) -> Result<Self> {
+        let path = dir.join(JOURNAL_FILE);
+        let mut f = File::create(&path).with_context(|| format!("create {:?}", path))?;
+        f.write_all(MAGIC)?;
+        let mut w = JournalWriter { f };
+        w.append(&bincode::serialize(header).context("serialize journal header")?)?;
+        Ok(w)
+    }
+
+    /// Continue the journal in `dir` after its last intact record.
+    pub fn reopen(dir: &Path, intact_len: u64) -> Result<Self> {
+        let path = dir.join(JOURNAL_FILE);
+        let f = OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("open {:?}", path))?;
+        f.set_len(intact_len)?;
+        Ok(JournalWriter { f })
+    }
+
+    pub fn segment(&mut self, seg: &Segment) -> Result<()> {
+        let raw = bincode::serialize(seg).context("serialize journal segment")?;
+        self.append(&zstd::stream::encode_all(&raw[..], 0).context("zstd compress segment")?)
+    }
+
+    fn append(&mut self, payload: &[u8]) -> Result<()> {
+        let mut h = Crc32::new();
+        h.update(payload);
+        let len = u32::try_from(payload.len()).context("journal segment over 4 GiB")?;
+        let mut rec = Vec::with_capacity(8 + payload.len());
+        rec.extend_from_slice(&len.to_le_bytes());
+        rec.extend_from_slice(&h.finalize().to_le_bytes());
+        rec.extend_from_slice(payload);
+        self.f.write_all(&rec)?;
+        self.f.sync_data()?;
+        Ok(())
+    }
+}
+
+/// The intact part of a journal.
+#[derive(Debug)]
+pub struct Journal {
+    pub header: JournalHeader,
+    pub segments: Vec<Segment>,
+    /// Bytes up to the end of the last intact record
+    pub intact_len: u64,
+}
+
+impl Journal {
+    pub fn path(dir: &Path) -> PathBuf {
+        dir.join(JOURNAL_FILE)
+    }
+
+    /// Read the journal in `dir`, stopping at the first torn or damaged record.
+    pub fn load(dir: &Path) -> Result<Self> {
+        let path = Self::path(dir);
+        let mut data = Vec::new();
+        File::open(&path)
+            .with_context(|| format!("no create journal at {:?}; nothing to resume", path))?
+            .read_to_end(&mut data)?;
+        if data.len() < MAGIC.len() || &data[..MAGIC.len()] != MAGIC {
+            bail!("{:?} is not a create journal", path);
+        }
+        let mut pos = MAGIC.len();
+        let mut records: Vec<&[u8]> = Vec::new();
+        while let Some(rec) = data.get(pos..pos + 8) {
+            let len = u32::from_le_bytes(rec[..4].try_into().unwrap()) as usize;
+            let crc = u32::from_le_bytes(rec[4..].try_into().unwrap());
+            let Some(payload) = data.get(pos + 8..pos + 8 + len) else { break };
+            let mut h = Crc32::new();
+            h.update(payload);
+            if h.finalize() != crc {
+                break;
+            }
+            records.push(payload);
+            pos += 8 + len;
+        }
+        let (first, rest) = records.split_first().context("create journal has no intact header")?;
+        let header: JournalHeader = bincode::deserialize(first).context("decode journal header")?;
+        let segments = rest
+            .iter()
+            .map(|p| {
+                let raw = zstd::stream::decode_all(*p).context("zstd decompress segment")?;
+                bincode::deserialize(&raw).context("decode journal segment")
+            })
+            .collect::<Result<Vec<Segment>>>()?;
+        Ok(Journal { header, segments, intact_len: pos as u64 })
+    }
+}
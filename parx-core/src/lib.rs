@@ -1,13 +1,140 @@
+//! ParXive core. The default `full` feature builds everything the CLI uses;
+//! `default-features = false, features = ["minimal"]` keeps just manifest
+//! parsing, [`merkle`] and `verify` with a small dependency tree. Without the
+//! `std` feature the crate is `no_std` + `alloc`: [`merkle`] (chunk hashing
+//! and roots), [`ext`] and manifest parsing remain. The `async` feature
+//! adds tokio variants of verify and repair (`async_api`); `uring` batches
+//! their reads, and create's, through io_uring on Linux ([`uring`]).
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "async")]
+pub mod async_api;
+#[cfg(feature = "full")]
+pub mod audit;
+#[cfg(feature = "full")]
+pub mod audit_log;
+#[cfg(feature = "full")]
+pub mod backend;
+#[cfg(feature = "full")]
+pub mod checkall;
+#[cfg(feature = "full")]
+pub mod codec;
+#[cfg(feature = "std")]
 pub mod cuda_backend;
+#[cfg(feature = "full")]
+pub mod direct;
+#[cfg(feature = "full")]
+pub mod domains;
+#[cfg(feature = "full")]
 pub mod encode;
+#[cfg(feature = "std")]
+pub mod export;
+pub mod ext;
+#[cfg(feature = "std")]
+pub mod fdlimit;
+#[cfg(feature = "full")]
+pub mod filter;
+#[cfg(feature = "full")]
+pub mod fsck;
+#[cfg(feature = "full")]
+pub mod fshint;
+pub mod geometry;
+#[cfg(feature = "full")]
+pub mod hashcache;
+#[cfg(feature = "full")]
+pub mod heal;
+#[cfg(any(feature = "full", feature = "windows-meta"))]
+mod hex;
+#[cfg(feature = "full")]
 pub mod index;
+#[cfg(feature = "full")]
+pub mod inuse;
+#[cfg(feature = "full")]
+pub mod jobs;
+#[cfg(feature = "full")]
+pub mod journal;
+#[cfg(feature = "full")]
+pub mod live;
+#[cfg(feature = "i18n")]
 pub mod localize;
 pub mod manifest;
+#[cfg(feature = "full")]
+pub mod manifest_backup;
+pub mod manifest_v2;
+pub mod media;
 pub mod merkle;
+pub mod meta;
+#[cfg(feature = "full")]
+pub mod metaparity;
+#[cfg(feature = "std")]
+pub mod moved;
+#[cfg(feature = "full")]
+pub mod outer;
+#[cfg(feature = "full")]
+pub mod pack;
+#[cfg(feature = "std")]
+pub mod pagecache;
+#[cfg(feature = "full")]
 pub mod parity_audit;
+#[cfg(feature = "full")]
+pub mod paritycache;
+#[cfg(feature = "std")]
 pub mod path_safety;
+#[cfg(feature = "std")]
 pub mod progress;
+#[cfg(feature = "full")]
+pub mod publish;
+#[cfg(feature = "full")]
+pub mod query;
+#[cfg(feature = "full")]
+pub mod rebalance;
+#[cfg(feature = "full")]
 pub mod repair;
+#[cfg(feature = "std")]
+pub mod report;
+#[cfg(feature = "std")]
+pub mod resync;
+#[cfg(feature = "std")]
+pub mod retry;
+#[cfg(feature = "full")]
 pub mod rs_codec;
+#[cfg(feature = "std")]
+pub mod scrub;
+#[cfg(feature = "full")]
+pub mod serve;
+#[cfg(feature = "full")]
+pub mod sets;
+#[cfg(feature = "full")]
+pub mod sign;
+#[cfg(feature = "std")]
+pub mod storage;
+pub mod stub;
+#[cfg(feature = "full")]
+pub mod submanifest;
+#[cfg(feature = "std")]
+pub mod symlink;
+#[cfg(feature = "full")]
+pub mod tarstream;
+#[cfg(feature = "std")]
+pub mod throttle;
+#[cfg(feature = "full")]
+pub mod update;
+#[cfg(feature = "std")]
+pub mod uring;
+#[cfg(feature = "full")]
+pub mod usage;
+#[cfg(feature = "std")]
 pub mod verify;
-pub mod volume; // new
+#[cfg(feature = "full")]
+pub mod versions;
+#[cfg(feature = "full")]
+pub mod volparity;
+#[cfg(feature = "full")]
+pub mod volume;
+#[cfg(feature = "full")]
+pub mod volwriter;
+#[cfg(feature = "std")]
+pub mod watchdog;
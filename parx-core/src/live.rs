@@ -0,0 +1,126 @@
+//! Live state of running operations, for `parx top`. A run publishes its
+//! progress [`Snapshot`] to `running/<op>-<pid>.json` in its parity dir
+//! through a [`StateFileSink`], rewriting the file (by rename, so readers
+//! never see half of it) at every report and removing it when done. [`running`]
+//! collects those of a parity dir and of the named sets under it.
+
+use crate::progress::{ProgressSink, Snapshot};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Directory of a parity dir holding the state files of running operations.
+pub const RUNNING_DIR: &str = "running";
+
+/// What a running operation last published about itself.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RunState {
+    /// The command, e.g. "create"
+    pub op: String,
+    pub pid: u32,
+    /// Parity dir the operation works on
+    pub dir: String,
+    /// When the state was written, in milliseconds since the Unix epoch
+    pub updated_ms: u64,
+    /// How often the state is rewritten
+    pub interval_ms: u64,
+    pub snapshot: Snapshot,
+}
+
+impl RunState {
+    /// No update for several intervals: the run was most likely killed
+    /// before it could remove its state file.
+    pub fn is_stale(&self) -> bool {
+        let late = self.interval_ms.saturating_mul(3) + 5_000;
+        now_ms().saturating_sub(self.updated_ms) > late
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64)
+}
+
+/// Publishes snapshots to the run's state file; removes it on the last one.
+pub struct StateFileSink {
+    path: PathBuf,
+    op: String,
+    dir: String,
+    interval: Duration,
+}
+
+impl StateFileSink {
+    /// State file of operation `op` of this process in `parity_dir`.
+    pub fn new(parity_dir: &Path, op: &str, interval: Duration) -> Result<Self> {
+        let running = parity_dir.join(RUNNING_DIR);
+        std::fs::create_dir_all(&running).with_context(|| format!("create dir {:?}", running))?;
+        Ok(Self {
+            path: running.join(format!("{}-{}.json", op, std::process::id())),
+            op: op.to_string(),
+            dir: parity_dir.display().to_string(),
+            interval,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn write(&self, snap: &Snapshot) -> Result<()> {
+        let state = RunState {
+            op: self.op.clone(),
+            pid: std::process::id(),
+            dir: self.dir.clone(),
+            updated_ms: now_ms(),
+            interval_ms: self.interval.as_millis() as u64,
+            snapshot: snap.clone(),
+        };
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec(&state)?)?;
+        std::fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+impl ProgressSink for StateFileSink {
+    fn report(&self, snap: &Snapshot) {
+        if snap.last {
+            let _ = std::fs::remove_file(&self.path);
+            // Only goes when no other run still uses it
+            if let Some(dir) = self.path.parent() {
+                let _ = std::fs::remove_dir(dir);
+            }
+        } else {
+            // Progress reporting never fails the run
+            let _ = self.write(snap);
+        }
+    }
+}
+
+/// Running operations of `parity_dir` and of its named sets, oldest first.
+/// State files that do not parse (being replaced on a platform without
+/// atomic rename) are skipped.
+pub fn running(parity_dir: &Path) -> Result<Vec<RunState>> {
+    let mut dirs = vec![parity_dir.join(RUNNING_DIR)];
+    let sets = parity_dir.join(crate::sets::SETS_DIR);
+    if sets.is_dir() {
+        for ent in std::fs::read_dir(&sets).with_context(|| format!("read_dir {:?}", sets))? {
+            dirs.push(ent?.path().join(RUNNING_DIR));
+        }
+    }
+    let mut out = Vec::new();
+    for dir in dirs.iter().filter(|d| d.is_dir()) {
+        for ent in std::fs::read_dir(dir).with_context(|| format!("read_dir {:?}", dir))? {
+            let path = ent?.path();
+            if path.extension().is_some_and(|e| e == "json") {
+                if let Some(state) =
+                    std::fs::read(&path).ok().and_then(|b| serde_json::from_slice(&b).ok())
+                {
+                    out.push(state);
+                }
+            }
+        }
+    }
+    out.sort_by_key(|s: &RunState| (s.updated_ms.saturating_sub(s.snapshot.elapsed_ms), s.pid));
+    Ok(out)
+}
@@ -1,18 +1,69 @@
+use crate::ext::ExtMap;
+use crate::geometry::Geometry;
+#[cfg(feature = "std")]
+use crate::manifest_v2::{self, RecoveryReport};
+use crate::media::MediaLayout;
+use crate::meta::FileMeta;
+use alloc::string::String;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "std")]
+use std::fs::File;
+#[cfg(feature = "std")]
+use std::io::Write;
+#[cfg(feature = "std")]
+use std::path::{Path, PathBuf};
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct FileEntry {
+    /// Id of the file within its set, kept by `update` for as long as the
+    /// file exists (0 = none; manifests written before ids were assigned)
+    #[serde(default, skip_serializing_if = "is_zero_u64")]
+    pub id: u64,
     pub rel_path: String,
     pub size: u64,
     pub chunks: Vec<ChunkRef>,
+    /// Permissions/ownership to re-apply when the file is rewritten
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub meta: Option<FileMeta>,
+    /// Container alignment of the chunks (`create --media-align`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media: Option<MediaLayout>,
+}
+
+/// A symbolic link of the protected tree (see `symlink`).
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SymlinkEntry {
+    pub rel_path: String,
+    /// The link's target as stored in the link, not resolved
+    pub target: String,
+}
+
+/// Most chunks one file may have: [`ChunkId::ordinal`] counts them in 32 bits.
+pub const MAX_FILE_CHUNKS: u64 = u32::MAX as u64 + 1;
+
+/// Identity of a chunk that does not depend on the layout: its file's id and
+/// its position in the file. [`ChunkRef::idx`] is the slot the chunk occupies
+/// in the stripe layout (stripe `idx / stripe_k`), which `update` may change.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ChunkId {
+    pub file: u64,
+    pub ordinal: u32,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ChunkRef {
+    /// Slot in the stripe layout
     pub idx: u64,
     pub file_offset: u64,
     pub len: u32,
     pub hash_hex: String,
+    /// Rolling checksum of the chunk's bytes, to find it again after data
+    /// before it shifted (see `resync`); absent in sets from older versions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub weak: Option<u32>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -20,13 +71,583 @@ pub struct Manifest {
     pub created_utc: String,
     pub chunk_size: usize,
     pub stripe_k: usize,
+    /// Parity percentage the set was created with; informational once
+    /// `parity_shards` is recorded
     pub parity_pct: u32,
+    /// Inner parity shards of a plain stripe (`m`); absent in sets from older
+    /// versions, which derive it from `parity_pct` (see [`Manifest::parity_shards`])
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parity_shards: Option<usize>,
     pub total_bytes: u64,
     pub total_chunks: u64,
     pub files: Vec<FileEntry>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub symlinks: Vec<SymlinkEntry>,
+    /// Next [`FileEntry::id`] to hand out; ids of removed files are not reused
+    #[serde(default, skip_serializing_if = "is_zero_u64")]
+    pub next_file_id: u64,
     pub merkle_root_hex: String,
     pub parity_dir: String,
     pub volumes: usize,
     pub outer_group: usize,
     pub outer_parity: usize,
+    /// Number of volumes each parity shard was written to
+    #[serde(default = "one")]
+    pub shard_copies: usize,
+    /// Exclude patterns the set was scanned with (reused by `update`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+    /// Patterns of files whose stripes carry `critical_parity` extra inner
+    /// shards (indexed as parity_idx `m..`); the shards are a prefix-extension
+    /// of the stripe's RS code, so any subset of them helps decoding
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub critical: Vec<String>,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub critical_parity: usize,
+    /// Files given their own parity level by `create --parity-rule`, each
+    /// laid out in stripes of its own; other stripes carry `parity_pct`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub parity_groups: Vec<ParityGroup>,
+    /// Parity files over the whole volumes (`create --volume-parity`, see
+    /// `volparity`): this many volumes may be lost outright
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub volume_parity: usize,
+    /// Label, notes and contact attached by the user
+    #[serde(default, skip_serializing_if = "SetInfo::is_empty")]
+    pub info: SetInfo,
+    /// Recovery executables copied into the parity dir
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub recovery_stubs: Vec<crate::stub::RecoveryStub>,
+    /// Extension map for forward-compatible metadata (see `ext`)
+    #[serde(default, skip_serializing_if = "ExtMap::is_empty")]
+    pub ext: ExtMap,
+    /// Key of a set with keyed chunk hashes, once given (see
+    /// [`Manifest::use_hash_key`]); never written out
+    #[serde(skip)]
+    pub hash_key: Option<HashKey>,
+    /// What a signature over a signed manifest covers, taken from the
+    /// manifest.json it was read from (see [`signing_form`]); never written
+    /// out, and dropped when the manifest is signed again
+    #[serde(skip)]
+    pub signed_form: Option<Vec<u8>>,
+}
+
+/// The files matched by one `--parity-rule` and the stripes they fill. Like
+/// critical parity, the shards past the set's `m` extend the stripe's RS
+/// code, so one codec of the largest size decodes every stripe.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ParityGroup {
+    /// Pattern of the rule (same syntax as `exclude`)
+    pub pattern: String,
+    pub parity_pct: u32,
+    /// Inner parity shards of each stripe in the group
+    pub parity_shards: usize,
+    /// The group holds stripes `first_stripe..first_stripe + stripes`
+    pub first_stripe: u64,
+    pub stripes: u64,
+}
+
+/// Human-readable description of a set, for whoever finds the media later.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SetInfo {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub contact: Option<String>,
+}
+
+impl SetInfo {
+    pub fn is_empty(&self) -> bool {
+        self.label.is_none() && self.notes.is_none() && self.contact.is_none()
+    }
+
+    /// Store the fields under their extension keys (volume headers).
+    pub fn to_ext(&self, ext: &mut ExtMap) {
+        use crate::ext::key;
+        for (k, v) in
+            [(key::LABEL, &self.label), (key::NOTES, &self.notes), (key::CONTACT, &self.contact)]
+        {
+            if let Some(v) = v {
+                ext.insert(k, v.as_bytes().to_vec());
+            }
+        }
+    }
+
+    pub fn from_ext(ext: &ExtMap) -> Self {
+        use crate::ext::key;
+        let get = |k| ext.get(k).map(|v| String::from_utf8_lossy(v).into_owned());
+        SetInfo { label: get(key::LABEL), notes: get(key::NOTES), contact: get(key::CONTACT) }
+    }
+}
+
+/// Random id given to a set at create and written to its manifest and to
+/// every volume header, so that a volume can be told apart from those of
+/// another set with the same layout.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SetId(pub [u8; 16]);
+
+impl SetId {
+    /// A fresh random (version 4) UUID.
+    #[cfg(feature = "full")]
+    pub fn random() -> Result<Self> {
+        let mut b = [0u8; 16];
+        getrandom::getrandom(&mut b).map_err(|e| anyhow::anyhow!("getrandom: {}", e))?;
+        b[6] = (b[6] & 0x0f) | 0x40;
+        b[8] = (b[8] & 0x3f) | 0x80;
+        Ok(SetId(b))
+    }
+
+    pub fn to_ext(self, ext: &mut ExtMap) {
+        ext.insert(crate::ext::key::SET_ID, self.0.to_vec());
+    }
+
+    /// `None` on sets made before set ids existed.
+    pub fn from_ext(ext: &ExtMap) -> Option<Self> {
+        ext.get(crate::ext::key::SET_ID).and_then(|v| v.try_into().ok()).map(SetId)
+    }
+}
+
+impl core::fmt::Display for SetId {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for (i, b) in self.0.iter().enumerate() {
+            if matches!(i, 4 | 6 | 8 | 10) {
+                f.write_str("-")?;
+            }
+            write!(f, "{:02x}", b)?;
+        }
+        Ok(())
+    }
+}
+
+/// What a volume header says about the set it belongs to: the set id and
+/// generation written at create and kept current by `update`, `forget` and
+/// `rebalance`. The manifest's tag is what the volumes must match.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SetTag {
+    pub id: Option<SetId>,
+    pub generation: Option<u64>,
+}
+
+/// Why a volume does not belong with a manifest (see [`SetTag::stray`]).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stray {
+    /// The volume belongs to another set
+    Foreign(SetId),
+    /// The volume belongs to the set, but to another generation of it: left
+    /// over from a create or update the manifest has since replaced, or
+    /// written by one the manifest does not know of yet
+    Stale { theirs: Option<u64>, ours: u64 },
+}
+
+impl SetTag {
+    pub fn from_ext(ext: &ExtMap) -> Self {
+        SetTag { id: SetId::from_ext(ext), generation: ext.get_u64(crate::ext::key::GENERATION) }
+    }
+
+    /// Why a volume tagged `theirs` does not belong with the set tagged
+    /// `self`; `None` when it does, and when either side predates the part
+    /// of the tag that would tell.
+    pub fn stray(&self, theirs: &SetTag) -> Option<Stray> {
+        match (self.id, theirs.id) {
+            (Some(ours), Some(id)) if ours != id => return Some(Stray::Foreign(id)),
+            (Some(_), None) => return None,
+            _ => {}
+        }
+        let ours = self.generation?;
+        (theirs.generation != Some(ours))
+            .then_some(Stray::Stale { theirs: theirs.generation, ours })
+    }
+}
+
+impl core::fmt::Display for Stray {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Stray::Foreign(id) => write!(f, "set {}", id),
+            Stray::Stale { theirs: Some(g), ours } => {
+                write!(f, "generation {}, manifest is generation {}", g, ours)
+            }
+            Stray::Stale { theirs: None, ours } => {
+                write!(f, "no generation, manifest is generation {}", ours)
+            }
+        }
+    }
+}
+
+/// Key for chunk hashes (`create --hash-key`): with it the recorded hashes
+/// are keyed BLAKE3, so a manifest or an index shows nothing about the data
+/// to whoever lacks the key, not even whether two chunks are equal to a
+/// guess. The manifest records only the key's [`HashKey::id`].
+#[derive(Clone, PartialEq, Eq)]
+pub struct HashKey(pub [u8; 32]);
+
+impl HashKey {
+    /// The key for `secret`, the contents of a key file.
+    pub fn from_secret(secret: &[u8]) -> Self {
+        HashKey(blake3::derive_key("parx 2024 chunk hash key", secret))
+    }
+
+    /// Read a key file: any secret of at least 16 bytes.
+    #[cfg(feature = "std")]
+    pub fn load(path: &Path) -> Result<Self> {
+        let secret =
+            std::fs::read(path).with_context(|| format!("read hash key {}", path.display()))?;
+        anyhow::ensure!(
+            secret.len() >= 16,
+            "hash key {} is too short ({} bytes; at least 16)",
+            path.display(),
+            secret.len()
+        );
+        Ok(Self::from_secret(&secret))
+    }
+
+    /// Short fingerprint recorded in the manifest, to tell a wrong key from
+    /// damaged data.
+    pub fn id(&self) -> String {
+        let h = blake3::keyed_hash(&self.0, b"parx hash key id");
+        String::from(&h.to_hex()[..16])
+    }
+}
+
+impl core::fmt::Debug for HashKey {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "HashKey({})", self.id())
+    }
+}
+
+/// `HASH_MODE` value of sets with keyed chunk hashes.
+const KEYED_BLAKE3: &str = "keyed-blake3";
+
+fn one() -> usize {
+    1
+}
+
+fn is_zero(v: &usize) -> bool {
+    *v == 0
+}
+
+fn is_zero_u64(v: &u64) -> bool {
+    *v == 0
+}
+
+impl Manifest {
+    /// The set's parity dir: `parity_dir` as recorded, or the directory of
+    /// `manifest_path` when the set was moved as a whole and the recorded one
+    /// no longer exists. A sub-manifest lives two levels below its set, in
+    /// `sub/<dir>/`.
+    #[cfg(feature = "std")]
+    pub fn parity_dir_at(&self, manifest_path: &Path) -> PathBuf {
+        let recorded = PathBuf::from(&self.parity_dir);
+        if recorded.is_dir() {
+            return recorded;
+        }
+        let mut dir = manifest_path.parent();
+        if self.ext.get(crate::ext::key::SUBSET).is_some() {
+            dir = dir.and_then(Path::parent).and_then(Path::parent);
+        }
+        match dir {
+            Some(p) if p.is_dir() => p.to_path_buf(),
+            _ => recorded,
+        }
+    }
+
+    /// A set without data: no files, or only empty ones. It verifies clean
+    /// and has nothing to rebuild, but stays valid so that `update` can fill it.
+    pub fn is_empty(&self) -> bool {
+        self.files.iter().all(|fe| fe.chunks.is_empty())
+    }
+
+    /// The set id recorded at create, if any.
+    pub fn set_id(&self) -> Option<SetId> {
+        SetId::from_ext(&self.ext)
+    }
+
+    /// Generation of the set (see [`SetTag`]); `None` for sets made before
+    /// generations were recorded.
+    pub fn generation(&self) -> Option<u64> {
+        self.ext.get_u64(crate::ext::key::GENERATION)
+    }
+
+    /// Count the generation up, before the volumes are rewritten for a new
+    /// state of the set; sets without one are left without.
+    pub fn next_generation(&mut self) {
+        if let Some(g) = self.generation() {
+            self.ext.insert_u64(crate::ext::key::GENERATION, g + 1);
+        }
+    }
+
+    /// The tag the set's volumes must carry.
+    pub fn set_tag(&self) -> SetTag {
+        SetTag::from_ext(&self.ext)
+    }
+
+    /// Record that the set's chunk hashes are keyed with `key`.
+    pub fn set_hash_key(&mut self, key: HashKey) {
+        let mode = alloc::format!("{} {}", KEYED_BLAKE3, key.id());
+        self.ext.insert(crate::ext::key::HASH_MODE, mode.into_bytes());
+        self.hash_key = Some(key);
+    }
+
+    /// The hash mode recorded at create; `None` for plain BLAKE3.
+    pub fn hash_mode(&self) -> Option<&str> {
+        self.ext.get(crate::ext::key::HASH_MODE).map(|v| core::str::from_utf8(v).unwrap_or("?"))
+    }
+
+    /// Take the key a keyed set needs before its chunks are checked. Fails
+    /// if it needs one and `key` is none or another one, if it needs none
+    /// and got one, or if it was made with a hash mode this build lacks.
+    #[cfg(feature = "std")]
+    pub fn use_hash_key(&mut self, key: Option<HashKey>) -> Result<()> {
+        match (self.hash_mode(), key) {
+            (None, None) => Ok(()),
+            (None, Some(_)) => {
+                anyhow::bail!("the set has plain chunk hashes; drop --hash-key")
+            }
+            (Some(mode), key) => {
+                let Some(id) = mode.strip_prefix(KEYED_BLAKE3).map(str::trim) else {
+                    anyhow::bail!("unknown chunk hash mode `{}`; a newer parx made the set", mode)
+                };
+                let Some(key) = key else {
+                    anyhow::bail!("the set has keyed chunk hashes; pass the key with --hash-key")
+                };
+                anyhow::ensure!(
+                    key.id() == id,
+                    "wrong hash key: the set was made with key {}, not {}",
+                    id,
+                    key.id()
+                );
+                self.hash_key = Some(key);
+                Ok(())
+            }
+        }
+    }
+
+    /// Fail for sets with keyed chunk hashes, for commands that do not
+    /// take the key.
+    #[cfg(feature = "std")]
+    pub fn require_plain_hashes(&self, what: &str) -> Result<()> {
+        if self.hash_mode().is_some() {
+            anyhow::bail!(
+                "{} does not support sets with keyed chunk hashes (`create --hash-key`)",
+                what
+            );
+        }
+        Ok(())
+    }
+
+    /// Hashes chunks as the set records them; keyed once
+    /// [`Manifest::use_hash_key`] took the key.
+    pub fn hasher(&self) -> crate::merkle::ChunkHasher {
+        crate::merkle::ChunkHasher::new(self.chunk_size, self.hash_key.as_ref().map(|k| k.0))
+    }
+
+    /// File name of volume `vid`, by the set's `--volume-name` template.
+    #[cfg(feature = "full")]
+    pub fn volume_name(&self, vid: usize) -> String {
+        let template = self.ext.get(crate::ext::key::VOLUME_NAME);
+        crate::volume::vol_name_by(template.and_then(|t| core::str::from_utf8(t).ok()), vid)
+    }
+
+    /// Directories besides the parity dir that hold volumes of the set, for
+    /// sets made with `--output-dirs`.
+    #[cfg(feature = "full")]
+    pub fn volume_dirs(&self) -> Vec<PathBuf> {
+        self.ext
+            .get(crate::ext::key::VOLUME_DIRS)
+            .and_then(|v| core::str::from_utf8(v).ok())
+            .map(|text| text.lines().filter(|l| !l.is_empty()).map(PathBuf::from).collect())
+            .unwrap_or_default()
+    }
+
+    /// Path of volume `vid` of the set whose manifest is in `parity_dir`.
+    #[cfg(feature = "full")]
+    pub fn volume_path(&self, parity_dir: &Path, vid: usize) -> PathBuf {
+        let dirs = self.volume_dirs();
+        let name = self.volume_name(vid);
+        match vid % (dirs.len() + 1) {
+            0 => parity_dir.join(name),
+            d => dirs[d - 1].join(name),
+        }
+    }
+
+    /// Byte size no volume exceeds, for sets made with `--volume-max-size`.
+    pub fn volume_max_size(&self) -> Option<u64> {
+        self.ext.get_u64(crate::ext::key::VOLUME_MAX_SIZE)
+    }
+
+    /// Volume of copy `c` of inner parity shard `pi` of stripe `s`.
+    #[cfg(feature = "full")]
+    pub fn inner_shard_volume(&self, s: u64, pi: usize, c: usize) -> usize {
+        let base = if self.volume_max_size().is_some() { s } else { 0 };
+        crate::volume::shard_volume(base, pi, c, self.volumes.max(1))
+    }
+
+    /// Inner parity shards of a plain stripe.
+    pub fn parity_shards(&self) -> usize {
+        self.parity_shards
+            .unwrap_or_else(|| Geometry::parity_for_pct(self.stripe_k, self.parity_pct))
+    }
+
+    pub fn geometry(&self) -> Geometry {
+        Geometry::from_manifest(self)
+    }
+
+    /// Inner parity shards of `stripe`: its parity group's, else those of a
+    /// plain stripe (critical-file extras not counted).
+    pub fn parity_shards_of(&self, stripe: u64) -> usize {
+        self.parity_groups
+            .iter()
+            .find(|g| (g.first_stripe..g.first_stripe + g.stripes).contains(&stripe))
+            .map_or_else(|| self.parity_shards(), |g| g.parity_shards)
+    }
+
+    /// Most inner parity shards any stripe may hold, counting critical
+    /// stripes and parity groups; the size of the codec that decodes them all.
+    pub fn max_parity_shards(&self) -> usize {
+        let m = self.parity_shards();
+        let groups = self.parity_groups.iter().map(|g| g.parity_shards);
+        groups.fold(m + self.critical_parity, usize::max)
+    }
+
+    /// Give every file without an id the next unused one, in manifest order.
+    pub fn assign_file_ids(&mut self) {
+        let max = self.files.iter().map(|f| f.id).max().unwrap_or(0);
+        let mut next = self.next_file_id.max(max + 1);
+        for fe in self.files.iter_mut().filter(|f| f.id == 0) {
+            fe.id = next;
+            next += 1;
+        }
+        self.next_file_id = next;
+    }
+
+    /// The stripe assignment: which chunk occupies each slot, by slot. Files
+    /// without an id are left out.
+    pub fn slot_table(&self) -> alloc::collections::BTreeMap<u64, ChunkId> {
+        self.files
+            .iter()
+            .filter(|fe| fe.id != 0)
+            .flat_map(|fe| {
+                fe.chunks
+                    .iter()
+                    .enumerate()
+                    // Below MAX_FILE_CHUNKS, checked when the file was chunked
+                    .map(move |(i, ch)| (ch.idx, ChunkId { file: fe.id, ordinal: i as u32 }))
+            })
+            .collect()
+    }
+}
+
+pub const MANIFEST_JSON: &str = "manifest.json";
+pub const MANIFEST_V2: &str = "manifest.v2";
+
+/// The bytes `save` writes to `manifest.json`.
+#[cfg(feature = "std")]
+pub fn to_json(mf: &Manifest) -> Result<Vec<u8>> {
+    Ok(serde_json::to_vec_pretty(mf)?)
+}
+
+/// The bytes a manifest signature covers, from the manifest's JSON as
+/// stored: the object without `parity_dir` (which only says where the set
+/// lives now) and without the signature, keys sorted, no whitespace. Fields
+/// this version does not know are covered like any other, and fields are
+/// covered as written, not as this version would write them.
+#[cfg(feature = "std")]
+pub fn signing_form(mut json: serde_json::Value) -> Result<Vec<u8>> {
+    use serde_json::Value;
+    let Some(obj) = json.as_object_mut() else {
+        anyhow::bail!("manifest is not a JSON object");
+    };
+    obj.remove("parity_dir");
+    let sig = crate::ext::key::MANIFEST_SIG.to_string();
+    if let Some(Value::Object(ext)) = obj.get_mut("ext") {
+        ext.remove(&sig);
+        if ext.is_empty() {
+            obj.remove("ext");
+        }
+    }
+    fn write(v: &Value, out: &mut Vec<u8>) -> Result<()> {
+        match v {
+            Value::Object(m) => {
+                let mut keys: Vec<&String> = m.keys().collect();
+                keys.sort();
+                out.push(b'{');
+                for (i, k) in keys.into_iter().enumerate() {
+                    if i > 0 {
+                        out.push(b',');
+                    }
+                    out.extend(serde_json::to_vec(k)?);
+                    out.push(b':');
+                    write(&m[k], out)?;
+                }
+                out.push(b'}');
+            }
+            Value::Array(a) => {
+                out.push(b'[');
+                for (i, e) in a.iter().enumerate() {
+                    if i > 0 {
+                        out.push(b',');
+                    }
+                    write(e, out)?;
+                }
+                out.push(b']');
+            }
+            other => out.extend(serde_json::to_vec(other)?),
+        }
+        Ok(())
+    }
+    let mut out = Vec::new();
+    write(&json, &mut out)?;
+    Ok(out)
+}
+
+/// Parse manifest.json text, keeping what a signature covers when the
+/// manifest is signed.
+#[cfg(feature = "std")]
+fn from_json(raw: &[u8]) -> serde_json::Result<Manifest> {
+    let mut mf = serde_json::from_slice::<Manifest>(raw)?;
+    if mf.ext.get(crate::ext::key::MANIFEST_SIG).is_some() {
+        mf.signed_form = serde_json::from_slice(raw).ok().and_then(|v| signing_form(v).ok());
+    }
+    Ok(mf)
+}
+
+/// Write `manifest.json` plus its checksummed v2 companion into `dir`.
+#[cfg(feature = "std")]
+pub fn save(mf: &Manifest, dir: &Path) -> Result<()> {
+    let mut f = File::create(dir.join(MANIFEST_JSON)).context("create manifest.json")?;
+    f.write_all(&to_json(mf)?)?;
+    let mut f2 = File::create(dir.join(MANIFEST_V2)).context("create manifest.v2")?;
+    f2.write_all(&manifest_v2::encode(mf)?)?;
+    Ok(())
+}
+
+/// Load a manifest. If `manifest.json` is unreadable, rebuild it from the
+/// manifest parity in the volumes next to it (see `metaparity`), else fall
+/// back to the v2 companion and return what could be salvaged along with a
+/// report of the lost metadata.
+#[cfg(feature = "std")]
+pub fn load(path: &Path) -> Result<(Manifest, Option<RecoveryReport>)> {
+    let raw = std::fs::read(path).with_context(|| format!("read {:?}", path))?;
+    if raw.starts_with(manifest_v2::FILE_MAGIC) {
+        let (mf, rep) = manifest_v2::decode_partial(&raw)?;
+        return Ok((mf, (!rep.is_complete()).then_some(rep)));
+    }
+    let json_err = match from_json(&raw) {
+        Ok(mf) => return Ok((mf, None)),
+        Err(e) => e,
+    };
+    #[cfg(feature = "full")]
+    if let Some(mf) = crate::metaparity::recover_manifest_json(path, &raw)
+        .and_then(|fixed| from_json(&fixed).ok())
+    {
+        return Ok((mf, None));
+    }
+    let v2 = path.with_file_name(MANIFEST_V2);
+    let Ok(data) = std::fs::read(&v2) else {
+        return Err(json_err).context("read manifest.json");
+    };
+    let (mf, rep) = manifest_v2::decode_partial(&data)
+        .with_context(|| format!("manifest.json unreadable ({}); v2 fallback failed", json_err))?;
+    Ok((mf, Some(rep)))
 }
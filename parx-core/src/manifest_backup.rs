@@ -0,0 +1,141 @@
+//! Copy of the manifest inside every volume, so a set whose `manifest.json`
+//! (and v2 companion) was lost can still be verified from a surviving volume.
+//! The manifest v2 bytes, compressed with the volume's backup codec unless
+//! that is `none`, are stored as [`ShardKind::ManifestBackup`] shards of at
+//! most `chunk_size` bytes; slice `n` is indexed as stripe `n`.
+
+use crate::codec::{self, Codec, Codecs};
+use crate::index::{read_index, read_trailer, write_index_and_trailer_with, IndexLimits};
+use crate::manifest::Manifest;
+use crate::manifest_v2::{self, RecoveryReport};
+use crate::metaparity::{MetaParity, TAG_MANIFEST};
+use crate::volume::{check_features, ReadMode, ShardKind, VolumeEntry, VolumeHeader};
+use anyhow::{bail, Context, Result};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Largest manifest a compressed backup may expand to.
+const MAX_BACKUP: usize = 1 << 30;
+
+/// Write the backup slices of `mf` at `end` and index them; returns the new end.
+pub fn append(
+    f: &mut File,
+    entries: &mut Vec<VolumeEntry>,
+    end: u64,
+    mf: &Manifest,
+    codec: Codec,
+) -> Result<u64> {
+    let mut bytes = manifest_v2::encode(mf)?;
+    if codec != Codec::None {
+        bytes = codec.compress(&bytes).context("compress manifest backup")?;
+    }
+    let mut off = end;
+    f.seek(SeekFrom::Start(off))?;
+    for (n, slice) in bytes.chunks(mf.chunk_size.max(1)).enumerate() {
+        f.write_all(slice)?;
+        entries.push(VolumeEntry {
+            stripe: n as u64,
+            parity_idx: 0,
+            offset: off,
+            len: slice.len() as u32,
+            hash: Some(*blake3::hash(slice).as_bytes()),
+            kind: ShardKind::ManifestBackup,
+        });
+        off += slice.len() as u64;
+    }
+    Ok(off)
+}
+
+/// Replace the backup in an indexed volume with one of `mf`, along with the
+/// manifest parity. The old slices stay behind as unindexed bytes; the new
+/// ones overwrite the old index.
+pub fn refresh(path: &Path, mf: &Manifest) -> Result<()> {
+    let mut f = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .with_context(|| format!("open {:?}", path))?;
+    let hdr = VolumeHeader::read_from(&f).with_context(|| format!("read {:?}", path))?;
+    check_features(hdr.flags, ReadMode::Strict)
+        .with_context(|| format!("refusing to update {:?}", path))?;
+    let codecs = Codecs::from_ext(&hdr.ext);
+    let (off, len, crc) = read_trailer(&mut f).with_context(|| format!("read {:?}", path))?;
+    let mut entries = read_index(&mut f, off, len, crc, &IndexLimits::default())
+        .with_context(|| format!("read index of {:?}", path))?;
+    entries.retain(|e| e.kind != ShardKind::ManifestBackup);
+    let end = append(&mut f, &mut entries, off, mf, codecs.backup)?;
+    f.set_len(end)?;
+    let mf_parity = MetaParity::protect(&crate::manifest::to_json(mf)?)?;
+    write_index_and_trailer_with(&f, &entries, &[(TAG_MANIFEST, &mf_parity)], codecs.index)?;
+    // Writing the index updated its head copy record
+    let mut hdr = VolumeHeader::read_from(&f)?;
+    hdr.entries = crate::volume::entry_count(&entries)?;
+    hdr.write_to(&f)?;
+    f.sync_all()?;
+    Ok(())
+}
+
+/// Read the manifest backed up in the volume at `path`. Damaged slices are
+/// passed on to the v2 decoder, which keeps every intact section and reports
+/// the rest.
+pub fn read(path: &Path) -> Result<(Manifest, Option<RecoveryReport>)> {
+    let mut f = File::open(path).with_context(|| format!("open {:?}", path))?;
+    let (off, len, crc) = read_trailer(&mut f).with_context(|| format!("read {:?}", path))?;
+    let mut slices: Vec<VolumeEntry> = read_index(&mut f, off, len, crc, &IndexLimits::default())
+        .with_context(|| format!("read index of {:?}", path))?
+        .into_iter()
+        .filter(|e| e.kind == ShardKind::ManifestBackup)
+        .collect();
+    if slices.is_empty() {
+        bail!("{:?} holds no manifest backup (written by an older parx?)", path);
+    }
+    slices.sort_by_key(|e| e.stripe);
+    let mut bytes = Vec::new();
+    let mut damaged = false;
+    for e in &slices {
+        let mut buf = vec![0u8; e.len as usize];
+        f.seek(SeekFrom::Start(e.offset))?;
+        f.read_exact(&mut buf).with_context(|| format!("read manifest backup of {:?}", path))?;
+        damaged |= e.hash.is_some_and(|h| *blake3::hash(&buf).as_bytes() != h);
+        bytes.extend_from_slice(&buf);
+    }
+    if codec::is_compressed(&bytes) {
+        bytes = codec::decompress(&bytes, MAX_BACKUP)
+            .with_context(|| format!("decompress manifest backup of {:?}", path))?;
+    }
+    let (mf, rep) = manifest_v2::decode_partial(&bytes)
+        .with_context(|| format!("decode manifest backup of {:?}", path))?;
+    Ok((mf, (damaged || !rep.is_complete()).then_some(rep)))
+}
+
+/// The manifest backed up in the volumes of `dir`, with the volume it came
+/// from: the first intact copy, else the most complete damaged one.
+pub fn find(dir: &Path) -> Result<(Manifest, PathBuf, Option<RecoveryReport>)> {
+    let mut vols: Vec<PathBuf> = std::fs::read_dir(dir)
+        .with_context(|| format!("read_dir {:?}", dir))?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.extension().is_some_and(|s| s == "parxv"))
+        .collect();
+    vols.sort();
+    let mut best: Option<(Manifest, PathBuf, RecoveryReport)> = None;
+    let mut last_err = None;
+    for p in vols {
+        match read(&p) {
+            Ok((mf, None)) => return Ok((mf, p, None)),
+            Ok((mf, Some(rep))) => {
+                if best.as_ref().map_or(true, |b| rep.files_recovered > b.2.files_recovered) {
+                    best = Some((mf, p, rep));
+                }
+            }
+            Err(e) => last_err = Some(e),
+        }
+    }
+    match (best, last_err) {
+        (Some((mf, p, rep)), _) => Ok((mf, p, Some(rep))),
+        (None, Some(e)) => {
+            Err(e.context(format!("no volume in {:?} has a usable manifest backup", dir)))
+        }
+        (None, None) => bail!("no volumes in {:?}", dir),
+    }
+}
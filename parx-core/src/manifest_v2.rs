@@ -0,0 +1,330 @@
+//! Manifest v2: a framed, checksummed companion to `manifest.json`.
+//!
+//! Layout: `PARXMF2\0` followed by independent sections, each
+//! `magic(4) kind(1) seq(8) len(4) crc32(4) payload(len)`. The CRC covers
+//! kind, seq, len and payload, so a damaged region only loses the sections it
+//! touches; the reader resynchronises on the next section magic.
+//! The manifest's extension map travels in its own EXT section as raw TLV.
+//! A file record too long for one section (a file of hundreds of GiB at
+//! 1 MiB chunks) is cut into FILE_PART sections, each `part(4) parts(4)`
+//! and a piece of the record, which readers put back together; readers
+//! that predate them report the file lost.
+//! SLOTS sections hold the stripe assignment ([`Manifest::slot_table`]) as
+//! runs of `slot(8) file_id(8) ordinal(4) count(4)`, so the chunks of a lost
+//! file record can still be attributed to their file.
+
+use crate::ext::ExtMap;
+use crate::manifest::{FileEntry, Manifest};
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::vec::Vec;
+use anyhow::{bail, Result};
+use crc32fast::Hasher as Crc32;
+use serde::{Deserialize, Serialize};
+
+pub const FILE_MAGIC: &[u8; 8] = b"PARXMF2\0";
+const SECTION_MAGIC: &[u8; 4] = b"PXS2";
+const FRAME_LEN: usize = 4 + 1 + 8 + 4 + 4;
+const MAX_SECTION_BYTES: usize = 64 * 1024 * 1024;
+
+const KIND_HEADER: u8 = 1;
+const KIND_FILE: u8 = 2;
+#[cfg(feature = "std")]
+const KIND_END: u8 = 3;
+const KIND_EXT: u8 = 4;
+const KIND_SLOTS: u8 = 5;
+const KIND_FILE_PART: u8 = 6;
+const PART_HEADER: usize = 4 + 4;
+const SLOT_RUN: usize = 8 + 8 + 4 + 4;
+#[cfg(feature = "std")]
+const RUNS_PER_SECTION: usize = 1 << 20;
+
+/// What a partial parse could not recover.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
+pub struct RecoveryReport {
+    pub header_ok: bool,
+    pub files_expected: Option<u64>,
+    pub files_recovered: u64,
+    /// Positions (0-based, manifest order) of file records that were lost.
+    pub lost_files: Vec<u64>,
+    /// Chunk index ranges (inclusive) no longer described by any file record.
+    pub lost_chunk_ranges: Vec<(u64, u64)>,
+    /// Ids of the files whose records were lost, from the slot table.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub lost_file_ids: Vec<u64>,
+    pub corrupt_sections: u64,
+}
+
+impl RecoveryReport {
+    pub fn is_complete(&self) -> bool {
+        self.header_ok && self.lost_files.is_empty() && self.corrupt_sections == 0
+    }
+}
+
+#[cfg(feature = "std")]
+pub fn encode(mf: &Manifest) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    out.extend_from_slice(FILE_MAGIC);
+    let mut header = mf.clone();
+    header.files = Vec::new();
+    header.ext = ExtMap::new();
+    push_section(&mut out, KIND_HEADER, mf.files.len() as u64, &serde_json::to_vec(&header)?)?;
+    if !mf.ext.is_empty() {
+        push_section(&mut out, KIND_EXT, 0, &mf.ext.encode())?;
+    }
+    for (i, fe) in mf.files.iter().enumerate() {
+        let record = serde_json::to_vec(fe)?;
+        if record.len() <= MAX_SECTION_BYTES {
+            push_section(&mut out, KIND_FILE, i as u64, &record)?;
+            continue;
+        }
+        let pieces = record.chunks(MAX_SECTION_BYTES - PART_HEADER);
+        let parts = u32::try_from(pieces.len())?;
+        for (part, piece) in pieces.enumerate() {
+            let mut payload = Vec::with_capacity(PART_HEADER + piece.len());
+            payload.extend_from_slice(&(part as u32).to_le_bytes());
+            payload.extend_from_slice(&parts.to_le_bytes());
+            payload.extend_from_slice(piece);
+            push_section(&mut out, KIND_FILE_PART, i as u64, &payload)?;
+        }
+    }
+    for (part, runs) in slot_runs(mf).chunks(RUNS_PER_SECTION).enumerate() {
+        let mut payload = Vec::with_capacity(runs.len() * SLOT_RUN);
+        for r in runs {
+            payload.extend_from_slice(&r.slot.to_le_bytes());
+            payload.extend_from_slice(&r.file.to_le_bytes());
+            payload.extend_from_slice(&r.ordinal.to_le_bytes());
+            payload.extend_from_slice(&r.count.to_le_bytes());
+        }
+        push_section(&mut out, KIND_SLOTS, part as u64, &payload)?;
+    }
+    push_section(&mut out, KIND_END, mf.files.len() as u64, &[])?;
+    Ok(out)
+}
+
+/// `count` consecutive slots from `slot` holding consecutive chunks of a file.
+#[cfg_attr(not(feature = "std"), allow(dead_code))]
+struct SlotRun {
+    slot: u64,
+    file: u64,
+    ordinal: u32,
+    count: u32,
+}
+
+#[cfg(feature = "std")]
+fn slot_runs(mf: &Manifest) -> Vec<SlotRun> {
+    let mut runs: Vec<SlotRun> = Vec::new();
+    for (slot, id) in mf.slot_table() {
+        if let Some(r) = runs.last_mut() {
+            if r.file == id.file
+                && r.slot + r.count as u64 == slot
+                && r.ordinal + r.count == id.ordinal
+                && r.count < u32::MAX
+            {
+                r.count += 1;
+                continue;
+            }
+        }
+        runs.push(SlotRun { slot, file: id.file, ordinal: id.ordinal, count: 1 });
+    }
+    runs
+}
+
+fn parse_slot_runs(payload: &[u8]) -> Option<Vec<SlotRun>> {
+    if payload.len() % SLOT_RUN != 0 {
+        return None;
+    }
+    let u64_at = |b: &[u8], at: usize| u64::from_le_bytes(b[at..at + 8].try_into().unwrap());
+    let u32_at = |b: &[u8], at: usize| u32::from_le_bytes(b[at..at + 4].try_into().unwrap());
+    Some(
+        payload
+            .chunks_exact(SLOT_RUN)
+            .map(|b| SlotRun {
+                slot: u64_at(b, 0),
+                file: u64_at(b, 8),
+                ordinal: u32_at(b, 16),
+                count: u32_at(b, 20),
+            })
+            .collect(),
+    )
+}
+
+#[cfg(feature = "std")]
+fn push_section(out: &mut Vec<u8>, kind: u8, seq: u64, payload: &[u8]) -> Result<()> {
+    // Readers drop longer sections as damaged
+    if payload.len() > MAX_SECTION_BYTES {
+        bail!(
+            "manifest v2 section of {} bytes is over the {} byte limit",
+            payload.len(),
+            MAX_SECTION_BYTES
+        );
+    }
+    let len = payload.len() as u32;
+    let mut h = Crc32::new();
+    h.update(&[kind]);
+    h.update(&seq.to_le_bytes());
+    h.update(&len.to_le_bytes());
+    h.update(payload);
+    out.extend_from_slice(SECTION_MAGIC);
+    out.push(kind);
+    out.extend_from_slice(&seq.to_le_bytes());
+    out.extend_from_slice(&len.to_le_bytes());
+    out.extend_from_slice(&h.finalize().to_le_bytes());
+    out.extend_from_slice(payload);
+    Ok(())
+}
+
+/// Strict decode: every section must be present and intact.
+pub fn decode(data: &[u8]) -> Result<Manifest> {
+    let (mf, rep) = decode_partial(data)?;
+    if !rep.is_complete() {
+        bail!(
+            "manifest v2 damaged: {} corrupt section(s), {} file record(s) lost",
+            rep.corrupt_sections,
+            rep.lost_files.len()
+        );
+    }
+    Ok(mf)
+}
+
+/// Best-effort decode that keeps every intact section and reports the rest.
+pub fn decode_partial(data: &[u8]) -> Result<(Manifest, RecoveryReport)> {
+    let mut rep = RecoveryReport::default();
+    let mut header: Option<(Manifest, u64)> = None;
+    let mut files: Vec<(u64, FileEntry)> = Vec::new();
+    let mut ext = ExtMap::new();
+    let mut slots: BTreeMap<u64, Vec<SlotRun>> = BTreeMap::new();
+    // Pieces of split file records by file: part count, pieces by part
+    let mut split: BTreeMap<u64, (u32, BTreeMap<u32, &[u8]>)> = BTreeMap::new();
+    let mut pos = if data.starts_with(FILE_MAGIC) { FILE_MAGIC.len() } else { 0 };
+    // True while skipping over a damaged region already counted once
+    let mut resyncing = false;
+    while let Some(at) = find_magic(data, pos) {
+        if at > pos && !resyncing {
+            rep.corrupt_sections += 1;
+        }
+        match parse_section(&data[at..]) {
+            Some((kind, seq, payload)) => {
+                resyncing = false;
+                pos = at + FRAME_LEN + payload.len();
+                match kind {
+                    KIND_HEADER => match serde_json::from_slice::<Manifest>(payload) {
+                        Ok(h) => header = Some((h, seq)),
+                        Err(_) => rep.corrupt_sections += 1,
+                    },
+                    KIND_FILE => match serde_json::from_slice::<FileEntry>(payload) {
+                        Ok(fe) => files.push((seq, fe)),
+                        Err(_) => rep.corrupt_sections += 1,
+                    },
+                    KIND_EXT => match ExtMap::decode(payload) {
+                        Ok(e) => ext = e,
+                        Err(_) => rep.corrupt_sections += 1,
+                    },
+                    KIND_FILE_PART if payload.len() >= PART_HEADER => {
+                        let part = u32::from_le_bytes(payload[..4].try_into().unwrap());
+                        let parts = u32::from_le_bytes(payload[4..8].try_into().unwrap());
+                        let (n, pieces) = split.entry(seq).or_insert((parts, BTreeMap::new()));
+                        if *n == parts && part < parts {
+                            pieces.insert(part, &payload[PART_HEADER..]);
+                        } else {
+                            rep.corrupt_sections += 1;
+                        }
+                    }
+                    KIND_SLOTS => match parse_slot_runs(payload) {
+                        Some(runs) => {
+                            slots.insert(seq, runs);
+                        }
+                        None => rep.corrupt_sections += 1,
+                    },
+                    // END and unknown (future) sections carry nothing we need
+                    _ => {}
+                }
+            }
+            None => {
+                if !resyncing {
+                    rep.corrupt_sections += 1;
+                }
+                resyncing = true;
+                pos = at + 1;
+            }
+        }
+    }
+    // Split records with every piece intact; the others count as lost
+    for (seq, (parts, pieces)) in split {
+        if pieces.len() != parts as usize {
+            continue;
+        }
+        let record: Vec<u8> = pieces.into_values().flatten().copied().collect();
+        match serde_json::from_slice::<FileEntry>(&record) {
+            Ok(fe) => files.push((seq, fe)),
+            Err(_) => rep.corrupt_sections += 1,
+        }
+    }
+    let Some((mut mf, expected)) = header else {
+        if files.is_empty() {
+            bail!("manifest v2: no recoverable sections");
+        }
+        bail!("manifest v2: header section lost ({} file record(s) intact)", files.len());
+    };
+    rep.header_ok = true;
+    rep.files_expected = Some(expected);
+    files.sort_by_key(|(seq, _)| *seq);
+    files.dedup_by_key(|(seq, _)| *seq);
+    let present: BTreeSet<u64> = files.iter().map(|(s, _)| *s).collect();
+    rep.lost_files = (0..expected).filter(|s| !present.contains(s)).collect();
+    rep.files_recovered = files.len() as u64;
+    mf.files = files.into_iter().map(|(_, fe)| fe).collect();
+    mf.ext = ext;
+
+    // Chunk indices no surviving record covers
+    let mut idxs: Vec<u64> = mf.files.iter().flat_map(|f| f.chunks.iter().map(|c| c.idx)).collect();
+    idxs.sort_unstable();
+    let mut next = 0u64;
+    for idx in idxs.into_iter().chain(core::iter::once(mf.total_chunks)) {
+        if idx > next {
+            rep.lost_chunk_ranges.push((next, idx - 1));
+        }
+        next = next.max(idx + 1);
+    }
+    // Files the slot table places in those ranges
+    let lost: BTreeSet<u64> = slots
+        .values()
+        .flatten()
+        .filter(|r| {
+            let end = r.slot + r.count as u64;
+            rep.lost_chunk_ranges.iter().any(|&(a, b)| r.slot <= b && a < end)
+        })
+        .map(|r| r.file)
+        .collect();
+    rep.lost_file_ids = lost.into_iter().collect();
+    Ok((mf, rep))
+}
+
+fn find_magic(data: &[u8], from: usize) -> Option<usize> {
+    if from >= data.len() {
+        return None;
+    }
+    data[from..].windows(SECTION_MAGIC.len()).position(|w| w == SECTION_MAGIC).map(|p| p + from)
+}
+
+fn parse_section(buf: &[u8]) -> Option<(u8, u64, &[u8])> {
+    if buf.len() < FRAME_LEN {
+        return None;
+    }
+    let kind = buf[4];
+    let seq = u64::from_le_bytes(buf[5..13].try_into().ok()?);
+    let len = u32::from_le_bytes(buf[13..17].try_into().ok()?) as usize;
+    let crc = u32::from_le_bytes(buf[17..21].try_into().ok()?);
+    if len > MAX_SECTION_BYTES || buf.len() < FRAME_LEN + len {
+        return None;
+    }
+    let payload = &buf[FRAME_LEN..FRAME_LEN + len];
+    let mut h = Crc32::new();
+    h.update(&[kind]);
+    h.update(&seq.to_le_bytes());
+    h.update(&(len as u32).to_le_bytes());
+    h.update(payload);
+    if h.finalize() != crc {
+        return None;
+    }
+    Some((kind, seq, payload))
+}
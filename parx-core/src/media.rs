@@ -0,0 +1,152 @@
+//! Container-aware chunk boundaries for media files (`create --media-align`).
+//!
+//! For MP4/MOV (ISO BMFF) the top-level boxes are located (for fragmented
+//! files every `moof`/`mdat` pair); for Matroska/WebM the top-level elements
+//! of the segment, i.e. every cluster, which starts on a keyframe. Chunks are
+//! then cut at those offsets, so a chunk that cannot be repaired damages one
+//! box or cluster instead of straddling two and the rest of the file stays
+//! playable. Anything that does not parse cleanly falls back to fixed-size
+//! chunking from the first offset that is not understood.
+
+use alloc::string::String;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "std")]
+use std::fs::File;
+#[cfg(feature = "std")]
+use std::io::{Read, Seek, SeekFrom};
+#[cfg(feature = "std")]
+use std::path::Path;
+
+/// Per-file record of container alignment, kept in the manifest.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct MediaLayout {
+    /// "mp4" or "mkv"
+    pub container: String,
+    /// Chunks that end on a container boundary
+    pub aligned_cuts: u64,
+}
+
+#[cfg(feature = "std")]
+const EBML_MAGIC: u32 = 0x1A45_DFA3;
+#[cfg(feature = "std")]
+const MKV_SEGMENT: u32 = 0x1853_8067;
+
+/// Detect the container of `path` and list its boundary offsets (ascending,
+/// excluding 0 and EOF). `None` when the file is not a recognised container.
+#[cfg(feature = "std")]
+pub fn detect(path: &Path) -> Option<(&'static str, Vec<u64>)> {
+    let mut f = File::open(path).ok()?;
+    let len = f.metadata().ok()?.len();
+    let mut head = [0u8; 8];
+    f.read_exact(&mut head).ok()?;
+    if &head[4..8] == b"ftyp" || &head[4..8] == b"styp" {
+        return Some(("mp4", mp4_boundaries(&mut f, len)));
+    }
+    if u32::from_be_bytes(head[0..4].try_into().unwrap()) == EBML_MAGIC {
+        return Some(("mkv", mkv_boundaries(&mut f, len)));
+    }
+    None
+}
+
+/// Smallest piece a boundary may split off the start of a chunk; closer
+/// boundaries are ignored rather than producing near-empty padded chunks.
+pub fn min_piece(chunk_size: usize) -> u64 {
+    (chunk_size as u64 / 16).max(1)
+}
+
+#[cfg(feature = "std")]
+fn read_at(f: &mut File, off: u64, buf: &mut [u8]) -> Option<()> {
+    f.seek(SeekFrom::Start(off)).ok()?;
+    f.read_exact(buf).ok()
+}
+
+#[cfg(feature = "std")]
+fn mp4_boundaries(f: &mut File, len: u64) -> Vec<u64> {
+    let mut out = Vec::new();
+    let mut off = 0u64;
+    let mut hdr = [0u8; 16];
+    while off + 8 <= len {
+        if read_at(f, off, &mut hdr[..8]).is_none() {
+            break;
+        }
+        let size = match u32::from_be_bytes(hdr[0..4].try_into().unwrap()) {
+            0 => len - off,
+            1 => {
+                if read_at(f, off + 8, &mut hdr[8..16]).is_none() {
+                    break;
+                }
+                u64::from_be_bytes(hdr[8..16].try_into().unwrap())
+            }
+            n => n as u64,
+        };
+        if size < 8 || size > len - off {
+            break;
+        }
+        off += size;
+        if off < len {
+            out.push(off);
+        }
+    }
+    out
+}
+
+/// EBML variable-length integer at `off`: (value, encoded length, all-ones).
+/// IDs keep their length marker bit, sizes do not.
+#[cfg(feature = "std")]
+fn vint(f: &mut File, off: u64, keep_marker: bool) -> Option<(u64, u64, bool)> {
+    let mut first = [0u8; 1];
+    read_at(f, off, &mut first)?;
+    let n = first[0].leading_zeros() as usize + 1;
+    if n > 8 {
+        return None;
+    }
+    let mut buf = [0u8; 8];
+    buf[0] = first[0];
+    if n > 1 {
+        read_at(f, off + 1, &mut buf[1..n])?;
+    }
+    let mut v = if keep_marker { first[0] as u64 } else { (first[0] as u64) & (0xFF >> n) };
+    for b in &buf[1..n] {
+        v = (v << 8) | *b as u64;
+    }
+    let all_ones = v == (1u64 << (7 * n)) - 1;
+    Some((v, n as u64, all_ones && !keep_marker))
+}
+
+#[cfg(feature = "std")]
+fn mkv_boundaries(f: &mut File, len: u64) -> Vec<u64> {
+    let mut out = Vec::new();
+    // EBML header element
+    let Some((_, id_len, _)) = vint(f, 0, true) else { return out };
+    let Some((size, sz_len, unknown)) = vint(f, id_len, false) else { return out };
+    if unknown {
+        return out;
+    }
+    let seg = id_len + sz_len + size;
+    match vint(f, seg, true) {
+        Some((id, _, _)) if id == MKV_SEGMENT as u64 => {}
+        _ => return out,
+    }
+    if seg < len {
+        out.push(seg);
+    }
+    let Some((_, id_len, _)) = vint(f, seg, true) else { return out };
+    let Some((size, sz_len, unknown)) = vint(f, seg + id_len, false) else { return out };
+    let body = seg + id_len + sz_len;
+    let end = if unknown { len } else { (body + size).min(len) };
+    // Top-level children of the segment (SeekHead, Info, Tracks, Cluster, ...)
+    let mut off = body;
+    while off < end {
+        out.push(off);
+        let Some((_, id_len, _)) = vint(f, off, true) else { break };
+        let Some((size, sz_len, unknown)) = vint(f, off + id_len, false) else { break };
+        if unknown {
+            // Live-written clusters without a size; we cannot skip over them
+            break;
+        }
+        off += id_len + sz_len + size;
+    }
+    out.retain(|&o| o > 0 && o < len);
+    out.dedup();
+    out
+}
@@ -1,4 +1,56 @@
-use blake3;
+use alloc::vec::Vec;
+
+/// Hash of a chunk as recorded in manifests: its bytes zero-padded to
+/// `chunk_size` (the last chunk of a file is usually short). Sets made with a
+/// hash key use [`ChunkHasher`] instead.
+pub fn chunk_hash(data: &[u8], chunk_size: usize) -> blake3::Hash {
+    ChunkHasher::new(chunk_size, None).hash(data)
+}
+
+/// Hashes chunks the way a set records them: zero-padded to the chunk size,
+/// with plain BLAKE3 or, for sets made with `create --hash-key`, with BLAKE3
+/// keyed by the set's key (see `Manifest::hasher`).
+#[derive(Clone, Copy)]
+pub struct ChunkHasher {
+    pub chunk_size: usize,
+    key: Option<[u8; 32]>,
+}
+
+impl core::fmt::Debug for ChunkHasher {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ChunkHasher")
+            .field("chunk_size", &self.chunk_size)
+            .field("keyed", &self.key.is_some())
+            .finish()
+    }
+}
+
+impl ChunkHasher {
+    pub fn new(chunk_size: usize, key: Option<[u8; 32]>) -> Self {
+        Self { chunk_size, key }
+    }
+
+    pub fn hash(&self, data: &[u8]) -> blake3::Hash {
+        const ZEROS: [u8; 1024] = [0u8; 1024];
+        let mut h = match &self.key {
+            Some(key) => blake3::Hasher::new_keyed(key),
+            None => blake3::Hasher::new(),
+        };
+        h.update(data);
+        let mut pad = self.chunk_size.saturating_sub(data.len());
+        while pad > 0 {
+            let n = pad.min(ZEROS.len());
+            h.update(&ZEROS[..n]);
+            pad -= n;
+        }
+        h.finalize()
+    }
+
+    /// Whether `data` hashes to the recorded `hash_hex`.
+    pub fn matches(&self, data: &[u8], hash_hex: &str) -> bool {
+        self.hash(data).to_hex().as_str() == hash_hex
+    }
+}
 
 /// Compute a simple binary Merkle root over BLAKE3 leaf hashes.
 /// Duplicates the last node when the layer is odd.
@@ -0,0 +1,422 @@
+//! File metadata (permissions, ownership, mtime, extended attributes)
+//! captured at create time and re-applied when repair or restore rewrites a
+//! file.
+//!
+//! Restoring ownership needs privileges; an unprivileged run still restores
+//! the mode and reports which files kept the current user as owner. Mode and
+//! ownership are always put back; the mtime and extended attributes only when
+//! asked for (`repair --restore-metadata`), and only on Linux.
+//!
+//! On Windows, with the `windows-meta` feature, the file attributes (readonly,
+//! hidden, system, archive) and NTFS alternate data streams are captured too.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "std")]
+use std::collections::HashMap;
+#[cfg(feature = "std")]
+use std::path::Path;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct FileMeta {
+    /// Unix permission bits (`st_mode & 0o7777`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uid: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gid: Option<u32>,
+    /// Modification time, seconds since the Unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mtime: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mtime_nsec: Option<u32>,
+    /// Extended attributes (`user.*`, `security.*`, ...)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub xattrs: Vec<Xattr>,
+    /// Windows `FILE_ATTRIBUTE_*` bits
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub win_attributes: Option<u32>,
+    /// NTFS alternate data streams (excluding the unnamed main stream)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub streams: Vec<AltStream>,
+}
+
+/// Streams up to this size are stored inline; larger ones are only listed.
+pub const MAX_INLINE_STREAM: u64 = 64 * 1024;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct AltStream {
+    pub name: String,
+    pub size: u64,
+    /// Stream content; absent when larger than [`MAX_INLINE_STREAM`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data_hex: Option<String>,
+}
+
+/// An extended attribute; values over [`MAX_INLINE_STREAM`] are not captured.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Xattr {
+    pub name: String,
+    pub value_hex: String,
+}
+
+impl FileMeta {
+    pub fn is_empty(&self) -> bool {
+        *self == FileMeta::default()
+    }
+}
+
+/// Capture what this platform can restore later; `None` when there is nothing.
+#[cfg(feature = "std")]
+pub fn capture(path: &Path, md: &std::fs::Metadata) -> Option<FileMeta> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        #[cfg(all(target_os = "linux", feature = "full"))]
+        let xattrs = linux::list_xattrs(path);
+        #[cfg(not(all(target_os = "linux", feature = "full")))]
+        let xattrs = {
+            let _ = path;
+            Vec::new()
+        };
+        Some(FileMeta {
+            mode: Some(md.mode() & 0o7777),
+            uid: Some(md.uid()),
+            gid: Some(md.gid()),
+            mtime: Some(md.mtime()),
+            mtime_nsec: Some(md.mtime_nsec() as u32),
+            xattrs,
+            ..Default::default()
+        })
+    }
+    #[cfg(all(windows, feature = "windows-meta"))]
+    {
+        use std::os::windows::fs::MetadataExt;
+        Some(FileMeta {
+            win_attributes: Some(md.file_attributes()),
+            streams: win::list_streams(path),
+            ..Default::default()
+        })
+    }
+    #[cfg(not(any(unix, all(windows, feature = "windows-meta"))))]
+    {
+        let _ = (path, md);
+        None
+    }
+}
+
+/// UID remapping applied before ownership is restored (`--chown-map 1000:2000,1001:2001`).
+#[cfg(feature = "std")]
+#[derive(Clone, Debug, Default)]
+pub struct ChownMap {
+    uids: HashMap<u32, u32>,
+}
+
+#[cfg(feature = "std")]
+impl ChownMap {
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut uids = HashMap::new();
+        for tok in spec.split(',').map(str::trim).filter(|t| !t.is_empty()) {
+            let Some((from, to)) = tok.split_once(':') else {
+                bail!("chown map entry {:?}: expected OLD:NEW", tok);
+            };
+            let from: u32 = from.parse().with_context(|| format!("chown map uid {:?}", from))?;
+            let to: u32 = to.parse().with_context(|| format!("chown map uid {:?}", to))?;
+            uids.insert(from, to);
+        }
+        Ok(ChownMap { uids })
+    }
+
+    pub fn map_uid(&self, uid: u32) -> u32 {
+        self.uids.get(&uid).copied().unwrap_or(uid)
+    }
+}
+
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MetaReport {
+    /// Files whose recorded metadata was fully re-applied
+    pub restored: u64,
+    /// Files left owned by the current user because we lack privileges
+    pub unprivileged: Vec<String>,
+    /// Files where applying metadata failed for another reason
+    pub failed: Vec<String>,
+    /// `file:stream` pairs too large to have been stored, so not restored
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub streams_not_restored: Vec<String>,
+    /// Files whose mtime and extended attributes could not be restored on
+    /// this platform (`--restore-metadata` outside Linux)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unsupported: Vec<String>,
+}
+
+#[cfg(feature = "std")]
+impl MetaReport {
+    pub fn is_empty(&self) -> bool {
+        self.restored == 0
+            && self.unprivileged.is_empty()
+            && self.failed.is_empty()
+            && self.streams_not_restored.is_empty()
+            && self.unsupported.is_empty()
+    }
+}
+
+/// Re-apply `meta` to a freshly written file, recording the outcome under `rel`.
+/// With `times_and_xattrs`, the mtime and extended attributes are restored too.
+#[cfg(feature = "std")]
+pub fn restore(
+    path: &Path,
+    rel: &str,
+    meta: &FileMeta,
+    map: &ChownMap,
+    times_and_xattrs: bool,
+    rep: &mut MetaReport,
+) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+        let mut ok = true;
+        let mut unprivileged = false;
+        // Extended attributes while the file is still ours and writable
+        #[cfg(all(target_os = "linux", feature = "full"))]
+        if times_and_xattrs {
+            match linux::set_xattrs(path, &meta.xattrs) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => unprivileged = true,
+                Err(_) => ok = false,
+            }
+        }
+        // Ownership first: chown may clear set-id bits, so the mode goes last
+        if meta.uid.is_some() || meta.gid.is_some() {
+            let uid = meta.uid.map(|u| map.map_uid(u));
+            let same = std::fs::metadata(path).is_ok_and(|md| {
+                uid.map_or(true, |u| u == md.uid()) && meta.gid.map_or(true, |g| g == md.gid())
+            });
+            if !same {
+                match std::os::unix::fs::chown(path, uid, meta.gid) {
+                    Ok(()) => {}
+                    Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+                        unprivileged = true
+                    }
+                    Err(_) => ok = false,
+                }
+            }
+        }
+        if let Some(mode) = meta.mode {
+            if std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).is_err() {
+                ok = false;
+            }
+        }
+        // The mtime last, since every other change may touch it
+        if times_and_xattrs {
+            #[cfg(all(target_os = "linux", feature = "full"))]
+            if let Some(secs) = meta.mtime {
+                ok &= linux::set_mtime(path, secs, meta.mtime_nsec.unwrap_or(0)).is_ok();
+            }
+            #[cfg(not(all(target_os = "linux", feature = "full")))]
+            if meta.mtime.is_some() || !meta.xattrs.is_empty() {
+                rep.unsupported.push(rel.to_string());
+            }
+        }
+        if !ok {
+            rep.failed.push(rel.to_string());
+        } else if unprivileged {
+            rep.unprivileged.push(rel.to_string());
+        } else {
+            rep.restored += 1;
+        }
+    }
+    #[cfg(all(windows, feature = "windows-meta"))]
+    {
+        let _ = map;
+        if times_and_xattrs && (meta.mtime.is_some() || !meta.xattrs.is_empty()) {
+            rep.unsupported.push(rel.to_string());
+        }
+        let mut ok = true;
+        for st in &meta.streams {
+            let Some(hex) = &st.data_hex else {
+                rep.streams_not_restored.push(format!("{}:{}", rel, st.name));
+                continue;
+            };
+            let mut target = path.as_os_str().to_owned();
+            target.push(":");
+            target.push(&st.name);
+            let written = crate::hex::unhex(hex)
+                .ok()
+                .is_some_and(|data| std::fs::write(Path::new(&target), data).is_ok());
+            ok &= written;
+        }
+        // Attributes last: a readonly file would refuse the stream writes
+        if let Some(attrs) = meta.win_attributes {
+            ok &= win::set_attributes(path, attrs);
+        }
+        if ok {
+            rep.restored += 1;
+        } else {
+            rep.failed.push(rel.to_string());
+        }
+    }
+    #[cfg(not(any(unix, all(windows, feature = "windows-meta"))))]
+    {
+        let _ = (path, rel, meta, map, times_and_xattrs, rep);
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "full"))]
+mod linux {
+    use super::{Xattr, MAX_INLINE_STREAM};
+    use std::ffi::{CStr, CString};
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+
+    fn cpath(path: &Path) -> io::Result<CString> {
+        CString::new(path.as_os_str().as_bytes()).map_err(|_| io::ErrorKind::InvalidInput.into())
+    }
+
+    /// Call `f` with a growing buffer until what it reports fits.
+    fn sized(mut f: impl FnMut(*mut libc::c_void, usize) -> isize) -> io::Result<Vec<u8>> {
+        loop {
+            let need = f(std::ptr::null_mut(), 0);
+            if need < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut buf = vec![0u8; need as usize];
+            let got = f(buf.as_mut_ptr().cast(), buf.len());
+            if got >= 0 {
+                buf.truncate(got as usize);
+                return Ok(buf);
+            }
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() != Some(libc::ERANGE) {
+                return Err(err);
+            }
+        }
+    }
+
+    /// Attributes of `path` itself (not a symlink target); unreadable or
+    /// oversized values are left out.
+    pub(super) fn list_xattrs(path: &Path) -> Vec<Xattr> {
+        let Ok(p) = cpath(path) else { return Vec::new() };
+        // SAFETY: `p` is NUL-terminated and the buffer pointer/length pairs
+        // come from `sized`, which allocates exactly that many bytes.
+        let names = sized(|b, n| unsafe { libc::llistxattr(p.as_ptr(), b.cast(), n) });
+        let Ok(names) = names else { return Vec::new() };
+        let mut out = Vec::new();
+        for name in names.split(|&c| c == 0).filter(|n| !n.is_empty()) {
+            let Ok(cname) = CString::new(name) else { continue };
+            // SAFETY: as above; `cname` is NUL-terminated.
+            let value = sized(|b, n| unsafe { libc::lgetxattr(p.as_ptr(), cname.as_ptr(), b, n) });
+            let Ok(value) = value else { continue };
+            if value.len() as u64 > MAX_INLINE_STREAM {
+                continue;
+            }
+            let name = CStr::to_string_lossy(&cname).into_owned();
+            out.push(Xattr { name, value_hex: crate::hex::hex(&value) });
+        }
+        out
+    }
+
+    pub(super) fn set_xattrs(path: &Path, xattrs: &[Xattr]) -> io::Result<()> {
+        let p = cpath(path)?;
+        for x in xattrs {
+            let name = CString::new(x.name.as_bytes())
+                .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+            let value = crate::hex::unhex(&x.value_hex)
+                .map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?;
+            // SAFETY: NUL-terminated path and name; `value` outlives the call.
+            let rc = unsafe {
+                libc::lsetxattr(p.as_ptr(), name.as_ptr(), value.as_ptr().cast(), value.len(), 0)
+            };
+            if rc != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    pub(super) fn set_mtime(path: &Path, secs: i64, nsec: u32) -> io::Result<()> {
+        let p = cpath(path)?;
+        let times = [
+            libc::timespec { tv_sec: 0, tv_nsec: libc::UTIME_OMIT },
+            libc::timespec { tv_sec: secs as libc::time_t, tv_nsec: nsec as _ },
+        ];
+        // SAFETY: NUL-terminated path and a two-element timespec array.
+        let rc = unsafe { libc::utimensat(libc::AT_FDCWD, p.as_ptr(), times.as_ptr(), 0) };
+        if rc != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(windows, feature = "windows-meta"))]
+mod win {
+    use super::{AltStream, MAX_INLINE_STREAM};
+    use std::os::windows::ffi::OsStrExt;
+    use std::path::Path;
+    use windows_sys::Win32::Foundation::INVALID_HANDLE_VALUE;
+    use windows_sys::Win32::Storage::FileSystem::{
+        FindClose, FindFirstStreamW, FindNextStreamW, FindStreamInfoStandard, SetFileAttributesW,
+        WIN32_FIND_STREAM_DATA,
+    };
+
+    /// Attribute bits we put back; everything else is owned by the filesystem.
+    const RESTORABLE: u32 = 0x1 | 0x2 | 0x4 | 0x20 | 0x2000; // RO|HIDDEN|SYSTEM|ARCHIVE|NOT_INDEXED
+
+    fn wide(path: &Path) -> Vec<u16> {
+        path.as_os_str().encode_wide().chain(std::iter::once(0)).collect()
+    }
+
+    pub(super) fn list_streams(path: &Path) -> Vec<AltStream> {
+        let w = wide(path);
+        let mut out = Vec::new();
+        // SAFETY: `w` is NUL-terminated and `data` is a valid out-parameter for
+        // the duration of each call; the handle is closed before returning.
+        unsafe {
+            let mut data: WIN32_FIND_STREAM_DATA = std::mem::zeroed();
+            let h = FindFirstStreamW(
+                w.as_ptr(),
+                FindStreamInfoStandard,
+                &mut data as *mut _ as *mut _,
+                0,
+            );
+            if h == INVALID_HANDLE_VALUE {
+                return out;
+            }
+            loop {
+                let len = data.cStreamName.iter().position(|&c| c == 0).unwrap_or(0);
+                let full = String::from_utf16_lossy(&data.cStreamName[..len]);
+                // ":name:$DATA"; the unnamed main stream is "::$DATA"
+                let name = full.trim_start_matches(':').trim_end_matches(":$DATA").to_string();
+                if !name.is_empty() {
+                    let size = data.StreamSize as u64;
+                    let data_hex = if size <= MAX_INLINE_STREAM {
+                        let mut sp = path.as_os_str().to_owned();
+                        sp.push(":");
+                        sp.push(&name);
+                        std::fs::read(Path::new(&sp)).ok().map(|b| crate::hex::hex(&b))
+                    } else {
+                        None
+                    };
+                    out.push(AltStream { name, size, data_hex });
+                }
+                if FindNextStreamW(h, &mut data as *mut _ as *mut _) == 0 {
+                    break;
+                }
+            }
+            FindClose(h);
+        }
+        out
+    }
+
+    pub(super) fn set_attributes(path: &Path, attrs: u32) -> bool {
+        let w = wide(path);
+        // SAFETY: `w` is a NUL-terminated UTF-16 path.
+        unsafe { SetFileAttributesW(w.as_ptr(), attrs & RESTORABLE) != 0 }
+    }
+}
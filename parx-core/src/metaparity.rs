@@ -0,0 +1,231 @@
+//! Reed-Solomon parity over metadata: each volume's index and the serialized
+//! `manifest.json`. A damaged region is rebuilt from it instead of only being
+//! detected by its CRC.
+//!
+//! The parity lives in a block between a volume's index and its trailer,
+//! where readers that predate it never look:
+//! `PARXMRS1 body_len(4) body crc32(body)(4)`. The body is a list of
+//! `tag(1) len(4) payload` sections, one per protected region; each payload is
+//! `data_len(8) shard_len(4) k(2) m(2) blake3(32) crc32[k] parity[m * shard_len]`.
+
+use crate::manifest::MANIFEST_JSON;
+use crate::rs_codec::RsCodec;
+use anyhow::Result;
+use crc32fast::Hasher as Crc32;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+const BLOCK_MAGIC: &[u8; 8] = b"PARXMRS1";
+/// Largest block a reader accepts.
+const MAX_BODY: usize = 64 * 1024 * 1024;
+
+/// Section tag of the parity over the volume's own (compressed) index.
+pub const TAG_INDEX: u8 = 1;
+/// Section tag of the parity over `manifest.json`.
+pub const TAG_MANIFEST: u8 = 2;
+
+fn crc32(b: &[u8]) -> u32 {
+    let mut h = Crc32::new();
+    h.update(b);
+    h.finalize()
+}
+
+/// RS parity over one metadata region, with what is needed to tell which of
+/// its shards are damaged and whether a rebuild is right.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MetaParity {
+    data_len: u64,
+    shard_len: usize,
+    hash: [u8; 32],
+    crcs: Vec<u32>,
+    parity: Vec<Vec<u8>>,
+}
+
+impl MetaParity {
+    /// Parity for `data`: up to 64 data shards of at least 256 bytes, a
+    /// quarter as many parity shards and never fewer than two.
+    pub fn protect(data: &[u8]) -> Result<Self> {
+        let shard_len = data.len().div_ceil(64).max(256);
+        let k = data.len().div_ceil(shard_len).max(1);
+        let m = k.div_ceil(4).max(2);
+        let mut bufs: Vec<Vec<u8>> = (0..k + m).map(|_| vec![0u8; shard_len]).collect();
+        for (b, d) in bufs.iter_mut().zip(data.chunks(shard_len)) {
+            b[..d.len()].copy_from_slice(d);
+        }
+        let crcs = bufs[..k].iter().map(|b| crc32(b)).collect();
+        let mut shards: Vec<&mut [u8]> = bufs.iter_mut().map(|b| b.as_mut_slice()).collect();
+        RsCodec::new(k, m)?.encode(&mut shards)?;
+        Ok(Self {
+            data_len: data.len() as u64,
+            shard_len,
+            hash: *blake3::hash(data).as_bytes(),
+            crcs,
+            parity: bufs.split_off(k),
+        })
+    }
+
+    /// Encoded size of the parity [`MetaParity::protect`] makes for
+    /// `data_len` bytes; grows with it.
+    pub fn encoded_len(data_len: usize) -> usize {
+        let shard_len = data_len.div_ceil(64).max(256);
+        let k = data_len.div_ceil(shard_len).max(1);
+        let m = k.div_ceil(4).max(2);
+        48 + 4 * k + m * shard_len
+    }
+
+    /// The original bytes, rebuilt from `damaged` where its shards fail their
+    /// CRC. `None` when too much is damaged (or `damaged` is some other data).
+    pub fn recover(&self, damaged: &[u8]) -> Option<Vec<u8>> {
+        let (k, m) = (self.crcs.len(), self.parity.len());
+        let mut shards: Vec<Option<Vec<u8>>> = (0..k)
+            .map(|i| {
+                let mut buf = vec![0u8; self.shard_len];
+                let start = (i * self.shard_len).min(damaged.len());
+                let end = ((i + 1) * self.shard_len).min(damaged.len());
+                buf[..end - start].copy_from_slice(&damaged[start..end]);
+                (crc32(&buf) == self.crcs[i]).then_some(buf)
+            })
+            .collect();
+        if shards.iter().any(Option::is_none) {
+            shards.extend(self.parity.iter().cloned().map(Some));
+            RsCodec::new(k, m).ok()?.reconstruct(&mut shards).ok()?;
+        }
+        let mut out: Vec<u8> = shards.into_iter().take(k).flatten().flatten().collect();
+        out.truncate(self.data_len as usize);
+        (*blake3::hash(&out).as_bytes() == self.hash).then_some(out)
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&self.data_len.to_le_bytes());
+        out.extend_from_slice(&(self.shard_len as u32).to_le_bytes());
+        out.extend_from_slice(&(self.crcs.len() as u16).to_le_bytes());
+        out.extend_from_slice(&(self.parity.len() as u16).to_le_bytes());
+        out.extend_from_slice(&self.hash);
+        for c in &self.crcs {
+            out.extend_from_slice(&c.to_le_bytes());
+        }
+        for p in &self.parity {
+            out.extend_from_slice(p);
+        }
+        out
+    }
+
+    fn decode(b: &[u8]) -> Option<Self> {
+        let head = b.get(..48)?;
+        let data_len = u64::from_le_bytes(head[..8].try_into().ok()?);
+        let shard_len = u32::from_le_bytes(head[8..12].try_into().ok()?) as usize;
+        let k = u16::from_le_bytes(head[12..14].try_into().ok()?) as usize;
+        let m = u16::from_le_bytes(head[14..16].try_into().ok()?) as usize;
+        let hash: [u8; 32] = head[16..48].try_into().ok()?;
+        let rest = &b[48..];
+        if k == 0 || rest.len() != k * 4 + m * shard_len || data_len > (k * shard_len) as u64 {
+            return None;
+        }
+        let (crcs, parity) = rest.split_at(k * 4);
+        Some(Self {
+            data_len,
+            shard_len,
+            hash,
+            crcs: crcs.chunks(4).map(|c| u32::from_le_bytes(c.try_into().unwrap())).collect(),
+            parity: parity.chunks(shard_len.max(1)).map(<[u8]>::to_vec).collect(),
+        })
+    }
+}
+
+/// Serialize a parity block holding `sections`. A section that would take
+/// the block past what readers accept is left out; its region is then only
+/// covered by its CRC.
+pub fn encode_block(sections: &[(u8, &MetaParity)]) -> Vec<u8> {
+    let mut body = Vec::new();
+    for (tag, mp) in sections {
+        let payload = mp.encode();
+        if body.len() + 5 + payload.len() > MAX_BODY {
+            continue;
+        }
+        body.push(*tag);
+        body.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        body.extend_from_slice(&payload);
+    }
+    let mut out = Vec::with_capacity(body.len() + 16);
+    out.extend_from_slice(BLOCK_MAGIC);
+    out.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    out.extend_from_slice(&body);
+    out.extend_from_slice(&crc32(&body).to_le_bytes());
+    out
+}
+
+/// Most bytes [`encode_block`] takes for parity over regions of `data_lens`.
+pub fn block_len(data_lens: &[usize]) -> usize {
+    data_lens.iter().map(|&n| 5 + MetaParity::encoded_len(n)).sum::<usize>() + 16
+}
+
+/// Read the parity block at `at` through `read_at`. `None` if there is none
+/// (volumes from older writers) or it is damaged itself.
+pub fn read_block(
+    mut read_at: impl FnMut(u64, &mut [u8]) -> Result<()>,
+    at: u64,
+) -> Option<BTreeMap<u8, MetaParity>> {
+    let mut head = [0u8; 12];
+    read_at(at, &mut head).ok()?;
+    if &head[..8] != BLOCK_MAGIC {
+        return None;
+    }
+    let len = u32::from_le_bytes(head[8..].try_into().unwrap()) as usize;
+    if len > MAX_BODY {
+        return None;
+    }
+    let mut body = vec![0u8; len + 4];
+    read_at(at + 12, &mut body).ok()?;
+    let crc = u32::from_le_bytes(body.split_off(len).try_into().unwrap());
+    if crc32(&body) != crc {
+        return None;
+    }
+    let mut sections = BTreeMap::new();
+    let mut rest = body.as_slice();
+    while !rest.is_empty() {
+        let tag = rest[0];
+        let n = u32::from_le_bytes(rest.get(1..5)?.try_into().ok()?) as usize;
+        sections.insert(tag, MetaParity::decode(rest.get(5..5 + n)?)?);
+        rest = &rest[5 + n..];
+    }
+    Some(sections)
+}
+
+/// Read the parity block of a volume file, which follows its index.
+pub fn read_volume_block(
+    f: &mut File,
+    idx_off: u64,
+    idx_len: u64,
+) -> Option<BTreeMap<u8, MetaParity>> {
+    read_block(
+        |off, buf| {
+            f.seek(SeekFrom::Start(off))?;
+            f.read_exact(buf)?;
+            Ok(())
+        },
+        idx_off + idx_len,
+    )
+}
+
+/// Rebuild the damaged `manifest.json` at `path` (whose bytes are `raw`) from
+/// the manifest parity in the volumes next to it.
+pub fn recover_manifest_json(path: &Path, raw: &[u8]) -> Option<Vec<u8>> {
+    if path.file_name()? != MANIFEST_JSON {
+        return None;
+    }
+    // The manifest naming the volumes is what is damaged: go by extension
+    let mut vols: Vec<_> = std::fs::read_dir(path.parent()?)
+        .ok()?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.extension().is_some_and(|e| e == "parxv"))
+        .collect();
+    vols.sort();
+    vols.into_iter().find_map(|p| {
+        let mut f = File::open(p).ok()?;
+        let (off, len, _) = crate::index::read_trailer(&mut f).ok()?;
+        read_volume_block(&mut f, off, len)?.get(&TAG_MANIFEST)?.recover(raw)
+    })
+}
@@ -0,0 +1,117 @@
+//! Detection of protected files that were renamed or moved.
+//!
+//! A renamed file looks lost to verify and repair: its recorded path is gone
+//! and its data sits under a name the manifest does not list. For each file
+//! that is missing, [`detect`] looks among the unlisted files under the
+//! set's common directory for one of the same size whose first chunk hashes
+//! the same, and confirms the candidate by hashing the rest of it. `verify`
+//! then reports the file as moved rather than lost, and `repair --fix-paths`
+//! records the new name in the manifest instead of recreating the old path.
+
+use crate::manifest::Manifest;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct MovedFile {
+    /// Path the manifest records
+    pub from: String,
+    /// Path the file's data was found under
+    pub to: String,
+}
+
+/// Record the new names of `moved` files in `mf`. The order of the files
+/// is kept, since the merkle root follows it.
+pub fn apply(mf: &mut Manifest, moved: &[MovedFile]) {
+    for m in moved {
+        if let Some(fe) = mf.files.iter_mut().find(|fe| fe.rel_path == m.from) {
+            fe.rel_path = m.to.clone();
+        }
+    }
+}
+
+#[cfg(feature = "full")]
+pub use scan::detect;
+
+#[cfg(feature = "full")]
+mod scan {
+    use super::MovedFile;
+    use crate::encode::{rel_sort_key, scan_files};
+    use crate::manifest::{FileEntry, Manifest};
+    use crate::merkle::ChunkHasher;
+    use crate::path_safety::{validate_path, PathPolicy};
+    use anyhow::Result;
+    use std::collections::HashSet;
+    use std::fs::File;
+    use std::io::{Read, Seek, SeekFrom};
+    use std::path::{Path, PathBuf};
+
+    /// Files of `mf` missing under `root` whose exact contents were found
+    /// under another, unlisted name. Files without chunks are never matched.
+    pub fn detect(mf: &Manifest, root: &Path, policy: PathPolicy) -> Result<Vec<MovedFile>> {
+        let missing: Vec<&FileEntry> = mf
+            .files
+            .iter()
+            .filter(|fe| !fe.chunks.is_empty())
+            .filter(|fe| {
+                validate_path(root, Path::new(&fe.rel_path), policy)
+                    .is_ok_and(|p| p.symlink_metadata().is_err())
+            })
+            .collect();
+        let base = root.join(common_dir(mf));
+        if missing.is_empty() || !base.is_dir() {
+            return Ok(Vec::new());
+        }
+        let listed: HashSet<&str> = mf.files.iter().map(|f| f.rel_path.as_str()).collect();
+        let (scanned, _) = scan_files(&base, &mf.exclude)?;
+        let mut found: HashSet<&str> = HashSet::new();
+        let mut moved = Vec::new();
+        for path in scanned {
+            let Ok(rel) = path.strip_prefix(root) else { continue };
+            let rel = String::from_utf8_lossy(&rel_sort_key(rel)).into_owned();
+            if listed.contains(rel.as_str()) {
+                continue;
+            }
+            let Ok(size) = path.metadata().map(|m| m.len()) else { continue };
+            let same_size = missing.iter().filter(|fe| fe.size == size);
+            for fe in same_size.filter(|fe| !found.contains(fe.rel_path.as_str())) {
+                if holds(&path, fe, &mf.hasher()) {
+                    found.insert(&fe.rel_path);
+                    moved.push(MovedFile { from: fe.rel_path.clone(), to: rel });
+                    break;
+                }
+            }
+        }
+        moved.sort_by(|a, b| a.from.cmp(&b.from));
+        Ok(moved)
+    }
+
+    /// The deepest directory holding every file of `mf`, relative to the
+    /// root; moved files are looked for below it.
+    fn common_dir(mf: &Manifest) -> PathBuf {
+        let mut common: Option<Vec<&str>> = None;
+        for fe in &mf.files {
+            let mut dirs: Vec<&str> = fe.rel_path.split(['/', '\\']).collect();
+            dirs.pop();
+            common = Some(match common {
+                None => dirs,
+                Some(c) => {
+                    c.into_iter().zip(dirs).take_while(|(a, b)| a == b).map(|(a, _)| a).collect()
+                }
+            });
+        }
+        common.unwrap_or_default().iter().collect()
+    }
+
+    /// Whether the file at `path` holds exactly the chunks of `fe`; the first
+    /// chunk is compared first, so most candidates are rejected after one
+    /// read.
+    fn holds(path: &Path, fe: &FileEntry, hasher: &ChunkHasher) -> bool {
+        let Ok(mut f) = File::open(path) else { return false };
+        fe.chunks.iter().all(|c| {
+            let mut buf = vec![0u8; c.len as usize];
+            f.seek(SeekFrom::Start(c.file_offset)).is_ok()
+                && f.read_exact(&mut buf).is_ok()
+                && hasher.matches(&buf, &c.hash_hex)
+        })
+    }
+}
@@ -0,0 +1,228 @@
+//! Outer RS across groups of stripes (`create --outer-group G --outer-parity P`).
+//!
+//! Stripes are taken G at a time and P outer shards are computed over each
+//! group's members: the inner parity shards of every stripe and, with
+//! `--outer-scope full`, its data chunks too. Inner parity handles scattered
+//! damage; the outer shards let repair recover a stripe that lost more than
+//! `m` shards in a burst, as long as the group as a whole lost at most `P`
+//! members. Outer shards are indexed as [`ShardKind::Outer`] with `stripe`
+//! set to the group number.
+//!
+//! Stripes of critical files (`create --critical`) take their extra inner
+//! parity shards into the group too. Which stripes those are is recorded as
+//! runs under [`key::CRITICAL_STRIPES`]; sets without the key have none.
+//!
+//! [`ShardKind::Outer`]: crate::volume::ShardKind::Outer
+
+use crate::ext::{key, ExtMap};
+use crate::manifest::Manifest;
+use crate::rs_codec::RsCodec;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::ops::Range;
+
+/// Members an outer group covers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OuterScope {
+    /// Inner parity shards only
+    #[default]
+    Parity,
+    /// Data chunks and inner parity shards
+    Full,
+}
+
+impl OuterScope {
+    pub fn to_ext(self, ext: &mut ExtMap) {
+        ext.insert_u32(key::OUTER_SCOPE, (self == OuterScope::Full) as u32);
+    }
+
+    pub fn from_ext(ext: &ExtMap) -> Self {
+        match ext.get_u32(key::OUTER_SCOPE) {
+            Some(1) => OuterScope::Full,
+            _ => OuterScope::Parity,
+        }
+    }
+}
+
+/// Geometry of the outer code of a set.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OuterLayout {
+    pub k: usize,
+    pub m: usize,
+    /// Stripes per group
+    pub group: usize,
+    /// Outer shards per group
+    pub parity: usize,
+    pub scope: OuterScope,
+    /// Extra inner parity shards of critical stripes
+    pub critical_parity: usize,
+    /// Critical stripes, as ascending runs
+    pub critical: Vec<Range<u64>>,
+}
+
+impl OuterLayout {
+    /// `None` when the set has no outer parity.
+    pub fn new(k: usize, m: usize, group: usize, parity: usize, scope: OuterScope) -> Option<Self> {
+        (group > 0 && parity > 0 && m > 0).then_some(Self {
+            k,
+            m,
+            group,
+            parity,
+            scope,
+            critical_parity: 0,
+            critical: Vec::new(),
+        })
+    }
+
+    pub fn from_manifest(mf: &Manifest) -> Option<Self> {
+        let m = mf.parity_shards();
+        let layout = Self::new(
+            mf.stripe_k,
+            m,
+            mf.outer_group,
+            mf.outer_parity,
+            OuterScope::from_ext(&mf.ext),
+        )?;
+        let runs = mf.ext.get(key::CRITICAL_STRIPES).unwrap_or_default();
+        let critical = runs
+            .chunks_exact(16)
+            .map(|r| {
+                let first = u64::from_le_bytes(r[..8].try_into().expect("8 bytes"));
+                let count = u64::from_le_bytes(r[8..].try_into().expect("8 bytes"));
+                first..first.saturating_add(count)
+            })
+            .collect();
+        Some(layout.with_critical(mf.critical_parity, critical))
+    }
+
+    /// The same layout with `critical` stripes carrying `extra` more inner
+    /// parity shards.
+    pub fn with_critical(self, extra: usize, critical: Vec<Range<u64>>) -> Self {
+        Self {
+            critical_parity: extra,
+            critical: if extra > 0 { critical } else { Vec::new() },
+            ..self
+        }
+    }
+
+    /// Runs of `stripes`, ascending.
+    pub fn runs(stripes: &HashSet<u64>) -> Vec<Range<u64>> {
+        let mut sorted: Vec<u64> = stripes.iter().copied().collect();
+        sorted.sort_unstable();
+        let mut runs: Vec<Range<u64>> = Vec::new();
+        for s in sorted {
+            match runs.last_mut() {
+                Some(r) if r.end == s => r.end += 1,
+                _ => runs.push(s..s + 1),
+            }
+        }
+        runs
+    }
+
+    /// Record the critical stripes in a manifest's ext map.
+    pub fn critical_to_ext(&self, ext: &mut ExtMap) -> Result<()> {
+        if self.critical.is_empty() {
+            return Ok(());
+        }
+        if self.critical.len() * 16 > crate::ext::MAX_EXT_VALUE {
+            bail!(
+                "critical files are spread over {} runs of stripes, too many to record for outer groups",
+                self.critical.len()
+            );
+        }
+        let value = self
+            .critical
+            .iter()
+            .flat_map(|r| r.start.to_le_bytes().into_iter().chain((r.end - r.start).to_le_bytes()))
+            .collect();
+        ext.insert(key::CRITICAL_STRIPES, value);
+        Ok(())
+    }
+
+    /// Inner parity shards of `stripe`.
+    pub fn parity_of(&self, stripe: u64) -> usize {
+        let i = self.critical.partition_point(|r| r.end <= stripe);
+        match self.critical.get(i) {
+            Some(r) if r.contains(&stripe) => self.m + self.critical_parity,
+            _ => self.m,
+        }
+    }
+
+    /// Slots of a stripe of `shards` shards (data and inner parity) that
+    /// are outer members.
+    fn member_slots(&self, shards: usize) -> Range<usize> {
+        match self.scope {
+            OuterScope::Parity => self.k..shards,
+            OuterScope::Full => 0..shards,
+        }
+    }
+
+    /// Reject geometries the GF(2^8) code cannot hold (at most 256 shards).
+    /// Critical stripes count with their extra parity.
+    pub fn validate(&self, critical_parity: usize) -> Result<()> {
+        let widest = self.k + self.m + critical_parity;
+        let members = self.group * self.member_slots(widest).len();
+        if members + self.parity > 256 {
+            bail!(
+                "outer group of {} stripes has {} members; with {} outer shards that exceeds 256 (use a smaller --outer-group)",
+                self.group,
+                members,
+                self.parity
+            );
+        }
+        Ok(())
+    }
+
+    pub fn group_of(&self, stripe: u64) -> u64 {
+        stripe / self.group as u64
+    }
+
+    /// Stripes of `group`; the last group of a set may be short.
+    pub fn stripes_of(&self, group: u64, total_stripes: u64) -> Range<u64> {
+        let start = group * self.group as u64;
+        start..(start + self.group as u64).min(total_stripes)
+    }
+
+    /// Outer shards over `stripes`, each holding its `k +`
+    /// [`parity_of`](Self::parity_of) shards.
+    pub fn encode(&self, stripes: &[Vec<Vec<u8>>]) -> Result<Vec<Vec<u8>>> {
+        let len = stripes.first().and_then(|s| s.first()).map(|b| b.len()).unwrap_or(0);
+        let mut members: Vec<Vec<u8>> = stripes
+            .iter()
+            .flat_map(|s| self.member_slots(s.len()).map(move |i| s[i].clone()))
+            .collect();
+        let n = members.len();
+        members.extend((0..self.parity).map(|_| vec![0u8; len]));
+        let rs = RsCodec::new(n, self.parity).context("init outer RS")?;
+        let mut shards: Vec<&mut [u8]> = members.iter_mut().map(|b| b.as_mut_slice()).collect();
+        rs.encode(&mut shards).context("outer RS encode")?;
+        Ok(members.split_off(n))
+    }
+
+    /// Fill in the missing members of `stripes` (each `k +`
+    /// [`parity_of`](Self::parity_of) slots; slots outside the scope are
+    /// left alone) from the outer shards.
+    pub fn reconstruct(
+        &self,
+        stripes: &mut [Vec<Option<Vec<u8>>>],
+        outer: Vec<Option<Vec<u8>>>,
+    ) -> Result<()> {
+        let mut members: Vec<Option<Vec<u8>>> = stripes
+            .iter()
+            .flat_map(|s| self.member_slots(s.len()).map(move |i| s[i].clone()))
+            .collect();
+        let n = members.len();
+        members.extend(outer);
+        let rs = RsCodec::new(n, self.parity).context("init outer RS")?;
+        rs.reconstruct(&mut members).context("outer RS reconstruct")?;
+        let mut it = members.into_iter();
+        for s in stripes.iter_mut() {
+            for i in self.member_slots(s.len()) {
+                s[i] = it.next().flatten();
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,329 @@
+//! Portable bundles of a whole parity set (`parx pack` / `parx unpack`).
+//!
+//! A bundle is a plain ustar archive. Its first member, [`ENVELOPE`], lists
+//! every other member with its size and BLAKE3 hash, plus a digest over that
+//! list; the remaining members are the files of the parity dir, in the
+//! envelope's order. Unpacking checks each member against the envelope while
+//! it streams and only moves files into place once every member matched, so
+//! a bundle damaged in transit never leaves a half-valid set behind, and the
+//! archive can come from a pipe or tape.
+//!
+//! Volumes are left out unless asked for; state that only makes sense on the
+//! machine that wrote it (the repair lock, an unfinished create journal, the
+//! paritycheck and hash caches and filesystem hints) never goes in. Extracted manifests
+//! get their `parity_dir` pointed at the destination, as when `versions`
+//! archives a set.
+
+use crate::manifest::{Manifest, MANIFEST_JSON, MANIFEST_V2};
+use crate::tarstream::{EntryKind, TarReader, TarWriter};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+pub const PACK_FORMAT: &str = "parx-pack/1";
+/// Name of the envelope member, always first in the archive.
+pub const ENVELOPE: &str = "PARXPACK.json";
+/// Largest envelope accepted when unpacking.
+const MAX_ENVELOPE: u64 = 64 << 20;
+const EXCLUDED: [&str; 5] = [
+    ".parx.repair.lock",
+    crate::journal::JOURNAL_FILE,
+    crate::paritycache::CACHE_FILE,
+    crate::fshint::HINTS_FILE,
+    crate::hashcache::CACHE_FILE,
+];
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PackMember {
+    /// Path relative to the parity dir, `/`-separated
+    pub path: String,
+    pub size: u64,
+    pub blake3_hex: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Envelope {
+    pub format: String,
+    /// Whether the parity volumes are in the bundle
+    pub volumes: bool,
+    pub members: Vec<PackMember>,
+    /// BLAKE3 of the JSON of `members`, so a damaged envelope is not mistaken
+    /// for damaged members
+    pub digest: String,
+}
+
+impl Envelope {
+    fn digest_of(members: &[PackMember]) -> Result<String> {
+        Ok(blake3::hash(&serde_json::to_vec(members)?).to_hex().to_string())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PackOptions {
+    /// Include the `*.parxv` volumes and `*.parxp` volume parity (the set is
+    /// only repairable with them)
+    pub volumes: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct UnpackOptions {
+    /// Only check the bundle; write nothing
+    pub check_only: bool,
+    /// Overwrite files that already exist in the destination
+    pub force: bool,
+}
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct PackReport {
+    pub files: u64,
+    pub bytes: u64,
+    pub volumes: bool,
+}
+
+/// Write a bundle of the parity dir `parx_dir` to `out`.
+pub fn pack(parx_dir: &Path, out: impl Write, opts: &PackOptions) -> Result<PackReport> {
+    if !parx_dir.join(MANIFEST_JSON).is_file() && !parx_dir.join(MANIFEST_V2).is_file() {
+        bail!("{:?} holds no manifest; nothing to pack", parx_dir);
+    }
+    let mut files = Vec::new();
+    collect(parx_dir, "", opts, &mut files)?;
+    files.sort();
+    let members = files
+        .iter()
+        .map(|rel| {
+            let path = parx_dir.join(rel);
+            let mut hasher = blake3::Hasher::new();
+            let size = io::copy(
+                &mut File::open(&path).with_context(|| format!("open {:?}", path))?,
+                &mut hasher,
+            )
+            .with_context(|| format!("read {:?}", path))?;
+            Ok(PackMember {
+                path: rel.clone(),
+                size,
+                blake3_hex: hasher.finalize().to_hex().to_string(),
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let envelope = Envelope {
+        format: PACK_FORMAT.to_string(),
+        volumes: opts.volumes,
+        digest: Envelope::digest_of(&members)?,
+        members,
+    };
+    let env_bytes = serde_json::to_vec_pretty(&envelope)?;
+    let mut tar = TarWriter::new(out);
+    tar.append(ENVELOPE, env_bytes.len() as u64, 0o644, 0, &env_bytes[..])?;
+    let mut rep = PackReport { volumes: opts.volumes, ..Default::default() };
+    for m in &envelope.members {
+        let path = parx_dir.join(&m.path);
+        let f = File::open(&path).with_context(|| format!("open {:?}", path))?;
+        let meta = f.metadata()?;
+        if meta.len() != m.size {
+            bail!("{:?} changed while packing", path);
+        }
+        tar.append(&m.path, m.size, mode_of(&meta), mtime_of(&meta), &f)?;
+        rep.files += 1;
+        rep.bytes += m.size;
+    }
+    tar.finish()?;
+    Ok(rep)
+}
+
+fn collect(dir: &Path, prefix: &str, opts: &PackOptions, out: &mut Vec<String>) -> Result<()> {
+    for ent in fs::read_dir(dir).with_context(|| format!("read_dir {:?}", dir))? {
+        let ent = ent?;
+        let Ok(name) = ent.file_name().into_string() else {
+            bail!("non-UTF-8 name {:?} in {:?}", ent.file_name(), dir);
+        };
+        let rel = if prefix.is_empty() { name.clone() } else { format!("{}/{}", prefix, name) };
+        let ty = ent.file_type()?;
+        if ty.is_dir() {
+            collect(&ent.path(), &rel, opts, out)?;
+        } else if ty.is_file()
+            && !EXCLUDED.contains(&name.as_str())
+            && (opts.volumes || !(name.ends_with(".parxv") || name.ends_with(".parxp")))
+            && rel != ENVELOPE
+        {
+            out.push(rel);
+        }
+    }
+    Ok(())
+}
+
+/// Check the bundle read from `input` and, unless `check_only`, extract it
+/// into `dest`. Fails on the first member that does not match the envelope,
+/// and when members are missing or unexpected.
+pub fn unpack(input: impl Read, dest: &Path, opts: &UnpackOptions) -> Result<Envelope> {
+    let mut tar = TarReader::new(input);
+    let Some(first) = tar.next_entry()? else { bail!("empty bundle") };
+    if first.path != ENVELOPE || first.kind != EntryKind::File {
+        bail!("not a parx bundle: first member is {:?}, expected {}", first.path, ENVELOPE);
+    }
+    if first.size > MAX_ENVELOPE {
+        bail!("bundle envelope of {} bytes is too large", first.size);
+    }
+    let mut raw = Vec::new();
+    tar.data().read_to_end(&mut raw)?;
+    let envelope: Envelope = serde_json::from_slice(&raw).context("parse bundle envelope")?;
+    if envelope.format != PACK_FORMAT {
+        bail!("unsupported bundle format {:?} (expected {})", envelope.format, PACK_FORMAT);
+    }
+    if Envelope::digest_of(&envelope.members)? != envelope.digest {
+        bail!("bundle envelope is damaged (digest mismatch)");
+    }
+    let mut expected: BTreeMap<&str, &PackMember> = BTreeMap::new();
+    for m in &envelope.members {
+        if m.path.starts_with('/')
+            || m.path.split('/').any(|p| p.is_empty() || p == "." || p == "..")
+        {
+            bail!("bundle envelope names unsafe path {:?}", m.path);
+        }
+        if expected.insert(m.path.as_str(), m).is_some() {
+            bail!("bundle envelope lists {:?} twice", m.path);
+        }
+    }
+    if !opts.check_only && !opts.force {
+        if let Some(m) = envelope.members.iter().find(|m| dest.join(&m.path).exists()) {
+            bail!("{:?} already exists; pass --force to overwrite", dest.join(&m.path));
+        }
+    }
+
+    // Members are staged next to their targets and only moved into place
+    // once the whole bundle checked out
+    let mut staged = Vec::new();
+    if let Err(e) = extract_members(&mut tar, expected, dest, opts, &mut staged) {
+        for (partial, _) in &staged {
+            let _ = fs::remove_file(partial);
+        }
+        return Err(e);
+    }
+    for (partial, target) in staged {
+        fs::rename(&partial, &target).with_context(|| format!("rename into {:?}", target))?;
+    }
+    if !opts.check_only {
+        for m in envelope.members.iter().filter(|m| is_manifest_json(&m.path)) {
+            relocate(&dest.join(&m.path))?;
+        }
+    }
+    Ok(envelope)
+}
+
+fn is_manifest_json(rel: &str) -> bool {
+    rel.rsplit('/').next() == Some(MANIFEST_JSON)
+}
+
+/// Point the manifest at `path` to the directory its set now lives in.
+fn relocate(path: &Path) -> Result<()> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let raw = fs::read(path)?;
+    let Ok(mut mf) = serde_json::from_slice::<Manifest>(&raw) else {
+        return Ok(());
+    };
+    let mut set_dir = dir;
+    if crate::submanifest::subset(&mf).is_some() {
+        set_dir = dir.parent().and_then(Path::parent).unwrap_or(dir);
+    }
+    let here = set_dir.to_string_lossy().to_string();
+    if mf.parity_dir != here {
+        mf.parity_dir = here.clone();
+        crate::manifest::save(&mf, dir)?;
+        // manifest.json keeps its other fields as stored, unknown ones
+        // included, so that a signature over it still holds
+        let mut json: serde_json::Value = serde_json::from_slice(&raw)?;
+        json["parity_dir"] = here.into();
+        fs::write(path, serde_json::to_vec_pretty(&json)?)?;
+    }
+    Ok(())
+}
+
+/// Stream the members after the envelope into their partial files, listed
+/// in `staged` as they are created.
+fn extract_members<R: Read>(
+    tar: &mut TarReader<R>,
+    mut expected: BTreeMap<&str, &PackMember>,
+    dest: &Path,
+    opts: &UnpackOptions,
+    staged: &mut Vec<(PathBuf, PathBuf)>,
+) -> Result<()> {
+    // Past the envelope a broken tar header can only be damage in transit
+    while let Some(ent) = tar.next_entry().context("bundle is damaged")? {
+        if ent.kind == EntryKind::Dir {
+            continue;
+        }
+        let Some(m) = expected.remove(ent.path.as_str()) else {
+            bail!("bundle member {:?} is not in the envelope (or appears twice)", ent.path);
+        };
+        if ent.size != m.size {
+            bail!("bundle member {:?} is {} bytes, envelope says {}", m.path, ent.size, m.size);
+        }
+        let target = dest.join(&m.path);
+        let partial = partial_path(&target);
+        let mut sink: Box<dyn Write> = if opts.check_only {
+            Box::new(io::sink())
+        } else {
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent).with_context(|| format!("create {:?}", parent))?;
+            }
+            let f = File::create(&partial).with_context(|| format!("create {:?}", partial))?;
+            staged.push((partial.clone(), target.clone()));
+            Box::new(f)
+        };
+        let mut hashing = HashingWriter { inner: &mut sink, hasher: blake3::Hasher::new() };
+        let copied = io::copy(&mut tar.data(), &mut hashing);
+        let hash = hashing.hasher.finalize().to_hex().to_string();
+        drop(sink);
+        let ok = matches!(copied, Ok(n) if n == m.size) && hash == m.blake3_hex;
+        if !ok {
+            copied.with_context(|| format!("read bundle member {:?}", m.path))?;
+            bail!("bundle member {:?} is damaged (size or BLAKE3 mismatch)", m.path);
+        }
+    }
+    if let Some(path) = expected.keys().next() {
+        bail!("bundle is missing {} member(s), first {:?}", expected.len(), path);
+    }
+    Ok(())
+}
+
+fn partial_path(target: &Path) -> PathBuf {
+    let mut name = target.file_name().unwrap_or_default().to_os_string();
+    name.push(".parx-unpack");
+    target.with_file_name(name)
+}
+
+struct HashingWriter<W> {
+    inner: W,
+    hasher: blake3::Hasher,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(unix)]
+fn mode_of(meta: &fs::Metadata) -> u32 {
+    std::os::unix::fs::PermissionsExt::mode(&meta.permissions())
+}
+
+#[cfg(not(unix))]
+fn mode_of(_meta: &fs::Metadata) -> u32 {
+    0o644
+}
+
+fn mtime_of(meta: &fs::Metadata) -> i64 {
+    meta.modified()
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs() as i64)
+}
@@ -0,0 +1,48 @@
+//! Page cache hints for long reads (`create --drop-cache`,
+//! `verify --drop-cache`).
+//!
+//! Hashing a multi-terabyte tree reads every byte once; left to itself the
+//! kernel keeps those pages and evicts what the machine's other users had
+//! cached. [`sequential`] tells it a file is about to be read front to back,
+//! so it reads ahead further and may drop pages behind the reader, and
+//! [`evict`] drops a file's clean pages once it has been hashed. Both are
+//! hints: they change nothing a reader sees, failures are ignored, and
+//! where `posix_fadvise` is not available (or without the `full` feature)
+//! they do nothing.
+
+use std::fs::File;
+
+/// Hint that `f` is about to be read front to back.
+pub fn sequential(f: &File) {
+    sys::advise(f, sys::SEQUENTIAL);
+}
+
+/// Hint that the cached pages of `f` will not be needed again.
+pub fn evict(f: &File) {
+    sys::advise(f, sys::DONTNEED);
+}
+
+#[cfg(all(target_os = "linux", feature = "full"))]
+mod sys {
+    use std::fs::File;
+    use std::os::unix::io::AsRawFd;
+
+    pub(super) const SEQUENTIAL: i32 = libc::POSIX_FADV_SEQUENTIAL;
+    pub(super) const DONTNEED: i32 = libc::POSIX_FADV_DONTNEED;
+
+    pub(super) fn advise(f: &File, advice: i32) {
+        // Offset 0 and length 0 cover the whole file. SAFETY: plain
+        // syscall on a descriptor `f` keeps open
+        let _ = unsafe { libc::posix_fadvise(f.as_raw_fd(), 0, 0, advice) };
+    }
+}
+
+#[cfg(not(all(target_os = "linux", feature = "full")))]
+mod sys {
+    use std::fs::File;
+
+    pub(super) const SEQUENTIAL: i32 = 0;
+    pub(super) const DONTNEED: i32 = 0;
+
+    pub(super) fn advise(_f: &File, _advice: i32) {}
+}
@@ -1,17 +1,20 @@
-use crate::index::{read_index, read_trailer, IndexLimits};
+use crate::index::{read_index, read_index_count, read_trailer, IndexLimits};
+use crate::manifest::{SetTag, Stray};
+use crate::paritycache::{Fingerprint, ParityCache};
+use crate::volume::{FeatureError, VolumeHeader};
 use anyhow::Result;
 use std::collections::HashMap;
 use std::fs::File;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug, Clone, serde::Serialize)]
 pub struct ParityAuditReport {
     pub volumes: usize,
-    pub stripe_parity_counts: HashMap<u32, usize>,
+    pub stripe_parity_counts: HashMap<u64, usize>,
 }
 
 pub fn audit(parity_dir: &Path) -> Result<ParityAuditReport> {
-    let mut counts: HashMap<u32, usize> = HashMap::new();
+    let mut counts: HashMap<u64, usize> = HashMap::new();
     let mut vols = 0usize;
     if parity_dir.exists() {
         for ent in std::fs::read_dir(parity_dir)? {
@@ -21,7 +24,7 @@ pub fn audit(parity_dir: &Path) -> Result<ParityAuditReport> {
                 let mut f = File::open(&p)?;
                 let (off, len, crc) = read_trailer(&mut f)?;
                 let entries = read_index(&mut f, off, len, crc, &IndexLimits::default())?;
-                for e in entries {
+                for e in entries.iter().filter(|e| e.is_inner()) {
                     *counts.entry(e.stripe).or_default() += 1;
                 }
             }
@@ -29,3 +32,193 @@ pub fn audit(parity_dir: &Path) -> Result<ParityAuditReport> {
     }
     Ok(ParityAuditReport { volumes: vols, stripe_parity_counts: counts })
 }
+
+/// Outcome of `paritycheck` for one volume.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct VolumeCheck {
+    pub volume: String,
+    pub entries: usize,
+    /// `ok`, `unsupported` (features this build cannot read), `error` or `open_error`
+    pub index: String,
+    /// Shards failing their hash; `None` unless the shards were hashed (`--deep`)
+    pub shards_bad: Option<usize>,
+    /// The result came from `paritycheck.cache`
+    pub cached: bool,
+    pub error: Option<String>,
+}
+
+/// `paritycheck` over a parity dir, in volume order.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ParitycheckReport {
+    /// Shard payloads were hashed, not just the indices parsed
+    pub deep: bool,
+    pub volumes: Vec<VolumeCheck>,
+    pub shards_bad: usize,
+    /// Tolerance of each failure domain level recorded in the set's
+    /// manifest (`create --volume-groups`), when it was at hand
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub domains: Vec<crate::domains::DomainTolerance>,
+}
+
+impl crate::report::Report for ParitycheckReport {
+    const KIND: &'static str = "paritycheck";
+}
+
+/// Check the volumes `vols` (all in `dir`'s set) for `paritycheck`: parse
+/// each index and, with `deep`, hash its shards. Volumes are checked
+/// concurrently (and their shards within each); results are in volume order.
+/// Deep results found in `cache` with a matching fingerprint are reused;
+/// the cache to save for the next run is returned alongside.
+pub fn paritycheck(
+    vols: &[PathBuf],
+    cache: &ParityCache,
+    deep: bool,
+) -> (ParitycheckReport, ParityCache) {
+    use rayon::prelude::*;
+    let mut next = ParityCache::default();
+    let results: Vec<_> = vols
+        .par_iter()
+        .map(|p| {
+            let name = file_name(p);
+            let mut f = File::open(p)?;
+            let fp = if deep { Fingerprint::of(&mut f) } else { None };
+            let checked = match fp.as_ref().and_then(|fp| cache.get(&name, fp)) {
+                Some(chk) => Ok((chk.clone(), true)),
+                None => crate::heal::check_volume(&mut f, deep).map(|(_, chk)| (chk, false)),
+            };
+            Ok::<_, std::io::Error>((name, fp, checked))
+        })
+        .collect();
+    let mut rep = ParitycheckReport { deep, ..Default::default() };
+    for (p, res) in vols.iter().zip(results) {
+        let failed = |volume: String, index: &str, e: String| VolumeCheck {
+            volume,
+            entries: 0,
+            index: index.to_string(),
+            shards_bad: None,
+            cached: false,
+            error: Some(e),
+        };
+        let (name, fp, checked) = match res {
+            Ok(r) => r,
+            Err(e) => {
+                rep.volumes.push(failed(file_name(p), "open_error", e.to_string()));
+                continue;
+            }
+        };
+        rep.volumes.push(match checked {
+            Ok((chk, cached)) => {
+                let shards_bad = deep.then_some(chk.bad.len());
+                rep.shards_bad += chk.bad.len();
+                let vc = VolumeCheck {
+                    volume: name.clone(),
+                    entries: chk.entries,
+                    index: "ok".to_string(),
+                    shards_bad,
+                    cached,
+                    error: None,
+                };
+                if let Some(fp) = fp {
+                    next.insert(&name, fp, chk);
+                }
+                vc
+            }
+            Err(e) if e.is::<FeatureError>() => failed(name, "unsupported", e.to_string()),
+            Err(e) => failed(name, "error", format!("{:#}", e)),
+        });
+    }
+    (rep, next)
+}
+
+/// Outcome of `quickcheck` for one volume.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct QuickVolume {
+    pub volume: String,
+    pub entries: usize,
+    /// `ok`, `foreign` (a volume of another set), `stale` (of another
+    /// generation of the set), `unsupported`, `error` or `open_error`
+    pub index: String,
+    /// Id of the set a foreign volume belongs to
+    pub set_id: Option<String>,
+    /// Generation a stale volume belongs to, if it records one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub generation: Option<u64>,
+    pub error: Option<String>,
+}
+
+/// `quickcheck` over a parity dir: index entry counts, nothing hashed.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct QuickcheckReport {
+    pub volumes: Vec<QuickVolume>,
+    pub total_entries: u64,
+    pub foreign: usize,
+    #[serde(default)]
+    pub stale: usize,
+}
+
+impl crate::report::Report for QuickcheckReport {
+    const KIND: &'static str = "quickcheck";
+}
+
+/// Count the index entries of `vols`. With the tag of the `set` they should
+/// belong to, volumes of another set are flagged `foreign`, those of another
+/// generation `stale`, and neither is read further.
+pub fn quickcheck(vols: &[PathBuf], set: SetTag) -> QuickcheckReport {
+    let mut rep = QuickcheckReport::default();
+    for p in vols {
+        let mut v = QuickVolume {
+            volume: file_name(p),
+            entries: 0,
+            index: "ok".to_string(),
+            set_id: None,
+            generation: None,
+            error: None,
+        };
+        let mut f = match File::open(p) {
+            Ok(f) => f,
+            Err(e) => {
+                v.index = "open_error".to_string();
+                v.error = Some(e.to_string());
+                rep.volumes.push(v);
+                continue;
+            }
+        };
+        let theirs = VolumeHeader::read_from(&f).ok().map(|h| SetTag::from_ext(&h.ext));
+        match theirs.and_then(|t| set.stray(&t)) {
+            Some(Stray::Foreign(id)) => {
+                v.index = "foreign".to_string();
+                v.set_id = Some(id.to_string());
+                rep.foreign += 1;
+                rep.volumes.push(v);
+                continue;
+            }
+            Some(Stray::Stale { theirs, .. }) => {
+                v.index = "stale".to_string();
+                v.generation = theirs;
+                rep.stale += 1;
+                rep.volumes.push(v);
+                continue;
+            }
+            None => {}
+        }
+        let counted = read_trailer(&mut f).and_then(|(off, len, crc)| {
+            read_index_count(&mut f, off, len, crc, &IndexLimits::default())
+        });
+        match counted {
+            Ok(n) => {
+                v.entries = n;
+                rep.total_entries += n as u64;
+            }
+            Err(e) => {
+                v.index = if e.is::<FeatureError>() { "unsupported" } else { "error" }.to_string();
+                v.error = Some(e.to_string());
+            }
+        }
+        rep.volumes.push(v);
+    }
+    rep
+}
+
+fn file_name(p: &Path) -> String {
+    p.file_name().map_or_else(|| p.display().to_string(), |n| n.to_string_lossy().into_owned())
+}
@@ -0,0 +1,75 @@
+//! Result cache for `paritycheck`. Hashing every shard of every volume on
+//! each run is wasted work when the volumes have not been touched, so each
+//! volume's [`ShardCheck`] is kept in `<parity dir>/paritycheck.cache` with a
+//! [`Fingerprint`] of the file: size, mtime and the CRC its index trailer
+//! records. A volume whose fingerprint still matches is not re-read. Bit rot
+//! that changes none of the three goes unnoticed until `--no-cache` or a
+//! `vol heal`/`verify` run reads the shards again.
+
+use crate::heal::ShardCheck;
+use crate::index::read_trailer;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+pub const CACHE_FILE: &str = "paritycheck.cache";
+
+/// What identifies a volume's content without reading its shards.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Fingerprint {
+    pub size: u64,
+    pub mtime_ns: i128,
+    pub trailer_crc: u32,
+}
+
+impl Fingerprint {
+    /// `None` when the trailer does not parse; such volumes are never cached.
+    pub fn of(f: &mut File) -> Option<Self> {
+        let md = f.metadata().ok()?;
+        let mtime_ns = md.modified().ok()?.duration_since(UNIX_EPOCH).ok()?.as_nanos() as i128;
+        let (_, _, trailer_crc) = read_trailer(f).ok()?;
+        Some(Self { size: md.len(), mtime_ns, trailer_crc })
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CachedCheck {
+    pub fingerprint: Fingerprint,
+    pub check: ShardCheck,
+}
+
+/// Last check of each volume, keyed by file name.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ParityCache {
+    pub volumes: BTreeMap<String, CachedCheck>,
+}
+
+impl ParityCache {
+    /// The cache in `parity_dir`; empty when there is none or it does not parse.
+    pub fn load(parity_dir: &Path) -> Self {
+        std::fs::read(parity_dir.join(CACHE_FILE))
+            .ok()
+            .and_then(|b| serde_json::from_slice(&b).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, parity_dir: &Path) -> Result<()> {
+        let path = parity_dir.join(CACHE_FILE);
+        let tmp = path.with_extension("cache.tmp");
+        std::fs::write(&tmp, serde_json::to_vec(self)?)
+            .with_context(|| format!("write {:?}", tmp))?;
+        std::fs::rename(&tmp, &path).with_context(|| format!("rename {:?}", tmp))
+    }
+
+    /// The cached check of `volume` if its fingerprint still matches.
+    pub fn get(&self, volume: &str, fp: &Fingerprint) -> Option<&ShardCheck> {
+        self.volumes.get(volume).filter(|c| c.fingerprint == *fp).map(|c| &c.check)
+    }
+
+    pub fn insert(&mut self, volume: &str, fingerprint: Fingerprint, check: ShardCheck) {
+        self.volumes.insert(volume.to_string(), CachedCheck { fingerprint, check });
+    }
+}
@@ -2,6 +2,7 @@ use anyhow::{bail, Result};
 #[cfg(windows)]
 use std::os::windows::fs::MetadataExt as _;
 use std::path::{Component, Path, PathBuf};
+use unicode_normalization::UnicodeNormalization;
 
 #[cfg(windows)]
 fn contains_path_case_insensitive(root: &Path, child: &Path) -> bool {
@@ -29,6 +30,49 @@ pub struct PathPolicy {
     pub follow_symlinks: bool,
 }
 
+/// Manifest form of a relative path: its components joined with `/` and
+/// put in Unicode NFC. The same tree gets the same names, and so the same
+/// file order and stripe layout, whatever the OS separator or the
+/// normalization its file system hands out (macOS decomposes, most others
+/// keep what was written).
+pub fn canonical_rel(rel: &Path) -> String {
+    let mut out = String::new();
+    for (i, c) in rel.components().enumerate() {
+        if i > 0 {
+            out.push('/');
+        }
+        out.extend(c.as_os_str().to_string_lossy().nfc());
+    }
+    out
+}
+
+/// `root.join(rel)`, with each component that does not exist as written
+/// replaced by the entry of its directory that has the same NFC form, so
+/// that a manifest path finds its file however the name is normalized on
+/// disk. Also returns the relative part as found.
+fn resolve(root: &Path, rel: &Path) -> (PathBuf, PathBuf) {
+    let mut cur = root.to_path_buf();
+    let mut found = PathBuf::new();
+    for comp in rel.components() {
+        let mut name = comp.as_os_str().to_os_string();
+        let ascii = name.to_str().is_some_and(|n| n.is_ascii());
+        if !ascii && std::fs::symlink_metadata(cur.join(&name)).is_err() {
+            let want: String = name.to_string_lossy().nfc().collect();
+            let same = std::fs::read_dir(&cur).ok().and_then(|dir| {
+                dir.flatten()
+                    .map(|e| e.file_name())
+                    .find(|n| n.to_string_lossy().nfc().eq(want.chars()))
+            });
+            if let Some(n) = same {
+                name = n;
+            }
+        }
+        cur.push(&name);
+        found.push(&name);
+    }
+    (cur, found)
+}
+
 /// Ensure `rel` is safe relative to `root`: no absolute, no `..`, and
 /// if `follow_symlinks` then canonicalized path must stay under root; otherwise
 /// warn on symlinks by returning a special error.
@@ -41,7 +85,7 @@ pub fn validate_path(root: &Path, rel: &Path, policy: PathPolicy) -> Result<Path
             bail!("parent traversal not allowed: {:?}", rel);
         }
     }
-    let candidate = root.join(rel);
+    let (candidate, on_disk) = resolve(root, rel);
     let meta = std::fs::symlink_metadata(&candidate);
     if !policy.follow_symlinks {
         if let Ok(m) = &meta {
@@ -51,7 +95,7 @@ pub fn validate_path(root: &Path, rel: &Path, policy: PathPolicy) -> Result<Path
         }
         // Also check any ancestor components are not symlinks
         let mut cur = root.to_path_buf();
-        for comp in rel.components() {
+        for comp in on_disk.components() {
             cur = cur.join(comp);
             if let Ok(m) = std::fs::symlink_metadata(&cur) {
                 let is_symlink = m.file_type().is_symlink();
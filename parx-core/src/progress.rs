@@ -1,90 +1,411 @@
-use std::sync::{
-    atomic::{AtomicBool, AtomicUsize, Ordering},
-    Arc, Mutex,
-};
-use std::thread;
+//! Progress of long-running work in hierarchical stages (`hash`, `encode`,
+//! `write/vol-000`, ...). Workers bump the atomic counters of their
+//! [`Stage`]; nothing on that path takes a lock (only creating a stage
+//! does). A reporter thread snapshots every stage at an interval, rolls the
+//! counters of child stages up into their parents, and hands the
+//! [`Snapshot`] to a [`ProgressSink`]: a line on stderr ([`TextSink`]), a
+//! JSON object per line ([`JsonSink`]), a state file that `parx top` reads
+//! from another terminal ([`crate::live::StateFileSink`]), or whatever a UI
+//! implements. The registry also carries a cancellation flag that the runs
+//! reporting into it check (see [`Progress::cancel`]).
+
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
 use std::time::{Duration, Instant};
 
+#[derive(Debug, Default)]
+struct Counters {
+    bytes_done: AtomicU64,
+    bytes_total: AtomicU64,
+    items_done: AtomicU64,
+    items_total: AtomicU64,
+    /// Milliseconds from the stage's start to its end, 0 while running
+    finished_ms: AtomicU64,
+    finished: AtomicBool,
+}
+
+#[derive(Debug)]
+struct StageInner {
+    path: String,
+    started: Instant,
+    counters: Counters,
+    /// What the stage is working on, e.g. the file being hashed
+    current: Mutex<Option<String>>,
+}
+
+/// Handle on one stage; cheap to clone and to update from any thread.
+#[derive(Clone, Debug)]
+pub struct Stage {
+    inner: Arc<StageInner>,
+    progress: Progress,
+}
+
+impl Stage {
+    pub fn path(&self) -> &str {
+        &self.inner.path
+    }
+
+    /// A stage below this one, e.g. `write` → `write/vol-000`.
+    pub fn child(&self, name: &str) -> Stage {
+        self.progress.stage(&format!("{}/{}", self.inner.path, name))
+    }
+
+    /// More expected bytes and items (files, stripes: whatever the stage
+    /// counts); adds up when a stage is reused, as by per-directory sets.
+    pub fn add_total(&self, bytes: u64, items: u64) {
+        let c = &self.inner.counters;
+        c.bytes_total.fetch_add(bytes, Ordering::Relaxed);
+        c.items_total.fetch_add(items, Ordering::Relaxed);
+    }
+
+    pub fn add_bytes(&self, n: u64) {
+        self.inner.counters.bytes_done.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn add_items(&self, n: u64) {
+        self.inner.counters.items_done.fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// Name what the stage works on now (a file, a volume); set once per
+    /// item, not per byte, as this one takes a lock.
+    pub fn set_current(&self, item: &str) {
+        *self.inner.current.lock().expect("progress current") = Some(item.to_string());
+    }
+
+    /// Mark the stage done; its rate stops at the time taken.
+    pub fn finish(&self) {
+        let c = &self.inner.counters;
+        if !c.finished.swap(true, Ordering::Relaxed) {
+            let ms = self.inner.started.elapsed().as_millis().max(1) as u64;
+            c.finished_ms.store(ms, Ordering::Relaxed);
+        }
+        *self.inner.current.lock().expect("progress current") = None;
+    }
+}
+
+/// A worker counted as busy until dropped (see [`Progress::busy`]).
+pub struct Busy(Arc<Shared>);
+
+impl Drop for Busy {
+    fn drop(&mut self) {
+        self.0.busy.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// One stage at the time of a snapshot. Counters of a stage include those
+/// of the stages below it.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct StageSnapshot {
+    /// `/`-separated, parents before their children
+    pub path: String,
+    pub depth: usize,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    pub items_done: u64,
+    pub items_total: u64,
+    pub elapsed_ms: u64,
+    /// Bytes per second since the stage started (until it finished)
+    pub bytes_per_sec: u64,
+    /// Time left at the current rate, while running with a known total
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub eta_ms: Option<u64>,
+    /// What the stage (or a running stage below it) works on now
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub current: Option<String>,
+    pub done: bool,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct Snapshot {
+    pub elapsed_ms: u64,
+    /// The last snapshot, taken when the work stopped
+    pub last: bool,
+    /// Worker threads of the run (0 = not reported) and how many of them
+    /// were busy at the time of the snapshot
+    #[serde(default)]
+    pub workers: u64,
+    #[serde(default)]
+    pub workers_busy: u64,
+    pub stages: Vec<StageSnapshot>,
+}
+
+/// Receives progress snapshots from the reporter thread.
+pub trait ProgressSink: Send + Sync {
+    fn report(&self, snap: &Snapshot);
+}
+
+/// Human-readable lines on stderr: one per stage still running, and a
+/// summary of the top-level stages at the end.
+#[derive(Debug, Default)]
+pub struct TextSink;
+
+impl ProgressSink for TextSink {
+    fn report(&self, snap: &Snapshot) {
+        for s in &snap.stages {
+            if (s.done && !snap.last) || (snap.last && s.depth > 0) {
+                continue;
+            }
+            let pct = match s.bytes_total {
+                0 => String::new(),
+                t => format!(" {:>3}%", (s.bytes_done as u128 * 100 / t as u128).min(100)),
+            };
+            let items = match s.items_total {
+                0 => String::new(),
+                t => format!(" | {}/{}", s.items_done, t),
+            };
+            eprintln!(
+                "[{:>4}s] {}{} | {} | {}/s{}",
+                snap.elapsed_ms / 1000,
+                s.path,
+                pct,
+                human_bytes(s.bytes_done),
+                human_bytes(s.bytes_per_sec),
+                items
+            );
+        }
+    }
+}
+
+/// One [`Snapshot`] per line of JSON on stderr, for UIs and scripts.
+#[derive(Debug, Default)]
+pub struct JsonSink;
+
+impl ProgressSink for JsonSink {
+    fn report(&self, snap: &Snapshot) {
+        if let Ok(line) = serde_json::to_string(snap) {
+            eprintln!("{}", line);
+        }
+    }
+}
+
+/// `n` bytes in B, KiB, MiB, ... with one decimal.
+pub fn human_bytes(n: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut v = n as f64;
+    let mut u = 0;
+    while v >= 1024.0 && u + 1 < UNITS.len() {
+        v /= 1024.0;
+        u += 1;
+    }
+    if u == 0 {
+        format!("{} B", n)
+    } else {
+        format!("{:.1} {}", v, UNITS[u])
+    }
+}
+
+#[derive(Default)]
+struct Shared {
+    stages: Mutex<Vec<Arc<StageInner>>>,
+    workers: AtomicU64,
+    busy: AtomicU64,
+    cancelled: AtomicBool,
+}
+
+/// Error a run stops with once its [`Progress`] was cancelled; find it with
+/// `err.downcast_ref::<Cancelled>()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Registry of the stages of one run. Clones share it.
 #[derive(Clone)]
 pub struct Progress {
-    enabled: bool,
-    pub stage: Arc<Mutex<String>>,
-    pub blocks_done: Arc<AtomicUsize>,
-    pub blocks_total: Arc<AtomicUsize>,
-    pub bytes_done: Arc<AtomicUsize>,
-    pub bytes_total: Arc<AtomicUsize>,
-    running: Arc<AtomicBool>,
+    shared: Arc<Shared>,
+    t0: Instant,
+}
+
+impl std::fmt::Debug for Progress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Progress").finish_non_exhaustive()
+    }
+}
+
+impl Default for Progress {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Progress {
-    pub fn new(enabled: bool) -> Self {
-        Self {
-            enabled,
-            stage: Arc::new(Mutex::new(String::new())),
-            blocks_done: Arc::new(AtomicUsize::new(0)),
-            blocks_total: Arc::new(AtomicUsize::new(0)),
-            bytes_done: Arc::new(AtomicUsize::new(0)),
-            bytes_total: Arc::new(AtomicUsize::new(0)),
-            running: Arc::new(AtomicBool::new(false)),
-        }
+    pub fn new() -> Self {
+        Self { shared: Arc::default(), t0: Instant::now() }
     }
-    pub fn set_stage(&self, s: &str) {
-        if self.enabled {
-            *self.stage.lock().unwrap() = s.to_string();
-        }
+
+    /// The stage at `path`, created on first use. Parents need not exist.
+    pub fn stage(&self, path: &str) -> Stage {
+        let mut stages = self.shared.stages.lock().expect("progress stages");
+        let inner = match stages.iter().find(|s| s.path == path) {
+            Some(s) => s.clone(),
+            None => {
+                let s = Arc::new(StageInner {
+                    path: path.to_string(),
+                    started: Instant::now(),
+                    counters: Counters::default(),
+                    current: Mutex::new(None),
+                });
+                stages.push(s.clone());
+                s
+            }
+        };
+        Stage { inner, progress: self.clone() }
     }
-    pub fn set_blocks_total(&self, n: usize) {
-        self.blocks_total.store(n, Ordering::Relaxed);
+
+    /// Size of the worker pool the run's stages share.
+    pub fn set_workers(&self, n: usize) {
+        self.shared.workers.store(n as u64, Ordering::Relaxed);
     }
-    pub fn inc_block(&self) {
-        self.blocks_done.fetch_add(1, Ordering::Relaxed);
+
+    /// Count one worker as busy until the guard is dropped.
+    pub fn busy(&self) -> Busy {
+        self.shared.busy.fetch_add(1, Ordering::Relaxed);
+        Busy(self.shared.clone())
     }
-    pub fn reset_bytes(&self, total: usize) {
-        self.bytes_total.store(total, Ordering::Relaxed);
-        self.bytes_done.store(0, Ordering::Relaxed);
+
+    /// Ask the run to stop. Runs check at points where stopping leaves
+    /// things consistent (between files, before writing, at journal
+    /// segments) and fail with [`Cancelled`] there.
+    pub fn cancel(&self) {
+        self.shared.cancelled.store(true, Ordering::Relaxed);
     }
-    pub fn add_bytes(&self, n: usize) {
-        self.bytes_done.fetch_add(n, Ordering::Relaxed);
+
+    pub fn is_cancelled(&self) -> bool {
+        self.shared.cancelled.load(Ordering::Relaxed)
     }
 
-    pub fn start(&self) {
-        if !self.enabled {
-            return;
+    /// `Err(Cancelled)` once [`Progress::cancel`] was called.
+    pub fn check_cancelled(&self) -> anyhow::Result<()> {
+        match self.is_cancelled() {
+            true => Err(Cancelled.into()),
+            false => Ok(()),
+        }
+    }
+
+    /// Current counters of every stage, children rolled up into parents
+    /// (including parents that were never created as stages themselves).
+    pub fn snapshot(&self) -> Snapshot {
+        let stages: Vec<Arc<StageInner>> =
+            self.shared.stages.lock().expect("progress stages").clone();
+        let mut paths: Vec<&str> = Vec::new();
+        for s in &stages {
+            let mut p = s.path.as_str();
+            loop {
+                if !paths.contains(&p) {
+                    paths.push(p);
+                }
+                match p.rfind('/') {
+                    Some(i) => p = &p[..i],
+                    None => break,
+                }
+            }
         }
-        self.running.store(true, Ordering::Relaxed);
-        let stage = self.stage.clone();
-        let blocks_done = self.blocks_done.clone();
-        let blocks_total = self.blocks_total.clone();
-        let bytes_done = self.bytes_done.clone();
-        let bytes_total = self.bytes_total.clone();
-        let running = self.running.clone();
-        thread::spawn(move || {
-            let t0 = Instant::now();
-            while running.load(Ordering::Relaxed) {
-                thread::sleep(Duration::from_secs(5));
-                if !running.load(Ordering::Relaxed) {
+        // Parents first, siblings in the order they were created
+        let first_seen = |p: &str| {
+            stages.iter().position(|s| s.path == p || s.path.starts_with(&format!("{}/", p)))
+        };
+        let key = |p: &str| -> Vec<usize> {
+            let mut k = Vec::new();
+            let mut end = 0;
+            for part in p.split('/') {
+                end += part.len();
+                k.push(first_seen(&p[..end]).unwrap_or(usize::MAX));
+                end += 1;
+            }
+            k
+        };
+        paths.sort_by_key(|p| key(p));
+        let out = paths
+            .iter()
+            .map(|&p| {
+                let prefix = format!("{}/", p);
+                let own = stages.iter().filter(|s| s.path == p || s.path.starts_with(&prefix));
+                let mut snap = StageSnapshot {
+                    path: p.to_string(),
+                    depth: p.matches('/').count(),
+                    done: true,
+                    ..Default::default()
+                };
+                let mut started = None::<Instant>;
+                let mut finished_at = None::<Instant>;
+                for s in own {
+                    let c = &s.counters;
+                    if snap.current.is_none() {
+                        snap.current = s.current.lock().expect("progress current").clone();
+                    }
+                    snap.bytes_done += c.bytes_done.load(Ordering::Relaxed);
+                    snap.bytes_total += c.bytes_total.load(Ordering::Relaxed);
+                    snap.items_done += c.items_done.load(Ordering::Relaxed);
+                    snap.items_total += c.items_total.load(Ordering::Relaxed);
+                    started = Some(started.map_or(s.started, |t| t.min(s.started)));
+                    if c.finished.load(Ordering::Relaxed) {
+                        let end = s.started
+                            + Duration::from_millis(c.finished_ms.load(Ordering::Relaxed));
+                        finished_at = Some(finished_at.map_or(end, |t| t.max(end)));
+                    } else {
+                        snap.done = false;
+                    }
+                }
+                let started = started.unwrap_or(self.t0);
+                let end = if snap.done { finished_at.unwrap_or(started) } else { Instant::now() };
+                let ms = end.saturating_duration_since(started).as_millis() as u64;
+                snap.elapsed_ms = ms;
+                snap.bytes_per_sec = (snap.bytes_done as u128 * 1000 / ms.max(1) as u128) as u64;
+                if !snap.done && snap.bytes_per_sec > 0 && snap.bytes_total > snap.bytes_done {
+                    let left = (snap.bytes_total - snap.bytes_done) as u128;
+                    snap.eta_ms = Some((left * 1000 / snap.bytes_per_sec as u128) as u64);
+                }
+                snap
+            })
+            .collect();
+        Snapshot {
+            elapsed_ms: self.t0.elapsed().as_millis() as u64,
+            last: false,
+            workers: self.shared.workers.load(Ordering::Relaxed),
+            workers_busy: self.shared.busy.load(Ordering::Relaxed),
+            stages: out,
+        }
+    }
+
+    /// Report to `sink` every `interval` on a thread of its own, until the
+    /// returned guard is dropped.
+    pub fn start(&self, sink: Arc<dyn ProgressSink>, interval: Duration) -> Reporter {
+        let (tx, rx) = mpsc::channel::<()>();
+        let me = self.clone();
+        let thread = thread::spawn(move || loop {
+            match rx.recv_timeout(interval) {
+                Err(RecvTimeoutError::Timeout) => sink.report(&me.snapshot()),
+                _ => {
+                    sink.report(&Snapshot { last: true, ..me.snapshot() });
                     break;
                 }
-                let s = stage.lock().unwrap().clone();
-                let bd = blocks_done.load(Ordering::Relaxed);
-                let bt = blocks_total.load(Ordering::Relaxed);
-                let bpd = bytes_done.load(Ordering::Relaxed);
-                let bpt = bytes_total.load(Ordering::Relaxed);
-                let bpct = if bpt > 0 { (bpd as f64 / bpt as f64) * 100.0 } else { 0.0 };
-                eprintln!(
-                    "[{:>4}s] {} | stripes {}/{} | bytes {}%",
-                    t0.elapsed().as_secs(),
-                    s,
-                    bd,
-                    bt,
-                    bpct as i32
-                );
             }
         });
+        Reporter { stop: Some(tx), thread: Some(thread) }
     }
-    pub fn stop(&self) {
-        if self.enabled {
-            self.running.store(false, Ordering::Relaxed);
+}
+
+/// A running reporter; dropping it sends one last snapshot and waits for
+/// the thread.
+pub struct Reporter {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for Reporter {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(t) = self.thread.take() {
+            let _ = t.join();
         }
     }
 }
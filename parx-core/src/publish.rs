@@ -0,0 +1,390 @@
+use crate::hex::{hex, unhex};
+use crate::manifest::Manifest;
+use crate::path_safety::{validate_path, PathPolicy};
+use crate::sign::{self, SignatureBlock};
+use anyhow::{bail, Context, Result};
+use blake3::hazmat::{
+    left_subtree_len, merge_subtrees_non_root, merge_subtrees_root, ChainingValue, HasherExt, Mode,
+};
+use ed25519_dalek::{SigningKey, VerifyingKey};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+use std::fs::File;
+use std::io::Read;
+use std::ops::Range;
+use std::path::Path;
+
+pub const BUNDLE_FORMAT: &str = "parx-pub/2";
+
+/// Bytes under each leaf of a bao outboard: 16 BLAKE3 chunks, so the tree
+/// costs 64 bytes per 16 KiB of data.
+pub const BAO_GROUP: u64 = 16 * 1024;
+
+/// Whole-file BLAKE3 "witness" used for a fast single-pass download check.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Witness {
+    pub rel_path: String,
+    pub size: u64,
+    pub blake3_hex: String,
+    /// Hex bao outboard of the file (`publish --bao`): its length (u64 LE),
+    /// then the BLAKE3 tree's parent nodes in pre-order down to
+    /// [`BAO_GROUP`] leaves. Locates the damage in a file that fails its
+    /// witness.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bao_outboard: Option<String>,
+}
+
+/// Signed part of a distribution bundle.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BundleBody {
+    pub format: String,
+    /// Hex of the manifest in the v2 encoding `create` writes to
+    /// `manifest.v2`, with the publisher's parity location cleared
+    pub manifest_v2: String,
+    pub witnesses: Vec<Witness>,
+}
+
+impl BundleBody {
+    /// Decode the manifest the bundle carries.
+    pub fn manifest(&self) -> Result<Manifest> {
+        crate::manifest_v2::decode(&unhex(&self.manifest_v2)?).context("decode bundle manifest")
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PublishOptions {
+    /// Add a bao outboard per file, so a consumer learns which byte ranges
+    /// of a damaged file are bad
+    pub bao: bool,
+}
+
+/// Detached distribution bundle (`*.parx-pub`) shipped alongside a released dataset.
+#[derive(Clone, Debug)]
+pub struct PublishBundle {
+    pub body: BundleBody,
+    pub signature: SignatureBlock,
+    /// `body` as signed and stored: signatures are made and checked over
+    /// these bytes, never over a re-serialization of `body`
+    body_json: Box<RawValue>,
+}
+
+/// A bundle as stored, with its body kept as written.
+#[derive(Serialize, Deserialize)]
+struct StoredBundle<'a> {
+    #[serde(borrow)]
+    body: &'a RawValue,
+    signature: SignatureBlock,
+}
+
+/// Byte range of a downloaded file that does not match its bao outboard.
+#[derive(Debug, Clone, Serialize)]
+pub struct DamagedRange {
+    pub rel_path: String,
+    pub offset: u64,
+    pub len: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckDownloadReport {
+    pub signer: String,
+    /// The signer was checked against a trusted key (`--pubkey`); without
+    /// one the bundle only vouches for itself
+    pub authenticated: bool,
+    pub files_ok: u64,
+    pub files_bad: Vec<String>,
+    /// Where the bad files of the right size differ, for files published
+    /// with a bao outboard
+    pub damaged: Vec<DamagedRange>,
+    pub files_missing: Vec<String>,
+    /// Files that could not be checked, with the reason
+    pub files_error: Vec<String>,
+}
+
+impl CheckDownloadReport {
+    pub fn is_ok(&self) -> bool {
+        self.files_bad.is_empty() && self.files_missing.is_empty() && self.files_error.is_empty()
+    }
+}
+
+/// Build and sign a bundle from a manifest and the data it describes.
+pub fn publish(
+    manifest_path: &Path,
+    root: &Path,
+    key: &SigningKey,
+    opts: &PublishOptions,
+) -> Result<PublishBundle> {
+    let (mut mf, recovery) = crate::manifest::load(manifest_path)?;
+    if recovery.is_some() {
+        bail!("refusing to publish a partially recovered manifest; recover it first");
+    }
+    // The publisher's parity location is meaningless to consumers
+    mf.parity_dir = String::new();
+    let witnesses: Result<Vec<Witness>> = mf
+        .files
+        .par_iter()
+        .map(|fe| {
+            let path = validate_path(root, Path::new(&fe.rel_path), PathPolicy::default())
+                .with_context(|| format!("validate path {:?}", fe.rel_path))?;
+            let (size, hash, outboard) = if opts.bao {
+                let (size, hash, ob) = outboard_file(&path)?;
+                (size, hash, Some(hex(&ob)))
+            } else {
+                let (size, hash) = hash_file(&path)?;
+                (size, hash, None)
+            };
+            if size != fe.size {
+                bail!("{:?}: size {} differs from manifest ({})", fe.rel_path, size, fe.size);
+            }
+            Ok(Witness {
+                rel_path: fe.rel_path.clone(),
+                size,
+                blake3_hex: hash,
+                bao_outboard: outboard,
+            })
+        })
+        .collect();
+    let body = BundleBody {
+        format: BUNDLE_FORMAT.to_string(),
+        manifest_v2: hex(&crate::manifest_v2::encode(&mf)?),
+        witnesses: witnesses?,
+    };
+    let body_json = RawValue::from_string(serde_json::to_string(&body)?)?;
+    let signature = sign::sign_bytes(key, body_json.get().as_bytes());
+    Ok(PublishBundle { body, signature, body_json })
+}
+
+pub fn write_bundle(bundle: &PublishBundle, path: &Path) -> Result<()> {
+    let stored = StoredBundle { body: &bundle.body_json, signature: bundle.signature.clone() };
+    let data = zstd::stream::encode_all(&serde_json::to_vec(&stored)?[..], 3)
+        .context("zstd compress bundle")?;
+    std::fs::write(path, data).with_context(|| format!("write {:?}", path))
+}
+
+/// Read a bundle and check its signature over the stored body (optionally
+/// pinned to `expected`). Returns the bundle together with the key that
+/// signed it.
+pub fn read_bundle(
+    path: &Path,
+    expected: Option<&VerifyingKey>,
+) -> Result<(PublishBundle, VerifyingKey)> {
+    let raw = std::fs::read(path).with_context(|| format!("read {:?}", path))?;
+    let json = zstd::stream::decode_all(&raw[..]).context("zstd decompress bundle")?;
+    let stored: StoredBundle = serde_json::from_slice(&json).context("parse bundle")?;
+    let vk = sign::verify_bytes(&stored.signature, stored.body.get().as_bytes(), expected)?;
+    let body: BundleBody = serde_json::from_str(stored.body.get()).context("parse bundle body")?;
+    if body.format != BUNDLE_FORMAT {
+        bail!("unsupported bundle format {:?}", body.format);
+    }
+    body.manifest()?;
+    let bundle =
+        PublishBundle { body, signature: stored.signature, body_json: stored.body.to_owned() };
+    Ok((bundle, vk))
+}
+
+/// Consumer side: check a downloaded tree against a signed bundle.
+pub fn check_download(
+    dir: &Path,
+    bundle_path: &Path,
+    expected: Option<&VerifyingKey>,
+) -> Result<CheckDownloadReport> {
+    let (bundle, vk) = read_bundle(bundle_path, expected)?;
+    let results: Vec<_> = bundle
+        .body
+        .witnesses
+        .par_iter()
+        .map(|w| {
+            let res = validate_path(dir, Path::new(&w.rel_path), PathPolicy::default())
+                .and_then(|p| check_file(&p, w));
+            (w.rel_path.clone(), res)
+        })
+        .collect();
+    let mut rep = CheckDownloadReport {
+        signer: sign::fingerprint(&vk),
+        authenticated: expected.is_some(),
+        files_ok: 0,
+        files_bad: Vec::new(),
+        damaged: Vec::new(),
+        files_missing: Vec::new(),
+        files_error: Vec::new(),
+    };
+    for (rel, res) in results {
+        match res {
+            Ok(None) => rep.files_ok += 1,
+            Ok(Some(ranges)) => {
+                rep.damaged.extend(ranges.into_iter().map(|r| DamagedRange {
+                    rel_path: rel.clone(),
+                    offset: r.start,
+                    len: r.end - r.start,
+                }));
+                rep.files_bad.push(rel);
+            }
+            Err(e) if not_found(&e) => rep.files_missing.push(rel),
+            Err(e) => rep.files_error.push(format!("{}: {:#}", rel, e)),
+        }
+    }
+    Ok(rep)
+}
+
+/// `None` if the file matches its witness, else the ranges its outboard
+/// shows damaged (none without an outboard or at another size).
+fn check_file(path: &Path, w: &Witness) -> Result<Option<Vec<Range<u64>>>> {
+    let (size, hash) = hash_file(path)?;
+    if size == w.size && hash == w.blake3_hex {
+        return Ok(None);
+    }
+    match &w.bao_outboard {
+        Some(ob) if size == w.size => {
+            let root = blake3::Hash::from_hex(&w.blake3_hex).context("witness hash")?;
+            Ok(Some(bao_damage(path, &unhex(ob)?, &root)?))
+        }
+        _ => Ok(Some(Vec::new())),
+    }
+}
+
+fn not_found(e: &anyhow::Error) -> bool {
+    e.root_cause()
+        .downcast_ref::<std::io::Error>()
+        .is_some_and(|e| e.kind() == std::io::ErrorKind::NotFound)
+}
+
+fn hash_file(path: &Path) -> Result<(u64, String)> {
+    let mut f = File::open(path).with_context(|| format!("open {:?}", path))?;
+    let mut hasher = blake3::Hasher::new();
+    let mut buf = vec![0u8; 1 << 20];
+    let mut size = 0u64;
+    loop {
+        let n = f.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        size += n as u64;
+    }
+    Ok((size, hasher.finalize().to_hex().to_string()))
+}
+
+/// Size, root hash and bao outboard of a file, in one pass over it.
+fn outboard_file(path: &Path) -> Result<(u64, String, Vec<u8>)> {
+    let mut f = File::open(path).with_context(|| format!("open {:?}", path))?;
+    let len = f.metadata()?.len();
+    let mut ob = len.to_le_bytes().to_vec();
+    let mut buf = vec![0u8; BAO_GROUP as usize];
+    let root = if len <= BAO_GROUP {
+        f.read_exact(&mut buf[..len as usize])?;
+        blake3::hash(&buf[..len as usize])
+    } else {
+        let (l, r) = outboard_children(&mut f, &mut buf, 0, len, &mut ob)?;
+        merge_subtrees_root(&l, &r, Mode::Hash)
+    };
+    // Grown while read: the tree would not cover it
+    if f.read(&mut buf[..1])? != 0 {
+        bail!("{:?} changed while hashing", path);
+    }
+    Ok((len, root.to_hex().to_string(), ob))
+}
+
+/// Chaining values of the two halves of the `len` bytes at `off`, read in
+/// order from `f`; their parent node and those below go to `ob` in
+/// pre-order.
+fn outboard_children(
+    f: &mut File,
+    buf: &mut [u8],
+    off: u64,
+    len: u64,
+    ob: &mut Vec<u8>,
+) -> Result<(ChainingValue, ChainingValue)> {
+    let at = ob.len();
+    ob.extend_from_slice(&[0u8; 64]);
+    let left = left_subtree_len(len);
+    let l = outboard_subtree(f, buf, off, left, ob)?;
+    let r = outboard_subtree(f, buf, off + left, len - left, ob)?;
+    ob[at..at + 32].copy_from_slice(&l);
+    ob[at + 32..at + 64].copy_from_slice(&r);
+    Ok((l, r))
+}
+
+fn outboard_subtree(
+    f: &mut File,
+    buf: &mut [u8],
+    off: u64,
+    len: u64,
+    ob: &mut Vec<u8>,
+) -> Result<ChainingValue> {
+    if len <= BAO_GROUP {
+        return leaf_cv(f, buf, off, len);
+    }
+    let (l, r) = outboard_children(f, buf, off, len, ob)?;
+    Ok(merge_subtrees_non_root(&l, &r, Mode::Hash))
+}
+
+fn leaf_cv(f: &mut File, buf: &mut [u8], off: u64, len: u64) -> Result<ChainingValue> {
+    let b = &mut buf[..len as usize];
+    f.read_exact(b)?;
+    Ok(blake3::Hasher::new().set_input_offset(off).update(b).finalize_non_root())
+}
+
+/// Leaves of the file at `path` that do not match the outboard `ob` of
+/// root hash `root`, adjacent ones merged. The outboard is checked against
+/// the root on the way down, so a damaged leaf is never blamed on it.
+fn bao_damage(path: &Path, ob: &[u8], root: &blake3::Hash) -> Result<Vec<Range<u64>>> {
+    let mut f = File::open(path).with_context(|| format!("open {:?}", path))?;
+    let len = match ob.get(..8) {
+        Some(h) => u64::from_le_bytes(h.try_into().unwrap()),
+        None => bail!("bao outboard too short"),
+    };
+    let parents = len.div_ceil(BAO_GROUP).saturating_sub(1);
+    if (ob.len() as u64 - 8) != parents * 64 {
+        bail!("bao outboard of {} bytes does not fit a {}-byte file", ob.len(), len);
+    }
+    let mut walk =
+        DamageWalk { f: &mut f, buf: vec![0u8; BAO_GROUP as usize], ob, at: 8, bad: Vec::new() };
+    if len <= BAO_GROUP {
+        walk.bad.push(0..len);
+        return Ok(walk.bad);
+    }
+    let (l, r) = walk.parent()?;
+    if merge_subtrees_root(&l, &r, Mode::Hash) != *root {
+        bail!("bao outboard does not match the witness hash");
+    }
+    let left = left_subtree_len(len);
+    walk.subtree(0, left, &l)?;
+    walk.subtree(left, len - left, &r)?;
+    Ok(walk.bad)
+}
+
+struct DamageWalk<'a> {
+    f: &'a mut File,
+    buf: Vec<u8>,
+    ob: &'a [u8],
+    /// Next parent node in `ob`
+    at: usize,
+    bad: Vec<Range<u64>>,
+}
+
+impl DamageWalk<'_> {
+    fn parent(&mut self) -> Result<(ChainingValue, ChainingValue)> {
+        let node = &self.ob[self.at..self.at + 64];
+        self.at += 64;
+        Ok((node[..32].try_into()?, node[32..].try_into()?))
+    }
+
+    fn subtree(&mut self, off: u64, len: u64, expected: &ChainingValue) -> Result<()> {
+        if len <= BAO_GROUP {
+            if leaf_cv(self.f, &mut self.buf, off, len)? != *expected {
+                match self.bad.last_mut() {
+                    Some(last) if last.end == off => last.end = off + len,
+                    _ => self.bad.push(off..off + len),
+                }
+            }
+            return Ok(());
+        }
+        let (l, r) = self.parent()?;
+        if merge_subtrees_non_root(&l, &r, Mode::Hash) != *expected {
+            bail!("bao outboard is inconsistent at byte {}", off);
+        }
+        let left = left_subtree_len(len);
+        self.subtree(off, left, &l)?;
+        self.subtree(off + left, len - left, &r)
+    }
+}
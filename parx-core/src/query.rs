@@ -0,0 +1,532 @@
+//! Point queries against a manifest and its volumes, and dumps of a single
+//! volume (debugging aids).
+
+use crate::ext::{key, ExtMap, MAX_EXT_VALUE};
+use crate::index::{raw_trailer, read_index, read_trailer, IndexCopy, IndexLimits};
+use crate::manifest::{self, ChunkId, SetId, SetInfo};
+use crate::metaparity::{TAG_INDEX, TAG_MANIFEST};
+use crate::volume::{feature, ShardKind, VolumeHeader, VOLUME_HEADER_FIXED, VOLUME_MAGIC};
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// One data chunk of a stripe.
+#[derive(Debug, Clone, Serialize)]
+pub struct StripeMember {
+    pub chunk: u64,
+    pub rel_path: String,
+    pub file_offset: u64,
+    pub len: u32,
+}
+
+/// Where a parity shard lives and whether it still matches its recorded hash.
+#[derive(Debug, Clone, Serialize)]
+pub struct ShardLocation {
+    pub volume: String,
+    pub parity_idx: u16,
+    pub offset: u64,
+    pub len: u32,
+    /// "ok", "bad" or "unhashed" (volume written without shard hashes)
+    pub status: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StripeLocation {
+    pub rel_path: String,
+    pub offset: u64,
+    /// Slot of the chunk in the stripe layout
+    pub chunk: u64,
+    /// Layout-independent id of the chunk (absent for manifests without file ids)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chunk_id: Option<ChunkId>,
+    /// Byte range of the chunk within the file
+    pub chunk_file_offset: u64,
+    pub chunk_len: u32,
+    pub stripe: u64,
+    /// Position of the chunk inside its stripe (0..k)
+    pub stripe_pos: usize,
+    pub stripe_k: usize,
+    /// Parity shards the stripe needs (m)
+    pub parity_needed: usize,
+    pub members: Vec<StripeMember>,
+    pub parity: Vec<ShardLocation>,
+    /// Outer group of the stripe when the set was created with `--outer-group`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub outer_group: Option<u64>,
+    pub outer: Vec<ShardLocation>,
+    /// Volumes whose index could not be read (their shards are unknown)
+    pub unreadable_volumes: Vec<String>,
+}
+
+/// Map a byte offset of a protected file to its chunk, stripe and shards.
+pub fn which_stripe(manifest_path: &Path, rel_path: &str, offset: u64) -> Result<StripeLocation> {
+    let (mf, _) = manifest::load(manifest_path)?;
+    let want = normalize(rel_path);
+    let fe = mf
+        .files
+        .iter()
+        .find(|f| normalize(&f.rel_path) == want)
+        .with_context(|| format!("{:?} is not in the manifest", rel_path))?;
+    if offset >= fe.size {
+        bail!("offset {} is past the end of {:?} ({} bytes)", offset, fe.rel_path, fe.size);
+    }
+    let (ordinal, ch) = fe
+        .chunks
+        .iter()
+        .enumerate()
+        .find(|(_, c)| offset >= c.file_offset && offset < c.file_offset + c.len as u64)
+        .with_context(|| format!("no chunk covers offset {} of {:?}", offset, fe.rel_path))?;
+
+    let geo = mf.geometry();
+    let stripe = geo.stripe_of(ch.idx);
+    let slots = geo.slots(stripe);
+    let mut members: Vec<StripeMember> = mf
+        .files
+        .iter()
+        .flat_map(|f| f.chunks.iter().map(move |c| (f, c)))
+        .filter(|(_, c)| slots.contains(&c.idx))
+        .map(|(f, c)| StripeMember {
+            chunk: c.idx,
+            rel_path: f.rel_path.clone(),
+            file_offset: c.file_offset,
+            len: c.len,
+        })
+        .collect();
+    members.sort_by_key(|m| m.chunk);
+
+    let outer_group = (mf.outer_group > 0).then(|| stripe / mf.outer_group as u64);
+    let mut loc = StripeLocation {
+        rel_path: fe.rel_path.clone(),
+        offset,
+        chunk: ch.idx,
+        chunk_id: (fe.id != 0).then_some(ChunkId { file: fe.id, ordinal: ordinal as u32 }),
+        chunk_file_offset: ch.file_offset,
+        chunk_len: ch.len,
+        stripe,
+        stripe_pos: geo.slot_of(ch.idx),
+        stripe_k: mf.stripe_k,
+        parity_needed: mf.parity_shards_of(stripe),
+        members,
+        parity: Vec::new(),
+        outer_group,
+        outer: Vec::new(),
+        unreadable_volumes: Vec::new(),
+    };
+
+    let mut vols: Vec<PathBuf> = Vec::new();
+    for dir in std::iter::once(PathBuf::from(&mf.parity_dir)).chain(mf.volume_dirs()) {
+        let Ok(rd) = std::fs::read_dir(&dir) else { continue };
+        let mut here: Vec<PathBuf> = rd
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.extension().map(|s| s == "parxv").unwrap_or(false))
+            .collect();
+        here.sort();
+        vols.extend(here);
+    }
+    for p in vols {
+        let name = p.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let read = File::open(&p).map_err(anyhow::Error::from).and_then(|mut f| {
+            let (off, len, crc) = read_trailer(&mut f)?;
+            Ok((read_index(&mut f, off, len, crc, &IndexLimits::default())?, f))
+        });
+        let Ok((entries, mut f)) = read else {
+            loc.unreadable_volumes.push(name);
+            continue;
+        };
+        for e in entries {
+            let wanted = match e.kind {
+                ShardKind::Inner => e.stripe == stripe,
+                ShardKind::Outer => Some(e.stripe) == loc.outer_group,
+                _ => false,
+            };
+            if !wanted {
+                continue;
+            }
+            let status = match e.hash {
+                None => "unhashed",
+                Some(h) => {
+                    let mut buf = vec![0u8; e.len as usize];
+                    let ok = f.seek(SeekFrom::Start(e.offset)).is_ok()
+                        && f.read_exact(&mut buf).is_ok()
+                        && *blake3::hash(&buf).as_bytes() == h;
+                    if ok {
+                        "ok"
+                    } else {
+                        "bad"
+                    }
+                }
+            };
+            let sl = ShardLocation {
+                volume: name.clone(),
+                parity_idx: e.parity_idx,
+                offset: e.offset,
+                len: e.len,
+                status: status.to_string(),
+            };
+            match e.kind {
+                ShardKind::Inner => loc.parity.push(sl),
+                _ => loc.outer.push(sl),
+            }
+        }
+    }
+    loc.parity.sort_by_key(|s| s.parity_idx);
+    Ok(loc)
+}
+
+pub(crate) fn normalize(p: &str) -> String {
+    let p = p.replace('\\', "/");
+    let mut s = p.as_str();
+    while let Some(rest) = s.strip_prefix("./") {
+        s = rest;
+    }
+    s.to_string()
+}
+
+/// What `parx info` shows: the set's description plus its shape. Fields the
+/// source does not carry (a lone volume has no file list) are absent.
+#[derive(Debug, Clone, Serialize)]
+pub struct InfoReport {
+    /// "manifest" or "volume"
+    pub source: String,
+    #[serde(flatten)]
+    pub info: SetInfo,
+    /// Random id of the set (see `manifest::SetId`), as a UUID
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub set_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_utc: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub files: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_bytes: Option<u64>,
+    /// The set holds no data (see `Manifest::is_empty`)
+    pub empty: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chunk_size: Option<u64>,
+    pub stripe_k: u64,
+    pub parity_shards: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub volumes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub volume_id: Option<u32>,
+    /// Volume feature flags (see `volume::feature`)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub features: Vec<String>,
+    /// Embedded recovery executables, checked against the manifest hashes
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub recovery_stubs: Vec<crate::stub::StubStatus>,
+}
+
+/// Describe a set from a parity dir, a manifest or a single volume file.
+/// A dir without a manifest falls back to its first volume.
+pub fn info(path: &Path) -> Result<InfoReport> {
+    let target = if path.is_dir() {
+        let mpath = path.join(manifest::MANIFEST_JSON);
+        if mpath.exists() {
+            mpath
+        } else {
+            let mut vols: Vec<PathBuf> = std::fs::read_dir(path)
+                .with_context(|| format!("read_dir {:?}", path))?
+                .filter_map(|e| e.ok().map(|e| e.path()))
+                .filter(|p| p.extension().map(|s| s == "parxv").unwrap_or(false))
+                .collect();
+            vols.sort();
+            vols.into_iter()
+                .next()
+                .with_context(|| format!("{:?} holds neither a manifest nor volumes", path))?
+        }
+    } else {
+        path.to_path_buf()
+    };
+    let mut f = File::open(&target).with_context(|| format!("open {:?}", target))?;
+    let mut magic = [0u8; 8];
+    let is_volume = f.read_exact(&mut magic).is_ok() && &magic == VOLUME_MAGIC;
+    if is_volume {
+        let hdr = VolumeHeader::read_from(&mut f)?;
+        return Ok(InfoReport {
+            source: "volume".to_string(),
+            info: SetInfo::from_ext(&hdr.ext),
+            set_id: SetId::from_ext(&hdr.ext).map(|id| id.to_string()),
+            created_utc: None,
+            files: None,
+            total_bytes: None,
+            empty: false,
+            chunk_size: hdr.ext.get_u32(key::CHUNK_SIZE).map(u64::from),
+            stripe_k: hdr.k as u64,
+            parity_shards: hdr.m as u64,
+            volumes: None,
+            volume_id: hdr.ext.get_u32(key::VOLUME_ID),
+            features: crate::volume::feature::describe(hdr.flags),
+            recovery_stubs: Vec::new(),
+        });
+    }
+    let (mf, _) = manifest::load(&target)?;
+    let dir = target.parent().unwrap_or(Path::new("."));
+    Ok(InfoReport {
+        source: "manifest".to_string(),
+        info: mf.info.clone(),
+        set_id: mf.set_id().map(|id| id.to_string()),
+        created_utc: Some(mf.created_utc.clone()),
+        files: Some(mf.files.len() as u64),
+        total_bytes: Some(mf.total_bytes),
+        empty: mf.is_empty(),
+        chunk_size: Some(mf.chunk_size as u64),
+        stripe_k: mf.stripe_k as u64,
+        parity_shards: mf.parity_shards() as u64,
+        volumes: Some(mf.volumes as u64),
+        volume_id: None,
+        features: Vec::new(),
+        recovery_stubs: crate::stub::check(dir, &mf.recovery_stubs),
+    })
+}
+
+/// One extension entry of a volume header.
+#[derive(Debug, Clone, Serialize)]
+pub struct TlvDump {
+    pub key: u16,
+    /// Well-known name of the key (see `ext::key`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<&'static str>,
+    pub len: usize,
+    /// Decoded for known keys, else the first 64 bytes in hex
+    pub value: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TrailerDump {
+    /// 1 (32-bit index length) or 2 (64-bit)
+    pub version: u8,
+    pub index_offset: u64,
+    pub index_len: u64,
+    pub crc32: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EntryDump {
+    pub kind: String,
+    pub stripe: u64,
+    pub parity_idx: u16,
+    pub offset: u64,
+    pub len: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IndexDump {
+    /// Codec the stored payload names, when it names one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub codec: Option<String>,
+    /// Schema of the decompressed payload: "v3" and up by magic, "v1/v2"
+    /// for bare bincode
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schema: Option<String>,
+    /// The stored payload matches the CRC of the trailer (or head copy
+    /// record); when not, the entries below come from its repair
+    pub crc_ok: bool,
+    /// Sections of the metadata parity block after the index
+    pub metaparity: Vec<String>,
+    pub entries: usize,
+    pub by_kind: std::collections::BTreeMap<String, u64>,
+    /// The first entries in index order
+    pub sample: Vec<EntryDump>,
+}
+
+/// What `parx inspect` shows: the parsed parts of one volume file. Parts
+/// that cannot be read carry the error instead, so a damaged volume still
+/// dumps what is left of it.
+#[derive(Debug, Clone, Serialize)]
+pub struct VolumeDump {
+    pub file_len: u64,
+    pub k: u32,
+    pub m: u32,
+    /// Entry count recorded in the header
+    pub entries: u32,
+    pub flags: u32,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub features: Vec<String>,
+    /// Where shard data may start
+    pub header_len: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub volume_id: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chunk_size: Option<u32>,
+    pub tlvs: Vec<TlvDump>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tlv_error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub index_copy: Option<IndexCopy>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trailer: Option<TrailerDump>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trailer_error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub index: Option<IndexDump>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub index_error: Option<String>,
+}
+
+/// Dump the header, extension entries, trailer and index of the volume at
+/// `path`, with up to `sample` index entries. Feature flags are shown, not
+/// enforced.
+pub fn inspect(path: &Path, sample: usize) -> Result<VolumeDump> {
+    let mut f = File::open(path).with_context(|| format!("open {:?}", path))?;
+    let file_len = f.metadata()?.len();
+    let mut fixed = [0u8; VOLUME_HEADER_FIXED];
+    f.read_exact(&mut fixed).context("read volume header")?;
+    if &fixed[..8] != VOLUME_MAGIC {
+        bail!("{:?} is not a ParXive volume (bad magic)", path);
+    }
+    let u32_at = |o: usize| u32::from_le_bytes(fixed[o..o + 4].try_into().unwrap());
+    let ext_len = u32_at(24) as u64;
+    let ext =
+        if ext_len > 16 * MAX_EXT_VALUE as u64 || VOLUME_HEADER_FIXED as u64 + ext_len > file_len {
+            Err(anyhow::anyhow!("extension area of {} bytes runs past the file", ext_len))
+        } else {
+            let mut buf = vec![0u8; ext_len as usize];
+            f.read_exact(&mut buf).map_err(anyhow::Error::from).and_then(|_| ExtMap::decode(&buf))
+        };
+    let mut dump = VolumeDump {
+        file_len,
+        k: u32_at(8),
+        m: u32_at(12),
+        entries: u32_at(16),
+        flags: u32_at(20),
+        features: feature::describe(u32_at(20)),
+        header_len: VOLUME_HEADER_FIXED as u64 + ext_len,
+        volume_id: None,
+        chunk_size: None,
+        tlvs: Vec::new(),
+        tlv_error: None,
+        index_copy: None,
+        trailer: None,
+        trailer_error: None,
+        index: None,
+        index_error: None,
+    };
+    let ext = match ext {
+        Ok(ext) => ext,
+        Err(e) => {
+            dump.tlv_error = Some(format!("{:#}", e));
+            ExtMap::new()
+        }
+    };
+    dump.volume_id = ext.get_u32(key::VOLUME_ID);
+    dump.chunk_size = ext.get_u32(key::CHUNK_SIZE);
+    dump.index_copy = IndexCopy::from_ext(&ext);
+    dump.tlvs = ext
+        .iter()
+        .map(|(k, v)| TlvDump { key: k, name: key::name(k), len: v.len(), value: tlv_value(k, v) })
+        .collect();
+
+    // Where the index is: the trailer, else the head copy record
+    let loc = match raw_trailer(&mut f) {
+        Ok((version, off, len, crc)) => {
+            let crc32 = format!("{:08x}", crc);
+            dump.trailer = Some(TrailerDump { version, index_offset: off, index_len: len, crc32 });
+            Some((off, len, crc))
+        }
+        Err(e) => {
+            dump.trailer_error = Some(format!("{:#}", e));
+            dump.index_copy.filter(|c| c.len > 0).map(|c| (c.idx_off, c.len, c.crc))
+        }
+    };
+    let Some((off, len, crc)) = loc else {
+        dump.index_error = Some("no trailer and no head copy to locate the index".into());
+        return Ok(dump);
+    };
+    let limits = IndexLimits::default();
+    let mut payload = vec![0u8; usize::try_from(len).unwrap_or(0).min(file_len as usize)];
+    let stored = f.seek(SeekFrom::Start(off)).and_then(|_| f.read_exact(&mut payload)).is_ok();
+    let crc_ok = stored && crc32fast::hash(&payload) == crc;
+    let schema = crc_ok
+        .then(|| crate::codec::decompress(&payload, limits.max_uncompressed_bytes).ok())
+        .flatten()
+        .map(|raw| match crate::volume::entries_schema(&raw) {
+            Some(v) => format!("v{}", v),
+            None => "v1/v2".into(),
+        });
+    let metaparity = crate::metaparity::read_volume_block(&mut f, off, len)
+        .map(|b| {
+            b.keys()
+                .map(|&t| match t {
+                    TAG_INDEX => "index".to_string(),
+                    TAG_MANIFEST => "manifest".to_string(),
+                    t => format!("tag {}", t),
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    match read_index(&mut f, off, len, crc, &limits) {
+        Ok(entries) => {
+            let mut by_kind = std::collections::BTreeMap::new();
+            for e in &entries {
+                *by_kind.entry(e.kind.to_string()).or_insert(0) += 1;
+            }
+            let sample = entries
+                .iter()
+                .take(sample)
+                .map(|e| EntryDump {
+                    kind: e.kind.to_string(),
+                    stripe: e.stripe,
+                    parity_idx: e.parity_idx,
+                    offset: e.offset,
+                    len: e.len,
+                    hash: e.hash.map(|h| crate::hex::hex(&h)),
+                })
+                .collect();
+            dump.index = Some(IndexDump {
+                codec: crate::codec::payload_codec(&payload),
+                schema,
+                crc_ok,
+                metaparity,
+                entries: entries.len(),
+                by_kind,
+                sample,
+            });
+        }
+        Err(e) => dump.index_error = Some(format!("{:#}", e)),
+    }
+    Ok(dump)
+}
+
+/// Render an extension value by what its key holds.
+fn tlv_value(k: u16, v: &[u8]) -> String {
+    let text = || std::str::from_utf8(v).ok().map(|s| format!("{:?}", s));
+    let num = || <[u8; 4]>::try_from(v).ok().map(|b| u32::from_le_bytes(b).to_string());
+    let decoded = match k {
+        key::LABEL
+        | key::NOTES
+        | key::CONTACT
+        | key::INDEX_CODEC
+        | key::BACKUP_CODEC
+        | key::SUBSET
+        | key::SUB_MANIFESTS
+        | key::VOLUME_NAME
+        | key::VOLUME_DIRS
+        | key::VOLUME_GROUPS => text(),
+        key::SET_ID => <[u8; 16]>::try_from(v).ok().map(|b| SetId(b).to_string()),
+        key::DEDUP | key::VOLUME_MAX_SIZE | key::GENERATION => {
+            <[u8; 8]>::try_from(v).ok().map(|b| u64::from_le_bytes(b).to_string())
+        }
+        key::INDEX_COPY => IndexCopy::from_ext(&{
+            let mut m = ExtMap::new();
+            m.insert(k, v.to_vec());
+            m
+        })
+        .map(|c| {
+            format!(
+                "off={} cap={} len={} crc={:08x} idx_off={}",
+                c.off, c.cap, c.len, c.crc, c.idx_off
+            )
+        }),
+        k if k < key::PRIVATE_BASE && key::name(k).is_some() && k != key::MANIFEST_SIG => num(),
+        _ => None,
+    };
+    decoded.unwrap_or_else(|| {
+        let more = if v.len() > 64 { ".." } else { "" };
+        format!("{}{}", crate::hex::hex(&v[..v.len().min(64)]), more)
+    })
+}
@@ -0,0 +1,149 @@
+//! Re-striping of a set worn by updates (`parx rebalance`).
+//!
+//! `update` and `forget` leave the slots of removed files, and of files that
+//! moved or shrank, as zero chunks, so after many runs a set carries stripes
+//! with few live chunks, and trailing stripes that are mostly holes, all with
+//! their full parity. [`rebalance`] moves the last chunks of the set into
+//! those holes, the holes of the emptiest stripes first, and re-encodes the
+//! stripes on both ends of every move from the chunks read back under the
+//! tree. Stripes left without live chunks at the end are dropped from the
+//! volumes. The data files are not touched; only which slot a chunk has
+//! changes. Work goes in batches, and stops between two of them once the
+//! [`RebalanceOptions`] budget is spent, leaving a consistent set that a later
+//! run carries on from.
+
+use crate::manifest::{self, MANIFEST_JSON};
+use crate::update::{check_supported, finish, lock, reencode, write_parity};
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Moves re-encoded together; `max_time` is checked between batches.
+const BATCH: usize = 256;
+
+#[derive(Clone, Debug, Default)]
+pub struct RebalanceOptions {
+    /// Stop before a batch would read more than this many bytes of chunk
+    /// data, counting every stripe it re-encodes as full
+    pub max_io: Option<u64>,
+    /// Start no further batch after this long
+    pub max_time: Option<Duration>,
+    /// Re-sign the manifest; required for signed sets, as for `update`
+    pub sign_key: Option<ed25519_dalek::SigningKey>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RebalanceReport {
+    /// Chunk slots below the end of the set that no file uses
+    pub holes_before: u64,
+    pub holes_after: u64,
+    pub stripes_before: u64,
+    pub stripes_after: u64,
+    /// Chunks given a lower slot
+    pub chunks_moved: u64,
+    /// Bytes of chunk data read to re-encode the affected stripes
+    pub bytes_read: u64,
+    /// Kept stripes whose parity was recomputed
+    pub stripes_rewritten: u64,
+    /// Whether the budget ran out while holes could still be filled
+    pub budget_exhausted: bool,
+}
+
+impl crate::report::Report for RebalanceReport {
+    const KIND: &'static str = "rebalance";
+}
+
+/// Compact the set in `output` as the module docs describe. `root` is the
+/// tree the manifest's rel paths are relative to (as for `repair`); the
+/// chunks of affected stripes are read from it and must be intact.
+pub fn rebalance(output: &Path, root: &Path, opts: &RebalanceOptions) -> Result<RebalanceReport> {
+    let (mut mf, recovery) = manifest::load(&output.join(MANIFEST_JSON))?;
+    if recovery.is_some() {
+        bail!("manifest.json is damaged; repair the manifest before rebalancing");
+    }
+    check_supported(&mf, "rebalance", opts.sign_key.as_ref())?;
+    let _lock = lock(output)?;
+    mf.next_generation();
+    let geo = mf.geometry();
+    let (k, cs) = (geo.k as u64, mf.chunk_size as u64);
+    // Slot -> (file, chunk) of every chunk in use
+    let mut owner: BTreeMap<u64, (usize, usize)> = BTreeMap::new();
+    for (fi, fe) in mf.files.iter().enumerate() {
+        for (ci, ch) in fe.chunks.iter().enumerate() {
+            owner.insert(ch.idx, (fi, ci));
+        }
+    }
+    let mut rep = RebalanceReport {
+        holes_before: geo.total_chunks - owner.len() as u64,
+        stripes_before: geo.stripes,
+        ..Default::default()
+    };
+    let mut live = vec![0usize; geo.stripes as usize];
+    for &idx in owner.keys() {
+        live[geo.stripe_of(idx) as usize] += 1;
+    }
+    let mut holes: Vec<u64> = (0..geo.total_chunks).filter(|i| !owner.contains_key(i)).collect();
+    holes.sort_by_key(|&i| (live[geo.stripe_of(i) as usize], i));
+    let mut holes = holes.into_iter();
+
+    let started = Instant::now();
+    let same = |rel: &str| rel.to_string();
+    loop {
+        let mut touched: Vec<u64> = Vec::new();
+        let mut stripes: BTreeSet<u64> = BTreeSet::new();
+        while touched.len() < 2 * BATCH {
+            let Some((&src, &(fi, ci))) = owner.last_key_value() else { break };
+            // Holes past the last chunk stay past it, whatever moves later
+            let Some(hole) = holes.by_ref().find(|&h| h < src) else { break };
+            let new = [hole, src].iter().filter(|&&i| !stripes.contains(&geo.stripe_of(i))).count();
+            let read = (stripes.len() + new) as u64 * k * cs;
+            if opts.max_io.is_some_and(|max| rep.bytes_read + read > max) {
+                rep.budget_exhausted = true;
+                break;
+            }
+            stripes.extend([geo.stripe_of(hole), geo.stripe_of(src)]);
+            owner.remove(&src);
+            owner.insert(hole, (fi, ci));
+            mf.files[fi].chunks[ci].idx = hole;
+            touched.extend([hole, src]);
+            rep.chunks_moved += 1;
+        }
+        if touched.is_empty() {
+            break;
+        }
+        // Stripes emptied at the end are dropped below, not re-encoded
+        let live_end = owner.last_key_value().map_or(0, |(&i, _)| i + 1);
+        let kept = live_end.div_ceil(k);
+        touched.retain(|&i| geo.stripe_of(i) < kept);
+        for s in stripes.range(..kept) {
+            rep.bytes_read += owner
+                .range(geo.slots(*s))
+                .map(|(_, &(fi, ci))| mf.files[fi].chunks[ci].len as u64)
+                .sum::<u64>();
+        }
+        let (rewritten, _) = reencode(output, root, &mf, &geo, &same, &BTreeMap::new(), &touched)?;
+        rep.stripes_rewritten += rewritten;
+        if rep.budget_exhausted {
+            break;
+        }
+        if opts.max_time.is_some_and(|max| started.elapsed() >= max) {
+            let last = owner.last_key_value().map_or(0, |(&i, _)| i);
+            rep.budget_exhausted = holes.clone().any(|h| h < last);
+            break;
+        }
+    }
+
+    let new_total = owner.last_key_value().map_or(0, |(&i, _)| i + 1);
+    rep.stripes_after = new_total.div_ceil(k);
+    rep.holes_after = new_total - owner.len() as u64;
+    if rep.chunks_moved == 0 && new_total == geo.total_chunks {
+        return Ok(rep);
+    }
+    if rep.stripes_after < geo.stripes {
+        write_parity(output, &mf, &[], geo.stripes, rep.stripes_after)?;
+    }
+    finish(output, mf, new_total, opts.sign_key.as_ref())?;
+    Ok(rep)
+}
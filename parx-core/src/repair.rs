@@ -1,46 +1,414 @@
-use crate::index::{read_index, read_trailer, IndexLimits};
-use crate::manifest::Manifest;
+use crate::audit_log::{self, AuditEvent};
+use crate::geometry::Geometry;
+use crate::hashcache::{self, HashCache};
+use crate::index::{read_index_from, read_set_tag, IndexLimits};
+use crate::inuse::{self, IfInUse};
+use crate::manifest::{self, ChunkRef, Manifest, SetTag, Stray};
+use crate::manifest_v2::RecoveryReport;
+use crate::merkle::ChunkHasher;
+use crate::meta::{self, ChownMap, FileMeta, MetaReport};
+use crate::moved::{self, MovedFile};
+use crate::outer::OuterLayout;
 use crate::path_safety::{validate_path, PathPolicy};
-use crate::rs_codec::RsCodec;
+use crate::resync;
+use crate::retry::{is_transient, RetryPolicy, RetryStats};
+use crate::rs_codec::{RsCodec, RsField};
+use crate::storage::{CostClass, DataSource, HttpSource, LocalSource, ReadCost};
+use crate::throttle::Throttle;
+use crate::volume::{vol_name, FeatureError, ShardKind, VolumeEntry};
+use crate::watchdog;
 use anyhow::{bail, Context, Result};
+use ed25519_dalek::{SigningKey, VerifyingKey};
 use fs2::FileExt;
 use rayon::prelude::*;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fs::File;
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::io::{self, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-#[derive(Debug, Clone, serde::Serialize)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct RepairReport {
     pub repaired_chunks: u64,
     pub failed_chunks: u64,
+    /// Parity shards found more than once across the searched dirs.
+    pub duplicate_shards: u64,
+    /// Parity shard copies rejected (unreadable or failed their hash).
+    pub bad_parity_copies: u64,
+    /// Volumes whose index could not be read.
+    pub unreadable_volumes: u64,
+    /// Volumes whose header names another set than the manifest's; their
+    /// shards are not used
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub foreign_volumes: Vec<String>,
+    /// Volumes of the set but of another generation than the manifest's
+    /// (left over from an earlier create or update); not used either
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stale_volumes: Vec<String>,
+    /// Parity shards fetched from sources not classed as local.
+    pub remote_shard_reads: u64,
+    /// Chunks recovered through outer parity after inner parity fell short.
+    pub outer_repaired_chunks: u64,
+    /// Chunks whose hash was checked (all of them unless `only_chunks` was set).
+    pub chunks_checked: u64,
+    /// Chunks of files taken as intact from the hash cache, not read (see
+    /// `RepairOptions::reuse_hashes`)
+    #[serde(default)]
+    pub chunks_reused: u64,
+    /// Chunks found intact at another offset of their file and moved back
+    /// (see [`crate::resync`]); included in `repaired_chunks`
+    #[serde(default)]
+    pub chunks_resynced: u64,
+    /// Missing files found intact under another name whose new name was
+    /// recorded in the manifest (see `RepairOptions::fix_paths`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub moved_files: Vec<MovedFile>,
+    /// Volumes and volume parity files rebuilt from volume parity before
+    /// the repair (see [`crate::volparity`])
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub volumes_restored: Vec<String>,
+    /// Lost or damaged volumes beyond what the volume parity covers
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub volumes_unrecoverable: Vec<String>,
+    /// Files skipped because their reads stalled (likely failing hardware);
+    /// their chunks are neither rewritten nor counted as repaired or failed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stalled_files: Vec<String>,
+    /// Files whose reads kept failing with transient errors through every
+    /// retry (see [`crate::retry`]); handled like stalled files, so repair
+    /// again once their mount is back
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unreachable_files: Vec<String>,
+    /// Files left unpatched because another process had them locked or
+    /// open for writing (see [`crate::inuse`]); handled like stalled files
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub in_use_files: Vec<InUseFile>,
+    /// Reads and writes tried again after a transient error, and how many
+    /// of them then succeeded
+    #[serde(default)]
+    pub io_retries: u64,
+    #[serde(default)]
+    pub io_recovered: u64,
+    /// Outcome of re-applying recorded permissions/ownership to rewritten files.
+    #[serde(default, skip_serializing_if = "MetaReport::is_empty")]
+    pub metadata: MetaReport,
+    /// Missing zero-length files that were recreated
+    #[serde(default)]
+    pub empty_files_restored: u64,
+    /// Recorded symlinks that were missing or pointed elsewhere and were recreated
+    #[serde(default)]
+    pub symlinks_restored: u64,
+    /// Symlinks that could not be recreated, as `path: reason`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub symlinks_failed: Vec<String>,
+    /// Time spent paused by `--auto-throttle`, in milliseconds
+    #[serde(default)]
+    pub throttled_ms: u64,
+    /// Chunk files written by `RepairOptions::extract_chunks`
+    #[serde(default)]
+    pub extracted_chunks: u64,
+    /// Present when manifest.json was unreadable and the v2 companion was used.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub manifest_recovery: Option<RecoveryReport>,
 }
 
-type ParityMap = HashMap<u32, Vec<(usize, Vec<u8>)>>;
-
-fn collect_parity_shards(parity_dir: &Path, chunk_size: usize) -> Result<ParityMap> {
-    let mut map: ParityMap = HashMap::new();
-    if !parity_dir.exists() {
-        return Ok(map);
-    }
-    for ent in std::fs::read_dir(parity_dir)? {
-        let p = ent?.path();
-        if p.extension().map(|s| s == "parxv").unwrap_or(false) {
-            let mut f = File::open(&p)?;
-            let (off, len, crc) = read_trailer(&mut f)?;
-            let entries = read_index(&mut f, off, len, crc, &IndexLimits::default())?;
-            for e in entries {
-                let mut buf = vec![0u8; e.len as usize];
-                f.seek(SeekFrom::Start(e.offset))?;
-                f.read_exact(&mut buf)?;
-                if buf.len() < chunk_size {
-                    buf.resize(chunk_size, 0);
+/// A damaged file repair did not patch because it was in use.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct InUseFile {
+    pub path: String,
+    /// Who uses it, e.g. "open for writing by pid 4242 (postgres)"
+    pub by: String,
+}
+
+impl crate::report::Report for RepairReport {
+    const KIND: &'static str = "repair";
+}
+
+pub(crate) type ParityMap = HashMap<u64, Vec<(usize, Vec<u8>)>>;
+
+/// One distinct copy of a parity shard.
+struct ShardCopy {
+    data: Vec<u8>,
+    /// How many times this exact content was seen
+    votes: u32,
+    /// Volume the copy was first read from (for the audit trail)
+    origin: String,
+}
+
+/// Every readable copy of every parity shard found across one or more parity
+/// dirs, ranked per shard: copies that agree with more other copies come first.
+#[derive(Default)]
+pub(crate) struct ParityCopies {
+    shards: HashMap<u64, BTreeMap<usize, Vec<ShardCopy>>>,
+    /// Identical copies of a shard seen more than once (mirrored volumes, split media).
+    pub duplicates: u64,
+    /// Copies skipped because they were unreadable or failed their index hash.
+    pub bad_copies: u64,
+    /// Volumes whose trailer/index could not be read at all.
+    pub unreadable_volumes: u64,
+    /// Volumes of another set, as `<volume> on <source> (set <id>)`.
+    pub foreign_volumes: Vec<String>,
+    /// Volumes of another generation of the set, as `<volume> on <source>
+    /// (generation <n>, manifest is generation <m>)`.
+    pub stale_volumes: Vec<String>,
+    /// Shards that had to be fetched from a source not classed as local.
+    pub costly_reads: u64,
+}
+
+impl ParityCopies {
+    fn add(&mut self, stripe: u64, parity_idx: usize, data: Vec<u8>, origin: &str) {
+        let copies = self.shards.entry(stripe).or_default().entry(parity_idx).or_default();
+        if let Some(c) = copies.iter_mut().find(|c| c.data == data) {
+            c.votes += 1;
+            self.duplicates += 1;
+        } else {
+            copies.push(ShardCopy { data, votes: 1, origin: origin.to_string() });
+        }
+    }
+
+    fn rank(&mut self) {
+        for per_idx in self.shards.values_mut() {
+            for copies in per_idx.values_mut() {
+                copies.sort_by_key(|c| std::cmp::Reverse(c.votes));
+            }
+        }
+    }
+
+    /// Parity shards of `stripe` for attempt `round`: round 0 takes the best
+    /// copy of each shard, later rounds swap in alternates where they exist.
+    /// `None` once no shard has an alternate left to try.
+    pub(crate) fn variant(&self, stripe: u64, round: usize) -> Option<Vec<(usize, Vec<u8>, &str)>> {
+        let per_idx = match self.shards.get(&stripe) {
+            Some(p) => p,
+            None => return (round == 0).then(Vec::new),
+        };
+        if round > 0 && per_idx.values().all(|c| c.len() <= round) {
+            return None;
+        }
+        Some(
+            per_idx
+                .iter()
+                .map(|(pi, copies)| {
+                    let c = &copies[round.min(copies.len() - 1)];
+                    (*pi, c.data.clone(), c.origin.as_str())
+                })
+                .collect(),
+        )
+    }
+
+    /// Distinct parity shards of `stripe` with at least one copy.
+    fn shards_of(&self, stripe: u64) -> usize {
+        self.shards.get(&stripe).map_or(0, |p| p.len())
+    }
+
+    /// Take in the copies and counts of a search of further sources.
+    fn merge(&mut self, other: ParityCopies) {
+        for (stripe, per_idx) in other.shards {
+            for (pi, copies) in per_idx {
+                let mine = self.shards.entry(stripe).or_default().entry(pi).or_default();
+                for c in copies {
+                    match mine.iter_mut().find(|m| m.data == c.data) {
+                        Some(m) => {
+                            m.votes += c.votes;
+                            self.duplicates += 1;
+                        }
+                        None => mine.push(c),
+                    }
                 }
-                map.entry(e.stripe).or_default().push((e.parity_idx as usize, buf));
             }
         }
+        self.duplicates += other.duplicates;
+        self.bad_copies += other.bad_copies;
+        self.unreadable_volumes += other.unreadable_volumes;
+        self.foreign_volumes.extend(other.foreign_volumes);
+        self.stale_volumes.extend(other.stale_volumes);
+        self.costly_reads += other.costly_reads;
+        self.rank();
+    }
+
+    pub(crate) fn into_best(self) -> ParityMap {
+        self.shards
+            .into_iter()
+            .map(|(s, per_idx)| {
+                (s, per_idx.into_iter().map(|(pi, mut c)| (pi, c.swap_remove(0).data)).collect())
+            })
+            .collect()
     }
-    Ok(map)
+}
+
+/// Parity volumes reachable through one [`DataSource`], e.g. a local dir,
+/// a slower network mount or an HTTP mirror.
+pub struct VolumeSource {
+    pub source: Box<dyn DataSource>,
+    /// Volume file names relative to the source
+    pub volumes: Vec<String>,
+}
+
+impl VolumeSource {
+    /// All `*.parxv` files in `dir` (none when the dir does not exist).
+    pub fn dir(dir: &Path, cost: ReadCost) -> Result<Self> {
+        let mut volumes = Vec::new();
+        if dir.exists() {
+            for ent in std::fs::read_dir(dir).with_context(|| format!("read_dir {:?}", dir))? {
+                let p = ent?.path();
+                if p.extension().map(|s| s == "parxv").unwrap_or(false) {
+                    volumes.push(p.file_name().unwrap_or_default().to_string_lossy().to_string());
+                }
+            }
+        }
+        volumes.sort();
+        let source = LocalSource::new(dir, PathPolicy::default()).with_cost(cost);
+        Ok(Self { source: Box::new(source), volumes })
+    }
+
+    /// Volumes `vol-000.parxv` .. of a set published under `base_url`.
+    pub fn http(base_url: &str, volume_count: usize, cost: ReadCost) -> Result<Self> {
+        let source = HttpSource::new(base_url)?.with_cost(cost);
+        Ok(Self { source: Box::new(source), volumes: (0..volume_count).map(vol_name).collect() })
+    }
+
+    /// The volumes of `mf`, by the names it gives them, published under `base_url`.
+    pub fn http_of(base_url: &str, mf: &Manifest, cost: ReadCost) -> Result<Self> {
+        let source = HttpSource::new(base_url)?.with_cost(cost);
+        let volumes = (0..mf.volumes.max(1)).map(|vid| mf.volume_name(vid)).collect();
+        Ok(Self { source: Box::new(source), volumes })
+    }
+}
+
+/// Where one copy of a parity shard can be read from.
+struct ShardLoc {
+    source: usize,
+    volume: String,
+    entry: VolumeEntry,
+    cost: ReadCost,
+}
+
+/// Merge the indices of every volume in `sources`, then read the `kind`
+/// shards of the `wanted` stripes (all when `None`; outer shards are keyed by
+/// group). Copies are read cheapest source
+/// first; for hashed shards the first copy that passes its hash is used and
+/// costlier copies are never fetched. Shards without a recorded hash are read
+/// from every source and ranked by agreement. Volumes whose header names
+/// another set or another generation than `set` are skipped.
+pub(crate) fn collect_parity_copies(
+    sources: &[&VolumeSource],
+    chunk_size: usize,
+    wanted: Option<&HashSet<u64>>,
+    kind: ShardKind,
+    set: SetTag,
+) -> Result<ParityCopies> {
+    let mut copies = ParityCopies::default();
+    let mut locs: HashMap<(u64, usize), Vec<ShardLoc>> = HashMap::new();
+    for (si, vs) in sources.iter().enumerate() {
+        let cost = vs.source.cost();
+        for vol in &vs.volumes {
+            let stray = match set == SetTag::default() {
+                true => None,
+                false => read_set_tag(vs.source.as_ref(), vol).and_then(|t| set.stray(&t)),
+            };
+            if let Some(stray) = stray {
+                let what = format!("{} on {} ({})", vol, vs.source.describe(), stray);
+                match stray {
+                    Stray::Foreign(_) => copies.foreign_volumes.push(what),
+                    Stray::Stale { .. } => copies.stale_volumes.push(what),
+                }
+                continue;
+            }
+            let entries = match read_index_from(vs.source.as_ref(), vol, &IndexLimits::default()) {
+                Ok(entries) => entries,
+                Err(e) if e.is::<FeatureError>() => {
+                    return Err(e.context(format!("{} on {}", vol, vs.source.describe())))
+                }
+                Err(_) => {
+                    copies.unreadable_volumes += 1;
+                    continue;
+                }
+            };
+            for e in entries.into_iter().filter(|e| e.kind == kind) {
+                let key = (e.stripe, e.parity_idx as usize);
+                locs.entry(key).or_default().push(ShardLoc {
+                    source: si,
+                    volume: vol.clone(),
+                    entry: e,
+                    cost,
+                });
+            }
+        }
+    }
+    for list in locs.values_mut() {
+        // Stable: equal-cost copies keep search order (primary parity dir first)
+        list.sort_by_key(|l| l.cost);
+    }
+    let mut first = read_first_copies(sources, &locs, wanted);
+    for ((stripe, pi), list) in locs {
+        for (i, l) in list.iter().enumerate() {
+            if l.entry.hash.is_some() && list[..i].iter().any(|o| o.entry.hash == l.entry.hash) {
+                copies.duplicates += 1;
+            }
+        }
+        if wanted.is_some_and(|w| !w.contains(&stripe)) {
+            continue;
+        }
+        let mut prefetched = first.remove(&(stripe, pi));
+        for l in &list {
+            let read = prefetched.take().unwrap_or_else(|| {
+                let mut buf = vec![0u8; l.entry.len as usize];
+                sources[l.source].source.read_at(&l.volume, l.entry.offset, &mut buf).map(|()| buf)
+            });
+            let Ok(mut buf) = read else {
+                copies.bad_copies += 1;
+                continue;
+            };
+            if l.entry.hash.is_some_and(|h| *blake3::hash(&buf).as_bytes() != h) {
+                copies.bad_copies += 1;
+                continue;
+            }
+            if l.cost.class != CostClass::Local {
+                copies.costly_reads += 1;
+            }
+            if buf.len() < chunk_size {
+                buf.resize(chunk_size, 0);
+            }
+            copies.add(stripe, pi, buf, &l.volume);
+            // A verified copy is authoritative; leave costlier copies unread
+            if l.entry.hash.is_some() {
+                break;
+            }
+        }
+    }
+    copies.rank();
+    Ok(copies)
+}
+
+/// The cheapest copy of each wanted shard, read as one batch per volume in
+/// offset order (see [`DataSource::read_many`]).
+fn read_first_copies(
+    sources: &[&VolumeSource],
+    locs: &HashMap<(u64, usize), Vec<ShardLoc>>,
+    wanted: Option<&HashSet<u64>>,
+) -> HashMap<(u64, usize), Result<Vec<u8>>> {
+    let mut by_volume: HashMap<(usize, &str), Vec<&ShardLoc>> = HashMap::new();
+    for ((stripe, _), list) in locs {
+        if wanted.is_some_and(|w| !w.contains(stripe)) {
+            continue;
+        }
+        if let Some(l) = list.first() {
+            by_volume.entry((l.source, l.volume.as_str())).or_default().push(l);
+        }
+    }
+    let mut out = HashMap::new();
+    for ((si, volume), mut shards) in by_volume {
+        shards.sort_by_key(|l| l.entry.offset);
+        let mut bufs: Vec<Vec<u8>> =
+            shards.iter().map(|l| vec![0u8; l.entry.len as usize]).collect();
+        let mut reqs: Vec<(u64, &mut [u8])> =
+            shards.iter().zip(&mut bufs).map(|(l, b)| (l.entry.offset, &mut b[..])).collect();
+        let results = sources[si].source.read_many(volume, &mut reqs);
+        for ((l, buf), res) in shards.iter().zip(bufs).zip(results) {
+            out.insert((l.entry.stripe, l.entry.parity_idx as usize), res.map(|()| buf));
+        }
+    }
+    out
 }
 
 pub fn repair(manifest_path: &Path, root: &Path) -> Result<RepairReport> {
@@ -52,172 +420,920 @@ pub fn repair_with_policy(
     root: &Path,
     policy: PathPolicy,
 ) -> Result<RepairReport> {
-    let mf: Manifest =
-        serde_json::from_reader(File::open(manifest_path)?).context("read manifest.json")?;
+    repair_with_options(manifest_path, root, policy, &RepairOptions::default())
+}
+
+/// Knobs beyond the path policy.
+#[derive(Default)]
+pub struct RepairOptions {
+    /// Extra dirs searched for parity volumes (see [`repair_multi`])
+    pub extra_dirs: Vec<PathBuf>,
+    /// Further volume locations with their own cost hints (network shares,
+    /// HTTP mirrors); shards are read from the cheapest source that has them
+    pub extra_sources: Vec<VolumeSource>,
+    /// Sign the audit-log entries written for this repair
+    pub audit_key: Option<SigningKey>,
+    /// Refuse to repair unless the manifest carries a valid signature by this key
+    pub verify_key: Option<VerifyingKey>,
+    /// UID remapping applied when restoring recorded ownership
+    pub chown_map: ChownMap,
+    /// Also restore the recorded mtimes and extended attributes of rewritten
+    /// files (see [`meta::restore`])
+    pub restore_metadata: bool,
+    /// Check only these chunks instead of every chunk, e.g. the ones a
+    /// filesystem scrub flagged (see [`crate::scrub`])
+    pub only_chunks: Option<BTreeSet<u64>>,
+    /// Skip files whose reads make no progress for this long (see [`watchdog`])
+    pub io_timeout: Option<Duration>,
+    /// Pause between chunks and stripes while the system is busy with I/O
+    pub throttle: Option<Throttle>,
+    /// Try reads and writes of the tree failing with transient errors again
+    /// (network mounts)
+    pub retry: RetryPolicy,
+    /// Take files as intact without reading them when the set's hash cache
+    /// found them so at most this long ago (see [`crate::hashcache`])
+    pub reuse_hashes: Option<Duration>,
+    /// Record the new name of missing files found intact elsewhere (see
+    /// [`crate::moved`]) in the manifest instead of recreating the old path
+    pub fix_paths: bool,
+    /// Write the reconstructed chunks to this dir as standalone files (see
+    /// [`EXTRACTED_INDEX`]) and leave the tree untouched: no file is
+    /// patched, recreated or re-linked and nothing goes to the audit log
+    pub extract_chunks: Option<PathBuf>,
+    /// Count the files checked into the stage `scan` and the stripes rebuilt
+    /// into `rebuild` of this registry (see [`crate::progress`])
+    pub progress: Option<crate::progress::Progress>,
+    /// Key of a set made with `create --hash-key`; required for those
+    pub hash_key: Option<manifest::HashKey>,
+    /// What to do with damaged files other processes have locked or open
+    /// for writing (`--if-in-use`)
+    pub if_in_use: IfInUse,
+}
+
+/// Index of `RepairOptions::extract_chunks`: one [`ExtractedChunk`] JSON
+/// line per location a chunk belongs at, next to the chunk files
+/// `<idx>-<hash>.chunk` (hash as in the manifest, over the chunk
+/// zero-padded to the chunk size; the file holds only the chunk's bytes).
+pub const EXTRACTED_INDEX: &str = "chunks.jsonl";
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ExtractedChunk {
+    pub chunk: u64,
+    pub hash: String,
+    /// Name of the chunk file in the extract dir
+    pub file: String,
+    /// Where the chunk belongs: file as recorded in the manifest, offset, length
+    pub target: String,
+    pub offset: u64,
+    pub len: u32,
+    pub stripe: u64,
+    /// What it was rebuilt from, as in the audit log
+    pub sources: Vec<String>,
+}
+
+/// Repair using parity volumes spread over the manifest's parity dir plus
+/// `extra_dirs` (split archives, mirrors on other media). Indices from all
+/// dirs are merged; when a shard exists more than once, the copy that passes
+/// its hash and agrees with most other copies is tried first, and alternates
+/// are used if the reconstructed chunks do not match the manifest.
+pub fn repair_multi(
+    manifest_path: &Path,
+    root: &Path,
+    policy: PathPolicy,
+    extra_dirs: &[PathBuf],
+) -> Result<RepairReport> {
+    let opts = RepairOptions { extra_dirs: extra_dirs.to_vec(), ..Default::default() };
+    repair_with_options(manifest_path, root, policy, &opts)
+}
+
+/// Full-featured entry point; every repaired chunk is recorded in the set's
+/// audit log (`audit_log::AUDIT_LOG`), signed when `opts.audit_key` is set.
+pub fn repair_with_options(
+    manifest_path: &Path,
+    root: &Path,
+    policy: PathPolicy,
+    opts: &RepairOptions,
+) -> Result<RepairReport> {
+    let (mut mf, manifest_recovery) = manifest::load(manifest_path)?;
+    mf.use_hash_key(opts.hash_key.clone())?;
+    if let Some(vk) = &opts.verify_key {
+        crate::sign::verify_manifest(&mf, Some(vk))?;
+    }
     // Global lock in parity dir to avoid concurrent repairs
-    let lock_path = Path::new(&mf.parity_dir).join(".parx.repair.lock");
+    let parity_dir = mf.parity_dir_at(manifest_path);
+    let lock_path = parity_dir.join(".parx.repair.lock");
     let lock_file = File::create(&lock_path).context("create global repair lock")?;
     lock_file.try_lock_exclusive().context("acquire global repair lock")?;
 
-    let k = mf.stripe_k;
-    let m = (mf.stripe_k as u64 * mf.parity_pct as u64).div_ceil(100) as usize;
-    if m == 0 {
-        bail!("no parity available (parity_pct=0)");
-    }
-    let _rs = RsCodec::new(k, m).context("init RS")?; // validate params early
-    let parity_map = collect_parity_shards(Path::new(&mf.parity_dir), mf.chunk_size)?;
-
-    // Build map idx -> (safe_path, offset, len) and record target file sizes
-    let mut idx_map: HashMap<u64, (PathBuf, u64, u32)> = HashMap::new();
-    let mut file_sizes: HashMap<PathBuf, u64> = HashMap::new();
-    for fe in &mf.files {
-        let safe = validate_path(root, Path::new(&fe.rel_path), policy)
-            .with_context(|| format!("validate path {:?}", fe.rel_path))?;
-        file_sizes.insert(safe.clone(), fe.size);
-        for ch in &fe.chunks {
-            idx_map.insert(ch.idx, (safe.clone(), ch.file_offset, ch.len));
-        }
-    }
-
-    // Identify missing/corrupted chunks
-    let mut to_repair: HashMap<u64, Vec<usize>> = HashMap::new();
-    for (&idx, (path, off, len)) in &idx_map {
-        if let Ok(mut f) = File::open(path) {
-            let mut buf = vec![0u8; mf.chunk_size];
-            if f.seek(SeekFrom::Start(*off)).is_ok() {
-                let mut small = vec![0u8; *len as usize];
-                if f.read_exact(&mut small).is_ok() {
-                    buf[..small.len()].copy_from_slice(&small);
+    // Whole volumes lost or damaged are rebuilt first, so that their shards
+    // take part in the repair
+    let mut restore = crate::volparity::RestoreReport::default();
+    if mf.volume_parity > 0 {
+        restore = crate::volparity::restore(&parity_dir)?;
+    }
+
+    let moved_files = if opts.fix_paths { moved::detect(&mf, root, policy)? } else { Vec::new() };
+    if !moved_files.is_empty() {
+        if crate::sign::verify_manifest(&mf, None)?.is_some() {
+            bail!(
+                "{} file(s) moved, but renaming them would void the manifest signature; \
+                 move them back or re-create the set",
+                moved_files.len()
+            );
+        }
+        moved::apply(&mut mf, &moved_files);
+        for vid in 0..mf.volumes.max(1) {
+            crate::manifest_backup::refresh(&mf.volume_path(&parity_dir, vid), &mf)?;
+        }
+        crate::volparity::refresh(&parity_dir)?;
+        manifest::save(&mf, &parity_dir)?;
+    }
+
+    let geo = mf.geometry();
+    let (k, m) = (geo.k, geo.m);
+    if m == 0 && !mf.is_empty() {
+        bail!("no parity available (parity_shards=0)");
+    }
+    // Stripes of critical files and parity groups may hold shards past m
+    let m_max = mf.max_parity_shards();
+    // One codec for all stripes: setting up a GF(2^16) matrix is costly. An
+    // empty set has no stripe to decode and may have been made without parity
+    let rs = RsCodec::with_field(RsField::from_ext(&mf.ext), k, m_max.max(1)).context("init RS")?;
+    let rp = Repair {
+        mf: &mf,
+        root,
+        policy,
+        opts,
+        parity_dir: &parity_dir,
+        geo,
+        hasher: mf.hasher(),
+        progress: opts.progress.clone().unwrap_or_default(),
+        io_stats: RetryStats::default(),
+    };
+
+    let mut targets = Targets::new(&mf, root, policy)?;
+    let mut scan = rp.scan_tree()?;
+    let resynced = rp.resync_moved(&mut targets, &mut scan)?;
+    let (to_repair, twin_copies) = rp.plan_stripes(&mut targets, &scan);
+    let local = rp.local_sources()?;
+    let sources: Vec<&VolumeSource> = local.iter().chain(opts.extra_sources.iter()).collect();
+    let parity = rp.gather_parity(&sources, &to_repair)?;
+    let outer = OuterLayout::from_manifest(&mf);
+    let rebuilt = Rebuild { rs: &rs, m_max, parity: &parity, keep: outer.is_some() };
+    let results = rp.rebuild_stripes(&targets, &rebuilt, to_repair);
+    // Nothing was written to the tree yet
+    rp.progress.check_cancelled()?;
+
+    let mut repaired_chunks = 0u64;
+    let mut failed_chunks = 0u64;
+    // Every location to rewrite, with its audit event
+    let mut fixes: Vec<Fix> = Vec::new();
+    let mut failed: HashMap<u64, Vec<usize>> = HashMap::new();
+    let mut recovered: HashMap<u64, Vec<u8>> = HashMap::new();
+    let stalled = &scan.stalled;
+    let is_stalled =
+        |idx: u64| targets.idx_map.get(&idx).is_some_and(|(p, ..)| stalled.contains(p));
+    let chunks_resynced = resynced.len() as u64;
+    for fix in twin_copies.into_iter().chain(resynced) {
+        repaired_chunks += 1;
+        fixes.push(fix);
+    }
+    for r in results {
+        // One audit event per edit
+        for (edit, event) in r.edits.into_iter().zip(r.events) {
+            if stalled.contains(&edit.0) {
+                continue;
+            }
+            repaired_chunks += 1;
+            fixes.push((edit, event));
+        }
+        let unrepaired: Vec<usize> =
+            r.unrepaired.into_iter().filter(|&i| !is_stalled(geo.chunk_at(r.stripe, i))).collect();
+        failed_chunks += unrepaired.len() as u64;
+        if !unrepaired.is_empty() {
+            failed.insert(r.stripe, unrepaired);
+        }
+        recovered.extend(r.recovered);
+    }
+
+    // Stripes that lost more than inner parity covers: try their outer groups
+    let mut outer_repaired_chunks = 0u64;
+    if let Some(layout) = outer.filter(|_| !failed.is_empty()) {
+        let (outer_fixes, chunks) =
+            rp.outer_fixes(&layout, &targets, &scan.stalled, recovered, &sources, &failed)?;
+        repaired_chunks += outer_fixes.len() as u64;
+        failed_chunks -= chunks;
+        outer_repaired_chunks += chunks;
+        fixes.extend(outer_fixes);
+    }
+    let mut applied = Applied::default();
+    let mut extracted_chunks = 0;
+    if let Some(dir) = &opts.extract_chunks {
+        // The tree is left as it is and nothing goes to the audit log
+        extracted_chunks = extract_chunks(dir, &fixes, &targets.hash_map)?;
+    } else {
+        applied = rp.apply_fixes(&targets, fixes)?;
+        repaired_chunks -= applied.in_use_chunks;
+    }
+
+    // Release global lock on drop
+    Ok(RepairReport {
+        repaired_chunks,
+        failed_chunks,
+        duplicate_shards: parity.duplicates,
+        bad_parity_copies: parity.bad_copies,
+        metadata: applied.metadata,
+        empty_files_restored: applied.empty_files_restored,
+        symlinks_restored: applied.symlinks_restored,
+        symlinks_failed: applied.symlinks_failed,
+        throttled_ms: opts.throttle.as_ref().map_or(0, |t| t.paused().as_millis() as u64),
+        unreadable_volumes: parity.unreadable_volumes,
+        foreign_volumes: parity.foreign_volumes,
+        stale_volumes: parity.stale_volumes,
+        remote_shard_reads: parity.costly_reads,
+        chunks_checked: scan.chunks_checked,
+        chunks_reused: scan.chunks_reused,
+        chunks_resynced,
+        moved_files,
+        volumes_restored: restore.restored,
+        volumes_unrecoverable: restore.unrecoverable,
+        stalled_files: scan.stalled_files,
+        unreachable_files: scan.unreachable_files,
+        in_use_files: applied.in_use_files,
+        io_retries: rp.io_stats.retries(),
+        io_recovered: rp.io_stats.recovered(),
+        outer_repaired_chunks,
+        extracted_chunks,
+        manifest_recovery,
+    })
+}
+
+/// Where the chunks of a set belong in the tree.
+struct Targets<'a> {
+    /// Slot -> (safe_path, offset, len) of its first location
+    idx_map: HashMap<u64, (PathBuf, u64, u32)>,
+    file_sizes: HashMap<PathBuf, u64>,
+    hash_map: HashMap<u64, &'a str>,
+    rel_map: HashMap<u64, &'a str>,
+    file_meta: HashMap<PathBuf, (&'a str, Option<&'a FileMeta>)>,
+    /// Further locations of slots shared by identical chunks (`create --dedup`)
+    twins: HashMap<u64, Vec<(PathBuf, u64, u32, &'a str)>>,
+    /// Zero-length files hold no chunk; only their existence is repaired
+    empty_files: Vec<PathBuf>,
+}
+
+impl<'a> Targets<'a> {
+    fn new(mf: &'a Manifest, root: &Path, policy: PathPolicy) -> Result<Self> {
+        let mut t = Targets {
+            idx_map: HashMap::new(),
+            file_sizes: HashMap::new(),
+            hash_map: HashMap::new(),
+            rel_map: HashMap::new(),
+            file_meta: HashMap::new(),
+            twins: HashMap::new(),
+            empty_files: Vec::new(),
+        };
+        for fe in &mf.files {
+            let safe = validate_path(root, Path::new(&fe.rel_path), policy)
+                .with_context(|| format!("validate path {:?}", fe.rel_path))?;
+            if fe.size == 0 {
+                t.empty_files.push(safe.clone());
+            }
+            t.file_sizes.insert(safe.clone(), fe.size);
+            t.file_meta.insert(safe.clone(), (&fe.rel_path, fe.meta.as_ref()));
+            for ch in &fe.chunks {
+                if t.idx_map.contains_key(&ch.idx) {
+                    let twin = (safe.clone(), ch.file_offset, ch.len, fe.rel_path.as_str());
+                    t.twins.entry(ch.idx).or_default().push(twin);
+                    continue;
                 }
+                t.idx_map.insert(ch.idx, (safe.clone(), ch.file_offset, ch.len));
+                t.hash_map.insert(ch.idx, &ch.hash_hex);
+                t.rel_map.insert(ch.idx, &fe.rel_path);
             }
-            let h = blake3::hash(&buf).to_hex().to_string();
-            let expected = mf
-                .files
+        }
+        Ok(t)
+    }
+
+    /// Every location of slot `idx` with the file it is recorded under:
+    /// the first, then its twins.
+    fn locations(&self, idx: u64) -> Vec<(PathBuf, u64, u32, &'a str)> {
+        let Some((path, off, len)) = self.idx_map.get(&idx) else { return Vec::new() };
+        std::iter::once((path.clone(), *off, *len, self.rel_map[&idx]))
+            .chain(self.twins.get(&idx).into_iter().flatten().cloned())
+            .collect()
+    }
+}
+
+/// What repair's read of the tree found.
+struct Scan {
+    /// Damaged locations of each slot
+    bad_at: BTreeMap<u64, Vec<(PathBuf, u64)>>,
+    chunks_checked: u64,
+    chunks_reused: u64,
+    /// Files whose reads stalled past `io_timeout` or kept failing: never
+    /// read again, and their chunks serve only as erasures
+    stalled: HashSet<PathBuf>,
+    stalled_files: Vec<String>,
+    unreachable_files: Vec<String>,
+}
+
+/// A stripe rebuilt from inner parity.
+struct StripeResult {
+    stripe: u64,
+    edits: Vec<Edit>,
+    events: Vec<AuditEvent>,
+    /// Positions inner parity could not restore
+    unrepaired: Vec<usize>,
+    /// Restored chunks (full, padded), kept for the outer pass
+    recovered: Vec<(u64, Vec<u8>)>,
+}
+
+/// What the inner pass decodes with.
+struct Rebuild<'a> {
+    rs: &'a RsCodec,
+    /// Parity shards of the widest stripe
+    m_max: usize,
+    parity: &'a ParityCopies,
+    /// Keep the restored chunks for the outer pass
+    keep: bool,
+}
+
+/// What writing the fixes to the tree came to.
+#[derive(Default)]
+struct Applied {
+    metadata: MetaReport,
+    empty_files_restored: u64,
+    symlinks_restored: u64,
+    symlinks_failed: Vec<String>,
+    in_use_files: Vec<InUseFile>,
+    /// Fixes left unwritten in files in use
+    in_use_chunks: u64,
+}
+
+/// What the stages of one repair share.
+struct Repair<'a> {
+    mf: &'a Manifest,
+    root: &'a Path,
+    policy: PathPolicy,
+    opts: &'a RepairOptions,
+    parity_dir: &'a Path,
+    geo: Geometry,
+    hasher: ChunkHasher,
+    progress: crate::progress::Progress,
+    io_stats: RetryStats,
+}
+
+impl Repair<'_> {
+    /// Identify missing/corrupted chunks, by location.
+    fn scan_tree(&self) -> Result<Scan> {
+        let (mf, opts, hasher) = (self.mf, self.opts, self.hasher);
+        let mut out = Scan {
+            bad_at: BTreeMap::new(),
+            chunks_checked: 0,
+            chunks_reused: 0,
+            stalled: HashSet::new(),
+            stalled_files: Vec::new(),
+            unreachable_files: Vec::new(),
+        };
+        let mut hashes = HashCache::load(self.parity_dir);
+        let mut hashes_dirty = false;
+        let scan = self.progress.stage("scan");
+        scan.add_total(mf.files.iter().map(|fe| fe.size).sum(), mf.files.len() as u64);
+        for fe in &mf.files {
+            self.progress.check_cancelled()?;
+            scan.set_current(&fe.rel_path);
+            scan.add_bytes(fe.size);
+            scan.add_items(1);
+            let chunks: Vec<(u64, u64, u32, String)> = fe
+                .chunks
                 .iter()
-                .flat_map(|fe| fe.chunks.iter())
-                .find(|c| c.idx == idx)
-                .map(|c| c.hash_hex.clone())
-                .unwrap_or_default();
-            if h != expected {
-                let stripe = idx / k as u64;
-                let data_i = (idx % k as u64) as usize;
-                to_repair.entry(stripe).or_default().push(data_i);
+                .filter(|c| opts.only_chunks.as_ref().map_or(true, |only| only.contains(&c.idx)))
+                .map(|c| (c.idx, c.file_offset, c.len, c.hash_hex.clone()))
+                .collect();
+            if chunks.is_empty() {
+                continue;
             }
-        } else {
-            // File missing: mark this data shard as missing for reconstruction
-            let stripe = idx / k as u64;
-            let data_i = (idx % k as u64) as usize;
-            to_repair.entry(stripe).or_default().push(data_i);
-        }
-    }
-
-    // Parallelize by stripe
-    let idx_map = idx_map; // move into closure
-    let file_sizes = file_sizes;
-    // parity_map is already owned and read-only
-    let chunk_size = mf.chunk_size;
-    type Edit = (PathBuf, u64, Vec<u8>);
-    type StripeResult = (u64, Vec<Edit>);
-    let results: Vec<StripeResult> = to_repair
-        .into_par_iter()
-        .map(|(stripe, missing)| {
-            let mut repaired_local = 0u64;
-            let mut edits_local: Vec<Edit> = Vec::new();
-            // K data shards
-            let mut data_bufs: Vec<Option<Vec<u8>>> = Vec::with_capacity(k);
-            for i in 0..k {
-                let idx = stripe * k as u64 + i as u64;
-                if missing.contains(&i) {
-                    data_bufs.push(None);
-                } else {
-                    let mut buf = vec![0u8; chunk_size];
-                    if let Some((path, off, len)) = idx_map.get(&idx) {
-                        if let Ok(mut f) = File::open(path) {
-                            let _ = f.seek(SeekFrom::Start(*off));
-                            let mut small = vec![0u8; *len as usize];
-                            if f.read_exact(&mut small).is_ok() {
-                                buf[..small.len()].copy_from_slice(&small);
+            let n = chunks.len() as u64;
+            let path = validate_path(self.root, Path::new(&fe.rel_path), self.policy)?;
+            let all: Vec<(u64, u64)> = chunks.iter().map(|c| (c.0, c.1)).collect();
+            let whole = chunks.len() == fe.chunks.len();
+            let cs = mf.chunk_size;
+            let p = path.clone();
+            let throttle = opts.throttle.clone();
+            let digest = hashcache::digest(&fe.chunks);
+            let cached = opts.reuse_hashes.zip(hashes.files.get(&fe.rel_path).cloned());
+            let (retry, stats) = (opts.retry, self.io_stats.clone());
+            let checked = watchdog::watched(opts.io_timeout, move |ticker| {
+                let before = hashcache::stamp_of(&p);
+                if let (Some((age, c)), Some(stamp)) = (&cached, &before) {
+                    if c.holds(stamp, &digest, *age) {
+                        return FileCheck::Reused;
+                    }
+                }
+                let mut f = match retry.run(&stats, || ticker.tick(), |_| File::open(&p)) {
+                    Ok(f) => f,
+                    Err(e) if is_transient(&e) => return FileCheck::Unreachable,
+                    // File missing: every chunk is missing for reconstruction
+                    Err(_) => return FileCheck::Read(all, None),
+                };
+                let mut bad = Vec::new();
+                for (idx, off, len, want) in chunks {
+                    if let Some(t) = &throttle {
+                        t.pause_with(|| ticker.tick());
+                    }
+                    let mut buf = vec![0u8; cs];
+                    let mut small = vec![0u8; len as usize];
+                    let read = retry.run(
+                        &stats,
+                        || ticker.tick(),
+                        |attempt| {
+                            if attempt > 0 {
+                                // A handle gone stale stays so; read through a fresh one
+                                f = File::open(&p)?;
                             }
-                        }
+                            f.seek(SeekFrom::Start(off))?;
+                            f.read_exact(&mut small)
+                        },
+                    );
+                    match read {
+                        Ok(()) => buf[..small.len()].copy_from_slice(&small),
+                        // Not known to be damaged: must not be rewritten
+                        Err(e) if is_transient(&e) => return FileCheck::Unreachable,
+                        Err(_) => {}
+                    }
+                    ticker.tick();
+                    if !hasher.matches(&buf, &want) {
+                        bad.push((idx, off));
                     }
-                    data_bufs.push(Some(buf));
                 }
+                let intact = whole && bad.is_empty();
+                let stamp =
+                    before.filter(|b| intact && hashcache::stamp_of(&p).as_ref() == Some(b));
+                FileCheck::Read(bad, stamp)
+            })?;
+            let bad = match checked {
+                Some(FileCheck::Reused) => {
+                    out.chunks_reused += n;
+                    continue;
+                }
+                Some(FileCheck::Read(bad, stamp)) => {
+                    out.chunks_checked += n;
+                    if let Some(stamp) = stamp {
+                        hashes.insert(&fe.rel_path, stamp, &fe.chunks);
+                        hashes_dirty = true;
+                    } else if !bad.is_empty() {
+                        hashes.remove(&fe.rel_path);
+                        hashes_dirty = true;
+                    }
+                    bad
+                }
+                Some(FileCheck::Unreachable) => {
+                    out.chunks_checked += n;
+                    out.stalled.insert(path.clone());
+                    out.unreachable_files.push(fe.rel_path.clone());
+                    fe.chunks.iter().map(|c| (c.idx, c.file_offset)).collect()
+                }
+                None => {
+                    out.chunks_checked += n;
+                    out.stalled.insert(path.clone());
+                    out.stalled_files.push(fe.rel_path.clone());
+                    fe.chunks.iter().map(|c| (c.idx, c.file_offset)).collect()
+                }
+            };
+            for (idx, off) in bad {
+                out.bad_at.entry(idx).or_default().push((path.clone(), off));
             }
-            let mut shards: Vec<Option<Vec<u8>>> = vec![None; k + m];
-            for (i, db) in data_bufs.into_iter().enumerate() {
-                shards[i] = db;
+        }
+
+        scan.finish();
+        if hashes_dirty {
+            // Best effort: a stale cache only costs a later read
+            let _ = hashes.save(self.parity_dir);
+        }
+        Ok(out)
+    }
+
+    /// Chunks that only moved within their file, because bytes were
+    /// inserted or deleted before them, are moved back instead of rebuilt.
+    fn resync_moved(&self, targets: &mut Targets, scan: &mut Scan) -> Result<Vec<Fix>> {
+        let mf = self.mf;
+        let bad_at = &mut scan.bad_at;
+        let mut resynced = Vec::new();
+        for fe in &mf.files {
+            let path = validate_path(self.root, Path::new(&fe.rel_path), self.policy)?;
+            let is_bad_here = |c: &ChunkRef| {
+                bad_at
+                    .get(&c.idx)
+                    .is_some_and(|b| b.iter().any(|(p, o)| *p == path && *o == c.file_offset))
+            };
+            let (failed, intact): (Vec<&ChunkRef>, Vec<&ChunkRef>) =
+                fe.chunks.iter().partition(|c| is_bad_here(c));
+            if failed.is_empty() || scan.stalled.contains(&path) {
+                continue;
             }
-            let mut parity = Vec::new();
-            if let Some(v) = parity_map.get(&(stripe as u32)) {
-                parity = v.clone();
+            let Ok(found) = resync::locate(&path, &failed, &intact, &self.hasher, &|| {}) else {
+                continue;
+            };
+            for c in failed {
+                let Some(&at) = found.get(&c.file_offset) else { continue };
+                let Some(buf) = crate::versions::read_padded(&path, at, c.len, mf.chunk_size)
+                else {
+                    continue;
+                };
+                if let Some(b) = bad_at.get_mut(&c.idx) {
+                    b.retain(|(p, o)| !(*p == path && *o == c.file_offset));
+                    if b.is_empty() {
+                        bad_at.remove(&c.idx);
+                    }
+                }
+                // Stripe reads of the slot find the chunk where it is now
+                let idx_map = &mut targets.idx_map;
+                if idx_map.get(&c.idx).is_some_and(|(p, o, _)| *p == path && *o == c.file_offset) {
+                    idx_map.insert(c.idx, (path.clone(), at, c.len));
+                }
+                let event = AuditEvent {
+                    action: "repair".to_string(),
+                    target: fe.rel_path.clone(),
+                    offset: c.file_offset,
+                    len: c.len,
+                    chunk: Some(c.idx),
+                    stripe: self.geo.stripe_of(c.idx),
+                    sources: vec![format!("moved:{}@{}", fe.rel_path, at)],
+                };
+                let edit = (path.clone(), c.file_offset, buf[..c.len as usize].to_vec());
+                resynced.push((edit, event));
+            }
+        }
+        Ok(resynced)
+    }
+
+    /// The stripes that need parity, with the positions lost in each. A
+    /// damaged location whose slot has an intact twin is copied from it
+    /// instead; those copies come second.
+    fn plan_stripes(
+        &self,
+        targets: &mut Targets,
+        scan: &Scan,
+    ) -> (HashMap<u64, Vec<usize>>, Vec<Fix>) {
+        let geo = &self.geo;
+        let mut to_repair: HashMap<u64, Vec<usize>> = HashMap::new();
+        let mut twin_copies: Vec<Fix> = Vec::new();
+        for (idx, bad) in &scan.bad_at {
+            let locs = targets.locations(*idx);
+            let is_bad = |l: &(PathBuf, u64, u32, &str)| {
+                scan.stalled.contains(&l.0) || bad.iter().any(|(p, o)| *p == l.0 && *o == l.1)
+            };
+            let Some(good) = locs.iter().find(|l| !is_bad(l)) else {
+                to_repair.entry(geo.stripe_of(*idx)).or_default().push(geo.slot_of(*idx));
+                continue;
+            };
+            let cs = self.mf.chunk_size;
+            let Some(buf) = crate::versions::read_padded(&good.0, good.1, good.2, cs) else {
+                to_repair.entry(geo.stripe_of(*idx)).or_default().push(geo.slot_of(*idx));
+                continue;
+            };
+            // The stripe and outer passes read the slot from the intact copy
+            targets.idx_map.insert(*idx, (good.0.clone(), good.1, good.2));
+            for l in locs.iter().filter(|l| is_bad(l) && !scan.stalled.contains(&l.0)) {
+                let event = AuditEvent {
+                    action: "repair".to_string(),
+                    target: l.3.to_string(),
+                    offset: l.1,
+                    len: l.2,
+                    chunk: Some(*idx),
+                    stripe: geo.stripe_of(*idx),
+                    sources: vec![format!("copy:{}@{}", good.3, good.1)],
+                };
+                twin_copies.push(((l.0.clone(), l.1, buf[..l.2 as usize].to_vec()), event));
             }
-            if parity.len() < m {
-                // cannot repair this stripe
-                return (0u64, edits_local);
+        }
+        (to_repair, twin_copies)
+    }
+
+    /// The set's own volume dirs first (`create --output-dirs`), then the
+    /// caller's.
+    fn local_sources(&self) -> Result<Vec<VolumeSource>> {
+        let mut dirs = vec![self.parity_dir.to_path_buf()];
+        for d in self.mf.volume_dirs().iter().chain(&self.opts.extra_dirs) {
+            let same = |a: &Path| match (a.canonicalize(), d.canonicalize()) {
+                (Ok(x), Ok(y)) => x == y,
+                _ => a == d.as_path(),
+            };
+            if !dirs.iter().any(|x| same(x)) {
+                dirs.push(d.clone());
             }
-            for (pi, pbuf) in parity.into_iter() {
-                if pi < m {
-                    shards[k + pi] = Some(pbuf);
+        }
+        dirs.iter().map(|d| VolumeSource::dir(d, ReadCost::LOCAL)).collect()
+    }
+
+    /// Only the stripes being repaired need their parity read, and sources
+    /// that are not local (mirrors, `--volumes-url`) only for the stripes the
+    /// local copies leave short: their indices are not even fetched otherwise.
+    fn gather_parity(
+        &self,
+        sources: &[&VolumeSource],
+        to_repair: &HashMap<u64, Vec<usize>>,
+    ) -> Result<ParityCopies> {
+        let mf = self.mf;
+        let (near, far): (Vec<&VolumeSource>, Vec<&VolumeSource>) =
+            sources.iter().partition(|vs| vs.source.cost().class == CostClass::Local);
+        let wanted: HashSet<u64> = to_repair.keys().copied().collect();
+        let mut parity = collect_parity_copies(
+            &near,
+            mf.chunk_size,
+            Some(&wanted),
+            ShardKind::Inner,
+            mf.set_tag(),
+        )?;
+        let short: HashSet<u64> = to_repair
+            .iter()
+            .filter(|(s, missing)| parity.shards_of(**s) < missing.len())
+            .map(|(s, _)| *s)
+            .collect();
+        if !far.is_empty() && !short.is_empty() {
+            parity.merge(collect_parity_copies(
+                &far,
+                mf.chunk_size,
+                Some(&short),
+                ShardKind::Inner,
+                mf.set_tag(),
+            )?);
+        }
+        Ok(parity)
+    }
+
+    /// Decode the damaged stripes from inner parity, in parallel.
+    fn rebuild_stripes(
+        &self,
+        targets: &Targets,
+        with: &Rebuild,
+        to_repair: HashMap<u64, Vec<usize>>,
+    ) -> Vec<StripeResult> {
+        let (geo, opts, progress) = (&self.geo, self.opts, &self.progress);
+        let (k, m_max, chunk_size) = (geo.k, with.m_max, self.mf.chunk_size);
+        let rebuild = progress.stage("rebuild");
+        rebuild.add_total(0, to_repair.len() as u64);
+        let results = to_repair
+            .into_par_iter()
+            .map(|(stripe, missing)| {
+                if let Some(t) = &opts.throttle {
+                    t.pause();
                 }
-            }
-            let rs = RsCodec::new(k, m).expect("init RS");
-            if rs.reconstruct(&mut shards).is_ok() {
-                for i in missing {
-                    let idx = stripe * k as u64 + i as u64;
-                    if let Some((path, off, len)) = idx_map.get(&idx) {
-                        if let Some(Some(buf)) = shards.get(i) {
-                            edits_local.push((path.clone(), *off, buf[..*len as usize].to_vec()));
-                            repaired_local += 1;
+                let _busy = progress.busy();
+                if progress.is_cancelled() {
+                    let (edits, events, recovered) = (Vec::new(), Vec::new(), Vec::new());
+                    return StripeResult { stripe, edits, events, unrepaired: missing, recovered };
+                }
+                let mut repaired_pos: Vec<usize> = Vec::new();
+                let mut recovered: Vec<(u64, Vec<u8>)> = Vec::new();
+                let mut edits_local: Vec<Edit> = Vec::new();
+                let mut events_local: Vec<AuditEvent> = Vec::new();
+                // K data shards
+                let mut data_bufs: Vec<Option<Vec<u8>>> = Vec::with_capacity(k);
+                for i in 0..k {
+                    let idx = geo.chunk_at(stripe, i);
+                    if missing.contains(&i) {
+                        data_bufs.push(None);
+                    } else {
+                        let mut buf = vec![0u8; chunk_size];
+                        if let Some((path, off, len)) = targets.idx_map.get(&idx) {
+                            if let Ok(mut f) = File::open(path) {
+                                let _ = f.seek(SeekFrom::Start(*off));
+                                let mut small = vec![0u8; *len as usize];
+                                if f.read_exact(&mut small).is_ok() {
+                                    buf[..small.len()].copy_from_slice(&small);
+                                }
+                            }
+                        }
+                        data_bufs.push(Some(buf));
+                    }
+                }
+                // Try the best parity copies first; fall back to alternates when
+                // the reconstructed chunks do not hash to what the manifest expects
+                let mut round = 0;
+                while let Some(parity) = with.parity.variant(stripe, round) {
+                    round += 1;
+                    if parity.len() < missing.len() {
+                        // cannot repair this stripe
+                        break;
+                    }
+                    let mut shards: Vec<Option<Vec<u8>>> = vec![None; k + m_max];
+                    let mut sources: Vec<String> = Vec::new();
+                    for (i, db) in data_bufs.iter().enumerate() {
+                        if db.is_some() {
+                            sources.push(format!("data[{}]", i));
+                        }
+                        shards[i] = db.clone();
+                    }
+                    for (pi, pbuf, origin) in parity.into_iter() {
+                        if pi < m_max {
+                            sources.push(format!("parity[{}]@{}", pi, origin));
+                            shards[k + pi] = Some(pbuf);
                         }
                     }
+                    if with.rs.reconstruct(&mut shards).is_err() {
+                        continue;
+                    }
+                    let verified = missing.iter().all(|&i| {
+                        let idx = geo.chunk_at(stripe, i);
+                        match (shards.get(i), targets.hash_map.get(&idx)) {
+                            (Some(Some(buf)), Some(want)) => self.hasher.matches(buf, want),
+                            _ => false,
+                        }
+                    });
+                    if !verified {
+                        continue;
+                    }
+                    for &i in &missing {
+                        let idx = geo.chunk_at(stripe, i);
+                        let Some(Some(buf)) = shards.get(i) else { continue };
+                        let locs = targets.locations(idx);
+                        if locs.is_empty() {
+                            continue;
+                        }
+                        for (path, off, len, rel) in locs {
+                            edits_local.push((path, off, buf[..len as usize].to_vec()));
+                            events_local.push(AuditEvent {
+                                action: "repair".to_string(),
+                                target: rel.to_string(),
+                                offset: off,
+                                len,
+                                chunk: Some(idx),
+                                stripe,
+                                sources: sources.clone(),
+                            });
+                        }
+                        repaired_pos.push(i);
+                        if with.keep {
+                            recovered.push((idx, buf.clone()));
+                        }
+                    }
+                    break;
+                }
+                rebuild.add_items(1);
+                StripeResult {
+                    stripe,
+                    edits: edits_local,
+                    events: events_local,
+                    unrepaired: missing.into_iter().filter(|i| !repaired_pos.contains(i)).collect(),
+                    recovered,
                 }
+            })
+            .collect();
+        rebuild.finish();
+        results
+    }
+
+    /// Rebuild what the inner pass left of `failed` through the outer
+    /// groups. Returns the fixes and how many chunks they restore.
+    fn outer_fixes(
+        &self,
+        layout: &OuterLayout,
+        targets: &Targets,
+        stalled: &HashSet<PathBuf>,
+        recovered: HashMap<u64, Vec<u8>>,
+        sources: &[&VolumeSource],
+        failed: &HashMap<u64, Vec<usize>>,
+    ) -> Result<(Vec<Fix>, u64)> {
+        let ctx = ChunkReader {
+            mf: self.mf,
+            idx_map: &targets.idx_map,
+            hash_map: &targets.hash_map,
+            recovered,
+            skip: stalled,
+        };
+        let mut fixes = Vec::new();
+        let mut chunks = 0;
+        for (idx, buf, sources) in repair_with_outer(layout, &ctx, sources, failed)? {
+            let Some((path, ..)) = targets.idx_map.get(&idx) else { continue };
+            if stalled.contains(path) {
+                continue;
+            }
+            for (path, off, len, rel) in targets.locations(idx) {
+                if stalled.contains(&path) {
+                    continue;
+                }
+                let event = AuditEvent {
+                    action: "repair".to_string(),
+                    target: rel.to_string(),
+                    offset: off,
+                    len,
+                    chunk: Some(idx),
+                    stripe: self.geo.stripe_of(idx),
+                    sources: sources.clone(),
+                };
+                fixes.push(((path, off, buf[..len as usize].to_vec()), event));
             }
-            (repaired_local, edits_local)
-        })
-        .collect();
+            chunks += 1;
+        }
+        Ok((fixes, chunks))
+    }
 
-    let mut repaired_chunks = 0u64;
-    // Collect per-file edits for atomic replacement
-    let mut file_edits: HashMap<PathBuf, Vec<(u64, Vec<u8>)>> = HashMap::new();
-    for (rc, edits) in results {
-        repaired_chunks += rc;
-        for (p, off, data) in edits {
-            file_edits.entry(p).or_default().push((off, data));
-        }
-    }
-    let failed_chunks = 0u64; // conservatively 0 here; detailed accounting optional
-                              // Apply edits: prefer atomic replace via temp+rename; fallback to in-place
-    for (path, mut edits) in file_edits {
-        edits.sort_by_key(|e| e.0);
+    /// Write the fixes into the tree, file by file, then recreate missing
+    /// empty files and symlinks and log what was done.
+    fn apply_fixes(&self, targets: &Targets, fixes: Vec<Fix>) -> Result<Applied> {
+        let opts = self.opts;
+        let mut applied = Applied::default();
+        // Collect per-file edits for atomic replacement
+        // Per file: the (offset, bytes) to write, and the events they log
+        type Edits = (Vec<(u64, Vec<u8>)>, Vec<AuditEvent>);
+        let mut file_edits: HashMap<PathBuf, Edits> = HashMap::new();
+        let mut events: Vec<AuditEvent> = Vec::new();
+        for ((p, off, data), event) in fixes {
+            let (edits, evs) = file_edits.entry(p).or_default();
+            edits.push((off, data));
+            evs.push(event);
+        }
+        for (path, (mut edits, evs)) in file_edits {
+            if let Some(by) = inuse::settle(&path, opts.if_in_use) {
+                let rel = targets
+                    .file_meta
+                    .get(&path)
+                    .map_or_else(|| path.display().to_string(), |m| m.0.to_string());
+                applied.in_use_chunks += evs.len() as u64;
+                applied.in_use_files.push(InUseFile { path: rel, by });
+                continue;
+            }
+            events.extend(evs);
+            edits.sort_by_key(|e| e.0);
+            self.write_file(&path, &edits, targets.file_sizes.get(&path).copied())?;
+            // The rewrite produced a new inode owned by us; put recorded metadata back
+            if let Some((rel, Some(fm))) = targets.file_meta.get(&path) {
+                let extra = opts.restore_metadata;
+                meta::restore(&path, rel, fm, &opts.chown_map, extra, &mut applied.metadata);
+            }
+        }
+
+        for path in &targets.empty_files {
+            if path.symlink_metadata().is_ok() {
+                continue;
+            }
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir).with_context(|| format!("create dir {:?}", dir))?;
+            }
+            File::create(path).with_context(|| format!("create {:?}", path))?;
+            applied.empty_files_restored += 1;
+            if let Some((rel, Some(fm))) = targets.file_meta.get(path) {
+                let extra = opts.restore_metadata;
+                meta::restore(path, rel, fm, &opts.chown_map, extra, &mut applied.metadata);
+            }
+        }
+
+        for link in &self.mf.symlinks {
+            match crate::symlink::restore(self.root, link, self.policy) {
+                Ok(made) => applied.symlinks_restored += made as u64,
+                Err(e) => applied.symlinks_failed.push(format!("{}: {:#}", link.rel_path, e)),
+            }
+        }
+
+        events.sort_by(|a, b| (&a.target, a.offset).cmp(&(&b.target, b.offset)));
+        audit_log::append(self.parity_dir, &events, opts.audit_key.as_ref())?;
+        Ok(applied)
+    }
+
+    /// Apply `edits` (sorted) to one file: prefer atomic replace via
+    /// temp+rename; fallback to in-place.
+    fn write_file(&self, path: &Path, edits: &[(u64, Vec<u8>)], size: Option<u64>) -> Result<()> {
+        let (retry, io_stats) = (self.opts.retry, &self.io_stats);
+        let on_disk = std::fs::metadata(path).map_or(0, |md| md.len());
+        if size.unwrap_or(0).max(on_disk) > REWRITE_MAX {
+            // Rewriting would need the whole file in memory and a second
+            // copy of it on disk; only the damaged ranges are written.
+            // Writing them again after a failure is harmless
+            return retry
+                .run(io_stats, || {}, |_| patch_in_place(path, edits, size))
+                .with_context(|| format!("repair {:?} in place", path));
+        }
         // backup once per file
         let bak = path.with_extension("parx.bak");
         if !bak.exists() {
-            let _ = std::fs::copy(&path, &bak);
+            let _ = std::fs::copy(path, &bak);
         }
         // Try atomic replace
         let parent = path.parent().unwrap_or(Path::new("."));
         let tmp = parent.join(format!("{}.parx.tmp", path.file_name().unwrap().to_string_lossy()));
         let atomic_res = (|| -> Result<()> {
-            let mut orig = match std::fs::read(&path) {
+            let mut orig = match retry.run(io_stats, || {}, |_| std::fs::read(path)) {
                 Ok(b) => b,
+                // Zeros in place of the file, on a mount that is down, would be
+                // written back over it
+                Err(e) if is_transient(&e) => return Err(e.into()),
                 Err(_) => {
                     // Recreate missing file buffer sized to manifest size (or grow on writes)
-                    let sz = *file_sizes.get(&path).unwrap_or(&0u64) as usize;
-                    vec![0u8; sz]
+                    vec![0u8; usize::try_from(size.unwrap_or(0))?]
                 }
             };
-            for (off, data) in &edits {
-                let off = *off as usize;
+            for (off, data) in edits {
+                let off = usize::try_from(*off)?;
                 if off + data.len() > orig.len() {
                     orig.resize(off + data.len(), 0);
                 }
                 orig[off..off + data.len()].copy_from_slice(data);
             }
             // Truncate back to manifest-declared file size if known
-            if let Some(sz) = file_sizes.get(&path) {
-                if orig.len() > *sz as usize {
-                    orig.truncate(*sz as usize);
-                }
+            if let Some(sz) = size {
+                orig.truncate(usize::try_from(sz)?);
             }
             {
                 let mut tf = std::fs::OpenOptions::new()
@@ -240,41 +1356,219 @@ pub fn repair_with_policy(
             // On Windows, rename fails if destination exists; try remove then rename.
             #[cfg(windows)]
             {
-                match std::fs::rename(&tmp, &path) {
+                match std::fs::rename(&tmp, path) {
                     Ok(()) => {}
                     Err(_) => {
-                        let _ = std::fs::remove_file(&path);
-                        std::fs::rename(&tmp, &path)?;
+                        let _ = std::fs::remove_file(path);
+                        std::fs::rename(&tmp, path)?;
                     }
                 }
             }
             #[cfg(not(windows))]
             {
-                std::fs::rename(&tmp, &path)?;
+                std::fs::rename(&tmp, path)?;
             }
             Ok(())
         })();
         if atomic_res.is_err() {
             // Fallback to in-place with advisory lock
-            if let Ok(mut f) = std::fs::OpenOptions::new()
-                .create(true)
-                .read(true)
-                .write(true)
-                .truncate(false)
-                .open(&path)
-            {
-                let _ = f.try_lock_exclusive();
-                for (off, data) in &edits {
-                    if f.seek(SeekFrom::Start(*off)).is_ok() {
-                        let _ = f.write_all(data);
+            let _ = patch_in_place(path, edits, None);
+        }
+        Ok(())
+    }
+}
+
+/// What repair's check of a file came to.
+enum FileCheck {
+    /// Its chunks were read: the damaged ones, and the stamp of an intact file
+    Read(Vec<(u64, u64)>, Option<crate::fshint::FileStamp>),
+    /// Found intact by the hash cache, not read
+    Reused,
+    /// Transient errors through every retry
+    Unreachable,
+}
+
+/// Bytes to write at an offset of a file
+type Edit = (PathBuf, u64, Vec<u8>);
+
+/// An edit with the audit event it logs
+type Fix = (Edit, AuditEvent);
+
+/// Write each fixed chunk once to `dir` and list every location it belongs
+/// at in [`EXTRACTED_INDEX`]; returns the number of chunk files written.
+fn extract_chunks(dir: &Path, fixes: &[Fix], hash_map: &HashMap<u64, &str>) -> Result<u64> {
+    std::fs::create_dir_all(dir).with_context(|| format!("create dir {:?}", dir))?;
+    let mut written = BTreeSet::new();
+    let mut index = Vec::new();
+    let mut sorted: Vec<&Fix> = fixes.iter().collect();
+    sorted.sort_by(|a, b| {
+        (a.1.chunk, &a.1.target, a.1.offset).cmp(&(b.1.chunk, &b.1.target, b.1.offset))
+    });
+    for ((_, _, data), event) in sorted {
+        let Some(idx) = event.chunk else { continue };
+        let hash = hash_map.get(&idx).copied().unwrap_or_default();
+        let file = format!("{:08}-{}.chunk", idx, hash);
+        if written.insert(idx) {
+            let path = dir.join(&file);
+            std::fs::write(&path, data).with_context(|| format!("write {:?}", path))?;
+        }
+        let rec = ExtractedChunk {
+            chunk: idx,
+            hash: hash.to_string(),
+            file,
+            target: event.target.clone(),
+            offset: event.offset,
+            len: event.len,
+            stripe: event.stripe,
+            sources: event.sources.clone(),
+        };
+        index.extend(serde_json::to_vec(&rec)?);
+        index.push(b'\n');
+    }
+    let path = dir.join(EXTRACTED_INDEX);
+    std::fs::write(&path, index).with_context(|| format!("write {:?}", path))?;
+    Ok(written.len() as u64)
+}
+
+/// Files up to this size are repaired by rewriting them whole and renaming
+/// the copy into place; larger ones are patched in place.
+const REWRITE_MAX: u64 = 256 << 20;
+
+/// Write `edits` into `path` where they belong, creating a missing file and
+/// cutting it (or extending it, sparse) to `size` when known.
+fn patch_in_place(path: &Path, edits: &[(u64, Vec<u8>)], size: Option<u64>) -> io::Result<()> {
+    let mut f = std::fs::OpenOptions::new()
+        .create(true)
+        .read(true)
+        .write(true)
+        .truncate(false)
+        .open(path)?;
+    let _ = f.try_lock_exclusive();
+    for (off, data) in edits {
+        f.seek(SeekFrom::Start(*off))?;
+        f.write_all(data)?;
+    }
+    if let Some(size) = size {
+        f.set_len(size)?;
+    }
+    f.sync_all()?;
+    // unlocking happens on drop; avoid std::File::unlock (MSRV >=1.89)
+    Ok(())
+}
+
+/// Source chunks of a set as the outer pass sees them: what the first pass
+/// restored, else the file contents if they still match the manifest.
+pub(crate) struct ChunkReader<'a> {
+    pub(crate) mf: &'a Manifest,
+    pub(crate) idx_map: &'a HashMap<u64, (PathBuf, u64, u32)>,
+    pub(crate) hash_map: &'a HashMap<u64, &'a str>,
+    pub(crate) recovered: HashMap<u64, Vec<u8>>,
+    /// Files not to read (stalled I/O)
+    pub(crate) skip: &'a HashSet<PathBuf>,
+}
+
+impl ChunkReader<'_> {
+    fn chunk(&self, idx: u64) -> Option<Vec<u8>> {
+        if idx >= self.mf.total_chunks {
+            // Padding slot of the last stripe
+            return Some(vec![0u8; self.mf.chunk_size]);
+        }
+        if let Some(buf) = self.recovered.get(&idx) {
+            return Some(buf.clone());
+        }
+        let (path, off, len) = self.idx_map.get(&idx).filter(|(p, ..)| !self.skip.contains(p))?;
+        let mut buf = vec![0u8; self.mf.chunk_size];
+        let mut f = File::open(path).ok()?;
+        f.seek(SeekFrom::Start(*off)).ok()?;
+        f.read_exact(&mut buf[..*len as usize]).ok()?;
+        self.matches(idx, &buf).then_some(buf)
+    }
+
+    fn matches(&self, idx: u64, buf: &[u8]) -> bool {
+        self.hash_map.get(&idx).is_some_and(|want| self.mf.hasher().matches(buf, want))
+    }
+}
+
+/// A chunk rebuilt from outer parity: index, bytes and the volumes read.
+pub(crate) type RecoveredChunk = (u64, Vec<u8>, Vec<String>);
+
+/// Second pass for stripes inner parity could not restore: rebuild the
+/// missing members of their outer groups from the outer shards, then decode
+/// each failed stripe again. Returns the restored chunks with their sources.
+pub(crate) fn repair_with_outer(
+    layout: &OuterLayout,
+    chunks: &ChunkReader,
+    sources: &[&VolumeSource],
+    failed: &HashMap<u64, Vec<usize>>,
+) -> Result<Vec<RecoveredChunk>> {
+    let (k, m) = (layout.k, layout.m);
+    let cs = chunks.mf.chunk_size;
+    let geo = chunks.mf.geometry();
+    let total_stripes = geo.stripes;
+    let groups: HashSet<u64> = failed.keys().map(|&s| layout.group_of(s)).collect();
+    let stripes: HashSet<u64> =
+        groups.iter().flat_map(|&g| layout.stripes_of(g, total_stripes)).collect();
+    let set = chunks.mf.set_tag();
+    let inner = collect_parity_copies(sources, cs, Some(&stripes), ShardKind::Inner, set)?;
+    let outer = collect_parity_copies(sources, cs, Some(&groups), ShardKind::Outer, set)?;
+    // Critical stripes' extra shards extend the plain stripes' code
+    let widest = m + layout.critical_parity;
+    let rs =
+        RsCodec::with_field(RsField::from_ext(&chunks.mf.ext), k, widest).context("init RS")?;
+    let mut out = Vec::new();
+    let mut groups: Vec<u64> = groups.into_iter().collect();
+    groups.sort_unstable();
+    for g in groups {
+        let mut members: Vec<Vec<Option<Vec<u8>>>> = Vec::new();
+        for s in layout.stripes_of(g, total_stripes) {
+            let ms = layout.parity_of(s);
+            let mut shards: Vec<Option<Vec<u8>>> =
+                geo.slots(s).map(|idx| chunks.chunk(idx)).collect();
+            shards.resize(k + widest, None);
+            if shards[..k].iter().all(Option::is_some) {
+                // Intact stripe: its inner parity follows from the data
+                let mut bufs: Vec<Vec<u8>> =
+                    shards.into_iter().map(|b| b.unwrap_or_else(|| vec![0u8; cs])).collect();
+                let mut refs: Vec<&mut [u8]> = bufs.iter_mut().map(|b| b.as_mut_slice()).collect();
+                rs.encode(&mut refs).context("RS encode")?;
+                shards = bufs.into_iter().map(Some).collect();
+            } else {
+                for (pi, pbuf, _) in inner.variant(s, 0).unwrap_or_default() {
+                    if pi < ms {
+                        shards[k + pi] = Some(pbuf);
+                    }
+                }
+            }
+            shards.truncate(k + ms);
+            members.push(shards);
+        }
+        let mut outer_shards: Vec<Option<Vec<u8>>> = vec![None; layout.parity];
+        let mut origins = vec![format!("outer group {}", g)];
+        for (pi, pbuf, origin) in outer.variant(g, 0).unwrap_or_default() {
+            if pi < layout.parity {
+                origins.push(format!("outer[{}]@{}", pi, origin));
+                outer_shards[pi] = Some(pbuf);
+            }
+        }
+        if layout.reconstruct(&mut members, outer_shards).is_err() {
+            continue;
+        }
+        for (s, mut shards) in layout.stripes_of(g, total_stripes).zip(members) {
+            let Some(missing) = failed.get(&s) else { continue };
+            // With parity-only groups the stripe now has all its parity shards
+            shards.resize(k + widest, None);
+            if rs.reconstruct(&mut shards).is_err() {
+                continue;
+            }
+            for &i in missing {
+                let idx = geo.chunk_at(s, i);
+                if let Some(Some(buf)) = shards.get(i) {
+                    if chunks.matches(idx, buf) {
+                        out.push((idx, buf.clone(), origins.clone()));
                     }
                 }
-                let _ = f.sync_all();
-                // unlocking happens on drop; avoid std::File::unlock (MSRV >=1.89)
             }
         }
     }
-
-    // Release global lock on drop
-    Ok(RepairReport { repaired_chunks, failed_chunks })
+    Ok(out)
 }
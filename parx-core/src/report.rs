@@ -0,0 +1,69 @@
+//! Versioned envelope for the JSON reports of `verify`, `audit`, `repair`,
+//! `paritycheck`, `vol heal` and `update`. A report's own fields sit at the
+//! top level next to `schema_version` and `kind`, so consumers can tell which
+//! report they hold and whether they understand it.
+//!
+//! Within a schema version field names and meanings are stable: fields may be
+//! added, and fields marked optional may be absent, but renaming, removing or
+//! re-typing a field bumps [`SCHEMA_VERSION`].
+
+use anyhow::{bail, Result};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+/// Version of the report schema written by this build.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// A report that can be wrapped in [`Versioned`].
+pub trait Report {
+    /// Value of the `kind` field, e.g. `"verify"`.
+    const KIND: &'static str;
+}
+
+impl<T: Report> Report for &T {
+    const KIND: &'static str = T::KIND;
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Versioned<T> {
+    pub schema_version: u32,
+    pub kind: String,
+    #[serde(flatten)]
+    pub report: T,
+}
+
+impl<T: Report> Versioned<T> {
+    pub fn new(report: T) -> Self {
+        Self { schema_version: SCHEMA_VERSION, kind: T::KIND.to_string(), report }
+    }
+}
+
+impl<T: Report + DeserializeOwned> Versioned<T> {
+    /// Parse a report, refusing other kinds and newer schema versions.
+    pub fn from_json(s: &str) -> Result<Self> {
+        // The envelope is checked first so a mismatch is named as such
+        // rather than as a missing field
+        #[derive(Deserialize)]
+        struct Envelope {
+            schema_version: u32,
+            kind: String,
+        }
+        let env: Envelope = serde_json::from_str(s)?;
+        if env.kind != T::KIND {
+            bail!("expected a {} report, got {:?}", T::KIND, env.kind);
+        }
+        if env.schema_version > SCHEMA_VERSION {
+            bail!(
+                "report schema version {} is newer than this build understands ({})",
+                env.schema_version,
+                SCHEMA_VERSION
+            );
+        }
+        Ok(serde_json::from_str(s)?)
+    }
+}
+
+/// Serialize `report` in its envelope as a single JSON line.
+pub fn to_json<T: Report + Serialize>(report: &T) -> Result<String> {
+    Ok(serde_json::to_string(&Versioned::new(report))?)
+}
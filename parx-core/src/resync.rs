@@ -0,0 +1,159 @@
+//! Resynchronisation of shifted data.
+//!
+//! Inserting or deleting a few bytes in a protected file moves every later
+//! chunk off its recorded offset, so each of them fails its hash although the
+//! data is intact. `create` records an rsync-style rolling checksum ([`weak`])
+//! of every chunk. For the chunks of a file that failed, [`locate`] slides a
+//! window of the chunk's length over the whole file, updating the checksum
+//! byte by byte, and confirms each checksum hit with the chunk's BLAKE3 hash.
+//! Bytes held by chunks that verified at their recorded offset are not
+//! searched, so a chunk of repetitive data is not "found" inside its intact
+//! neighbours.
+//! `verify` then counts only the chunks found nowhere as bad, and `repair`
+//! moves the displaced ones back instead of rebuilding them from parity.
+
+use crate::manifest::ChunkRef;
+use crate::merkle::ChunkHasher;
+use anyhow::{Context, Result};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+/// Files larger than this are not searched: a pass a byte at a time over a
+/// multi-TB file would take days.
+pub const SCAN_MAX: u64 = 64 << 30;
+
+/// rsync's weak checksum: the byte sum and the position-weighted byte sum,
+/// both mod 2^16.
+#[derive(Clone, Copy, Debug)]
+struct Rolling {
+    a: u32,
+    b: u32,
+    len: u32,
+}
+
+impl Rolling {
+    fn new(data: &[u8]) -> Self {
+        let len = data.len() as u32;
+        let (mut a, mut b) = (0u32, 0u32);
+        for (i, &x) in data.iter().enumerate() {
+            a = a.wrapping_add(x as u32);
+            b = b.wrapping_add((len - i as u32).wrapping_mul(x as u32));
+        }
+        Self { a, b, len }
+    }
+
+    /// Slide the window one byte: `out` leaves at the front, `inn` enters.
+    fn roll(&mut self, out: u8, inn: u8) {
+        self.a = self.a.wrapping_sub(out as u32).wrapping_add(inn as u32);
+        self.b = self.b.wrapping_sub(self.len.wrapping_mul(out as u32)).wrapping_add(self.a);
+    }
+
+    fn value(&self) -> u32 {
+        (self.a & 0xFFFF) | (self.b << 16)
+    }
+}
+
+/// The rolling checksum of a chunk's bytes (without padding), as recorded in
+/// [`ChunkRef::weak`].
+pub fn weak(data: &[u8]) -> u32 {
+    Rolling::new(data).value()
+}
+
+/// Find `chunks` of the file at `path` at other offsets than the recorded
+/// ones, outside the `intact` chunks. Returns the offset each found chunk now
+/// starts at, keyed by its recorded offset; chunks without a rolling checksum
+/// are not looked for, nor is anything in files over [`SCAN_MAX`]. `hasher`
+/// is the set's; `tick` is called every MiB scanned.
+pub fn locate(
+    path: &Path,
+    chunks: &[&ChunkRef],
+    intact: &[&ChunkRef],
+    hasher: &ChunkHasher,
+    tick: &dyn Fn(),
+) -> Result<HashMap<u64, u64>> {
+    if std::fs::metadata(path).with_context(|| format!("stat {:?}", path))?.len() > SCAN_MAX {
+        return Ok(HashMap::new());
+    }
+    let mut taken: Vec<(u64, u64)> =
+        intact.iter().map(|c| (c.file_offset, c.file_offset + c.len as u64)).collect();
+    taken.sort_unstable();
+    // Wanted chunks by length, then by rolling checksum
+    let mut by_len: HashMap<u32, HashMap<u32, Vec<&ChunkRef>>> = HashMap::new();
+    for c in chunks.iter().filter(|c| c.len > 0) {
+        if let Some(w) = c.weak {
+            by_len.entry(c.len).or_default().entry(w).or_default().push(c);
+        }
+    }
+    let mut found = HashMap::new();
+    for (len, wanted) in by_len {
+        scan(path, len as usize, &wanted, &taken, hasher, tick, &mut found)?;
+    }
+    Ok(found)
+}
+
+/// One pass over the file with a window of `len` bytes.
+fn scan(
+    path: &Path,
+    len: usize,
+    wanted: &HashMap<u32, Vec<&ChunkRef>>,
+    taken: &[(u64, u64)],
+    hasher: &ChunkHasher,
+    tick: &dyn Fn(),
+    found: &mut HashMap<u64, u64>,
+) -> Result<()> {
+    let total: usize = wanted.values().map(Vec::len).sum();
+    let mut r = BufReader::with_capacity(
+        1 << 20,
+        File::open(path).with_context(|| format!("open {:?}", path))?,
+    );
+    // The window is a ring: its oldest byte is at `head`
+    let mut win = vec![0u8; len];
+    if r.read_exact(&mut win).is_err() {
+        return Ok(());
+    }
+    let mut roll = Rolling::new(&win);
+    let (mut head, mut pos) = (0usize, 0u64);
+    let mut flat = Vec::with_capacity(len);
+    let mut hits = 0usize;
+    let mut next = [0u8; 1];
+    // Whether the window overlaps an intact chunk
+    let overlaps = |pos: u64| {
+        let i = taken.partition_point(|&(_, end)| end <= pos);
+        taken.get(i).is_some_and(|&(start, _)| start < pos + len as u64)
+    };
+    loop {
+        if let Some(cands) = wanted.get(&roll.value()) {
+            if cands.iter().any(|c| !found.contains_key(&c.file_offset)) && !overlaps(pos) {
+                flat.clear();
+                flat.extend_from_slice(&win[head..]);
+                flat.extend_from_slice(&win[..head]);
+                let hash = hasher.hash(&flat).to_hex();
+                for c in cands.iter().filter(|c| c.hash_hex == hash.as_str()) {
+                    if let Entry::Vacant(e) = found.entry(c.file_offset) {
+                        e.insert(pos);
+                        hits += 1;
+                    }
+                }
+                if hits == total {
+                    return Ok(());
+                }
+            }
+        }
+        match r.read(&mut next) {
+            Ok(0) => return Ok(()),
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e).with_context(|| format!("read {:?}", path)),
+        }
+        roll.roll(win[head], next[0]);
+        win[head] = next[0];
+        head = (head + 1) % len;
+        pos += 1;
+        if pos % (1 << 20) == 0 {
+            tick();
+        }
+    }
+}
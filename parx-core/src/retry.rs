@@ -0,0 +1,130 @@
+//! Retries of reads and writes that fail for a moment (`--io-retries`).
+//! Network filesystems (NFS, SMB, sshfs, rclone mounts) report a dropped
+//! connection or a server restart as an I/O error on whatever call was in
+//! flight, and the same call usually works a little later. Errors that look
+//! like that ([`is_transient`]) are retried with exponential backoff; any
+//! other error (a missing file, a short read, permission denied) is final at
+//! once. Whatever still fails after the last retry is reported as such, so
+//! that it is not taken for damage.
+
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Longest wait between two attempts, however many retries are allowed.
+pub const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// How often and how patiently a failed I/O call is tried again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts after the first one (0 = fail on the first error)
+    pub retries: u32,
+    /// Wait before the first retry; doubled for each further one, up to
+    /// [`MAX_BACKOFF`]
+    pub backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy { retries: 3, backoff: Duration::from_millis(500) }
+    }
+}
+
+impl RetryPolicy {
+    pub const NONE: RetryPolicy = RetryPolicy { retries: 0, backoff: Duration::ZERO };
+
+    /// Wait before retry number `n` (0-based).
+    pub fn delay(&self, n: u32) -> Duration {
+        self.backoff.saturating_mul(1 << n.min(16)).min(MAX_BACKOFF)
+    }
+
+    /// Run `op` until it succeeds, fails with an error that is not
+    /// transient, or runs out of retries. `op` gets the attempt number, so
+    /// that it can reopen a file whose handle went stale. `tick` is called
+    /// while waiting, to keep an I/O watchdog from taking the wait for a
+    /// stall.
+    pub fn run<T>(
+        &self,
+        stats: &RetryStats,
+        tick: impl Fn(),
+        mut op: impl FnMut(u32) -> io::Result<T>,
+    ) -> io::Result<T> {
+        let mut attempt = 0;
+        loop {
+            match op(attempt) {
+                Ok(v) => {
+                    if attempt > 0 {
+                        stats.0[1].fetch_add(1, Ordering::Relaxed);
+                    }
+                    return Ok(v);
+                }
+                Err(e) if attempt < self.retries && is_transient(&e) => {
+                    stats.0[0].fetch_add(1, Ordering::Relaxed);
+                    let until = Instant::now() + self.delay(attempt);
+                    let mut now = Instant::now();
+                    while now < until {
+                        std::thread::sleep((until - now).min(Duration::from_millis(100)));
+                        tick();
+                        now = Instant::now();
+                    }
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// Counts of a run's retries, shared by its workers.
+#[derive(Clone, Default, Debug)]
+pub struct RetryStats(Arc<[AtomicU64; 2]>);
+
+impl RetryStats {
+    /// Calls tried again after a transient error
+    pub fn retries(&self) -> u64 {
+        self.0[0].load(Ordering::Relaxed)
+    }
+
+    /// Calls that succeeded on a retry
+    pub fn recovered(&self) -> u64 {
+        self.0[1].load(Ordering::Relaxed)
+    }
+}
+
+/// Whether `e` looks like a passing failure of the storage or the network
+/// behind it (timeouts, dropped connections, stale NFS handles) rather than
+/// a lasting state of the file.
+pub fn is_transient(e: &io::Error) -> bool {
+    use io::ErrorKind::*;
+    if matches!(
+        e.kind(),
+        Interrupted
+            | TimedOut
+            | WouldBlock
+            | ConnectionReset
+            | ConnectionAborted
+            | NotConnected
+            | BrokenPipe
+    ) {
+        return true;
+    }
+    // ESTALE, ENETDOWN, ENETUNREACH, ENETRESET, EHOSTDOWN, EHOSTUNREACH
+    #[cfg(target_os = "linux")]
+    let codes = [116, 100, 101, 102, 112, 113];
+    #[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd"))]
+    let codes = [70, 50, 51, 52, 64, 65];
+    // ERROR_NETNAME_DELETED, ERROR_UNEXP_NET_ERR, ERROR_SEM_TIMEOUT,
+    // ERROR_NETWORK_UNREACHABLE
+    #[cfg(windows)]
+    let codes = [64, 59, 121, 1231];
+    #[cfg(not(any(
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        windows
+    )))]
+    let codes: [i32; 0] = [];
+    e.raw_os_error().is_some_and(|c| codes.contains(&c))
+}
@@ -1,26 +1,172 @@
-use anyhow::Result;
-use reed_solomon_erasure::galois_8::ReedSolomon;
+use crate::ext::{key, ExtMap};
+use anyhow::{ensure, Result};
+use reed_solomon_erasure::{galois_16, galois_8};
+
+/// Galois field a set's inner RS code works in. GF(2^8) holds at most 256
+/// shards per stripe; larger stripes use GF(2^16), whose symbols are byte
+/// pairs, so its shards must have an even length. The field is recorded in
+/// the manifest and every volume header.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RsField {
+    #[default]
+    Gf8,
+    Gf16,
+}
+
+impl RsField {
+    /// Smallest field whose code holds `shards` shards.
+    pub fn for_shards(shards: usize) -> Self {
+        if shards > 256 {
+            RsField::Gf16
+        } else {
+            RsField::Gf8
+        }
+    }
+
+    /// Most shards (data and parity) a stripe may have in this field.
+    pub fn max_shards(self) -> usize {
+        match self {
+            RsField::Gf8 => 256,
+            RsField::Gf16 => 65536,
+        }
+    }
+
+    /// Sets written before GF(2^16) carry no key and read back as GF(2^8).
+    pub fn to_ext(self, ext: &mut ExtMap) {
+        if self == RsField::Gf16 {
+            ext.insert_u32(key::RS_FIELD, 16);
+        }
+    }
+
+    pub fn from_ext(ext: &ExtMap) -> Self {
+        match ext.get_u32(key::RS_FIELD) {
+            Some(16) => RsField::Gf16,
+            _ => RsField::Gf8,
+        }
+    }
+}
+
+enum Inner {
+    Gf8(Box<galois_8::ReedSolomon>),
+    Gf16(Box<galois_16::ReedSolomon>),
+}
 
 pub struct RsCodec {
     pub k: usize,
     pub m: usize,
-    inner: ReedSolomon,
+    inner: Inner,
 }
 
 impl RsCodec {
+    /// GF(2^8) codec, as used by outer groups and sets without a field key.
     pub fn new(k: usize, m: usize) -> Result<Self> {
-        let inner = ReedSolomon::new(k, m)?;
+        Self::with_field(RsField::Gf8, k, m)
+    }
+
+    pub fn with_field(field: RsField, k: usize, m: usize) -> Result<Self> {
+        let inner = match field {
+            RsField::Gf8 => Inner::Gf8(Box::new(galois_8::ReedSolomon::new(k, m)?)),
+            RsField::Gf16 => Inner::Gf16(Box::new(galois_16::ReedSolomon::new(k, m)?)),
+        };
         Ok(Self { k, m, inner })
     }
 
+    pub fn field(&self) -> RsField {
+        match self.inner {
+            Inner::Gf8(_) => RsField::Gf8,
+            Inner::Gf16(_) => RsField::Gf16,
+        }
+    }
+
+    /// Parity rows of the GF(2^8) encoding matrix, `m` rows of `k`
+    /// coefficients: parity `p` is the sum over `j` of `rows[p][j] * data[j]`.
+    /// Device kernels use it to produce the same parity as [`Self::encode`].
+    pub fn parity_matrix(&self) -> Result<Vec<Vec<u8>>> {
+        ensure!(self.field() == RsField::Gf8, "parity matrix is only available for GF(2^8)");
+        // With data shard j the unit vector e_j, byte j of parity p is (p, j)
+        let mut shards: Vec<Vec<u8>> = (0..self.k + self.m)
+            .map(|i| {
+                let mut v = vec![0u8; self.k];
+                if i < self.k {
+                    v[i] = 1;
+                }
+                v
+            })
+            .collect();
+        let mut refs: Vec<&mut [u8]> = shards.iter_mut().map(|v| v.as_mut_slice()).collect();
+        self.encode(&mut refs)?;
+        Ok(shards.split_off(self.k))
+    }
+
     pub fn encode(&self, shards: &mut [&mut [u8]]) -> Result<()> {
-        self.inner.encode(shards)?;
+        match &self.inner {
+            Inner::Gf8(rs) => rs.encode(shards)?,
+            Inner::Gf16(rs) => {
+                ensure!(
+                    shards.iter().all(|s| s.len() % 2 == 0),
+                    "GF(2^16) shards need an even length"
+                );
+                let mut wide: Vec<Vec<[u8; 2]>> = shards.iter().map(|s| widen(s)).collect();
+                rs.encode(&mut wide)?;
+                for (s, w) in shards.iter_mut().zip(&wide).skip(self.k) {
+                    narrow_into(w, s);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Parity of `data` (`k` shards) into `parity` (`m` shards), leaving
+    /// the data where it is.
+    pub fn encode_sep(&self, data: &[&[u8]], parity: &mut [&mut [u8]]) -> Result<()> {
+        match &self.inner {
+            Inner::Gf8(rs) => rs.encode_sep(data, parity)?,
+            Inner::Gf16(rs) => {
+                ensure!(
+                    data.iter().all(|s| s.len() % 2 == 0),
+                    "GF(2^16) shards need an even length"
+                );
+                let wide: Vec<Vec<[u8; 2]>> = data.iter().map(|s| widen(s)).collect();
+                let mut out: Vec<Vec<[u8; 2]>> =
+                    parity.iter().map(|s| vec![[0u8; 2]; s.len() / 2]).collect();
+                rs.encode_sep(&wide, &mut out)?;
+                for (s, w) in parity.iter_mut().zip(&out) {
+                    narrow_into(w, s);
+                }
+            }
+        }
         Ok(())
     }
 
     // Note: reconstruct expects Option<Vec<u8>> buffers
     pub fn reconstruct(&self, shards: &mut [Option<Vec<u8>>]) -> Result<()> {
-        self.inner.reconstruct(shards)?;
+        match &self.inner {
+            Inner::Gf8(rs) => rs.reconstruct(shards)?,
+            Inner::Gf16(rs) => {
+                let len = shards.iter().flatten().map(|s| s.len()).next().unwrap_or(0);
+                ensure!(len % 2 == 0, "GF(2^16) shards need an even length");
+                let mut wide: Vec<Option<Vec<[u8; 2]>>> =
+                    shards.iter().map(|s| s.as_deref().map(widen)).collect();
+                rs.reconstruct(&mut wide)?;
+                for (s, w) in shards.iter_mut().zip(wide) {
+                    if s.is_none() {
+                        let mut buf = vec![0u8; len];
+                        narrow_into(&w.unwrap_or_default(), &mut buf);
+                        *s = Some(buf);
+                    }
+                }
+            }
+        }
         Ok(())
     }
 }
+
+fn widen(bytes: &[u8]) -> Vec<[u8; 2]> {
+    bytes.chunks_exact(2).map(|c| [c[0], c[1]]).collect()
+}
+
+fn narrow_into(symbols: &[[u8; 2]], out: &mut [u8]) {
+    for (o, b) in out.iter_mut().zip(symbols.iter().flatten()) {
+        *o = *b;
+    }
+}
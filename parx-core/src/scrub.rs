@@ -0,0 +1,145 @@
+//! Filesystem scrub reports as repair input. ZFS (`zpool status -v`) and
+//! btrfs (kernel log lines written during `btrfs scrub`) already know which
+//! files failed their checksums; mapping those to chunks lets `repair` check
+//! and rebuild just the affected regions instead of hashing the whole tree.
+
+use crate::manifest::Manifest;
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::path::{Component, Path};
+
+/// A damaged file named by a scrub report.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ScrubError {
+    /// Path as reported: absolute (ZFS), relative to the dataset or subvolume
+    pub path: String,
+    /// Damaged byte range `(offset, len)` within the file; `None` = whole file
+    pub range: Option<(u64, u64)>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ScrubReport {
+    pub errors: Vec<ScrubError>,
+    /// Entries that name no file (metadata, deleted objects, inode-only lines)
+    pub unresolved: Vec<String>,
+}
+
+/// Chunks to check for a set of scrub errors.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ScrubTargets {
+    pub chunks: BTreeSet<u64>,
+    /// Reported paths that match no protected file
+    pub unmatched: Vec<String>,
+}
+
+/// Parse `zpool status -v` output and/or btrfs kernel log lines (`dmesg`,
+/// `journalctl -k`); both may appear in the same text.
+pub fn parse_report(text: &str) -> ScrubReport {
+    let mut rep = ScrubReport::default();
+    let mut in_zfs_list = false;
+    for line in text.lines() {
+        if line.contains("Permanent errors have been detected") {
+            in_zfs_list = true;
+            continue;
+        }
+        if in_zfs_list {
+            let entry = line.trim();
+            if entry.is_empty() {
+                continue;
+            }
+            if !line.starts_with(char::is_whitespace) {
+                in_zfs_list = false;
+            } else {
+                zfs_entry(entry, &mut rep);
+                continue;
+            }
+        }
+        if line.contains("BTRFS")
+            && (line.contains("checksum error") || line.contains("csum failed"))
+        {
+            btrfs_line(line, &mut rep);
+        }
+    }
+    rep.errors.dedup();
+    rep.unresolved.dedup();
+    rep
+}
+
+/// `/mnt/pool/file`, `pool/fs:/file` (dataset not mounted) or an object id
+/// such as `<metadata>:<0x0>` / `pool/fs:<0x1f>` that maps to no path.
+fn zfs_entry(entry: &str, rep: &mut ScrubReport) {
+    if entry.starts_with('<') || entry.contains(":<0x") {
+        rep.unresolved.push(entry.to_string());
+    } else if let Some((_, path)) = entry.split_once(":/").filter(|_| !entry.starts_with('/')) {
+        rep.errors.push(ScrubError { path: format!("/{}", path), range: None });
+    } else {
+        rep.errors.push(ScrubError { path: entry.to_string(), range: None });
+    }
+}
+
+/// `... checksum error at logical L on dev D, physical P, root R, inode I,
+/// offset O, length N, links 1 (path: dir/file)`; lines without a path
+/// (metadata, `csum failed ... ino I off O`) cannot be mapped.
+fn btrfs_line(line: &str, rep: &mut ScrubReport) {
+    let path = line
+        .find("(path: ")
+        .map(|i| &line[i + 7..])
+        .and_then(|rest| rest.rfind(')').map(|j| &rest[..j]));
+    let Some(path) = path else {
+        rep.unresolved.push(line.trim().to_string());
+        return;
+    };
+    let range = match (number_after(line, ", offset "), number_after(line, ", length ")) {
+        (Some(off), Some(len)) => Some((off, len)),
+        _ => None,
+    };
+    rep.errors.push(ScrubError { path: path.to_string(), range });
+}
+
+fn number_after(line: &str, key: &str) -> Option<u64> {
+    let rest = &line[line.find(key)? + key.len()..];
+    let end = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+    rest[..end].parse().ok()
+}
+
+fn components(p: &str) -> Vec<&str> {
+    Path::new(p)
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(s) => s.to_str(),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Map scrub errors to the chunks they touch. Reported paths carry the mount
+/// point or subvolume prefix, so a file matches when its manifest path is a
+/// trailing run of the reported path's components; the longest match wins.
+pub fn map_to_chunks(mf: &Manifest, errors: &[ScrubError]) -> ScrubTargets {
+    let files: Vec<Vec<&str>> = mf.files.iter().map(|f| components(&f.rel_path)).collect();
+    let mut out = ScrubTargets::default();
+    for e in errors {
+        let hit = components(&e.path);
+        let best = files
+            .iter()
+            .enumerate()
+            .filter(|(_, rel)| !rel.is_empty() && hit.ends_with(rel))
+            .max_by_key(|(_, rel)| rel.len());
+        let Some((i, _)) = best else {
+            out.unmatched.push(e.path.clone());
+            continue;
+        };
+        for ch in &mf.files[i].chunks {
+            let touched = match e.range {
+                None => true,
+                Some((off, len)) => {
+                    off < ch.file_offset + ch.len as u64 && ch.file_offset < off + len.max(1)
+                }
+            };
+            if touched {
+                out.chunks.insert(ch.idx);
+            }
+        }
+    }
+    out
+}
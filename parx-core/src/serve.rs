@@ -0,0 +1,243 @@
+//! Integrity proxy (`parx serve`): applications ask over a local socket for
+//! a byte range of a protected file and get it back only after the chunks
+//! covering it matched their manifest hashes, repaired from parity first if
+//! they did not. This gives end-to-end integrity without filesystem support.
+//!
+//! Protocol: one request per line, `{"path":"data/a.bin","offset":0,"len":4096}`
+//! (paths as recorded in the manifest). Each reply is one JSON line,
+//! `{"ok":true,"len":N,"repaired":R}` followed by exactly N raw bytes, or
+//! `{"ok":false,"error":"..."}`. A connection may carry any number of requests.
+
+use crate::manifest::{self, Manifest};
+use crate::path_safety::{validate_path, PathPolicy};
+use crate::query::normalize;
+use crate::repair::{self, RepairOptions};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{BufRead, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Largest range one request may ask for.
+pub const MAX_READ: u64 = 64 << 20;
+
+/// Longest request line, newline included; a connection sending a longer
+/// one gets an error reply and is closed.
+pub const MAX_REQUEST_LINE: usize = 64 << 10;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ReadRequest {
+    pub path: String,
+    pub offset: u64,
+    pub len: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReadReply {
+    pub ok: bool,
+    /// Bytes following this line
+    #[serde(default)]
+    pub len: u64,
+    /// Chunks of the range that were repaired from parity first
+    #[serde(default)]
+    pub repaired: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ServeOptions {
+    pub policy: PathPolicy,
+    /// Repair damaged chunks before answering; otherwise such reads fail
+    pub repair: bool,
+}
+
+impl Default for ServeOptions {
+    fn default() -> Self {
+        Self { policy: PathPolicy::default(), repair: true }
+    }
+}
+
+/// Verified reads from one protected set.
+pub struct IntegrityProxy {
+    manifest_path: PathBuf,
+    root: PathBuf,
+    mf: Manifest,
+    /// Normalized rel path -> position in `mf.files`
+    files: HashMap<String, usize>,
+    opts: ServeOptions,
+    /// Repairs rewrite files; one at a time
+    repair_lock: Mutex<()>,
+}
+
+impl IntegrityProxy {
+    pub fn open(manifest_path: &Path, root: &Path, opts: ServeOptions) -> Result<Self> {
+        let (mf, recovery) = manifest::load(manifest_path)?;
+        if recovery.is_some() {
+            bail!("manifest damaged; run `parx recover-manifest` before serving");
+        }
+        mf.require_plain_hashes("serve")?;
+        let files = mf.files.iter().enumerate().map(|(i, f)| (normalize(&f.rel_path), i)).collect();
+        Ok(Self {
+            manifest_path: manifest_path.to_path_buf(),
+            root: root.to_path_buf(),
+            mf,
+            files,
+            opts,
+            repair_lock: Mutex::new(()),
+        })
+    }
+
+    /// Bytes `offset..offset + len` of `path` (cut at the end of the file),
+    /// with the number of chunks that had to be repaired first.
+    pub fn read(&self, path: &str, offset: u64, len: u64) -> Result<(Vec<u8>, u64)> {
+        let Some(&i) = self.files.get(&normalize(path)) else {
+            bail!("{:?} is not in the protected set", path);
+        };
+        let fe = &self.mf.files[i];
+        if len > MAX_READ {
+            bail!("read of {} bytes exceeds the limit of {}", len, MAX_READ);
+        }
+        if offset > fe.size {
+            bail!("offset {} is past the end of {:?} ({} bytes)", offset, path, fe.size);
+        }
+        let end = offset.saturating_add(len).min(fe.size);
+        let covering: Vec<_> = fe
+            .chunks
+            .iter()
+            .filter(|c| c.file_offset < end && c.file_offset + c.len as u64 > offset)
+            .collect();
+        let full = validate_path(&self.root, Path::new(&fe.rel_path), self.opts.policy)
+            .with_context(|| format!("validate path {:?}", fe.rel_path))?;
+
+        // A missing or short file just fails the hashes of the chunks it lost.
+        // The answer is built only from bytes that were hashed; it is not
+        // used unless every covering chunk matched.
+        let read_checked = || -> Result<(Vec<u8>, BTreeSet<u64>)> {
+            let mut out = vec![0u8; (end - offset) as usize];
+            let mut f = File::open(&full).ok();
+            let mut bad = BTreeSet::new();
+            for ch in &covering {
+                let mut buf = vec![0u8; ch.len as usize];
+                let ok = f.as_mut().is_some_and(|f| {
+                    f.seek(SeekFrom::Start(ch.file_offset)).is_ok()
+                        && f.read_exact(&mut buf).is_ok()
+                }) && self.mf.hasher().matches(&buf, &ch.hash_hex);
+                if !ok {
+                    bad.insert(ch.idx);
+                    continue;
+                }
+                let from = ch.file_offset.max(offset);
+                let to = (ch.file_offset + ch.len as u64).min(end);
+                out[(from - offset) as usize..(to - offset) as usize].copy_from_slice(
+                    &buf[(from - ch.file_offset) as usize..(to - ch.file_offset) as usize],
+                );
+            }
+            Ok((out, bad))
+        };
+
+        let (out, bad) = read_checked()?;
+        if bad.is_empty() {
+            return Ok((out, 0));
+        }
+        if !self.opts.repair {
+            bail!("{} chunk(s) of {:?} in this range are damaged", bad.len(), path);
+        }
+        let _guard = self.repair_lock.lock().unwrap_or_else(|e| e.into_inner());
+        let ropts = RepairOptions { only_chunks: Some(bad.clone()), ..Default::default() };
+        repair::repair_with_options(&self.manifest_path, &self.root, self.opts.policy, &ropts)?;
+        let (out, still) = read_checked()?;
+        if !still.is_empty() {
+            bail!("{} chunk(s) of {:?} in this range are damaged beyond repair", still.len(), path);
+        }
+        Ok((out, bad.len() as u64))
+    }
+
+    /// Answer the requests on one connection until it closes.
+    pub fn handle(&self, mut input: impl BufRead, mut output: impl Write) -> Result<()> {
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            // Bounded, so that a client cannot make the proxy buffer at will
+            let n = (&mut input).take(MAX_REQUEST_LINE as u64).read_until(b'\n', &mut line)?;
+            if n == 0 {
+                break;
+            }
+            if n == MAX_REQUEST_LINE && line.last() != Some(&b'\n') {
+                let reply = ReadReply {
+                    error: Some(format!("request longer than {} bytes", MAX_REQUEST_LINE)),
+                    ..Default::default()
+                };
+                writeln!(output, "{}", serde_json::to_string(&reply)?)?;
+                output.flush()?;
+                break;
+            }
+            if line.iter().all(u8::is_ascii_whitespace) {
+                continue;
+            }
+            let res = serde_json::from_slice::<ReadRequest>(&line)
+                .context("bad request")
+                .and_then(|r| self.read(&r.path, r.offset, r.len));
+            let (reply, data) = match res {
+                Ok((data, repaired)) => {
+                    (ReadReply { ok: true, len: data.len() as u64, repaired, error: None }, data)
+                }
+                Err(e) => (
+                    ReadReply { error: Some(format!("{:#}", e)), ..Default::default() },
+                    Vec::new(),
+                ),
+            };
+            writeln!(output, "{}", serde_json::to_string(&reply)?)?;
+            output.write_all(&data)?;
+            output.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Listen on the Unix socket `socket` (replacing a stale one) and serve
+    /// each connection on its own thread. Runs until the listener fails.
+    /// Anything at `socket` other than a socket is left alone.
+    #[cfg(unix)]
+    pub fn serve(self: std::sync::Arc<Self>, socket: &Path) -> Result<()> {
+        use std::os::unix::fs::FileTypeExt;
+        use std::os::unix::net::UnixListener;
+        match std::fs::symlink_metadata(socket) {
+            Ok(m) if m.file_type().is_socket() => std::fs::remove_file(socket)
+                .with_context(|| format!("remove stale {:?}", socket))?,
+            Ok(_) => bail!("{:?} exists and is not a socket; refusing to replace it", socket),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e).with_context(|| format!("stat {:?}", socket)),
+        }
+        let listener = UnixListener::bind(socket).with_context(|| format!("bind {:?}", socket))?;
+        for conn in listener.incoming() {
+            let conn = conn?;
+            let proxy = self.clone();
+            std::thread::spawn(move || {
+                let Ok(input) = conn.try_clone() else { return };
+                let _ = proxy.handle(std::io::BufReader::new(input), conn);
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Client side: ask the proxy on `socket` for a verified range.
+#[cfg(unix)]
+pub fn request(socket: &Path, path: &str, offset: u64, len: u64) -> Result<Vec<u8>> {
+    use std::os::unix::net::UnixStream;
+    let mut conn = UnixStream::connect(socket).with_context(|| format!("connect {:?}", socket))?;
+    let req = ReadRequest { path: path.to_string(), offset, len };
+    writeln!(conn, "{}", serde_json::to_string(&req)?)?;
+    let mut input = std::io::BufReader::new(conn);
+    let mut line = String::new();
+    input.read_line(&mut line)?;
+    let reply: ReadReply = serde_json::from_str(&line).context("bad reply")?;
+    if !reply.ok {
+        bail!("{}", reply.error.unwrap_or_else(|| "read failed".to_string()));
+    }
+    let mut data = vec![0u8; reply.len as usize];
+    input.read_exact(&mut data)?;
+    Ok(data)
+}
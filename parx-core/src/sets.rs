@@ -0,0 +1,151 @@
+//! Named parity sets: several independent sets under one parity dir, each in
+//! `sets/<name>/` with its own manifest, volumes and parameters (`--set NAME`
+//! on create, verify and repair). [`list`] summarizes them for `parx sets
+//! list` from their manifests and volume indices, without reading the data.
+
+use crate::manifest::{self, MANIFEST_JSON};
+use crate::verify::{parity_shortfall, ParityShortfall};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Directory of the parity dir holding the named sets.
+pub const SETS_DIR: &str = "sets";
+
+/// Refuse names that are not a single plain path component.
+pub fn check_name(name: &str) -> Result<()> {
+    let ok = |c: char| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.');
+    if name.is_empty() || name.len() > 64 || name.starts_with('.') || !name.chars().all(ok) {
+        bail!("invalid set name {:?}: use up to 64 letters, digits, '-', '_' or '.', not starting with '.'", name);
+    }
+    Ok(())
+}
+
+/// Where the set `name` lives under `parity_dir`.
+pub fn dir_of(parity_dir: &Path, name: &str) -> Result<PathBuf> {
+    check_name(name)?;
+    Ok(parity_dir.join(SETS_DIR).join(name))
+}
+
+/// The manifest of the set `name` under `parity_dir`.
+pub fn manifest_of(parity_dir: &Path, name: &str) -> Result<PathBuf> {
+    let path = dir_of(parity_dir, name)?.join(MANIFEST_JSON);
+    if !path.exists() {
+        bail!("no set named {:?} in {:?} (see `parx sets list`)", name, parity_dir);
+    }
+    Ok(path)
+}
+
+/// State of a set's parity, going by its manifest and volume indices.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SetHealth {
+    /// Every stripe holds all the parity it was made with
+    Ok,
+    /// Some stripes lost parity shards but keep at least one
+    Degraded,
+    /// Some stripes have no parity left
+    Unprotected,
+    /// The manifest does not load
+    Unreadable,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SetSummary {
+    pub name: String,
+    pub path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub set_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_utc: Option<String>,
+    pub files: u64,
+    /// Bytes of data the set protects
+    pub data_bytes: u64,
+    /// Bytes on disk in the set's dir: volumes, manifests and the rest
+    pub parity_bytes: u64,
+    pub volumes: u64,
+    pub health: SetHealth,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parity_shortfall: Option<ParityShortfall>,
+    /// Why the manifest did not load
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SetsReport {
+    pub parity_dir: String,
+    pub sets: Vec<SetSummary>,
+}
+
+impl crate::report::Report for SetsReport {
+    const KIND: &'static str = "sets";
+}
+
+/// Every named set under `parity_dir`, by name. A dir without `sets/`
+/// holds none.
+pub fn list(parity_dir: &Path) -> Result<SetsReport> {
+    let base = parity_dir.join(SETS_DIR);
+    let mut sets = Vec::new();
+    if base.is_dir() {
+        for ent in std::fs::read_dir(&base).with_context(|| format!("read_dir {:?}", base))? {
+            let ent = ent?;
+            let name = ent.file_name().to_string_lossy().into_owned();
+            if !ent.file_type()?.is_dir() || check_name(&name).is_err() {
+                continue;
+            }
+            if ent.path().join(MANIFEST_JSON).exists() {
+                sets.push(summarize(&name, &ent.path()));
+            }
+        }
+    }
+    sets.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(SetsReport { parity_dir: parity_dir.display().to_string(), sets })
+}
+
+fn summarize(name: &str, dir: &Path) -> SetSummary {
+    let parity_bytes = std::fs::read_dir(dir)
+        .map(|rd| {
+            rd.filter_map(|e| e.ok()?.metadata().ok())
+                .filter(|m| m.is_file())
+                .map(|m| m.len())
+                .sum()
+        })
+        .unwrap_or(0);
+    let mut s = SetSummary {
+        name: name.to_string(),
+        path: dir.display().to_string(),
+        set_id: None,
+        label: None,
+        created_utc: None,
+        files: 0,
+        data_bytes: 0,
+        parity_bytes,
+        volumes: 0,
+        health: SetHealth::Unreadable,
+        parity_shortfall: None,
+        error: None,
+    };
+    let mf = match manifest::load(&dir.join(MANIFEST_JSON)) {
+        Ok((mf, _)) => mf,
+        Err(e) => {
+            s.error = Some(format!("{:#}", e));
+            return s;
+        }
+    };
+    s.set_id = mf.set_id().map(|id| id.to_string());
+    s.label = mf.info.label.clone();
+    s.created_utc = Some(mf.created_utc.clone());
+    s.files = mf.files.len() as u64;
+    s.data_bytes = mf.total_bytes;
+    s.volumes = mf.volumes as u64;
+    s.parity_shortfall = parity_shortfall(&mf, dir);
+    s.health = match &s.parity_shortfall {
+        None => SetHealth::Ok,
+        Some(short) if short.stripes_unprotected > 0 => SetHealth::Unprotected,
+        Some(_) => SetHealth::Degraded,
+    };
+    s
+}
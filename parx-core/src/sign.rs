@@ -0,0 +1,140 @@
+use crate::ext::key;
+use crate::hex::{hex, unhex};
+use crate::manifest::Manifest;
+use anyhow::{bail, Context, Result};
+use ed25519_dalek::pkcs8::{DecodePrivateKey, DecodePublicKey, EncodePrivateKey, EncodePublicKey};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Detached ed25519 signature as stored in ParXive metadata files.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SignatureBlock {
+    pub alg: String,
+    pub public_key_hex: String,
+    pub sig_hex: String,
+}
+
+/// Load a PKCS#8 PEM private key (`openssl genpkey -algorithm ed25519`).
+pub fn load_signing_key(path: &Path) -> Result<SigningKey> {
+    let pem = std::fs::read_to_string(path).with_context(|| format!("read key {:?}", path))?;
+    SigningKey::from_pkcs8_pem(&pem)
+        .map_err(|e| anyhow::anyhow!("parse private key {:?}: {}", path, e))
+}
+
+/// Load a public key from SPKI PEM; a private key file is also accepted.
+pub fn load_verifying_key(path: &Path) -> Result<VerifyingKey> {
+    let pem = std::fs::read_to_string(path).with_context(|| format!("read key {:?}", path))?;
+    if let Ok(vk) = VerifyingKey::from_public_key_pem(&pem) {
+        return Ok(vk);
+    }
+    match SigningKey::from_pkcs8_pem(&pem) {
+        Ok(sk) => Ok(sk.verifying_key()),
+        Err(e) => bail!("parse public key {:?}: {}", path, e),
+    }
+}
+
+/// Generate a fresh keypair and write `<priv_path>` (PKCS#8) and `<pub_path>` (SPKI).
+pub fn generate_keypair(priv_path: &Path, pub_path: &Path) -> Result<VerifyingKey> {
+    let mut seed = [0u8; 32];
+    getrandom::getrandom(&mut seed).map_err(|e| anyhow::anyhow!("getrandom: {}", e))?;
+    let sk = SigningKey::from_bytes(&seed);
+    let priv_pem = sk
+        .to_pkcs8_pem(Default::default())
+        .map_err(|e| anyhow::anyhow!("encode private key: {}", e))?;
+    let pub_pem = sk
+        .verifying_key()
+        .to_public_key_pem(Default::default())
+        .map_err(|e| anyhow::anyhow!("encode public key: {}", e))?;
+    std::fs::write(priv_path, priv_pem.as_bytes())
+        .with_context(|| format!("write {:?}", priv_path))?;
+    std::fs::write(pub_path, pub_pem.as_bytes())
+        .with_context(|| format!("write {:?}", pub_path))?;
+    Ok(sk.verifying_key())
+}
+
+pub fn sign_bytes(sk: &SigningKey, msg: &[u8]) -> SignatureBlock {
+    let sig = sk.sign(msg);
+    SignatureBlock {
+        alg: "ed25519".to_string(),
+        public_key_hex: hex(sk.verifying_key().as_bytes()),
+        sig_hex: hex(&sig.to_bytes()),
+    }
+}
+
+/// Check `block` over `msg`. When `expected` is given the signer must match it.
+pub fn verify_bytes(
+    block: &SignatureBlock,
+    msg: &[u8],
+    expected: Option<&VerifyingKey>,
+) -> Result<VerifyingKey> {
+    if block.alg != "ed25519" {
+        bail!("unsupported signature algorithm {:?}", block.alg);
+    }
+    let pk: [u8; 32] = unhex(&block.public_key_hex)?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("public key must be 32 bytes"))?;
+    let vk = VerifyingKey::from_bytes(&pk).context("decode public key")?;
+    if let Some(want) = expected {
+        if want.as_bytes() != vk.as_bytes() {
+            bail!("signed by an unexpected key ({})", fingerprint(&vk));
+        }
+    }
+    let sig: [u8; 64] = unhex(&block.sig_hex)?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("signature must be 64 bytes"))?;
+    vk.verify(msg, &Signature::from_bytes(&sig)).context("signature check failed")?;
+    Ok(vk)
+}
+
+/// Bytes a manifest signature covers (see [`crate::manifest::signing_form`]):
+/// as read from manifest.json for a manifest loaded from one, else as this
+/// version writes `mf`.
+pub fn manifest_signing_bytes(mf: &Manifest) -> Result<Vec<u8>> {
+    match &mf.signed_form {
+        Some(form) => Ok(form.clone()),
+        None => crate::manifest::signing_form(serde_json::to_value(mf)?),
+    }
+}
+
+/// Embed a signature over `mf` in its extension map, replacing any old one.
+pub fn sign_manifest(mf: &mut Manifest, sk: &SigningKey) -> Result<()> {
+    // Over the manifest as it is now, not as it was read
+    mf.signed_form = None;
+    let sig = sk.sign(&manifest_signing_bytes(mf)?);
+    let mut value = sk.verifying_key().as_bytes().to_vec();
+    value.extend_from_slice(&sig.to_bytes());
+    mf.ext.insert(key::MANIFEST_SIG, value);
+    Ok(())
+}
+
+/// The key that signed `mf`, or `None` for an unsigned manifest. Fails when
+/// the signature does not match the manifest or, with `expected`, was made
+/// by another key.
+pub fn verify_manifest(
+    mf: &Manifest,
+    expected: Option<&VerifyingKey>,
+) -> Result<Option<VerifyingKey>> {
+    let Some(value) = mf.ext.get(key::MANIFEST_SIG) else {
+        if expected.is_some() {
+            bail!("manifest is not signed");
+        }
+        return Ok(None);
+    };
+    if value.len() != 32 + 64 {
+        bail!("manifest signature has {} bytes, expected 96", value.len());
+    }
+    let block = SignatureBlock {
+        alg: "ed25519".to_string(),
+        public_key_hex: hex(&value[..32]),
+        sig_hex: hex(&value[32..]),
+    };
+    let vk = verify_bytes(&block, &manifest_signing_bytes(mf)?, expected)
+        .context("manifest signature")?;
+    Ok(Some(vk))
+}
+
+/// Short, human-comparable key id (first 16 hex chars of BLAKE3 over the key).
+pub fn fingerprint(vk: &VerifyingKey) -> String {
+    blake3::hash(vk.as_bytes()).to_hex()[..16].to_string()
+}
@@ -0,0 +1,233 @@
+use crate::path_safety::{validate_path, PathPolicy};
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+#[cfg(feature = "http")]
+use std::time::Duration;
+
+/// Random-access, read-only source of dataset bytes addressed by manifest rel_path.
+pub trait DataSource: Send + Sync {
+    /// Fill `buf` with the bytes of `rel_path` starting at `offset`.
+    fn read_at(&self, rel_path: &str, offset: u64, buf: &mut [u8]) -> Result<()>;
+    /// Size in bytes of `rel_path`.
+    fn len(&self, rel_path: &str) -> Result<u64>;
+    /// Fill each buffer from its offset in `rel_path`, one result per
+    /// request. Sources that keep several reads in flight override it.
+    fn read_many(&self, rel_path: &str, reqs: &mut [(u64, &mut [u8])]) -> Vec<Result<()>> {
+        reqs.iter_mut().map(|(off, buf)| self.read_at(rel_path, *off, buf)).collect()
+    }
+    /// Short human-readable description (used in error context).
+    fn describe(&self) -> String;
+    /// Expected cost of reads from this source; planners read cheap sources first.
+    fn cost(&self) -> ReadCost {
+        ReadCost::LOCAL
+    }
+}
+
+/// Coarse class of a source's read cost, cheapest first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CostClass {
+    /// Local disk
+    Local,
+    /// Network share or other nearby but slower device
+    Lan,
+    /// WAN / object storage; slow and possibly billed per request
+    Remote,
+}
+
+/// Read-cost hint: the class orders sources, the latency breaks ties within a class.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub struct ReadCost {
+    pub class: CostClass,
+    pub latency_ms: u32,
+}
+
+impl ReadCost {
+    pub const LOCAL: ReadCost = ReadCost { class: CostClass::Local, latency_ms: 0 };
+    pub const LAN: ReadCost = ReadCost { class: CostClass::Lan, latency_ms: 5 };
+    pub const REMOTE: ReadCost = ReadCost { class: CostClass::Remote, latency_ms: 50 };
+
+    /// Parse `local`, `lan` or `remote`, optionally with a latency: `remote:200`.
+    pub fn parse(s: &str) -> Result<Self> {
+        let (class, latency) = match s.split_once(':') {
+            Some((c, l)) => (c, Some(l)),
+            None => (s, None),
+        };
+        let mut cost = match class.trim().to_ascii_lowercase().as_str() {
+            "local" => Self::LOCAL,
+            "lan" => Self::LAN,
+            "remote" => Self::REMOTE,
+            other => bail!("unknown cost class {:?} (expected local, lan or remote)", other),
+        };
+        if let Some(l) = latency {
+            cost.latency_ms =
+                l.trim().parse().with_context(|| format!("latency {:?} (milliseconds)", l))?;
+        }
+        Ok(cost)
+    }
+}
+
+/// Files under a local root, with the usual path policy applied.
+pub struct LocalSource {
+    pub root: PathBuf,
+    pub policy: PathPolicy,
+    cost: ReadCost,
+}
+
+impl LocalSource {
+    pub fn new(root: &Path, policy: PathPolicy) -> Self {
+        Self { root: root.to_path_buf(), policy, cost: ReadCost::LOCAL }
+    }
+
+    /// Override the cost hint, e.g. for a mounted network share.
+    pub fn with_cost(mut self, cost: ReadCost) -> Self {
+        self.cost = cost;
+        self
+    }
+
+    fn path(&self, rel_path: &str) -> Result<PathBuf> {
+        validate_path(&self.root, Path::new(rel_path), self.policy)
+            .with_context(|| format!("validate path {:?}", rel_path))
+    }
+}
+
+impl DataSource for LocalSource {
+    fn read_at(&self, rel_path: &str, offset: u64, buf: &mut [u8]) -> Result<()> {
+        let path = self.path(rel_path)?;
+        let mut f = File::open(&path).with_context(|| format!("open {:?}", path))?;
+        f.seek(SeekFrom::Start(offset))?;
+        f.read_exact(buf)?;
+        Ok(())
+    }
+
+    /// One open, then the reads as a batch (see [`crate::uring`]).
+    fn read_many(&self, rel_path: &str, reqs: &mut [(u64, &mut [u8])]) -> Vec<Result<()>> {
+        let opened = self
+            .path(rel_path)
+            .and_then(|path| File::open(&path).with_context(|| format!("open {:?}", path)));
+        match opened {
+            Ok(f) => crate::uring::read_at_many(&f, reqs)
+                .into_iter()
+                .map(|r| r.with_context(|| format!("read {:?}", rel_path)))
+                .collect(),
+            Err(e) => reqs.iter().map(|_| Err(anyhow::anyhow!("{:#}", e))).collect(),
+        }
+    }
+
+    fn len(&self, rel_path: &str) -> Result<u64> {
+        let path = self.path(rel_path)?;
+        Ok(std::fs::metadata(&path).with_context(|| format!("stat {:?}", path))?.len())
+    }
+
+    fn describe(&self) -> String {
+        self.root.to_string_lossy().to_string()
+    }
+
+    fn cost(&self) -> ReadCost {
+        self.cost
+    }
+}
+
+/// Files published under an HTTP(S) URL prefix, fetched with range requests.
+#[cfg(feature = "http")]
+pub struct HttpSource {
+    base: String,
+    agent: ureq::Agent,
+    cost: ReadCost,
+}
+
+#[cfg(feature = "http")]
+impl HttpSource {
+    pub fn new(base_url: &str) -> Result<Self> {
+        if !(base_url.starts_with("http://") || base_url.starts_with("https://")) {
+            bail!("unsupported URL scheme (expected http:// or https://): {}", base_url);
+        }
+        let agent = ureq::AgentBuilder::new()
+            .timeout_connect(Duration::from_secs(30))
+            .timeout_read(Duration::from_secs(120))
+            .build();
+        Ok(Self { base: base_url.trim_end_matches('/').to_string(), agent, cost: ReadCost::REMOTE })
+    }
+
+    /// Override the cost hint (e.g. `ReadCost::LAN` for a server on the local network).
+    pub fn with_cost(mut self, cost: ReadCost) -> Self {
+        self.cost = cost;
+        self
+    }
+
+    /// Full URL for a manifest rel_path (segments percent-encoded).
+    pub fn url_for(&self, rel_path: &str) -> Result<String> {
+        let rel = Path::new(rel_path);
+        if rel.is_absolute()
+            || rel.components().any(|c| matches!(c, std::path::Component::ParentDir))
+        {
+            bail!("unsafe relative path for remote fetch: {:?}", rel_path);
+        }
+        let segs: Vec<String> =
+            rel_path.split(['/', '\\']).filter(|s| !s.is_empty()).map(percent_encode).collect();
+        Ok(format!("{}/{}", self.base, segs.join("/")))
+    }
+}
+
+#[cfg(feature = "http")]
+impl DataSource for HttpSource {
+    fn read_at(&self, rel_path: &str, offset: u64, buf: &mut [u8]) -> Result<()> {
+        if buf.is_empty() {
+            return Ok(());
+        }
+        let url = self.url_for(rel_path)?;
+        let range = format!("bytes={}-{}", offset, offset + buf.len() as u64 - 1);
+        let resp = match self.agent.get(&url).set("Range", &range).call() {
+            Ok(r) => r,
+            Err(ureq::Error::Status(code, _)) => bail!("GET {}: HTTP {}", url, code),
+            Err(e) => return Err(e).with_context(|| format!("GET {}", url)),
+        };
+        // Servers that ignore Range reply 200 with the whole body; skip forward ourselves.
+        let full_body = resp.status() == 200;
+        let mut rd = resp.into_reader();
+        if full_body && offset > 0 {
+            let skipped = std::io::copy(&mut (&mut rd).take(offset), &mut std::io::sink())?;
+            if skipped < offset {
+                bail!("GET {}: body shorter than requested offset {}", url, offset);
+            }
+        }
+        rd.read_exact(buf).with_context(|| format!("read {} ({})", url, range))?;
+        Ok(())
+    }
+
+    fn len(&self, rel_path: &str) -> Result<u64> {
+        let url = self.url_for(rel_path)?;
+        let resp = match self.agent.head(&url).call() {
+            Ok(r) => r,
+            Err(ureq::Error::Status(code, _)) => bail!("HEAD {}: HTTP {}", url, code),
+            Err(e) => return Err(e).with_context(|| format!("HEAD {}", url)),
+        };
+        resp.header("Content-Length")
+            .and_then(|v| v.trim().parse().ok())
+            .with_context(|| format!("HEAD {}: no Content-Length", url))
+    }
+
+    fn describe(&self) -> String {
+        self.base.clone()
+    }
+
+    fn cost(&self) -> ReadCost {
+        self.cost
+    }
+}
+
+#[cfg(feature = "http")]
+fn percent_encode(seg: &str) -> String {
+    let mut out = String::with_capacity(seg.len());
+    for b in seg.bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b'~') {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
@@ -0,0 +1,90 @@
+//! Recovery executables shipped inside the parity dir (`create --embed-recovery-stub`).
+//!
+//! A copy of the `parx` binary is written next to the volumes as
+//! `parx-recover-<os>-<arch>`, so whoever finds the set later can repair it
+//! without obtaining the tool. Its hash is recorded in the manifest and
+//! checked by `parx info` before anyone is told to run it.
+
+use alloc::string::String;
+#[cfg(feature = "std")]
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "std")]
+use std::path::Path;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct RecoveryStub {
+    /// File name inside the parity dir
+    pub file: String,
+    /// `<os>-<arch>` the executable was built for
+    pub platform: String,
+    pub size: u64,
+    pub blake3_hex: String,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct StubStatus {
+    pub file: String,
+    pub platform: String,
+    /// "ok", "missing" or "hash mismatch"
+    pub status: String,
+}
+
+impl StubStatus {
+    pub fn is_ok(&self) -> bool {
+        self.status == "ok"
+    }
+}
+
+/// Platform string of the running build, e.g. `linux-x86_64`.
+#[cfg(feature = "std")]
+pub fn current_platform() -> String {
+    format!("{}-{}", std::env::consts::OS, std::env::consts::ARCH)
+}
+
+#[cfg(feature = "std")]
+fn stub_name(platform: &str) -> String {
+    let exe = if platform.starts_with("windows") { ".exe" } else { "" };
+    format!("parx-recover-{}{}", platform, exe)
+}
+
+/// Copy `exe` (built for the current platform) into `output` and describe it.
+#[cfg(feature = "std")]
+pub fn embed(output: &Path, exe: &Path) -> Result<RecoveryStub> {
+    let platform = current_platform();
+    let file = stub_name(&platform);
+    let dest = output.join(&file);
+    std::fs::copy(exe, &dest).with_context(|| format!("copy {:?} to {:?}", exe, dest))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&dest, std::fs::Permissions::from_mode(0o755))?;
+    }
+    let data = std::fs::read(&dest).with_context(|| format!("read {:?}", dest))?;
+    Ok(RecoveryStub {
+        file,
+        platform,
+        size: data.len() as u64,
+        blake3_hex: blake3::hash(&data).to_hex().to_string(),
+    })
+}
+
+/// Check each recorded stub in `output` against its manifest hash.
+#[cfg(feature = "std")]
+pub fn check(output: &Path, stubs: &[RecoveryStub]) -> Vec<StubStatus> {
+    stubs
+        .iter()
+        .map(|s| {
+            let status = match std::fs::read(output.join(&s.file)) {
+                Err(_) => "missing",
+                Ok(data) if blake3::hash(&data).to_hex().as_str() == s.blake3_hex => "ok",
+                Ok(_) => "hash mismatch",
+            };
+            StubStatus {
+                file: s.file.clone(),
+                platform: s.platform.clone(),
+                status: status.to_string(),
+            }
+        })
+        .collect()
+}
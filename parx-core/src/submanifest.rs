@@ -0,0 +1,123 @@
+//! Sub-manifests: one manifest per top-level directory of a set (`create
+//! --sub-manifests`), so that a directory copied on its own, together with
+//! its sub-manifest and the set's volumes, can be verified and repaired
+//! standalone.
+//!
+//! `create` starts every top-level directory on a fresh stripe (the slots in
+//! between hold zeros), so the stripes of a sub-manifest hold nothing of
+//! other directories. A sub-manifest keeps the set's slots, file ids and
+//! volumes, records paths relative to its directory and has its own Merkle
+//! root over its chunks. The set's manifest lists every one of those roots
+//! (`ext::key::SUB_MANIFESTS`), so whoever trusts or signed the set's
+//! manifest can check a sub-manifest against it with [`belongs_to`].
+//!
+//! Sub-manifests are written to `sub/<dir>/` in the parity dir; when the
+//! recorded parity dir is gone, the set is looked for two levels up.
+
+use crate::ext::{key, ExtMap};
+use crate::manifest::{Manifest, MANIFEST_JSON};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Directory of the parity dir holding the sub-manifests.
+pub const SUB_DIR: &str = "sub";
+
+/// Where the sub-manifest of the top-level directory `dir` is written.
+pub fn path_of(parity_dir: &Path, dir: &str) -> PathBuf {
+    parity_dir.join(SUB_DIR).join(dir).join(MANIFEST_JSON)
+}
+
+/// The top-level directory of `rel_path`; `None` for files directly in the
+/// root, which belong to no sub-manifest.
+pub fn top_dir(rel_path: &str) -> Option<&str> {
+    rel_path.split_once('/').map(|(dir, _)| dir)
+}
+
+/// The directory a sub-manifest covers; `None` for the manifest of a set.
+pub fn subset(mf: &Manifest) -> Option<&str> {
+    mf.ext.get(key::SUBSET).and_then(|v| std::str::from_utf8(v).ok())
+}
+
+/// Sub-manifests listed in the manifest of a set: Merkle root by directory.
+pub fn listed(mf: &Manifest) -> BTreeMap<String, String> {
+    let Some(text) = mf.ext.get(key::SUB_MANIFESTS).and_then(|v| std::str::from_utf8(v).ok())
+    else {
+        return BTreeMap::new();
+    };
+    text.lines()
+        .filter_map(|l| l.split_once(' '))
+        .map(|(root, dir)| (dir.to_string(), root.to_string()))
+        .collect()
+}
+
+/// Whether `sub` is a sub-manifest that the manifest `set` lists, with the
+/// same Merkle root.
+pub fn belongs_to(sub: &Manifest, set: &Manifest) -> bool {
+    subset(sub)
+        .is_some_and(|dir| listed(set).get(dir).is_some_and(|root| *root == sub.merkle_root_hex))
+}
+
+/// Record the Merkle root of each directory's sub-manifest in `ext`.
+pub(crate) fn record(ext: &mut ExtMap, roots: &BTreeMap<String, String>) {
+    let text: String = roots.iter().map(|(dir, root)| format!("{} {}\n", root, dir)).collect();
+    ext.insert(key::SUB_MANIFESTS, text.into_bytes());
+}
+
+/// The sub-manifest of every directory in `roots`, cut out of the manifest
+/// of a set whose paths start with `prefix`.
+pub(crate) fn split(
+    mf: &Manifest,
+    prefix: Option<&str>,
+    roots: &BTreeMap<String, String>,
+) -> Vec<(String, Manifest)> {
+    roots
+        .iter()
+        .map(|(name, root)| {
+            let dir = &match prefix {
+                Some(pre) => format!("{}/{}", pre, name),
+                None => name.clone(),
+            };
+            let files: Vec<_> = mf
+                .files
+                .iter()
+                .filter_map(|fe| {
+                    Some(crate::manifest::FileEntry {
+                        rel_path: under(dir, &fe.rel_path)?.to_string(),
+                        ..fe.clone()
+                    })
+                })
+                .collect();
+            let mut ext = mf.ext.clone();
+            for k in [key::MANIFEST_SIG, key::SUB_MANIFESTS, key::DEDUP] {
+                ext.remove(k);
+            }
+            ext.insert(key::SUBSET, name.clone().into_bytes());
+            let sub = Manifest {
+                total_bytes: files.iter().map(|fe| fe.size).sum(),
+                files,
+                symlinks: mf
+                    .symlinks
+                    .iter()
+                    .filter_map(|l| {
+                        Some(crate::manifest::SymlinkEntry {
+                            rel_path: under(dir, &l.rel_path)?.to_string(),
+                            ..l.clone()
+                        })
+                    })
+                    .collect(),
+                merkle_root_hex: root.clone(),
+                exclude: crate::encode::subset_patterns(name, &mf.exclude),
+                critical: crate::encode::subset_patterns(name, &mf.critical),
+                recovery_stubs: Vec::new(),
+                ext,
+                ..mf.clone()
+            };
+            (name.clone(), sub)
+        })
+        .collect()
+}
+
+/// `rel` relative to the top-level directory `dir`, if it is under it.
+fn under<'a>(dir: &str, rel: &'a str) -> Option<&'a str> {
+    rel.strip_prefix(dir)?.strip_prefix('/')
+}
@@ -0,0 +1,99 @@
+//! Symbolic links recorded in the manifest ([`SymlinkEntry`]).
+//!
+//! A link is stored as its path and target text; there is nothing to protect
+//! with parity, so `verify` compares the link on disk with the record and
+//! `repair` recreates links that are missing or point elsewhere. The link's
+//! own path follows the [`PathPolicy`] like file paths do. Only targets that
+//! stay under the root when resolved from the link's directory are recreated:
+//! an absolute or escaping target is reported instead, so a manifest cannot
+//! make repair plant links out of the protected tree.
+
+use crate::manifest::SymlinkEntry;
+use crate::path_safety::{validate_path, PathPolicy};
+use anyhow::{bail, Context, Result};
+use std::path::{Component, Path, PathBuf};
+
+/// Where the link `rel` lives under `root`; its parent dirs are checked
+/// against `policy` but the link itself is not followed.
+pub fn link_path(root: &Path, rel: &str, policy: PathPolicy) -> Result<PathBuf> {
+    let rel = Path::new(rel);
+    let Some(name) = rel.file_name() else { bail!("symlink path {:?} has no name", rel) };
+    let parent = match rel.parent().filter(|p| !p.as_os_str().is_empty()) {
+        Some(p) => validate_path(root, p, policy)?,
+        None => root.to_path_buf(),
+    };
+    Ok(parent.join(name))
+}
+
+/// Whether `target`, read relative to the directory of the link `rel`, stays
+/// under the root (judged on the paths alone).
+pub fn target_contained(rel: &str, target: &str) -> bool {
+    let target = Path::new(target);
+    let mut depth = Path::new(rel).components().count() as i64 - 1;
+    for c in target.components() {
+        match c {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::ParentDir => {
+                depth -= 1;
+                if depth < 0 {
+                    return false;
+                }
+            }
+            Component::RootDir | Component::Prefix(_) => return false,
+        }
+    }
+    true
+}
+
+/// Whether the link is present and points where the manifest says.
+pub fn is_intact(root: &Path, link: &SymlinkEntry, policy: PathPolicy) -> Result<bool> {
+    let path = link_path(root, &link.rel_path, policy)
+        .with_context(|| format!("validate path {:?}", link.rel_path))?;
+    Ok(std::fs::read_link(&path).is_ok_and(|t| t == Path::new(&link.target)))
+}
+
+/// Recreate the link if it is missing or points elsewhere; `Ok(true)` when
+/// it was (re)created. Anything but a symlink in its place is left alone.
+pub fn restore(root: &Path, link: &SymlinkEntry, policy: PathPolicy) -> Result<bool> {
+    if is_intact(root, link, policy)? {
+        return Ok(false);
+    }
+    if !target_contained(&link.rel_path, &link.target) {
+        bail!("target {:?} leaves the protected tree", link.target);
+    }
+    let path = link_path(root, &link.rel_path, policy)?;
+    match std::fs::symlink_metadata(&path) {
+        Ok(md) if md.file_type().is_symlink() => {
+            std::fs::remove_file(&path).with_context(|| format!("remove {:?}", path))?
+        }
+        Ok(_) => bail!("{:?} exists and is not a symlink", path),
+        Err(_) => {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+        }
+    }
+    make_link(Path::new(&link.target), &path).with_context(|| format!("create link {:?}", path))?;
+    Ok(true)
+}
+
+#[cfg(unix)]
+fn make_link(target: &Path, path: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, path)
+}
+
+#[cfg(windows)]
+fn make_link(target: &Path, path: &Path) -> std::io::Result<()> {
+    let resolved = path.parent().map_or_else(|| target.to_path_buf(), |p| p.join(target));
+    if resolved.is_dir() {
+        std::os::windows::fs::symlink_dir(target, path)
+    } else {
+        std::os::windows::fs::symlink_file(target, path)
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn make_link(_target: &Path, _path: &Path) -> std::io::Result<()> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
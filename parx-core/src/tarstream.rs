@@ -0,0 +1,329 @@
+//! Minimal reader for tar streams (`create --stdin-tar`): ustar and old v7
+//! headers, GNU long names (`L`) and base-256 sizes, and the `path`/`size`
+//! records of PAX extended headers (`x`). Entries are read strictly in order,
+//! so the archive can come from a pipe. [`TarWriter`] writes the ustar
+//! archives of `parx pack`.
+
+use anyhow::{bail, Context, Result};
+use std::io::{self, Read, Write};
+
+const BLOCK: usize = 512;
+/// Longest GNU long name or PAX header accepted.
+const MAX_META: u64 = 1 << 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntryKind {
+    /// Regular file (`0`, NUL or contiguous `7`)
+    File,
+    Dir,
+    /// Links, devices, FIFOs and anything else without protectable content
+    Other,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TarEntry {
+    /// Member path with `./` and trailing `/` stripped; never absolute and
+    /// never containing `..`
+    pub path: String,
+    pub kind: EntryKind,
+    pub size: u64,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    /// Modification time, seconds since the Unix epoch
+    pub mtime: i64,
+}
+
+pub struct TarReader<R> {
+    inner: R,
+    /// Unread data of the current entry
+    left: u64,
+    /// Padding after the current entry's data
+    pad: u64,
+    done: bool,
+}
+
+impl<R: Read> TarReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self { inner, left: 0, pad: 0, done: false }
+    }
+
+    /// Header of the next member, skipping what is left of the current one.
+    /// `None` at the end-of-archive marker (or a clean end of the stream).
+    pub fn next_entry(&mut self) -> Result<Option<TarEntry>> {
+        let mut long_name: Option<String> = None;
+        let mut pax_path: Option<String> = None;
+        let mut pax_size: Option<u64> = None;
+        loop {
+            self.skip(self.left + self.pad)?;
+            self.left = 0;
+            self.pad = 0;
+            if self.done {
+                return Ok(None);
+            }
+            let mut hdr = [0u8; BLOCK];
+            if !self.fill(&mut hdr)? || hdr.iter().all(|&b| b == 0) {
+                self.done = true;
+                return Ok(None);
+            }
+            let sum = octal(&hdr[148..156]).context("tar header checksum")?;
+            let actual: u64 = hdr
+                .iter()
+                .enumerate()
+                .map(|(i, &b)| if (148..156).contains(&i) { b' ' as u64 } else { b as u64 })
+                .sum();
+            if sum != actual {
+                bail!("tar header checksum mismatch (not a tar stream?)");
+            }
+            let size = match pax_size.take() {
+                Some(s) => s,
+                None => number(&hdr[124..136]).context("tar entry size")?,
+            };
+            self.left = size;
+            self.pad = (BLOCK as u64 - size % BLOCK as u64) % BLOCK as u64;
+            match hdr[156] {
+                b'L' => {
+                    long_name = Some(trim_nul(&self.read_meta(size)?));
+                    continue;
+                }
+                b'x' => {
+                    for (key, value) in pax_records(&self.read_meta(size)?)? {
+                        match key.as_str() {
+                            "path" => pax_path = Some(value),
+                            "size" => pax_size = Some(value.parse().context("PAX size record")?),
+                            _ => {}
+                        }
+                    }
+                    continue;
+                }
+                // Long link names and global PAX headers do not name the next file
+                b'K' | b'g' => continue,
+                _ => {}
+            }
+            let name = match pax_path.take().or(long_name.take()) {
+                Some(n) => n,
+                None => {
+                    let name = trim_nul(&hdr[..100]);
+                    let prefix = trim_nul(&hdr[345..500]);
+                    // The prefix field only means a prefix in POSIX ustar
+                    if &hdr[257..263] == b"ustar\0" && !prefix.is_empty() {
+                        format!("{}/{}", prefix, name)
+                    } else {
+                        name
+                    }
+                }
+            };
+            let kind = match hdr[156] {
+                b'0' | 0 | b'7' if name.ends_with('/') => EntryKind::Dir,
+                b'0' | 0 | b'7' => EntryKind::File,
+                b'5' => EntryKind::Dir,
+                _ => EntryKind::Other,
+            };
+            return Ok(Some(TarEntry {
+                path: clean_path(&name)?,
+                kind,
+                size,
+                mode: octal(&hdr[100..108]).unwrap_or(0) as u32 & 0o7777,
+                uid: number(&hdr[108..116]).unwrap_or(0) as u32,
+                gid: number(&hdr[116..124]).unwrap_or(0) as u32,
+                mtime: number(&hdr[136..148]).unwrap_or(0) as i64,
+            }));
+        }
+    }
+
+    /// Data of the current entry.
+    pub fn data(&mut self) -> impl Read + '_ {
+        EntryData { tar: self }
+    }
+
+    fn read_meta(&mut self, size: u64) -> Result<Vec<u8>> {
+        if size > MAX_META {
+            bail!("tar extended header of {} bytes is too large", size);
+        }
+        let mut buf = Vec::with_capacity(size as usize);
+        self.data().read_to_end(&mut buf)?;
+        if buf.len() as u64 != size {
+            bail!("tar stream ends inside an extended header");
+        }
+        Ok(buf)
+    }
+
+    /// Read a whole block; `false` on a clean end of the stream.
+    fn fill(&mut self, buf: &mut [u8]) -> Result<bool> {
+        let mut got = 0;
+        while got < buf.len() {
+            match self.inner.read(&mut buf[got..]) {
+                Ok(0) if got == 0 => return Ok(false),
+                Ok(0) => bail!("tar stream ends inside a header"),
+                Ok(n) => got += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(true)
+    }
+
+    fn skip(&mut self, n: u64) -> Result<()> {
+        let skipped = io::copy(&mut (&mut self.inner).take(n), &mut io::sink())?;
+        if skipped != n {
+            bail!("tar stream ends inside an entry");
+        }
+        Ok(())
+    }
+}
+
+/// Writes regular files as ustar members, with GNU long names (`L`) for
+/// paths over 100 bytes.
+pub struct TarWriter<W> {
+    inner: W,
+}
+
+impl<W: Write> TarWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner }
+    }
+
+    /// Append a file member of exactly `size` bytes read from `data`.
+    pub fn append(
+        &mut self,
+        path: &str,
+        size: u64,
+        mode: u32,
+        mtime: i64,
+        data: impl Read,
+    ) -> Result<()> {
+        if path.len() > 100 {
+            let mut name = path.as_bytes().to_vec();
+            name.push(0);
+            self.header("././@LongLink", name.len() as u64, 0, 0, b'L')?;
+            self.inner.write_all(&name)?;
+            self.pad(name.len() as u64)?;
+        }
+        self.header(path, size, mode, mtime, b'0')?;
+        let copied = io::copy(&mut data.take(size), &mut self.inner)?;
+        if copied != size {
+            bail!("{} shrank while archiving ({} of {} bytes)", path, copied, size);
+        }
+        self.pad(size)
+    }
+
+    /// Write the end-of-archive marker and hand back the writer.
+    pub fn finish(mut self) -> Result<W> {
+        self.inner.write_all(&[0u8; 2 * BLOCK])?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn header(&mut self, path: &str, size: u64, mode: u32, mtime: i64, kind: u8) -> Result<()> {
+        let mut hdr = [0u8; BLOCK];
+        let name = path.as_bytes();
+        hdr[..name.len().min(100)].copy_from_slice(&name[..name.len().min(100)]);
+        put_octal(&mut hdr[100..108], (mode & 0o7777) as u64);
+        put_octal(&mut hdr[108..116], 0);
+        put_octal(&mut hdr[116..124], 0);
+        if size < 1 << 33 {
+            put_octal(&mut hdr[124..136], size);
+        } else {
+            hdr[124] = 0x80;
+            hdr[128..136].copy_from_slice(&size.to_be_bytes());
+        }
+        put_octal(&mut hdr[136..148], mtime.max(0) as u64);
+        hdr[156] = kind;
+        hdr[257..263].copy_from_slice(b"ustar\0");
+        hdr[263..265].copy_from_slice(b"00");
+        let sum: u64 = hdr.iter().map(|&b| b as u64).sum::<u64>() + 8 * b' ' as u64;
+        put_octal(&mut hdr[148..155], sum);
+        hdr[155] = b' ';
+        self.inner.write_all(&hdr)?;
+        Ok(())
+    }
+
+    fn pad(&mut self, size: u64) -> Result<()> {
+        let pad = (BLOCK as u64 - size % BLOCK as u64) % BLOCK as u64;
+        self.inner.write_all(&[0u8; BLOCK][..pad as usize])?;
+        Ok(())
+    }
+}
+
+/// Zero-padded octal filling all but the last (NUL) byte of `field`.
+fn put_octal(field: &mut [u8], v: u64) {
+    let s = format!("{:0width$o}", v, width = field.len() - 1);
+    field[..s.len()].copy_from_slice(s.as_bytes());
+}
+
+struct EntryData<'a, R> {
+    tar: &'a mut TarReader<R>,
+}
+
+impl<R: Read> Read for EntryData<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let want = buf.len().min(self.tar.left.min(usize::MAX as u64) as usize);
+        if want == 0 {
+            return Ok(0);
+        }
+        let n = self.tar.inner.read(&mut buf[..want])?;
+        self.tar.left -= n as u64;
+        Ok(n)
+    }
+}
+
+fn trim_nul(b: &[u8]) -> String {
+    let end = b.iter().position(|&c| c == 0).unwrap_or(b.len());
+    String::from_utf8_lossy(&b[..end]).into_owned()
+}
+
+fn octal(field: &[u8]) -> Result<u64> {
+    let s = trim_nul(field);
+    let s = s.trim_matches(|c: char| c == ' ' || c == '\0');
+    if s.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(s, 8).with_context(|| format!("bad octal field {:?}", s))
+}
+
+/// Octal, or GNU base-256 when the high bit of the first byte is set.
+fn number(field: &[u8]) -> Result<u64> {
+    if field[0] & 0x80 == 0 {
+        return octal(field);
+    }
+    let mut v: u64 = (field[0] & 0x7f) as u64;
+    for &b in &field[1..] {
+        v = v.checked_mul(256).context("tar number overflows")? | b as u64;
+    }
+    Ok(v)
+}
+
+/// `len key=value\n` records of a PAX extended header.
+fn pax_records(mut b: &[u8]) -> Result<Vec<(String, String)>> {
+    let mut out = Vec::new();
+    while !b.is_empty() {
+        let sp = b.iter().position(|&c| c == b' ').context("bad PAX record")?;
+        let len: usize = std::str::from_utf8(&b[..sp])?.parse().context("bad PAX record")?;
+        if len <= sp + 1 || len > b.len() || b[len - 1] != b'\n' {
+            bail!("bad PAX record");
+        }
+        let rec = String::from_utf8_lossy(&b[sp + 1..len - 1]).into_owned();
+        if let Some((k, v)) = rec.split_once('=') {
+            out.push((k.to_string(), v.to_string()));
+        }
+        b = &b[len..];
+    }
+    Ok(out)
+}
+
+/// Strip `./` and trailing slashes and refuse paths that would leave the
+/// extraction dir.
+fn clean_path(name: &str) -> Result<String> {
+    if name.starts_with('/') {
+        bail!("refusing absolute path {:?} in tar stream", name);
+    }
+    let mut parts = Vec::new();
+    for part in name.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => bail!("refusing path {:?} with `..` in tar stream", name),
+            p => parts.push(p),
+        }
+    }
+    Ok(parts.join("/"))
+}
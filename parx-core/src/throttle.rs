@@ -0,0 +1,122 @@
+//! Load-aware pausing (`--auto-throttle`). Long verify and repair runs check
+//! the kernel's I/O pressure (PSI, `/proc/pressure/io` on Linux) between
+//! chunks and sleep while other work is stalled on I/O, so a scrub of a busy
+//! disk yields to its users instead of competing with them.
+//!
+//! The share of time some task waited on I/O over the last 10 seconds
+//! (`some avg10`) pauses the run once it reaches the threshold; the run
+//! resumes when it falls under half of it. Where pressure cannot be read the
+//! run never pauses.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Where Linux publishes I/O pressure.
+pub const PSI_IO: &str = "/proc/pressure/io";
+
+/// Shared by every thread of a run; cloning is cheap.
+#[derive(Clone, Debug)]
+pub struct Throttle {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    source: PathBuf,
+    pause_at: f64,
+    resume_at: f64,
+    poll: Duration,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    checked: Option<Instant>,
+    busy_since: Option<Instant>,
+    paused: Duration,
+    pauses: u64,
+}
+
+impl Throttle {
+    /// Pause while I/O pressure is at least `pct` percent, checked once a second.
+    pub fn new(pct: f64) -> Self {
+        Self::with_source(PSI_IO, pct, Duration::from_secs(1))
+    }
+
+    /// Read pressure from `source` (a file in PSI format) every `poll`.
+    pub fn with_source(source: impl Into<PathBuf>, pct: f64, poll: Duration) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                source: source.into(),
+                pause_at: pct,
+                resume_at: pct / 2.0,
+                poll,
+                state: Mutex::default(),
+            }),
+        }
+    }
+
+    /// Whether pressure can be read here at all.
+    pub fn available(&self) -> bool {
+        read_pressure(&self.inner.source).is_some()
+    }
+
+    /// Return once the system is not busy.
+    pub fn pause(&self) {
+        self.pause_with(|| {})
+    }
+
+    /// [`pause`](Self::pause), calling `tick` on every poll while paused
+    /// (keeps an `--io-timeout` watchdog from taking the pause for a stall).
+    pub fn pause_with(&self, mut tick: impl FnMut()) {
+        while self.busy() {
+            tick();
+            std::thread::sleep(self.inner.poll);
+        }
+    }
+
+    /// Wall-clock time spent paused so far.
+    pub fn paused(&self) -> Duration {
+        let st = self.inner.state.lock().unwrap_or_else(|e| e.into_inner());
+        st.paused + st.busy_since.map_or(Duration::ZERO, |t| t.elapsed())
+    }
+
+    /// How many times the run was paused.
+    pub fn pauses(&self) -> u64 {
+        self.inner.state.lock().unwrap_or_else(|e| e.into_inner()).pauses
+    }
+
+    fn busy(&self) -> bool {
+        let inner = &*self.inner;
+        let mut st = inner.state.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        if st.checked.map_or(true, |t| now.duration_since(t) >= inner.poll) {
+            st.checked = Some(now);
+            let limit = if st.busy_since.is_some() { inner.resume_at } else { inner.pause_at };
+            let busy = read_pressure(&inner.source).is_some_and(|p| p >= limit);
+            match (busy, st.busy_since) {
+                (true, None) => {
+                    st.busy_since = Some(now);
+                    st.pauses += 1;
+                }
+                (false, Some(since)) => {
+                    st.paused += now.duration_since(since);
+                    st.busy_since = None;
+                }
+                _ => {}
+            }
+        }
+        st.busy_since.is_some()
+    }
+}
+
+/// `some avg10` of a PSI file (`some avg10=1.23 avg60=... total=...`).
+pub fn parse_pressure(text: &str) -> Option<f64> {
+    let line = text.lines().find(|l| l.starts_with("some "))?;
+    line.split_whitespace().find_map(|f| f.strip_prefix("avg10="))?.parse().ok()
+}
+
+fn read_pressure(source: &Path) -> Option<f64> {
+    parse_pressure(&std::fs::read_to_string(source).ok()?)
+}
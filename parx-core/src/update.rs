@@ -0,0 +1,665 @@
+//! Incremental updates of an existing set.
+//!
+//! [`update`] re-scans the source tree and brings the set in line with it
+//! without re-creating it. Files are compared chunk by chunk against the
+//! manifest: a changed file keeps its chunk slots when it still fits in them
+//! (only the slots whose content differs are re-encoded), otherwise it moves
+//! to the end like a new file. Slots of removed files, and those a file no
+//! longer needs, stay in the layout as zero chunks. Only stripes that hold a
+//! new, changed or freed slot get their parity recomputed: existing stripes
+//! are rewritten in place, further stripes are appended to the volumes.
+//!
+//! [`append_only`] handles sources that only ever gain files, such as the pack
+//! directories of backup repositories: known files must be unchanged, which
+//! is judged by size alone so they are not read, and new files are chunked
+//! after the existing ones.
+//!
+//! [`forget`] drops files that were deleted on purpose from the set without
+//! scanning the tree: their slots are freed like those of removed files and
+//! only the stripes they shared are re-encoded, from the remaining chunks.
+
+use crate::codec::Codecs;
+use crate::encode::{aligned_cuts, media_cuts, read_chunks, scan_files};
+use crate::filter::{ChunkFilter, FILTER_FILE};
+use crate::geometry::Geometry;
+use crate::hashcache::{self, HashCache};
+use crate::index::{read_index, read_trailer, write_index_and_trailer_with, IndexLimits};
+use crate::manifest::{self, ChunkRef, FileEntry, MANIFEST_JSON};
+use crate::media::MediaLayout;
+use crate::merkle;
+use crate::merkle::ChunkHasher;
+use crate::path_safety::{validate_path, PathPolicy};
+use crate::rs_codec::{RsCodec, RsField};
+use crate::volume::{check_features, ReadMode, ShardKind, VolumeEntry, VolumeHeader};
+use anyhow::{bail, Context, Result};
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UpdateReport {
+    pub files_unchanged: u64,
+    pub files_added: u64,
+    pub files_changed: u64,
+    pub files_removed: u64,
+    /// Chunks appended after the old ones (new files and files that outgrew their slots)
+    pub chunks_added: u64,
+    /// Existing chunk slots that were given new content
+    pub chunks_rewritten: u64,
+    /// Existing chunk slots no file uses any more (encoded as zeros)
+    pub chunks_freed: u64,
+    pub bytes_added: u64,
+    /// Existing stripes whose parity was rewritten
+    pub stripes_rewritten: u64,
+    pub stripes_added: u64,
+    /// Symlinks added, removed or pointed elsewhere
+    #[serde(default)]
+    pub symlinks_changed: u64,
+    /// Known files taken as unchanged from the hash cache, not read
+    #[serde(default)]
+    pub files_reused: u64,
+}
+
+impl crate::report::Report for UpdateReport {
+    const KIND: &'static str = "update";
+}
+
+/// Add the files that appeared under `root` since the set in `output` was
+/// created. Fails without touching anything when a known file changed size or
+/// disappeared. `rel_prefix` is the prefix the manifest's rel paths carry in
+/// front of paths relative to `root` (the CLI records paths relative to CWD).
+pub fn append_only(output: &Path, root: &Path, rel_prefix: Option<&str>) -> Result<UpdateReport> {
+    let opts = UpdateOptions { append_only: true, ..Default::default() };
+    update_with_options(output, root, rel_prefix, &opts)
+}
+
+/// Bring the set in `output` in line with `root`: protect added files, re-encode
+/// the stripes of changed ones and drop removed ones (see the module docs).
+/// Every known file is read to find its changed chunks; `rel_prefix` is as
+/// for [`append_only`].
+pub fn update(output: &Path, root: &Path, rel_prefix: Option<&str>) -> Result<UpdateReport> {
+    update_with_options(output, root, rel_prefix, &UpdateOptions::default())
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct UpdateOptions {
+    /// Run as [`append_only`]
+    pub append_only: bool,
+    /// Re-sign the updated manifest. A signed set can only be updated with
+    /// the key that signed it; without one the update is refused, since it
+    /// would leave a manifest whose signature no longer matches.
+    pub sign_key: Option<ed25519_dalek::SigningKey>,
+    /// Take known files as unchanged without reading them when the set's
+    /// hash cache found them so at most this long ago (see [`crate::hashcache`])
+    pub reuse_hashes: Option<std::time::Duration>,
+}
+
+/// [`update`] or [`append_only`], as `opts` selects.
+pub fn update_with_options(
+    output: &Path,
+    root: &Path,
+    rel_prefix: Option<&str>,
+    opts: &UpdateOptions,
+) -> Result<UpdateReport> {
+    run(output, root, rel_prefix, opts)
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ForgetReport {
+    pub files_forgotten: u64,
+    pub bytes_forgotten: u64,
+    pub symlinks_forgotten: u64,
+    /// Chunk slots of the forgotten files, now holes encoded as zeros
+    pub chunks_freed: u64,
+    /// Stripes whose parity was recomputed without the freed slots
+    pub stripes_rewritten: u64,
+}
+
+impl crate::report::Report for ForgetReport {
+    const KIND: &'static str = "forget";
+}
+
+/// Remove `paths` from the set in `output`: each names a file or symlink as
+/// recorded in the manifest, or a directory whose entries all go. Their chunk
+/// slots are freed and the stripes holding them get their parity recomputed
+/// from the other chunks, which are read under `root` (the tree the
+/// manifest's rel paths are relative to, as for `repair`) and must be
+/// intact. Fails without touching anything when a path is not in the set.
+/// A forgotten file still on disk is added again by the next [`update`].
+pub fn forget(
+    output: &Path,
+    root: &Path,
+    paths: &[String],
+    sign_key: Option<&ed25519_dalek::SigningKey>,
+) -> Result<ForgetReport> {
+    let (mut mf, recovery) = manifest::load(&output.join(MANIFEST_JSON))?;
+    if recovery.is_some() {
+        bail!("manifest.json is damaged; repair the manifest before forgetting files");
+    }
+    check_supported(&mf, "forget", sign_key)?;
+    let mut wanted = Vec::with_capacity(paths.len());
+    for p in paths {
+        let mut norm = p.replace('\\', "/");
+        while let Some(rest) = norm.strip_prefix("./") {
+            norm = rest.to_string();
+        }
+        let norm = norm.trim_end_matches('/').to_string();
+        if norm.is_empty() || norm == "." {
+            bail!("{:?} would forget the whole set; re-run `parx create` instead", p);
+        }
+        wanted.push(norm);
+    }
+    let mut hits = vec![false; wanted.len()];
+    let mut matches = |rel: &str| -> bool {
+        let rel = rel.replace('\\', "/");
+        let mut any = false;
+        for (w, hit) in wanted.iter().zip(hits.iter_mut()) {
+            if rel == *w || rel.strip_prefix(w.as_str()).is_some_and(|r| r.starts_with('/')) {
+                *hit = true;
+                any = true;
+            }
+        }
+        any
+    };
+    let mut rep = ForgetReport::default();
+    let mut freed = Vec::new();
+    // Pin the next id first, so that the ids of forgotten files are not reused
+    mf.assign_file_ids();
+    let geo = mf.geometry();
+    let mut kept = Vec::with_capacity(mf.files.len());
+    for fe in std::mem::take(&mut mf.files) {
+        if matches(&fe.rel_path) {
+            freed.extend(fe.chunks.iter().map(|c| c.idx));
+            rep.files_forgotten += 1;
+            rep.bytes_forgotten += fe.size;
+        } else {
+            kept.push(fe);
+        }
+    }
+    mf.files = kept;
+    let before = mf.symlinks.len();
+    mf.symlinks.retain(|l| !matches(&l.rel_path));
+    rep.symlinks_forgotten = (before - mf.symlinks.len()) as u64;
+    if let Some(i) = hits.iter().position(|h| !h) {
+        bail!("{:?} is not in the set", paths[i]);
+    }
+    rep.chunks_freed = freed.len() as u64;
+
+    let _lock = lock(output)?;
+    mf.next_generation();
+    let same = |rel: &str| rel.to_string();
+    let (rewritten, _) = reencode(output, root, &mf, &geo, &same, &BTreeMap::new(), &freed)?;
+    rep.stripes_rewritten = rewritten;
+    let mut hashes = HashCache::load(output);
+    let listed: HashSet<&str> = mf.files.iter().map(|f| f.rel_path.as_str()).collect();
+    hashes.files.retain(|rel, _| listed.contains(rel.as_str()));
+    // Best effort, as for update
+    let _ = hashes.save(output);
+    finish(output, mf, geo.total_chunks, sign_key)?;
+    Ok(rep)
+}
+
+/// A file chunked as it is now, chunk indices not yet assigned.
+fn chunk_file(
+    path: &Path,
+    rel_path: String,
+    hasher: &ChunkHasher,
+    media_align: bool,
+) -> Result<(FileEntry, Vec<Vec<u8>>)> {
+    let (media, cuts) = media_cuts(path, media_align);
+    let (md, chunks) = read_chunks(path, hasher, &cuts, false, false)?;
+    let mut fe = FileEntry {
+        id: 0,
+        rel_path,
+        size: md.len(),
+        chunks: Vec::with_capacity(chunks.len()),
+        meta: crate::meta::capture(path, &md),
+        media: media.map(|container| MediaLayout {
+            container: container.to_string(),
+            aligned_cuts: aligned_cuts(&chunks, &cuts),
+        }),
+    };
+    let mut bufs = Vec::with_capacity(chunks.len());
+    for tc in chunks {
+        fe.chunks.push(ChunkRef {
+            idx: 0,
+            file_offset: tc.file_offset,
+            len: tc.len,
+            hash_hex: tc.hash_hex,
+            weak: Some(tc.weak),
+        });
+        bufs.push(tc.buf);
+    }
+    Ok((fe, bufs))
+}
+
+fn run(
+    output: &Path,
+    root: &Path,
+    rel_prefix: Option<&str>,
+    opts: &UpdateOptions,
+) -> Result<UpdateReport> {
+    let append_only = opts.append_only;
+    let what = if append_only { "append-only update" } else { "update" };
+    let (mut mf, recovery) = manifest::load(&output.join(MANIFEST_JSON))?;
+    if recovery.is_some() {
+        bail!("manifest.json is damaged; repair the manifest before updating");
+    }
+    check_supported(&mf, what, opts.sign_key.as_ref())?;
+    if mf.ext.get_u32(crate::ext::key::FILE_LIST).is_some_and(|v| v != 0) {
+        bail!("the set was created from --files-from and a scan would add unlisted files; re-run `parx create`");
+    }
+    let _lock = lock(output)?;
+
+    let strip = |rel: &str| -> String {
+        rel_prefix
+            .and_then(|p| rel.strip_prefix(p))
+            .and_then(|r| r.strip_prefix(['/', '\\']))
+            .unwrap_or(rel)
+            .to_string()
+    };
+    let with_prefix = |rel: &str| -> String {
+        match rel_prefix {
+            Some(p) => format!("{}/{}", p, rel),
+            None => rel.to_string(),
+        }
+    };
+    let mut rep = UpdateReport::default();
+    let known: HashMap<String, usize> =
+        mf.files.iter().enumerate().map(|(i, f)| (strip(&f.rel_path), i)).collect();
+    let mut present: HashMap<usize, PathBuf> = HashMap::new();
+    let mut new_files = Vec::new();
+    let (scanned, symlinks) = scan_files(root, &mf.exclude)?;
+    for path in scanned {
+        let rel = path.strip_prefix(root).expect("walked path not under root");
+        let rel = crate::path_safety::canonical_rel(rel);
+        match known.get(&rel) {
+            Some(&i) => {
+                present.insert(i, path);
+            }
+            None => new_files.push((rel, path)),
+        }
+    }
+    if append_only {
+        let mut changed: Vec<String> = Vec::new();
+        for (i, fe) in mf.files.iter().enumerate() {
+            let same = match present.get(&i) {
+                Some(path) => std::fs::metadata(path)?.len() == fe.size,
+                None => false,
+            };
+            if !same {
+                changed.push(strip(&fe.rel_path));
+            }
+        }
+        if !changed.is_empty() {
+            changed.sort();
+            bail!(
+                "append-only update: {} known file(s) changed or removed (first: {:?}); re-run `parx create`",
+                changed.len(),
+                changed[0]
+            );
+        }
+    }
+
+    // Compare known files chunk by chunk; new content goes to `fresh`
+    // Of the set as it was
+    let geo = mf.geometry();
+    let hasher = mf.hasher();
+    let old_total = geo.total_chunks;
+    let media_align = mf.ext.get_u32(crate::ext::key::MEDIA_ALIGN).is_some_and(|v| v != 0);
+    let mut next_idx = old_total;
+    let mut fresh: BTreeMap<u64, Vec<u8>> = BTreeMap::new();
+    let mut freed: Vec<u64> = Vec::new();
+    let mut kept: Vec<FileEntry> = Vec::with_capacity(mf.files.len());
+    let mut moved: Vec<FileEntry> = Vec::new();
+    let mut hashes = HashCache::load(output);
+    let mut append = |mut fe: FileEntry, bufs: Vec<Vec<u8>>, fresh: &mut BTreeMap<_, _>| {
+        for (ch, buf) in fe.chunks.iter_mut().zip(bufs) {
+            ch.idx = next_idx;
+            fresh.insert(next_idx, buf);
+            next_idx += 1;
+        }
+        fe
+    };
+    for (i, old) in std::mem::take(&mut mf.files).into_iter().enumerate() {
+        let Some(path) = present.get(&i) else {
+            freed.extend(old.chunks.iter().map(|c| c.idx));
+            rep.files_removed += 1;
+            continue;
+        };
+        if append_only {
+            kept.push(old);
+            rep.files_unchanged += 1;
+            continue;
+        }
+        let stamp = hashcache::stamp_of(path);
+        if let (Some(age), Some(st)) = (opts.reuse_hashes, &stamp) {
+            if hashes.holds(&old.rel_path, st, &old.chunks, age) {
+                let md = std::fs::metadata(path)?;
+                kept.push(FileEntry { meta: crate::meta::capture(path, &md), ..old });
+                rep.files_unchanged += 1;
+                rep.files_reused += 1;
+                continue;
+            }
+        }
+        let (mut fe, bufs) = chunk_file(path, old.rel_path.clone(), &hasher, media_align)?;
+        if let Some(st) = stamp.filter(|s| hashcache::stamp_of(path).as_ref() == Some(s)) {
+            hashes.insert(&fe.rel_path, st, &fe.chunks);
+        }
+        fe.id = old.id;
+        let same_chunk = |a: &ChunkRef, b: &ChunkRef| a.len == b.len && a.hash_hex == b.hash_hex;
+        if fe.size == old.size
+            && fe.chunks.len() == old.chunks.len()
+            && fe.chunks.iter().zip(&old.chunks).all(|(a, b)| same_chunk(a, b))
+        {
+            kept.push(FileEntry { meta: fe.meta, ..old });
+            rep.files_unchanged += 1;
+            continue;
+        }
+        rep.files_changed += 1;
+        if fe.chunks.len() <= old.chunks.len() {
+            // Still fits: reuse the file's slots, re-encode the ones that differ
+            for ((ch, buf), o) in fe.chunks.iter_mut().zip(bufs).zip(&old.chunks) {
+                ch.idx = o.idx;
+                if !same_chunk(ch, o) {
+                    fresh.insert(o.idx, buf);
+                }
+            }
+            freed.extend(old.chunks[fe.chunks.len()..].iter().map(|c| c.idx));
+            kept.push(fe);
+        } else {
+            freed.extend(old.chunks.iter().map(|c| c.idx));
+            moved.push(append(fe, bufs, &mut fresh));
+        }
+    }
+    for (rel, path) in &new_files {
+        let stamp = hashcache::stamp_of(path);
+        let (fe, bufs) = chunk_file(path, with_prefix(rel), &hasher, media_align)?;
+        if let Some(st) = stamp.filter(|s| hashcache::stamp_of(path).as_ref() == Some(s)) {
+            hashes.insert(&fe.rel_path, st, &fe.chunks);
+        }
+        rep.files_added += 1;
+        rep.bytes_added += fe.size;
+        moved.push(append(fe, bufs, &mut fresh));
+    }
+    kept.extend(moved);
+    mf.files = kept;
+    let listed: HashSet<&str> = mf.files.iter().map(|f| f.rel_path.as_str()).collect();
+    hashes.files.retain(|rel, _| listed.contains(rel.as_str()));
+    // Best effort: a stale cache only costs a later read
+    let _ = hashes.save(output);
+    mf.assign_file_ids();
+    let symlinks: Vec<manifest::SymlinkEntry> = symlinks
+        .into_iter()
+        .map(|l| manifest::SymlinkEntry { rel_path: with_prefix(&l.rel_path), ..l })
+        .collect();
+    let links = |ls: &[manifest::SymlinkEntry]| -> BTreeMap<String, String> {
+        ls.iter().map(|l| (l.rel_path.clone(), l.target.clone())).collect()
+    };
+    let (old_links, new_links) = (links(&mf.symlinks), links(&symlinks));
+    rep.symlinks_changed = old_links
+        .keys()
+        .chain(new_links.keys().filter(|k| !old_links.contains_key(*k)))
+        .filter(|k| old_links.get(*k) != new_links.get(*k))
+        .count() as u64;
+    mf.symlinks = symlinks;
+    let new_total = next_idx;
+    rep.chunks_added = new_total - old_total;
+    rep.chunks_rewritten = fresh.range(..old_total).count() as u64;
+    rep.chunks_freed = freed.len() as u64;
+    if rep.files_added + rep.files_changed + rep.files_removed + rep.symlinks_changed == 0 {
+        return Ok(rep);
+    }
+
+    mf.next_generation();
+    let (rewritten, added) = reencode(output, root, &mf, &geo, &strip, &fresh, &freed)?;
+    rep.stripes_rewritten = rewritten;
+    rep.stripes_added = added;
+    finish(output, mf, new_total, opts.sign_key.as_ref())?;
+    Ok(rep)
+}
+
+/// Refuse sets whose layout `what` cannot maintain yet, and signed sets
+/// without the key that signed them.
+pub(crate) fn check_supported(
+    mf: &manifest::Manifest,
+    what: &str,
+    sign_key: Option<&ed25519_dalek::SigningKey>,
+) -> Result<()> {
+    if let Some(sk) = sign_key {
+        // Re-signing must not launder a manifest someone else changed
+        if let Some(vk) = crate::sign::verify_manifest(mf, None)? {
+            if vk != sk.verifying_key() {
+                bail!("the manifest is signed by another key ({})", crate::sign::fingerprint(&vk));
+            }
+        }
+    } else if mf.ext.get(crate::ext::key::MANIFEST_SIG).is_some() {
+        bail!("the manifest is signed; {} needs its signing key (--sign-key)", what);
+    }
+    if crate::outer::OuterLayout::from_manifest(mf).is_some() {
+        bail!("{} does not maintain outer parity yet; re-run `parx create`", what);
+    }
+    if mf.critical_parity > 0 {
+        bail!("{} does not maintain critical-file parity yet; re-run `parx create`", what);
+    }
+    if !mf.parity_groups.is_empty() {
+        bail!("{} does not maintain --parity-rule groups yet; re-run `parx create`", what);
+    }
+    if mf.ext.get(crate::ext::key::DEDUP).is_some() {
+        bail!("{} does not maintain deduplicated slots yet; re-run `parx create`", what);
+    }
+    if mf.ext.get(crate::ext::key::SUB_MANIFESTS).is_some() {
+        bail!("{} does not maintain sub-manifests yet; re-run `parx create`", what);
+    }
+    if mf.volume_max_size().is_some() {
+        bail!("{} does not keep volumes within --volume-max-size yet; re-run `parx create`", what);
+    }
+    if !mf.volume_dirs().is_empty() {
+        bail!("{} does not spread volumes over --output-dirs yet; re-run `parx create`", what);
+    }
+    if mf.hash_mode().is_some() {
+        bail!("{} does not maintain keyed chunk hashes yet; re-run `parx create`", what);
+    }
+    Ok(())
+}
+
+/// Held while the set is rewritten; repair takes the same lock.
+pub(crate) fn lock(output: &Path) -> Result<File> {
+    let lock_file =
+        File::create(output.join(".parx.repair.lock")).context("create global repair lock")?;
+    lock_file.try_lock_exclusive().context("acquire global repair lock")?;
+    Ok(lock_file)
+}
+
+/// Recompute the parity of every stripe holding a `fresh` or `freed` slot
+/// from the new content and the chunks still listed in `mf`, read under
+/// `root` (`strip` maps rel paths to paths below it). `geo` is the layout
+/// before the change. Returns the number of existing stripes rewritten and
+/// of stripes appended.
+pub(crate) fn reencode(
+    output: &Path,
+    root: &Path,
+    mf: &manifest::Manifest,
+    geo: &Geometry,
+    strip: &dyn Fn(&str) -> String,
+    fresh: &BTreeMap<u64, Vec<u8>>,
+    freed: &[u64],
+) -> Result<(u64, u64)> {
+    let (k, cs) = (geo.k, mf.chunk_size);
+    let m = mf.parity_shards();
+    if m > 0 && !(fresh.is_empty() && freed.is_empty()) {
+        let stripes: BTreeSet<u64> =
+            fresh.keys().chain(freed.iter()).map(|&idx| geo.stripe_of(idx)).collect();
+        // Unchanged chunks of those stripes, read back and verified
+        let mut old_chunks: HashMap<u64, (PathBuf, u64, u32, &str)> = HashMap::new();
+        for fe in &mf.files {
+            for ch in fe
+                .chunks
+                .iter()
+                .filter(|c| !fresh.contains_key(&c.idx) && stripes.contains(&geo.stripe_of(c.idx)))
+            {
+                let safe =
+                    validate_path(root, Path::new(&strip(&fe.rel_path)), PathPolicy::default())
+                        .with_context(|| format!("validate path {:?}", fe.rel_path))?;
+                old_chunks.insert(ch.idx, (safe, ch.file_offset, ch.len, &ch.hash_hex));
+            }
+        }
+        let rs = RsCodec::with_field(RsField::from_ext(&mf.ext), k, m).context("init RS")?;
+        let mut parity: Vec<(u64, Vec<Vec<u8>>)> = Vec::new();
+        for &s in &stripes {
+            let mut bufs: Vec<Vec<u8>> = Vec::with_capacity(k + m);
+            for idx in geo.slots(s) {
+                if let Some(buf) = fresh.get(&idx) {
+                    bufs.push(buf.clone());
+                } else if let Some((path, off, len, want)) = old_chunks.get(&idx) {
+                    let mut buf = vec![0u8; cs];
+                    let mut f = File::open(path).with_context(|| format!("open {:?}", path))?;
+                    f.seek(SeekFrom::Start(*off))?;
+                    f.read_exact(&mut buf[..*len as usize])?;
+                    if !mf.hasher().matches(&buf, want) {
+                        bail!("chunk {} of {:?} no longer matches the manifest; run `parx repair` first", idx, path);
+                    }
+                    bufs.push(buf);
+                } else {
+                    // Past the end, or a freed slot
+                    bufs.push(vec![0u8; cs]);
+                }
+            }
+            bufs.extend((0..m).map(|_| vec![0u8; cs]));
+            let mut shards: Vec<&mut [u8]> = bufs.iter_mut().map(|b| b.as_mut_slice()).collect();
+            rs.encode(&mut shards).context("RS encode")?;
+            parity.push((s, bufs.split_off(k)));
+        }
+        let old_stripes = geo.stripes;
+        let rewritten = stripes.range(..old_stripes).count() as u64;
+        write_parity(output, mf, &parity, old_stripes, u64::MAX)?;
+        return Ok((rewritten, parity.len() as u64 - rewritten));
+    }
+    Ok((0, 0))
+}
+
+/// Record `new_total` chunk slots and the files now in `mf`, re-sign it and
+/// save it with everything derived from it.
+pub(crate) fn finish(
+    output: &Path,
+    mut mf: manifest::Manifest,
+    new_total: u64,
+    sign_key: Option<&ed25519_dalek::SigningKey>,
+) -> Result<()> {
+    mf.total_chunks = new_total;
+    mf.total_bytes = mf.files.iter().map(|f| f.size).sum();
+    // In file order, as verify hashes them: a chunk's slot does not count
+    let mut hashes: Vec<blake3::Hash> = Vec::with_capacity(new_total as usize);
+    for fe in &mf.files {
+        for ch in &fe.chunks {
+            hashes.push(blake3::Hash::from_hex(&ch.hash_hex).context("chunk hash in manifest")?);
+        }
+    }
+    mf.merkle_root_hex = merkle::root(&hashes).to_hex().to_string();
+    if let Some(sk) = sign_key {
+        crate::sign::sign_manifest(&mut mf, sk)?;
+    }
+    for vid in 0..mf.volumes.max(1) {
+        crate::manifest_backup::refresh(&output.join(mf.volume_name(vid)), &mf)?;
+    }
+    crate::volparity::refresh(output)?;
+    manifest::save(&mf, output)?;
+    ChunkFilter::from_manifest(&mf)?.save(&output.join(FILTER_FILE))
+}
+
+/// Patch the shards of already indexed stripes in place and append shards of
+/// stripes `>= first_new`, placed like `create` does; drop the shards of
+/// stripes `>= keep`, cutting them off the end of a volume where nothing
+/// kept follows them; then rewrite each index.
+pub(crate) fn write_parity(
+    output: &Path,
+    mf: &manifest::Manifest,
+    parity: &[(u64, Vec<Vec<u8>>)],
+    first_new: u64,
+    keep: u64,
+) -> Result<()> {
+    let vol_count = mf.volumes.max(1);
+    let copies = mf.shard_copies.max(1);
+    let mut vols: Vec<(File, Vec<VolumeEntry>, u64)> = Vec::with_capacity(vol_count);
+    let mut codecs = Vec::with_capacity(vol_count);
+    for vid in 0..vol_count {
+        let p = output.join(mf.volume_name(vid));
+        let mut f = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&p)
+            .with_context(|| format!("open {:?}", p))?;
+        let hdr = VolumeHeader::read_from(&f).with_context(|| format!("read {:?}", p))?;
+        check_features(hdr.flags, ReadMode::Strict)
+            .with_context(|| format!("refusing to update {:?}", p))?;
+        let (off, len, crc) = read_trailer(&mut f).with_context(|| format!("read {:?}", p))?;
+        let entries = read_index(&mut f, off, len, crc, &IndexLimits::default())
+            .with_context(|| format!("read index of {:?}", p))?;
+        codecs.push(Codecs::from_ext(&hdr.ext).index);
+        vols.push((f, entries, off));
+    }
+    for (stripe, shards) in parity {
+        if *stripe < first_new {
+            for (f, entries, _) in vols.iter_mut() {
+                for e in entries.iter_mut().filter(|e| e.stripe == *stripe && e.is_inner()) {
+                    let Some(shard) = shards.get(e.parity_idx as usize) else { continue };
+                    let data = &shard[..(e.len as usize).min(shard.len())];
+                    f.seek(SeekFrom::Start(e.offset))?;
+                    f.write_all(data)?;
+                    e.hash = Some(*blake3::hash(data).as_bytes());
+                }
+            }
+            continue;
+        }
+        for (pi, shard) in shards.iter().enumerate() {
+            let hash = *blake3::hash(shard).as_bytes();
+            for c in 0..copies {
+                let (f, entries, end) = &mut vols[(pi + c) % vol_count];
+                f.seek(SeekFrom::Start(*end))?;
+                f.write_all(shard)?;
+                entries.push(VolumeEntry {
+                    stripe: *stripe,
+                    parity_idx: pi as u16,
+                    offset: *end,
+                    len: shard.len() as u32,
+                    hash: Some(hash),
+                    kind: ShardKind::Inner,
+                });
+                *end += shard.len() as u64;
+            }
+        }
+    }
+    for (_, entries, end) in vols.iter_mut() {
+        let dropped = |e: &VolumeEntry| e.is_inner() && e.stripe >= keep;
+        // The manifest backup is written anew after this; it may go
+        let backup = |e: &VolumeEntry| e.kind == ShardKind::ManifestBackup;
+        let cut = entries.iter().filter(|e| dropped(e)).map(|e| e.offset).min();
+        let kept_end = entries
+            .iter()
+            .filter(|e| !dropped(e) && !backup(e))
+            .map(|e| e.offset + e.len as u64)
+            .max();
+        if let Some(cut) = cut.filter(|&c| kept_end.map_or(true, |k| k <= c)) {
+            *end = cut;
+            entries.retain(|e| !(backup(e) && e.offset + e.len as u64 > cut));
+        }
+        entries.retain(|e| !dropped(e));
+    }
+    for ((f, entries, end), codec) in vols.iter_mut().zip(codecs) {
+        f.set_len(*end)?;
+        write_index_and_trailer_with(f, entries, &[], codec)?;
+        let mut hdr = VolumeHeader::read_from(&*f)?;
+        hdr.entries = crate::volume::entry_count(entries)?;
+        // Only replaced, never added: the header must keep its length
+        let key = crate::ext::key::GENERATION;
+        if let Some(g) = mf.generation().filter(|_| hdr.ext.get(key).is_some()) {
+            hdr.ext.insert_u64(key, g);
+        }
+        hdr.write_to(&*f)?;
+        f.sync_all()?;
+    }
+    Ok(())
+}
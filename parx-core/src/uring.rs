@@ -0,0 +1,264 @@
+//! Batched positioned reads and writes (feature `uring`).
+//!
+//! Verify, create and repair read chunks and shards at known offsets, one
+//! `seek` + `read_exact` after the other, which leaves NVMe arrays waiting
+//! on a single request at a time. [`read_at_many`] and [`write_at_many`]
+//! take a batch of requests instead. Built with `uring` on Linux, each
+//! thread submits its batches to an io_uring ring of its own, up to
+//! [`QUEUE_DEPTH`] requests in flight; where the kernel refuses to set up a
+//! ring (old kernels, seccomp filters in containers) and in every other
+//! build they are positioned reads and writes in turn, so callers need no
+//! fallback of their own. [`enabled`] tells whether batching pays off, for
+//! callers that have a cheaper path for single requests.
+
+use std::fs::File;
+use std::io;
+
+/// Requests in flight per ring.
+pub const QUEUE_DEPTH: usize = 64;
+
+/// Bytes a caller should gather into one batch at most.
+pub const BATCH_BYTES: usize = 32 << 20;
+
+/// Whether batches go to io_uring on this thread.
+pub fn enabled() -> bool {
+    sys::enabled()
+}
+
+/// Fill each buffer from its offset in `file`. One result per request, in
+/// order; a request running past the end of the file fails with
+/// `UnexpectedEof`, like `read_exact`.
+pub fn read_at_many(file: &File, reqs: &mut [(u64, &mut [u8])]) -> Vec<io::Result<()>> {
+    match sys::read_at_many(file, reqs) {
+        Some(res) => res,
+        None => reqs.iter_mut().map(|(off, buf)| read_exact_at(file, buf, *off)).collect(),
+    }
+}
+
+/// Write each buffer at its offset in `file`; the first error, if any.
+pub fn write_at_many(file: &File, reqs: &[(u64, &[u8])]) -> io::Result<()> {
+    match sys::write_at_many(file, reqs) {
+        Some(res) => res,
+        None => reqs.iter().try_for_each(|(off, buf)| write_all_at(file, buf, *off)),
+    }
+}
+
+#[cfg(unix)]
+pub(crate) fn read_exact_at(f: &File, buf: &mut [u8], off: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(f, buf, off)
+}
+
+#[cfg(windows)]
+pub(crate) fn read_exact_at(f: &File, mut buf: &mut [u8], mut off: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match f.seek_read(buf, off) {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => {
+                buf = &mut buf[n..];
+                off += n as u64;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+pub(crate) fn write_all_at(f: &File, buf: &[u8], off: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::write_all_at(f, buf, off)
+}
+
+#[cfg(windows)]
+pub(crate) fn write_all_at(f: &File, mut buf: &[u8], mut off: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match f.seek_write(buf, off) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(n) => {
+                buf = &buf[n..];
+                off += n as u64;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(all(feature = "uring", target_os = "linux"))]
+mod sys {
+    use super::{read_exact_at, write_all_at, QUEUE_DEPTH};
+    use io_uring::{opcode, types, IoUring};
+    use std::cell::RefCell;
+    use std::fs::File;
+    use std::io;
+    use std::os::unix::io::AsRawFd;
+
+    /// The thread's ring: not tried yet, set up, or refused by the kernel.
+    enum Ring {
+        Untried,
+        Ready(Box<IoUring>),
+        Unavailable,
+    }
+
+    thread_local! {
+        static RING: RefCell<Ring> = const { RefCell::new(Ring::Untried) };
+    }
+
+    /// Run `f` with the thread's ring; `None` without one.
+    fn with_ring<T>(f: impl FnOnce(&mut IoUring) -> io::Result<T>) -> Option<T> {
+        RING.with(|cell| {
+            let mut ring = cell.borrow_mut();
+            if matches!(*ring, Ring::Untried) {
+                *ring = match IoUring::new(QUEUE_DEPTH as u32) {
+                    Ok(r) => Ring::Ready(Box::new(r)),
+                    Err(_) => Ring::Unavailable,
+                };
+            }
+            let Ring::Ready(r) = &mut *ring else { return None };
+            match f(r) {
+                Ok(v) => Some(v),
+                Err(_) => {
+                    // Nothing is in flight any more (see `run`), but entries
+                    // the kernel did not take would go out with the next
+                    // submit: close this ring, which drops them, and use
+                    // none from now on
+                    *ring = Ring::Unavailable;
+                    None
+                }
+            }
+        })
+    }
+
+    pub(super) fn enabled() -> bool {
+        with_ring(|_| Ok(())).is_some()
+    }
+
+    /// `io_uring_enter` flag to wait for completions (`IORING_ENTER_GETEVENTS`).
+    const ENTER_GETEVENTS: u32 = 1;
+
+    /// Submit `entries` in rounds of at most [`QUEUE_DEPTH`] and wait for
+    /// all of them; the result (byte count or negated errno) of each, in
+    /// order. On error, every entry the kernel took has completed before
+    /// it returns, so none still points at the callers' buffers; entries it
+    /// did not take are left in the submission queue.
+    fn run(ring: &mut IoUring, entries: &[io_uring::squeue::Entry]) -> io::Result<Vec<i32>> {
+        let mut results = vec![0i32; entries.len()];
+        for (round, batch) in entries.chunks(QUEUE_DEPTH).enumerate() {
+            let base = round * QUEUE_DEPTH;
+            for (i, e) in batch.iter().enumerate() {
+                let e = e.clone().user_data((base + i) as u64);
+                // Safety: the buffers the entries point at outlive the round,
+                // which waits for every completion below
+                unsafe { ring.submission().push(&e) }.map_err(io::Error::other)?;
+            }
+            let mut done = 0;
+            while done < batch.len() {
+                let res = ring.submit_and_wait(batch.len() - done);
+                // Whatever left the submission queue is in the kernel's hands
+                let taken = batch.len() - ring.submission().len();
+                match res {
+                    Ok(_) => {}
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) if e.raw_os_error() == Some(libc::EBUSY) && done > 0 => {}
+                    Err(e) => {
+                        drain(ring, taken - done);
+                        return Err(e);
+                    }
+                }
+                for cqe in ring.completion() {
+                    results[cqe.user_data() as usize] = cqe.result();
+                    done += 1;
+                }
+            }
+        }
+        Ok(results)
+    }
+
+    /// Wait for `in_flight` completions without submitting anything more.
+    fn drain(ring: &mut IoUring, mut in_flight: usize) {
+        while in_flight > 0 {
+            // Safety: no submissions, only a wait for completions
+            let res = unsafe {
+                ring.submitter().enter::<libc::sigset_t>(0, in_flight as u32, ENTER_GETEVENTS, None)
+            };
+            match res {
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) if e.raw_os_error() == Some(libc::EBUSY) => {}
+                // The kernel would go on writing into buffers about to be
+                // freed; stopping here is the only safe way out
+                Err(e) => {
+                    eprintln!("parx: io_uring: cannot wait for requests in flight: {}", e);
+                    std::process::abort();
+                }
+            }
+            in_flight = in_flight.saturating_sub(ring.completion().count());
+        }
+    }
+
+    pub(super) fn read_at_many(
+        file: &File,
+        reqs: &mut [(u64, &mut [u8])],
+    ) -> Option<Vec<io::Result<()>>> {
+        let fd = types::Fd(file.as_raw_fd());
+        let entries: Vec<_> = reqs
+            .iter_mut()
+            .map(|(off, buf)| {
+                let len = buf.len().min(u32::MAX as usize) as u32;
+                opcode::Read::new(fd, buf.as_mut_ptr(), len).offset(*off).build()
+            })
+            .collect();
+        let results = with_ring(|ring| run(ring, &entries))?;
+        let out = reqs
+            .iter_mut()
+            .zip(results)
+            .map(|((off, buf), res)| match res {
+                n if n < 0 => Err(io::Error::from_raw_os_error(-n)),
+                // Short reads are rare (end of file, signals); finish them in place
+                n => read_exact_at(file, &mut buf[n as usize..], *off + n as u64),
+            })
+            .collect();
+        Some(out)
+    }
+
+    pub(super) fn write_at_many(file: &File, reqs: &[(u64, &[u8])]) -> Option<io::Result<()>> {
+        let fd = types::Fd(file.as_raw_fd());
+        let entries: Vec<_> = reqs
+            .iter()
+            .map(|(off, buf)| {
+                let len = buf.len().min(u32::MAX as usize) as u32;
+                opcode::Write::new(fd, buf.as_ptr(), len).offset(*off).build()
+            })
+            .collect();
+        let results = with_ring(|ring| run(ring, &entries))?;
+        let out = reqs.iter().zip(results).try_for_each(|((off, buf), res)| match res {
+            n if n < 0 => Err(io::Error::from_raw_os_error(-n)),
+            n => write_all_at(file, &buf[n as usize..], *off + n as u64),
+        });
+        Some(out)
+    }
+}
+
+#[cfg(not(all(feature = "uring", target_os = "linux")))]
+mod sys {
+    use std::fs::File;
+    use std::io;
+
+    pub(super) fn enabled() -> bool {
+        false
+    }
+
+    pub(super) fn read_at_many(
+        _file: &File,
+        _reqs: &mut [(u64, &mut [u8])],
+    ) -> Option<Vec<io::Result<()>>> {
+        None
+    }
+
+    pub(super) fn write_at_many(_file: &File, _reqs: &[(u64, &[u8])]) -> Option<io::Result<()>> {
+        None
+    }
+}
@@ -0,0 +1,197 @@
+//! Disk usage of a set (`parx du`): what the protected data takes, what its
+//! parity takes, what the metadata around it takes and what is left over.
+//! Volumes are split by their index: shard payloads are parity; the header,
+//! the index head copy space, manifest backups and everything from the index
+//! on are metadata; bytes none of those cover (old manifest backups and
+//! shards of stripes that `update`, `forget` or `rebalance` dropped or moved
+//! past) are garbage. Only the manifest, the volume headers and the indices
+//! are read.
+
+use crate::index::{read_index, read_trailer, IndexCopy, IndexLimits};
+use crate::manifest::{self, MANIFEST_JSON};
+use crate::volume::{ShardKind, VolumeHeader};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::path::Path;
+
+/// Files of the parity dir besides volumes that belong to the set.
+const METADATA_FILES: &[&str] = &[
+    manifest::MANIFEST_JSON,
+    manifest::MANIFEST_V2,
+    crate::filter::FILTER_FILE,
+    crate::hashcache::CACHE_FILE,
+    crate::paritycache::CACHE_FILE,
+    crate::fshint::HINTS_FILE,
+    crate::audit_log::AUDIT_LOG,
+    crate::volparity::DESCRIPTOR,
+    crate::journal::JOURNAL_FILE,
+    ".parx.repair.lock",
+];
+
+/// Dirs of the parity dir holding set metadata.
+const METADATA_DIRS: &[&str] = &[crate::submanifest::SUB_DIR, crate::live::RUNNING_DIR];
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct VolumeUsage {
+    pub volume: String,
+    pub path: String,
+    /// Size of the file
+    pub bytes: u64,
+    pub parity_bytes: u64,
+    pub metadata_bytes: u64,
+    pub garbage_bytes: u64,
+    /// Why the volume could not be split up; its bytes count as other
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct UsageReport {
+    pub parity_dir: String,
+    pub files: u64,
+    /// Bytes of data the set protects
+    pub source_bytes: u64,
+    /// Parity shards in the volumes, every copy counted
+    pub parity_bytes: u64,
+    /// Volume parity files (`create --volume-parity`)
+    pub volume_parity_bytes: u64,
+    /// Volume headers, indices and manifest backups, plus the manifests,
+    /// filter, caches and logs in the parity dir
+    pub metadata_bytes: u64,
+    /// Bytes in volumes that nothing refers to any more; a re-create gives
+    /// them back
+    pub garbage_bytes: u64,
+    /// Chunk slots below the end of the set that no file uses
+    pub holes: u64,
+    /// Parity `parx rebalance` would free by moving chunks into the holes
+    pub rebalance_reclaimable_bytes: u64,
+    /// Retained versions (`create --keep-versions`)
+    pub versions_bytes: u64,
+    /// Other files in the parity dir (recovery stub, unreadable volumes,
+    /// unknown files); named sets under `sets/` are not counted
+    pub other_bytes: u64,
+    /// Parity, volume parity, metadata, garbage and other bytes in percent
+    /// of the source bytes
+    pub overhead_pct: f64,
+    pub volumes: Vec<VolumeUsage>,
+}
+
+impl crate::report::Report for UsageReport {
+    const KIND: &'static str = "usage";
+}
+
+/// Disk usage of the set in `parity_dir` (its volumes wherever
+/// `--output-dirs` put them).
+pub fn usage(parity_dir: &Path) -> Result<UsageReport> {
+    let (mf, _) = manifest::load(&parity_dir.join(MANIFEST_JSON))?;
+    let geo = mf.geometry();
+    let live: BTreeSet<u64> =
+        mf.files.iter().flat_map(|f| f.chunks.iter().map(|c| c.idx)).collect();
+    let compact = (live.len() as u64).div_ceil(geo.k as u64);
+    let mut rep = UsageReport {
+        parity_dir: parity_dir.display().to_string(),
+        files: mf.files.len() as u64,
+        source_bytes: mf.total_bytes,
+        holes: geo.total_chunks.saturating_sub(live.len() as u64),
+        rebalance_reclaimable_bytes: geo.stripes.saturating_sub(compact)
+            * geo.m as u64
+            * geo.chunk_size as u64
+            * mf.shard_copies.max(1) as u64,
+        ..Default::default()
+    };
+    let mut names = BTreeSet::new();
+    for vid in 0..mf.volumes.max(1) {
+        let path = mf.volume_path(parity_dir, vid);
+        let mut vu = VolumeUsage {
+            volume: mf.volume_name(vid),
+            path: path.display().to_string(),
+            ..Default::default()
+        };
+        match split_volume(&path, &mut vu) {
+            Ok(()) => {
+                rep.parity_bytes += vu.parity_bytes;
+                rep.metadata_bytes += vu.metadata_bytes;
+                rep.garbage_bytes += vu.garbage_bytes;
+            }
+            Err(e) => {
+                vu.error = Some(format!("{:#}", e));
+                rep.other_bytes += vu.bytes;
+            }
+        }
+        names.insert(vu.volume.clone());
+        rep.volumes.push(vu);
+    }
+
+    for ent in std::fs::read_dir(parity_dir)? {
+        let ent = ent?;
+        let name = ent.file_name().to_string_lossy().into_owned();
+        let path = ent.path();
+        if ent.file_type()?.is_dir() {
+            let bytes = dir_bytes(&path);
+            if name == crate::sets::SETS_DIR {
+                continue;
+            } else if name == crate::versions::VERSIONS_DIR {
+                rep.versions_bytes += bytes;
+            } else if METADATA_DIRS.contains(&name.as_str()) {
+                rep.metadata_bytes += bytes;
+            } else {
+                rep.other_bytes += bytes;
+            }
+            continue;
+        }
+        if names.contains(&name) {
+            continue;
+        }
+        let bytes = ent.metadata()?.len();
+        if path.extension().is_some_and(|x| x == "parxp") {
+            rep.volume_parity_bytes += bytes;
+        } else if METADATA_FILES.contains(&name.as_str()) {
+            rep.metadata_bytes += bytes;
+        } else {
+            rep.other_bytes += bytes;
+        }
+    }
+    let overhead = rep.parity_bytes
+        + rep.volume_parity_bytes
+        + rep.metadata_bytes
+        + rep.garbage_bytes
+        + rep.other_bytes;
+    if rep.source_bytes > 0 {
+        rep.overhead_pct = overhead as f64 * 100.0 / rep.source_bytes as f64;
+    }
+    Ok(rep)
+}
+
+/// Fill in the parity, metadata and garbage bytes of the volume at `path`.
+fn split_volume(path: &Path, vu: &mut VolumeUsage) -> Result<()> {
+    let mut f = File::open(path)?;
+    vu.bytes = f.metadata()?.len();
+    let hdr = VolumeHeader::read_from(&f)?;
+    let (off, len, crc) = read_trailer(&mut f)?;
+    let entries = read_index(&mut f, off, len, crc, &IndexLimits::default())?;
+    let mut meta = hdr.encoded_len() + vu.bytes.saturating_sub(off);
+    if let Some(copy) = IndexCopy::from_ext(&hdr.ext) {
+        meta += copy.cap;
+    }
+    for e in &entries {
+        match e.kind {
+            ShardKind::ManifestBackup => meta += e.len as u64,
+            _ => vu.parity_bytes += e.len as u64,
+        }
+    }
+    vu.metadata_bytes = meta.min(vu.bytes.saturating_sub(vu.parity_bytes));
+    vu.garbage_bytes = vu.bytes.saturating_sub(vu.parity_bytes + vu.metadata_bytes);
+    Ok(())
+}
+
+fn dir_bytes(dir: &Path) -> u64 {
+    walkdir::WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .filter_map(|e| e.metadata().ok())
+        .map(|m| m.len())
+        .sum()
+}
@@ -1,17 +1,187 @@
-use crate::manifest::Manifest;
-use crate::merkle;
+use crate::fdlimit::FdLimit;
+use crate::manifest;
+use crate::manifest_v2::RecoveryReport;
+use crate::merkle::{self, ChunkHasher};
+use crate::moved::MovedFile;
 use crate::path_safety::{validate_path, PathPolicy};
+use crate::retry::{RetryPolicy, RetryStats};
+use crate::storage::DataSource;
+#[cfg(feature = "http")]
+use crate::storage::HttpSource;
+use crate::throttle::Throttle;
 use anyhow::{Context, Result};
+#[cfg(feature = "parallel")]
 use rayon::prelude::*;
-use std::fs::File;
+use std::collections::{HashMap, HashSet};
 use std::io::{Read, Seek, SeekFrom};
 use std::path::Path;
+use std::time::Duration;
 
-#[derive(Debug, Clone, serde::Serialize)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct VerifyReport {
     pub chunks_ok: u64,
     pub chunks_bad: u64,
     pub merkle_ok: bool,
+    /// Chunks found intact at another offset of their file, because bytes
+    /// were inserted or deleted before them (see [`crate::resync`]); they are
+    /// not counted in `chunks_bad`, and `repair` moves them back
+    #[serde(default)]
+    pub chunks_displaced: u64,
+    /// Files not read because they were last verified more recently than
+    /// `--older-than`; their chunks are in neither count
+    #[serde(default)]
+    pub files_skipped: u64,
+    /// Missing files whose contents were found intact under another name
+    /// (see [`crate::moved`]); their chunks count as ok
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub moved_files: Vec<MovedFile>,
+    /// Files with bad chunks, each with what is wrong with it
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub damaged_files: Vec<FileDamage>,
+    /// Files skipped because their reads stalled past the I/O timeout (likely
+    /// failing hardware); their chunks count as bad
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stalled_files: Vec<String>,
+    /// Recorded symlinks that are missing or point elsewhere
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub symlinks_bad: Vec<String>,
+    /// Time spent paused by `--auto-throttle`, in milliseconds
+    #[serde(default)]
+    pub throttled_ms: u64,
+    /// Reads and opens tried again after a transient error (see
+    /// [`crate::retry`]), and how many of them then succeeded
+    #[serde(default)]
+    pub io_retries: u64,
+    #[serde(default)]
+    pub io_recovered: u64,
+    /// Present when manifest.json was unreadable and the v2 companion was used,
+    /// or when the manifest backup read with `--from-volume` was damaged.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub manifest_recovery: Option<RecoveryReport>,
+    /// Present when the volume indices list fewer parity shards for some
+    /// stripes than the set was made with, whatever the state of the data
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parity_shortfall: Option<ParityShortfall>,
+}
+
+impl crate::report::Report for VerifyReport {
+    const KIND: &'static str = "verify";
+}
+
+/// Stripes left with less parity than the set was made with, going by the
+/// volume indices: the protection degraded even if no data is damaged yet.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ParityShortfall {
+    /// Stripes missing some of their parity shards
+    pub stripes_degraded: u64,
+    /// Stripes with no parity shard left, where a single lost chunk cannot
+    /// be repaired
+    pub stripes_unprotected: u64,
+    /// Fewest parity shards left on a degraded stripe
+    pub min_parity_left: usize,
+    /// Volumes that are missing or whose index could not be read
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub volumes_unreadable: Vec<String>,
+    /// Volumes of another set or another generation of this one, as
+    /// `<volume> (<why>)`; their shards are not counted
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub volumes_stray: Vec<String>,
+}
+
+/// Why the chunks of a file failed verification.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum DamageKind {
+    /// Some chunks no longer match their hash (bit rot, in-place edits)
+    ContentMismatch,
+    /// The file is shorter than recorded; chunks past its end are bad
+    Truncated,
+    /// Nothing at the recorded path
+    Missing,
+    /// The file could not be opened for lack of permission
+    PermissionDenied,
+    /// The file could not be opened or read for another reason (see `error`)
+    Unreadable,
+    /// Reads kept failing with errors that look transient (a dropped network
+    /// mount, a stale handle) through every retry; the file may well be
+    /// intact, and a later run can tell
+    Unreachable,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct FileDamage {
+    pub path: String,
+    pub kind: DamageKind,
+    pub chunks_bad: u64,
+    /// The open or read error, for `Unreadable` files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// How a file that could not be opened is classified.
+/// Classify the error that stopped checking a file by its I/O cause.
+fn file_damage(e: &anyhow::Error) -> (DamageKind, Option<String>) {
+    let io = e.chain().find_map(|c| c.downcast_ref::<std::io::Error>());
+    match io.map(|io| io.kind()) {
+        Some(std::io::ErrorKind::NotFound) => (DamageKind::Missing, None),
+        Some(std::io::ErrorKind::PermissionDenied) => (DamageKind::PermissionDenied, None),
+        _ if io.is_some_and(crate::retry::is_transient) => {
+            (DamageKind::Unreachable, Some(format!("{:#}", e)))
+        }
+        _ => (DamageKind::Unreadable, Some(format!("{:#}", e))),
+    }
+}
+
+/// A file whose check failed: every chunk is bad and the error is kept.
+fn failed_file(chunks: usize, e: &anyhow::Error) -> FileResult {
+    let lost = vec![blake3::hash(&[]); chunks];
+    (0, chunks as u64, 0, lost, None, Some(file_damage(e)))
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct VerifyOptions {
+    pub policy: PathPolicy,
+    /// Give up on a file whose reads make no progress for this long
+    pub io_timeout: Option<Duration>,
+    /// Pause between chunks while the system is busy with I/O
+    pub throttle: Option<Throttle>,
+    /// Files open at once across all workers (see [`crate::fdlimit`])
+    pub max_open_files: Option<usize>,
+    /// Try opens and reads failing with transient errors again (network
+    /// mounts)
+    pub retry: RetryPolicy,
+    /// Refuse to verify unless the manifest carries a valid signature by this key
+    #[cfg(feature = "full")]
+    pub verify_key: Option<ed25519_dalek::VerifyingKey>,
+    /// Only read files last found intact longer ago than this (or never), by
+    /// the time the hash cache recorded for them; unchanged files checked
+    /// more recently are skipped
+    #[cfg(feature = "full")]
+    pub older_than: Option<Duration>,
+    /// Look for missing files under other names (see [`crate::moved`])
+    #[cfg(feature = "full")]
+    pub detect_moves: bool,
+    /// Count bytes and files into the stage `verify` of this registry (see
+    /// [`crate::progress`])
+    pub progress: Option<crate::progress::Progress>,
+    /// Key of a set made with `create --hash-key`; required for those
+    pub hash_key: Option<manifest::HashKey>,
+    /// Read files with sequential read-ahead and drop their pages from the
+    /// cache once checked (see [`crate::pagecache`])
+    pub drop_cache: bool,
+}
+
+/// The manifest's files, in parallel when built with `parallel`.
+#[cfg(feature = "parallel")]
+fn files(mf: &manifest::Manifest) -> rayon::slice::Iter<'_, manifest::FileEntry> {
+    mf.files.par_iter()
+}
+
+#[cfg(not(feature = "parallel"))]
+fn files(mf: &manifest::Manifest) -> std::slice::Iter<'_, manifest::FileEntry> {
+    mf.files.iter()
 }
 
 pub fn verify(manifest_path: &Path, root: &Path) -> Result<VerifyReport> {
@@ -23,44 +193,460 @@ pub fn verify_with_policy(
     root: &Path,
     policy: PathPolicy,
 ) -> Result<VerifyReport> {
-    let mf: Manifest =
-        serde_json::from_reader(File::open(manifest_path)?).context("read manifest.json")?;
-    let per_file: Result<Vec<(u64, u64, Vec<blake3::Hash>)>> = mf
-        .files
-        .par_iter()
-        .map(|fe| -> Result<(u64, u64, Vec<blake3::Hash>)> {
-            let path = validate_path(root, Path::new(&fe.rel_path), policy)
-                .with_context(|| format!("validate path {:?}", fe.rel_path))?;
-            let mut f = File::open(&path).with_context(|| format!("open {:?}", path))?;
-            let mut ok = 0u64;
-            let mut bad = 0u64;
-            let mut hashes = Vec::with_capacity(fe.chunks.len());
-            for ch in &fe.chunks {
-                let mut buf = vec![0u8; mf.chunk_size];
-                f.seek(SeekFrom::Start(ch.file_offset))?;
-                let mut small = vec![0u8; ch.len as usize];
-                f.read_exact(&mut small)?;
-                buf[..small.len()].copy_from_slice(&small);
-                let h = blake3::hash(&buf);
-                if h.to_hex().to_string() == ch.hash_hex {
-                    ok += 1;
-                } else {
-                    bad += 1;
+    verify_with_options(manifest_path, root, &VerifyOptions { policy, ..Default::default() })
+}
+
+/// Stamp of a file that held still while it was read (see `hashcache`)
+#[cfg(feature = "full")]
+type Stamp = Option<crate::fshint::FileStamp>;
+#[cfg(not(feature = "full"))]
+type Stamp = Option<()>;
+
+#[cfg(feature = "full")]
+fn stamp_of(path: &Path) -> Stamp {
+    crate::hashcache::stamp_of(path)
+}
+
+#[cfg(not(feature = "full"))]
+fn stamp_of(_path: &Path) -> Stamp {
+    None
+}
+
+/// Chunks ok, bad and displaced, all chunk hashes, the stamp of an intact
+/// file, and what is wrong with a damaged one
+type FileResult = (u64, u64, u64, Vec<blake3::Hash>, Stamp, Option<(DamageKind, Option<String>)>);
+
+/// The chunk hashes the manifest records for `fe`.
+fn recorded_hashes(fe: &manifest::FileEntry) -> Result<Vec<blake3::Hash>> {
+    fe.chunks
+        .iter()
+        .map(|c| blake3::Hash::from_hex(&c.hash_hex))
+        .collect::<Result<_, _>>()
+        .with_context(|| format!("chunk hash of {:?}", fe.rel_path))
+}
+
+/// Files that `opts.older_than` skips: unchanged since the hash cache in
+/// `cache_dir` last found them intact, and that recently.
+#[cfg(feature = "full")]
+fn recently_verified<'a>(
+    mf: &'a manifest::Manifest,
+    root: &Path,
+    cache_dir: Option<&Path>,
+    opts: &VerifyOptions,
+) -> HashSet<&'a str> {
+    let (Some(age), Some(dir)) = (opts.older_than, cache_dir) else { return HashSet::new() };
+    let cache = crate::hashcache::HashCache::load(dir);
+    mf.files
+        .iter()
+        .filter(|fe| {
+            validate_path(root, Path::new(&fe.rel_path), opts.policy)
+                .ok()
+                .and_then(|p| stamp_of(&p))
+                .is_some_and(|st| cache.holds(&fe.rel_path, &st, &fe.chunks, age))
+        })
+        .map(|fe| fe.rel_path.as_str())
+        .collect()
+}
+
+/// Files found intact are recorded in the set's hash cache, so that a repair
+/// or update right after does not read them again.
+pub fn verify_with_options(
+    manifest_path: &Path,
+    root: &Path,
+    opts: &VerifyOptions,
+) -> Result<VerifyReport> {
+    let (mut mf, manifest_recovery) = manifest::load(manifest_path)?;
+    mf.use_hash_key(opts.hash_key.clone())?;
+    #[cfg(feature = "full")]
+    let cache_dir = Some(mf.parity_dir_at(manifest_path));
+    #[cfg(not(feature = "full"))]
+    let cache_dir = None;
+    let report = verify_manifest(&mf, manifest_recovery, root, cache_dir, opts)?;
+    #[cfg(feature = "full")]
+    let report = VerifyReport {
+        parity_shortfall: parity_shortfall(&mf, &mf.parity_dir_at(manifest_path)),
+        ..report
+    };
+    Ok(report)
+}
+
+/// Compare the inner parity shards the volume indices in `parity_dir` list
+/// for each stripe with what the set was made with (critical-file extras
+/// not counted). Only the indices are read: a listed shard whose bytes are
+/// on disk counts as there, and `paritycheck --deep` is what hashes them.
+#[cfg(feature = "full")]
+pub fn parity_shortfall(mf: &manifest::Manifest, parity_dir: &Path) -> Option<ParityShortfall> {
+    use crate::index::{read_index, read_trailer, IndexLimits};
+    use crate::volume::ShardKind;
+    let geo = mf.geometry();
+    if mf.is_empty() || mf.max_parity_shards() == 0 {
+        return None;
+    }
+    let mut held: HashMap<u64, HashSet<u16>> = HashMap::new();
+    let mut volumes_unreadable = Vec::new();
+    let mut volumes_stray = Vec::new();
+    let tag = mf.set_tag();
+    for vid in 0..mf.volumes.max(1) {
+        let name = mf.volume_name(vid);
+        let listed = || -> Result<_> {
+            let mut f = std::fs::File::open(mf.volume_path(parity_dir, vid))?;
+            let hdr = crate::volume::VolumeHeader::read_from(&f);
+            let theirs = hdr.ok().map(|h| manifest::SetTag::from_ext(&h.ext));
+            if let Some(stray) = theirs.and_then(|t| tag.stray(&t)) {
+                return Ok(Err(stray));
+            }
+            let end = f.metadata()?.len();
+            let (off, len, crc) = read_trailer(&mut f)?;
+            let entries = read_index(&mut f, off, len, crc, &IndexLimits::default())?;
+            Ok(Ok(entries.into_iter().filter(move |e| {
+                e.kind == ShardKind::Inner && e.offset.saturating_add(e.len as u64) <= end
+            })))
+        };
+        match listed() {
+            Ok(Err(stray)) => volumes_stray.push(format!("{} ({})", name, stray)),
+            Ok(Ok(entries)) => {
+                for e in entries {
+                    held.entry(e.stripe).or_default().insert(e.parity_idx);
+                }
+            }
+            Err(_) => volumes_unreadable.push(name),
+        }
+    }
+    let mut short = ParityShortfall { min_parity_left: usize::MAX, ..Default::default() };
+    for s in 0..geo.stripes {
+        let want = mf.parity_shards_of(s);
+        let have = held.get(&s).map_or(0, |h| h.iter().filter(|&&pi| (pi as usize) < want).count());
+        if have < want {
+            short.stripes_degraded += 1;
+            short.stripes_unprotected += u64::from(have == 0);
+            short.min_parity_left = short.min_parity_left.min(have);
+        }
+    }
+    short.volumes_unreadable = volumes_unreadable;
+    short.volumes_stray = volumes_stray;
+    (short.stripes_degraded > 0).then_some(short)
+}
+
+/// Verify against the manifest backed up inside `volume`, for sets whose
+/// manifest files were lost.
+#[cfg(feature = "full")]
+pub fn verify_from_volume(
+    volume: &Path,
+    root: &Path,
+    opts: &VerifyOptions,
+) -> Result<VerifyReport> {
+    let (mut mf, manifest_recovery) = crate::manifest_backup::read(volume)?;
+    mf.use_hash_key(opts.hash_key.clone())?;
+    verify_manifest(&mf, manifest_recovery, root, None, opts)
+}
+
+/// How the files of a run are read, shared by its workers.
+#[derive(Clone)]
+struct FileIo {
+    throttle: Option<Throttle>,
+    fds: FdLimit,
+    retry: RetryPolicy,
+    stats: RetryStats,
+    drop_cache: bool,
+}
+
+/// Read and hash every chunk of the file at `path`.
+fn check_file(
+    path: &Path,
+    chunks: &[manifest::ChunkRef],
+    size: u64,
+    hasher: ChunkHasher,
+    io: &FileIo,
+    ticker: &crate::watchdog::Ticker,
+) -> Result<FileResult> {
+    let before = stamp_of(path);
+    let (mut f, _permit) = io
+        .retry
+        .run(&io.stats, || ticker.tick(), |_| io.fds.open(path))
+        .with_context(|| format!("open {:?}", path))?;
+    let md = f.metadata().with_context(|| format!("stat {:?}", path))?;
+    if !md.is_file() {
+        let lost = vec![blake3::hash(&[]); chunks.len()];
+        let damage = (DamageKind::Unreadable, Some("not a regular file".to_string()));
+        return Ok((0, chunks.len() as u64, 0, lost, None, Some(damage)));
+    }
+    if io.drop_cache {
+        crate::pagecache::sequential(&f);
+    }
+    let short = md.len() < size;
+    let mut ok = 0u64;
+    let mut bad = 0u64;
+    let mut hashes = Vec::with_capacity(chunks.len());
+    let (mut intact, mut failed) = (Vec::new(), Vec::new());
+    let per_batch = match crate::uring::enabled() {
+        true => (crate::uring::BATCH_BYTES / hasher.chunk_size.max(1))
+            .clamp(1, crate::uring::QUEUE_DEPTH),
+        false => 1,
+    };
+    for group in chunks.chunks(per_batch) {
+        if let Some(t) = &io.throttle {
+            t.pause_with(|| ticker.tick());
+        }
+        let mut bufs: Vec<Vec<u8>> = group.iter().map(|ch| vec![0u8; ch.len as usize]).collect();
+        // Chunks that fail as a batch are read again below, with retries
+        let batched: Vec<Option<std::io::Result<()>>> = if group.len() > 1 {
+            let mut reqs: Vec<(u64, &mut [u8])> =
+                group.iter().zip(&mut bufs).map(|(ch, b)| (ch.file_offset, &mut b[..])).collect();
+            crate::uring::read_at_many(&f, &mut reqs).into_iter().map(Some).collect()
+        } else {
+            group.iter().map(|_| None).collect()
+        };
+        for ((ch, mut buf), batched) in group.iter().zip(bufs).zip(batched) {
+            let read = match batched {
+                Some(Ok(())) => Ok(()),
+                Some(Err(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => Err(e),
+                _ => io.retry.run(
+                    &io.stats,
+                    || ticker.tick(),
+                    |attempt| {
+                        if attempt > 0 {
+                            // A handle gone stale stays so; read through a fresh one
+                            f = std::fs::File::open(path)?;
+                        }
+                        f.seek(SeekFrom::Start(ch.file_offset))?;
+                        f.read_exact(&mut buf)
+                    },
+                ),
+            };
+            // A chunk cut off by a shortened file is bad, like in repair
+            let whole = match read {
+                Ok(()) => true,
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => false,
+                Err(e) => return Err(e).with_context(|| format!("read {:?}", path)),
+            };
+            ticker.tick();
+            let h = hasher.hash(&buf);
+            if whole && h.to_hex().to_string() == ch.hash_hex {
+                ok += 1;
+                intact.push(ch);
+            } else {
+                bad += 1;
+                failed.push(ch);
+            }
+            hashes.push(h);
+        }
+    }
+    let stamp = before.filter(|b| bad == 0 && stamp_of(path).as_ref() == Some(b));
+    // Chunks that only moved are not damaged
+    let displaced = if failed.is_empty() {
+        0
+    } else {
+        crate::resync::locate(path, &failed, &intact, &hasher, &|| ticker.tick())
+            .map_or(0, |f| f.len() as u64)
+    };
+    if io.drop_cache {
+        crate::pagecache::evict(&f);
+    }
+    let bad = bad - displaced;
+    let damage = match (short, bad) {
+        (true, _) => Some((DamageKind::Truncated, None)),
+        (false, 0) => None,
+        (false, _) => Some((DamageKind::ContentMismatch, None)),
+    };
+    Ok((ok, bad, displaced, hashes, stamp, damage))
+}
+
+fn verify_manifest(
+    mf: &manifest::Manifest,
+    manifest_recovery: Option<RecoveryReport>,
+    root: &Path,
+    cache_dir: Option<std::path::PathBuf>,
+    opts: &VerifyOptions,
+) -> Result<VerifyReport> {
+    #[cfg(feature = "full")]
+    if let Some(vk) = &opts.verify_key {
+        crate::sign::verify_manifest(mf, Some(vk))?;
+    }
+    let hasher = mf.hasher();
+    #[cfg(feature = "full")]
+    let recent = recently_verified(mf, root, cache_dir.as_deref(), opts);
+    #[cfg(not(feature = "full"))]
+    let recent: HashSet<&str> = HashSet::new();
+    #[cfg(feature = "full")]
+    let moved_files =
+        if opts.detect_moves { crate::moved::detect(mf, root, opts.policy)? } else { Vec::new() };
+    #[cfg(not(feature = "full"))]
+    let moved_files: Vec<MovedFile> = Vec::new();
+    let io = FileIo {
+        throttle: opts.throttle.clone(),
+        fds: FdLimit::new(opts.max_open_files),
+        retry: opts.retry,
+        stats: RetryStats::default(),
+        drop_cache: opts.drop_cache,
+    };
+    // Moved files were hashed whole while they were looked for
+    let moved: HashMap<&str, &str> =
+        moved_files.iter().map(|m| (m.from.as_str(), m.to.as_str())).collect();
+    let check = |fe: &manifest::FileEntry| -> Result<Option<FileResult>> {
+        if recent.contains(fe.rel_path.as_str()) {
+            // The merkle root is checked with the recorded hashes
+            return Ok(Some((0, 0, 0, recorded_hashes(fe)?, None, None)));
+        }
+        if moved.contains_key(fe.rel_path.as_str()) {
+            let n = fe.chunks.len() as u64;
+            return Ok(Some((n, 0, 0, recorded_hashes(fe)?, None, None)));
+        }
+        let path = validate_path(root, Path::new(&fe.rel_path), opts.policy)
+            .with_context(|| format!("validate path {:?}", fe.rel_path))?;
+        let chunks = fe.chunks.clone();
+        let size = fe.size;
+        let io = io.clone();
+        // Per-file I/O failures are reported, not fatal to the run
+        crate::watchdog::watched(opts.io_timeout, move |ticker| {
+            check_file(&path, &chunks, size, hasher, &io, ticker)
+                .unwrap_or_else(|e| failed_file(chunks.len(), &e))
+        })
+    };
+    let progress = opts.progress.clone().unwrap_or_default();
+    let stage = progress.stage("verify");
+    stage.add_total(mf.files.iter().map(|fe| fe.size).sum(), mf.files.len() as u64);
+    let counted = |fe: &manifest::FileEntry| {
+        progress.check_cancelled()?;
+        stage.set_current(&fe.rel_path);
+        let res = check(fe);
+        stage.add_bytes(fe.size);
+        stage.add_items(1);
+        res
+    };
+    let per_file: Result<Vec<Option<FileResult>>> = files(mf).map(counted).collect();
+    stage.finish();
+    let mut chunks_ok = 0u64;
+    let mut chunks_bad = 0u64;
+    let mut chunks_displaced = 0u64;
+    let files_skipped = recent.len() as u64;
+    let mut all_hashes = Vec::new();
+    let mut damaged_files = Vec::new();
+    let mut stalled_files = Vec::new();
+    let mut intact = Vec::new();
+    for (fe, res) in mf.files.iter().zip(per_file?) {
+        match res {
+            Some((ok, bad, displaced, hashes, stamp, damage)) => {
+                chunks_ok += ok;
+                chunks_bad += bad;
+                chunks_displaced += displaced;
+                all_hashes.extend(hashes);
+                if let Some(stamp) = stamp {
+                    intact.push((fe, stamp));
                 }
-                hashes.push(h);
+                if let Some((kind, error)) = damage {
+                    let path = fe.rel_path.clone();
+                    damaged_files.push(FileDamage { path, kind, chunks_bad: bad, error });
+                }
+            }
+            None => {
+                chunks_bad += fe.chunks.len() as u64;
+                stalled_files.push(fe.rel_path.clone());
+            }
+        }
+    }
+    let merkle_ok = stalled_files.is_empty()
+        && merkle::root(&all_hashes).to_hex().to_string() == mf.merkle_root_hex;
+    #[cfg(feature = "full")]
+    if let Some(dir) = cache_dir.filter(|_| !intact.is_empty()) {
+        let mut cache = crate::hashcache::HashCache::load(&dir);
+        for (fe, stamp) in intact {
+            cache.insert(&fe.rel_path, stamp, &fe.chunks);
+        }
+        // Best effort: verify must work on read-only parity dirs
+        let _ = cache.save(&dir);
+    }
+    #[cfg(not(feature = "full"))]
+    let _ = (cache_dir, intact);
+    let mut symlinks_bad = Vec::new();
+    for link in &mf.symlinks {
+        if !crate::symlink::is_intact(root, link, opts.policy)? {
+            symlinks_bad.push(link.rel_path.clone());
+        }
+    }
+    Ok(VerifyReport {
+        chunks_ok,
+        chunks_bad,
+        merkle_ok,
+        chunks_displaced,
+        files_skipped,
+        moved_files,
+        damaged_files,
+        stalled_files,
+        symlinks_bad,
+        throttled_ms: opts.throttle.as_ref().map_or(0, |t| t.paused().as_millis() as u64),
+        io_retries: io.stats.retries(),
+        io_recovered: io.stats.recovered(),
+        manifest_recovery,
+        parity_shortfall: None,
+    })
+}
+
+/// Verify a remote copy published under `base_url` (read-only, HTTP range requests).
+#[cfg(feature = "http")]
+pub fn verify_remote(manifest_path: &Path, base_url: &str) -> Result<VerifyReport> {
+    let src = HttpSource::new(base_url)?;
+    verify_with_source(manifest_path, &src)
+}
+
+/// Verify against any `DataSource`, fetching exactly the manifest's chunk ranges.
+pub fn verify_with_source(manifest_path: &Path, src: &dyn DataSource) -> Result<VerifyReport> {
+    let (mf, manifest_recovery) = manifest::load(manifest_path)?;
+    mf.require_plain_hashes("verify --remote")?;
+    let check = |fe: &manifest::FileEntry| -> Result<(u64, u64, Vec<blake3::Hash>)> {
+        let mut ok = 0u64;
+        let mut bad = 0u64;
+        let mut hashes = Vec::with_capacity(fe.chunks.len());
+        for ch in &fe.chunks {
+            let mut buf = vec![0u8; ch.len as usize];
+            src.read_at(&fe.rel_path, ch.file_offset, &mut buf)
+                .with_context(|| format!("fetch {:?} from {}", fe.rel_path, src.describe()))?;
+            let h = merkle::chunk_hash(&buf, mf.chunk_size);
+            if h.to_hex().to_string() == ch.hash_hex {
+                ok += 1;
+            } else {
+                bad += 1;
+            }
+            hashes.push(h);
+        }
+        Ok((ok, bad, hashes))
+    };
+    let per_file: Vec<FileResult> = files(&mf)
+        .map(|fe| match check(fe) {
+            Ok((ok, bad, hashes)) => {
+                let damage = (bad > 0).then_some((DamageKind::ContentMismatch, None));
+                (ok, bad, 0, hashes, None, damage)
             }
-            Ok((ok, bad, hashes))
+            Err(e) => failed_file(fe.chunks.len(), &e),
         })
         .collect();
-    let per_file = per_file?;
     let mut chunks_ok = 0u64;
     let mut chunks_bad = 0u64;
     let mut all_hashes = Vec::new();
-    for (ok, bad, hashes) in per_file {
+    let mut damaged_files = Vec::new();
+    for (fe, (ok, bad, _, hashes, _, damage)) in mf.files.iter().zip(per_file) {
         chunks_ok += ok;
         chunks_bad += bad;
         all_hashes.extend(hashes);
+        if let Some((kind, error)) = damage {
+            let path = fe.rel_path.clone();
+            damaged_files.push(FileDamage { path, kind, chunks_bad: bad, error });
+        }
     }
     let merkle_ok = merkle::root(&all_hashes).to_hex().to_string() == mf.merkle_root_hex;
-    Ok(VerifyReport { chunks_ok, chunks_bad, merkle_ok })
+    Ok(VerifyReport {
+        chunks_ok,
+        chunks_bad,
+        merkle_ok,
+        chunks_displaced: 0,
+        files_skipped: 0,
+        moved_files: Vec::new(),
+        damaged_files,
+        stalled_files: Vec::new(),
+        symlinks_bad: Vec::new(),
+        throttled_ms: 0,
+        io_retries: 0,
+        io_recovered: 0,
+        manifest_recovery,
+        parity_shortfall: None,
+    })
 }
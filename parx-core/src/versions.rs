@@ -0,0 +1,680 @@
+//! Multi-version parity store: `.parx/versions/graph.json` plus one archived
+//! manifest + volume set per retained version under `.parx/versions/vN/`.
+//!
+//! Versions share chunks: an archive keeps only the parity of stripes with
+//! some chunk the next version lacks, and a restore takes the rest from the
+//! tree or rebuilds it from the newer versions.
+
+use crate::codec::Codecs;
+use crate::index::{read_index, read_trailer, write_index_and_trailer_with, IndexLimits};
+use crate::manifest::{self, Manifest};
+use crate::meta::{self, ChownMap, MetaReport};
+use crate::outer::OuterLayout;
+use crate::path_safety::{validate_path, PathPolicy};
+use crate::repair::{collect_parity_copies, repair_with_outer, ChunkReader, VolumeSource};
+use crate::rs_codec::{RsCodec, RsField};
+use crate::storage::ReadCost;
+use crate::uring::{read_exact_at, write_all_at};
+use crate::volume::{check_features, ReadMode, ShardKind, VolumeEntry, VolumeHeader};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+pub const VERSIONS_DIR: &str = "versions";
+const GRAPH_FILE: &str = "graph.json";
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct VersionNode {
+    pub id: u32,
+    pub parent: Option<u32>,
+    pub created_utc: String,
+    pub merkle_root_hex: String,
+    pub total_chunks: u64,
+    /// Chunks whose content already existed in the parent version.
+    pub reused_chunks: u64,
+    /// Location relative to the parity dir ("." for the live set); None once pruned.
+    pub dir: Option<String>,
+    /// Stripes whose parity the archive left out: the next version holds
+    /// all their chunks, and restoring takes them from there.
+    #[serde(default)]
+    pub stripes_without_parity: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct VersionGraph {
+    pub versions: Vec<VersionNode>,
+}
+
+impl VersionGraph {
+    pub fn load(parity_dir: &Path) -> Result<Self> {
+        let p = parity_dir.join(VERSIONS_DIR).join(GRAPH_FILE);
+        if !p.exists() {
+            return Ok(Self::default());
+        }
+        serde_json::from_reader(File::open(&p)?).with_context(|| format!("read {:?}", p))
+    }
+
+    pub fn save(&self, parity_dir: &Path) -> Result<()> {
+        let dir = parity_dir.join(VERSIONS_DIR);
+        std::fs::create_dir_all(&dir).with_context(|| format!("create dir {:?}", dir))?;
+        let mut f = File::create(dir.join(GRAPH_FILE)).context("create graph.json")?;
+        f.write_all(serde_json::to_string_pretty(self)?.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn current(&self) -> Option<&VersionNode> {
+        self.versions.iter().find(|v| v.dir.as_deref() == Some("."))
+    }
+
+    pub fn get(&self, id: u32) -> Option<&VersionNode> {
+        self.versions.iter().find(|v| v.id == id)
+    }
+
+    /// The version recorded with `id` as its parent.
+    fn get_child(&self, id: u32) -> Option<&VersionNode> {
+        self.versions.iter().find(|v| v.parent == Some(id))
+    }
+
+    fn next_id(&self) -> u32 {
+        self.versions.iter().map(|v| v.id + 1).max().unwrap_or(1)
+    }
+}
+
+/// A live set moved aside by [`set_aside`] while a re-create writes a new
+/// one in its place.
+pub struct SetAside {
+    parity_dir: PathBuf,
+}
+
+/// Where [`set_aside`] keeps the live set until the re-create is over.
+const PENDING_DIR: &str = ".pending";
+
+fn is_volume_file(p: &Path) -> bool {
+    p.extension().is_some_and(|s| s == "parxv" || s == "parxp")
+        || p.file_name().is_some_and(|n| n == crate::volparity::DESCRIPTOR)
+}
+
+/// Move the live volumes and volume parity of `parity_dir` into
+/// `versions/.pending/`, next to a copy of its manifests, so that a
+/// re-create can write a new set in their place; `manifest.json` stays, and
+/// the new set counts its generation on from it. Once the create succeeded,
+/// [`SetAside::archive`] keeps the old set as a version; when it failed,
+/// [`SetAside::restore`] puts it back. `None` when there is no live set.
+///
+/// A set left aside by a run that died is dealt with first: put back when
+/// the live manifest is still its own, archived when a new one replaced it.
+pub fn set_aside(parity_dir: &Path) -> Result<Option<SetAside>> {
+    let pending = parity_dir.join(VERSIONS_DIR).join(PENDING_DIR);
+    if pending.exists() {
+        let left = SetAside { parity_dir: parity_dir.to_path_buf() };
+        let live = std::fs::read(parity_dir.join(manifest::MANIFEST_JSON)).ok();
+        if live.is_none() || live == std::fs::read(pending.join(manifest::MANIFEST_JSON)).ok() {
+            left.restore()?;
+        } else {
+            let id = left.archive()?;
+            record_current(parity_dir, Some(id), usize::MAX)?;
+        }
+    }
+    let live = parity_dir.join(manifest::MANIFEST_JSON);
+    if !live.exists() {
+        return Ok(None);
+    }
+    std::fs::create_dir_all(&pending).with_context(|| format!("create dir {:?}", pending))?;
+    for name in [manifest::MANIFEST_JSON, manifest::MANIFEST_V2] {
+        let p = parity_dir.join(name);
+        if p.exists() {
+            std::fs::copy(&p, pending.join(name)).with_context(|| format!("copy {:?}", p))?;
+        }
+    }
+    for ent in std::fs::read_dir(parity_dir)? {
+        let p = ent?.path();
+        if is_volume_file(&p) {
+            std::fs::rename(&p, pending.join(p.file_name().unwrap()))?;
+        }
+    }
+    Ok(Some(SetAside { parity_dir: parity_dir.to_path_buf() }))
+}
+
+impl SetAside {
+    fn pending(&self) -> PathBuf {
+        self.parity_dir.join(VERSIONS_DIR).join(PENDING_DIR)
+    }
+
+    /// The re-create succeeded: keep the old set as a version in
+    /// `versions/vN/`, without the parity of stripes whose chunks the new
+    /// set all holds (see [`thin`]). Returns the version id.
+    pub fn archive(self) -> Result<u32> {
+        let pending = self.pending();
+        let mut graph = VersionGraph::load(&self.parity_dir)?;
+        let mf = load_manifest(&pending)?;
+        let id = match graph.current() {
+            Some(cur) => cur.id,
+            None => {
+                // Live set predates version tracking: register it now
+                let id = graph.next_id();
+                graph.versions.push(node_for(&mf, id, None, 0));
+                id
+            }
+        };
+        let rel = format!("{}/v{}", VERSIONS_DIR, id);
+        let dest = self.parity_dir.join(&rel);
+        if dest.exists() {
+            bail!("{:?} already exists; move it away to archive version {}", dest, id);
+        }
+        std::fs::rename(&pending, &dest).with_context(|| format!("rename {:?}", pending))?;
+        let mut archived = mf;
+        archived.parity_dir = dest.to_string_lossy().to_string();
+        manifest::save(&archived, &dest)?;
+        let dropped = match load_manifest(&self.parity_dir) {
+            Ok(next) => thin(&dest, &archived, &next)?,
+            Err(_) => 0,
+        };
+        for v in &mut graph.versions {
+            if v.id == id {
+                v.dir = Some(rel.clone());
+                v.stripes_without_parity = dropped;
+            }
+        }
+        graph.save(&self.parity_dir)?;
+        Ok(id)
+    }
+
+    /// The re-create failed: put the old set back in place of whatever it
+    /// wrote.
+    pub fn restore(self) -> Result<()> {
+        let pending = self.pending();
+        for ent in std::fs::read_dir(&self.parity_dir)? {
+            let p = ent?.path();
+            if is_volume_file(&p) {
+                std::fs::remove_file(&p).with_context(|| format!("remove {:?}", p))?;
+            }
+        }
+        for ent in std::fs::read_dir(&pending)? {
+            let p = ent?.path();
+            let to = self.parity_dir.join(p.file_name().unwrap());
+            std::fs::rename(&p, &to).with_context(|| format!("rename {:?}", p))?;
+        }
+        std::fs::remove_dir(&pending).with_context(|| format!("remove {:?}", pending))
+    }
+}
+
+/// Drop from the volumes in `dir` the inner parity of the stripes of `mf`
+/// whose every chunk `next` holds too: restoring `mf` takes those chunks
+/// from `next` and needs none of it. Sets with keyed chunk hashes are left
+/// whole. Returns how many stripes lost their parity.
+fn thin(dir: &Path, mf: &Manifest, next: &Manifest) -> Result<u64> {
+    if mf.hash_mode().is_some() || next.hash_mode().is_some() {
+        return Ok(0);
+    }
+    let known: HashSet<&str> = chunk_hashes(next).collect();
+    let geo = mf.geometry();
+    let mut kept: HashSet<u64> = HashSet::new();
+    for ch in mf.files.iter().flat_map(|fe| &fe.chunks) {
+        if !known.contains(ch.hash_hex.as_str()) {
+            kept.insert(geo.stripe_of(ch.idx));
+        }
+    }
+    let drop: HashSet<u64> = (0..geo.stripes).filter(|s| !kept.contains(s)).collect();
+    if drop.is_empty() {
+        return Ok(0);
+    }
+    for ent in std::fs::read_dir(dir)? {
+        let p = ent?.path();
+        if p.extension().is_some_and(|s| s == "parxv") {
+            drop_shards(&p, &drop).with_context(|| format!("thin {:?}", p))?;
+        }
+    }
+    crate::volparity::refresh(dir)?;
+    Ok(drop.len() as u64)
+}
+
+/// Rewrite the volume at `path` without the inner shards of `stripes`,
+/// the shards it keeps packed where the first one started.
+fn drop_shards(path: &Path, stripes: &HashSet<u64>) -> Result<()> {
+    let mut src = File::open(path)?;
+    let mut hdr = VolumeHeader::read_from(&src)?;
+    check_features(hdr.flags, ReadMode::Strict)?;
+    let (off, len, crc) = read_trailer(&mut src)?;
+    let entries = read_index(&mut src, off, len, crc, &IndexLimits::default())?;
+    let dropped = |e: &VolumeEntry| e.is_inner() && stripes.contains(&e.stripe);
+    if !entries.iter().any(dropped) {
+        return Ok(());
+    }
+    let start = entries.iter().map(|e| e.offset).min().unwrap_or(off);
+    let mut kept: Vec<VolumeEntry> = entries.into_iter().filter(|e| !dropped(e)).collect();
+    kept.sort_by_key(|e| e.offset);
+
+    let tmp = path.with_extension("parxv.tmp");
+    let dst = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&tmp)
+        .with_context(|| format!("create {:?}", tmp))?;
+    // Header and head copy area as they are
+    let mut buf = vec![0u8; start as usize];
+    read_exact_at(&src, &mut buf, 0)?;
+    write_all_at(&dst, &buf, 0)?;
+    let mut end = start;
+    for e in &mut kept {
+        buf.resize(e.len as usize, 0);
+        read_exact_at(&src, &mut buf, e.offset)?;
+        write_all_at(&dst, &buf, end)?;
+        e.offset = end;
+        end += e.len as u64;
+    }
+    write_index_and_trailer_with(&dst, &kept, &[], Codecs::from_ext(&hdr.ext).index)?;
+    // The head copy record was just updated; keep it
+    hdr = VolumeHeader::read_from(&dst)?;
+    hdr.entries = crate::volume::entry_count(&kept)?;
+    hdr.write_to(&dst)?;
+    dst.sync_all()?;
+    std::fs::rename(&tmp, path).with_context(|| format!("rename {:?}", tmp))?;
+    Ok(())
+}
+
+/// Register the freshly created live set as a child of `parent` and prune
+/// archived versions so that at most `keep` of them remain.
+pub fn record_current(parity_dir: &Path, parent: Option<u32>, keep: usize) -> Result<u32> {
+    let mut graph = VersionGraph::load(parity_dir)?;
+    let mf = load_manifest(parity_dir)?;
+    let reused = match parent.and_then(|p| graph.get(p)).and_then(|n| n.dir.clone()) {
+        Some(dir) => {
+            let pm = load_manifest(&parity_dir.join(dir))?;
+            let known: HashSet<&str> = chunk_hashes(&pm).collect();
+            chunk_hashes(&mf).filter(|h| known.contains(h)).count() as u64
+        }
+        None => 0,
+    };
+    let id = graph.next_id();
+    graph.versions.push(node_for(&mf, id, parent, reused));
+
+    let mut archived: Vec<u32> = graph
+        .versions
+        .iter()
+        .filter(|v| v.dir.as_deref().map(|d| d != ".").unwrap_or(false))
+        .map(|v| v.id)
+        .collect();
+    archived.sort_unstable();
+    while archived.len() > keep {
+        let old = archived.remove(0);
+        if let Some(n) = graph.versions.iter_mut().find(|v| v.id == old) {
+            if let Some(d) = n.dir.take() {
+                let _ = std::fs::remove_dir_all(parity_dir.join(d));
+            }
+        }
+    }
+    graph.save(parity_dir)?;
+    Ok(id)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RestoreReport {
+    pub version: u32,
+    pub files_written: u64,
+    /// Files of the live set the version does not have, deleted
+    #[serde(default)]
+    pub files_removed: u64,
+    pub chunks_in_place: u64,
+    pub chunks_reused: u64,
+    pub chunks_reconstructed: u64,
+    /// Of `chunks_reconstructed`, those that needed the version's outer parity
+    pub outer_reconstructed: u64,
+    pub chunks_failed: u64,
+    /// Outcome of re-applying recorded permissions/ownership to written files.
+    #[serde(default, skip_serializing_if = "MetaReport::is_empty")]
+    pub metadata: MetaReport,
+}
+
+impl crate::report::Report for RestoreReport {
+    const KIND: &'static str = "restore";
+}
+
+/// Bytes of stripes a restore rebuilds at once.
+const REBUILD_BATCH_BYTES: usize = 64 << 20;
+
+/// A retained version as restore reads it.
+struct Version {
+    dir: PathBuf,
+    mf: Manifest,
+    /// Chunk hash of each slot a file uses
+    hash_of: HashMap<u64, String>,
+    /// A slot holding each chunk hash
+    slot_of: HashMap<String, u64>,
+}
+
+impl Version {
+    fn new(dir: PathBuf, mf: Manifest) -> Self {
+        let mut hash_of = HashMap::new();
+        let mut slot_of = HashMap::new();
+        for ch in mf.files.iter().flat_map(|fe| &fe.chunks) {
+            hash_of.insert(ch.idx, ch.hash_hex.clone());
+            slot_of.entry(ch.hash_hex.clone()).or_insert(ch.idx);
+        }
+        Version { dir, mf, hash_of, slot_of }
+    }
+}
+
+/// Where the tree holds a chunk, by content hash.
+type Donors = HashMap<String, (PathBuf, u64, u32)>;
+
+/// Where a rebuilt chunk goes: changed file, offset and length.
+type Dest = (usize, u64, u32);
+
+fn donor_chunk(donors: &Donors, hash: &str, cs: usize) -> Option<Vec<u8>> {
+    let (path, off, len) = donors.get(hash)?;
+    read_padded(path, *off, *len, cs).filter(|b| blake3::hash(b).to_hex().as_str() == hash)
+}
+
+/// Restore the tree under `root` to protected version `id`.
+///
+/// Each chunk of the target version is taken from its original location when
+/// unchanged, from any other location in the live set with identical content
+/// (chunk-level reuse across versions), or reconstructed from that version's
+/// archived parity when neither exists; chunks that parity cannot give back
+/// (an archive keeps none for stripes the next version holds in full) come
+/// from the versions after it in turn, down to the live set. Files that
+/// differ are rebuilt in a temporary file next to them, stripe by stripe,
+/// and only replace the original once every chunk is in. Once every file is
+/// restored, those of the live set the version does not have are removed.
+pub fn restore_as_of(
+    parity_dir: &Path,
+    id: u32,
+    root: &Path,
+    policy: PathPolicy,
+) -> Result<RestoreReport> {
+    let graph = VersionGraph::load(parity_dir)?;
+    let node = graph.get(id).with_context(|| format!("unknown version {}", id))?;
+    let Some(dir) = node.dir.clone() else {
+        bail!("version {} has been pruned", id);
+    };
+    let target = load_manifest(&parity_dir.join(&dir))?;
+    target.require_plain_hashes("repair --as-of")?;
+    let chain = chain_from(&graph, parity_dir, id, Version::new(parity_dir.join(&dir), target));
+    let target = &chain[0];
+    let live = load_manifest(parity_dir).ok();
+    let cs = target.mf.chunk_size;
+    let geo = target.mf.geometry();
+
+    // Donor index: content hash -> location in the live set
+    let mut donors = Donors::new();
+    if let Some(live) = &live {
+        for fe in &live.files {
+            let Ok(path) = validate_path(root, Path::new(&fe.rel_path), policy) else { continue };
+            for ch in &fe.chunks {
+                donors.entry(ch.hash_hex.clone()).or_insert((path.clone(), ch.file_offset, ch.len));
+            }
+        }
+    }
+
+    let mut rep = RestoreReport {
+        version: id,
+        files_written: 0,
+        files_removed: 0,
+        chunks_in_place: 0,
+        chunks_reused: 0,
+        chunks_reconstructed: 0,
+        outer_reconstructed: 0,
+        chunks_failed: 0,
+        metadata: MetaReport::default(),
+    };
+    // Files that differ from the version, with the chunks already in place
+    let mut changed: Vec<(usize, PathBuf, Vec<bool>)> = Vec::new();
+    for (fi, fe) in target.mf.files.iter().enumerate() {
+        let path = validate_path(root, Path::new(&fe.rel_path), policy)
+            .with_context(|| format!("validate path {:?}", fe.rel_path))?;
+        let cur = File::open(&path).ok();
+        let same_size =
+            cur.as_ref().and_then(|f| f.metadata().ok()).map(|m| m.len()) == Some(fe.size);
+        let mut in_place = Vec::with_capacity(fe.chunks.len());
+        for ch in &fe.chunks {
+            let ok = cur
+                .as_ref()
+                .and_then(|f| read_padded_from(f, ch.file_offset, ch.len, cs))
+                .is_some_and(|b| blake3::hash(&b).to_hex().as_str() == ch.hash_hex);
+            if ok {
+                rep.chunks_in_place += 1;
+                // Checked just now, unlike the live set's locations
+                donors.insert(ch.hash_hex.clone(), (path.clone(), ch.file_offset, ch.len));
+            }
+            in_place.push(ok);
+        }
+        if !(same_size && in_place.iter().all(|&b| b)) {
+            changed.push((fi, path, in_place));
+        }
+    }
+
+    // Copy what the tree has into the temporary files; note the rest by slot
+    let mut failed_files: HashSet<usize> = HashSet::new();
+    let mut wanted: BTreeMap<u64, Vec<Dest>> = BTreeMap::new();
+    for (ci, (fi, path, in_place)) in changed.iter().enumerate() {
+        let fe = &target.mf.files[*fi];
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let tmp = tmp_path(path);
+        let out = File::create(&tmp).with_context(|| format!("create {:?}", tmp))?;
+        out.set_len(fe.size)?;
+        for (ch, &here) in fe.chunks.iter().zip(in_place) {
+            let buf = match here {
+                true => read_padded(path, ch.file_offset, ch.len, cs),
+                false => donor_chunk(&donors, &ch.hash_hex, cs),
+            };
+            match buf {
+                Some(b) => {
+                    rep.chunks_reused += u64::from(!here);
+                    write_all_at(&out, &b[..ch.len as usize], ch.file_offset)
+                        .with_context(|| format!("write {:?}", tmp))?;
+                }
+                None => wanted.entry(ch.idx).or_default().push((ci, ch.file_offset, ch.len)),
+            }
+        }
+    }
+
+    // Rebuild the rest from parity, a batch of stripes at a time
+    let per_stripe = (geo.k + target.mf.max_parity_shards()) * cs;
+    let batch = (REBUILD_BATCH_BYTES / per_stripe.max(1)).max(1);
+    let stripes: BTreeSet<u64> = wanted.keys().map(|&idx| geo.stripe_of(idx)).collect();
+    let stripes: Vec<u64> = stripes.into_iter().collect();
+    for part in stripes.chunks(batch) {
+        let (lo, hi) = (geo.chunk_at(part[0], 0), geo.chunk_at(part[part.len() - 1], geo.k));
+        let slots: Vec<(u64, &Vec<Dest>)> =
+            wanted.range(lo..hi).map(|(&idx, to)| (idx, to)).collect();
+        let hashes = slots.iter().filter_map(|(idx, _)| target.hash_of.get(idx).cloned()).collect();
+        let got = rebuild(&chain, &donors, hashes)?;
+        for (idx, to) in slots {
+            match target.hash_of.get(&idx).and_then(|h| got.get(h)) {
+                Some((buf, outer)) => {
+                    rep.chunks_reconstructed += 1;
+                    rep.outer_reconstructed += u64::from(*outer);
+                    for &(ci, off, len) in to {
+                        let tmp = tmp_path(&changed[ci].1);
+                        let out = OpenOptions::new().write(true).open(&tmp)?;
+                        write_all_at(&out, &buf[..len as usize], off)
+                            .with_context(|| format!("write {:?}", tmp))?;
+                    }
+                }
+                None => {
+                    rep.chunks_failed += 1;
+                    failed_files.extend(to.iter().map(|&(ci, ..)| ci));
+                }
+            }
+        }
+    }
+
+    // Put the complete files in place
+    for (ci, (fi, path, _)) in changed.iter().enumerate() {
+        let tmp = tmp_path(path);
+        if failed_files.contains(&ci) {
+            let _ = std::fs::remove_file(&tmp);
+            continue;
+        }
+        File::open(&tmp)?.sync_all()?;
+        std::fs::rename(&tmp, path).with_context(|| format!("rename {:?}", tmp))?;
+        let fe = &target.mf.files[*fi];
+        if let Some(fm) = &fe.meta {
+            let map = ChownMap::default();
+            meta::restore(path, &fe.rel_path, fm, &map, false, &mut rep.metadata);
+        }
+        rep.files_written += 1;
+    }
+
+    // Files the version does not have; kept while a restore is incomplete,
+    // as a later run may still need their chunks
+    if let (Some(live), 0) = (&live, rep.chunks_failed) {
+        let keep: HashSet<&str> = target.mf.files.iter().map(|fe| fe.rel_path.as_str()).collect();
+        for fe in live.files.iter().filter(|fe| !keep.contains(fe.rel_path.as_str())) {
+            let path = validate_path(root, Path::new(&fe.rel_path), policy)
+                .with_context(|| format!("validate path {:?}", fe.rel_path))?;
+            match std::fs::remove_file(&path) {
+                Ok(()) => rep.files_removed += 1,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e).with_context(|| format!("remove {:?}", path)),
+            }
+        }
+    }
+    Ok(rep)
+}
+
+/// `first` and the versions after it, each the child of the one before, up
+/// to the live set; the walk stops at a pruned or unreadable version, or
+/// one whose chunk hashes are keyed.
+fn chain_from(graph: &VersionGraph, parity_dir: &Path, id: u32, first: Version) -> Vec<Version> {
+    let mut at = graph.get_child(id);
+    let mut chain = vec![first];
+    while let Some(node) = at.take().filter(|_| chain.len() <= graph.versions.len()) {
+        let Some(dir) = node.dir.as_deref().map(|d| parity_dir.join(d)) else { break };
+        let Ok(mf) = load_manifest(&dir) else { break };
+        if mf.hash_mode().is_some() {
+            break;
+        }
+        chain.push(Version::new(dir, mf));
+        at = graph.get_child(node.id);
+    }
+    chain
+}
+
+/// Chunks of `want` (by hash) rebuilt from the parity of `chain[0]`, and
+/// those it cannot give back from the versions after it; each with whether
+/// outer parity was needed.
+fn rebuild(
+    chain: &[Version],
+    donors: &Donors,
+    want: HashSet<String>,
+) -> Result<HashMap<String, (Vec<u8>, bool)>> {
+    let Some((v, newer)) = chain.split_first() else { return Ok(HashMap::new()) };
+    let mut got = HashMap::new();
+    let geo = v.mf.geometry();
+    let (cs, k, m) = (v.mf.chunk_size, geo.k, v.mf.max_parity_shards());
+    let stripes: HashSet<u64> =
+        want.iter().filter_map(|h| v.slot_of.get(h)).map(|&idx| geo.stripe_of(idx)).collect();
+    if !stripes.is_empty() {
+        let source = VolumeSource::dir(&v.dir, ReadCost::LOCAL)?;
+        let set = v.mf.set_tag();
+        let parity = collect_parity_copies(&[&source], cs, Some(&stripes), ShardKind::Inner, set)?
+            .into_best();
+        let field = RsField::from_ext(&v.mf.ext);
+        let rs = if m > 0 { Some(RsCodec::with_field(field, k, m)?) } else { None };
+        let mut failed: HashMap<u64, Vec<usize>> = HashMap::new();
+        for &s in &stripes {
+            let mut shards: Vec<Option<Vec<u8>>> = geo
+                .slots(s)
+                .map(|idx| match v.hash_of.get(&idx) {
+                    Some(h) => donor_chunk(donors, h, cs),
+                    // Padding slot, or a hole left by update
+                    None => Some(vec![0u8; cs]),
+                })
+                .collect();
+            shards.resize(k + m, None);
+            for (pi, pbuf) in parity.get(&s).cloned().unwrap_or_default() {
+                if pi < m {
+                    shards[k + pi] = Some(pbuf);
+                }
+            }
+            let missing: Vec<usize> = (0..k).filter(|&i| shards[i].is_none()).collect();
+            if !rs.as_ref().is_some_and(|rs| rs.reconstruct(&mut shards).is_ok()) {
+                failed.insert(s, missing);
+                continue;
+            }
+            for i in missing {
+                let h = &v.hash_of[&geo.chunk_at(s, i)];
+                let Some(buf) = shards[i].take() else { continue };
+                if want.contains(h) && blake3::hash(&buf).to_hex().as_str() == h {
+                    got.insert(h.clone(), (buf, false));
+                }
+            }
+        }
+
+        // Stripes beyond inner parity: try the version's outer groups
+        if let Some(layout) = OuterLayout::from_manifest(&v.mf).filter(|_| !failed.is_empty()) {
+            let idx_map = v
+                .hash_of
+                .iter()
+                .filter_map(|(&idx, h)| Some((idx, donors.get(h)?.clone())))
+                .collect();
+            let hash_map = v.hash_of.iter().map(|(&idx, h)| (idx, h.as_str())).collect();
+            let recovered = got.iter().map(|(h, (buf, _))| (v.slot_of[h], buf.clone())).collect();
+            let ctx = ChunkReader {
+                mf: &v.mf,
+                idx_map: &idx_map,
+                hash_map: &hash_map,
+                recovered,
+                skip: &HashSet::new(),
+            };
+            for (idx, buf, _) in repair_with_outer(&layout, &ctx, &[&source], &failed)? {
+                let h = &v.hash_of[&idx];
+                if want.contains(h) {
+                    got.entry(h.clone()).or_insert((buf, true));
+                }
+            }
+        }
+    }
+
+    let rest: HashSet<String> = want.into_iter().filter(|h| !got.contains_key(h)).collect();
+    if !rest.is_empty() {
+        got.extend(rebuild(newer, donors, rest)?);
+    }
+    Ok(got)
+}
+
+/// Where a file being restored is rebuilt before it replaces `path`.
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".parx-restore");
+    path.with_file_name(name)
+}
+
+fn load_manifest(dir: &Path) -> Result<Manifest> {
+    Ok(manifest::load(&dir.join(manifest::MANIFEST_JSON))?.0)
+}
+
+fn node_for(mf: &Manifest, id: u32, parent: Option<u32>, reused: u64) -> VersionNode {
+    VersionNode {
+        id,
+        parent,
+        created_utc: mf.created_utc.clone(),
+        merkle_root_hex: mf.merkle_root_hex.clone(),
+        total_chunks: mf.total_chunks,
+        reused_chunks: reused,
+        dir: Some(".".to_string()),
+        stripes_without_parity: 0,
+    }
+}
+
+fn chunk_hashes(mf: &Manifest) -> impl Iterator<Item = &str> {
+    mf.files.iter().flat_map(|fe| fe.chunks.iter().map(|c| c.hash_hex.as_str()))
+}
+
+pub(crate) fn read_padded(path: &Path, off: u64, len: u32, chunk_size: usize) -> Option<Vec<u8>> {
+    read_padded_from(&File::open(path).ok()?, off, len, chunk_size)
+}
+
+fn read_padded_from(f: &File, off: u64, len: u32, chunk_size: usize) -> Option<Vec<u8>> {
+    let mut buf = vec![0u8; chunk_size];
+    read_exact_at(f, buf.get_mut(..len as usize)?, off).ok()?;
+    Some(buf)
+}
@@ -0,0 +1,334 @@
+//! Parity across whole volumes (`create --volume-parity N`).
+//!
+//! Inner parity spreads each stripe's shards over the volumes, so losing a
+//! whole `.parxv` file costs every stripe a shard and can sink the ones that
+//! also lost data. This layer cuts every volume into [`BLOCK`]-byte blocks
+//! and computes N RS parity blocks over the blocks at the same position
+//! (volumes shorter than the longest read as zeros past their end). The
+//! parity is written to `volpar-000.parxp` ..; with it, any N lost or
+//! damaged files among the volumes and parity files can be rebuilt byte for
+//! byte.
+//!
+//! The sizes and hashes of all members are kept in [`DESCRIPTOR`] and, so
+//! that losing it is not fatal, after the blocks of every parity file. They
+//! cannot live in the manifest, whose backup every volume carries. Commands
+//! that rewrite volumes (`update`, `vol heal`, `repair --fix-paths`) refresh
+//! the parity afterwards.
+
+use crate::rs_codec::{RsCodec, RsField};
+use anyhow::{bail, ensure, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Sidecar describing the volume parity of the set in a parity dir.
+pub const DESCRIPTOR: &str = "volparity.json";
+
+/// Bytes of each volume covered by one RS stripe.
+pub const BLOCK: u64 = 1 << 20;
+
+/// Ends a parity file: descriptor JSON, its length (u64 LE), then this.
+const TRAILER_MAGIC: &[u8; 8] = b"PARXVPD1";
+
+/// A volume or parity file as it was when the parity was computed.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Member {
+    /// File name inside the parity dir
+    pub name: String,
+    /// Bytes covered (for parity files, the blocks without the trailer)
+    pub size: u64,
+    pub blake3_hex: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct VolumeParity {
+    pub block_size: u64,
+    pub volumes: Vec<Member>,
+    pub parity: Vec<Member>,
+}
+
+/// Outcome of [`restore`].
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct RestoreReport {
+    /// Volumes and parity files rebuilt because they were missing or damaged
+    pub restored: Vec<String>,
+    /// Missing or damaged files left as they are: more were lost than the
+    /// volume parity covers
+    pub unrecoverable: Vec<String>,
+}
+
+/// Name of the `i`-th parity file.
+pub fn parity_name(i: usize) -> String {
+    format!("volpar-{:03}.parxp", i)
+}
+
+/// Compute `shards` parity files over `volumes` (names inside `dir`) and
+/// write them with the descriptor. Parity files of an earlier, larger
+/// volume parity are removed.
+pub fn protect(dir: &Path, volumes: &[String], shards: usize) -> Result<VolumeParity> {
+    ensure!(shards > 0, "volume parity needs at least one shard");
+    ensure!(!volumes.is_empty(), "no volumes to protect");
+    let field = RsField::for_shards(volumes.len() + shards);
+    if volumes.len() + shards > field.max_shards() {
+        bail!("{} volumes and {} volume parity shards exceed the RS code", volumes.len(), shards);
+    }
+    let rs = RsCodec::with_field(field, volumes.len(), shards).context("init RS")?;
+
+    let mut inputs = Vec::with_capacity(volumes.len());
+    for name in volumes {
+        let p = dir.join(name);
+        let f = File::open(&p).with_context(|| format!("open {:?}", p))?;
+        let size = f.metadata()?.len();
+        inputs.push((f, size, blake3::Hasher::new()));
+    }
+    let blocks = inputs.iter().map(|(_, size, _)| *size).max().unwrap_or(0).div_ceil(BLOCK);
+    let mut outputs = Vec::with_capacity(shards);
+    for i in 0..shards {
+        let tmp = dir.join(format!("{}.tmp", parity_name(i)));
+        let f = File::create(&tmp).with_context(|| format!("create {:?}", tmp))?;
+        outputs.push((tmp, BufWriter::new(f), blake3::Hasher::new()));
+    }
+
+    let mut bufs = vec![vec![0u8; BLOCK as usize]; volumes.len() + shards];
+    for j in 0..blocks {
+        for ((f, size, hasher), buf) in inputs.iter_mut().zip(&mut bufs) {
+            let n = read_block(f, *size, BLOCK, j, buf)
+                .with_context(|| format!("read volume block {}", j))?;
+            hasher.update(&buf[..n]);
+        }
+        let mut refs: Vec<&mut [u8]> = bufs.iter_mut().map(|b| b.as_mut_slice()).collect();
+        rs.encode(&mut refs).context("RS encode")?;
+        for ((_, w, hasher), buf) in outputs.iter_mut().zip(&bufs[volumes.len()..]) {
+            w.write_all(buf)?;
+            hasher.update(buf);
+        }
+    }
+
+    let vp = VolumeParity {
+        block_size: BLOCK,
+        volumes: volumes
+            .iter()
+            .zip(&inputs)
+            .map(|(name, (_, size, h))| Member {
+                name: name.clone(),
+                size: *size,
+                blake3_hex: h.finalize().to_hex().to_string(),
+            })
+            .collect(),
+        parity: outputs
+            .iter()
+            .enumerate()
+            .map(|(i, (_, _, h))| Member {
+                name: parity_name(i),
+                size: blocks * BLOCK,
+                blake3_hex: h.finalize().to_hex().to_string(),
+            })
+            .collect(),
+    };
+    let json = serde_json::to_vec(&vp)?;
+    for (i, (tmp, mut w, _)) in outputs.into_iter().enumerate() {
+        write_trailer(&mut w, &json)?;
+        w.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        let dest = dir.join(parity_name(i));
+        std::fs::rename(&tmp, &dest).with_context(|| format!("rename {:?}", tmp))?;
+    }
+    for i in shards.. {
+        let stale = dir.join(parity_name(i));
+        if !stale.exists() {
+            break;
+        }
+        std::fs::remove_file(&stale).with_context(|| format!("remove {:?}", stale))?;
+    }
+    save(dir, &json)?;
+    Ok(vp)
+}
+
+/// Recompute the volume parity of `dir` after its volumes changed; a no-op
+/// for sets without one.
+pub fn refresh(dir: &Path) -> Result<()> {
+    if let Some(vp) = load(dir)? {
+        let names: Vec<String> = vp.volumes.iter().map(|m| m.name.clone()).collect();
+        protect(dir, &names, vp.parity.len())?;
+    }
+    Ok(())
+}
+
+/// Delete the volume parity of `dir`, if any.
+pub fn remove(dir: &Path) -> Result<()> {
+    let mut paths = vec![dir.join(DESCRIPTOR)];
+    paths.extend((0..).map(|i| dir.join(parity_name(i))).take_while(|p| p.exists()));
+    for p in paths.iter().filter(|p| p.exists()) {
+        std::fs::remove_file(p).with_context(|| format!("remove {:?}", p))?;
+    }
+    Ok(())
+}
+
+/// The descriptor of `dir`: the sidecar, or else the copy at the end of the
+/// first parity file that carries a readable one. `None` when the dir has no
+/// volume parity.
+pub fn load(dir: &Path) -> Result<Option<VolumeParity>> {
+    if let Ok(raw) = std::fs::read(dir.join(DESCRIPTOR)) {
+        if let Ok(vp) = serde_json::from_slice(&raw) {
+            return Ok(Some(vp));
+        }
+    }
+    let mut names: Vec<PathBuf> = match std::fs::read_dir(dir) {
+        Ok(rd) => rd
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.extension().is_some_and(|s| s == "parxp"))
+            .collect(),
+        Err(_) => return Ok(None),
+    };
+    names.sort();
+    let found = names.iter().find_map(|p| read_trailer(p).ok());
+    if found.is_none() && dir.join(DESCRIPTOR).exists() {
+        bail!("{} in {:?} is damaged and no parity file carries a copy", DESCRIPTOR, dir);
+    }
+    Ok(found)
+}
+
+/// Check every volume and parity file of `dir` against the descriptor and
+/// rebuild the missing or damaged ones, as long as no more than the number
+/// of parity files were lost. Rebuilt files replace the damaged ones only
+/// once they match their recorded hash.
+pub fn restore(dir: &Path) -> Result<RestoreReport> {
+    let mut rep = RestoreReport::default();
+    let Some(vp) = load(dir)? else {
+        bail!("no volume parity in {:?}", dir);
+    };
+    ensure!(vp.block_size > 0 && !vp.parity.is_empty(), "malformed {}", DESCRIPTOR);
+    let members: Vec<&Member> = vp.volumes.iter().chain(&vp.parity).collect();
+    let intact: Vec<bool> = members.iter().map(|m| is_intact(&dir.join(&m.name), m)).collect();
+    let lost: Vec<usize> = (0..members.len()).filter(|&i| !intact[i]).collect();
+    if lost.is_empty() {
+        return Ok(rep);
+    }
+    if lost.len() > vp.parity.len() {
+        rep.unrecoverable = lost.iter().map(|&i| members[i].name.clone()).collect();
+        return Ok(rep);
+    }
+
+    let k = vp.volumes.len();
+    let rs = RsCodec::with_field(RsField::for_shards(members.len()), k, vp.parity.len())
+        .context("init RS")?;
+    let bs = vp.block_size;
+    let mut sources: Vec<Option<File>> = Vec::with_capacity(members.len());
+    for (m, ok) in members.iter().zip(&intact) {
+        let p = dir.join(&m.name);
+        sources.push(if *ok {
+            Some(File::open(&p).with_context(|| format!("open {:?}", p))?)
+        } else {
+            None
+        });
+    }
+    let mut sinks = Vec::with_capacity(lost.len());
+    for &i in &lost {
+        let tmp = dir.join(format!("{}.tmp", members[i].name));
+        let f = File::create(&tmp).with_context(|| format!("create {:?}", tmp))?;
+        sinks.push((i, tmp, BufWriter::new(f), blake3::Hasher::new()));
+    }
+
+    let blocks = vp.parity[0].size.div_ceil(bs);
+    for j in 0..blocks {
+        let mut shards: Vec<Option<Vec<u8>>> = Vec::with_capacity(members.len());
+        for (m, src) in members.iter().zip(sources.iter_mut()) {
+            shards.push(match src {
+                Some(f) => {
+                    let mut buf = vec![0u8; bs as usize];
+                    read_block(f, m.size, bs, j, &mut buf)
+                        .with_context(|| format!("read {} block {}", m.name, j))?;
+                    Some(buf)
+                }
+                None => None,
+            });
+        }
+        rs.reconstruct(&mut shards).context("RS reconstruct")?;
+        for (i, _, w, hasher) in sinks.iter_mut() {
+            let start = j * bs;
+            let n = members[*i].size.saturating_sub(start).min(bs) as usize;
+            let block = shards[*i].as_deref().unwrap_or_default();
+            w.write_all(&block[..n])?;
+            hasher.update(&block[..n]);
+        }
+    }
+
+    let json = serde_json::to_vec(&vp)?;
+    for (i, tmp, mut w, hasher) in sinks {
+        let m = members[i];
+        if hasher.finalize().to_hex().as_str() != m.blake3_hex {
+            let _ = std::fs::remove_file(&tmp);
+            bail!("rebuilt {} does not match its recorded hash", m.name);
+        }
+        if i >= k {
+            write_trailer(&mut w, &json)?;
+        }
+        w.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        let dest = dir.join(&m.name);
+        std::fs::rename(&tmp, &dest).with_context(|| format!("rename {:?}", tmp))?;
+        rep.restored.push(m.name.clone());
+    }
+    if !dir.join(DESCRIPTOR).is_file() {
+        save(dir, &json)?;
+    }
+    Ok(rep)
+}
+
+/// Whether the first `m.size` bytes of `path` hash to the recorded value;
+/// a volume must also be exactly that long.
+fn is_intact(path: &Path, m: &Member) -> bool {
+    let Ok(f) = File::open(path) else { return false };
+    let Ok(len) = f.metadata().map(|md| md.len()) else { return false };
+    let is_parity = path.extension().is_some_and(|s| s == "parxp");
+    if len < m.size || (!is_parity && len != m.size) {
+        return false;
+    }
+    let mut hasher = blake3::Hasher::new();
+    match std::io::copy(&mut f.take(m.size), &mut hasher) {
+        Ok(n) if n == m.size => hasher.finalize().to_hex().as_str() == m.blake3_hex,
+        _ => false,
+    }
+}
+
+/// Read block `j` (of `bs` bytes) of a file of `size` bytes into `buf`,
+/// zero-filling past its end; returns the bytes that came from the file.
+fn read_block(f: &mut File, size: u64, bs: u64, j: u64, buf: &mut [u8]) -> Result<usize> {
+    let start = j * bs;
+    let n = size.saturating_sub(start).min(bs) as usize;
+    if n > 0 {
+        f.seek(SeekFrom::Start(start))?;
+        f.read_exact(&mut buf[..n])?;
+    }
+    buf[n..].fill(0);
+    Ok(n)
+}
+
+fn write_trailer(w: &mut impl Write, json: &[u8]) -> Result<()> {
+    w.write_all(json)?;
+    w.write_all(&(json.len() as u64).to_le_bytes())?;
+    w.write_all(TRAILER_MAGIC)?;
+    Ok(())
+}
+
+fn read_trailer(path: &Path) -> Result<VolumeParity> {
+    let mut f = File::open(path)?;
+    let len = f.metadata()?.len();
+    ensure!(len >= 16, "too short");
+    let mut tail = [0u8; 16];
+    f.seek(SeekFrom::Start(len - 16))?;
+    f.read_exact(&mut tail)?;
+    ensure!(&tail[8..] == TRAILER_MAGIC, "no descriptor");
+    let jlen = u64::from_le_bytes(tail[..8].try_into().expect("8 bytes"));
+    ensure!(jlen <= len - 16, "descriptor length out of range");
+    let mut json = vec![0u8; jlen as usize];
+    f.seek(SeekFrom::Start(len - 16 - jlen))?;
+    f.read_exact(&mut json)?;
+    Ok(serde_json::from_slice(&json)?)
+}
+
+fn save(dir: &Path, json: &[u8]) -> Result<()> {
+    let path = dir.join(DESCRIPTOR);
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, json).with_context(|| format!("write {:?}", tmp))?;
+    std::fs::rename(&tmp, &path).with_context(|| format!("rename {:?}", tmp))
+}
@@ -1,4 +1,197 @@
+use crate::ext::ExtMap;
+use anyhow::{bail, Result};
 use serde::{Deserialize, Serialize};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+pub const VOLUME_MAGIC: &[u8; 8] = b"PARXVOL\0";
+/// Fixed part of the header; the extension area (if any) follows directly.
+pub const VOLUME_HEADER_FIXED: usize = 32;
+
+/// Bits of [`VolumeHeader::flags`]. The low 16 bits are required features:
+/// a reader that does not implement one cannot interpret the shards and must
+/// refuse the volume. The high 16 bits are optional features that only add
+/// information (index offsets still point at the plain shard bytes), so a
+/// reader may ignore them in compat mode (see [`ReadMode`]).
+pub mod feature {
+    /// Shards are encrypted; the index and header are not.
+    pub const ENCRYPTED: u32 = 1 << 0;
+    /// Shards are stored compressed; `len` in the index is the stored size.
+    pub const COMPRESSED: u32 = 1 << 1;
+    /// Each shard is preceded by a frame (length + CRC) for scanning
+    /// volumes without an index. Index offsets point past the frame.
+    pub const SHARD_FRAMING: u32 = 1 << 16;
+
+    pub const REQUIRED_MASK: u32 = 0x0000_FFFF;
+    /// Bits this build can read; writers currently set none.
+    pub const SUPPORTED: u32 = 0;
+
+    pub fn name(bit: u32) -> Option<&'static str> {
+        match bit {
+            ENCRYPTED => Some("encryption"),
+            COMPRESSED => Some("compression"),
+            SHARD_FRAMING => Some("per-shard framing"),
+            _ => None,
+        }
+    }
+
+    /// Human-readable list of the bits in `flags`.
+    pub fn describe(flags: u32) -> Vec<String> {
+        (0..32)
+            .map(|i| 1u32 << i)
+            .filter(|b| flags & b != 0)
+            .map(|b| match name(b) {
+                Some(n) => n.to_string(),
+                None => format!("unknown bit {}", b.trailing_zeros()),
+            })
+            .collect()
+    }
+}
+
+/// How readers treat optional features they do not implement. Required
+/// features they do not implement are refused in either mode.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReadMode {
+    /// Refuse any volume using a feature this build does not implement
+    #[default]
+    Strict,
+    /// Best effort: ignore unsupported optional features (`--compat-read`)
+    Compat,
+}
+
+static COMPAT_READ: AtomicBool = AtomicBool::new(false);
+
+/// Process-wide mode used when volumes are opened for reading.
+pub fn set_read_mode(mode: ReadMode) {
+    COMPAT_READ.store(mode == ReadMode::Compat, Ordering::Relaxed);
+}
+
+pub fn read_mode() -> ReadMode {
+    if COMPAT_READ.load(Ordering::Relaxed) {
+        ReadMode::Compat
+    } else {
+        ReadMode::Strict
+    }
+}
+
+static FORCE_OLD_READER: AtomicBool = AtomicBool::new(false);
+
+/// Process-wide: read index payloads of a newer schema as the newest one this
+/// build knows instead of refusing them (`--force-old-reader`).
+pub fn set_force_old_reader(on: bool) {
+    FORCE_OLD_READER.store(on, Ordering::Relaxed);
+}
+
+pub fn force_old_reader() -> bool {
+    FORCE_OLD_READER.load(Ordering::Relaxed)
+}
+
+/// A volume declares features this build cannot (or, in strict mode, will
+/// not) read. Kept as a distinct error so callers that skip unreadable
+/// volumes can still stop and say why.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeatureError(pub String);
+
+impl std::fmt::Display for FeatureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for FeatureError {}
+
+/// Check feature bits against what this build supports. Returns the optional
+/// features being ignored (only ever non-empty in compat mode).
+pub fn check_features(flags: u32, mode: ReadMode) -> Result<Vec<String>> {
+    let unsupported = flags & !feature::SUPPORTED;
+    let required = unsupported & feature::REQUIRED_MASK;
+    if required != 0 {
+        return Err(FeatureError(format!(
+            "volume requires {} which this build of parx cannot read",
+            feature::describe(required).join(", ")
+        ))
+        .into());
+    }
+    let optional = feature::describe(unsupported);
+    if !optional.is_empty() && mode == ReadMode::Strict {
+        return Err(FeatureError(format!(
+            "volume uses optional feature(s) {} not supported by this build; pass --compat-read to read it anyway",
+            optional.join(", ")
+        ))
+        .into());
+    }
+    Ok(optional)
+}
+
+/// Feature check on the raw start of a volume. Data without the volume magic
+/// (bare index files) carries no flags and passes.
+pub fn check_header_bytes(fixed: &[u8], mode: ReadMode) -> Result<()> {
+    if fixed.len() < VOLUME_HEADER_FIXED || &fixed[..8] != VOLUME_MAGIC {
+        return Ok(());
+    }
+    check_features(u32::from_le_bytes(fixed[20..24].try_into().unwrap()), mode).map(|_| ())
+}
+
+/// On-disk volume header:
+/// `magic(8) k(4) m(4) entries(4) flags(4) ext_len(4) reserved(4)` + `ext[ext_len]`.
+/// Volumes written before the extension area existed have zeros in
+/// flags/ext_len/reserved and read back with an empty map.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VolumeHeader {
+    pub k: u32,
+    pub m: u32,
+    pub entries: u32,
+    /// Feature bits, see [`feature`].
+    pub flags: u32,
+    pub ext: ExtMap,
+}
+
+impl VolumeHeader {
+    /// Total header length on disk, i.e. where shard data may start.
+    pub fn encoded_len(&self) -> u64 {
+        (VOLUME_HEADER_FIXED + self.ext.encode().len()) as u64
+    }
+
+    pub fn write_to<W: Write + Seek>(&self, mut w: W) -> Result<()> {
+        let ext = self.ext.encode();
+        let mut buf = Vec::with_capacity(VOLUME_HEADER_FIXED + ext.len());
+        buf.extend_from_slice(VOLUME_MAGIC);
+        buf.extend_from_slice(&self.k.to_le_bytes());
+        buf.extend_from_slice(&self.m.to_le_bytes());
+        buf.extend_from_slice(&self.entries.to_le_bytes());
+        buf.extend_from_slice(&self.flags.to_le_bytes());
+        buf.extend_from_slice(&(ext.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&[0u8; 4]);
+        buf.extend_from_slice(&ext);
+        w.seek(SeekFrom::Start(0))?;
+        w.write_all(&buf)?;
+        Ok(())
+    }
+
+    pub fn read_from<R: Read + Seek>(mut r: R) -> Result<Self> {
+        let mut fixed = [0u8; VOLUME_HEADER_FIXED];
+        r.seek(SeekFrom::Start(0))?;
+        r.read_exact(&mut fixed)?;
+        if &fixed[..8] != VOLUME_MAGIC {
+            bail!("not a ParXive volume (bad magic)");
+        }
+        let u32_at = |o: usize| u32::from_le_bytes(fixed[o..o + 4].try_into().unwrap());
+        check_features(u32_at(20), read_mode())?;
+        let ext_len = u32_at(24) as usize;
+        if ext_len > 16 * crate::ext::MAX_EXT_VALUE {
+            bail!("volume extension area too large ({} bytes)", ext_len);
+        }
+        let mut ext = vec![0u8; ext_len];
+        r.read_exact(&mut ext)?;
+        Ok(VolumeHeader {
+            k: u32_at(8),
+            m: u32_at(12),
+            entries: u32_at(16),
+            flags: u32_at(20),
+            ext: ExtMap::decode(&ext)?,
+        })
+    }
+}
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct VolumeHeaderBin {
@@ -11,15 +204,113 @@ pub struct VolumeHeaderBin {
     pub manifest_hash: [u8; 32],
 }
 
-/// V2 entry (PARXBV2): adds `outer_for_stripe` to indicate outer RS shard.
-#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+/// What an index entry's shard holds. Stored as one byte so kinds added
+/// later decode as [`ShardKind::Unknown`] instead of failing the index.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[serde(from = "u8", into = "u8")]
+pub enum ShardKind {
+    /// RS parity of the data chunks of `stripe`
+    #[default]
+    Inner,
+    /// Outer parity of group `stripe` (see `outer`)
+    Outer,
+    /// Slice `stripe` of the encoded manifest (manifest v2 bytes)
+    ManifestBackup,
+    /// Verbatim copy of data chunk `parity_idx` of `stripe`
+    Replica,
+    /// Parity over every stripe of the set; `stripe` numbers the group
+    GlobalParity,
+    /// Written by a newer parx; kept in the index and otherwise ignored
+    Unknown(u8),
+}
+
+impl From<u8> for ShardKind {
+    fn from(v: u8) -> Self {
+        match v {
+            0 => ShardKind::Inner,
+            1 => ShardKind::Outer,
+            2 => ShardKind::ManifestBackup,
+            3 => ShardKind::Replica,
+            4 => ShardKind::GlobalParity,
+            n => ShardKind::Unknown(n),
+        }
+    }
+}
+
+impl From<ShardKind> for u8 {
+    fn from(k: ShardKind) -> u8 {
+        match k {
+            ShardKind::Inner => 0,
+            ShardKind::Outer => 1,
+            ShardKind::ManifestBackup => 2,
+            ShardKind::Replica => 3,
+            ShardKind::GlobalParity => 4,
+            ShardKind::Unknown(n) => n,
+        }
+    }
+}
+
+impl std::fmt::Display for ShardKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ShardKind::Inner => f.write_str("inner"),
+            ShardKind::Outer => f.write_str("outer"),
+            ShardKind::ManifestBackup => f.write_str("manifest-backup"),
+            ShardKind::Replica => f.write_str("replica"),
+            ShardKind::GlobalParity => f.write_str("global-parity"),
+            ShardKind::Unknown(n) => write!(f, "unknown({})", n),
+        }
+    }
+}
+
+/// V3 entry (PARXBV3): 64-bit stripe numbers and an explicit shard kind.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
 pub struct VolumeEntry {
-    pub stripe: u32,     // inner parity: stripe index; outer parity: u32::MAX
+    pub stripe: u64,
     pub parity_idx: u16, // for inner: 0..m-1; for outer: 0..outer_m-1
     pub offset: u64,
     pub len: u32,
     pub hash: Option<[u8; 32]>,
-    pub outer_for_stripe: Option<u32>, // Some(stripe) when this is parity-of-parity shard for that stripe
+    pub kind: ShardKind,
+}
+
+impl VolumeEntry {
+    pub fn is_inner(&self) -> bool {
+        self.kind == ShardKind::Inner
+    }
+}
+
+/// The number of `entries` as a volume header counts them (32 bits).
+pub fn entry_count(entries: &[VolumeEntry]) -> Result<u32> {
+    match u32::try_from(entries.len()) {
+        Ok(n) => Ok(n),
+        Err(_) => bail!("{} index entries are more than a volume header can count", entries.len()),
+    }
+}
+
+/// Prefix of a v3 index payload. V1/V2 payloads are bare bincode vectors.
+pub const ENTRIES_V3_MAGIC: &[u8; 8] = b"PARXBV3\0";
+/// Newest index schema this build reads and the one it writes.
+pub const ENTRIES_SCHEMA: u32 = 3;
+
+/// Schema version named by the magic of an index payload (`PARXBV<n>\0`);
+/// `None` for the bare v1/v2 payloads.
+pub fn entries_schema(data: &[u8]) -> Option<u32> {
+    let rest = data.strip_prefix(b"PARXBV")?;
+    let end = rest.iter().take(10).position(|&b| b == 0)?;
+    std::str::from_utf8(&rest[..end]).ok()?.parse().ok()
+}
+
+/// V2 entry (PARXBV2): 32-bit stripes, outer shards marked by the
+/// `stripe == u32::MAX` sentinel plus `outer_for_stripe`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct VolumeEntryV2 {
+    pub stripe: u32,
+    pub parity_idx: u16,
+    pub offset: u64,
+    pub len: u32,
+    pub hash: Option<[u8; 32]>,
+    pub outer_for_stripe: Option<u32>,
 }
 
 /// V1 entry (PARXBV1): no `outer_for_stripe` field.
@@ -32,23 +323,71 @@ pub struct VolumeEntryV1 {
     pub hash: Option<[u8; 32]>,
 }
 
+impl From<VolumeEntryV2> for VolumeEntry {
+    fn from(v2: VolumeEntryV2) -> Self {
+        let (stripe, kind) = match v2.outer_for_stripe {
+            Some(s) => (s as u64, ShardKind::Outer),
+            None => (v2.stripe as u64, ShardKind::Inner),
+        };
+        VolumeEntry {
+            stripe,
+            parity_idx: v2.parity_idx,
+            offset: v2.offset,
+            len: v2.len,
+            hash: v2.hash,
+            kind,
+        }
+    }
+}
+
 impl From<VolumeEntryV1> for VolumeEntry {
     fn from(v1: VolumeEntryV1) -> Self {
         VolumeEntry {
-            stripe: v1.stripe,
+            stripe: v1.stripe as u64,
             parity_idx: v1.parity_idx,
             offset: v1.offset,
             len: v1.len,
             hash: v1.hash,
-            outer_for_stripe: None,
+            kind: ShardKind::Inner,
         }
     }
 }
 
-/// Try decoding V2; if that fails, fall back to V1 and map.
+/// Serialize entries as a v3 index payload.
+pub fn encode_entries(entries: &[VolumeEntry]) -> Result<Vec<u8>, bincode::Error> {
+    let mut out = ENTRIES_V3_MAGIC.to_vec();
+    bincode::serialize_into(&mut out, entries)?;
+    Ok(out)
+}
+
+/// Decode an index payload by the schema its magic names. A schema newer
+/// than [`ENTRIES_SCHEMA`] is refused with a [`FeatureError`] unless
+/// `force`, which decodes it as v3 on the chance that the newer layout only
+/// added to it.
+pub fn decode_entries(data: &[u8], force: bool) -> Result<Vec<VolumeEntry>> {
+    match entries_schema(data) {
+        Some(v) if v > ENTRIES_SCHEMA && !force => Err(FeatureError(format!(
+            "index schema v{} was written by a newer parx (this build reads up to v{}); \
+             please upgrade, or pass --force-old-reader to try reading it anyway",
+            v, ENTRIES_SCHEMA
+        ))
+        .into()),
+        Some(v) if v > ENTRIES_SCHEMA => bincode::deserialize(&data[ENTRIES_V3_MAGIC.len()..])
+            .map_err(|e| {
+                anyhow::anyhow!("index schema v{} does not read as v{}: {}", v, ENTRIES_SCHEMA, e)
+            }),
+        Some(v) if v < ENTRIES_SCHEMA => bail!("unknown index schema v{}", v),
+        _ => Ok(decode_entries_anyver(data)?),
+    }
+}
+
+/// Decode V3 (by its magic); otherwise try V2 and fall back to V1.
 pub fn decode_entries_anyver(data: &[u8]) -> Result<Vec<VolumeEntry>, bincode::Error> {
-    if let Ok(v2) = bincode::deserialize::<Vec<VolumeEntry>>(data) {
-        return Ok(v2);
+    if let Some(v3) = data.strip_prefix(ENTRIES_V3_MAGIC) {
+        return bincode::deserialize(v3);
+    }
+    if let Ok(v2) = bincode::deserialize::<Vec<VolumeEntryV2>>(data) {
+        return Ok(v2.into_iter().map(VolumeEntry::from).collect());
     }
     let v1s: Vec<VolumeEntryV1> = bincode::deserialize(data)?;
     Ok(v1s.into_iter().map(VolumeEntry::from).collect())
@@ -58,3 +397,40 @@ pub fn decode_entries_anyver(data: &[u8]) -> Result<Vec<VolumeEntry>, bincode::E
 pub fn vol_name(id: usize) -> String {
     format!("vol-{:03}.parxv", id)
 }
+
+/// Placeholder of a volume name template, replaced by the volume id
+/// zero-padded to three digits like [`vol_name`].
+pub const NAME_ID: &str = "{id}";
+
+/// A template for `create --volume-name`: a plain file name holding
+/// [`NAME_ID`] once and no other braces, ending in `.parxv` so the volumes
+/// are still found by their extension.
+pub fn check_name_template(template: &str) -> Result<()> {
+    let plain = template.replacen(NAME_ID, "", 1);
+    if template.matches(NAME_ID).count() != 1 {
+        bail!("volume name {:?} must hold {} exactly once", template, NAME_ID);
+    }
+    if !template.ends_with(".parxv") || template.starts_with('.') {
+        bail!("volume name {:?} must end in .parxv and not start with '.'", template);
+    }
+    if plain.contains(['{', '}', '/', '\\']) || plain.chars().any(char::is_control) {
+        bail!("volume name {:?} may only hold {} besides a plain file name", template, NAME_ID);
+    }
+    Ok(())
+}
+
+/// Name of volume `id` by `template` (see [`check_name_template`]), or the
+/// standard one.
+pub fn vol_name_by(template: Option<&str>, id: usize) -> String {
+    match template {
+        Some(t) => t.replacen(NAME_ID, &format!("{:03}", id), 1),
+        None => vol_name(id),
+    }
+}
+
+/// Volume of copy `c` of parity shard `pi` of stripe (or outer group)
+/// `base`: copies go to the next volumes round-robin, so each lands on a
+/// distinct one.
+pub fn shard_volume(base: u64, pi: usize, c: usize, volumes: usize) -> usize {
+    ((base % volumes as u64) as usize + pi + c) % volumes
+}
@@ -0,0 +1,50 @@
+//! Per-file I/O watchdog (`--io-timeout`). A dying disk can leave a read
+//! blocked in the kernel indefinitely; running each file's reads on a helper
+//! thread lets verify and repair give up on that file, report it and carry on.
+//! A blocked read cannot be cancelled, so the helper thread is abandoned.
+
+use anyhow::{bail, Result};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Progress signal from watched work: the watchdog fires only after a whole
+/// timeout passes without a tick, so large files are not cut short.
+#[derive(Clone, Default)]
+pub struct Ticker(Arc<AtomicU64>);
+
+impl Ticker {
+    pub fn tick(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn count(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Run `work`, inline without a timeout, else on a helper thread. `None`
+/// means it stalled: no tick and no result for `timeout`.
+pub fn watched<T, F>(timeout: Option<Duration>, work: F) -> Result<Option<T>>
+where
+    T: Send + 'static,
+    F: FnOnce(&Ticker) -> T + Send + 'static,
+{
+    let ticker = Ticker::default();
+    let Some(timeout) = timeout else { return Ok(Some(work(&ticker))) };
+    let (tx, rx) = mpsc::channel();
+    let t = ticker.clone();
+    std::thread::Builder::new().name("parx-io".into()).spawn(move || {
+        let _ = tx.send(work(&t));
+    })?;
+    let mut seen = 0;
+    loop {
+        match rx.recv_timeout(timeout) {
+            Ok(v) => return Ok(Some(v)),
+            Err(RecvTimeoutError::Timeout) if ticker.count() == seen => return Ok(None),
+            Err(RecvTimeoutError::Timeout) => seen = ticker.count(),
+            Err(RecvTimeoutError::Disconnected) => bail!("I/O thread panicked"),
+        }
+    }
+}
@@ -0,0 +1,69 @@
+//! Run with `cargo test -p parx-core --features async --test async_api`.
+#![cfg(feature = "async")]
+
+use futures_core::Stream;
+use parx_core::async_api::{repair_async, verify_async, Event, Operation};
+use parx_core::encode::Encoder;
+use parx_core::progress::Snapshot;
+use std::fs;
+use std::future::poll_fn;
+use std::pin::Pin;
+
+mod common;
+
+/// Every snapshot and the report (or the error) the operation ended with.
+async fn drain<R>(mut op: Operation<R>) -> (Vec<Snapshot>, anyhow::Result<R>) {
+    let mut snaps = Vec::new();
+    while let Some(ev) = poll_fn(|cx| Pin::new(&mut op).poll_next(cx)).await {
+        match ev {
+            Ok(Event::Progress(s)) => snaps.push(s),
+            Ok(Event::Done(r)) => {
+                assert!(poll_fn(|cx| Pin::new(&mut op).poll_next(cx)).await.is_none());
+                return (snaps, Ok(r));
+            }
+            Err(e) => return (snaps, Err(e)),
+        }
+    }
+    panic!("stream ended without a report");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn repair_streams_progress_and_ends_with_the_report() {
+    let td = tempfile::tempdir().unwrap();
+    let (root, out) = (td.path().join("data"), td.path().join(".parx"));
+    fs::create_dir_all(&root).unwrap();
+    let a: Vec<u8> = (0..40_000u32).map(|i| (i % 251) as u8).collect();
+    fs::write(root.join("a.bin"), &a).unwrap();
+    fs::write(root.join("b.bin"), vec![3u8; 9_000]).unwrap();
+    Encoder::encode(&root, &out, &common::cfg(4096)).unwrap();
+    let mpath = out.join("manifest.json");
+    let mut bad = a.clone();
+    bad[5_000] ^= 0xFF;
+    fs::write(root.join("a.bin"), &bad).unwrap();
+
+    let (snaps, rep) = drain(verify_async(mpath.clone(), root.clone(), Default::default())).await;
+    assert_eq!(rep.unwrap().chunks_bad, 1);
+    let last = snaps.last().unwrap();
+    assert!(last.last && last.stages[0].path == "verify", "{:?}", last);
+    assert_eq!((last.stages[0].items_done, last.stages[0].bytes_done), (2, 49_000));
+
+    let op = repair_async(mpath.clone(), root.clone(), Default::default(), Default::default());
+    let (snaps, rep) = drain(op).await;
+    assert_eq!(rep.unwrap().repaired_chunks, 1);
+    let last = snaps.last().unwrap();
+    let stage = |p: &str| last.stages.iter().find(|s| s.path == p).unwrap();
+    assert_eq!(stage("scan").items_done, 2);
+    assert_eq!((stage("rebuild").items_done, stage("rebuild").items_total), (1, 1));
+    assert_eq!(fs::read(root.join("a.bin")).unwrap(), a);
+
+    let vr = verify_async(mpath, root, Default::default()).finish().await.unwrap();
+    assert!(vr.chunks_bad == 0 && vr.merkle_ok);
+}
+
+#[tokio::test]
+async fn a_failed_run_ends_the_stream_with_its_error() {
+    let td = tempfile::tempdir().unwrap();
+    let op = verify_async(td.path().join("manifest.json"), td.path().into(), Default::default());
+    let (_, rep) = drain(op).await;
+    assert!(rep.is_err());
+}
@@ -0,0 +1,84 @@
+use parx_core::encode::Encoder;
+use parx_core::repair::{self, RepairOptions};
+use parx_core::throttle::{parse_pressure, Throttle};
+use parx_core::verify::{self, VerifyOptions};
+use std::fs;
+use std::time::{Duration, Instant};
+
+mod common;
+
+fn psi(avg10: &str) -> String {
+    format!(
+        "some avg10={} avg60=1.00 avg300=0.50 total=12345\nfull avg10=0.00 avg60=0.00 avg300=0.00 total=0\n",
+        avg10
+    )
+}
+
+#[test]
+fn pressure_is_parsed() {
+    assert_eq!(parse_pressure(&psi("12.50")), Some(12.5));
+    assert_eq!(parse_pressure("full avg10=3.00 avg60=0 avg300=0 total=0"), None);
+    assert_eq!(parse_pressure(""), None);
+}
+
+#[test]
+fn pauses_until_pressure_drops_under_half() {
+    let td = tempfile::tempdir().unwrap();
+    let src = td.path().join("io");
+    fs::write(&src, psi("50.00")).unwrap();
+    let t = Throttle::with_source(&src, 20.0, Duration::from_millis(10));
+    assert!(t.available());
+    let writer = {
+        let src = src.clone();
+        std::thread::spawn(move || {
+            // Between the thresholds: still paused
+            std::thread::sleep(Duration::from_millis(60));
+            fs::write(&src, psi("15.00")).unwrap();
+            std::thread::sleep(Duration::from_millis(60));
+            fs::write(&src, psi("5.00")).unwrap();
+        })
+    };
+    let start = Instant::now();
+    let mut ticks = 0;
+    t.pause_with(|| ticks += 1);
+    writer.join().unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(100));
+    assert!(ticks > 1);
+    assert_eq!(t.pauses(), 1);
+    assert!(t.paused() >= Duration::from_millis(100));
+
+    // Under the threshold again: no pause
+    fs::write(&src, psi("15.00")).unwrap();
+    std::thread::sleep(Duration::from_millis(20));
+    t.pause();
+    assert_eq!(t.pauses(), 1);
+    assert!(!Throttle::with_source(td.path().join("missing"), 20.0, Duration::ZERO).available());
+}
+
+#[test]
+fn quiet_system_does_not_slow_verify_or_repair() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    let out = td.path().join(".parx");
+    fs::create_dir_all(&root).unwrap();
+    let data: Vec<u8> = (0..9000u32).map(|i| (i * 7) as u8).collect();
+    fs::write(root.join("a.bin"), &data).unwrap();
+    let cfg = common::cfg(1024);
+    Encoder::encode(&root, &out, &cfg).unwrap();
+    let src = td.path().join("io");
+    fs::write(&src, psi("0.00")).unwrap();
+    let throttle = Some(Throttle::with_source(&src, 20.0, Duration::from_millis(10)));
+    let mf = out.join("manifest.json");
+
+    let opts = VerifyOptions { throttle: throttle.clone(), ..Default::default() };
+    let vr = verify::verify_with_options(&mf, &root, &opts).unwrap();
+    assert_eq!((vr.chunks_bad, vr.throttled_ms), (0, 0));
+
+    let mut damaged = data.clone();
+    damaged[1100..1200].fill(0);
+    fs::write(root.join("a.bin"), &damaged).unwrap();
+    let opts = RepairOptions { throttle, ..Default::default() };
+    let rr = repair::repair_with_options(&mf, &root, Default::default(), &opts).unwrap();
+    assert_eq!((rr.repaired_chunks, rr.failed_chunks, rr.throttled_ms), (1, 0, 0));
+    assert_eq!(fs::read(root.join("a.bin")).unwrap(), data);
+}
@@ -0,0 +1,67 @@
+use parx_core::checkall::{check_all, CheckAllOptions, Grade};
+use parx_core::encode::Encoder;
+use parx_core::manifest::MANIFEST_JSON;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::fs::{self, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+
+mod common;
+
+#[test]
+fn grade_follows_the_worst_check() {
+    let td = tempfile::tempdir().unwrap();
+    let (root, out) = (td.path().join("data"), td.path().join(".parx"));
+    fs::create_dir_all(&root).unwrap();
+    let mut rng = StdRng::seed_from_u64(36);
+    let data: Vec<u8> = (0..64 * 1024).map(|_| rng.gen()).collect();
+    fs::write(root.join("a.bin"), &data).unwrap();
+    Encoder::encode(&root, &out, &common::cfg(4096)).unwrap();
+    let mpath = out.join(MANIFEST_JSON);
+    let opts = CheckAllOptions::default();
+
+    let rep = check_all(&mpath, &root, &opts).unwrap();
+    assert_eq!(rep.grade, Grade::Green, "{:?}", rep.reasons);
+    assert!(rep.reasons.is_empty() && rep.geometry.problems.is_empty());
+    assert_eq!((rep.quickcheck.volumes.len(), rep.geometry.volumes_checked), (2, 2));
+    assert!(rep.paritycheck.deep);
+
+    // Rotten data alone is for repair
+    let mut bad = data.clone();
+    bad[100] ^= 0xFF;
+    fs::write(root.join("a.bin"), &bad).unwrap();
+    let rep = check_all(&mpath, &root, &opts).unwrap();
+    assert_eq!(rep.grade, Grade::Amber, "{:?}", rep.reasons);
+    assert_eq!(rep.verify.chunks_bad, 1);
+
+    // With a parity shard rotten as well, repair may come up short
+    let vol = out.join("vol-000.parxv");
+    let (entries, _) =
+        parx_core::heal::check_volume(&mut fs::File::open(&vol).unwrap(), false).unwrap();
+    let inner = entries.iter().find(|e| e.is_inner()).unwrap();
+    let mut f = OpenOptions::new().write(true).open(&vol).unwrap();
+    f.seek(SeekFrom::Start(inner.offset)).unwrap();
+    f.write_all(&[0xAB; 16]).unwrap();
+    drop(f);
+    let rep = check_all(&mpath, &root, &opts).unwrap();
+    assert_eq!(rep.grade, Grade::Red, "{:?}", rep.reasons);
+    assert_eq!(rep.paritycheck.shards_bad, 1);
+    assert!(rep.reasons[0].contains("parity damaged too"), "{:?}", rep.reasons);
+}
+
+#[test]
+fn a_manifest_out_of_step_with_its_files_is_red() {
+    let td = tempfile::tempdir().unwrap();
+    let (root, out) = (td.path().join("data"), td.path().join(".parx"));
+    fs::create_dir_all(&root).unwrap();
+    fs::write(root.join("a.bin"), vec![3u8; 20_000]).unwrap();
+    Encoder::encode(&root, &out, &common::cfg(4096)).unwrap();
+    let mpath = out.join(MANIFEST_JSON);
+    let mut mf: serde_json::Value = serde_json::from_slice(&fs::read(&mpath).unwrap()).unwrap();
+    mf["stripe_k"] = 3.into();
+    fs::write(&mpath, serde_json::to_vec(&mf).unwrap()).unwrap();
+
+    let rep = check_all(&mpath, &root, &CheckAllOptions::default()).unwrap();
+    assert_eq!(rep.grade, Grade::Red, "{:?}", rep.reasons);
+    let problems = &rep.geometry.problems;
+    assert!(problems.iter().any(|p| p.contains("header says 4 data shards")), "{:?}", problems);
+}
@@ -1,4 +1,5 @@
-use parx_core::rs_codec::RsCodec;
+use parx_core::backend::{ComputeBackend, CpuBackend};
+use parx_core::rs_codec::{RsCodec, RsField};
 use rand::{rngs::StdRng, Rng, SeedableRng};
 
 #[test]
@@ -33,3 +34,83 @@ fn rs_reconstruct_exact_missing_under_m() {
         assert_eq!(opts[i].as_ref().unwrap(), &shards[i]);
     }
 }
+
+#[test]
+fn gf16_codec_holds_more_than_256_shards() {
+    let (k, m) = (257usize, 4usize);
+    assert!(RsCodec::new(k, m).is_err());
+    let field = RsField::for_shards(k + m);
+    assert_eq!(field, RsField::Gf16);
+
+    let mut rng = StdRng::seed_from_u64(7);
+    let mut shards: Vec<Vec<u8>> = (0..k + m)
+        .map(|i| if i < k { (0..102).map(|_| rng.gen()).collect() } else { vec![0; 102] })
+        .collect();
+    let mut refs: Vec<&mut [u8]> = shards.iter_mut().map(|v| v.as_mut_slice()).collect();
+    let rs = RsCodec::with_field(field, k, m).unwrap();
+    rs.encode(&mut refs).unwrap();
+    // The backend encodes beside the data and agrees
+    let data: Vec<&[u8]> = shards[..k].iter().map(|d| d.as_slice()).collect();
+    let parity = CpuBackend.encode_batch(&rs, &[data], 102).unwrap();
+    assert_eq!(parity[0], shards[k..]);
+
+    let mut opts: Vec<Option<Vec<u8>>> = shards.iter().cloned().map(Some).collect();
+    for i in (0..k + m).step_by(60).take(m) {
+        opts[i] = None;
+    }
+    rs.reconstruct(&mut opts).unwrap();
+    let restored: Vec<Vec<u8>> = opts.into_iter().map(Option::unwrap).collect();
+    assert_eq!(restored, shards);
+
+    // Symbols are byte pairs
+    let mut odd: Vec<Vec<u8>> = vec![vec![0; 101]; k + m];
+    let mut refs: Vec<&mut [u8]> = odd.iter_mut().map(|v| v.as_mut_slice()).collect();
+    assert!(rs.encode(&mut refs).is_err());
+}
+
+#[test]
+fn device_kernel_inputs_reproduce_cpu_parity() {
+    use parx_core::backend::Gf8Tables;
+    let (k, m, len) = (10usize, 4usize, 64usize);
+    let mut rng = StdRng::seed_from_u64(9);
+    let stripes: Vec<Vec<Vec<u8>>> =
+        (0..3).map(|_| (0..k).map(|_| (0..len).map(|_| rng.gen()).collect()).collect()).collect();
+    let data: Vec<Vec<&[u8]>> =
+        stripes.iter().map(|s| s.iter().map(|d| d.as_slice()).collect()).collect();
+    let rs = RsCodec::new(k, m).unwrap();
+    let parity = CpuBackend.encode_batch(&rs, &data, len).unwrap();
+
+    // What the CUDA kernel computes: a table-lookup dot product per byte
+    let coef = rs.parity_matrix().unwrap();
+    let t = Gf8Tables::new();
+    for (s, stripe) in stripes.iter().enumerate() {
+        for (p, row) in coef.iter().enumerate() {
+            let want: Vec<u8> = (0..len)
+                .map(|x| (0..k).fold(0u8, |acc, j| acc ^ t.mul(row[j], stripe[j][x])))
+                .collect();
+            assert_eq!(parity[s][p], want);
+        }
+    }
+}
+
+/// Runs only where the `cuda` feature is built and a device is present.
+#[cfg(feature = "cuda")]
+#[test]
+fn cuda_backend_reproduces_cpu_parity() {
+    use parx_core::cuda_backend::cuda::CudaBackend;
+    let Ok(gpu) = CudaBackend::new() else {
+        eprintln!("no CUDA device; skipping");
+        return;
+    };
+    let (k, m, len) = (10usize, 4usize, 4096usize);
+    let mut rng = StdRng::seed_from_u64(11);
+    let stripes: Vec<Vec<Vec<u8>>> =
+        (0..5).map(|_| (0..k).map(|_| (0..len).map(|_| rng.gen()).collect()).collect()).collect();
+    let data: Vec<Vec<&[u8]>> =
+        stripes.iter().map(|s| s.iter().map(|d| d.as_slice()).collect()).collect();
+    let rs = RsCodec::new(k, m).unwrap();
+    assert_eq!(
+        gpu.encode_batch(&rs, &data, len).unwrap(),
+        CpuBackend.encode_batch(&rs, &data, len).unwrap()
+    );
+}
@@ -0,0 +1,19 @@
+//! Helpers shared by the integration tests.
+
+use parx_core::encode::{EncoderConfig, GpuMode};
+
+/// Stripes of 4 data chunks with 50% parity over 2 volumes, on the CPU;
+/// tests that need other settings override fields with `..cfg(n)`.
+pub fn cfg(chunk_size: usize) -> EncoderConfig {
+    EncoderConfig {
+        chunk_size,
+        stripe_k: 4,
+        parity_pct: 50,
+        volumes: 2,
+        outer_group: 0,
+        outer_parity: 0,
+        interleave_files: false,
+        shard_copies: 1,
+        gpu: GpuMode::Off,
+    }
+}
@@ -0,0 +1,82 @@
+use parx_core::encode::{EncodeOptions, Encoder};
+use parx_core::index::{read_index, read_trailer, IndexLimits};
+use parx_core::journal::{Journal, JOURNAL_FILE};
+use parx_core::repair;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+
+mod common;
+
+/// 10 stripes of random data.
+fn setup(root: &Path) -> Vec<u8> {
+    fs::create_dir_all(root).unwrap();
+    let mut rng = StdRng::seed_from_u64(11);
+    let data: Vec<u8> = (0..40 * 1024).map(|_| rng.gen()).collect();
+    fs::write(root.join("f.bin"), &data).unwrap();
+    data
+}
+
+#[test]
+fn interrupted_create_resumes_from_its_journal() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    let out = td.path().join(".parx");
+    let data = setup(&root);
+    let opts = EncodeOptions {
+        segment_stripes: 3,
+        interrupt_after_segments: Some(2),
+        ..Default::default()
+    };
+    assert!(Encoder::encode_with(&root, &out, &common::cfg(1024), &opts).is_err());
+    let j = Journal::load(&out).unwrap();
+    assert_eq!(j.segments.last().unwrap().stripes_done, 6);
+    // Shards written after the last segment are torn, as after a crash
+    let mut v = OpenOptions::new().append(true).open(out.join("vol-000.parxv")).unwrap();
+    v.write_all(&[0xAA; 700]).unwrap();
+    drop(v);
+
+    let opts = EncodeOptions { segment_stripes: 3, resume: true, ..Default::default() };
+    let mf = Encoder::encode_with(&root, &out, &common::cfg(1024), &opts).unwrap();
+    assert!(!out.join(JOURNAL_FILE).exists());
+    let mut entries = 0;
+    for v in ["vol-000.parxv", "vol-001.parxv"] {
+        let mut f = File::open(out.join(v)).unwrap();
+        let (off, len, crc) = read_trailer(&mut f).unwrap();
+        let index = read_index(&mut f, off, len, crc, &IndexLimits::default()).unwrap();
+        entries += index.iter().filter(|e| e.is_inner()).count();
+    }
+    assert_eq!(entries, 10 * 2);
+    assert_eq!(mf.total_chunks, 40);
+
+    // Parity of stripes from both runs repairs
+    let file = root.join("f.bin");
+    let mut f = OpenOptions::new().write(true).open(&file).unwrap();
+    for stripe in [1u64, 8] {
+        f.seek(SeekFrom::Start(stripe * 4096)).unwrap();
+        f.write_all(&[0u8; 2048]).unwrap();
+    }
+    drop(f);
+    let rr = repair::repair(&out.join("manifest.json"), &root).unwrap();
+    assert_eq!((rr.repaired_chunks, rr.failed_chunks), (4, 0));
+    assert_eq!(fs::read(&file).unwrap(), data);
+}
+
+#[test]
+fn resume_refuses_a_changed_tree() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    let out = td.path().join(".parx");
+    setup(&root);
+    let opts = EncodeOptions {
+        segment_stripes: 3,
+        interrupt_after_segments: Some(1),
+        ..Default::default()
+    };
+    assert!(Encoder::encode_with(&root, &out, &common::cfg(1024), &opts).is_err());
+    fs::write(root.join("new.bin"), b"late arrival").unwrap();
+    let opts = EncodeOptions { resume: true, ..Default::default() };
+    let err = Encoder::encode_with(&root, &out, &common::cfg(1024), &opts).unwrap_err();
+    assert!(err.to_string().contains("other input or settings"), "{err}");
+}
@@ -0,0 +1,68 @@
+use parx_core::encode::{EncodeOptions, Encoder, EncoderConfig};
+use parx_core::index::{read_index, read_trailer, IndexLimits};
+use parx_core::repair;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+
+mod common;
+
+fn zero(path: &Path, off: u64, len: usize) {
+    let mut f = OpenOptions::new().write(true).open(path).unwrap();
+    f.seek(SeekFrom::Start(off)).unwrap();
+    f.write_all(&vec![0u8; len]).unwrap();
+}
+
+#[test]
+fn stripes_of_critical_files_get_extra_parity_and_survive_more_damage() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir_all(&root).unwrap();
+    let mut rng = StdRng::seed_from_u64(9);
+    // One stripe each (k=4, 1 KiB chunks): a.db is critical, b.log is not
+    let a: Vec<u8> = (0..4096).map(|_| rng.gen()).collect();
+    let b: Vec<u8> = (0..4096).map(|_| rng.gen()).collect();
+    fs::write(root.join("a.db"), &a).unwrap();
+    fs::write(root.join("b.log"), &b).unwrap();
+    let cfg = EncoderConfig { parity_pct: 25, volumes: 3, ..common::cfg(1024) };
+    let opts =
+        EncodeOptions { critical: vec!["*.db".into()], critical_parity: 2, ..Default::default() };
+    let mf = Encoder::encode_with(&root, &td.path().join(".parx"), &cfg, &opts).unwrap();
+    assert_eq!(mf.critical_parity, 2);
+    let a_stripe = mf.files.iter().find(|f| f.rel_path == "a.db").unwrap().chunks[0].idx / 4;
+
+    // The index records three shards for the critical stripe, one for the other
+    let mut per_stripe: HashMap<u64, usize> = HashMap::new();
+    for v in 0..3 {
+        let mut f = File::open(td.path().join(format!(".parx/vol-{:03}.parxv", v))).unwrap();
+        let (off, len, crc) = read_trailer(&mut f).unwrap();
+        let index = read_index(&mut f, off, len, crc, &IndexLimits::default()).unwrap();
+        for e in index.iter().filter(|e| e.is_inner()) {
+            *per_stripe.entry(e.stripe).or_default() += 1;
+        }
+    }
+    assert_eq!(per_stripe[&a_stripe], 3);
+    assert_eq!(per_stripe[&(1 - a_stripe)], 1);
+
+    // Three lost chunks are repairable in a.db, two are not in b.log
+    zero(&root.join("a.db"), 0, 3 * 1024);
+    zero(&root.join("b.log"), 0, 2 * 1024);
+    let rr = repair::repair(&td.path().join(".parx/manifest.json"), &root).unwrap();
+    assert_eq!(rr.repaired_chunks, 3);
+    assert_eq!(rr.failed_chunks, 2);
+    assert_eq!(fs::read(root.join("a.db")).unwrap(), a);
+}
+
+#[test]
+fn critical_patterns_need_an_extra_parity_count() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir_all(&root).unwrap();
+    fs::write(root.join("a.db"), b"x").unwrap();
+    let cfg = EncoderConfig { parity_pct: 25, volumes: 1, ..common::cfg(1024) };
+    let opts = EncodeOptions { critical: vec!["*.db".into()], ..Default::default() };
+    let err = Encoder::encode_with(&root, &td.path().join(".parx"), &cfg, &opts).unwrap_err();
+    assert!(err.to_string().contains("--critical-parity"), "{err}");
+}
@@ -0,0 +1,69 @@
+use parx_core::encode::{EncodeOptions, Encoder};
+use parx_core::path_safety::PathPolicy;
+use parx_core::{audit, ext, repair, update, verify};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::fs;
+
+mod common;
+
+#[test]
+fn identical_chunks_share_a_slot_and_repair_each_other() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    let out = td.path().join(".parx");
+    fs::create_dir_all(&root).unwrap();
+    let mut rng = StdRng::seed_from_u64(16);
+    let image: Vec<u8> = (0..8 * 1024).map(|_| rng.gen()).collect();
+    fs::write(root.join("a.img"), &image).unwrap();
+    fs::write(root.join("b.img"), &image).unwrap();
+    // Zero runs inside one file collapse too
+    let mut sparse = vec![0u8; 6 * 1024];
+    sparse[..1024].copy_from_slice(&image[..1024]);
+    fs::write(root.join("c.img"), &sparse).unwrap();
+
+    let opts = EncodeOptions { dedup: true, ..Default::default() };
+    let mf = Encoder::encode_with(&root, &out, &common::cfg(1024), &opts).unwrap();
+    // a.img: 8 slots; b.img and c.img's first chunk reuse them; one zero slot
+    assert_eq!(mf.total_chunks, 9);
+    assert_eq!(mf.ext.get_u64(ext::key::DEDUP), Some(8 + 1 + 4));
+    let mpath = out.join("manifest.json");
+    let vr = verify::verify(&mpath, &root).unwrap();
+    assert_eq!((vr.chunks_ok, vr.chunks_bad, vr.merkle_ok), (22, 0, true));
+
+    // One damaged location is copied from its intact twin
+    let mut damaged = image.clone();
+    damaged[2048..2100].fill(0);
+    fs::write(root.join("b.img"), &damaged).unwrap();
+    let ar = audit::audit(&mpath, &root, PathPolicy::default()).unwrap();
+    assert!(ar.damaged.is_empty(), "{:?}", ar.damaged);
+    let rr = repair::repair(&mpath, &root).unwrap();
+    assert_eq!((rr.repaired_chunks, rr.failed_chunks), (1, 0));
+    assert_eq!(fs::read(root.join("b.img")).unwrap(), image);
+
+    // Every location of a slot damaged: parity restores all of them
+    fs::write(root.join("a.img"), &damaged).unwrap();
+    fs::write(root.join("b.img"), &damaged).unwrap();
+    let mut zeros_hit = sparse.clone();
+    zeros_hit[3000] = 1;
+    fs::write(root.join("c.img"), &zeros_hit).unwrap();
+    assert_eq!(audit::audit(&mpath, &root, PathPolicy::default()).unwrap().chunks_bad, 1);
+    let rr = repair::repair_with_policy(&mpath, &root, PathPolicy::default()).unwrap();
+    assert_eq!((rr.repaired_chunks, rr.failed_chunks), (3, 0));
+    assert_eq!(fs::read(root.join("a.img")).unwrap(), image);
+    assert_eq!(fs::read(root.join("b.img")).unwrap(), image);
+    assert_eq!(fs::read(root.join("c.img")).unwrap(), sparse);
+
+    let err = update::update(&out, &root, None).unwrap_err();
+    assert!(err.to_string().contains("deduplicated"), "{err}");
+}
+
+#[test]
+fn without_dedup_every_location_keeps_its_slot() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir_all(&root).unwrap();
+    fs::write(root.join("a"), vec![5u8; 4096]).unwrap();
+    let mf = Encoder::encode(&root, &td.path().join(".parx"), &common::cfg(1024)).unwrap();
+    assert_eq!(mf.total_chunks, 4);
+    assert!(mf.ext.get(ext::key::DEDUP).is_none());
+}
@@ -0,0 +1,63 @@
+use parx_core::direct::{self, DirectReader, ALIGN, READ_BLOCK};
+use parx_core::encode::{EncodeOptions, Encoder, EncoderConfig};
+use parx_core::verify::verify;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::fs::{self, OpenOptions};
+use std::io::Read;
+
+mod common;
+
+#[test]
+fn unbuffered_reads_and_writes_match_buffered_ones() {
+    let td = tempfile::tempdir().unwrap();
+    let mut rng = StdRng::seed_from_u64(362);
+    let data: Vec<u8> = (0..2 * READ_BLOCK + 12_345).map(|_| rng.gen()).collect();
+    let src = td.path().join("src.bin");
+    fs::write(&src, &data).unwrap();
+    // Filesystems without unbuffered I/O leave nothing to compare
+    let Some(mut r) = DirectReader::open(&src).unwrap() else { return };
+    let mut back = Vec::new();
+    let mut buf = vec![0u8; 7777];
+    loop {
+        match r.read(&mut buf).unwrap() {
+            0 => break,
+            n => back.extend_from_slice(&buf[..n]),
+        }
+    }
+    assert_eq!(back, data);
+
+    let dst = td.path().join("dst.bin");
+    let buffered =
+        OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&dst).unwrap();
+    let unbuffered = direct::open_write(&dst).unwrap().unwrap();
+    // Unaligned ends, a write within one block, and one across several
+    let pieces = [(0, 100), (100, 3 * ALIGN + 117), (3 * ALIGN + 117, data.len())];
+    for &(from, to) in pieces.iter().rev() {
+        direct::write_at(&unbuffered, &buffered, from as u64, &data[from..to]).unwrap();
+    }
+    assert_eq!(fs::read(&dst).unwrap(), data);
+}
+
+#[test]
+fn direct_io_create_gives_a_sound_set() {
+    let td = tempfile::tempdir().unwrap();
+    let (root, out) = (td.path().join("data"), td.path().join(".parx"));
+    fs::create_dir_all(&root).unwrap();
+    let mut rng = StdRng::seed_from_u64(363);
+    let a: Vec<u8> = (0..300_001).map(|_| rng.gen()).collect();
+    fs::write(root.join("a.bin"), &a).unwrap();
+    fs::write(root.join("b.bin"), b"short").unwrap();
+    let cfg = EncoderConfig { volumes: 3, ..common::cfg(6000) };
+    let opts = EncodeOptions { direct_io: true, ..Default::default() };
+    Encoder::encode_with(&root, &out, &cfg, &opts).unwrap();
+    let mpath = out.join("manifest.json");
+    let vr = verify(&mpath, &root).unwrap();
+    assert!(vr.chunks_bad == 0 && vr.merkle_ok && vr.parity_shortfall.is_none(), "{:?}", vr);
+
+    let mut bad = a.clone();
+    bad[150_000..150_100].fill(0);
+    fs::write(root.join("a.bin"), &bad).unwrap();
+    let rr = parx_core::repair::repair(&mpath, &root).unwrap();
+    assert_eq!(rr.failed_chunks, 0, "{:?}", rr);
+    assert_eq!(fs::read(root.join("a.bin")).unwrap(), a);
+}
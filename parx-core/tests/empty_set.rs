@@ -0,0 +1,54 @@
+use parx_core::encode::{EncodeOptions, Encoder};
+use parx_core::path_safety::PathPolicy;
+use parx_core::{heal, repair, update, verify};
+use std::fs;
+
+mod common;
+
+#[test]
+fn empty_set_verifies_repairs_and_fills() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir_all(&root).unwrap();
+    let out = td.path().join(".parx");
+    let mf = Encoder::encode(&root, &out, &common::cfg(1024)).unwrap();
+    assert!(mf.is_empty());
+    assert_eq!((mf.total_chunks, mf.parity_shards()), (0, 2));
+
+    let mpath = out.join("manifest.json");
+    let vr = verify::verify(&mpath, &root).unwrap();
+    assert_eq!((vr.chunks_ok, vr.chunks_bad, vr.merkle_ok), (0, 0, true));
+    let rr = repair::repair(&mpath, &root).unwrap();
+    assert_eq!((rr.repaired_chunks, rr.failed_chunks), (0, 0));
+
+    // The empty set stays a normal one once update gives it data
+    let data = vec![7u8; 3000];
+    fs::write(root.join("a.bin"), &data).unwrap();
+    update::update(&out, &root, None).unwrap();
+    let mut bad = data.clone();
+    bad[..2048].fill(0);
+    fs::write(root.join("a.bin"), &bad).unwrap();
+    let rr = repair::repair(&mpath, &root).unwrap();
+    assert_eq!((rr.repaired_chunks, rr.failed_chunks), (2, 0));
+    assert_eq!(fs::read(root.join("a.bin")).unwrap(), data);
+}
+
+#[test]
+fn empty_set_without_parity_is_not_an_error() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir_all(&root).unwrap();
+    fs::write(root.join("empty"), b"").unwrap();
+    let out = td.path().join(".parx");
+    let opts = EncodeOptions { parity_shards: Some(0), ..Default::default() };
+    let mf = Encoder::encode_with(&root, &out, &common::cfg(1024), &opts).unwrap();
+    assert!(mf.is_empty());
+
+    let mpath = out.join("manifest.json");
+    fs::remove_file(root.join("empty")).unwrap();
+    let rr = repair::repair(&mpath, &root).unwrap();
+    assert_eq!(rr.empty_files_restored, 1);
+    assert!(root.join("empty").is_file());
+    let hr = heal::heal(&mpath, &root, PathPolicy::default(), None).unwrap();
+    assert_eq!(hr.shards_bad, 0);
+}
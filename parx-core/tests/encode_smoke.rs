@@ -1,7 +1,16 @@
-use parx_core::encode::{Encoder, EncoderConfig};
-use parx_core::index;
-use std::fs::{self, File};
-use std::io::{Read, Seek, SeekFrom};
+use parx_core::encode::{EncodeOptions, Encoder, EncoderConfig, GpuMode};
+use parx_core::filter::{self, ChunkFilter, FILTER_FILE};
+use parx_core::manifest::{self, ChunkId};
+use parx_core::uring::{read_at_many, write_at_many, QUEUE_DEPTH};
+use parx_core::usage::usage;
+use parx_core::{index, manifest_v2, repair, update, verify};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+mod common;
 
 #[test]
 fn encode_small_dataset_and_verify_manifest_merkle() {
@@ -21,6 +30,8 @@ fn encode_small_dataset_and_verify_manifest_merkle() {
         outer_group: 0,
         outer_parity: 0,
         interleave_files: false,
+        shard_copies: 1,
+        gpu: GpuMode::Off,
     };
     let manifest = Encoder::encode(&root, &out, &cfg).unwrap();
 
@@ -63,3 +74,320 @@ fn encode_small_dataset_and_verify_manifest_merkle() {
         assert!(count > 0);
     }
 }
+
+#[test]
+fn scanned_files_are_ordered_bytewise_on_slash_joined_paths() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    for rel in ["b", "a/b", "a.txt", "B", "a/Z", "é"] {
+        let p = root.join(rel);
+        fs::create_dir_all(p.parent().unwrap()).unwrap();
+        fs::write(&p, rel).unwrap();
+    }
+    let cfg = EncoderConfig {
+        chunk_size: 4096,
+        stripe_k: 4,
+        parity_pct: 50,
+        volumes: 1,
+        outer_group: 0,
+        outer_parity: 0,
+        interleave_files: false,
+        shard_copies: 1,
+        gpu: GpuMode::Off,
+    };
+    let manifest = Encoder::encode(&root, &td.path().join(".parx"), &cfg).unwrap();
+    let order: Vec<&str> = manifest.files.iter().map(|f| f.rel_path.as_str()).collect();
+    // Not per-directory walk order: `a.txt` sorts before the `a/` subtree
+    assert_eq!(order, ["B", "a.txt", "a/Z", "a/b", "b", "é"]);
+}
+
+#[test]
+fn names_decomposed_either_way_get_the_same_manifest() {
+    let td = tempfile::tempdir().unwrap();
+    // "café/résumé.txt" composed (NFC) and decomposed (NFD)
+    let nfc = "caf\u{e9}/r\u{e9}sum\u{e9}.txt";
+    let nfd = "cafe\u{301}/re\u{301}sume\u{301}.txt";
+    let mut manifests = Vec::new();
+    for (tree, name) in [("composed", nfc), ("decomposed", nfd)] {
+        let root = td.path().join(tree);
+        fs::create_dir_all(root.join(name).parent().unwrap()).unwrap();
+        fs::write(root.join(name), vec![7u8; 6000]).unwrap();
+        fs::write(root.join("caf.txt"), b"between the two forms").unwrap();
+        let out = td.path().join(format!("{}.parx", tree));
+        manifests.push(Encoder::encode(&root, &out, &common::cfg(4096)).unwrap());
+        // The NFC manifest path finds the file whatever its form on disk
+        let vr = verify::verify(&out.join("manifest.json"), &root).unwrap();
+        assert_eq!(vr.chunks_bad, 0, "{}", tree);
+    }
+    let paths = |mf: &manifest::Manifest| -> Vec<String> {
+        mf.files.iter().map(|f| f.rel_path.clone()).collect()
+    };
+    assert_eq!(paths(&manifests[0]), ["caf.txt", nfc]);
+    assert_eq!(paths(&manifests[1]), paths(&manifests[0]));
+    assert_eq!(manifests[1].merkle_root_hex, manifests[0].merkle_root_hex);
+
+    // Both forms side by side, where the file system keeps them apart
+    let root = td.path().join("both");
+    fs::create_dir_all(&root).unwrap();
+    fs::write(root.join("\u{e9}"), b"one").unwrap();
+    fs::write(root.join("e\u{301}"), b"two").unwrap();
+    if fs::read_dir(&root).unwrap().count() == 2 {
+        let err =
+            Encoder::encode(&root, &td.path().join("both.parx"), &common::cfg(4096)).unwrap_err();
+        assert!(err.to_string().contains("Unicode normalization"), "{}", err);
+    }
+}
+
+#[test]
+fn rel_prefix_gives_the_same_manifest_as_the_cli() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir(&root).unwrap();
+    fs::write(root.join("a.db"), vec![3u8; 9000]).unwrap();
+    let cfg = EncoderConfig {
+        chunk_size: 4096,
+        stripe_k: 4,
+        parity_pct: 50,
+        volumes: 1,
+        outer_group: 0,
+        outer_parity: 0,
+        interleave_files: false,
+        shard_copies: 1,
+        gpu: GpuMode::Off,
+    };
+    let out = td.path().join(".parx");
+    let opts = EncodeOptions {
+        rel_prefix: Some("data".into()),
+        // Patterns still match the path under the input
+        critical: vec!["a.db".into()],
+        critical_parity: 1,
+        ..Default::default()
+    };
+    let mf = Encoder::encode_with(&root, &out, &cfg, &opts).unwrap();
+    assert_eq!(mf.files[0].rel_path, "data/a.db");
+    let (saved, _) = parx_core::manifest::load(&out.join("manifest.json")).unwrap();
+    assert_eq!(saved.files[0].rel_path, "data/a.db");
+    let vr = parx_core::verify::verify(&out.join("manifest.json"), td.path()).unwrap();
+    assert_eq!((vr.chunks_ok, vr.chunks_bad), (3, 0));
+
+    if !cfg!(feature = "cuda") {
+        let gpu = EncoderConfig { gpu: GpuMode::On, ..cfg };
+        let err = Encoder::encode(&root, &out, &gpu).unwrap_err();
+        assert!(err.to_string().contains("no CUDA support"), "{err}");
+    }
+}
+
+fn bytes(rng: &mut StdRng, n: usize) -> Vec<u8> {
+    (0..n).map(|_| rng.gen()).collect()
+}
+
+fn mp4_box(typ: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut b = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+    b.extend_from_slice(typ);
+    b.extend_from_slice(payload);
+    b
+}
+
+/// ftyp + moov + three moof/mdat fragments; returns the file and the moof offsets.
+fn fragmented_mp4(rng: &mut StdRng) -> (Vec<u8>, Vec<u64>) {
+    let mut f = mp4_box(b"ftyp", b"isomiso2");
+    f.extend(mp4_box(b"moov", &bytes(rng, 1000)));
+    let mut moofs = Vec::new();
+    for _ in 0..3 {
+        moofs.push(f.len() as u64);
+        f.extend(mp4_box(b"moof", &bytes(rng, 200)));
+        f.extend(mp4_box(b"mdat", &bytes(rng, 9000)));
+    }
+    (f, moofs)
+}
+
+/// EBML header + unknown-size segment with Info and four clusters.
+fn matroska(rng: &mut StdRng) -> (Vec<u8>, Vec<u64>) {
+    let mut f = vec![0x1A, 0x45, 0xDF, 0xA3, 0x84, b'w', b'e', b'b', b'm'];
+    f.extend([0x18, 0x53, 0x80, 0x67, 0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+    f.extend([0x15, 0x49, 0xA9, 0x66, 0x41, 0xF4]);
+    f.extend(bytes(rng, 500));
+    let mut clusters = Vec::new();
+    for _ in 0..4 {
+        clusters.push(f.len() as u64);
+        let n = 6000u32;
+        f.extend([0x1F, 0x43, 0xB6, 0x75, 0x10 | (n >> 24) as u8, (n >> 16) as u8]);
+        f.extend([(n >> 8) as u8, n as u8]);
+        f.extend(bytes(rng, n as usize));
+    }
+    (f, clusters)
+}
+
+#[test]
+fn media_files_are_chunked_on_container_boundaries() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir(&root).unwrap();
+    let mut rng = StdRng::seed_from_u64(17);
+    let (mp4, moofs) = fragmented_mp4(&mut rng);
+    let (mkv, clusters) = matroska(&mut rng);
+    fs::write(root.join("clip.mp4"), &mp4).unwrap();
+    fs::write(root.join("clip.mkv"), &mkv).unwrap();
+    fs::write(root.join("notes.bin"), bytes(&mut rng, 10_000)).unwrap();
+
+    let out = td.path().join(".parx");
+    let cfg = EncoderConfig { volumes: 1, ..common::cfg(4096) };
+    let opts = EncodeOptions { media_align: true, ..Default::default() };
+    let mf = Encoder::encode_with(&root, &out, &cfg, &opts).unwrap();
+
+    for (name, container, starts) in [("clip.mp4", "mp4", &moofs), ("clip.mkv", "mkv", &clusters)] {
+        let fe = mf.files.iter().find(|f| f.rel_path == name).unwrap();
+        let media = fe.media.as_ref().unwrap();
+        assert_eq!(media.container, container);
+        for s in starts {
+            assert!(fe.chunks.iter().any(|c| c.file_offset == *s), "{} @ {}", name, s);
+        }
+        assert!(media.aligned_cuts >= starts.len() as u64 - 1);
+        assert!(fe.chunks.iter().all(|c| c.len as usize <= cfg.chunk_size));
+    }
+    let plain = mf.files.iter().find(|f| f.rel_path == "notes.bin").unwrap();
+    assert!(plain.media.is_none());
+    assert_eq!(plain.chunks.len(), 3);
+
+    // The filter chunks media the same way
+    let flt = ChunkFilter::load(&out.join(FILTER_FILE)).unwrap();
+    assert!(filter::contains(&flt, &root.join("clip.mkv")).unwrap().all());
+
+    // Damage inside the second fragment is repaired like any other chunk
+    let mut f = OpenOptions::new().write(true).open(root.join("clip.mp4")).unwrap();
+    f.seek(SeekFrom::Start(moofs[1] + 50)).unwrap();
+    f.write_all(&[0u8; 100]).unwrap();
+    drop(f);
+    let mpath = out.join("manifest.json");
+    let rr = repair::repair(&mpath, &root).unwrap();
+    assert_eq!(rr.failed_chunks, 0);
+    assert_eq!(fs::read(root.join("clip.mp4")).unwrap(), mp4);
+    assert_eq!(verify::verify(&mpath, &root).unwrap().chunks_bad, 0);
+}
+
+#[test]
+fn batches_over_the_queue_depth_land_at_their_offsets() {
+    let td = tempfile::tempdir().unwrap();
+    let path = td.path().join("blocks.bin");
+    let f =
+        OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&path).unwrap();
+    let n = QUEUE_DEPTH * 2 + 3;
+    let blocks: Vec<Vec<u8>> = (0..n).map(|i| vec![i as u8; 1000 + i]).collect();
+    let mut offsets = Vec::new();
+    let mut end = 0u64;
+    for b in &blocks {
+        offsets.push(end);
+        end += b.len() as u64;
+    }
+    // Written back to front, so that no write merely appends
+    let reqs: Vec<(u64, &[u8])> =
+        offsets.iter().zip(&blocks).rev().map(|(&o, b)| (o, b.as_slice())).collect();
+    write_at_many(&f, &reqs).unwrap();
+    assert_eq!(fs::read(&path).unwrap(), blocks.concat());
+
+    let f = File::open(&path).unwrap();
+    let mut bufs: Vec<Vec<u8>> = blocks.iter().map(|b| vec![0xEE; b.len()]).collect();
+    let mut reqs: Vec<(u64, &mut [u8])> =
+        offsets.iter().zip(&mut bufs).map(|(&o, b)| (o, &mut b[..])).collect();
+    // One read past the end
+    let mut tail = [0u8; 10];
+    reqs.push((end - 4, &mut tail[..]));
+    let res = read_at_many(&f, &mut reqs);
+    assert_eq!(res.len(), n + 1);
+    assert!(res[..n].iter().all(|r| r.is_ok()));
+    assert_eq!(res[n].as_ref().unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    assert_eq!(bufs, blocks);
+}
+
+#[test]
+fn file_ids_survive_updates_and_are_not_reused() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir_all(&root).unwrap();
+    for (name, fill) in [("a.bin", 1u8), ("b.bin", 2), ("c.bin", 3)] {
+        fs::write(root.join(name), vec![fill; 9_000]).unwrap();
+    }
+    let out = td.path().join(".parx");
+    let cfg = EncoderConfig { volumes: 1, ..common::cfg(4096) };
+    let mf = Encoder::encode(&root, &out, &cfg).unwrap();
+    let ids = |mf: &manifest::Manifest| -> Vec<(String, u64)> {
+        mf.files.iter().map(|f| (f.rel_path.clone(), f.id)).collect()
+    };
+    let named = |v: &[(&str, u64)]| -> Vec<(String, u64)> {
+        v.iter().map(|(n, id)| (n.to_string(), *id)).collect()
+    };
+    assert_eq!(ids(&mf), named(&[("a.bin", 1), ("b.bin", 2), ("c.bin", 3)]));
+    assert_eq!(mf.next_file_id, 4);
+    assert_eq!(mf.slot_table()[&4], ChunkId { file: 2, ordinal: 1 });
+
+    // c.bin outgrows its slots and moves; it keeps its id, d.bin gets a new one
+    fs::remove_file(root.join("b.bin")).unwrap();
+    fs::write(root.join("c.bin"), vec![3u8; 20_000]).unwrap();
+    fs::write(root.join("d.bin"), vec![4u8; 100]).unwrap();
+    update::update(&out, &root, None).unwrap();
+    let (mf, _) = manifest::load(&out.join(manifest::MANIFEST_JSON)).unwrap();
+    let mut got = ids(&mf);
+    got.sort();
+    assert_eq!(got, named(&[("a.bin", 1), ("c.bin", 3), ("d.bin", 4)]));
+    let c = mf.files.iter().find(|f| f.id == 3).unwrap();
+    assert!(c.chunks[0].idx >= 9, "moved after the old layout");
+    assert_eq!(mf.slot_table()[&c.chunks[4].idx], ChunkId { file: 3, ordinal: 4 });
+    let v2 = manifest_v2::decode(&fs::read(out.join(manifest::MANIFEST_V2)).unwrap()).unwrap();
+    assert_eq!(v2.slot_table(), mf.slot_table());
+
+    fs::remove_file(root.join("d.bin")).unwrap();
+    update::update(&out, &root, None).unwrap();
+    fs::write(root.join("e.bin"), vec![5u8; 100]).unwrap();
+    update::update(&out, &root, None).unwrap();
+    let (mf, _) = manifest::load(&out.join(manifest::MANIFEST_JSON)).unwrap();
+    assert_eq!(mf.files.iter().find(|f| f.rel_path == "e.bin").unwrap().id, 5);
+}
+
+fn dir_bytes(dir: &Path) -> u64 {
+    fs::read_dir(dir)
+        .unwrap()
+        .map(|e| e.unwrap().metadata().unwrap())
+        .filter(|m| m.is_file())
+        .map(|m| m.len())
+        .sum()
+}
+
+#[test]
+fn a_fresh_set_splits_into_parity_and_metadata_without_garbage() {
+    let td = tempfile::tempdir().unwrap();
+    let (root, out) = (td.path().join("data"), td.path().join(".parx"));
+    fs::create_dir_all(&root).unwrap();
+    let mut rng = StdRng::seed_from_u64(5);
+    for i in 0..6 {
+        let data: Vec<u8> = (0..12_000).map(|_| rng.gen()).collect();
+        fs::write(root.join(format!("f{}.bin", i)), data).unwrap();
+    }
+    Encoder::encode(&root, &out, &common::cfg(4096)).unwrap();
+
+    let rep = usage(&out).unwrap();
+    // 18 chunks in 5 stripes of 4 + 2 parity shards
+    assert_eq!((rep.files, rep.source_bytes), (6, 72_000));
+    assert_eq!(rep.parity_bytes, 5 * 2 * 4096);
+    assert_eq!((rep.garbage_bytes, rep.other_bytes, rep.holes), (0, 0, 0));
+    assert_eq!(rep.rebalance_reclaimable_bytes, 0);
+    assert_eq!(rep.volumes.len(), 2);
+    for v in &rep.volumes {
+        assert!(v.error.is_none(), "{:?}", v);
+        assert_eq!(v.bytes, v.parity_bytes + v.metadata_bytes, "{:?}", v);
+    }
+    assert_eq!(rep.parity_bytes + rep.metadata_bytes, dir_bytes(&out));
+    let expect = dir_bytes(&out) as f64 * 100.0 / 72_000.0;
+    assert!((rep.overhead_pct - expect).abs() < 1e-9, "{}", rep.overhead_pct);
+
+    // Removals leave holes; the two emptied stripes are what rebalance frees
+    for i in [0, 2, 4] {
+        fs::remove_file(root.join(format!("f{}.bin", i))).unwrap();
+    }
+    update::update(&out, &root, None).unwrap();
+    let rep = usage(&out).unwrap();
+    assert_eq!((rep.files, rep.source_bytes, rep.holes), (3, 36_000, 9));
+    assert_eq!(rep.rebalance_reclaimable_bytes, 2 * 2 * 4096);
+    // The manifest backups the update superseded are left in the volumes
+    assert!(rep.volumes.iter().all(|v| v.garbage_bytes > 0), "{:?}", rep.volumes);
+    assert_eq!(rep.parity_bytes + rep.metadata_bytes + rep.garbage_bytes, dir_bytes(&out));
+}
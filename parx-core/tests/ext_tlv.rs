@@ -0,0 +1,68 @@
+use parx_core::encode::Encoder;
+use parx_core::ext::{key, ExtMap};
+use parx_core::volume::{vol_name, VolumeHeader};
+use parx_core::{manifest, manifest_v2};
+use std::fs::{self, File};
+use std::io::Cursor;
+
+mod common;
+
+#[test]
+fn tlv_roundtrip_keeps_unknown_keys() {
+    let mut m = ExtMap::new();
+    m.insert_u32(key::VOLUME_ID, 7);
+    m.insert(0x7abc, b"from the future".to_vec());
+    m.insert(key::PRIVATE_BASE + 1, Vec::new());
+    let back = ExtMap::decode(&m.encode()).unwrap();
+    assert_eq!(back, m);
+    assert_eq!(back.get_u32(key::VOLUME_ID), Some(7));
+    assert_eq!(back.get(0x7abc), Some(&b"from the future"[..]));
+
+    let enc = m.encode();
+    assert!(ExtMap::decode(&enc[..enc.len() - 1]).is_err());
+}
+
+#[test]
+fn legacy_zeroed_header_reads_with_empty_ext() {
+    let mut raw = Vec::new();
+    raw.extend_from_slice(b"PARXVOL\0");
+    raw.extend_from_slice(&4u32.to_le_bytes());
+    raw.extend_from_slice(&2u32.to_le_bytes());
+    raw.extend_from_slice(&9u32.to_le_bytes());
+    raw.extend_from_slice(&[0u8; 12]);
+    let h = VolumeHeader::read_from(Cursor::new(raw)).unwrap();
+    assert_eq!((h.k, h.m, h.entries, h.flags), (4, 2, 9, 0));
+    assert!(h.ext.is_empty());
+}
+
+#[test]
+fn encoder_writes_header_ext_and_manifest_ext_survives_v2() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir_all(&root).unwrap();
+    fs::write(root.join("a.bin"), vec![5u8; 20_000]).unwrap();
+    let out = td.path().join(".parx");
+    let cfg = common::cfg(4096);
+    let mut mf = Encoder::encode(&root, &out, &cfg).unwrap();
+    let mut older = mf.clone();
+
+    let h = VolumeHeader::read_from(File::open(out.join(vol_name(1))).unwrap()).unwrap();
+    assert_eq!((h.k, h.m), (4, 2));
+    assert_eq!(h.ext.get_u32(key::VOLUME_ID), Some(1));
+    assert_eq!(h.ext.get_u32(key::CHUNK_SIZE), Some(4096));
+
+    mf.ext.insert(0x7001, b"unknown to this reader".to_vec());
+    let back = manifest_v2::decode(&manifest_v2::encode(&mf).unwrap()).unwrap();
+    assert_eq!(back.ext, mf.ext);
+
+    // JSON manifests without an ext map (older sets) still load
+    let old = td.path().join("old");
+    fs::create_dir(&old).unwrap();
+    older.ext = ExtMap::new();
+    manifest::save(&older, &old).unwrap();
+    let text = fs::read_to_string(old.join(manifest::MANIFEST_JSON)).unwrap();
+    assert!(!text.contains("\"ext\""));
+    let (plain, _) = manifest::load(&old.join(manifest::MANIFEST_JSON)).unwrap();
+    assert!(plain.ext.is_empty());
+    assert_eq!(plain.set_id(), None);
+}
@@ -0,0 +1,54 @@
+use parx_core::encode::{Encoder, EncoderConfig};
+use parx_core::fdlimit::{self, FdLimit};
+use parx_core::verify::{self, VerifyOptions};
+use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+mod common;
+
+#[test]
+fn open_files_stay_within_the_limit() {
+    let td = tempfile::tempdir().unwrap();
+    let path = td.path().join("f");
+    fs::write(&path, b"x").unwrap();
+    let fds = FdLimit::new(Some(2));
+    let (open, most) = (AtomicUsize::new(0), AtomicUsize::new(0));
+    std::thread::scope(|s| {
+        for _ in 0..8 {
+            s.spawn(|| {
+                for _ in 0..20 {
+                    let (_f, _permit) = fds.open(&path).unwrap();
+                    let now = open.fetch_add(1, Ordering::SeqCst) + 1;
+                    most.fetch_max(now, Ordering::SeqCst);
+                    std::thread::yield_now();
+                    open.fetch_sub(1, Ordering::SeqCst);
+                }
+            });
+        }
+    });
+    assert!(most.load(Ordering::SeqCst) <= 2);
+
+    // Exhaustion is told apart from other open errors
+    #[cfg(unix)]
+    assert!(fdlimit::is_exhausted(&std::io::Error::from_raw_os_error(24)));
+    assert!(!fdlimit::is_exhausted(&fs::File::open(td.path().join("gone")).unwrap_err()));
+}
+
+#[test]
+fn verify_with_one_open_file_checks_everything() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir_all(&root).unwrap();
+    for i in 0..32u8 {
+        fs::write(root.join(format!("f{i:02}.bin")), vec![i; 1500]).unwrap();
+    }
+    let cfg = EncoderConfig { parity_pct: 25, volumes: 1, ..common::cfg(1024) };
+    let out = td.path().join(".parx");
+    Encoder::encode(&root, &out, &cfg).unwrap();
+    fs::write(root.join("f07.bin"), vec![0u8; 1500]).unwrap();
+
+    let opts = VerifyOptions { max_open_files: Some(1), ..Default::default() };
+    let vr = verify::verify_with_options(&out.join("manifest.json"), &root, &opts).unwrap();
+    assert_eq!((vr.chunks_ok, vr.chunks_bad), (62, 2));
+    assert_eq!(vr.damaged_files.len(), 1);
+}
@@ -0,0 +1,85 @@
+use parx_core::encode::Encoder;
+use parx_core::manifest::{self, MANIFEST_JSON};
+use parx_core::{repair, update, verify};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::fs;
+use std::path::Path;
+
+mod common;
+
+fn write(root: &Path, rel: &str, rng: &mut StdRng, len: usize) -> Vec<u8> {
+    let path = root.join(rel);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    let data: Vec<u8> = (0..len).map(|_| rng.gen()).collect();
+    fs::write(path, &data).unwrap();
+    data
+}
+
+#[test]
+fn forgotten_files_free_their_slots_and_the_rest_stays_repairable() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    let out = td.path().join(".parx");
+    let mut rng = StdRng::seed_from_u64(7);
+    let a = write(&root, "a.bin", &mut rng, 10_000);
+    write(&root, "old/b.bin", &mut rng, 9_000);
+    write(&root, "old/c.bin", &mut rng, 3_000);
+    let d = write(&root, "keep/d.bin", &mut rng, 12_000);
+    let before = Encoder::encode(&root, &out, &common::cfg(4096)).unwrap();
+    let mpath = out.join(MANIFEST_JSON);
+
+    fs::remove_dir_all(root.join("old")).unwrap();
+    assert!(verify::verify(&mpath, &root).unwrap().chunks_bad > 0);
+    let rep = update::forget(&out, &root, &["./old/".to_string()], None).unwrap();
+    assert_eq!((rep.files_forgotten, rep.bytes_forgotten), (2, 12_000));
+    assert_eq!(rep.chunks_freed, 4);
+    assert!(rep.stripes_rewritten >= 1);
+
+    let (mf, _) = manifest::load(&mpath).unwrap();
+    let names: Vec<&str> = mf.files.iter().map(|f| f.rel_path.as_str()).collect();
+    assert_eq!(names, ["a.bin", "keep/d.bin"]);
+    assert_eq!(mf.total_chunks, before.total_chunks, "freed slots stay as holes");
+    assert_eq!(mf.next_file_id, before.next_file_id);
+    let vr = verify::verify(&mpath, &root).unwrap();
+    assert!(vr.chunks_bad == 0 && vr.merkle_ok, "{:?}", vr);
+
+    // The recomputed parity no longer needs the forgotten chunks
+    let mut bad_a = a.clone();
+    bad_a[100] ^= 0xFF;
+    fs::write(root.join("a.bin"), &bad_a).unwrap();
+    let mut bad_d = d.clone();
+    bad_d[9_000] ^= 0xFF;
+    fs::write(root.join("keep/d.bin"), &bad_d).unwrap();
+    let rr = repair::repair(&mpath, &root).unwrap();
+    assert_eq!(rr.failed_chunks, 0);
+    assert_eq!(fs::read(root.join("a.bin")).unwrap(), a);
+    assert_eq!(fs::read(root.join("keep/d.bin")).unwrap(), d);
+}
+
+#[test]
+fn unknown_paths_and_damaged_neighbours_are_refused() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    let out = td.path().join(".parx");
+    let mut rng = StdRng::seed_from_u64(8);
+    let a = write(&root, "a.bin", &mut rng, 6_000);
+    write(&root, "b.bin", &mut rng, 6_000);
+    Encoder::encode(&root, &out, &common::cfg(4096)).unwrap();
+    let mpath = out.join(MANIFEST_JSON);
+    let saved = fs::read(&mpath).unwrap();
+
+    // "a" is only a prefix of a file name, not a directory
+    let err = update::forget(&out, &root, &["b.bin".into(), "a".into()], None).unwrap_err();
+    assert!(err.to_string().contains("\"a\" is not in the set"), "{}", err);
+    let err = update::forget(&out, &root, &[".".into()], None).unwrap_err();
+    assert!(err.to_string().contains("whole set"), "{}", err);
+    assert_eq!(fs::read(&mpath).unwrap(), saved);
+
+    // b.bin shares a stripe with a.bin, whose chunks must be read intact
+    let mut bad = a.clone();
+    bad[10] ^= 1;
+    fs::write(root.join("a.bin"), &bad).unwrap();
+    let err = update::forget(&out, &root, &["b.bin".into()], None).unwrap_err();
+    assert!(err.to_string().contains("run `parx repair` first"), "{}", err);
+    assert_eq!(fs::read(&mpath).unwrap(), saved);
+}
@@ -0,0 +1,125 @@
+use parx_core::encode::{Encoder, EncoderConfig};
+use parx_core::fsck::{fsck, FsckOptions};
+use parx_core::index::{read_index, read_trailer, IndexCopy, IndexLimits};
+use parx_core::volume::{vol_name, ShardKind, VolumeEntry, VolumeHeader};
+use parx_core::{heal, repair};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+
+mod common;
+
+fn cfg(copies: usize) -> EncoderConfig {
+    EncoderConfig { shard_copies: copies, ..common::cfg(4096) }
+}
+
+fn random(seed: u64, len: usize) -> Vec<u8> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    (0..len).map(|_| rng.gen()).collect()
+}
+
+fn index(vol: &Path) -> anyhow::Result<Vec<VolumeEntry>> {
+    let mut f = File::open(vol)?;
+    let (off, len, crc) = read_trailer(&mut f)?;
+    read_index(&mut f, off, len, crc, &IndexLimits::default())
+}
+
+/// Parity shards as (kind, stripe, parity_idx, bytes).
+fn shards(vol: &Path, entries: &[VolumeEntry]) -> Vec<(u8, u64, u16, Vec<u8>)> {
+    let data = fs::read(vol).unwrap();
+    let mut v: Vec<_> = entries
+        .iter()
+        .filter(|e| matches!(e.kind, ShardKind::Inner | ShardKind::Outer))
+        .map(|e| {
+            let b = data[e.offset as usize..(e.offset + e.len as u64) as usize].to_vec();
+            (u8::from(e.kind), e.stripe, e.parity_idx, b)
+        })
+        .collect();
+    v.sort();
+    v
+}
+
+/// Overwrite the head copy of the index and everything from the index on.
+fn wreck_index(vol: &Path) {
+    let entries = index(vol).unwrap();
+    let copy = IndexCopy::from_ext(&VolumeHeader::read_from(File::open(vol).unwrap()).unwrap().ext)
+        .unwrap();
+    let mut f = OpenOptions::new().read(true).write(true).open(vol).unwrap();
+    let (off, _, _) = read_trailer(&mut f).unwrap();
+    let len = f.metadata().unwrap().len();
+    f.seek(SeekFrom::Start(off)).unwrap();
+    f.write_all(&vec![0x5a; (len - off) as usize]).unwrap();
+    f.seek(SeekFrom::Start(copy.off)).unwrap();
+    f.write_all(&vec![0x5a; copy.len as usize]).unwrap();
+    drop(f);
+    assert!(index(vol).is_err());
+    assert!(!entries.is_empty());
+}
+
+#[test]
+fn rebuilds_a_lost_index_from_the_source_tree() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir_all(&root).unwrap();
+    let data = random(1, 70_000);
+    fs::write(root.join("a.bin"), &data).unwrap();
+    let out = td.path().join(".parx");
+    Encoder::encode(&root, &out, &cfg(1)).unwrap();
+    let mpath = out.join("manifest.json");
+    let vol = out.join(vol_name(0));
+    let before = shards(&vol, &index(&vol).unwrap());
+
+    // Nothing to do while the index reads
+    let opts = FsckOptions { root: Some(root.clone()), ..Default::default() };
+    let fr = fsck(&vol, &mpath, &opts).unwrap();
+    assert!(fr.index_intact && !fr.index_rewritten);
+
+    wreck_index(&vol);
+    let fr = fsck(&vol, &mpath, &opts).unwrap();
+    assert!(!fr.index_intact && fr.index_rewritten);
+    assert_eq!(fr.shards_found as usize, before.len());
+    assert_eq!((fr.shards_missing, fr.stripes_unreadable), (0, 0));
+    assert_eq!(shards(&vol, &index(&vol).unwrap()), before);
+
+    // The rebuilt volume heals clean and repairs the data
+    let hr = heal::heal(&mpath, &root, Default::default(), None).unwrap();
+    assert_eq!((hr.shards_bad, hr.unreadable_volumes.len()), (0, 0));
+    fs::remove_file(out.join(vol_name(1))).unwrap();
+    let mut bad = data.clone();
+    bad[4096..8192].fill(0);
+    fs::write(root.join("a.bin"), &bad).unwrap();
+    let rr = repair::repair(&mpath, &root).unwrap();
+    assert_eq!((rr.repaired_chunks, rr.failed_chunks), (1, 0));
+    assert_eq!(fs::read(root.join("a.bin")).unwrap(), data);
+}
+
+#[test]
+fn finds_shards_off_the_stride_from_replicas() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir_all(&root).unwrap();
+    fs::write(root.join("a.bin"), random(2, 50_000)).unwrap();
+    let out = td.path().join(".parx");
+    Encoder::encode(&root, &out, &cfg(2)).unwrap();
+    let mpath = out.join("manifest.json");
+    let vol = out.join(vol_name(0));
+    let before = shards(&vol, &index(&vol).unwrap());
+
+    // Shift every shard by 100 bytes and lose the index
+    wreck_index(&vol);
+    let copy =
+        IndexCopy::from_ext(&VolumeHeader::read_from(File::open(&vol).unwrap()).unwrap().ext)
+            .unwrap();
+    let mut bytes = fs::read(&vol).unwrap();
+    let start = (copy.off + copy.cap) as usize;
+    bytes.splice(start..start, vec![0u8; 100]);
+    fs::write(&vol, &bytes).unwrap();
+
+    // No source tree: vol-001 holds the second copy of every shard
+    let fr = fsck(&vol, &mpath, &FsckOptions::default()).unwrap();
+    assert_eq!(fr.shards_found as usize, before.len());
+    assert_eq!(fr.shards_missing, 0);
+    let entries = index(&vol).unwrap();
+    assert_eq!(shards(&vol, &entries), before);
+}
@@ -0,0 +1,117 @@
+use parx_core::encode::{EncodeOptions, Encoder, EncoderConfig};
+use parx_core::geometry::Geometry;
+use std::fs;
+
+mod common;
+
+#[test]
+fn parity_for_pct_rounds_up() {
+    let cases = [
+        // (k, pct, m)
+        (1, 0, 0),
+        (1, 1, 1),
+        (1, 100, 1),
+        (1, 101, 2),
+        (3, 33, 1),
+        (3, 34, 2),
+        (4, 25, 1),
+        (4, 26, 2),
+        (10, 10, 1),
+        (10, 11, 2),
+        (16, 50, 8),
+        (64, 1, 1),
+        (200, 100, 200),
+        (1000, 999, 9990),
+    ];
+    for (k, pct, m) in cases {
+        assert_eq!(Geometry::parity_for_pct(k, pct), m, "k={} pct={}", k, pct);
+    }
+    // The widest GF(2^16) stripe at a percentage past 100
+    assert_eq!(Geometry::parity_for_pct(65535, 1000), 655350);
+    assert_eq!(Geometry::parity_for_pct(65535, u32::MAX), 2_814_706_816_779);
+}
+
+#[test]
+fn pct_for_parity_rounds_down_and_round_trips() {
+    assert_eq!(Geometry::pct_for_parity(3, 1), 33);
+    assert_eq!(Geometry::pct_for_parity(3, 2), 66);
+    assert_eq!(Geometry::pct_for_parity(16, 8), 50);
+    assert_eq!(Geometry::pct_for_parity(1, 0), 0);
+    // k = 0 is read as 1 rather than dividing by zero
+    assert_eq!(Geometry::pct_for_parity(0, 2), 200);
+    assert_eq!(Geometry::pct_for_parity(1, usize::MAX), u32::MAX);
+    // The rounded-down percentage never asks for more shards than there are
+    for k in 1..=300 {
+        for m in 0..=k {
+            let pct = Geometry::pct_for_parity(k, m);
+            assert!(Geometry::parity_for_pct(k, pct) <= m, "k={} m={}", k, m);
+        }
+    }
+}
+
+#[test]
+fn stripes_and_slots_at_the_edges() {
+    let g = Geometry::new(4, 2, 4096, 0);
+    assert_eq!(g.stripes, 0);
+    assert_eq!(g.k_active(0), 0);
+    assert_eq!(g.parity_bytes(2, 1), 0);
+
+    let g = Geometry::new(4, 2, 4096, 1);
+    assert_eq!(g.stripes, 1);
+    assert_eq!((g.k_active(0), g.k_active(1)), (1, 0));
+
+    let g = Geometry::new(4, 2, 4096, 4);
+    assert_eq!(g.stripes, 1);
+    assert_eq!((g.k_active(0), g.k_active(1)), (4, 0));
+
+    let g = Geometry::new(4, 2, 4096, 9);
+    assert_eq!(g.stripes, 3);
+    assert_eq!([g.k_active(0), g.k_active(1), g.k_active(2), g.k_active(3)], [4, 4, 1, 0]);
+    assert_eq!(g.slots(2), 8..12);
+    for idx in 0..12 {
+        let (s, slot) = (g.stripe_of(idx), g.slot_of(idx));
+        assert!(g.slots(s).contains(&idx));
+        assert_eq!(g.chunk_at(s, slot), idx);
+    }
+    assert_eq!(g.k_active(u64::MAX), 0);
+
+    // k = 0 never divides by zero: every chunk is its own stripe
+    let g = Geometry::new(0, 0, 1, 3);
+    assert_eq!((g.k, g.stripes), (1, 3));
+    assert_eq!((g.stripe_of(2), g.slot_of(2)), (2, 0));
+
+    let g = Geometry::new(1, 1, 1, u64::MAX);
+    assert_eq!(g.stripes, u64::MAX);
+    assert_eq!(g.k_active(u64::MAX - 1), 1);
+    let g = Geometry::new(2, 1, 1, u64::MAX);
+    assert_eq!(g.stripes, u64::MAX / 2 + 1);
+    assert_eq!(g.k_active(u64::MAX / 2), 1);
+    assert_eq!(g.k_active(u64::MAX / 2 - 1), 2);
+}
+
+#[test]
+fn parity_bytes_counts_copies() {
+    let g = Geometry::new(4, 2, 4096, 9);
+    assert_eq!(g.parity_bytes(2, 1), 3 * 2 * 4096);
+    assert_eq!(g.parity_bytes(2, 3), 3 * 3 * 2 * 4096);
+    // No copies still means one
+    assert_eq!(g.parity_bytes(2, 0), g.parity_bytes(2, 1));
+}
+
+#[test]
+fn manifest_geometry_matches_what_create_laid_out() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir_all(&root).unwrap();
+    fs::write(root.join("a.bin"), vec![7u8; 9 * 1024 + 1]).unwrap();
+    let cfg = EncoderConfig { parity_pct: 30, volumes: 1, ..common::cfg(1024) };
+    let mf = Encoder::encode(&root, &td.path().join("p1"), &cfg).unwrap();
+    let g = mf.geometry();
+    assert_eq!((g.k, g.m, g.chunk_size, g.total_chunks, g.stripes), (4, 2, 1024, 10, 3));
+    assert_eq!(g.k_active(2), 2);
+
+    // Shards given directly record a percentage that maps back to no more
+    let opts = EncodeOptions { parity_shards: Some(3), ..Default::default() };
+    let mf = Encoder::encode_with(&root, &td.path().join("p2"), &cfg, &opts).unwrap();
+    assert_eq!((mf.parity_pct, mf.geometry().m), (75, 3));
+}
@@ -0,0 +1,114 @@
+use parx_core::encode::Encoder;
+use parx_core::hashcache::{HashCache, CACHE_FILE};
+use parx_core::path_safety::PathPolicy;
+use parx_core::repair::{self, RepairOptions};
+use parx_core::update::{self, UpdateOptions};
+use parx_core::verify;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::fs;
+use std::time::Duration;
+
+mod common;
+
+const HOUR: Option<Duration> = Some(Duration::from_secs(3600));
+
+#[test]
+fn phases_reuse_the_hashes_of_unchanged_files() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    let out = td.path().join(".parx");
+    fs::create_dir_all(&root).unwrap();
+    let mut rng = StdRng::seed_from_u64(17);
+    let mut random = |n: usize| -> Vec<u8> { (0..n).map(|_| rng.gen()).collect() };
+    fs::write(root.join("a.bin"), random(4096)).unwrap();
+    let b = random(3000);
+    fs::write(root.join("b.bin"), &b).unwrap();
+    fs::write(root.join("c.bin"), random(2048)).unwrap();
+    Encoder::encode(&root, &out, &common::cfg(1024)).unwrap();
+    assert_eq!(HashCache::load(&out).files.len(), 3);
+
+    // Only the damaged file is read; the others were hashed by create
+    let mut damaged = b.clone();
+    damaged[100..200].fill(0);
+    fs::write(root.join("b.bin"), &damaged).unwrap();
+    let mpath = out.join("manifest.json");
+    let opts = RepairOptions { reuse_hashes: HOUR, ..Default::default() };
+    let rr = repair::repair_with_options(&mpath, &root, PathPolicy::default(), &opts).unwrap();
+    assert_eq!((rr.chunks_checked, rr.chunks_reused, rr.repaired_chunks), (3, 6, 1));
+    assert_eq!(fs::read(root.join("b.bin")).unwrap(), b);
+    fs::remove_file(root.join("b.parx.bak")).unwrap();
+
+    // The repaired file is read once more, then every file comes from the cache
+    let uopts = UpdateOptions { reuse_hashes: HOUR, ..Default::default() };
+    let rep = update::update_with_options(&out, &root, None, &uopts).unwrap();
+    assert_eq!((rep.files_unchanged, rep.files_reused), (3, 2));
+    let rep = update::update_with_options(&out, &root, None, &uopts).unwrap();
+    assert_eq!((rep.files_unchanged, rep.files_reused), (3, 3));
+
+    // A changed file no longer matches its stamp
+    fs::write(root.join("c.bin"), random(2048)).unwrap();
+    let rep = update::update_with_options(&out, &root, None, &uopts).unwrap();
+    assert_eq!((rep.files_changed, rep.files_reused), (1, 2));
+
+    // Without reuse, or with entries older than allowed, everything is read
+    let rr = repair::repair_with_options(&mpath, &root, PathPolicy::default(), &Default::default())
+        .unwrap();
+    assert_eq!((rr.chunks_checked, rr.chunks_reused), (9, 0));
+    let mut cache = HashCache::load(&out);
+    for c in cache.files.values_mut() {
+        c.hashed_unix -= 7200;
+    }
+    cache.save(&out).unwrap();
+    let rr = repair::repair_with_options(&mpath, &root, PathPolicy::default(), &opts).unwrap();
+    assert_eq!((rr.chunks_checked, rr.chunks_reused), (9, 0));
+}
+
+#[test]
+fn verify_records_intact_files_only() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    let out = td.path().join(".parx");
+    fs::create_dir_all(&root).unwrap();
+    fs::write(root.join("a.bin"), vec![1u8; 3000]).unwrap();
+    fs::write(root.join("b.bin"), vec![2u8; 3000]).unwrap();
+    Encoder::encode(&root, &out, &common::cfg(1024)).unwrap();
+    fs::remove_file(out.join(CACHE_FILE)).unwrap();
+    fs::write(root.join("b.bin"), vec![3u8; 3000]).unwrap();
+
+    let vr = verify::verify(&out.join("manifest.json"), &root).unwrap();
+    assert_eq!(vr.chunks_bad, 3);
+    let cache = HashCache::load(&out);
+    assert_eq!(cache.files.keys().collect::<Vec<_>>(), ["a.bin"]);
+}
+
+#[test]
+fn verify_older_than_skips_recently_checked_files() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    let out = td.path().join(".parx");
+    fs::create_dir_all(&root).unwrap();
+    for (name, byte) in [("a.bin", 1u8), ("b.bin", 2), ("c.bin", 3)] {
+        fs::write(root.join(name), vec![byte; 3000]).unwrap();
+    }
+    Encoder::encode(&root, &out, &common::cfg(1024)).unwrap();
+    let mpath = out.join("manifest.json");
+    let month = Some(Duration::from_secs(30 * 86_400));
+    let opts = verify::VerifyOptions { older_than: month, ..Default::default() };
+
+    // Everything was hashed by create just now
+    let vr = verify::verify_with_options(&mpath, &root, &opts).unwrap();
+    assert_eq!((vr.files_skipped, vr.chunks_ok, vr.chunks_bad, vr.merkle_ok), (3, 0, 0, true));
+
+    // A file last checked long ago is read, and so is a changed one
+    let mut cache = HashCache::load(&out);
+    cache.files.get_mut("a.bin").unwrap().hashed_unix -= 40 * 86_400;
+    cache.save(&out).unwrap();
+    fs::write(root.join("c.bin"), vec![4u8; 3000]).unwrap();
+    let vr = verify::verify_with_options(&mpath, &root, &opts).unwrap();
+    assert_eq!((vr.files_skipped, vr.chunks_ok, vr.chunks_bad, vr.merkle_ok), (1, 3, 3, false));
+
+    // The intact one is due again only in a month
+    fs::write(root.join("c.bin"), vec![3u8; 3000]).unwrap();
+    let vr = verify::verify_with_options(&mpath, &root, &opts).unwrap();
+    assert_eq!((vr.files_skipped, vr.chunks_ok, vr.chunks_bad, vr.merkle_ok), (2, 3, 0, true));
+}
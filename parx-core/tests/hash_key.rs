@@ -0,0 +1,72 @@
+use parx_core::encode::{EncodeOptions, Encoder};
+use parx_core::manifest::{self, HashKey, MANIFEST_JSON};
+use parx_core::merkle::chunk_hash;
+use parx_core::path_safety::PathPolicy;
+use parx_core::repair::{repair_with_options, RepairOptions};
+use parx_core::verify::{verify_with_options, VerifyOptions};
+use parx_core::{audit, update};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::fs;
+
+mod common;
+
+#[test]
+fn keyed_hashes_are_checked_only_with_the_key() {
+    let td = tempfile::tempdir().unwrap();
+    let (root, out) = (td.path().join("data"), td.path().join(".parx"));
+    fs::create_dir_all(&root).unwrap();
+    let mut rng = StdRng::seed_from_u64(35);
+    let data: Vec<u8> = (0..40_000).map(|_| rng.gen()).collect();
+    fs::write(root.join("a.bin"), &data).unwrap();
+    let key = HashKey::from_secret(b"correct horse battery staple");
+    let opts = EncodeOptions { hash_key: Some(key.clone()), ..Default::default() };
+    Encoder::encode_with(&root, &out, &common::cfg(4096), &opts).unwrap();
+
+    // Nothing in the manifest matches the plain hash of the data
+    let mpath = out.join(MANIFEST_JSON);
+    let (mf, _) = manifest::load(&mpath).unwrap();
+    assert_eq!(mf.hash_mode(), Some(format!("keyed-blake3 {}", key.id()).as_str()));
+    let first = &mf.files[0].chunks[0];
+    assert_ne!(first.hash_hex, chunk_hash(&data[..4096], 4096).to_hex().as_str());
+    assert!(first.weak.is_none());
+    assert!(!out.join(parx_core::filter::FILTER_FILE).exists());
+
+    let with = |key: Option<HashKey>| VerifyOptions { hash_key: key, ..Default::default() };
+    let vr = verify_with_options(&mpath, &root, &with(Some(key.clone()))).unwrap();
+    assert!(vr.chunks_bad == 0 && vr.merkle_ok, "{:?}", vr);
+    let err = verify_with_options(&mpath, &root, &with(None)).unwrap_err();
+    assert!(err.to_string().contains("--hash-key"), "{}", err);
+    let other = HashKey::from_secret(b"another secret of sixteen bytes");
+    let err = verify_with_options(&mpath, &root, &with(Some(other))).unwrap_err();
+    assert!(err.to_string().contains("wrong hash key"), "{}", err);
+
+    let mut bad = data.clone();
+    bad[5000] ^= 0xFF;
+    bad[20_000] ^= 0xFF;
+    fs::write(root.join("a.bin"), &bad).unwrap();
+    let vr = verify_with_options(&mpath, &root, &with(Some(key.clone()))).unwrap();
+    assert_eq!(vr.chunks_bad, 2);
+    let ropts = RepairOptions { hash_key: Some(key), ..Default::default() };
+    let rr = repair_with_options(&mpath, &root, PathPolicy::default(), &ropts).unwrap();
+    assert_eq!((rr.repaired_chunks, rr.failed_chunks), (2, 0), "{:?}", rr);
+    assert_eq!(fs::read(root.join("a.bin")).unwrap(), data);
+
+    // Commands without the key refuse the set rather than call it damaged
+    let err = audit::audit(&mpath, &root, PathPolicy::default()).unwrap_err();
+    assert!(err.to_string().contains("keyed chunk hashes"), "{}", err);
+    let err = update::update(&out, &root, None).unwrap_err();
+    assert!(err.to_string().contains("keyed chunk hashes"), "{}", err);
+}
+
+#[test]
+fn a_plain_set_refuses_a_key() {
+    let td = tempfile::tempdir().unwrap();
+    let (root, out) = (td.path().join("data"), td.path().join(".parx"));
+    fs::create_dir_all(&root).unwrap();
+    fs::write(root.join("a.bin"), vec![7u8; 10_000]).unwrap();
+    Encoder::encode(&root, &out, &common::cfg(4096)).unwrap();
+    let opts =
+        VerifyOptions { hash_key: Some(HashKey::from_secret(&[1; 32])), ..Default::default() };
+    let err = verify_with_options(&out.join(MANIFEST_JSON), &root, &opts).unwrap_err();
+    assert!(err.to_string().contains("plain chunk hashes"), "{}", err);
+}
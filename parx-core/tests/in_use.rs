@@ -0,0 +1,81 @@
+#![cfg(unix)]
+
+use fs2::FileExt;
+use parx_core::encode::Encoder;
+use parx_core::inuse::{self, IfInUse};
+use parx_core::repair::{self, RepairOptions};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+mod common;
+
+/// a.bin and b.bin in a set, both then damaged; returns the root and the
+/// manifest path.
+fn setup(td: &Path) -> (PathBuf, PathBuf) {
+    let root = td.join("data");
+    fs::create_dir(&root).unwrap();
+    fs::write(root.join("a.bin"), vec![1u8; 16 * 1024]).unwrap();
+    fs::write(root.join("b.bin"), vec![2u8; 16 * 1024]).unwrap();
+    let out = td.join(".parx");
+    let cfg = common::cfg(4096);
+    Encoder::encode(&root, &out, &cfg).unwrap();
+    for (name, byte) in [("a.bin", 1u8), ("b.bin", 2u8)] {
+        let mut bad = vec![byte; 16 * 1024];
+        bad[5000..5100].fill(0xEE);
+        fs::write(root.join(name), bad).unwrap();
+    }
+    (root, out.join("manifest.json"))
+}
+
+#[test]
+fn a_locked_file_is_skipped_unless_forced() {
+    let td = tempfile::tempdir().unwrap();
+    let (root, mf) = setup(td.path());
+    let a = root.join("a.bin");
+    let holder = fs::File::open(&a).unwrap();
+    holder.lock_exclusive().unwrap();
+    assert!(inuse::in_use(&a).unwrap().contains("locked"));
+    assert_eq!(inuse::in_use(&root.join("b.bin")), None);
+
+    let rep =
+        repair::repair_with_options(&mf, &root, Default::default(), &RepairOptions::default())
+            .unwrap();
+    assert_eq!(rep.in_use_files.len(), 1, "{:?}", rep);
+    assert_eq!(rep.in_use_files[0].path, "a.bin");
+    assert_eq!(rep.repaired_chunks, 1);
+    assert_eq!(fs::read(root.join("b.bin")).unwrap(), vec![2u8; 16 * 1024]);
+    assert_ne!(fs::read(&a).unwrap(), vec![1u8; 16 * 1024]);
+
+    let opts = RepairOptions { if_in_use: IfInUse::Force, ..Default::default() };
+    let rep = repair::repair_with_options(&mf, &root, Default::default(), &opts).unwrap();
+    assert!(rep.in_use_files.is_empty(), "{:?}", rep);
+    assert_eq!(fs::read(&a).unwrap(), vec![1u8; 16 * 1024]);
+    drop(holder);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn a_file_open_for_writing_elsewhere_is_seen_and_waited_for() {
+    let td = tempfile::tempdir().unwrap();
+    let (root, mf) = setup(td.path());
+    let a = root.join("a.bin");
+    // Another process holds a.bin open for appending, like a logger would
+    let mut child = std::process::Command::new("sh")
+        .arg("-c")
+        .arg("exec 3>>\"$0\"; sleep 2")
+        .arg(&a)
+        .spawn()
+        .unwrap();
+    let seen = (0..50).find_map(|_| {
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        inuse::in_use(&a)
+    });
+    assert!(seen.is_some_and(|why| why.contains("open for writing by pid")));
+
+    let opts = RepairOptions { if_in_use: IfInUse::Wait, ..Default::default() };
+    let rep = repair::repair_with_options(&mf, &root, Default::default(), &opts).unwrap();
+    child.wait().unwrap();
+    assert!(rep.in_use_files.is_empty(), "{:?}", rep);
+    assert_eq!(rep.repaired_chunks, 2);
+    assert_eq!(fs::read(&a).unwrap(), vec![1u8; 16 * 1024]);
+}
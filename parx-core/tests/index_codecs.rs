@@ -0,0 +1,137 @@
+use parx_core::codec::{self, Codec, Codecs};
+use parx_core::encode::{EncodeOptions, Encoder};
+use parx_core::index::{read_index, read_trailer, IndexLimits};
+use parx_core::volume::VolumeHeader;
+use parx_core::{manifest_backup, update};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+
+mod common;
+
+#[test]
+fn every_codec_roundtrips() {
+    let mut rng = StdRng::seed_from_u64(3);
+    let random: Vec<u8> = (0..100_000).map(|_| rng.gen()).collect();
+    let mut repetitive = Vec::new();
+    for i in 0..20_000u32 {
+        repetitive.extend_from_slice(&(i / 7).to_le_bytes());
+        repetitive.extend_from_slice(b"PARX");
+    }
+    // A match just within and one beyond the 64 KiB window
+    let mut far = random[..70_000].to_vec();
+    far.extend_from_slice(&random[4_465..5_000]);
+    far.extend_from_slice(&random[..300]);
+    let inputs: [&[u8]; 7] = [b"", b"a", b"abcabcabcabc", &[7u8; 5000], &random, &repetitive, &far];
+    for c in [Codec::None, Codec::Lz4, Codec::Zstd(0), Codec::Zstd(19)] {
+        for input in inputs {
+            let packed = c.compress(input).unwrap();
+            assert!(packed.len() <= c.max_len(input.len()), "{} over its bound", c);
+            assert!(codec::is_compressed(&packed));
+            assert_eq!(codec::decompress(&packed, input.len()).unwrap(), input, "{}", c);
+        }
+    }
+    let packed = Codec::Lz4.compress(&repetitive).unwrap();
+    assert!(packed.len() < repetitive.len() / 4);
+    assert!(codec::decompress(&packed, repetitive.len() - 1).is_err());
+    // Damaged lz4 data is refused, not expanded
+    let mut bad = packed.clone();
+    let n = bad.len();
+    bad[n / 2..].fill(0xFF);
+    assert!(codec::decompress(&bad, repetitive.len()).map_or(true, |out| out != repetitive));
+}
+
+/// `PXCD` frame around an LZ4 block of `raw_len` bytes.
+fn lz4_frame(block: &[u8], raw_len: u64) -> Vec<u8> {
+    let mut f = b"PXCD".to_vec();
+    f.push(codec::id::LZ4);
+    f.extend_from_slice(&raw_len.to_le_bytes());
+    f.extend_from_slice(block);
+    f
+}
+
+#[test]
+fn lz4_reads_blocks_written_by_the_spec_and_refuses_bad_ones() {
+    // Built by hand from lz4_Block_format.md: 4 literals "abcd" and a match
+    // at offset 4 of 4 + 15 + 1 bytes, then the 5 closing literals
+    let block =
+        [0x4F, b'a', b'b', b'c', b'd', 0x04, 0x00, 0x01, 0x50, b'h', b'e', b'l', b'l', b'o'];
+    let raw = [&b"abcd".repeat(6)[..], b"hello"].concat();
+    assert_eq!(codec::decompress(&lz4_frame(&block, 29), 29).unwrap(), raw);
+    // A single literal run, the shape of incompressible input
+    let mut lits = vec![0xF0, 20 - 15];
+    lits.extend_from_slice(b"twenty literal bytes");
+    assert_eq!(codec::decompress(&lz4_frame(&lits, 20), 20).unwrap(), b"twenty literal bytes");
+
+    // Cut anywhere, the block is refused
+    for cut in 0..block.len() {
+        assert!(codec::decompress(&lz4_frame(&block[..cut], 29), 29).is_err(), "cut at {}", cut);
+    }
+    // A match longer than the recorded size, or reaching before the start
+    let mut long = block;
+    long[7] = 0x02;
+    assert!(codec::decompress(&lz4_frame(&long, 29), 29).is_err());
+    let mut early = block;
+    early[5] = 0x05;
+    assert!(codec::decompress(&lz4_frame(&early, 29), 29).is_err());
+    // More literals than the block holds
+    let mut short = block;
+    short[8] = 0x60;
+    assert!(codec::decompress(&lz4_frame(&short, 29), 29).is_err());
+
+    // What we write is a plain block: the frame carries it untouched
+    let packed = Codec::Lz4.compress(&raw).unwrap();
+    assert_eq!(codec::decompress(&lz4_frame(&packed[13..], 29), 29).unwrap(), raw);
+}
+
+#[test]
+fn codec_names_parse() {
+    assert_eq!("lz4".parse::<Codec>().unwrap(), Codec::Lz4);
+    assert_eq!("zstd:9".parse::<Codec>().unwrap(), Codec::Zstd(9));
+    assert_eq!("ZSTD".parse::<Codec>().unwrap(), Codec::Zstd(0));
+    assert_eq!(Codec::Zstd(9).to_string(), "zstd:9");
+    assert!("zstd:99".parse::<Codec>().is_err());
+    assert!("brotli".parse::<Codec>().is_err());
+}
+
+/// First bytes of the index of `vol` and its recorded codecs.
+fn index_head(vol: &std::path::Path) -> ([u8; 4], Codecs) {
+    let mut f = File::open(vol).unwrap();
+    let (off, len, crc) = read_trailer(&mut f).unwrap();
+    read_index(&mut f, off, len, crc, &IndexLimits::default()).unwrap();
+    let mut head = [0u8; 4];
+    f.seek(SeekFrom::Start(off)).unwrap();
+    f.read_exact(&mut head).unwrap();
+    (head, Codecs::from_ext(&VolumeHeader::read_from(&f).unwrap().ext))
+}
+
+#[test]
+fn chosen_codecs_are_recorded_and_kept_by_update() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    let out = td.path().join(".parx");
+    fs::create_dir_all(&root).unwrap();
+    fs::write(root.join("a.bin"), vec![1u8; 9000]).unwrap();
+    let codecs = Codecs { index: Codec::Lz4, backup: Codec::Zstd(5) };
+    let opts = EncodeOptions { codecs, ..Default::default() };
+    Encoder::encode_with(&root, &out, &common::cfg(1024), &opts).unwrap();
+    let vol = out.join("vol-000.parxv");
+    assert_eq!(index_head(&vol), (*b"PXCD", codecs));
+    let (mf, rep) = manifest_backup::read(&vol).unwrap();
+    assert!(rep.is_none());
+    assert_eq!(mf.files.len(), 1);
+
+    fs::write(root.join("b.bin"), vec![2u8; 5000]).unwrap();
+    update::update(&out, &root, None).unwrap();
+    assert_eq!(index_head(&vol), (*b"PXCD", codecs));
+    let (mf, _) = manifest_backup::read(&vol).unwrap();
+    assert_eq!(mf.files.len(), 2);
+
+    // The default stays a bare zstd frame that older readers understand
+    let out2 = td.path().join(".parx2");
+    Encoder::encode_with(&root, &out2, &common::cfg(1024), &EncodeOptions::default()).unwrap();
+    let (head, recorded) = index_head(&out2.join("vol-000.parxv"));
+    assert_eq!((head, recorded), ([0x28, 0xB5, 0x2F, 0xFD], Codecs::default()));
+    let hdr = VolumeHeader::read_from(File::open(out2.join("vol-000.parxv")).unwrap()).unwrap();
+    assert!(hdr.ext.get(parx_core::ext::key::INDEX_CODEC).is_none());
+}
@@ -0,0 +1,91 @@
+use parx_core::encode::Encoder;
+use parx_core::index::{read_index, read_trailer, IndexCopy, IndexLimits};
+use parx_core::volume::{vol_name, VolumeHeader};
+use parx_core::{repair, update};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+
+mod common;
+
+fn random(seed: u64, len: usize) -> Vec<u8> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    (0..len).map(|_| rng.gen()).collect()
+}
+
+fn head_copy(vol: &Path) -> IndexCopy {
+    let hdr = VolumeHeader::read_from(File::open(vol).unwrap()).unwrap();
+    IndexCopy::from_ext(&hdr.ext).unwrap()
+}
+
+/// Overwrite everything from the start of the index to the end of the file.
+fn wreck_tail(vol: &Path) {
+    let mut f = OpenOptions::new().read(true).write(true).open(vol).unwrap();
+    let (off, _, _) = read_trailer(&mut f).unwrap();
+    let len = f.metadata().unwrap().len();
+    f.seek(SeekFrom::Start(off)).unwrap();
+    f.write_all(&vec![0x5a; (len - off) as usize]).unwrap();
+}
+
+#[test]
+fn wrecked_tail_falls_back_to_head_copy() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir_all(&root).unwrap();
+    let data = random(1, 100_000);
+    fs::write(root.join("a.bin"), &data).unwrap();
+    let out = td.path().join(".parx");
+    Encoder::encode(&root, &out, &common::cfg(4096)).unwrap();
+
+    let vol = out.join(vol_name(0));
+    let copy = head_copy(&vol);
+    assert!(copy.len > 0 && copy.len <= copy.cap);
+    let mut f = File::open(&vol).unwrap();
+    let (off, len, crc) = read_trailer(&mut f).unwrap();
+    assert_eq!((copy.idx_off, copy.len, copy.crc), (off, len, crc));
+    let before = read_index(&mut f, off, len, crc, &IndexLimits::default()).unwrap();
+    drop(f);
+
+    // Index, metadata parity and trailer all gone
+    wreck_tail(&vol);
+    let mut f = File::open(&vol).unwrap();
+    let (off, len, crc) = read_trailer(&mut f).unwrap();
+    let after = read_index(&mut f, off, len, crc, &IndexLimits::default()).unwrap();
+    assert_eq!(format!("{:?}", after), format!("{:?}", before));
+    drop(f);
+
+    let mut bad = data.clone();
+    bad[..4096].fill(0);
+    fs::write(root.join("a.bin"), &bad).unwrap();
+    let rr = repair::repair(&out.join("manifest.json"), &root).unwrap();
+    assert_eq!((rr.repaired_chunks, rr.failed_chunks), (1, 0));
+    assert_eq!(fs::read(root.join("a.bin")).unwrap(), data);
+}
+
+#[test]
+fn copy_is_dropped_once_the_index_outgrows_its_space() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir_all(&root).unwrap();
+    fs::write(root.join("a.bin"), random(2, 20_000)).unwrap();
+    let out = td.path().join(".parx");
+    Encoder::encode(&root, &out, &common::cfg(4096)).unwrap();
+    let vol = out.join(vol_name(0));
+    let reserved = head_copy(&vol);
+
+    // Far more stripes than the reservation was sized for
+    fs::write(root.join("b.bin"), random(3, 8 << 20)).unwrap();
+    update::update(&out, &root, None).unwrap();
+    let copy = head_copy(&vol);
+    assert_eq!((copy.off, copy.cap), (reserved.off, reserved.cap));
+    assert_eq!(copy.len, 0);
+    let mut f = File::open(&vol).unwrap();
+    let (off, len, crc) = read_trailer(&mut f).unwrap();
+    assert_eq!(copy.idx_off, off);
+    read_index(&mut f, off, len, crc, &IndexLimits::default()).unwrap();
+    drop(f);
+
+    wreck_tail(&vol);
+    assert!(read_trailer(&mut File::open(&vol).unwrap()).is_err());
+}
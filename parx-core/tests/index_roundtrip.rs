@@ -1,5 +1,5 @@
 use parx_core::index;
-use parx_core::volume::VolumeEntry;
+use parx_core::volume::{self, ShardKind, VolumeEntry, VolumeEntryV2};
 use std::fs::File;
 use std::io::Write;
 
@@ -17,7 +17,7 @@ fn index_write_read_roundtrip() {
             offset: 32,
             len: 1024,
             hash: None,
-            outer_for_stripe: None,
+            kind: ShardKind::Inner,
         },
         VolumeEntry {
             stripe: 1,
@@ -25,7 +25,7 @@ fn index_write_read_roundtrip() {
             offset: 1056,
             len: 1024,
             hash: None,
-            outer_for_stripe: None,
+            kind: ShardKind::Inner,
         },
     ];
     index::write_index_and_trailer(&f, &entries).unwrap();
@@ -37,3 +37,143 @@ fn index_write_read_roundtrip() {
     assert_eq!(out[0].stripe, 0);
     assert_eq!(out[1].parity_idx, 1);
 }
+
+#[test]
+fn wide_trailer_is_read_and_used_only_when_needed() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("vol-wide.parxv");
+    let f = File::create(&path).unwrap();
+    (&f).write_all(&[0u8; 32]).unwrap();
+    let entries = vec![VolumeEntry { stripe: 3, offset: 32, len: 64, ..Default::default() }];
+    index::write_index_and_trailer(&f, &entries).unwrap();
+    drop(f);
+
+    // Small indices keep the 32-bit trailer older readers understand
+    let mut f = File::open(&path).unwrap();
+    let (off, len, crc) = index::read_trailer(&mut f).unwrap();
+    let v1 = std::fs::read(&path).unwrap();
+    assert_eq!(&v1[v1.len() - 26..v1.len() - 17], b"PARXINDEX");
+    assert_eq!(v1[v1.len() - 17], 0);
+
+    // Lengths past 4 GiB switch to the 64-bit trailer
+    let wide = index::encode_trailer(off, 5 << 30, crc);
+    assert_eq!(wide.len(), 30);
+    assert_eq!(wide[9], 2);
+
+    // Same index behind a 64-bit trailer (its real length in the wide field)
+    let mut v2 = v1[..v1.len() - 26].to_vec();
+    v2.extend_from_slice(&wide[..18]);
+    v2.extend_from_slice(&len.to_le_bytes());
+    v2.extend_from_slice(&crc.to_le_bytes());
+    std::fs::write(&path, &v2).unwrap();
+    let mut f = File::open(&path).unwrap();
+    let (off2, len2, crc2) = index::read_trailer(&mut f).unwrap();
+    assert_eq!((off2, len2, crc2), (off, len, crc));
+    let out = index::read_index(&mut f, off2, len2, crc2, &index::IndexLimits::default()).unwrap();
+    assert_eq!(out[0].stripe, 3);
+}
+
+#[test]
+fn v3_entries_carry_wide_stripes_and_v2_outer_sentinel_is_mapped() {
+    let big = VolumeEntry {
+        stripe: (1u64 << 33) + 5,
+        parity_idx: 2,
+        offset: 4096,
+        len: 512,
+        hash: Some([9u8; 32]),
+        kind: ShardKind::Outer,
+    };
+    let raw = volume::encode_entries(std::slice::from_ref(&big)).unwrap();
+    assert!(raw.starts_with(volume::ENTRIES_V3_MAGIC));
+    assert_eq!(volume::decode_entries_anyver(&raw).unwrap(), vec![big]);
+
+    // Indices written before v3: outer shards used the u32::MAX sentinel
+    let legacy = vec![
+        VolumeEntryV2 {
+            stripe: 7,
+            parity_idx: 0,
+            offset: 32,
+            len: 64,
+            hash: None,
+            outer_for_stripe: None,
+        },
+        VolumeEntryV2 {
+            stripe: u32::MAX,
+            parity_idx: 1,
+            offset: 96,
+            len: 64,
+            hash: None,
+            outer_for_stripe: Some(7),
+        },
+    ];
+    let out = volume::decode_entries_anyver(&bincode::serialize(&legacy).unwrap()).unwrap();
+    assert_eq!((out[0].stripe, out[0].kind), (7, ShardKind::Inner));
+    assert_eq!((out[1].stripe, out[1].kind), (7, ShardKind::Outer));
+}
+
+#[test]
+fn shard_kinds_from_newer_writers_survive_decoding() {
+    let entries: Vec<VolumeEntry> = [0u8, 2, 3, 4, 200]
+        .iter()
+        .enumerate()
+        .map(|(i, &k)| VolumeEntry {
+            stripe: i as u64,
+            kind: ShardKind::from(k),
+            ..Default::default()
+        })
+        .collect();
+    let out = volume::decode_entries_anyver(&volume::encode_entries(&entries).unwrap()).unwrap();
+    assert_eq!(out, entries);
+    assert_eq!(out[1].kind, ShardKind::ManifestBackup);
+    assert_eq!(out[4].kind, ShardKind::Unknown(200));
+    assert_eq!(out[4].kind.to_string(), "unknown(200)");
+    assert_eq!(out.iter().filter(|e| e.is_inner()).count(), 1);
+}
+
+#[test]
+fn newer_index_schemas_and_codecs_are_refused_by_name() {
+    let entries = vec![VolumeEntry { stripe: 4, offset: 32, len: 64, ..Default::default() }];
+    let mut raw = volume::encode_entries(&entries).unwrap();
+    assert_eq!(volume::entries_schema(&raw), Some(volume::ENTRIES_SCHEMA));
+    assert_eq!(volume::entries_schema(&bincode::serialize(&entries).unwrap()), None);
+
+    raw[6] = b'4';
+    assert_eq!(volume::entries_schema(&raw), Some(4));
+    let e = volume::decode_entries(&raw, false).unwrap_err();
+    assert!(e.is::<volume::FeatureError>());
+    assert!(e.to_string().contains("index schema v4 was written by a newer parx"), "{}", e);
+    // The escape hatch reads it as v3
+    assert_eq!(volume::decode_entries(&raw, true).unwrap(), entries);
+    // ...and still fails, by name, when the layout really changed
+    raw.truncate(12);
+    let e = volume::decode_entries(&raw, true).unwrap_err();
+    assert!(e.to_string().contains("does not read as v3"), "{}", e);
+
+    // The same through a volume's index
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("vol-next.parxv");
+    let mut v4 = volume::encode_entries(&entries).unwrap();
+    v4[6] = b'4';
+    let payload = parx_core::codec::Codec::default().compress(&v4).unwrap();
+    let mut bytes = vec![0u8; 32];
+    bytes.extend_from_slice(&payload);
+    bytes.extend_from_slice(&index::encode_trailer(32, payload.len() as u64, {
+        let mut h = crc32fast::Hasher::new();
+        h.update(&payload);
+        h.finalize()
+    }));
+    std::fs::write(&path, &bytes).unwrap();
+    let mut f = File::open(&path).unwrap();
+    let (off, len, crc) = index::read_trailer(&mut f).unwrap();
+    let e = index::read_index(&mut f, off, len, crc, &index::IndexLimits::default()).unwrap_err();
+    assert!(e.is::<volume::FeatureError>(), "{:#}", e);
+
+    // A codec id this build does not know
+    let mut frame = b"PXCD".to_vec();
+    frame.push(9);
+    frame.extend_from_slice(&4u64.to_le_bytes());
+    frame.extend_from_slice(b"abcd");
+    let e = parx_core::codec::decompress(&frame, 1 << 20).unwrap_err();
+    assert!(e.is::<volume::FeatureError>());
+    assert!(e.to_string().contains("codec id 9 by a newer parx"), "{}", e);
+}
@@ -0,0 +1,85 @@
+use parx_core::retry::{is_transient, RetryPolicy, RetryStats, MAX_BACKOFF};
+use std::cell::Cell;
+use std::io::{Error, ErrorKind};
+use std::time::Duration;
+
+fn policy(retries: u32) -> RetryPolicy {
+    RetryPolicy { retries, backoff: Duration::from_millis(1) }
+}
+
+#[test]
+fn transient_errors_are_retried_until_the_call_succeeds() {
+    let stats = RetryStats::default();
+    let ticks = Cell::new(0);
+    let mut attempts = Vec::new();
+    let got = policy(3).run(
+        &stats,
+        || ticks.set(ticks.get() + 1),
+        |attempt| {
+            attempts.push(attempt);
+            match attempt {
+                0 => Err(Error::from(ErrorKind::ConnectionReset)),
+                1 => Err(Error::from(ErrorKind::TimedOut)),
+                _ => Ok(7),
+            }
+        },
+    );
+    assert_eq!(got.unwrap(), 7);
+    assert_eq!(attempts, [0, 1, 2]);
+    assert_eq!((stats.retries(), stats.recovered()), (2, 1));
+    assert!(ticks.get() >= 2);
+
+    // A second call shares the counts
+    let calls = Cell::new(0);
+    let got: std::io::Result<()> = policy(2).run(
+        &stats,
+        || {},
+        |_| {
+            calls.set(calls.get() + 1);
+            Err(Error::from(ErrorKind::Interrupted))
+        },
+    );
+    assert_eq!(got.unwrap_err().kind(), ErrorKind::Interrupted);
+    assert_eq!(calls.get(), 3);
+    assert_eq!((stats.retries(), stats.recovered()), (4, 1));
+}
+
+#[test]
+fn lasting_errors_fail_at_once() {
+    let stats = RetryStats::default();
+    for kind in [ErrorKind::NotFound, ErrorKind::PermissionDenied, ErrorKind::UnexpectedEof] {
+        let calls = Cell::new(0);
+        let got: std::io::Result<()> = policy(5).run(
+            &stats,
+            || {},
+            |_| {
+                calls.set(calls.get() + 1);
+                Err(Error::from(kind))
+            },
+        );
+        assert_eq!(got.unwrap_err().kind(), kind);
+        assert_eq!(calls.get(), 1, "{:?}", kind);
+        assert!(!is_transient(&Error::from(kind)));
+    }
+    assert_eq!(stats.retries(), 0);
+
+    let got: std::io::Result<()> =
+        RetryPolicy::NONE.run(&stats, || {}, |_| Err(Error::from(ErrorKind::TimedOut)));
+    assert!(got.is_err());
+    assert_eq!(stats.retries(), 0);
+}
+
+#[test]
+fn stale_handles_count_as_transient_and_backoff_is_capped() {
+    #[cfg(target_os = "linux")]
+    assert!(is_transient(&Error::from_raw_os_error(116)));
+    assert!(is_transient(&Error::from(ErrorKind::BrokenPipe)));
+    // EIO on a local disk is a bad sector, not a hiccup
+    #[cfg(unix)]
+    assert!(!is_transient(&Error::from_raw_os_error(5)));
+
+    let p = RetryPolicy { retries: 40, backoff: Duration::from_millis(500) };
+    assert_eq!(p.delay(0), Duration::from_millis(500));
+    assert_eq!(p.delay(2), Duration::from_secs(2));
+    assert_eq!(p.delay(39), MAX_BACKOFF);
+}
@@ -0,0 +1,53 @@
+#![cfg(unix)]
+
+use parx_core::encode::Encoder;
+use parx_core::repair::{self, RepairOptions};
+use parx_core::verify::{self, VerifyOptions};
+use std::fs::{self, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::Duration;
+
+mod common;
+
+/// a.bin (stripe 0) and b.bin (stripe 1); b.bin is then swapped for a FIFO
+/// without a writer, whose open blocks like a read from a hung disk.
+fn setup(td: &Path) -> (std::path::PathBuf, std::path::PathBuf) {
+    let root = td.join("data");
+    fs::create_dir(&root).unwrap();
+    fs::write(root.join("a.bin"), vec![1u8; 16 * 1024]).unwrap();
+    fs::write(root.join("b.bin"), vec![2u8; 16 * 1024]).unwrap();
+    let out = td.join(".parx");
+    let cfg = common::cfg(4096);
+    Encoder::encode(&root, &out, &cfg).unwrap();
+    fs::remove_file(root.join("b.bin")).unwrap();
+    let st = std::process::Command::new("mkfifo").arg(root.join("b.bin")).status().unwrap();
+    assert!(st.success());
+    (root, out.join("manifest.json"))
+}
+
+#[test]
+fn verify_skips_and_reports_a_stalled_file() {
+    let td = tempfile::tempdir().unwrap();
+    let (root, mf) = setup(td.path());
+    let opts = VerifyOptions { io_timeout: Some(Duration::from_millis(200)), ..Default::default() };
+    let vr = verify::verify_with_options(&mf, &root, &opts).unwrap();
+    assert_eq!(vr.stalled_files, ["b.bin"]);
+    assert_eq!((vr.chunks_ok, vr.chunks_bad), (4, 4));
+    assert!(!vr.merkle_ok);
+}
+
+#[test]
+fn repair_completes_around_a_stalled_file() {
+    let td = tempfile::tempdir().unwrap();
+    let (root, mf) = setup(td.path());
+    let mut f = OpenOptions::new().write(true).open(root.join("a.bin")).unwrap();
+    f.seek(SeekFrom::Start(100)).unwrap();
+    f.write_all(&[0xEE; 10]).unwrap();
+    drop(f);
+    let opts = RepairOptions { io_timeout: Some(Duration::from_millis(200)), ..Default::default() };
+    let rr = repair::repair_with_options(&mf, &root, Default::default(), &opts).unwrap();
+    assert_eq!(rr.stalled_files, ["b.bin"]);
+    assert_eq!((rr.repaired_chunks, rr.failed_chunks), (1, 0));
+    assert_eq!(fs::read(root.join("a.bin")).unwrap(), vec![1u8; 16 * 1024]);
+}
@@ -0,0 +1,125 @@
+use parx_core::encode::{EncodeOptions, Encoder};
+use parx_core::jobs::{JobManager, JobState};
+use parx_core::progress::{Cancelled, Progress, ProgressSink, Snapshot};
+use parx_core::repair::{self, RepairOptions};
+use parx_core::verify::{self, VerifyOptions};
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+mod common;
+
+/// A protected tree with one flipped byte in a.bin; returns a.bin as it was.
+fn damaged_set(root: &Path, out: &Path) -> Vec<u8> {
+    fs::create_dir_all(root).unwrap();
+    let a: Vec<u8> = (0..30_000u32).map(|i| (i % 253) as u8).collect();
+    fs::write(root.join("a.bin"), &a).unwrap();
+    fs::write(root.join("b.bin"), vec![9u8; 7_000]).unwrap();
+    Encoder::encode(root, out, &common::cfg(4096)).unwrap();
+    let mut bad = a.clone();
+    bad[12_345] ^= 0x40;
+    fs::write(root.join("a.bin"), bad).unwrap();
+    a
+}
+
+#[derive(Default)]
+struct Recorder(Mutex<Vec<Snapshot>>);
+
+impl ProgressSink for Recorder {
+    fn report(&self, snap: &Snapshot) {
+        self.0.lock().unwrap().push(snap.clone());
+    }
+}
+
+#[test]
+fn jobs_report_status_progress_and_results() {
+    let td = tempfile::tempdir().unwrap();
+    let (root, out) = (td.path().join("data"), td.path().join(".parx"));
+    let a = damaged_set(&root, &out);
+    let mpath = out.join("manifest.json");
+    let jobs = JobManager::new(Duration::from_millis(10));
+
+    let v = jobs.spawn_verify(mpath.clone(), root.clone(), VerifyOptions::default());
+    let rec = Arc::new(Recorder::default());
+    assert!(jobs.subscribe(v, rec.clone()));
+    let st = jobs.wait(v).unwrap();
+    assert_eq!((st.kind.as_str(), st.state), ("verify", JobState::Succeeded));
+    assert_eq!(st.result.as_ref().unwrap()["chunks_bad"], 1);
+    assert!(st.progress.last && st.error.is_none());
+    let snaps = rec.0.lock().unwrap().clone();
+    let last = snaps.last().unwrap();
+    assert!(last.last && last.stages[0].path == "verify", "{:?}", last);
+    assert_eq!(last.stages[0].items_done, 2);
+
+    let r = jobs.spawn_repair(mpath.clone(), root.clone(), Default::default(), Default::default());
+    assert_ne!(r, v);
+    let st = jobs.wait(r).unwrap();
+    assert_eq!(st.state, JobState::Succeeded, "{:?}", st.error);
+    assert_eq!(st.result.as_ref().unwrap()["repaired_chunks"], 1);
+    assert_eq!(fs::read(root.join("a.bin")).unwrap(), a);
+    let stages: Vec<&str> = st.progress.stages.iter().map(|s| s.path.as_str()).collect();
+    assert_eq!(stages, ["scan", "rebuild"]);
+
+    // A finished job hands its last snapshot to late subscribers
+    let late = Arc::new(Recorder::default());
+    assert!(jobs.subscribe(r, late.clone()));
+    assert!(late.0.lock().unwrap()[0].last);
+    assert!(!jobs.cancel(r));
+
+    let ids: Vec<u64> = jobs.list().iter().map(|s| s.id).collect();
+    assert_eq!(ids, [v, r]);
+    assert_eq!(jobs.remove(v).unwrap().state, JobState::Succeeded);
+    assert!(jobs.status(v).is_none() && jobs.wait(v).is_none());
+
+    let bad = jobs.spawn_verify(td.path().join("nope.json"), root, VerifyOptions::default());
+    let st = jobs.wait(bad).unwrap();
+    assert!(st.state == JobState::Failed && st.error.is_some() && st.result.is_none());
+}
+
+#[test]
+fn cancelled_jobs_stop_at_their_next_check() {
+    let jobs = JobManager::default();
+    let id = jobs.spawn("wait", |progress: Progress| {
+        while !progress.is_cancelled() {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        progress.check_cancelled()?;
+        Ok(())
+    });
+    assert_eq!(jobs.status(id).unwrap().state, JobState::Running);
+    assert!(jobs.remove(id).is_none(), "running jobs stay");
+    assert!(jobs.cancel(id));
+    let st = jobs.status(id).unwrap().state;
+    assert!(st == JobState::Cancelling || st == JobState::Cancelled, "{:?}", st);
+    let st = jobs.wait(id).unwrap();
+    assert_eq!((st.state, st.error.as_deref()), (JobState::Cancelled, Some("cancelled")));
+
+    let id = jobs.spawn("panics", |_| -> anyhow::Result<()> { panic!("boom") });
+    assert_eq!(jobs.wait(id).unwrap().state, JobState::Failed);
+}
+
+#[test]
+fn operations_check_their_progress_for_cancellation() {
+    let td = tempfile::tempdir().unwrap();
+    let (root, out) = (td.path().join("data"), td.path().join(".parx"));
+    damaged_set(&root, &out);
+    let mpath = out.join("manifest.json");
+    let damaged = fs::read(root.join("a.bin")).unwrap();
+    let cancelled = Progress::new();
+    cancelled.cancel();
+
+    let opts = VerifyOptions { progress: Some(cancelled.clone()), ..Default::default() };
+    let err = verify::verify_with_options(&mpath, &root, &opts).unwrap_err();
+    assert!(err.downcast_ref::<Cancelled>().is_some(), "{:#}", err);
+
+    let opts = RepairOptions { progress: Some(cancelled.clone()), ..Default::default() };
+    let err = repair::repair_with_options(&mpath, &root, Default::default(), &opts).unwrap_err();
+    assert!(err.downcast_ref::<Cancelled>().is_some(), "{:#}", err);
+    assert_eq!(fs::read(root.join("a.bin")).unwrap(), damaged, "nothing written");
+
+    let opts = EncodeOptions { progress: Some(cancelled), ..Default::default() };
+    let err =
+        Encoder::encode_with(&root, &td.path().join("new"), &common::cfg(4096), &opts).unwrap_err();
+    assert!(err.downcast_ref::<Cancelled>().is_some(), "{:#}", err);
+}
@@ -0,0 +1,89 @@
+use parx_core::encode::{Encoder, EncoderConfig};
+use parx_core::{manifest, repair, verify};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+mod common;
+
+const TIB: u64 = 1 << 40;
+
+fn cfg(chunk_size: usize) -> EncoderConfig {
+    EncoderConfig { stripe_k: 2, volumes: 1, ..common::cfg(chunk_size) }
+}
+
+fn write_at(path: &Path, off: u64, data: &[u8]) {
+    let mut f = OpenOptions::new().write(true).open(path).unwrap();
+    f.seek(SeekFrom::Start(off)).unwrap();
+    f.write_all(data).unwrap();
+}
+
+fn read_at(path: &Path, off: u64, len: usize) -> Vec<u8> {
+    let mut f = fs::File::open(path).unwrap();
+    f.seek(SeekFrom::Start(off)).unwrap();
+    let mut buf = vec![0u8; len];
+    f.read_exact(&mut buf).unwrap();
+    buf
+}
+
+#[test]
+fn chunk_past_5_tib_of_a_sparse_file_is_verified_and_repaired_in_place() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir_all(&root).unwrap();
+    let mut rng = StdRng::seed_from_u64(5);
+    let tail: Vec<u8> = (0..4096).map(|_| rng.gen()).collect();
+    let other: Vec<u8> = (0..4096).map(|_| rng.gen()).collect();
+    fs::write(root.join("big.img"), &tail).unwrap();
+    fs::write(root.join("other.bin"), &other).unwrap();
+    let out = td.path().join(".parx");
+    Encoder::encode(&root, &out, &cfg(4096)).unwrap();
+
+    // Move the chunk to 5 TiB into a sparse file, as if the image had been
+    // protected whole with its leading holes left out of the layout
+    let big = root.join("big.img");
+    let at = 5 * TIB;
+    let f = OpenOptions::new().write(true).truncate(true).open(&big).unwrap();
+    f.set_len(at + 4096).unwrap();
+    drop(f);
+    write_at(&big, at, &tail);
+    let mpath = out.join("manifest.json");
+    let (mut mf, _) = manifest::load(&mpath).unwrap();
+    let fe = mf.files.iter_mut().find(|f| f.rel_path == "big.img").unwrap();
+    fe.size = at + 4096;
+    fe.chunks[0].file_offset = at;
+    manifest::save(&mf, &out).unwrap();
+
+    let vr = verify::verify(&mpath, &root).unwrap();
+    assert_eq!((vr.chunks_ok, vr.chunks_bad, vr.merkle_ok), (2, 0, true));
+
+    write_at(&big, at + 100, b"rot");
+    let vr = verify::verify(&mpath, &root).unwrap();
+    assert_eq!((vr.chunks_bad, vr.damaged_files[0].path.as_str()), (1, "big.img"));
+
+    let rr = repair::repair(&mpath, &root).unwrap();
+    assert_eq!((rr.repaired_chunks, rr.failed_chunks), (1, 0));
+    assert_eq!(read_at(&big, at, 4096), tail);
+    // Patched where it was damaged: still sparse, and no whole-file backup
+    let md = fs::metadata(&big).unwrap();
+    assert_eq!(md.len(), at + 4096);
+    #[cfg(unix)]
+    assert!(std::os::unix::fs::MetadataExt::blocks(&md) < 1024);
+    assert!(!root.join("big.parx.bak").exists());
+    let vr = verify::verify(&mpath, &root).unwrap();
+    assert_eq!((vr.chunks_bad, vr.merkle_ok), (0, true));
+}
+
+#[test]
+fn chunk_sizes_past_32_bits_are_refused() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir_all(&root).unwrap();
+    fs::write(root.join("a.bin"), b"x").unwrap();
+    let out = td.path().join(".parx");
+    for size in [0, u32::MAX as usize + 1] {
+        let err = Encoder::encode(&root, &out, &cfg(size)).unwrap_err();
+        assert!(err.to_string().contains("is not between 1 byte and 4 GiB"), "{err}");
+    }
+}
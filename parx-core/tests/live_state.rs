@@ -0,0 +1,64 @@
+use parx_core::live::{self, RunState, StateFileSink, RUNNING_DIR};
+use parx_core::progress::{Progress, ProgressSink, Snapshot};
+use std::fs;
+use std::time::Duration;
+
+#[test]
+fn snapshots_carry_eta_current_item_and_busy_workers() {
+    let p = Progress::new();
+    p.set_workers(4);
+    let hash = p.stage("hash");
+    hash.add_total(1000, 2);
+    hash.set_current("photos/a.jpg");
+    std::thread::sleep(Duration::from_millis(20));
+    hash.add_bytes(250);
+    let busy = [p.busy(), p.busy()];
+
+    let snap = p.snapshot();
+    assert_eq!((snap.workers, snap.workers_busy), (4, 2));
+    let s = &snap.stages[0];
+    assert_eq!(s.current.as_deref(), Some("photos/a.jpg"));
+    // Three times as much left as done, at the same rate
+    let eta = s.eta_ms.expect("eta while running");
+    assert!(eta >= 2 * s.elapsed_ms && eta <= 4 * s.elapsed_ms + 10, "{:?}", s);
+
+    drop(busy);
+    hash.finish();
+    let snap = p.snapshot();
+    assert_eq!(snap.workers_busy, 0);
+    assert_eq!((snap.stages[0].eta_ms, snap.stages[0].current.as_deref()), (None, None));
+}
+
+#[test]
+fn state_file_is_published_found_and_removed() {
+    let td = tempfile::tempdir().unwrap();
+    let parx = td.path().join(".parx");
+    let photos = parx.join("sets/photos");
+    assert!(live::running(&parx).unwrap().is_empty());
+
+    let p = Progress::new();
+    p.stage("hash").add_total(10, 1);
+    let main = StateFileSink::new(&parx, "create", Duration::from_secs(1)).unwrap();
+    let named = StateFileSink::new(&photos, "update", Duration::from_secs(1)).unwrap();
+    main.report(&p.snapshot());
+    named.report(&p.snapshot());
+    assert!(main.path().starts_with(parx.join(RUNNING_DIR)));
+    // Leftovers of an interrupted write are not states
+    fs::write(parx.join(RUNNING_DIR).join("create-1.json.tmp"), b"{").unwrap();
+
+    let runs = live::running(&parx).unwrap();
+    let ops: Vec<&str> = runs.iter().map(|r| r.op.as_str()).collect();
+    assert_eq!(ops.len(), 2);
+    assert!(ops.contains(&"create") && ops.contains(&"update"));
+    let run = &runs[0];
+    assert_eq!(run.pid, std::process::id());
+    assert_eq!(run.snapshot.stages[0].bytes_total, 10);
+    assert!(!run.is_stale());
+
+    named.report(&Snapshot { last: true, ..p.snapshot() });
+    assert!(!photos.join(RUNNING_DIR).exists());
+    assert_eq!(live::running(&parx).unwrap().len(), 1);
+
+    let old = RunState { updated_ms: 1, ..runs[0].clone() };
+    assert!(old.is_stale());
+}
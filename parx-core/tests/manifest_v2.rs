@@ -0,0 +1,101 @@
+use parx_core::encode::{Encoder, EncoderConfig};
+use parx_core::{manifest, manifest_v2, verify};
+use std::fs;
+
+mod common;
+
+fn encode_three_files(td: &std::path::Path) -> std::path::PathBuf {
+    let root = td.join("data");
+    fs::create_dir_all(&root).unwrap();
+    fs::write(root.join("a.bin"), vec![1u8; 9_000]).unwrap();
+    fs::write(root.join("b.bin"), vec![2u8; 9_000]).unwrap();
+    fs::write(root.join("c.bin"), vec![3u8; 9_000]).unwrap();
+    let out = td.join(".parx");
+    let cfg = EncoderConfig { volumes: 1, ..common::cfg(4096) };
+    Encoder::encode(&root, &out, &cfg).unwrap();
+    out
+}
+
+#[test]
+fn v2_roundtrip_matches_json() {
+    let td = tempfile::tempdir().unwrap();
+    let out = encode_three_files(td.path());
+    let (json, rec) = manifest::load(&out.join(manifest::MANIFEST_JSON)).unwrap();
+    assert!(rec.is_none());
+    let v2 = manifest_v2::decode(&fs::read(out.join(manifest::MANIFEST_V2)).unwrap()).unwrap();
+    assert_eq!(serde_json::to_value(&json).unwrap(), serde_json::to_value(&v2).unwrap());
+}
+
+#[test]
+fn damaged_file_record_is_reported_and_others_survive() {
+    let td = tempfile::tempdir().unwrap();
+    let out = encode_three_files(td.path());
+    let mut data = fs::read(out.join(manifest::MANIFEST_V2)).unwrap();
+    // Scribble over the middle of the b.bin record
+    let at = data.windows(5).position(|w| w == b"b.bin").unwrap();
+    data[at..at + 5].copy_from_slice(b"XXXXX");
+
+    assert!(manifest_v2::decode(&data).is_err());
+    let (mf, rep) = manifest_v2::decode_partial(&data).unwrap();
+    assert_eq!(rep.files_expected, Some(3));
+    assert_eq!(rep.files_recovered, 2);
+    assert_eq!(rep.lost_files, vec![1]);
+    assert_eq!(rep.lost_chunk_ranges, vec![(3, 5)]);
+    // The slot table still names the file those chunks belonged to
+    assert_eq!(rep.lost_file_ids, vec![2]);
+    let names: Vec<_> = mf.files.iter().map(|f| f.rel_path.as_str()).collect();
+    assert_eq!(names, ["a.bin", "c.bin"]);
+}
+
+#[test]
+fn verify_falls_back_to_v2_when_json_is_garbage() {
+    let td = tempfile::tempdir().unwrap();
+    let out = encode_three_files(td.path());
+    let mpath = out.join(manifest::MANIFEST_JSON);
+    let mut json = fs::read(&mpath).unwrap();
+    json.truncate(json.len() / 2);
+    fs::write(&mpath, json).unwrap();
+
+    let rep = verify::verify(&mpath, &td.path().join("data")).unwrap();
+    assert_eq!(rep.chunks_bad, 0);
+    let rec = rep.manifest_recovery.expect("recovery report");
+    assert!(rec.is_complete());
+    assert_eq!(rec.files_recovered, 3);
+}
+
+/// The set of `encode_three_files` with its first file grown to `chunks`
+/// chunks (the chunk records only; nothing on disk matches).
+fn with_huge_file(out: &std::path::Path, chunks: u64) -> manifest::Manifest {
+    let (mut mf, _) = manifest::load(&out.join(manifest::MANIFEST_JSON)).unwrap();
+    let proto = mf.files[0].chunks[0].clone();
+    let fe = &mut mf.files[0];
+    fe.chunks = (0..chunks)
+        .map(|i| manifest::ChunkRef { idx: i, file_offset: i * 4096, ..proto.clone() })
+        .collect();
+    fe.size = chunks * 4096;
+    mf
+}
+
+#[test]
+fn records_past_the_section_limit_are_split_and_read_back() {
+    let td = tempfile::tempdir().unwrap();
+    let out = encode_three_files(td.path());
+    // Well over 64 MiB of JSON for one file record
+    let mf = with_huge_file(&out, 700_000);
+    assert!(serde_json::to_vec(&mf.files[0]).unwrap().len() > 64 << 20);
+    let mut data = manifest_v2::encode(&mf).unwrap();
+    let back = manifest_v2::decode(&data).unwrap();
+    assert_eq!(back.files.len(), 3);
+    assert_eq!(back.files[0].chunks.len(), 700_000);
+    assert_eq!(
+        serde_json::to_value(&back.files[0]).unwrap(),
+        serde_json::to_value(&mf.files[0]).unwrap()
+    );
+
+    // A damaged piece loses that file only
+    let mid = 8 + (64 << 20) + 1000;
+    data[mid] ^= 0xFF;
+    let (part, rep) = manifest_v2::decode_partial(&data).unwrap();
+    assert_eq!(rep.lost_files, vec![0]);
+    assert_eq!(part.files.len(), 2);
+}
@@ -30,3 +30,13 @@ fn merkle_pair_and_triplet() {
     // Just sanity: should be deterministic and not equal to pair root
     assert_ne!(root3, expect_ab);
 }
+
+#[test]
+fn chunk_hash_matches_zero_padded_buffer() {
+    let data = b"short tail chunk";
+    for chunk_size in [data.len(), 4096, 5000] {
+        let mut padded = vec![0u8; chunk_size];
+        padded[..data.len()].copy_from_slice(data);
+        assert_eq!(merkle::chunk_hash(data, chunk_size), blake3::hash(&padded));
+    }
+}
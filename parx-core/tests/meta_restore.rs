@@ -0,0 +1,100 @@
+#![cfg(unix)]
+
+use parx_core::encode::{Encoder, EncoderConfig};
+use parx_core::meta::ChownMap;
+use parx_core::repair::{self, RepairOptions};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+
+mod common;
+
+#[test]
+fn repair_restores_recorded_mode_of_recreated_file() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir(&root).unwrap();
+    fs::write(root.join("a.bin"), vec![1u8; 8 * 1024]).unwrap();
+    fs::write(root.join("b.sh"), vec![2u8; 8 * 1024]).unwrap();
+    fs::set_permissions(root.join("b.sh"), fs::Permissions::from_mode(0o750)).unwrap();
+
+    let out = td.path().join(".parx");
+    let cfg = EncoderConfig { volumes: 1, ..common::cfg(4096) };
+    let mf = Encoder::encode(&root, &out, &cfg).unwrap();
+    let b = mf.files.iter().find(|f| f.rel_path == "b.sh").unwrap();
+    assert_eq!(b.meta.as_ref().unwrap().mode, Some(0o750));
+
+    fs::remove_file(root.join("b.sh")).unwrap();
+    let uid = b.meta.as_ref().unwrap().uid.unwrap();
+    let opts = RepairOptions {
+        chown_map: ChownMap::parse(&format!("{}:{}", uid, uid)).unwrap(),
+        ..Default::default()
+    };
+    let rr =
+        repair::repair_with_options(&out.join("manifest.json"), &root, Default::default(), &opts)
+            .unwrap();
+    assert_eq!(rr.repaired_chunks, 2);
+    assert_eq!(rr.metadata.restored, 1);
+    assert!(rr.metadata.unprivileged.is_empty() && rr.metadata.failed.is_empty());
+    let mode = fs::metadata(root.join("b.sh")).unwrap().permissions().mode() & 0o7777;
+    assert_eq!(mode, 0o750);
+}
+
+#[test]
+fn chown_map_rejects_malformed_entries() {
+    assert_eq!(ChownMap::parse("1000:2000, 5:6").unwrap().map_uid(1000), 2000);
+    assert_eq!(ChownMap::parse("").unwrap().map_uid(7), 7);
+    assert!(ChownMap::parse("1000").is_err());
+    assert!(ChownMap::parse("a:b").is_err());
+}
+
+#[cfg(all(target_os = "linux", feature = "full"))]
+#[test]
+fn restore_metadata_puts_back_mtime_and_xattrs() {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir(&root).unwrap();
+    let file = root.join("a.bin");
+    fs::write(&file, vec![3u8; 8 * 1024]).unwrap();
+    let p = CString::new(file.as_os_str().as_bytes()).unwrap();
+    let times = [
+        libc::timespec { tv_sec: 0, tv_nsec: libc::UTIME_OMIT },
+        libc::timespec { tv_sec: 1_000_000_000, tv_nsec: 250 },
+    ];
+    assert_eq!(unsafe { libc::utimensat(libc::AT_FDCWD, p.as_ptr(), times.as_ptr(), 0) }, 0);
+    // Not every filesystem takes user xattrs; the mtime is checked regardless
+    let name = CString::new("user.parx.test").unwrap();
+    let has_xattr =
+        unsafe { libc::setxattr(p.as_ptr(), name.as_ptr(), b"blue".as_ptr().cast(), 4, 0) == 0 };
+
+    let out = td.path().join(".parx");
+    let cfg = EncoderConfig { volumes: 1, ..common::cfg(4096) };
+    let mf = Encoder::encode(&root, &out, &cfg).unwrap();
+    let meta = mf.files[0].meta.clone().unwrap();
+    assert_eq!((meta.mtime, meta.mtime_nsec), (Some(1_000_000_000), Some(250)));
+    assert_eq!(meta.xattrs.len(), has_xattr as usize);
+
+    let mtime = || fs::metadata(&file).unwrap().modified().unwrap();
+    let recorded = mtime();
+    // Without the flag only mode and ownership come back
+    fs::remove_file(&file).unwrap();
+    repair::repair(&out.join("manifest.json"), &root).unwrap();
+    assert_ne!(mtime(), recorded);
+
+    fs::remove_file(&file).unwrap();
+    let opts = RepairOptions { restore_metadata: true, ..Default::default() };
+    let rr =
+        repair::repair_with_options(&out.join("manifest.json"), &root, Default::default(), &opts)
+            .unwrap();
+    assert_eq!(rr.metadata.restored, 1);
+    assert_eq!(mtime(), recorded);
+    if has_xattr {
+        let mut buf = [0u8; 16];
+        let n = unsafe {
+            libc::getxattr(p.as_ptr(), name.as_ptr(), buf.as_mut_ptr().cast(), buf.len())
+        };
+        assert_eq!(&buf[..n.max(0) as usize], b"blue");
+    }
+}
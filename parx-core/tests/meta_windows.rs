@@ -0,0 +1,44 @@
+#![cfg(all(windows, feature = "windows-meta"))]
+
+use parx_core::encode::{Encoder, EncoderConfig};
+use parx_core::repair;
+use std::fs;
+use std::os::windows::fs::MetadataExt;
+use std::process::Command;
+
+mod common;
+
+const READONLY: u32 = 0x1;
+const HIDDEN: u32 = 0x2;
+
+#[test]
+fn repair_restores_attributes_and_alternate_streams() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir(&root).unwrap();
+    let file = root.join("doc.txt");
+    fs::write(&file, vec![3u8; 8 * 1024]).unwrap();
+    fs::write(root.join("doc.txt:note"), b"kept alongside").unwrap();
+    assert!(Command::new("attrib").arg("+H").arg(&file).status().unwrap().success());
+    let mut perm = fs::metadata(&file).unwrap().permissions();
+    perm.set_readonly(true);
+    fs::set_permissions(&file, perm).unwrap();
+
+    let out = td.path().join(".parx");
+    let cfg = EncoderConfig { volumes: 1, ..common::cfg(4096) };
+    let mf = Encoder::encode(&root, &out, &cfg).unwrap();
+    let meta = mf.files[0].meta.as_ref().unwrap();
+    assert_eq!(meta.streams.len(), 1);
+    assert_eq!(meta.streams[0].name, "note");
+    assert_eq!(meta.win_attributes.unwrap() & (READONLY | HIDDEN), READONLY | HIDDEN);
+
+    assert!(Command::new("attrib").args(["-R", "-H"]).arg(&file).status().unwrap().success());
+    fs::remove_file(&file).unwrap();
+
+    let rr = repair::repair(&out.join("manifest.json"), &root).unwrap();
+    assert_eq!(rr.repaired_chunks, 2);
+    assert_eq!(rr.metadata.restored, 1, "{:?}", rr.metadata);
+    assert_eq!(fs::read(root.join("doc.txt:note")).unwrap(), b"kept alongside");
+    let attrs = fs::metadata(&file).unwrap().file_attributes();
+    assert_eq!(attrs & (READONLY | HIDDEN), READONLY | HIDDEN);
+}
@@ -0,0 +1,75 @@
+use parx_core::encode::Encoder;
+use parx_core::index::{read_index, read_trailer, IndexLimits};
+use parx_core::manifest;
+use parx_core::metaparity::MetaParity;
+use parx_core::repair;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+
+mod common;
+
+fn overwrite(path: &Path, at: u64, bytes: &[u8]) {
+    let mut f = OpenOptions::new().write(true).open(path).unwrap();
+    f.seek(SeekFrom::Start(at)).unwrap();
+    f.write_all(bytes).unwrap();
+}
+
+#[test]
+fn damaged_regions_rebuild_within_the_parity_budget() {
+    let data: Vec<u8> = (0..40_000u32).map(|i| (i * 7 % 253) as u8).collect();
+    let mp = MetaParity::protect(&data).unwrap();
+    assert_eq!(mp.recover(&data).unwrap(), data);
+
+    // 64 shards of 625 bytes and 16 parity shards: two damaged runs and a
+    // lost tail are rebuilt
+    let mut bad = data.clone();
+    bad[100..700].fill(0);
+    bad[20_000..20_010].fill(0xFF);
+    bad.truncate(39_000);
+    assert_eq!(mp.recover(&bad).unwrap(), data);
+
+    // Damage in more shards than there is parity is not
+    let mut worse = data.clone();
+    for s in 0..17 {
+        worse[s * 2000] ^= 1;
+    }
+    assert!(mp.recover(&worse).is_none());
+    assert!(MetaParity::protect(b"").unwrap().recover(b"").is_some());
+}
+
+#[test]
+fn damaged_index_and_manifest_are_rebuilt_from_volume_trailers() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir(&root).unwrap();
+    for i in 0..20u8 {
+        fs::write(root.join(format!("f{:02}.bin", i)), vec![i; 6000]).unwrap();
+    }
+    let out = td.path().join(".parx");
+    let cfg = common::cfg(4096);
+    Encoder::encode(&root, &out, &cfg).unwrap();
+
+    // Index bytes of vol-000 hit: the CRC fails, the parity block rebuilds it
+    let vol = out.join("vol-000.parxv");
+    let mut f = File::open(&vol).unwrap();
+    let (off, len, crc) = read_trailer(&mut f).unwrap();
+    let before = read_index(&mut f, off, len, crc, &IndexLimits::default()).unwrap();
+    overwrite(&vol, off + len / 2, &[0u8; 16]);
+    let mut f = File::open(&vol).unwrap();
+    assert_eq!(read_index(&mut f, off, len, crc, &IndexLimits::default()).unwrap(), before);
+
+    // A scribbled-over run of manifest.json is rebuilt without the v2 fallback
+    let mf = out.join("manifest.json");
+    overwrite(&mf, 300, &[b'#'; 40]);
+    assert!(serde_json::from_slice::<manifest::Manifest>(&fs::read(&mf).unwrap()).is_err());
+    let (loaded, recovery) = manifest::load(&mf).unwrap();
+    assert!(recovery.is_none());
+    assert_eq!(loaded.files.len(), 20);
+
+    // Both still drive a repair
+    overwrite(&root.join("f03.bin"), 10, &[0xAA; 100]);
+    let rr = repair::repair(&mf, &root).unwrap();
+    assert_eq!((rr.repaired_chunks, rr.failed_chunks, rr.unreadable_volumes), (1, 0, 0));
+    assert_eq!(fs::read(root.join("f03.bin")).unwrap(), vec![3u8; 6000]);
+}
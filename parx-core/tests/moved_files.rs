@@ -0,0 +1,71 @@
+use parx_core::encode::Encoder;
+use parx_core::moved::MovedFile;
+use parx_core::repair::{self, RepairOptions};
+use parx_core::verify::{self, VerifyOptions};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::fs;
+
+mod common;
+
+#[test]
+fn renamed_file_is_reported_and_recorded_by_fix_paths() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    let out = td.path().join(".parx");
+    fs::create_dir_all(root.join("sub")).unwrap();
+    let mut rng = StdRng::seed_from_u64(31);
+    let a: Vec<u8> = (0..5_000).map(|_| rng.gen()).collect();
+    let b: Vec<u8> = (0..5_000).map(|_| rng.gen()).collect();
+    fs::write(root.join("a.bin"), &a).unwrap();
+    fs::write(root.join("sub/b.bin"), &b).unwrap();
+    Encoder::encode(&root, &out, &common::cfg(1024)).unwrap();
+    let mpath = out.join("manifest.json");
+    fs::rename(root.join("a.bin"), root.join("sub/renamed.bin")).unwrap();
+
+    // Without the content match a moved file is lost
+    let vr = verify::verify(&mpath, &root).unwrap();
+    assert_eq!((vr.chunks_ok, vr.chunks_bad, vr.merkle_ok), (5, 5, false));
+    assert!(vr.moved_files.is_empty());
+
+    let moved = vec![MovedFile { from: "a.bin".into(), to: "sub/renamed.bin".into() }];
+    let opts = VerifyOptions { detect_moves: true, ..Default::default() };
+    let vr = verify::verify_with_options(&mpath, &root, &opts).unwrap();
+    assert_eq!((vr.chunks_ok, vr.chunks_bad, vr.merkle_ok), (10, 0, true));
+    assert_eq!(vr.moved_files, moved);
+
+    let opts = RepairOptions { fix_paths: true, ..Default::default() };
+    let policy = Default::default();
+    let rr = repair::repair_with_options(&mpath, &root, policy, &opts).unwrap();
+    assert_eq!((rr.repaired_chunks, rr.failed_chunks), (0, 0));
+    assert_eq!(rr.moved_files, moved);
+    assert!(!root.join("a.bin").exists());
+
+    // The manifest and its backups now name the new path
+    let vr = verify::verify(&mpath, &root).unwrap();
+    assert_eq!((vr.chunks_ok, vr.chunks_bad, vr.merkle_ok), (10, 0, true));
+    let vr =
+        verify::verify_from_volume(&out.join("vol-000.parxv"), &root, &Default::default()).unwrap();
+    assert_eq!((vr.chunks_bad, vr.merkle_ok), (0, true));
+}
+
+#[test]
+fn changed_file_under_new_name_is_not_a_move() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    let out = td.path().join(".parx");
+    fs::create_dir_all(&root).unwrap();
+    fs::write(root.join("a.bin"), vec![7u8; 2000]).unwrap();
+    fs::write(root.join("c.bin"), vec![9u8; 2000]).unwrap();
+    Encoder::encode(&root, &out, &common::cfg(1024)).unwrap();
+    let mpath = out.join("manifest.json");
+    fs::remove_file(root.join("a.bin")).unwrap();
+    let mut other = vec![7u8; 2000];
+    other[1500] = 8;
+    fs::write(root.join("b.bin"), other).unwrap();
+
+    let opts = RepairOptions { fix_paths: true, ..Default::default() };
+    let rr = repair::repair_with_options(&mpath, &root, Default::default(), &opts).unwrap();
+    assert!(rr.moved_files.is_empty());
+    assert_eq!(rr.repaired_chunks, 2);
+    assert_eq!(fs::read(root.join("a.bin")).unwrap(), vec![7u8; 2000]);
+}
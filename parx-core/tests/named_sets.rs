@@ -0,0 +1,58 @@
+use parx_core::encode::{Encoder, EncoderConfig};
+use parx_core::sets::{self, SetHealth};
+use parx_core::volume::vol_name;
+use std::fs;
+
+mod common;
+
+#[test]
+fn set_names_are_single_plain_components() {
+    for ok in ["photos", "docs-2024", "a_b.c", "X"] {
+        sets::check_name(ok).unwrap();
+    }
+    for bad in ["", ".", "..", ".hidden", "a/b", "a\\b", "sp ace", &"n".repeat(65)] {
+        assert!(sets::check_name(bad).is_err(), "{:?}", bad);
+    }
+    let td = tempfile::tempdir().unwrap();
+    assert!(sets::dir_of(td.path(), "../up").is_err());
+    let err = sets::manifest_of(td.path(), "photos").unwrap_err();
+    assert!(err.to_string().contains("no set named \"photos\""), "{}", err);
+}
+
+#[test]
+fn list_reports_each_set_with_its_own_layout_and_health() {
+    let td = tempfile::tempdir().unwrap();
+    let parx = td.path().join(".parx");
+    for (name, k, volumes) in [("photos", 4, 2), ("docs", 8, 1)] {
+        let root = td.path().join(name);
+        fs::create_dir(&root).unwrap();
+        fs::write(root.join("a.bin"), vec![k as u8; 40_000]).unwrap();
+        let cfg = EncoderConfig { stripe_k: k, volumes, ..common::cfg(4096) };
+        Encoder::encode(&root, &sets::dir_of(&parx, name).unwrap(), &cfg).unwrap();
+    }
+    // Not sets: a stray file and a dir without a manifest
+    fs::write(parx.join(sets::SETS_DIR).join("notes.txt"), b"x").unwrap();
+    fs::create_dir(parx.join(sets::SETS_DIR).join("empty")).unwrap();
+    fs::remove_file(sets::dir_of(&parx, "photos").unwrap().join(vol_name(1))).unwrap();
+    for f in ["manifest.json", "manifest.v2"] {
+        fs::write(sets::dir_of(&parx, "docs").unwrap().join(f), b"{").unwrap();
+    }
+
+    let rep = sets::list(&parx).unwrap();
+    let names: Vec<&str> = rep.sets.iter().map(|s| s.name.as_str()).collect();
+    assert_eq!(names, ["docs", "photos"]);
+
+    let docs = &rep.sets[0];
+    assert_eq!(docs.health, SetHealth::Unreadable);
+    assert!(docs.error.is_some());
+
+    let photos = &rep.sets[1];
+    assert_eq!((photos.files, photos.data_bytes, photos.volumes), (1, 40_000, 2));
+    assert!(photos.set_id.is_some());
+    assert!(photos.parity_bytes > 0);
+    assert_eq!(photos.health, SetHealth::Degraded);
+    let short = photos.parity_shortfall.as_ref().unwrap();
+    assert_eq!(short.volumes_unreadable, ["vol-001.parxv"]);
+
+    assert!(sets::list(&td.path().join("nowhere")).unwrap().sets.is_empty());
+}
@@ -0,0 +1,153 @@
+use parx_core::encode::{EncodeOptions, Encoder, EncoderConfig};
+use parx_core::index::{read_index, read_trailer, IndexLimits};
+use parx_core::outer::OuterScope;
+use parx_core::path_safety::PathPolicy;
+use parx_core::repair;
+use parx_core::versions;
+use parx_core::volume::ShardKind;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+
+mod common;
+
+fn cfg(parity_pct: u32, outer_group: usize, outer_parity: usize) -> EncoderConfig {
+    EncoderConfig { parity_pct, outer_group, outer_parity, ..common::cfg(1024) }
+}
+
+/// 16 chunks = 4 stripes = one outer group of 4.
+fn setup(td: &Path, cfg: &EncoderConfig, scope: OuterScope) -> Vec<u8> {
+    let root = td.join("data");
+    fs::create_dir_all(&root).unwrap();
+    let mut rng = StdRng::seed_from_u64(5);
+    let data: Vec<u8> = (0..16 * 1024).map(|_| rng.gen()).collect();
+    fs::write(root.join("f.bin"), &data).unwrap();
+    let opts = EncodeOptions { outer_scope: scope, ..Default::default() };
+    Encoder::encode_with(&root, &td.join(".parx"), cfg, &opts).unwrap();
+    data
+}
+
+fn zero(path: &Path, off: u64, len: usize) {
+    let mut f = OpenOptions::new().write(true).open(path).unwrap();
+    f.seek(SeekFrom::Start(off)).unwrap();
+    f.write_all(&vec![0u8; len]).unwrap();
+}
+
+#[test]
+fn full_scope_outer_parity_recovers_a_whole_lost_stripe() {
+    let td = tempfile::tempdir().unwrap();
+    let data = setup(td.path(), &cfg(25, 4, 4), OuterScope::Full);
+    let file = td.path().join("data/f.bin");
+    // Stripe 1 loses all four chunks; inner parity (m=1) cannot help
+    zero(&file, 4096, 4096);
+    let rr =
+        repair::repair(&td.path().join(".parx/manifest.json"), &td.path().join("data")).unwrap();
+    assert_eq!(rr.failed_chunks, 0);
+    assert_eq!(rr.outer_repaired_chunks, 4);
+    assert_eq!(fs::read(&file).unwrap(), data);
+}
+
+#[test]
+fn parity_scope_outer_parity_restores_lost_inner_parity() {
+    let td = tempfile::tempdir().unwrap();
+    let data = setup(td.path(), &cfg(50, 4, 2), OuterScope::Parity);
+    let file = td.path().join("data/f.bin");
+    // Stripe 2 loses one chunk and both of its inner parity shards
+    zero(&file, 2 * 4096 + 1024, 10);
+    let mut outer = 0;
+    for v in ["vol-000.parxv", "vol-001.parxv"] {
+        let p = td.path().join(".parx").join(v);
+        let mut f = File::open(&p).unwrap();
+        let (off, len, crc) = read_trailer(&mut f).unwrap();
+        for e in read_index(&mut f, off, len, crc, &IndexLimits::default()).unwrap() {
+            if e.kind == ShardKind::Inner && e.stripe == 2 {
+                zero(&p, e.offset, 16);
+            }
+            outer += (e.kind == ShardKind::Outer) as usize;
+        }
+    }
+    assert_eq!(outer, 2);
+    let rr =
+        repair::repair(&td.path().join(".parx/manifest.json"), &td.path().join("data")).unwrap();
+    assert_eq!(rr.failed_chunks, 0);
+    assert_eq!(rr.outer_repaired_chunks, 1);
+    assert_eq!(fs::read(&file).unwrap(), data);
+}
+
+#[test]
+fn outer_groups_cover_the_extra_parity_of_critical_stripes() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir_all(&root).unwrap();
+    let mut rng = StdRng::seed_from_u64(6);
+    // a.db fills stripe 0, b.bin stripes 1 to 3
+    let a: Vec<u8> = (0..4 * 1024).map(|_| rng.gen()).collect();
+    let b: Vec<u8> = (0..12 * 1024).map(|_| rng.gen()).collect();
+    fs::write(root.join("a.db"), &a).unwrap();
+    fs::write(root.join("b.bin"), &b).unwrap();
+    let opts = EncodeOptions {
+        critical: vec!["a.db".into()],
+        critical_parity: 1,
+        outer_scope: OuterScope::Parity,
+        ..Default::default()
+    };
+    let out = td.path().join(".parx");
+    Encoder::encode_with(&root, &out, &cfg(25, 4, 2), &opts).unwrap();
+
+    // Stripe 0 loses two chunks and both inner parity shards, its plain
+    // one and its critical extra: only with both back from the outer group
+    // can it decode
+    zero(&root.join("a.db"), 0, 10);
+    zero(&root.join("a.db"), 2048, 10);
+    let mut inner = 0;
+    for v in ["vol-000.parxv", "vol-001.parxv"] {
+        let p = out.join(v);
+        let mut f = File::open(&p).unwrap();
+        let (off, len, crc) = read_trailer(&mut f).unwrap();
+        for e in read_index(&mut f, off, len, crc, &IndexLimits::default()).unwrap() {
+            if e.kind == ShardKind::Inner && e.stripe == 0 {
+                zero(&p, e.offset, 16);
+                inner += 1;
+            }
+        }
+    }
+    assert_eq!(inner, 2);
+    let rr = repair::repair(&out.join("manifest.json"), &root).unwrap();
+    assert_eq!((rr.failed_chunks, rr.outer_repaired_chunks), (0, 2));
+    assert_eq!(fs::read(root.join("a.db")).unwrap(), a);
+}
+
+#[test]
+fn restoring_a_version_uses_its_outer_parity() {
+    let td = tempfile::tempdir().unwrap();
+    let (root, out) = (td.path().join("data"), td.path().join(".parx"));
+    let cfg = cfg(25, 4, 4);
+    let v1 = setup(td.path(), &cfg, OuterScope::Full);
+    // The tree moves on to a new version; stripe 1 of v1 has no donor left
+    let aside = versions::set_aside(&out).unwrap().unwrap();
+    let mut v2 = v1.clone();
+    v2[4096..8192].fill(0x5A);
+    fs::write(root.join("f.bin"), &v2).unwrap();
+    let opts = EncodeOptions { outer_scope: OuterScope::Full, ..Default::default() };
+    Encoder::encode_with(&root, &out, &cfg, &opts).unwrap();
+    let parent = aside.archive().unwrap();
+    versions::record_current(&out, Some(parent), 2).unwrap();
+
+    let rr = versions::restore_as_of(&out, parent, &root, PathPolicy::default()).unwrap();
+    assert_eq!((rr.chunks_failed, rr.outer_reconstructed), (0, 4));
+    assert_eq!(fs::read(root.join("f.bin")).unwrap(), v1);
+}
+
+#[test]
+fn outer_groups_beyond_the_field_size_are_rejected() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir_all(&root).unwrap();
+    fs::write(root.join("f.bin"), [1u8; 100]).unwrap();
+    let opts = EncodeOptions { outer_scope: OuterScope::Full, ..Default::default() };
+    let err = Encoder::encode_with(&root, &td.path().join(".parx"), &cfg(25, 64, 4), &opts)
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("exceeds 256"), "{}", err);
+}
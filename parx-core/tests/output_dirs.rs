@@ -0,0 +1,90 @@
+use parx_core::audit::audit;
+use parx_core::encode::{EncodeOptions, Encoder, EncoderConfig};
+use parx_core::path_safety::PathPolicy;
+use parx_core::verify::parity_shortfall;
+use parx_core::volume::vol_name;
+use parx_core::{repair, update};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::fs;
+use std::path::Path;
+
+mod common;
+
+fn cfg() -> EncoderConfig {
+    EncoderConfig { parity_pct: 75, volumes: 3, ..common::cfg(4096) }
+}
+
+fn tree(root: &Path, seed: u64, len: usize) -> Vec<u8> {
+    fs::create_dir_all(root).unwrap();
+    let mut rng = StdRng::seed_from_u64(seed);
+    let data: Vec<u8> = (0..len).map(|_| rng.gen()).collect();
+    fs::write(root.join("a.bin"), &data).unwrap();
+    data
+}
+
+#[test]
+fn volumes_go_round_robin_and_a_lost_drive_is_survived() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    let data = tree(&root, 1, 100_000);
+    let drives: Vec<_> = ["a", "b", "c"].iter().map(|d| td.path().join(d).join(".parx")).collect();
+    let opts = EncodeOptions { volume_dirs: drives[1..].to_vec(), ..Default::default() };
+    let mf = Encoder::encode_with(&root, &drives[0], &cfg(), &opts).unwrap();
+
+    assert_eq!(mf.volume_dirs(), drives[1..].to_vec());
+    for (vid, drive) in drives.iter().enumerate() {
+        assert_eq!(mf.volume_path(&drives[0], vid), drive.join(vol_name(vid)));
+        assert!(drive.join(vol_name(vid)).is_file());
+        let vols = fs::read_dir(drive)
+            .unwrap()
+            .filter(|e| e.as_ref().unwrap().path().extension().is_some_and(|x| x == "parxv"));
+        assert_eq!(vols.count(), 1);
+    }
+    assert!(drives[0].join("manifest.json").is_file());
+    assert!(!drives[1].join("manifest.json").exists());
+    assert!(parity_shortfall(&mf, &drives[0]).is_none());
+
+    // Drive b fails; two chunks of every stripe are damaged, so the parity
+    // on both other drives is needed
+    fs::remove_dir_all(td.path().join("b")).unwrap();
+    let short = parity_shortfall(&mf, &drives[0]).unwrap();
+    assert_eq!(short.volumes_unreadable, vec![vol_name(1)]);
+    assert_eq!(short.min_parity_left, 2);
+    let mut bad = data.clone();
+    for s in 0..mf.geometry().stripes as usize {
+        for at in [(s * 4 + 1) * 4096, (s * 4 + 2) * 4096] {
+            if at < bad.len() {
+                bad[at] ^= 0xFF;
+            }
+        }
+    }
+    fs::write(root.join("a.bin"), &bad).unwrap();
+    let mpath = drives[0].join("manifest.json");
+    let ar = audit(&mpath, &root, PathPolicy::default()).unwrap();
+    assert!(ar.repairable && ar.chunks_bad > 0, "{:?}", ar);
+    let rr = repair::repair(&mpath, &root).unwrap();
+    assert_eq!(rr.failed_chunks, 0);
+    assert_eq!(fs::read(root.join("a.bin")).unwrap(), data);
+
+    let err = update::update(&drives[0], &root, None).unwrap_err();
+    assert!(err.to_string().contains("--output-dirs"), "{}", err);
+}
+
+#[test]
+fn volume_parity_and_the_parity_dir_itself_are_refused() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    tree(&root, 2, 20_000);
+    let out = td.path().join("a");
+    let opts = EncodeOptions {
+        volume_dirs: vec![td.path().join("b")],
+        volume_parity: 1,
+        ..Default::default()
+    };
+    let err = Encoder::encode_with(&root, &out, &cfg(), &opts).unwrap_err();
+    assert!(err.to_string().contains("--output-dirs"), "{}", err);
+
+    let opts = EncodeOptions { volume_dirs: vec![out.clone()], ..Default::default() };
+    let err = Encoder::encode_with(&root, &out, &cfg(), &opts).unwrap_err();
+    assert!(err.to_string().contains("is the parity dir itself"), "{}", err);
+}
@@ -0,0 +1,86 @@
+use parx_core::encode::Encoder;
+use parx_core::pack::{self, PackOptions, UnpackOptions, ENVELOPE};
+use parx_core::tarstream::TarReader;
+use std::fs;
+use std::io::Read;
+
+mod common;
+
+fn set(td: &std::path::Path) -> std::path::PathBuf {
+    let root = td.join("data");
+    let out = td.join(".parx");
+    fs::create_dir_all(&root).unwrap();
+    fs::write(root.join("a.bin"), vec![3u8; 9000]).unwrap();
+    let cfg = common::cfg(1024);
+    Encoder::encode(&root, &out, &cfg).unwrap();
+    out
+}
+
+#[test]
+fn bundle_is_a_tar_led_by_its_envelope() {
+    let td = tempfile::tempdir().unwrap();
+    let out = set(td.path());
+    fs::write(out.join(".parx.repair.lock"), b"").unwrap();
+    fs::create_dir_all(out.join("versions/v1")).unwrap();
+    let long = format!("versions/v1/{}.json", "n".repeat(120));
+    fs::write(out.join(&long), b"{}").unwrap();
+
+    let mut bundle = Vec::new();
+    let rep = pack::pack(&out, &mut bundle, &PackOptions::default()).unwrap();
+    assert!(!rep.volumes);
+    let mut tar = TarReader::new(&bundle[..]);
+    let mut names = Vec::new();
+    while let Some(e) = tar.next_entry().unwrap() {
+        let mut buf = Vec::new();
+        tar.data().read_to_end(&mut buf).unwrap();
+        assert_eq!(buf.len() as u64, e.size);
+        names.push(e.path);
+    }
+    assert_eq!(names[0], ENVELOPE);
+    assert!(names.contains(&"manifest.json".to_string()));
+    assert!(names.contains(&long));
+    assert!(names.iter().all(|n| !n.ends_with(".parxv") && !n.ends_with(".lock")), "{:?}", names);
+    assert_eq!(rep.files as usize, names.len() - 1);
+
+    let dest = td.path().join("copy");
+    let env = pack::unpack(&bundle[..], &dest, &UnpackOptions::default()).unwrap();
+    assert_eq!(env.members.len() as u64, rep.files);
+    assert_eq!(fs::read(dest.join(&long)).unwrap(), b"{}");
+    let (mf, _) = parx_core::manifest::load(&dest.join("manifest.json")).unwrap();
+    assert_eq!(mf.parity_dir, dest.to_string_lossy());
+}
+
+#[test]
+fn damaged_or_incomplete_bundles_are_refused() {
+    let td = tempfile::tempdir().unwrap();
+    let out = set(td.path());
+    let mut bundle = Vec::new();
+    pack::pack(&out, &mut bundle, &PackOptions { volumes: true }).unwrap();
+    let check = UnpackOptions { check_only: true, ..Default::default() };
+    pack::unpack(&bundle[..], td.path(), &check).unwrap();
+
+    // Truncated: a member is missing
+    let cut = &bundle[..bundle.len() / 2];
+    assert!(pack::unpack(cut, td.path(), &check).is_err());
+
+    // A flipped byte in a volume fails its hash; nothing is moved into place
+    let mut bad = bundle.clone();
+    let n = bad.len();
+    bad[n - 4096] ^= 1;
+    let dest = td.path().join("dest");
+    let err = pack::unpack(&bad[..], &dest, &UnpackOptions::default()).unwrap_err();
+    assert!(err.to_string().contains("damaged"), "{err}");
+    let leftovers: Vec<_> = fs::read_dir(&dest)
+        .unwrap()
+        .flatten()
+        .filter(|e| e.file_name().to_string_lossy().contains("parx-unpack"))
+        .collect();
+    assert!(leftovers.is_empty());
+
+    // Not a bundle at all
+    let mut plain = Vec::new();
+    let mut w = parx_core::tarstream::TarWriter::new(&mut plain);
+    w.append("x", 1, 0o644, 0, &b"x"[..]).unwrap();
+    w.finish().unwrap();
+    assert!(pack::unpack(&plain[..], td.path(), &check).is_err());
+}
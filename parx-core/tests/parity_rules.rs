@@ -0,0 +1,100 @@
+use parx_core::encode::{EncodeOptions, Encoder, EncoderConfig, ParityRule};
+use parx_core::index::{read_index, read_trailer, IndexLimits};
+use parx_core::{repair, verify};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+
+mod common;
+
+fn cfg() -> EncoderConfig {
+    EncoderConfig { parity_pct: 25, volumes: 3, ..common::cfg(1024) }
+}
+
+fn zero(path: &Path, off: u64, len: usize) {
+    let mut f = OpenOptions::new().write(true).open(path).unwrap();
+    f.seek(SeekFrom::Start(off)).unwrap();
+    f.write_all(&vec![0u8; len]).unwrap();
+}
+
+#[test]
+fn rule_groups_get_their_own_stripes_and_parity() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir_all(root.join("photos/2024")).unwrap();
+    let mut rng = StdRng::seed_from_u64(41);
+    let mut random = |n: usize| -> Vec<u8> { (0..n).map(|_| rng.gen()).collect() };
+    // Three chunks each, so every group needs padding to fill its stripe
+    let bulk = random(3000);
+    let photo = random(3000);
+    let iso = random(3000);
+    fs::write(root.join("bulk.bin"), &bulk).unwrap();
+    fs::write(root.join("photos/2024/a.jpg"), &photo).unwrap();
+    fs::write(root.join("disk.iso"), &iso).unwrap();
+    let opts = EncodeOptions {
+        parity_rules: vec!["photos/**=75".parse().unwrap(), "*.iso=50".parse().unwrap()],
+        ..Default::default()
+    };
+    let mf = Encoder::encode_with(&root, &td.path().join(".parx"), &cfg(), &opts).unwrap();
+
+    // Unmatched files first, then each rule's files on stripes of their own
+    let stripe_of = |name: &str| {
+        let fe = mf.files.iter().find(|f| f.rel_path == name).unwrap();
+        fe.chunks[0].idx / 4
+    };
+    assert_eq!(stripe_of("bulk.bin"), 0);
+    assert_eq!(stripe_of("photos/2024/a.jpg"), 1);
+    assert_eq!(stripe_of("disk.iso"), 2);
+    let groups: Vec<(u64, u64, usize)> =
+        mf.parity_groups.iter().map(|g| (g.first_stripe, g.stripes, g.parity_shards)).collect();
+    assert_eq!(groups, [(1, 1, 3), (2, 1, 2)]);
+    assert_eq!(mf.max_parity_shards(), 3);
+
+    let mut per_stripe: HashMap<u64, usize> = HashMap::new();
+    for v in 0..3 {
+        let mut f = File::open(td.path().join(format!(".parx/vol-{:03}.parxv", v))).unwrap();
+        let (off, len, crc) = read_trailer(&mut f).unwrap();
+        let index = read_index(&mut f, off, len, crc, &IndexLimits::default()).unwrap();
+        for e in index.iter().filter(|e| e.is_inner()) {
+            *per_stripe.entry(e.stripe).or_default() += 1;
+        }
+    }
+    assert_eq!((per_stripe[&0], per_stripe[&1], per_stripe[&2]), (1, 3, 2));
+
+    let mpath = td.path().join(".parx/manifest.json");
+    let vr = verify::verify(&mpath, &root).unwrap();
+    assert_eq!((vr.chunks_bad, vr.merkle_ok), (0, true));
+
+    // Every photo chunk and two of the iso are repairable; two bulk ones are not
+    zero(&root.join("photos/2024/a.jpg"), 0, 3000);
+    zero(&root.join("disk.iso"), 0, 2048);
+    zero(&root.join("bulk.bin"), 0, 2048);
+    let rr = repair::repair(&mpath, &root).unwrap();
+    assert_eq!((rr.repaired_chunks, rr.failed_chunks), (5, 2));
+    assert_eq!(fs::read(root.join("photos/2024/a.jpg")).unwrap(), photo);
+    assert_eq!(fs::read(root.join("disk.iso")).unwrap(), iso);
+}
+
+#[test]
+fn parity_rules_are_parsed_and_checked() {
+    let rule: ParityRule = "a=b/*.x=40%".parse().unwrap();
+    assert_eq!((rule.pattern.as_str(), rule.parity_pct), ("a=b/*.x", 40));
+    for bad in ["*.iso", "*.iso=", "=20", "*.iso=0", "*.iso=many"] {
+        assert!(bad.parse::<ParityRule>().is_err(), "{bad}");
+    }
+
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir_all(&root).unwrap();
+    fs::write(root.join("a.db"), b"x").unwrap();
+    let opts = EncodeOptions {
+        parity_rules: vec!["*.db=50".parse().unwrap()],
+        critical: vec!["*.db".into()],
+        critical_parity: 1,
+        ..Default::default()
+    };
+    let err = Encoder::encode_with(&root, &td.path().join(".parx"), &cfg(), &opts).unwrap_err();
+    assert!(err.to_string().contains("--parity-rule cannot be combined"), "{err}");
+}
@@ -0,0 +1,47 @@
+use parx_core::encode::{EncodeOptions, Encoder, EncoderConfig};
+use parx_core::manifest::Manifest;
+use parx_core::{repair, verify};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::fs;
+
+mod common;
+
+#[test]
+fn parity_shards_are_exact_and_recorded() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir_all(&root).unwrap();
+    let mut rng = StdRng::seed_from_u64(11);
+    let data: Vec<u8> = (0..10 * 1024).map(|_| rng.gen()).collect();
+    fs::write(root.join("a.bin"), &data).unwrap();
+    let cfg = EncoderConfig { stripe_k: 10, parity_pct: 35, ..common::cfg(1024) };
+    let opts = EncodeOptions { parity_shards: Some(4), ..Default::default() };
+    let out = td.path().join(".parx");
+    let mf = Encoder::encode_with(&root, &out, &cfg, &opts).unwrap();
+    assert_eq!((mf.parity_shards, mf.parity_pct), (Some(4), 40));
+
+    // Four lost chunks of the one stripe are exactly what k=10,m=4 holds
+    let mut bad = data.clone();
+    bad[..4096].fill(0);
+    fs::write(root.join("a.bin"), &bad).unwrap();
+    let mpath = out.join("manifest.json");
+    let rr = repair::repair(&mpath, &root).unwrap();
+    assert_eq!((rr.repaired_chunks, rr.failed_chunks), (4, 0));
+    let vr = verify::verify(&mpath, &root).unwrap();
+    assert_eq!((vr.chunks_bad, vr.merkle_ok), (0, true));
+}
+
+#[test]
+fn manifests_without_parity_shards_derive_them() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir_all(&root).unwrap();
+    fs::write(root.join("a.bin"), vec![5u8; 4096]).unwrap();
+    let cfg = EncoderConfig { stripe_k: 8, parity_pct: 30, volumes: 1, ..common::cfg(1024) };
+    let mf = Encoder::encode(&root, &td.path().join(".parx"), &cfg).unwrap();
+    assert_eq!(mf.parity_shards, Some(3));
+    let mut json = serde_json::to_value(&mf).unwrap();
+    json.as_object_mut().unwrap().remove("parity_shards");
+    let old: Manifest = serde_json::from_value(json).unwrap();
+    assert_eq!((old.parity_shards, old.parity_shards()), (None, 3));
+}
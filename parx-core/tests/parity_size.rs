@@ -0,0 +1,61 @@
+use parx_core::encode::{EncodeOptions, Encoder, EncoderConfig};
+use parx_core::{repair, verify};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::fs;
+
+mod common;
+
+fn cfg(stripe_k: usize, shard_copies: usize) -> EncoderConfig {
+    EncoderConfig { stripe_k, parity_pct: 10, shard_copies, ..common::cfg(1024) }
+}
+
+fn budget(size: u64) -> EncodeOptions {
+    EncodeOptions { parity_size: Some(size), ..Default::default() }
+}
+
+#[test]
+fn parity_size_sets_the_shards_per_stripe() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir_all(&root).unwrap();
+    let mut rng = StdRng::seed_from_u64(7);
+    let data: Vec<u8> = (0..8 * 1024).map(|_| rng.gen()).collect();
+    fs::write(root.join("a.bin"), &data).unwrap();
+
+    // 2 stripes of 4 chunks: 5000 bytes hold 2 shards per stripe, not 3
+    let out = td.path().join(".parx");
+    let mf = Encoder::encode_with(&root, &out, &cfg(4, 1), &budget(5000)).unwrap();
+    assert_eq!((mf.parity_pct, mf.max_parity_shards()), (50, 2));
+    let parity: u64 =
+        (0..2).map(|v| fs::metadata(out.join(format!("vol-{:03}.parxv", v))).unwrap().len()).sum();
+    assert!(parity >= 4 * 1024, "{parity}");
+
+    // Replicated shards count against the budget; 3 data shards record 33%
+    let out2 = td.path().join(".parx2");
+    let mf = Encoder::encode_with(&root, &out2, &cfg(3, 2), &budget(7000)).unwrap();
+    assert_eq!((mf.parity_pct, mf.max_parity_shards()), (33, 1));
+
+    let mut bad = data.clone();
+    bad[..2048].fill(0);
+    fs::write(root.join("a.bin"), &bad).unwrap();
+    let rr = repair::repair(&out.join("manifest.json"), &root).unwrap();
+    assert_eq!((rr.repaired_chunks, rr.failed_chunks), (2, 0));
+    let vr = verify::verify(&out.join("manifest.json"), &root).unwrap();
+    assert_eq!((vr.chunks_bad, vr.merkle_ok), (0, true));
+}
+
+#[test]
+fn parity_size_too_small_or_combined_is_refused() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir_all(&root).unwrap();
+    fs::write(root.join("a.bin"), vec![1u8; 8 * 1024]).unwrap();
+    let out = td.path().join(".parx");
+    let err = Encoder::encode_with(&root, &out, &cfg(4, 1), &budget(2047)).unwrap_err();
+    assert!(err.to_string().contains("--parity-size 2047 is too small"), "{err}");
+
+    let opts =
+        EncodeOptions { critical: vec!["a.bin".into()], critical_parity: 1, ..budget(1 << 20) };
+    let err = Encoder::encode_with(&root, &out, &cfg(4, 1), &opts).unwrap_err();
+    assert!(err.to_string().contains("--parity-size cannot be combined"), "{err}");
+}
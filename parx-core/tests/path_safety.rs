@@ -34,6 +34,8 @@ fn verify_rejects_symlink_by_default_allows_with_flag_when_contained() {
         outer_group: 0,
         outer_parity: 0,
         interleave_files: false,
+        shard_copies: 1,
+        gpu: parx_core::encode::GpuMode::Off,
     };
     let mut manifest = parx_core::encode::Encoder::encode(&root, &out, &cfg).unwrap();
 
@@ -83,6 +85,8 @@ fn verify_blocks_symlink_escape_even_when_following() {
         outer_group: 0,
         outer_parity: 0,
         interleave_files: false,
+        shard_copies: 1,
+        gpu: parx_core::encode::GpuMode::Off,
     };
     let mut manifest = parx_core::encode::Encoder::encode(&root, &out, &cfg).unwrap();
 
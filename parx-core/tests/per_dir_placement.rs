@@ -0,0 +1,75 @@
+use parx_core::encode::{EncodeOptions, Encoder, PER_DIR_SET};
+use parx_core::{manifest, repair, verify};
+use std::fs;
+use std::path::Path;
+
+mod common;
+
+fn copy_dir(from: &Path, to: &Path) {
+    fs::create_dir_all(to).unwrap();
+    for ent in fs::read_dir(from).unwrap().flatten() {
+        if ent.file_type().unwrap().is_dir() {
+            copy_dir(&ent.path(), &to.join(ent.file_name()));
+        } else {
+            fs::copy(ent.path(), to.join(ent.file_name())).unwrap();
+        }
+    }
+}
+
+#[test]
+fn every_top_level_dir_gets_its_own_repairable_set() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("tree");
+    fs::create_dir_all(root.join("photos/2004")).unwrap();
+    fs::create_dir_all(root.join("docs")).unwrap();
+    fs::create_dir_all(root.join("empty")).unwrap();
+    let photo: Vec<u8> = (0..7000u32).map(|i| (i * 13 % 251) as u8).collect();
+    fs::write(root.join("photos/2004/a.jpg"), &photo).unwrap();
+    fs::write(root.join("docs/notes.txt"), vec![b'n'; 3000]).unwrap();
+    fs::write(root.join("docs/skip.bin"), vec![0u8; 100]).unwrap();
+    fs::write(root.join("readme.txt"), b"top level").unwrap();
+
+    let opts = EncodeOptions { exclude: vec!["docs/skip.bin".into()], ..Default::default() };
+    let sets = Encoder::encode_per_dir(&root, &common::cfg(1024), &opts).unwrap();
+    let outputs: Vec<_> = sets.iter().map(|s| s.output.clone()).collect();
+    assert_eq!(
+        outputs,
+        vec![
+            root.join("docs").join(PER_DIR_SET),
+            root.join("photos").join(PER_DIR_SET),
+            root.join(PER_DIR_SET)
+        ]
+    );
+    let paths = |dir: &Path| -> Vec<String> {
+        let (mf, _) = manifest::load(&dir.join(PER_DIR_SET).join("manifest.json")).unwrap();
+        mf.files.iter().map(|f| f.rel_path.clone()).collect()
+    };
+    assert_eq!(paths(&root.join("docs")), vec!["notes.txt"]);
+    assert_eq!(paths(&root.join("photos")), vec!["2004/a.jpg"]);
+    assert_eq!(paths(&root), vec!["readme.txt"]);
+
+    // A copy of one directory with its set repairs without the rest
+    let copy = td.path().join("elsewhere/photos");
+    copy_dir(&root.join("photos"), &copy);
+    fs::remove_dir_all(&root).unwrap();
+    let mut damaged = photo.clone();
+    damaged[1100..1200].fill(0);
+    fs::write(copy.join("2004/a.jpg"), &damaged).unwrap();
+    // The recorded parity dir is gone; the set is found next to its manifest
+    let mf = copy.join(PER_DIR_SET).join("manifest.json");
+    assert_eq!(verify::verify(&mf, &copy).unwrap().chunks_bad, 1);
+    let rr = repair::repair(&mf, &copy).unwrap();
+    assert_eq!((rr.repaired_chunks, rr.failed_chunks), (1, 0));
+    assert_eq!(fs::read(copy.join("2004/a.jpg")).unwrap(), photo);
+}
+
+#[test]
+fn per_dir_placement_refuses_file_lists() {
+    let td = tempfile::tempdir().unwrap();
+    fs::create_dir_all(td.path().join("d")).unwrap();
+    let opts = EncodeOptions { files: Some(Vec::new()), ..Default::default() };
+    assert!(Encoder::encode_per_dir(td.path(), &common::cfg(1024), &opts).is_err());
+    assert!(
+        Encoder::encode_per_dir(td.path(), &common::cfg(1024), &EncodeOptions::default()).is_err()
+    );
+}
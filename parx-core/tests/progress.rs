@@ -0,0 +1,124 @@
+use parx_core::encode::{EncodeOptions, Encoder, EncoderConfig};
+use parx_core::progress::{Progress, ProgressSink, Snapshot};
+use std::fs;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+mod common;
+
+#[derive(Default)]
+struct Collect(Mutex<Vec<Snapshot>>);
+
+impl ProgressSink for Collect {
+    fn report(&self, snap: &Snapshot) {
+        self.0.lock().unwrap().push(snap.clone());
+    }
+}
+
+#[test]
+fn children_roll_up_into_their_parents() {
+    let p = Progress::new();
+    let hash = p.stage("hash");
+    let write = p.stage("write");
+    let v0 = write.child("vol-000");
+    let v1 = p.stage("write/vol-001");
+    // A parent that is only implied by its children
+    let deep = p.stage("verify/files/a.bin");
+    hash.add_total(100, 2);
+    hash.add_bytes(60);
+    hash.add_items(1);
+    v0.add_total(10, 0);
+    v1.add_total(30, 0);
+    v0.add_bytes(10);
+    v1.add_bytes(5);
+    deep.add_bytes(7);
+    v0.finish();
+
+    let snap = p.snapshot();
+    let paths: Vec<&str> = snap.stages.iter().map(|s| s.path.as_str()).collect();
+    assert_eq!(
+        paths,
+        [
+            "hash",
+            "write",
+            "write/vol-000",
+            "write/vol-001",
+            "verify",
+            "verify/files",
+            "verify/files/a.bin"
+        ]
+    );
+    let get = |p: &str| snap.stages.iter().find(|s| s.path == p).unwrap().clone();
+    assert_eq!((get("hash").bytes_done, get("hash").items_done), (60, 1));
+    let w = get("write");
+    assert_eq!((w.depth, w.bytes_done, w.bytes_total, w.done), (0, 15, 40, false));
+    assert!(get("write/vol-000").done);
+    assert_eq!((get("verify").bytes_done, get("verify/files").depth), (7, 1));
+
+    v1.finish();
+    write.finish();
+    let w = p.snapshot().stages.into_iter().find(|s| s.path == "write").unwrap();
+    assert!(w.done);
+    // A finished stage keeps its rate instead of decaying
+    std::thread::sleep(Duration::from_millis(20));
+    let again = p.snapshot().stages.into_iter().find(|s| s.path == "write").unwrap();
+    assert_eq!((again.elapsed_ms, again.bytes_per_sec), (w.elapsed_ms, w.bytes_per_sec));
+}
+
+#[test]
+fn counters_add_up_across_threads() {
+    let p = Progress::new();
+    let stage = p.stage("encode");
+    std::thread::scope(|s| {
+        for _ in 0..8 {
+            let stage = stage.clone();
+            s.spawn(move || {
+                for _ in 0..10_000 {
+                    stage.add_bytes(3);
+                    stage.add_items(1);
+                }
+            });
+        }
+    });
+    let snap = &p.snapshot().stages[0];
+    assert_eq!((snap.bytes_done, snap.items_done), (240_000, 80_000));
+}
+
+#[test]
+fn encode_reports_each_stage_and_volume_to_the_sink() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir(&root).unwrap();
+    fs::write(root.join("a.bin"), vec![1u8; 9 * 4096]).unwrap();
+    fs::write(root.join("b.bin"), vec![2u8; 1000]).unwrap();
+    let cfg = EncoderConfig { volumes: 3, ..common::cfg(4096) };
+    let progress = Progress::new();
+    let sink = Arc::new(Collect::default());
+    let reporter = progress.start(sink.clone(), Duration::from_secs(3600));
+    let opts = EncodeOptions { progress: Some(progress.clone()), ..Default::default() };
+    let mf = Encoder::encode_with(&root, &td.path().join(".parx"), &cfg, &opts).unwrap();
+    drop(reporter);
+
+    // The interval never passed: only the last snapshot, taken on drop
+    let snaps = sink.0.lock().unwrap();
+    assert_eq!(snaps.len(), 1);
+    let last = &snaps[0];
+    assert!(last.last);
+    let get = |p: &str| last.stages.iter().find(|s| s.path == p).unwrap();
+    let hash = get("hash");
+    assert_eq!(
+        (hash.bytes_done, hash.bytes_total, hash.items_done),
+        (9 * 4096 + 1000, 9 * 4096 + 1000, 2)
+    );
+    let stripes = mf.geometry().stripes;
+    let encode = get("encode");
+    assert_eq!((encode.items_done, encode.items_total), (stripes, stripes));
+    let write = get("write");
+    assert_eq!(write.bytes_done, stripes * 2 * 4096);
+    assert_eq!(write.bytes_done, write.bytes_total);
+    for vid in 0..3 {
+        let v = get(&format!("write/vol-{:03}.parxv", vid));
+        assert_eq!((v.depth, v.bytes_done, v.done), (1, v.bytes_total, true));
+    }
+    assert!(last.stages.iter().all(|s| s.done));
+}
@@ -0,0 +1,107 @@
+use parx_core::encode::Encoder;
+use parx_core::manifest::{self, MANIFEST_JSON};
+use parx_core::rebalance::{rebalance, RebalanceOptions};
+use parx_core::verify::parity_shortfall;
+use parx_core::{repair, update, verify};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::fs;
+use std::path::Path;
+
+mod common;
+
+/// Ten files of three chunks each, then every other one of the first six
+/// removed by an update: nine holes in eight stripes.
+fn worn_set(root: &Path, out: &Path, seed: u64) -> Vec<(String, Vec<u8>)> {
+    fs::create_dir_all(root).unwrap();
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut files = Vec::new();
+    for i in 0..10 {
+        let data: Vec<u8> = (0..12_000).map(|_| rng.gen()).collect();
+        let name = format!("f{}.bin", i);
+        fs::write(root.join(&name), &data).unwrap();
+        files.push((name, data));
+    }
+    Encoder::encode(root, out, &common::cfg(4096)).unwrap();
+    for i in [1, 3, 5] {
+        fs::remove_file(root.join(&files[i].0)).unwrap();
+    }
+    update::update(out, root, None).unwrap();
+    files.into_iter().enumerate().filter(|(i, _)| ![1, 3, 5].contains(i)).map(|(_, f)| f).collect()
+}
+
+fn volume_bytes(out: &Path) -> u64 {
+    fs::read_dir(out)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.extension().is_some_and(|x| x == "parxv"))
+        .map(|p| fs::metadata(p).unwrap().len())
+        .sum()
+}
+
+/// Flip a byte in two chunks of every kept file and check repair restores them.
+fn damage_and_repair(root: &Path, out: &Path, files: &[(String, Vec<u8>)]) {
+    for (name, data) in files.iter().step_by(2) {
+        let mut bad = data.clone();
+        bad[100] ^= 0xFF;
+        fs::write(root.join(name), &bad).unwrap();
+    }
+    let rr = repair::repair(&out.join(MANIFEST_JSON), root).unwrap();
+    assert_eq!(rr.failed_chunks, 0, "{:?}", rr);
+    for (name, data) in files {
+        assert_eq!(&fs::read(root.join(name)).unwrap(), data, "{}", name);
+    }
+}
+
+#[test]
+fn rebalance_fills_holes_and_drops_emptied_stripes() {
+    let td = tempfile::tempdir().unwrap();
+    let (root, out) = (td.path().join("data"), td.path().join(".parx"));
+    let files = worn_set(&root, &out, 11);
+    let size_before = volume_bytes(&out);
+
+    let rep = rebalance(&out, &root, &RebalanceOptions::default()).unwrap();
+    assert_eq!((rep.holes_before, rep.holes_after), (9, 0));
+    assert_eq!((rep.stripes_before, rep.stripes_after), (8, 6));
+    assert!(rep.chunks_moved > 0 && rep.bytes_read > 0 && !rep.budget_exhausted, "{:?}", rep);
+    assert!(volume_bytes(&out) < size_before);
+
+    let mpath = out.join(MANIFEST_JSON);
+    let (mf, _) = manifest::load(&mpath).unwrap();
+    assert_eq!(mf.total_chunks, 21);
+    let mut slots: Vec<u64> =
+        mf.files.iter().flat_map(|f| f.chunks.iter().map(|c| c.idx)).collect();
+    slots.sort_unstable();
+    assert_eq!(slots, (0..21).collect::<Vec<_>>());
+    assert!(parity_shortfall(&mf, &out).is_none());
+    let vr = verify::verify(&mpath, &root).unwrap();
+    assert!(vr.chunks_bad == 0 && vr.merkle_ok, "{:?}", vr);
+    damage_and_repair(&root, &out, &files);
+
+    // Nothing left to do
+    let again = rebalance(&out, &root, &RebalanceOptions::default()).unwrap();
+    assert_eq!((again.chunks_moved, again.holes_after, again.stripes_after), (0, 0, 6));
+}
+
+#[test]
+fn a_spent_budget_leaves_a_consistent_set_to_continue_from() {
+    let td = tempfile::tempdir().unwrap();
+    let (root, out) = (td.path().join("data"), td.path().join(".parx"));
+    let files = worn_set(&root, &out, 12);
+
+    // Room for the two stripes of the first move (and of any other move
+    // between the same two)
+    let opts = RebalanceOptions { max_io: Some(2 * 4 * 4096), ..Default::default() };
+    let rep = rebalance(&out, &root, &opts).unwrap();
+    assert!(rep.chunks_moved > 0 && rep.bytes_read <= 2 * 4 * 4096, "{:?}", rep);
+    assert!(rep.budget_exhausted && rep.holes_after > 0, "{:?}", rep);
+    let mpath = out.join(MANIFEST_JSON);
+    assert_eq!(verify::verify(&mpath, &root).unwrap().chunks_bad, 0);
+    damage_and_repair(&root, &out, &files);
+
+    let none = RebalanceOptions { max_io: Some(0), ..Default::default() };
+    assert_eq!(rebalance(&out, &root, &none).unwrap().chunks_moved, 0);
+
+    let rep = rebalance(&out, &root, &RebalanceOptions::default()).unwrap();
+    assert_eq!((rep.holes_after, rep.stripes_after), (0, 6));
+    damage_and_repair(&root, &out, &files);
+}
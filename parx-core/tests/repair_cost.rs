@@ -0,0 +1,184 @@
+use parx_core::encode::Encoder;
+use parx_core::repair::{self, RepairOptions, VolumeSource};
+use parx_core::storage::{CostClass, ReadCost};
+use parx_core::volume::vol_name;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Seek, SeekFrom, Write};
+use std::net::TcpListener;
+use std::path::PathBuf;
+
+mod common;
+
+/// Minimal HTTP/1.1 file server honouring HEAD and `Range: bytes=a-b`; one request per connection.
+fn serve_dir(root: PathBuf) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(s) => s,
+                Err(_) => continue,
+            };
+            let mut rd = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            rd.read_line(&mut line).unwrap();
+            let head = line.starts_with("HEAD ");
+            let path = line.split_whitespace().nth(1).unwrap_or("/").to_string();
+            let mut range = None;
+            loop {
+                let mut h = String::new();
+                if rd.read_line(&mut h).unwrap() == 0 || h == "\r\n" {
+                    break;
+                }
+                if let Some(v) = h.to_ascii_lowercase().strip_prefix("range: bytes=") {
+                    let (a, b) = v.trim().split_once('-').unwrap();
+                    range = Some((a.parse::<usize>().unwrap(), b.parse::<usize>().unwrap()));
+                }
+            }
+            match fs::read(root.join(path.trim_start_matches('/'))) {
+                Ok(data) => {
+                    let (status, body) = match range {
+                        Some((a, b)) => ("206 Partial Content", &data[a..=b.min(data.len() - 1)]),
+                        None => ("200 OK", &data[..]),
+                    };
+                    let hdr = format!(
+                        "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        status,
+                        body.len()
+                    );
+                    let _ = stream.write_all(hdr.as_bytes());
+                    if !head {
+                        let _ = stream.write_all(body);
+                    }
+                }
+                Err(_) => {
+                    let _ = stream.write_all(
+                        b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    );
+                }
+            }
+        }
+    });
+    format!("http://{}", addr)
+}
+
+#[test]
+fn repair_prefers_local_copies_over_remote_mirror() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir(&root).unwrap();
+    let mut rng = StdRng::seed_from_u64(5);
+    let data: Vec<u8> = (0..32 * 1024).map(|_| rng.gen()).collect();
+    fs::write(root.join("a.bin"), &data).unwrap();
+
+    let out = td.path().join(".parx");
+    let cfg = common::cfg(4096);
+    Encoder::encode(&root, &out, &cfg).unwrap();
+    let mpath = out.join("manifest.json");
+
+    // Full mirror over HTTP; vol-001 is also on a second local medium
+    let mirror = td.path().join("mirror");
+    fs::create_dir(&mirror).unwrap();
+    for i in 0..2 {
+        fs::copy(out.join(vol_name(i)), mirror.join(vol_name(i))).unwrap();
+    }
+    let url = serve_dir(mirror);
+    let media_b = td.path().join("media-b");
+    fs::create_dir(&media_b).unwrap();
+    fs::rename(out.join(vol_name(1)), media_b.join(vol_name(1))).unwrap();
+
+    let damage = |offsets: &[u64]| {
+        let mut f = OpenOptions::new().write(true).open(root.join("a.bin")).unwrap();
+        for &at in offsets {
+            f.seek(SeekFrom::Start(at)).unwrap();
+            f.write_all(&[0u8; 32]).unwrap();
+        }
+    };
+
+    damage(&[10]);
+    let opts = RepairOptions {
+        extra_dirs: vec![media_b.clone()],
+        extra_sources: vec![VolumeSource::http(&url, 2, ReadCost::REMOTE).unwrap()],
+        ..Default::default()
+    };
+    let rr = repair::repair_with_options(&mpath, &root, Default::default(), &opts).unwrap();
+    assert_eq!(rr.repaired_chunks, 1);
+    assert_eq!(rr.remote_shard_reads, 0, "{:?}", rr);
+    // The mirror's indices were not even read
+    assert_eq!(rr.duplicate_shards, 0);
+    assert_eq!(fs::read(root.join("a.bin")).unwrap(), data);
+
+    // Without the second medium the remote mirror has to supply vol-001's
+    // shards for a stripe that lost two chunks
+    damage(&[10, 4096 + 10]);
+    let opts = RepairOptions {
+        extra_sources: vec![VolumeSource::http(&url, 2, ReadCost::REMOTE).unwrap()],
+        ..Default::default()
+    };
+    fs::remove_file(media_b.join(vol_name(1))).unwrap();
+    let rr = repair::repair_with_options(&mpath, &root, Default::default(), &opts).unwrap();
+    assert_eq!(rr.repaired_chunks, 2);
+    assert!(rr.remote_shard_reads > 0, "{:?}", rr);
+    assert_eq!(fs::read(root.join("a.bin")).unwrap(), data);
+
+    // One lost chunk is covered by vol-000 alone, so the mirror is not asked
+    // at all (it is gone by now: its indices would count as unreadable)
+    damage(&[10]);
+    let gone = {
+        let l = TcpListener::bind("127.0.0.1:0").unwrap();
+        format!("http://{}", l.local_addr().unwrap())
+    };
+    let opts = RepairOptions {
+        extra_sources: vec![VolumeSource::http(&gone, 2, ReadCost::REMOTE).unwrap()],
+        ..Default::default()
+    };
+    let rr = repair::repair_with_options(&mpath, &root, Default::default(), &opts).unwrap();
+    assert_eq!((rr.repaired_chunks, rr.unreadable_volumes, rr.remote_shard_reads), (1, 0, 0));
+    assert_eq!(fs::read(root.join("a.bin")).unwrap(), data);
+}
+
+#[test]
+fn repair_reads_only_damaged_stripes_from_a_mirror_without_local_volumes() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir(&root).unwrap();
+    let mut rng = StdRng::seed_from_u64(6);
+    let data: Vec<u8> = (0..64 * 1024).map(|_| rng.gen()).collect();
+    fs::write(root.join("a.bin"), &data).unwrap();
+    let out = td.path().join(".parx");
+    let cfg = common::cfg(4096);
+    let mf = Encoder::encode(&root, &out, &cfg).unwrap();
+
+    // Only the manifest was downloaded; the volumes stay on the server
+    let mirror = td.path().join("mirror");
+    fs::create_dir(&mirror).unwrap();
+    for vid in 0..2 {
+        fs::rename(out.join(mf.volume_name(vid)), mirror.join(mf.volume_name(vid))).unwrap();
+    }
+    let url = serve_dir(mirror);
+    let mut bad = data.clone();
+    bad[5 * 4096 + 1] ^= 0xFF;
+    fs::write(root.join("a.bin"), &bad).unwrap();
+    let opts = RepairOptions {
+        extra_sources: vec![VolumeSource::http_of(&url, &mf, ReadCost::REMOTE).unwrap()],
+        ..Default::default()
+    };
+    let rr =
+        repair::repair_with_options(&out.join("manifest.json"), &root, Default::default(), &opts)
+            .unwrap();
+    assert_eq!(rr.repaired_chunks, 1);
+    // One stripe of four lost a chunk: only its two shards are fetched
+    assert_eq!(rr.remote_shard_reads, 2, "{:?}", rr);
+    assert_eq!(fs::read(root.join("a.bin")).unwrap(), data);
+}
+
+#[test]
+fn read_cost_parses_class_and_latency() {
+    assert_eq!(ReadCost::parse("local").unwrap(), ReadCost::LOCAL);
+    let c = ReadCost::parse("remote:200").unwrap();
+    assert_eq!((c.class, c.latency_ms), (CostClass::Remote, 200));
+    assert!(ReadCost::LAN < ReadCost::REMOTE);
+    assert!(ReadCost::parse("tape").is_err());
+    assert!(ReadCost::parse("lan:x").is_err());
+}
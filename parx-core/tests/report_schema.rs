@@ -0,0 +1,97 @@
+use parx_core::audit::{self, AuditReport};
+use parx_core::checkall::{self, CheckAllReport};
+use parx_core::encode::Encoder;
+use parx_core::heal::HealReport;
+use parx_core::parity_audit::{ParitycheckReport, VolumeCheck};
+use parx_core::path_safety::PathPolicy;
+use parx_core::rebalance::RebalanceReport;
+use parx_core::repair::{self, RepairReport};
+use parx_core::report::{to_json, Report, Versioned, SCHEMA_VERSION};
+use parx_core::update::{ForgetReport, UpdateReport};
+use parx_core::usage::{UsageReport, VolumeUsage};
+use parx_core::verify::{self, VerifyReport};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fs;
+
+mod common;
+
+/// Parse `report`'s JSON back and check it serializes to the same text.
+fn roundtrip<T: Report + Serialize + DeserializeOwned>(report: &T) -> serde_json::Value {
+    let json = to_json(report).unwrap();
+    let back = Versioned::<T>::from_json(&json).unwrap();
+    assert_eq!((back.schema_version, back.kind.as_str()), (SCHEMA_VERSION, T::KIND));
+    assert_eq!(to_json(&back.report).unwrap(), json);
+    serde_json::from_str(&json).unwrap()
+}
+
+#[test]
+fn reports_roundtrip_through_the_versioned_schema() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir(&root).unwrap();
+    fs::write(root.join("a.bin"), vec![1u8; 16 * 1024]).unwrap();
+    let out = td.path().join(".parx");
+    let cfg = common::cfg(4096);
+    Encoder::encode(&root, &out, &cfg).unwrap();
+    let mut a = vec![1u8; 16 * 1024];
+    a[5000] = 0;
+    fs::write(root.join("a.bin"), &a).unwrap();
+    let mf = out.join("manifest.json");
+
+    let v = roundtrip::<VerifyReport>(&verify::verify(&mf, &root).unwrap());
+    assert_eq!((v["kind"].as_str(), v["chunks_bad"].as_u64()), (Some("verify"), Some(1)));
+    let a = roundtrip::<AuditReport>(&audit::audit(&mf, &root, PathPolicy::default()).unwrap());
+    assert_eq!(a["damaged"][0]["bad_chunks"], serde_json::json!([1]));
+    let c = checkall::check_all(&mf, &root, &Default::default()).unwrap();
+    let c = roundtrip::<CheckAllReport>(&c);
+    assert_eq!((c["grade"].as_str(), c["verify"]["chunks_bad"].as_u64()), (Some("AMBER"), Some(1)));
+    let r = roundtrip::<RepairReport>(&repair::repair(&mf, &root).unwrap());
+    assert_eq!(r["repaired_chunks"], 1);
+    roundtrip(&HealReport {
+        unreadable_volumes: vec!["vol-009.parxv".into()],
+        ..Default::default()
+    });
+    roundtrip(&UpdateReport { files_added: 2, chunks_freed: 3, ..Default::default() });
+    roundtrip(&RebalanceReport { chunks_moved: 4, budget_exhausted: true, ..Default::default() });
+    roundtrip(&ForgetReport { files_forgotten: 1, chunks_freed: 2, ..Default::default() });
+    roundtrip(&UsageReport {
+        source_bytes: 100,
+        overhead_pct: 12.5,
+        volumes: vec![VolumeUsage {
+            volume: "vol-000.parxv".into(),
+            bytes: 9,
+            ..Default::default()
+        }],
+        ..Default::default()
+    });
+    let p = roundtrip(&ParitycheckReport {
+        deep: true,
+        volumes: vec![VolumeCheck {
+            volume: "vol-000.parxv".into(),
+            entries: 4,
+            index: "ok".into(),
+            shards_bad: Some(0),
+            cached: false,
+            error: None,
+        }],
+        shards_bad: 0,
+        domains: vec![],
+    });
+    // Optional fields of the newer reports are always written
+    assert!(p["volumes"][0]["error"].is_null());
+}
+
+#[test]
+fn reports_of_other_kinds_or_newer_schemas_are_refused() {
+    let json = to_json(&UpdateReport::default()).unwrap();
+    let err = Versioned::<HealReport>::from_json(&json).unwrap_err();
+    assert!(err.to_string().contains("expected a heal report"), "{}", err);
+
+    let newer = json.replace(
+        &format!("\"schema_version\":{}", SCHEMA_VERSION),
+        &format!("\"schema_version\":{}", SCHEMA_VERSION + 1),
+    );
+    assert!(Versioned::<UpdateReport>::from_json(&newer).is_err());
+    assert!(Versioned::<UpdateReport>::from_json(&json).is_ok());
+}
@@ -0,0 +1,89 @@
+use parx_core::encode::Encoder;
+use parx_core::path_safety::PathPolicy;
+use parx_core::repair::{self, RepairOptions};
+use parx_core::scrub::{map_to_chunks, parse_report, ScrubError};
+use std::fs::{self, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+
+mod common;
+
+const ZPOOL: &str = "  pool: tank
+ state: ONLINE
+status: One or more devices has experienced an error resulting in data
+        corruption.  Applications may be affected.
+config:
+
+        NAME        STATE     READ WRITE CKSUM
+        tank        ONLINE       0     0     0
+          sda       ONLINE       0     0     4
+
+errors: Permanent errors have been detected in the following files:
+
+        /tank/photos/2001/img.jpg
+        tank/archive:/old/notes.txt
+        <metadata>:<0x3f>
+        tank/photos:<0x1f2>
+";
+
+const DMESG: &str = "\
+[ 812.1] BTRFS info (device sdb1): scrub: started on devid 1
+[ 812.5] BTRFS warning (device sdb1): checksum error at logical 298844160 on dev /dev/sdb1, physical 298844160, root 5, inode 257, offset 8192, length 4096, links 1 (path: data/b.bin)
+[ 812.5] BTRFS warning (device sdb1): checksum error at logical 30081024 on dev /dev/sdb1, physical 30081024: metadata leaf (level 0) in tree 2
+[ 812.6] BTRFS error (device sdb1): bdev /dev/sdb1 errs: wr 0, rd 0, flush 0, corrupt 2, gen 0
+";
+
+#[test]
+fn parses_zpool_status_and_btrfs_kernel_lines() {
+    let rep = parse_report(ZPOOL);
+    assert_eq!(
+        rep.errors,
+        [
+            ScrubError { path: "/tank/photos/2001/img.jpg".into(), range: None },
+            ScrubError { path: "/old/notes.txt".into(), range: None },
+        ]
+    );
+    assert_eq!(rep.unresolved, ["<metadata>:<0x3f>", "tank/photos:<0x1f2>"]);
+
+    let rep = parse_report(DMESG);
+    assert_eq!(rep.errors, [ScrubError { path: "data/b.bin".into(), range: Some((8192, 4096)) }]);
+    assert_eq!(rep.unresolved.len(), 1);
+}
+
+#[test]
+fn scrub_driven_repair_only_touches_reported_regions() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir(&root).unwrap();
+    fs::write(root.join("a.bin"), vec![1u8; 32 * 1024]).unwrap();
+    fs::write(root.join("b.bin"), vec![2u8; 32 * 1024]).unwrap();
+    let out = td.path().join(".parx");
+    let cfg = common::cfg(4096);
+    let mf = Encoder::encode(&root, &out, &cfg).unwrap();
+
+    // The scrub saw b.bin's third chunk; a.bin's damage went unreported
+    for (name, off) in [("b.bin", 8192u64), ("a.bin", 0)] {
+        let mut f = OpenOptions::new().write(true).open(root.join(name)).unwrap();
+        f.seek(SeekFrom::Start(off)).unwrap();
+        f.write_all(&[0xA5; 100]).unwrap();
+    }
+    let targets = map_to_chunks(&mf, &parse_report(DMESG).errors);
+    assert_eq!(targets.chunks.iter().copied().collect::<Vec<_>>(), [10]);
+
+    let opts = RepairOptions { only_chunks: Some(targets.chunks), ..Default::default() };
+    let rr = repair::repair_with_options(
+        &out.join("manifest.json"),
+        &root,
+        PathPolicy::default(),
+        &opts,
+    )
+    .unwrap();
+    assert_eq!((rr.chunks_checked, rr.repaired_chunks, rr.failed_chunks), (1, 1, 0));
+    assert_eq!(fs::read(root.join("b.bin")).unwrap(), vec![2u8; 32 * 1024]);
+    assert_ne!(fs::read(root.join("a.bin")).unwrap(), vec![1u8; 32 * 1024]);
+
+    // Whole-file entries (ZFS) map to every chunk of the file
+    let errors = [ScrubError { path: "/mnt/tank/data/a.bin".into(), range: None }];
+    let targets = map_to_chunks(&mf, &errors);
+    assert_eq!(targets.chunks.len(), 8);
+    assert!(targets.unmatched.is_empty());
+}
@@ -0,0 +1,133 @@
+use parx_core::encode::Encoder;
+use parx_core::manifest::{SetId, SetTag};
+use parx_core::volume::{vol_name, VolumeHeader};
+use parx_core::{parity_audit, repair, update, verify};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+
+mod common;
+
+fn make_set(dir: &Path, seed: u64) -> (Vec<u8>, parx_core::manifest::Manifest) {
+    let root = dir.join("data");
+    fs::create_dir_all(&root).unwrap();
+    let mut rng = StdRng::seed_from_u64(seed);
+    let data: Vec<u8> = (0..32 * 1024).map(|_| rng.gen()).collect();
+    fs::write(root.join("a.bin"), &data).unwrap();
+    let cfg = common::cfg(4096);
+    let mf = Encoder::encode(&root, &dir.join(".parx"), &cfg).unwrap();
+    (data, mf)
+}
+
+fn header_set_id(path: &Path) -> Option<SetId> {
+    SetId::from_ext(&VolumeHeader::read_from(File::open(path).unwrap()).unwrap().ext)
+}
+
+#[test]
+fn every_volume_carries_the_set_id_of_its_manifest() {
+    let td = tempfile::tempdir().unwrap();
+    let (_, a) = make_set(&td.path().join("a"), 1);
+    let (_, b) = make_set(&td.path().join("b"), 1);
+    let id = a.set_id().expect("create records a set id");
+    // Same input and settings, still another set
+    assert_ne!(Some(id), b.set_id());
+
+    let text = id.to_string();
+    assert_eq!(text.len(), 36);
+    assert_eq!((&text[8..9], &text[14..15]), ("-", "4"));
+    for v in 0..2 {
+        assert_eq!(header_set_id(&td.path().join("a/.parx").join(vol_name(v))), Some(id));
+    }
+    // The manifest keeps it across a save and load
+    let (loaded, _) = parx_core::manifest::load(&td.path().join("a/.parx/manifest.json")).unwrap();
+    assert_eq!(loaded.set_id(), Some(id));
+}
+
+#[test]
+fn repair_skips_a_volume_of_another_set() {
+    let td = tempfile::tempdir().unwrap();
+    let (data, a) = make_set(&td.path().join("a"), 2);
+    let (_, b) = make_set(&td.path().join("b"), 3);
+    let out = td.path().join("a/.parx");
+    // A stray vol-001 from a set with the same layout but other data
+    fs::copy(td.path().join("b/.parx").join(vol_name(1)), out.join(vol_name(1))).unwrap();
+
+    let root = td.path().join("a/data");
+    let mut f = OpenOptions::new().write(true).open(root.join("a.bin")).unwrap();
+    f.seek(SeekFrom::Start(10)).unwrap();
+    f.write_all(&[0u8; 32]).unwrap();
+    drop(f);
+
+    let rr = repair::repair(&out.join("manifest.json"), &root).unwrap();
+    assert_eq!((rr.repaired_chunks, rr.failed_chunks), (1, 0));
+    assert_eq!(rr.foreign_volumes.len(), 1, "{:?}", rr.foreign_volumes);
+    let foreign = &rr.foreign_volumes[0];
+    assert!(foreign.starts_with("vol-001.parxv"), "{}", foreign);
+    assert!(foreign.contains(&b.set_id().unwrap().to_string()), "{}", foreign);
+    assert!(!foreign.contains(&a.set_id().unwrap().to_string()), "{}", foreign);
+    assert_eq!(fs::read(root.join("a.bin")).unwrap(), data);
+}
+
+fn header_generation(path: &Path) -> Option<u64> {
+    SetTag::from_ext(&VolumeHeader::read_from(File::open(path).unwrap()).unwrap().ext).generation
+}
+
+#[test]
+fn create_and_update_count_the_generation_up_in_manifest_and_volumes() {
+    let td = tempfile::tempdir().unwrap();
+    let (_, mf) = make_set(td.path(), 4);
+    assert_eq!(mf.generation(), Some(1));
+    // Again into the same output dir: one past the set it replaces
+    let (_, mf) = make_set(td.path(), 4);
+    assert_eq!(mf.generation(), Some(2));
+    let out = td.path().join(".parx");
+    for v in 0..2 {
+        assert_eq!(header_generation(&out.join(vol_name(v))), Some(2));
+    }
+
+    fs::write(td.path().join("data/b.bin"), vec![7u8; 9000]).unwrap();
+    update::update(&out, &td.path().join("data"), None).unwrap();
+    let (mf, _) = parx_core::manifest::load(&out.join("manifest.json")).unwrap();
+    assert_eq!(mf.generation(), Some(3));
+    for v in 0..2 {
+        assert_eq!(header_generation(&out.join(vol_name(v))), Some(3));
+    }
+}
+
+#[test]
+fn a_volume_of_an_earlier_generation_is_stale_and_not_used() {
+    let td = tempfile::tempdir().unwrap();
+    let (data, mf) = make_set(td.path(), 5);
+    let (out, root) = (td.path().join(".parx"), td.path().join("data"));
+    let old = td.path().join("old-vol-001.parxv");
+    fs::copy(out.join(vol_name(1)), &old).unwrap();
+    fs::write(root.join("b.bin"), vec![9u8; 5000]).unwrap();
+    update::update(&out, &root, None).unwrap();
+    // The copy from before the update comes back, same set but stale parity
+    fs::copy(&old, out.join(vol_name(1))).unwrap();
+
+    let mut f = OpenOptions::new().write(true).open(root.join("a.bin")).unwrap();
+    f.seek(SeekFrom::Start(10)).unwrap();
+    f.write_all(&[0u8; 32]).unwrap();
+    drop(f);
+
+    let mpath = out.join("manifest.json");
+    let vr = verify::verify(&mpath, &root).unwrap();
+    let short = vr.parity_shortfall.expect("stale volume's shards are not counted");
+    assert_eq!(short.volumes_stray, vec!["vol-001.parxv (generation 1, manifest is generation 2)"]);
+
+    let (now, _) = parx_core::manifest::load(&mpath).unwrap();
+    assert_eq!(now.set_id(), mf.set_id());
+    let vols: Vec<_> = (0..2).map(|v| out.join(vol_name(v))).collect();
+    let qc = parity_audit::quickcheck(&vols, now.set_tag());
+    assert_eq!((qc.foreign, qc.stale), (0, 1));
+    assert_eq!((qc.volumes[1].index.as_str(), qc.volumes[1].generation), ("stale", Some(1)));
+
+    let rr = repair::repair(&mpath, &root).unwrap();
+    assert_eq!((rr.repaired_chunks, rr.failed_chunks), (1, 0));
+    assert!(rr.foreign_volumes.is_empty());
+    assert_eq!(rr.stale_volumes.len(), 1, "{:?}", rr.stale_volumes);
+    assert!(rr.stale_volumes[0].starts_with("vol-001.parxv"), "{}", rr.stale_volumes[0]);
+    assert_eq!(fs::read(root.join("a.bin")).unwrap(), data);
+}
@@ -0,0 +1,99 @@
+use parx_core::encode::{EncodeOptions, Encoder, EncoderConfig};
+use parx_core::{manifest, repair, submanifest, update, verify};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+
+mod common;
+
+#[test]
+fn a_copied_dir_verifies_and_repairs_with_its_sub_manifest() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    let out = td.path().join(".parx");
+    fs::create_dir_all(root.join("photos/raw")).unwrap();
+    fs::create_dir_all(root.join("docs")).unwrap();
+    let mut rng = StdRng::seed_from_u64(30);
+    let mut random = |n: usize| -> Vec<u8> { (0..n).map(|_| rng.gen()).collect() };
+    let photo = random(5000);
+    fs::write(root.join("photos/a.jpg"), &photo).unwrap();
+    fs::write(root.join("photos/raw/b.cr2"), random(3000)).unwrap();
+    fs::write(root.join("docs/c.txt"), random(2100)).unwrap();
+    fs::write(root.join("top.txt"), random(500)).unwrap();
+
+    let opts = EncodeOptions { sub_manifests: true, ..Default::default() };
+    let mf = Encoder::encode_with(&root, &out, &common::cfg(1024), &opts).unwrap();
+    assert!(verify::verify(&out.join("manifest.json"), &root).unwrap().merkle_ok);
+    // No stripe holds chunks of two top-level dirs (or of one and the root)
+    let mut owner: HashMap<u64, Option<&str>> = HashMap::new();
+    for fe in &mf.files {
+        for c in &fe.chunks {
+            let dir = submanifest::top_dir(&fe.rel_path);
+            assert_eq!(*owner.entry(c.idx / 4).or_insert(dir), dir, "{}", fe.rel_path);
+        }
+    }
+    // docs: 3 chunks and a skipped slot, photos: 8, top.txt: 1
+    assert_eq!(mf.total_chunks, 13);
+    let listed = submanifest::listed(&mf);
+    assert_eq!(listed.keys().collect::<Vec<_>>(), ["docs", "photos"]);
+
+    let sub_path = submanifest::path_of(&out, "photos");
+    let (sub, _) = manifest::load(&sub_path).unwrap();
+    assert!(submanifest::belongs_to(&sub, &mf));
+    assert_eq!(submanifest::subset(&sub), Some("photos"));
+    let paths: HashSet<&str> = sub.files.iter().map(|f| f.rel_path.as_str()).collect();
+    assert_eq!(paths, HashSet::from(["a.jpg", "raw/b.cr2"]));
+    assert_eq!(sub.total_bytes, 8000);
+
+    // Copy photos/ with its sub-manifest and the volumes; the original set is gone
+    let dst = td.path().join("copy");
+    let photos = dst.join("photos");
+    let set = dst.join(".parx");
+    fs::create_dir_all(photos.join("raw")).unwrap();
+    fs::create_dir_all(set.join("sub/photos")).unwrap();
+    for rel in ["a.jpg", "raw/b.cr2"] {
+        fs::copy(root.join("photos").join(rel), photos.join(rel)).unwrap();
+    }
+    for name in ["vol-000.parxv", "vol-001.parxv"] {
+        fs::copy(out.join(name), set.join(name)).unwrap();
+    }
+    for name in ["manifest.json", "manifest.v2"] {
+        fs::copy(out.join("sub/photos").join(name), set.join("sub/photos").join(name)).unwrap();
+    }
+    fs::remove_dir_all(&out).unwrap();
+    fs::remove_dir_all(&root).unwrap();
+
+    let sub_path = set.join("sub/photos/manifest.json");
+    let vr = verify::verify(&sub_path, &photos).unwrap();
+    assert_eq!((vr.chunks_bad, vr.merkle_ok), (0, true));
+    assert_eq!(sub.parity_dir_at(&sub_path), set);
+
+    let mut damaged = photo.clone();
+    damaged[1100..1200].fill(0);
+    fs::write(photos.join("a.jpg"), &damaged).unwrap();
+    assert!(!verify::verify(&sub_path, &photos).unwrap().merkle_ok);
+    let rr = repair::repair(&sub_path, &photos).unwrap();
+    assert_eq!((rr.repaired_chunks, rr.failed_chunks), (1, 0));
+    assert_eq!(fs::read(photos.join("a.jpg")).unwrap(), photo);
+    assert!(verify::verify(&sub_path, &photos).unwrap().merkle_ok);
+}
+
+#[test]
+fn sub_manifests_are_refused_where_stripes_span_dirs() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    let out = td.path().join(".parx");
+    fs::create_dir_all(root.join("d")).unwrap();
+    fs::write(root.join("d/f"), vec![3u8; 3000]).unwrap();
+    let opts = EncodeOptions { sub_manifests: true, ..Default::default() };
+    let outer = EncoderConfig { outer_group: 2, outer_parity: 1, ..common::cfg(1024) };
+    let err = Encoder::encode_with(&root, &out, &outer, &opts).unwrap_err();
+    assert!(err.to_string().contains("--sub-manifests"), "{err}");
+
+    Encoder::encode_with(&root, &out, &common::cfg(1024), &opts).unwrap();
+    let err = update::update(&out, &root, None).unwrap_err();
+    assert!(err.to_string().contains("sub-manifests"), "{err}");
+    // A set made again without them drops the stale ones
+    Encoder::encode(&root, &out, &common::cfg(1024)).unwrap();
+    assert!(!out.join(submanifest::SUB_DIR).exists());
+}
@@ -0,0 +1,90 @@
+#![cfg(unix)]
+
+use parx_core::encode::{Encoder, EncoderConfig};
+use parx_core::manifest::SymlinkEntry;
+use parx_core::symlink::target_contained;
+use parx_core::{repair, update, verify};
+use std::fs;
+use std::os::unix::fs::symlink;
+
+mod common;
+
+fn cfg() -> EncoderConfig {
+    EncoderConfig { volumes: 1, ..common::cfg(4096) }
+}
+
+#[test]
+fn symlinks_are_recorded_verified_and_recreated() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir_all(root.join("sub")).unwrap();
+    fs::write(root.join("a.bin"), vec![1u8; 10_000]).unwrap();
+    symlink("a.bin", root.join("link")).unwrap();
+    symlink("../a.bin", root.join("sub/up")).unwrap();
+    symlink("/etc/hostname", root.join("abs")).unwrap();
+    let out = td.path().join(".parx");
+    let mf = Encoder::encode(&root, &out, &cfg()).unwrap();
+    let link =
+        |rel: &str, target: &str| SymlinkEntry { rel_path: rel.into(), target: target.into() };
+    assert_eq!(
+        mf.symlinks,
+        vec![link("abs", "/etc/hostname"), link("link", "a.bin"), link("sub/up", "../a.bin")]
+    );
+    assert_eq!(mf.files.len(), 1);
+    let manifest = out.join("manifest.json");
+    let vr = verify::verify(&manifest, &root).unwrap();
+    assert!(vr.symlinks_bad.is_empty() && vr.chunks_bad == 0);
+
+    fs::remove_file(root.join("link")).unwrap();
+    fs::remove_file(root.join("sub/up")).unwrap();
+    symlink("elsewhere", root.join("sub/up")).unwrap();
+    fs::remove_file(root.join("abs")).unwrap();
+    let vr = verify::verify(&manifest, &root).unwrap();
+    assert_eq!(vr.symlinks_bad, ["abs", "link", "sub/up"]);
+
+    let rr = repair::repair(&manifest, &root).unwrap();
+    assert_eq!(rr.symlinks_restored, 2);
+    // An absolute target is never planted by repair
+    assert_eq!(rr.symlinks_failed.len(), 1);
+    assert!(rr.symlinks_failed[0].starts_with("abs: "), "{:?}", rr.symlinks_failed);
+    assert_eq!(fs::read_link(root.join("link")).unwrap().to_str(), Some("a.bin"));
+    assert_eq!(fs::read_link(root.join("sub/up")).unwrap().to_str(), Some("../a.bin"));
+    assert!(fs::symlink_metadata(root.join("abs")).is_err());
+
+    // A regular file in a link's place is not replaced
+    fs::remove_file(root.join("link")).unwrap();
+    fs::write(root.join("link"), b"mine").unwrap();
+    let rr = repair::repair(&manifest, &root).unwrap();
+    assert_eq!(rr.symlinks_restored, 0);
+    assert_eq!(fs::read(root.join("link")).unwrap(), b"mine");
+}
+
+#[test]
+fn update_tracks_symlink_changes() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir_all(&root).unwrap();
+    fs::write(root.join("a.bin"), vec![1u8; 10_000]).unwrap();
+    let out = td.path().join(".parx");
+    Encoder::encode(&root, &out, &cfg()).unwrap();
+
+    symlink("a.bin", root.join("link")).unwrap();
+    let rep = update::update(&out, &root, None).unwrap();
+    assert_eq!((rep.symlinks_changed, rep.files_changed), (1, 0));
+    let vr = verify::verify(&out.join("manifest.json"), &root).unwrap();
+    assert!(vr.symlinks_bad.is_empty());
+    fs::remove_file(root.join("link")).unwrap();
+    let rr = repair::repair(&out.join("manifest.json"), &root).unwrap();
+    assert_eq!(rr.symlinks_restored, 1);
+    assert_eq!(update::update(&out, &root, None).unwrap().symlinks_changed, 0);
+}
+
+#[test]
+fn link_targets_must_stay_under_the_root() {
+    assert!(target_contained("link", "a.bin"));
+    assert!(target_contained("d/e/link", "../../a.bin"));
+    assert!(target_contained("d/link", "./x/../y"));
+    assert!(!target_contained("link", "../a.bin"));
+    assert!(!target_contained("d/link", "x/../../../a"));
+    assert!(!target_contained("link", "/etc/passwd"));
+}
@@ -0,0 +1,94 @@
+use parx_core::encode::{Encoder, EncoderConfig};
+use parx_core::verify;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::path::PathBuf;
+
+mod common;
+
+/// Minimal HTTP/1.1 file server honouring `Range: bytes=a-b`; one request per connection.
+fn serve_dir(root: PathBuf) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(s) => s,
+                Err(_) => continue,
+            };
+            let mut rd = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            rd.read_line(&mut line).unwrap();
+            let path = line.split_whitespace().nth(1).unwrap_or("/").to_string();
+            let mut range = None;
+            loop {
+                let mut h = String::new();
+                if rd.read_line(&mut h).unwrap() == 0 || h == "\r\n" {
+                    break;
+                }
+                if let Some(v) = h.to_ascii_lowercase().strip_prefix("range: bytes=") {
+                    let (a, b) = v.trim().split_once('-').unwrap();
+                    range = Some((a.parse::<usize>().unwrap(), b.parse::<usize>().unwrap()));
+                }
+            }
+            let file = root.join(path.trim_start_matches('/').replace("%20", " "));
+            match fs::read(&file) {
+                Ok(data) => {
+                    let (status, body) = match range {
+                        Some((a, b)) => ("206 Partial Content", &data[a..=b.min(data.len() - 1)]),
+                        None => ("200 OK", &data[..]),
+                    };
+                    let hdr = format!(
+                        "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        status,
+                        body.len()
+                    );
+                    let _ = stream.write_all(hdr.as_bytes());
+                    let _ = stream.write_all(body);
+                }
+                Err(_) => {
+                    let _ = stream.write_all(
+                        b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    );
+                }
+            }
+        }
+    });
+    format!("http://{}", addr)
+}
+
+#[test]
+fn verify_remote_detects_good_and_bad_mirror() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir_all(root.join("sub dir")).unwrap();
+    fs::write(root.join("a.bin"), vec![7u8; 10_000]).unwrap();
+    fs::write(root.join("sub dir/b.bin"), vec![9u8; 5_000]).unwrap();
+
+    let out = td.path().join(".parx");
+    let cfg = EncoderConfig { volumes: 1, ..common::cfg(4096) };
+    Encoder::encode(&root, &out, &cfg).unwrap();
+    let mpath = out.join("manifest.json");
+
+    let url = serve_dir(root.clone());
+    let rep = verify::verify_remote(&mpath, &url).unwrap();
+    assert_eq!(rep.chunks_bad, 0);
+    assert!(rep.merkle_ok);
+
+    // Damage the "mirror" copy and check the mismatch is reported
+    let mut bytes = fs::read(root.join("a.bin")).unwrap();
+    bytes[5000] ^= 0xFF;
+    fs::write(root.join("a.bin"), bytes).unwrap();
+    let rep = verify::verify_remote(&mpath, &url).unwrap();
+    assert_eq!(rep.chunks_bad, 1);
+    assert!(!rep.merkle_ok);
+}
+
+#[test]
+fn verify_remote_rejects_non_http_urls() {
+    let td = tempfile::tempdir().unwrap();
+    let err = verify::verify_remote(&td.path().join("manifest.json"), "ftp://mirror/x")
+        .expect_err("expected scheme error");
+    assert!(format!("{:#}", err).contains("unsupported URL scheme"));
+}
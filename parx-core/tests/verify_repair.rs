@@ -1,8 +1,23 @@
-use parx_core::encode::{Encoder, EncoderConfig};
-use parx_core::repair;
-use parx_core::verify;
+use parx_core::audit::{audit_with, AuditOptions};
+use parx_core::encode::{Encoder, EncoderConfig, GpuMode};
+use parx_core::fshint::{Hints, HINTS_FILE};
+use parx_core::index::{read_index, read_trailer, IndexLimits};
+use parx_core::path_safety::PathPolicy;
+use parx_core::repair::{self, ExtractedChunk, RepairOptions, EXTRACTED_INDEX};
+#[cfg(unix)]
+use parx_core::serve::{request, IntegrityProxy, ServeOptions, MAX_REQUEST_LINE};
+use parx_core::verify::{self, ParityShortfall};
+use parx_core::volume::vol_name;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use std::fs::{self, OpenOptions};
 use std::io::{Seek, SeekFrom, Write};
+#[cfg(unix)]
+use std::sync::Arc;
+#[cfg(unix)]
+use std::time::{Duration, Instant};
+
+mod common;
 
 #[test]
 fn verify_then_repair_simple_corruption() {
@@ -22,6 +37,8 @@ fn verify_then_repair_simple_corruption() {
         outer_group: 0,
         outer_parity: 0,
         interleave_files: false,
+        shard_copies: 1,
+        gpu: GpuMode::Off,
     };
     let manifest = Encoder::encode(&root, &out, &cfg).unwrap();
 
@@ -73,6 +90,8 @@ fn repair_single_file_bitflip() {
         outer_group: 0,
         outer_parity: 0,
         interleave_files: false,
+        shard_copies: 1,
+        gpu: GpuMode::Off,
     };
     let _manifest = Encoder::encode(&root, &out, &cfg).unwrap();
 
@@ -99,3 +118,540 @@ fn repair_single_file_bitflip() {
     assert_eq!(vr3.chunks_bad, 0);
     assert!(vr3.merkle_ok);
 }
+
+#[test]
+fn repair_falls_back_to_replicated_parity_copy() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir(&root).unwrap();
+    let data: Vec<u8> = (0..16 * 1024u32).map(|i| (i * 31 % 251) as u8).collect();
+    fs::write(root.join("a.bin"), &data).unwrap();
+
+    let out = td.path().join(".parx");
+    let cfg = EncoderConfig {
+        chunk_size: 4096,
+        stripe_k: 4,
+        parity_pct: 50,
+        volumes: 2,
+        outer_group: 0,
+        outer_parity: 0,
+        interleave_files: false,
+        shard_copies: 2,
+        gpu: GpuMode::Off,
+    };
+    let mf = Encoder::encode(&root, &out, &cfg).unwrap();
+    assert_eq!(mf.shard_copies, 2);
+
+    // Every volume now holds both parity shards of the single stripe; rot
+    // both of them in vol-000 but leave its index intact
+    let vol0 = out.join("vol-000.parxv");
+    let mut v = OpenOptions::new().read(true).write(true).open(&vol0).unwrap();
+    let (off, len, crc) = read_trailer(&mut v).unwrap();
+    let entries = read_index(&mut v, off, len, crc, &IndexLimits::default()).unwrap();
+    v.seek(SeekFrom::Start(entries[0].offset + 100)).unwrap();
+    v.write_all(&vec![0xEEu8; 2 * 4096 - 200]).unwrap();
+    drop(v);
+
+    let mut f = OpenOptions::new().write(true).open(root.join("a.bin")).unwrap();
+    f.seek(SeekFrom::Start(5000)).unwrap();
+    f.write_all(&[0u8; 16]).unwrap();
+    drop(f);
+
+    let rr = repair::repair(&out.join("manifest.json"), &root).unwrap();
+    assert_eq!(rr.repaired_chunks, 1);
+    assert_eq!(rr.bad_parity_copies, 2);
+    assert_eq!(fs::read(root.join("a.bin")).unwrap(), data);
+
+    let too_many = EncoderConfig { shard_copies: 3, ..cfg };
+    assert!(Encoder::encode(&root, &out, &too_many).is_err());
+}
+
+#[test]
+fn stripes_past_256_shards_use_gf16() {
+    use parx_core::ext::key;
+    use parx_core::rs_codec::RsField;
+    use parx_core::volume::{vol_name, VolumeHeader};
+
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir(&root).unwrap();
+    let data: Vec<u8> = (0..257 * 64u32).map(|i| (i % 253) as u8).collect();
+    fs::write(root.join("a.bin"), &data).unwrap();
+    let out = td.path().join(".parx");
+    let cfg = EncoderConfig {
+        chunk_size: 64,
+        stripe_k: 257,
+        parity_pct: 2,
+        volumes: 3,
+        outer_group: 0,
+        outer_parity: 0,
+        interleave_files: false,
+        shard_copies: 1,
+        gpu: GpuMode::Off,
+    };
+    let mf = Encoder::encode(&root, &out, &cfg).unwrap();
+    assert_eq!(RsField::from_ext(&mf.ext), RsField::Gf16);
+    let hdr = VolumeHeader::read_from(fs::File::open(out.join(vol_name(0))).unwrap()).unwrap();
+    assert_eq!(hdr.ext.get_u32(key::RS_FIELD), Some(16));
+
+    // Four chunks lost across the stripe, within its six parity shards
+    let mut f = OpenOptions::new().write(true).open(root.join("a.bin")).unwrap();
+    for c in (0..257u64).step_by(70) {
+        f.seek(SeekFrom::Start(c * 64)).unwrap();
+        f.write_all(&[0xEE; 8]).unwrap();
+    }
+    drop(f);
+    let rr = repair::repair(&out.join("manifest.json"), &root).unwrap();
+    assert_eq!((rr.repaired_chunks, rr.failed_chunks), (4, 0));
+    assert_eq!(fs::read(root.join("a.bin")).unwrap(), data);
+}
+
+#[test]
+fn verify_classifies_damaged_files() {
+    use verify::DamageKind;
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir(&root).unwrap();
+    for name in ["a.bin", "b.bin", "c.bin", "d.bin", "e.bin"] {
+        fs::write(root.join(name), vec![name.as_bytes()[0]; 8 * 1024]).unwrap();
+    }
+    let out = td.path().join(".parx");
+    let cfg = EncoderConfig {
+        chunk_size: 4096,
+        stripe_k: 4,
+        parity_pct: 50,
+        volumes: 1,
+        outer_group: 0,
+        outer_parity: 0,
+        interleave_files: false,
+        shard_copies: 1,
+        gpu: GpuMode::Off,
+    };
+    Encoder::encode(&root, &out, &cfg).unwrap();
+
+    // a rots, b is cut short, c is gone, d is replaced by a directory
+    let mut a = vec![b'a'; 8 * 1024];
+    a[5000] ^= 1;
+    fs::write(root.join("a.bin"), a).unwrap();
+    fs::write(root.join("b.bin"), vec![b'b'; 5000]).unwrap();
+    fs::remove_file(root.join("c.bin")).unwrap();
+    fs::remove_file(root.join("d.bin")).unwrap();
+    fs::create_dir(root.join("d.bin")).unwrap();
+
+    let vr = verify::verify(&out.join("manifest.json"), &root).unwrap();
+    assert_eq!((vr.chunks_ok, vr.chunks_bad, vr.merkle_ok), (4, 6, false));
+    let found: Vec<(&str, DamageKind, u64)> =
+        vr.damaged_files.iter().map(|d| (d.path.as_str(), d.kind, d.chunks_bad)).collect();
+    assert_eq!(
+        found,
+        [
+            ("a.bin", DamageKind::ContentMismatch, 1),
+            ("b.bin", DamageKind::Truncated, 1),
+            ("c.bin", DamageKind::Missing, 2),
+            ("d.bin", DamageKind::Unreadable, 2),
+        ]
+    );
+    assert!(vr.damaged_files[3].error.is_some());
+}
+
+#[test]
+fn verify_keeps_going_past_files_it_cannot_check() {
+    use parx_core::storage::{DataSource, LocalSource};
+    use verify::DamageKind;
+
+    // Reads like the local tree, but every fetch of b.bin fails
+    struct Flaky(LocalSource);
+    impl DataSource for Flaky {
+        fn read_at(&self, rel_path: &str, offset: u64, buf: &mut [u8]) -> anyhow::Result<()> {
+            anyhow::ensure!(rel_path != "sub/b.bin", "connection reset");
+            self.0.read_at(rel_path, offset, buf)
+        }
+        fn len(&self, rel_path: &str) -> anyhow::Result<u64> {
+            self.0.len(rel_path)
+        }
+        fn describe(&self) -> String {
+            "flaky".into()
+        }
+    }
+
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir_all(root.join("sub")).unwrap();
+    for name in ["a.bin", "sub/b.bin", "c.bin"] {
+        fs::write(root.join(name), vec![name.as_bytes()[0]; 8 * 1024]).unwrap();
+    }
+    let out = td.path().join(".parx");
+    let cfg = EncoderConfig {
+        chunk_size: 4096,
+        stripe_k: 4,
+        parity_pct: 50,
+        volumes: 1,
+        outer_group: 0,
+        outer_parity: 0,
+        interleave_files: false,
+        shard_copies: 1,
+        gpu: GpuMode::Off,
+    };
+    Encoder::encode(&root, &out, &cfg).unwrap();
+    let mpath = out.join("manifest.json");
+
+    let src = Flaky(LocalSource::new(&root, Default::default()));
+    let vr = verify::verify_with_source(&mpath, &src).unwrap();
+    assert_eq!((vr.chunks_ok, vr.chunks_bad, vr.merkle_ok), (4, 2, false));
+    let b = &vr.damaged_files[0];
+    assert_eq!((b.path.as_str(), b.kind, b.chunks_bad), ("sub/b.bin", DamageKind::Unreadable, 2));
+    assert!(b.error.as_deref().unwrap().contains("connection reset"), "{:?}", b.error);
+
+    // A file where a directory was cannot be read through; c.bin is gone
+    fs::remove_dir_all(root.join("sub")).unwrap();
+    fs::write(root.join("sub"), b"not a directory").unwrap();
+    fs::remove_file(root.join("c.bin")).unwrap();
+    let vr = verify::verify(&mpath, &root).unwrap();
+    assert_eq!((vr.chunks_ok, vr.chunks_bad), (2, 4));
+    let found: Vec<(&str, DamageKind)> =
+        vr.damaged_files.iter().map(|d| (d.path.as_str(), d.kind)).collect();
+    assert_eq!(found, [("c.bin", DamageKind::Missing), ("sub/b.bin", DamageKind::Unreadable)]);
+}
+
+#[test]
+fn signatures_with_non_hex_text_are_refused() {
+    use parx_core::sign::{verify_bytes, SignatureBlock};
+    let block = |pk: &str, sig: &str| SignatureBlock {
+        alg: "ed25519".to_string(),
+        public_key_hex: pk.to_string(),
+        sig_hex: sig.to_string(),
+    };
+    // Multi-byte chars straddling a byte pair, and signs
+    for bad in [format!("a{}b", "é".repeat(31)), format!("+f{}", "ab".repeat(31)), "zz".repeat(32)]
+    {
+        let err = verify_bytes(&block(&bad, &"00".repeat(64)), b"msg", None).unwrap_err();
+        assert!(err.to_string().contains("invalid hex"), "{:?}: {}", bad, err);
+    }
+}
+
+#[test]
+fn manifest_signatures_cover_the_stored_fields() {
+    use ed25519_dalek::Signer;
+    use parx_core::encode::EncodeOptions;
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir_all(&root).unwrap();
+    fs::write(root.join("a.bin"), vec![9u8; 20_000]).unwrap();
+    let vk =
+        parx_core::sign::generate_keypair(&td.path().join("k"), &td.path().join("k.pub")).unwrap();
+    let sk = parx_core::sign::load_signing_key(&td.path().join("k")).unwrap();
+    let out = td.path().join(".parx");
+    let cfg = EncoderConfig {
+        chunk_size: 4096,
+        stripe_k: 4,
+        parity_pct: 50,
+        volumes: 2,
+        outer_group: 0,
+        outer_parity: 0,
+        interleave_files: false,
+        shard_copies: 1,
+        gpu: GpuMode::Off,
+    };
+    let opts = EncodeOptions { sign_key: Some(sk.clone()), ..Default::default() };
+    Encoder::encode_with(&root, &out, &cfg, &opts).unwrap();
+    let mpath = out.join("manifest.json");
+    let vopts = verify::VerifyOptions { verify_key: Some(vk), ..Default::default() };
+    let check = || verify::verify_with_options(&mpath, &root, &vopts);
+    assert!(check().unwrap().merkle_ok);
+
+    // As a newer writer would store it: a field this version does not know,
+    // and a defaulted one left out
+    let mut json: serde_json::Value = serde_json::from_slice(&fs::read(&mpath).unwrap()).unwrap();
+    json["x_future"] = serde_json::json!({ "level": 2 });
+    json.as_object_mut().unwrap().remove("shard_copies");
+    let msg = parx_core::manifest::signing_form(json.clone()).unwrap();
+    let mut sig = vk.as_bytes().to_vec();
+    sig.extend_from_slice(&sk.sign(&msg).to_bytes());
+    json["ext"]["13"] = serde_json::json!(sig);
+    fs::write(&mpath, serde_json::to_vec_pretty(&json).unwrap()).unwrap();
+    assert!(check().unwrap().merkle_ok);
+    // Moving the set is fine, dropping the unknown field is not
+    json["parity_dir"] = "/elsewhere".into();
+    fs::write(&mpath, serde_json::to_vec(&json).unwrap()).unwrap();
+    assert!(check().unwrap().merkle_ok);
+    json.as_object_mut().unwrap().remove("x_future");
+    fs::write(&mpath, serde_json::to_vec_pretty(&json).unwrap()).unwrap();
+    let err = check().unwrap_err();
+    assert!(format!("{:#}", err).contains("signature check failed"), "{:#}", err);
+}
+
+#[test]
+fn unchanged_files_on_checksumming_fs_are_not_rehashed() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir(&root).unwrap();
+    fs::write(root.join("a.bin"), vec![1u8; 16 * 1024]).unwrap();
+    fs::write(root.join("b.bin"), vec![2u8; 16 * 1024]).unwrap();
+    let out = td.path().join(".parx");
+    let cfg = common::cfg(4096);
+    Encoder::encode(&root, &out, &cfg).unwrap();
+    let mf = out.join("manifest.json");
+    let audit = |opts: &AuditOptions| audit_with(&mf, &root, PathPolicy::default(), opts).unwrap();
+
+    // Without hints, or on a filesystem that does not checksum data, all is hashed
+    assert_eq!(audit(&AuditOptions::default()).files_trusted, 0);
+    assert!(!out.join(HINTS_FILE).exists());
+
+    let opts = AuditOptions { fs_hints: true, assume_checksummed: true, ..Default::default() };
+    let first = audit(&opts);
+    if Hints::load(&out).files.is_empty() {
+        return; // no inode stamps on this platform
+    }
+    assert_eq!((first.files_trusted, first.chunks_bad), (0, 0));
+    assert_eq!(audit(&opts).files_trusted, 2);
+
+    // A rewrite changes the stamp, so the file is hashed again and stays out
+    // of the hints while damaged
+    let mut f = OpenOptions::new().write(true).open(root.join("b.bin")).unwrap();
+    f.seek(SeekFrom::Start(5000)).unwrap();
+    f.write_all(&[0xEE; 10]).unwrap();
+    drop(f);
+    let ar = audit(&opts);
+    assert_eq!((ar.files_trusted, ar.chunks_bad), (1, 1));
+    assert!(ar.repairable);
+    assert_eq!(Hints::load(&out).files.keys().collect::<Vec<_>>(), ["a.bin"]);
+}
+
+#[test]
+fn verify_reports_stripes_left_with_less_parity() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir(&root).unwrap();
+    fs::write(root.join("a.bin"), vec![3u8; 9 * 4096]).unwrap();
+    let out = td.path().join(".parx");
+    let cfg = common::cfg(4096);
+    Encoder::encode(&root, &out, &cfg).unwrap();
+    let mpath = out.join("manifest.json");
+    assert_eq!(verify::verify(&mpath, &root).unwrap().parity_shortfall, None);
+
+    // Each volume holds one of the two parity shards of every stripe
+    fs::remove_file(out.join(vol_name(1))).unwrap();
+    let vr = verify::verify(&mpath, &root).unwrap();
+    assert_eq!((vr.chunks_bad, vr.merkle_ok), (0, true));
+    assert_eq!(
+        vr.parity_shortfall,
+        Some(ParityShortfall {
+            stripes_degraded: 3,
+            stripes_unprotected: 0,
+            min_parity_left: 1,
+            volumes_unreadable: vec!["vol-001.parxv".into()],
+            volumes_stray: vec![],
+        })
+    );
+
+    // vol-000 cut back to where its shards start: the index copy after the
+    // header still lists them, but their bytes are gone
+    let vol0 = out.join(vol_name(0));
+    let mut f = OpenOptions::new().read(true).write(true).open(&vol0).unwrap();
+    let (off, len, crc) = read_trailer(&mut f).unwrap();
+    let entries = read_index(&mut f, off, len, crc, &IndexLimits::default()).unwrap();
+    f.set_len(entries.iter().map(|e| e.offset).min().unwrap()).unwrap();
+    drop(f);
+    let vr = verify::verify(&mpath, &root).unwrap();
+    let short = vr.parity_shortfall.unwrap();
+    assert_eq!((short.stripes_degraded, short.stripes_unprotected), (3, 3));
+    assert_eq!(short.min_parity_left, 0);
+}
+
+#[test]
+fn shifted_chunks_are_found_and_moved_back() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    let out = td.path().join(".parx");
+    fs::create_dir_all(&root).unwrap();
+    let mut rng = StdRng::seed_from_u64(29);
+    let a: Vec<u8> = (0..10_240).map(|_| rng.gen()).collect();
+    let b: Vec<u8> = (0..6_000).map(|_| rng.gen()).collect();
+    fs::write(root.join("a.bin"), &a).unwrap();
+    fs::write(root.join("b.bin"), &b).unwrap();
+    Encoder::encode(&root, &out, &common::cfg(1024)).unwrap();
+    let mpath = out.join("manifest.json");
+    let (mf, _) = parx_core::manifest::load(&mpath).unwrap();
+    assert!(mf.files.iter().flat_map(|f| &f.chunks).all(|c| c.weak.is_some()));
+
+    // Seven bytes inserted into chunk 1 of a, five deleted from chunk 2 of b:
+    // every later chunk moves but stays intact
+    let mut a2 = a[..1500].to_vec();
+    a2.extend_from_slice(b"INSERTS");
+    a2.extend_from_slice(&a[1500..]);
+    fs::write(root.join("a.bin"), &a2).unwrap();
+    let mut b2 = b[..2500].to_vec();
+    b2.extend_from_slice(&b[2505..]);
+    fs::write(root.join("b.bin"), &b2).unwrap();
+
+    let vr = verify::verify(&mpath, &root).unwrap();
+    assert_eq!((vr.chunks_bad, vr.chunks_displaced), (2, 11));
+    assert!(!vr.merkle_ok);
+
+    let rr = repair::repair(&mpath, &root).unwrap();
+    assert_eq!((rr.repaired_chunks, rr.chunks_resynced), (13, 11));
+    assert_eq!(fs::read(root.join("a.bin")).unwrap(), a);
+    assert_eq!(fs::read(root.join("b.bin")).unwrap(), b);
+    let vr = verify::verify(&mpath, &root).unwrap();
+    assert_eq!((vr.chunks_bad, vr.chunks_displaced, vr.merkle_ok), (0, 0, true));
+}
+
+#[test]
+fn repair_merges_split_volumes_and_skips_bad_copy() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir(&root).unwrap();
+    let mut rng = StdRng::seed_from_u64(11);
+    let data: Vec<u8> = (0..32 * 1024).map(|_| rng.gen()).collect();
+    fs::write(root.join("a.bin"), &data).unwrap();
+
+    let out = td.path().join(".parx");
+    let cfg = common::cfg(4096);
+    Encoder::encode(&root, &out, &cfg).unwrap();
+    let mpath = out.join("manifest.json");
+
+    // Second "medium": vol-001 lives only there, vol-000 is mirrored
+    let media_b = td.path().join("media-b");
+    fs::create_dir(&media_b).unwrap();
+    fs::rename(out.join(vol_name(1)), media_b.join(vol_name(1))).unwrap();
+    fs::copy(out.join(vol_name(0)), media_b.join(vol_name(0))).unwrap();
+
+    // Rot the first parity shard of the primary vol-000 copy
+    let mut v = OpenOptions::new().read(true).write(true).open(out.join(vol_name(0))).unwrap();
+    let (off, len, crc) = read_trailer(&mut v).unwrap();
+    let entries = read_index(&mut v, off, len, crc, &IndexLimits::default()).unwrap();
+    v.seek(SeekFrom::Start(entries[0].offset)).unwrap();
+    v.write_all(&[0x5Au8; 64]).unwrap();
+
+    // Damage the first stripe of the data
+    let mut f = OpenOptions::new().write(true).open(root.join("a.bin")).unwrap();
+    f.seek(SeekFrom::Start(10)).unwrap();
+    f.write_all(&[0u8; 32]).unwrap();
+    drop(f);
+
+    let rr = repair::repair_multi(&mpath, &root, Default::default(), &[media_b]).unwrap();
+    assert_eq!(rr.repaired_chunks, 1);
+    assert_eq!(rr.failed_chunks, 0);
+    assert!(rr.duplicate_shards > 0, "{:?}", rr);
+    assert_eq!(rr.bad_parity_copies, 1);
+    assert_eq!(fs::read(root.join("a.bin")).unwrap(), data);
+    assert_eq!(verify::verify(&mpath, &root).unwrap().chunks_bad, 0);
+}
+
+#[test]
+fn extracted_chunks_match_the_manifest_and_the_tree_is_untouched() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir_all(&root).unwrap();
+    let mut rng = StdRng::seed_from_u64(7);
+    // Five and a half chunks: the last one is short
+    let data: Vec<u8> = (0..4096 * 5 + 2048).map(|_| rng.gen()).collect();
+    fs::write(root.join("a.bin"), &data).unwrap();
+    fs::write(root.join("empty"), b"").unwrap();
+    let cfg = common::cfg(4096);
+    let mf = Encoder::encode(&root, &td.path().join(".parx"), &cfg).unwrap();
+    let manifest = td.path().join(".parx/manifest.json");
+
+    let mut bad = data.clone();
+    bad[4096 + 10] ^= 0xFF;
+    bad[4096 * 5 + 100] ^= 0xFF;
+    fs::write(root.join("a.bin"), &bad).unwrap();
+    fs::remove_file(root.join("empty")).unwrap();
+
+    let out = td.path().join("chunks");
+    let opts = RepairOptions { extract_chunks: Some(out.clone()), ..Default::default() };
+    let rep = repair::repair_with_options(&manifest, &root, PathPolicy::default(), &opts).unwrap();
+    assert_eq!((rep.repaired_chunks, rep.failed_chunks, rep.extracted_chunks), (2, 0, 2));
+    assert_eq!(rep.empty_files_restored, 0);
+
+    // Nothing written to the tree
+    assert_eq!(fs::read(root.join("a.bin")).unwrap(), bad);
+    assert!(!root.join("empty").exists());
+    assert!(!root.join("a.parx.bak").exists());
+    assert!(!td.path().join(".parx/audit.log").exists());
+
+    let index = fs::read_to_string(out.join(EXTRACTED_INDEX)).unwrap();
+    let recs: Vec<ExtractedChunk> =
+        index.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+    assert_eq!(recs.iter().map(|r| r.chunk).collect::<Vec<_>>(), [1, 5]);
+    let chunks = &mf.files.iter().find(|f| f.rel_path.ends_with("a.bin")).unwrap().chunks;
+    for r in &recs {
+        let ch = chunks.iter().find(|c| c.idx == r.chunk).unwrap();
+        assert_eq!(r.hash, ch.hash_hex);
+        assert_eq!(r.file, format!("{:08}-{}.chunk", r.chunk, ch.hash_hex));
+        assert_eq!((r.offset, r.len), (ch.file_offset, ch.len));
+        assert!(r.target.ends_with("a.bin"));
+        assert!(!r.sources.is_empty());
+        let bytes = fs::read(out.join(&r.file)).unwrap();
+        let at = r.offset as usize;
+        assert_eq!(bytes, &data[at..at + r.len as usize]);
+    }
+    assert_eq!(recs[1].len, 2048);
+}
+
+#[cfg(unix)]
+#[test]
+fn proxy_answers_only_with_verified_bytes() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir(&root).unwrap();
+    let a: Vec<u8> = (0..20_000u32).map(|i| (i % 251) as u8).collect();
+    fs::write(root.join("a.bin"), &a).unwrap();
+    let out = td.path().join(".parx");
+    let cfg = common::cfg(4096);
+    Encoder::encode(&root, &out, &cfg).unwrap();
+    let mf = out.join("manifest.json");
+    let proxy = IntegrityProxy::open(&mf, &root, ServeOptions::default()).unwrap();
+    let socket = td.path().join("parx.sock");
+    let served = socket.clone();
+    std::thread::spawn(move || Arc::new(proxy).serve(&served));
+    let start = Instant::now();
+    while !socket.exists() {
+        assert!(start.elapsed() < Duration::from_secs(10), "socket never appeared");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    // A range across chunks, and one cut at the end of the file
+    assert_eq!(request(&socket, "a.bin", 3000, 6000).unwrap(), &a[3000..9000]);
+    assert_eq!(request(&socket, "./a.bin", 19_000, 5000).unwrap(), &a[19_000..]);
+    assert!(request(&socket, "b.bin", 0, 10).is_err());
+
+    // Damage under the range is repaired before the bytes are returned
+    let mut f = OpenOptions::new().write(true).open(root.join("a.bin")).unwrap();
+    f.seek(SeekFrom::Start(5000)).unwrap();
+    f.write_all(&[0xEE; 64]).unwrap();
+    drop(f);
+    assert_eq!(request(&socket, "a.bin", 4500, 1000).unwrap(), &a[4500..5500]);
+    assert_eq!(fs::read(root.join("a.bin")).unwrap(), a);
+
+    // Without repair the read fails instead
+    let strict = ServeOptions { repair: false, ..Default::default() };
+    let proxy = IntegrityProxy::open(&mf, &root, strict).unwrap();
+    let mut f = OpenOptions::new().write(true).open(root.join("a.bin")).unwrap();
+    f.seek(SeekFrom::Start(100)).unwrap();
+    f.write_all(&[0xEE; 4]).unwrap();
+    drop(f);
+    let err = proxy.read("a.bin", 0, 50).unwrap_err();
+    assert!(err.to_string().contains("damaged"), "{}", err);
+    assert_eq!(proxy.read("a.bin", 4096, 50).unwrap(), (a[4096..4146].to_vec(), 0));
+
+    // A request line past the limit is answered with an error and the
+    // connection closed, without reading it all
+    let mut input = vec![b'x'; 4 * MAX_REQUEST_LINE];
+    input.extend_from_slice(b"\n{\"path\":\"a.bin\",\"offset\":4096,\"len\":4}\n");
+    let mut reply = Vec::new();
+    proxy.handle(&input[..], &mut reply).unwrap();
+    let reply = String::from_utf8(reply).unwrap();
+    assert_eq!(reply.lines().count(), 1, "{}", reply);
+    assert!(reply.contains("request longer than"), "{}", reply);
+    let mut reply = Vec::new();
+    proxy.handle(&input[4 * MAX_REQUEST_LINE + 1..], &mut reply).unwrap();
+    assert!(reply.starts_with(b"{\"ok\":true"), "{}", String::from_utf8_lossy(&reply));
+
+    // Only a stale socket is replaced, never a file someone put there
+    let victim = td.path().join("victim.txt");
+    fs::write(&victim, b"keep me").unwrap();
+    let err = Arc::new(proxy).serve(&victim).unwrap_err();
+    assert!(err.to_string().contains("is not a socket"), "{}", err);
+    assert_eq!(fs::read(&victim).unwrap(), b"keep me");
+}
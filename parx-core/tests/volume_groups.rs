@@ -0,0 +1,106 @@
+use parx_core::domains::{self, Level};
+use parx_core::encode::{EncodeOptions, Encoder, EncoderConfig};
+use parx_core::repair;
+use parx_core::volume::vol_name;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::fs::{self, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+
+mod common;
+
+fn cfg() -> EncoderConfig {
+    EncoderConfig { volumes: 4, ..common::cfg(1024) }
+}
+
+fn write_data(root: &Path) -> Vec<u8> {
+    fs::create_dir_all(root).unwrap();
+    let mut rng = StdRng::seed_from_u64(17);
+    let data: Vec<u8> = (0..16 * 1024).map(|_| rng.gen()).collect();
+    fs::write(root.join("a.bin"), &data).unwrap();
+    data
+}
+
+fn groups(specs: &[&str]) -> EncodeOptions {
+    EncodeOptions {
+        volume_groups: specs.iter().map(|s| s.parse().unwrap()).collect(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn spec_parses_and_round_trips() {
+    let level: Level = "host=a:0-2+5,b:3+4".parse().unwrap();
+    assert_eq!(level.name, "host");
+    let ids: Vec<&[usize]> = level.groups.iter().map(|g| g.volumes.as_slice()).collect();
+    assert_eq!(ids, [&[0, 1, 2, 5][..], &[3, 4][..]]);
+    assert_eq!(level.to_string(), "host=a:0+1+2+5,b:3+4");
+    assert_eq!(level.to_string().parse::<Level>().unwrap(), level);
+
+    for bad in ["host", "host=a", "host=a:x", "host=a:3-1", "=a:0", "host=a b:0"] {
+        assert!(bad.parse::<Level>().is_err(), "{}", bad);
+    }
+    let two: Level = "host=a:0-1,b:2".parse().unwrap();
+    assert!(domains::check(std::slice::from_ref(&two), 3).is_ok());
+    let err = domains::check(std::slice::from_ref(&two), 4).unwrap_err().to_string();
+    assert!(err.contains("volume 3 is in no host group"), "{}", err);
+    let err = domains::check(&[two], 2).unwrap_err().to_string();
+    assert!(err.contains("lists volume 2"), "{}", err);
+    let twice: Level = "host=a:0-1,b:1-2".parse().unwrap();
+    let err = domains::check(&[twice], 3).unwrap_err().to_string();
+    assert!(err.contains("volume 1 is in host"), "{}", err);
+}
+
+#[test]
+fn create_refuses_a_group_holding_all_parity_of_a_stripe() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    write_data(&root);
+    // Two parity shards per stripe, on volumes 0 and 1
+    let err = Encoder::encode_with(
+        &root,
+        &td.path().join(".parx"),
+        &cfg(),
+        &groups(&["host=a:0-1,b:2-3"]),
+    )
+    .unwrap_err();
+    let msg = format!("{:#}", err);
+    assert!(msg.contains("losing host \"a\" would take every parity shard"), "{}", msg);
+    let err =
+        Encoder::encode_with(&root, &td.path().join(".parx"), &cfg(), &groups(&["site=x:0-3"]))
+            .unwrap_err();
+    assert!(format!("{:#}", err).contains("losing site"), "{:#}", err);
+}
+
+#[test]
+fn tolerance_is_recorded_reported_and_holds() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    let data = write_data(&root);
+    let out = td.path().join(".parx");
+    let opts = groups(&["disk=d0:0,d1:1,d2:2,d3:3", "host=a:0+2,b:1+3"]);
+    let mf = Encoder::encode_with(&root, &out, &cfg(), &opts).unwrap();
+
+    let (loaded, _) = parx_core::manifest::load(&out.join("manifest.json")).unwrap();
+    assert_eq!(domains::from_ext(&loaded.ext).unwrap(), opts.volume_groups);
+    let tols = domains::of_manifest(&loaded).unwrap();
+    let got: Vec<(&str, usize, usize, usize)> = tols
+        .iter()
+        .map(|t| (t.level.as_str(), t.groups, t.tolerates, t.parity_left_after_one))
+        .collect();
+    // Parity sits on volumes 0 and 1 only: losing both takes it all
+    assert_eq!(got, [("disk", 4, 1, 1), ("host", 2, 1, 1)]);
+
+    // Losing host a (volumes 0 and 2) leaves every stripe one parity shard
+    for v in [0, 2] {
+        fs::remove_file(out.join(vol_name(v))).unwrap();
+    }
+    let mut f = OpenOptions::new().write(true).open(root.join("a.bin")).unwrap();
+    f.seek(SeekFrom::Start(5000)).unwrap();
+    f.write_all(&[0u8; 100]).unwrap();
+    drop(f);
+    let rr = repair::repair(&out.join("manifest.json"), &root).unwrap();
+    assert_eq!((rr.repaired_chunks, rr.failed_chunks), (1, 0));
+    assert_eq!(fs::read(root.join("a.bin")).unwrap(), data);
+    assert_eq!(mf.volumes, 4);
+}
@@ -0,0 +1,163 @@
+use parx_core::codec::{Codec, Codecs};
+use parx_core::encode::{EncodeOptions, Encoder, EncoderConfig};
+use parx_core::fsck::{fsck, FsckOptions};
+use parx_core::index::{read_index, read_trailer, IndexCopy, IndexLimits};
+use parx_core::volume::{self, ShardKind, VolumeEntry, VolumeHeader};
+use parx_core::{repair, update};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+
+mod common;
+
+fn cfg() -> EncoderConfig {
+    EncoderConfig { volumes: 3, ..common::cfg(4096) }
+}
+
+fn tree(dir: &Path, seed: u64, len: usize) -> Vec<u8> {
+    let root = dir.join("data");
+    fs::create_dir_all(&root).unwrap();
+    let mut rng = StdRng::seed_from_u64(seed);
+    let data: Vec<u8> = (0..len).map(|_| rng.gen()).collect();
+    fs::write(root.join("a.bin"), &data).unwrap();
+    data
+}
+
+fn index(vol: &Path) -> anyhow::Result<Vec<VolumeEntry>> {
+    let mut f = File::open(vol)?;
+    let (off, len, crc) = read_trailer(&mut f)?;
+    read_index(&mut f, off, len, crc, &IndexLimits::default())
+}
+
+#[test]
+fn no_volume_exceeds_the_cap_whatever_the_codecs() {
+    let td = tempfile::tempdir().unwrap();
+    tree(td.path(), 1, 300_000);
+    let root = td.path().join("data");
+    let codecs = [Codec::Zstd(0), Codec::Lz4, Codec::None];
+    for (i, cap) in [32 << 10, 40_000, 64 << 10, 1 << 20].into_iter().enumerate() {
+        let out = td.path().join(format!("out{}", i));
+        let opts = EncodeOptions {
+            volume_max_size: Some(cap),
+            volume_name: Some("disc-{id}.parxv".to_string()),
+            codecs: Codecs { index: codecs[i % 3], backup: codecs[(i + 1) % 3] },
+            ..Default::default()
+        };
+        let mf = Encoder::encode_with(&root, &out, &cfg(), &opts).unwrap();
+        assert_eq!(mf.volume_max_size(), Some(cap));
+        // 19 stripes of two 4 KiB shards
+        let parity = 19 * 2 * 4096;
+        assert!(mf.volumes as u64 >= parity / cap, "{} volumes for cap {}", mf.volumes, cap);
+        let mut names = HashSet::new();
+        let mut shards = 0;
+        for vid in 0..mf.volumes {
+            let name = mf.volume_name(vid);
+            assert_eq!(name, format!("disc-{:03}.parxv", vid));
+            let len = fs::metadata(out.join(&name)).unwrap().len();
+            assert!(len <= cap, "{} is {} bytes, cap {}", name, len, cap);
+            shards += index(&out.join(&name)).unwrap().iter().filter(|e| e.is_inner()).count();
+            names.insert(name);
+        }
+        assert_eq!(shards, 19 * 2);
+        assert!(!out.join(volume::vol_name(0)).exists());
+    }
+}
+
+#[test]
+fn each_stripe_spreads_over_the_volumes_and_survives_losing_one() {
+    let td = tempfile::tempdir().unwrap();
+    let data = tree(td.path(), 2, 200_000);
+    let root = td.path().join("data");
+    let out = td.path().join(".parx");
+    let opts = EncodeOptions { volume_max_size: Some(40_000), ..Default::default() };
+    let mf = Encoder::encode_with(&root, &out, &cfg(), &opts).unwrap();
+    assert!(mf.volumes >= 4, "{}", mf.volumes);
+
+    // Both shards of a stripe sit on different volumes, and every volume
+    // holds some
+    let mut seen = std::collections::HashMap::new();
+    for vid in 0..mf.volumes {
+        let entries = index(&out.join(mf.volume_name(vid))).unwrap();
+        assert!(entries.iter().any(|e| e.kind == ShardKind::Inner));
+        for e in entries.iter().filter(|e| e.is_inner()) {
+            assert_eq!(mf.inner_shard_volume(e.stripe, e.parity_idx as usize, 0), vid);
+            assert!(seen.insert((e.stripe, e.parity_idx), vid).is_none());
+        }
+    }
+    for s in 0..mf.geometry().stripes {
+        assert_ne!(seen[&(s, 0)], seen[&(s, 1)]);
+    }
+
+    // Rebuild a lost index: fsck places shards the way create did
+    let vol = out.join(mf.volume_name(1));
+    let before = index(&vol).unwrap();
+    let copy =
+        IndexCopy::from_ext(&VolumeHeader::read_from(File::open(&vol).unwrap()).unwrap().ext)
+            .unwrap();
+    let mut f = OpenOptions::new().read(true).write(true).open(&vol).unwrap();
+    let (off, _, _) = read_trailer(&mut f).unwrap();
+    let len = f.metadata().unwrap().len();
+    f.seek(SeekFrom::Start(off)).unwrap();
+    f.write_all(&vec![0x5a; (len - off) as usize]).unwrap();
+    f.seek(SeekFrom::Start(copy.off)).unwrap();
+    f.write_all(&vec![0x5a; copy.len as usize]).unwrap();
+    drop(f);
+    let mpath = out.join("manifest.json");
+    let fr = fsck(&vol, &mpath, &FsckOptions { root: Some(root.clone()), ..Default::default() })
+        .unwrap();
+    assert!(fr.index_rewritten);
+    let inner = |v: &[VolumeEntry]| v.iter().filter(|e| e.is_inner()).count();
+    assert_eq!(fr.shards_found as usize, inner(&before));
+
+    // Lose a whole volume and damage a chunk of every stripe it held a shard of
+    fs::remove_file(out.join(mf.volume_name(2))).unwrap();
+    let mut bad = data.clone();
+    for s in 0..mf.geometry().stripes {
+        let at = (s as usize * 4 + 1) * 4096;
+        if at < bad.len() {
+            bad[at] ^= 0xFF;
+        }
+    }
+    fs::write(root.join("a.bin"), &bad).unwrap();
+    let rr = repair::repair(&mpath, &root).unwrap();
+    assert_eq!(rr.failed_chunks, 0);
+    assert_eq!(fs::read(root.join("a.bin")).unwrap(), data);
+
+    let err = update::update(&out, &root, None).unwrap_err();
+    assert!(err.to_string().contains("--volume-max-size"), "{}", err);
+}
+
+#[test]
+fn tiny_caps_and_odd_templates_are_refused() {
+    let td = tempfile::tempdir().unwrap();
+    tree(td.path(), 3, 50_000);
+    let root = td.path().join("data");
+    let opts = EncodeOptions { volume_max_size: Some(8192), ..Default::default() };
+    let err = Encoder::encode_with(&root, &td.path().join("a"), &cfg(), &opts).unwrap_err();
+    assert!(err.to_string().contains("--volume-max-size 8192 is too small"), "{}", err);
+    // Room for one shard a volume, where each stripe puts two on some volume
+    let big = td.path().join("big");
+    tree(&big, 4, 300_000);
+    let opts = EncodeOptions { volume_max_size: Some(29_000), ..Default::default() };
+    let err =
+        Encoder::encode_with(&big.join("data"), &big.join(".parx"), &cfg(), &opts).unwrap_err();
+    assert!(err.to_string().contains("a volume fits 1 shard(s)"), "{}", err);
+
+    volume::check_name_template("disc-{id}.parxv").unwrap();
+    volume::check_name_template("{id}.parxv").unwrap();
+    for bad in [
+        "disc.parxv",
+        "disc-{id}-{id}.parxv",
+        "disc-{id}.iso",
+        ".disc-{id}.parxv",
+        "a/disc-{id}.parxv",
+        "disc-{id}-{n}.parxv",
+    ] {
+        assert!(volume::check_name_template(bad).is_err(), "{}", bad);
+    }
+    let opts = EncodeOptions { volume_name: Some("disc.parxv".to_string()), ..Default::default() };
+    assert!(Encoder::encode_with(&root, &td.path().join("b"), &cfg(), &opts).is_err());
+    assert!(!td.path().join("b").exists());
+}
@@ -0,0 +1,101 @@
+use parx_core::encode::{EncodeOptions, Encoder, EncoderConfig};
+use parx_core::{repair, update, verify, volparity};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::fs;
+
+mod common;
+
+fn cfg(volumes: usize) -> EncoderConfig {
+    EncoderConfig { stripe_k: 16, volumes, ..common::cfg(64 * 1024) }
+}
+
+fn random(seed: u64, len: usize) -> Vec<u8> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    (0..len).map(|_| rng.gen()).collect()
+}
+
+#[test]
+fn lost_volumes_are_rebuilt_before_repair() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir_all(&root).unwrap();
+    let data = random(1, 8 << 20);
+    fs::write(root.join("a.bin"), &data).unwrap();
+    let out = td.path().join(".parx");
+    let opts = EncodeOptions { volume_parity: 2, ..Default::default() };
+    let mf = Encoder::encode_with(&root, &out, &cfg(3), &opts).unwrap();
+    assert_eq!(mf.volume_parity, 2);
+    let vp = volparity::load(&out).unwrap().unwrap();
+    assert_eq!((vp.volumes.len(), vp.parity.len()), (3, 2));
+    // Volumes over a block long take several stripes of the volume parity
+    assert!(vp.volumes.iter().any(|v| v.size > volparity::BLOCK));
+
+    // Two of three volumes lost (one cut short), the descriptor gone and
+    // data damaged
+    let originals: Vec<Vec<u8>> =
+        (0..3).map(|v| fs::read(out.join(parx_core::volume::vol_name(v))).unwrap()).collect();
+    fs::remove_file(out.join("vol-000.parxv")).unwrap();
+    let mut vol2 = originals[2].clone();
+    vol2.truncate(vol2.len() / 2);
+    fs::write(out.join("vol-002.parxv"), &vol2).unwrap();
+    fs::remove_file(out.join(volparity::DESCRIPTOR)).unwrap();
+    let mut bad = data.clone();
+    bad[..200_000].fill(0);
+    fs::write(root.join("a.bin"), &bad).unwrap();
+
+    let mpath = out.join("manifest.json");
+    let rr = repair::repair(&mpath, &root).unwrap();
+    assert_eq!(rr.volumes_restored, vec!["vol-000.parxv", "vol-002.parxv"]);
+    assert!(rr.volumes_unrecoverable.is_empty());
+    assert_eq!(rr.failed_chunks, 0);
+    assert!(rr.repaired_chunks > 0);
+    for (v, orig) in originals.iter().enumerate() {
+        let now = fs::read(out.join(parx_core::volume::vol_name(v))).unwrap();
+        assert!(now == *orig, "volume {} differs after restore", v);
+    }
+    assert!(out.join(volparity::DESCRIPTOR).is_file());
+    assert_eq!(fs::read(root.join("a.bin")).unwrap(), data);
+    let vr = verify::verify(&mpath, &root).unwrap();
+    assert_eq!((vr.chunks_bad, vr.merkle_ok), (0, true));
+}
+
+#[test]
+fn too_many_losses_are_reported_and_left_alone() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir_all(&root).unwrap();
+    fs::write(root.join("a.bin"), random(2, 300_000)).unwrap();
+    let out = td.path().join(".parx");
+    let opts = EncodeOptions { volume_parity: 1, ..Default::default() };
+    Encoder::encode_with(&root, &out, &cfg(3), &opts).unwrap();
+    fs::remove_file(out.join("vol-001.parxv")).unwrap();
+    fs::remove_file(out.join(volparity::parity_name(0))).unwrap();
+    let rep = volparity::restore(&out).unwrap();
+    assert!(rep.restored.is_empty());
+    assert_eq!(rep.unrecoverable, vec!["vol-001.parxv", "volpar-000.parxp"]);
+    assert!(!out.join("vol-001.parxv").exists());
+}
+
+#[test]
+fn update_refreshes_volume_parity() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir_all(&root).unwrap();
+    fs::write(root.join("a.bin"), random(3, 500_000)).unwrap();
+    let out = td.path().join(".parx");
+    let opts = EncodeOptions { volume_parity: 1, ..Default::default() };
+    Encoder::encode_with(&root, &out, &cfg(2), &opts).unwrap();
+    fs::write(root.join("b.bin"), random(4, 700_000)).unwrap();
+    update::update(&out, &root, None).unwrap();
+
+    let vol1 = fs::read(out.join("vol-001.parxv")).unwrap();
+    fs::remove_file(out.join("vol-001.parxv")).unwrap();
+    let rep = volparity::restore(&out).unwrap();
+    assert_eq!(rep.restored, vec!["vol-001.parxv"]);
+    assert_eq!(fs::read(out.join("vol-001.parxv")).unwrap(), vol1);
+
+    // A create without volume parity drops that of the set it replaces
+    Encoder::encode_with(&root, &out, &cfg(2), &EncodeOptions::default()).unwrap();
+    assert!(volparity::load(&out).unwrap().is_none());
+    assert!(!out.join(volparity::parity_name(0)).exists());
+}
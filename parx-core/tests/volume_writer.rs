@@ -0,0 +1,69 @@
+use parx_core::encode::{EncodeOptions, Encoder, EncoderConfig};
+use parx_core::index::{read_index, read_trailer, IndexLimits};
+use parx_core::repair;
+use parx_core::volwriter::WriteTuning;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+
+mod common;
+
+fn cfg() -> EncoderConfig {
+    EncoderConfig { volumes: 3, shard_copies: 2, ..common::cfg(1024) }
+}
+
+#[test]
+fn every_writer_tuning_yields_sound_volumes() {
+    let tunings = [
+        WriteTuning { block_size: 0, queue_depth: 1, streams: 1 },
+        WriteTuning { block_size: 5000, queue_depth: 2, streams: 4 },
+        WriteTuning::default(),
+    ];
+    for write in tunings {
+        let td = tempfile::tempdir().unwrap();
+        let root = td.path().join("data");
+        let out = td.path().join(".parx");
+        fs::create_dir_all(&root).unwrap();
+        let mut rng = StdRng::seed_from_u64(5);
+        let data: Vec<u8> = (0..37 * 1024 + 300).map(|_| rng.gen()).collect();
+        fs::write(root.join("f.bin"), &data).unwrap();
+        let opts = EncodeOptions { segment_stripes: 3, write, ..Default::default() };
+        Encoder::encode_with(&root, &out, &cfg(), &opts).unwrap();
+
+        // Every indexed shard is where the index says, with its hash
+        let mut inner = 0;
+        for v in 0..3 {
+            let mut f = File::open(out.join(format!("vol-{:03}.parxv", v))).unwrap();
+            let (off, len, crc) = read_trailer(&mut f).unwrap();
+            for e in read_index(&mut f, off, len, crc, &IndexLimits::default()).unwrap() {
+                let mut buf = vec![0u8; e.len as usize];
+                f.seek(SeekFrom::Start(e.offset)).unwrap();
+                f.read_exact(&mut buf).unwrap();
+                assert_eq!(Some(*blake3::hash(&buf).as_bytes()), e.hash, "{:?}", write);
+                inner += e.is_inner() as usize;
+            }
+        }
+        assert_eq!(inner, 10 * 2 * 2);
+
+        let file = root.join("f.bin");
+        let mut f = OpenOptions::new().write(true).open(&file).unwrap();
+        f.seek(SeekFrom::Start(6 * 1024)).unwrap();
+        f.write_all(&[0u8; 2048]).unwrap();
+        drop(f);
+        let rr = repair::repair(&out.join("manifest.json"), &root).unwrap();
+        assert_eq!((rr.repaired_chunks, rr.failed_chunks), (2, 0));
+        assert_eq!(fs::read(&file).unwrap(), data);
+    }
+}
+
+#[test]
+fn writer_tuning_is_validated() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().join("data");
+    fs::create_dir_all(&root).unwrap();
+    fs::write(root.join("f.bin"), b"x").unwrap();
+    let write = WriteTuning { streams: 0, ..Default::default() };
+    let opts = EncodeOptions { write, ..Default::default() };
+    let err = Encoder::encode_with(&root, &td.path().join(".parx"), &cfg(), &opts).unwrap_err();
+    assert!(err.to_string().contains("--write-streams"), "{err}");
+}